@@ -79,6 +79,35 @@ pub fn url_to_path(url: &Url) -> Option<PathBuf> {
     Some(PathBuf::from(path))
 }
 
+/// Returns the path with a `\\?\` prefix if it is an absolute Windows path, e.g. `C:\foo` becomes
+/// `\\?\C:\foo` and `\\server\share\foo` becomes `\\?\UNC\server\share\foo`.
+///
+/// These prefixed paths are not subject to the `MAX_PATH` limit of 260 characters, which is easily
+/// exceeded for packages on network shares. On other operating systems the path is returned
+/// unchanged.
+pub fn to_long_path(path: PathBuf) -> PathBuf {
+    if cfg!(windows) {
+        if let Some(long_path) = path.to_str().and_then(windows_long_path) {
+            return PathBuf::from(long_path);
+        }
+    }
+    path
+}
+
+fn windows_long_path(path: &str) -> Option<String> {
+    // Verbatim paths are not normalized so they must only contain backslashes.
+    let path = path.replace('/', "\\");
+    if path.starts_with("\\\\?\\") || path.starts_with("\\\\.\\") {
+        None
+    } else if let Some(unc) = path.strip_prefix("\\\\") {
+        Some(format!("\\\\?\\UNC\\{unc}"))
+    } else if starts_with_windows_drive_letter(&path) && path.as_bytes().get(2) == Some(&b'\\') {
+        Some(format!("\\\\?\\{path}"))
+    } else {
+        None
+    }
+}
+
 const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
 const PATH: &AsciiSet = &FRAGMENT.add(b'#').add(b'?').add(b'{').add(b'}');
 pub(crate) const PATH_SEGMENT: &AsciiSet = &PATH.add(b'/').add(b'%');
@@ -185,6 +214,9 @@ mod tests {
     // Percent encoding
     #[case("file:///foo/ba%20r", Some("/foo/ba r"))]
     #[case("file:///C%3A/Test/Foo.txt", Some("C:\\Test\\Foo.txt"))]
+    // UNC paths
+    #[case("file://server/share/Foo.txt", Some("\\\\server\\share\\Foo.txt"))]
+    #[case("file://server/share/ba%20r", Some("\\\\server\\share\\ba r"))]
     // Non file URLs
     #[case("http://example.com", None)]
     fn test_url_to_path(#[case] url: &str, #[case] expected: Option<&str>) {
@@ -211,4 +243,19 @@ mod tests {
             expected
         );
     }
+
+    #[rstest]
+    #[case::drive("C:\\Test\\Foo.txt", Some("\\\\?\\C:\\Test\\Foo.txt"))]
+    #[case::forward_slashes("C:/Test/Foo.txt", Some("\\\\?\\C:\\Test\\Foo.txt"))]
+    #[case::unc(
+        "\\\\server\\share\\Foo.txt",
+        Some("\\\\?\\UNC\\server\\share\\Foo.txt")
+    )]
+    #[case::already_verbatim("\\\\?\\C:\\Test\\Foo.txt", None)]
+    #[case::device("\\\\.\\pipe\\foo", None)]
+    #[case::relative_to_drive("C:Foo.txt", None)]
+    #[case::unix_path("/home/bob/foo.txt", None)]
+    fn test_windows_long_path(#[case] path: &str, #[case] expected: Option<&str>) {
+        assert_eq!(super::windows_long_path(path).as_deref(), expected);
+    }
 }
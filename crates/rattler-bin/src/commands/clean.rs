@@ -0,0 +1,295 @@
+use crate::commands::create::cache_dir;
+use indicatif::HumanBytes;
+use rattler::package_cache::{GarbageCollectionOptions, PackageCache};
+use rattler_config::Config;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+/// Inspects and prunes the package cache and the repodata cache.
+///
+/// Without `--older-than` or `--max-size` all packages that are not used by a prefix and the
+/// entire repodata cache are removed.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// Only clean the package cache.
+    #[clap(long)]
+    packages: bool,
+
+    /// Only clean the repodata cache.
+    #[clap(long)]
+    repodata: bool,
+
+    /// List the entries in the cache with their sizes without removing anything.
+    #[clap(long)]
+    list: bool,
+
+    /// Show what would be removed without removing anything.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Only remove entries that were not modified for this long, e.g. `30d`, `12h` or `90m`.
+    #[clap(long)]
+    older_than: Option<HumanDuration>,
+
+    /// Remove the oldest entries until the cache is at most this large, e.g. `10GB` or `500MB`.
+    #[clap(long)]
+    max_size: Option<HumanSize>,
+}
+
+/// A duration passed on the command line as a number followed by a unit.
+#[derive(Debug, Clone, Copy)]
+struct HumanDuration(Duration);
+
+impl FromStr for HumanDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, unit) = split_number(s);
+        let value: u64 = value
+            .parse()
+            .map_err(|e| format!("invalid duration '{s}': {e}"))?;
+        let seconds = match unit.trim() {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => {
+                return Err(format!(
+                    "invalid duration '{s}', expected a unit of s, m, h, d or w"
+                ))
+            }
+        };
+        value
+            .checked_mul(seconds)
+            .map(|seconds| Self(Duration::from_secs(seconds)))
+            .ok_or_else(|| format!("invalid duration '{s}': the duration is too long"))
+    }
+}
+
+/// A size in bytes passed on the command line as a number optionally followed by a unit.
+#[derive(Debug, Clone, Copy)]
+struct HumanSize(u64);
+
+impl FromStr for HumanSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, unit) = split_number(s);
+        let value: u64 = value
+            .parse()
+            .map_err(|e| format!("invalid size '{s}': {e}"))?;
+        let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" | "KIB" => 1 << 10,
+            "M" | "MB" | "MIB" => 1 << 20,
+            "G" | "GB" | "GIB" => 1 << 30,
+            "T" | "TB" | "TIB" => 1 << 40,
+            _ => {
+                return Err(format!(
+                    "invalid size '{s}', expected a unit of B, KB, MB, GB or TB"
+                ))
+            }
+        };
+        value
+            .checked_mul(multiplier)
+            .map(Self)
+            .ok_or_else(|| format!("invalid size '{s}': the size is too large"))
+    }
+}
+
+/// Splits a string into its leading digits and the rest.
+fn split_number(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()))
+}
+
+pub async fn clean(opt: Opt, config: &Config) -> anyhow::Result<()> {
+    let cache_dir = cache_dir(config)?;
+    let both = !opt.packages && !opt.repodata;
+    if opt.packages || both {
+        clean_packages(&opt, &cache_dir.join("pkgs")).await?;
+    }
+    if opt.repodata || both {
+        clean_repodata(&opt, &cache_dir.join("repodata"))?;
+    }
+    Ok(())
+}
+
+/// Prunes the packages that are no longer used by any prefix.
+async fn clean_packages(opt: &Opt, cache_dir: &Path) -> anyhow::Result<()> {
+    println!("Package cache: {}", cache_dir.display());
+    let report = PackageCache::new(cache_dir)
+        .collect_garbage(if opt.list {
+            GarbageCollectionOptions {
+                max_age: None,
+                dry_run: true,
+                ..GarbageCollectionOptions::default()
+            }
+        } else {
+            GarbageCollectionOptions {
+                max_age: max_age(opt),
+                max_size: opt.max_size.map(|size| size.0),
+                dry_run: opt.dry_run,
+                ..GarbageCollectionOptions::default()
+            }
+        })
+        .await?;
+
+    for entry in &report.packages {
+        if opt.list || entry.removed {
+            println!(
+                "  {} {:>10} {}{}",
+                removal_marker(opt, entry.removed),
+                HumanBytes(entry.size).to_string(),
+                entry.path.display(),
+                if entry.referenced { " (in use)" } else { "" },
+            );
+        }
+    }
+    for path in &report.removed_partial_downloads {
+        if !opt.list {
+            println!(
+                "  {} {} (incomplete)",
+                removal_marker(opt, true),
+                path.display()
+            );
+        }
+    }
+    print_summary(opt, report.freed_bytes, report.remaining_bytes);
+    Ok(())
+}
+
+/// A file in the repodata cache.
+struct RepodataCacheEntry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Returns all files in the repodata cache. Lock files are skipped because they might be held by
+/// another process.
+fn repodata_cache_entries(
+    dir: &Path,
+    entries: &mut Vec<RepodataCacheEntry>,
+) -> std::io::Result<()> {
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in read_dir {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            repodata_cache_entries(&path, entries)?;
+        } else if path.extension().and_then(|ext| ext.to_str()) != Some("lock") {
+            entries.push(RepodataCacheEntry {
+                path,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Prunes the cached repodata. The files in the repodata cache are only a cache of remote data so
+/// removing any of them is safe, they are downloaded again when required.
+fn clean_repodata(opt: &Opt, cache_dir: &Path) -> anyhow::Result<()> {
+    println!("Repodata cache: {}", cache_dir.display());
+    let mut entries = Vec::new();
+    repodata_cache_entries(cache_dir, &mut entries)?;
+    entries.sort_by_key(|entry| entry.modified);
+
+    let now = SystemTime::now();
+    let max_age = max_age(opt);
+    let mut total_size = entries.iter().map(|entry| entry.size).sum::<u64>();
+    let mut freed_bytes = 0;
+    for entry in entries {
+        let age = now.duration_since(entry.modified).unwrap_or_default();
+        let too_old = max_age.is_some_and(|max_age| age >= max_age);
+        let over_budget = opt.max_size.is_some_and(|max_size| total_size > max_size.0);
+        let remove = !opt.list && (too_old || over_budget);
+        if remove {
+            if !opt.dry_run {
+                std::fs::remove_file(&entry.path)?;
+            }
+            total_size -= entry.size;
+            freed_bytes += entry.size;
+        }
+        if opt.list || remove {
+            println!(
+                "  {} {:>10} {}",
+                removal_marker(opt, remove),
+                HumanBytes(entry.size).to_string(),
+                entry.path.display(),
+            );
+        }
+    }
+    print_summary(opt, freed_bytes, total_size);
+    Ok(())
+}
+
+/// Returns the age after which unused entries are removed.
+fn max_age(opt: &Opt) -> Option<Duration> {
+    match (opt.older_than, opt.max_size) {
+        (Some(older_than), _) => Some(older_than.0),
+        // Only enforce the budget.
+        (None, Some(_)) => None,
+        (None, None) => Some(Duration::ZERO),
+    }
+}
+
+fn removal_marker(opt: &Opt, removed: bool) -> console::StyledObject<&'static str> {
+    if !removed || opt.list {
+        console::style(" ")
+    } else if opt.dry_run {
+        console::style("~").yellow()
+    } else {
+        console::style("-").red()
+    }
+}
+
+fn print_summary(opt: &Opt, freed_bytes: u64, remaining_bytes: u64) {
+    if opt.list {
+        println!("  Total: {}", HumanBytes(remaining_bytes));
+    } else if opt.dry_run {
+        println!(
+            "  Would free {}, {} would remain",
+            HumanBytes(freed_bytes),
+            HumanBytes(remaining_bytes)
+        );
+    } else {
+        println!(
+            "{} Freed {}, {} remains",
+            console::style(console::Emoji("✔", "")).green(),
+            HumanBytes(freed_bytes),
+            HumanBytes(remaining_bytes)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HumanDuration, HumanSize};
+    use std::{str::FromStr, time::Duration};
+
+    #[test]
+    fn test_parse_human_units() {
+        assert_eq!(HumanSize::from_str("10GB").unwrap().0, 10 << 30);
+        assert_eq!(HumanSize::from_str("500").unwrap().0, 500);
+        assert!(HumanSize::from_str("10XB").is_err());
+        assert!(HumanSize::from_str("18446744073709551615TB").is_err());
+
+        assert_eq!(
+            HumanDuration::from_str("2h").unwrap().0,
+            Duration::from_secs(2 * 60 * 60)
+        );
+        assert!(HumanDuration::from_str("18446744073709551615w").is_err());
+    }
+}
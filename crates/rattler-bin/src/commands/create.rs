@@ -4,7 +4,6 @@ use futures::{stream, stream::FuturesUnordered, FutureExt, StreamExt, TryFutureE
 use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
 use rattler::{
-    default_cache_dir,
     install::{
         link_package, unlink_package, InstallDriver, InstallOptions, Transaction,
         TransactionOperation,
@@ -16,8 +15,11 @@ use rattler_conda_types::{
     Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, PackageRecord, ParseStrictness,
     Platform, PrefixRecord, RepoDataRecord, Version,
 };
+use rattler_config::Config;
+use rattler_lock::{LockFile, SolvedEnvironment};
 use rattler_networking::{
-    retry_policies::default_retry_policy, AuthenticationMiddleware, AuthenticationStorage,
+    retry_policies::{retry_policy_from_config, ExponentialBackoff},
+    AuthenticationMiddleware, MirrorMiddleware,
 };
 use rattler_repodata_gateway::{Gateway, RepoData};
 use rattler_solve::{
@@ -25,6 +27,7 @@ use rattler_solve::{
     resolvo, SolverImpl, SolverTask,
 };
 use reqwest::Client;
+use serde::Serialize;
 use std::future::IntoFuture;
 use std::sync::Arc;
 use std::time::Instant;
@@ -63,24 +66,85 @@ pub struct Opt {
 
     #[clap(long)]
     target_prefix: Option<PathBuf>,
+
+    /// Write a lock-file of the packages in the environment to this path after installing them.
+    #[clap(long)]
+    lock_file: Option<PathBuf>,
+
+    /// Print a machine readable summary as JSON instead of human readable output.
+    #[clap(long)]
+    json: bool,
+}
+
+/// The summary of the `create` command that is printed with `--json`.
+#[derive(Debug, Serialize)]
+struct CreateSummary {
+    prefix: PathBuf,
+    platform: String,
+    dry_run: bool,
+    operations: Vec<OperationSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_file: Option<PathBuf>,
+}
+
+/// A single operation of the transaction in the [`CreateSummary`].
+#[derive(Debug, Serialize)]
+struct OperationSummary {
+    operation: &'static str,
+    name: String,
+    version: String,
+    build: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_version: Option<String>,
 }
 
-pub async fn create(opt: Opt) -> anyhow::Result<()> {
+impl OperationSummary {
+    fn new(operation: &'static str, record: &RepoDataRecord) -> Self {
+        Self {
+            operation,
+            name: record.package_record.name.as_normalized().to_string(),
+            version: record.package_record.version.to_string(),
+            build: record.package_record.build.clone(),
+            previous_version: None,
+        }
+    }
+}
+
+impl From<&TransactionOperation<&PrefixRecord, RepoDataRecord>> for OperationSummary {
+    fn from(operation: &TransactionOperation<&PrefixRecord, RepoDataRecord>) -> Self {
+        match operation {
+            TransactionOperation::Install(r) => Self::new("install", r),
+            TransactionOperation::Change { old, new } => Self {
+                previous_version: Some(old.repodata_record.package_record.version.to_string()),
+                ..Self::new("change", new)
+            },
+            TransactionOperation::Reinstall(r) => Self::new("reinstall", &r.repodata_record),
+            TransactionOperation::Remove(r) => Self::new("remove", &r.repodata_record),
+        }
+    }
+}
+
+pub async fn create(opt: Opt, config: &Config) -> anyhow::Result<()> {
     let channel_config = ChannelConfig::default_with_root_dir(env::current_dir()?);
     let current_dir = env::current_dir()?;
     let target_prefix = opt
         .target_prefix
         .unwrap_or_else(|| current_dir.join(".prefix"));
-    println!("Target prefix: {}", target_prefix.display());
+    let json = opt.json;
+    if !json {
+        println!("Target prefix: {}", target_prefix.display());
+    }
 
     // Determine the platform we're going to install for
     let install_platform = if let Some(platform) = opt.platform {
         Platform::from_str(&platform)?
     } else {
-        Platform::current()
+        config.platform()
     };
 
-    println!("Installing for platform: {install_platform:?}");
+    if !json {
+        println!("Installing for platform: {install_platform:?}");
+    }
 
     // Parse the specs from the command line. We do this explicitly instead of allow clap to deal
     // with this because we need to parse the `channel_config` when parsing matchspecs.
@@ -91,7 +155,7 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
         .collect::<Result<Vec<_>, _>>()?;
 
     // Find the default cache directory. Create it if it doesnt exist yet.
-    let cache_dir = default_cache_dir()?;
+    let cache_dir = cache_dir(config)?;
     std::fs::create_dir_all(&cache_dir)
         .map_err(|e| anyhow::anyhow!("could not create cache directory: {}", e))?;
 
@@ -99,10 +163,14 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
     // this also requires the use of the `channel_config` so we have to do this manually.
     let channels = opt
         .channels
-        .unwrap_or_else(|| vec![String::from("conda-forge")])
+        .unwrap_or_else(|| config.default_channels())
         .into_iter()
         .map(|channel_str| Channel::from_str(channel_str, &channel_config))
         .collect::<Result<Vec<_>, _>>()?;
+    let lock_file_channels = channels
+        .iter()
+        .map(|channel| channel.base_url().to_string())
+        .collect::<Vec<_>>();
 
     // Determine the packages that are currently installed in the environment.
     let installed_packages = find_installed_packages(&target_prefix, 100)
@@ -112,17 +180,7 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
     // For each channel/subdirectory combination, download and cache the `repodata.json` that should
     // be available from the corresponding Url. The code below also displays a nice CLI progress-bar
     // to give users some more information about what is going on.
-    let download_client = Client::builder()
-        .no_gzip()
-        .build()
-        .expect("failed to create client");
-
-    let authentication_storage = AuthenticationStorage::default();
-    let download_client = reqwest_middleware::ClientBuilder::new(download_client)
-        .with_arc(Arc::new(AuthenticationMiddleware::new(
-            authentication_storage,
-        )))
-        .build();
+    let download_client = download_client(config)?;
 
     // Get the package names from the matchspecs so we can only load the package records that we need.
     let gateway = Gateway::builder()
@@ -133,24 +191,24 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
     let start_load_repo_data = Instant::now();
     let repo_data = wrap_in_async_progress(
         "loading repodata",
-        gateway
-            .query(
-                channels,
-                [install_platform, Platform::NoArch],
-                specs.clone(),
-            )
-            .recursive(true),
+        gateway.query_closure(
+            channels,
+            [install_platform, Platform::NoArch],
+            specs.clone(),
+        ),
     )
     .await
     .context("failed to load repodata")?;
 
     // Determine the number of recors
     let total_records: usize = repo_data.iter().map(RepoData::len).sum();
-    println!(
-        "Loaded {} records in {:?}",
-        total_records,
-        start_load_repo_data.elapsed()
-    );
+    if !json {
+        println!(
+            "Loaded {} records in {:?}",
+            total_records,
+            start_load_repo_data.elapsed()
+        );
+    }
 
     // Determine virtual packages of the system. These packages define the capabilities of the
     // system. Some packages depend on these virtual packages to indiciate compability with the
@@ -183,12 +241,14 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
         }
     })?;
 
-    println!(
-        "Virtual packages:\n{}\n",
-        virtual_packages
-            .iter()
-            .format_with("\n", |i, f| f(&format_args!("  - {i}",)),)
-    );
+    if !json {
+        println!(
+            "Virtual packages:\n{}\n",
+            virtual_packages
+                .iter()
+                .format_with("\n", |i, f| f(&format_args!("  - {i}",)),)
+        );
+    }
 
     // Now that we parsed and downloaded all information, construct the packaging problem that we
     // need to solve. We do this by constructing a `SolverProblem`. This encapsulates all the
@@ -201,8 +261,9 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
     let solver_task = SolverTask {
         locked_packages,
         virtual_packages,
-        specs,
+        specs: specs.clone(),
         timeout: opt.timeout.map(Duration::from_millis),
+        channel_priority: config.channel_priority().into(),
         ..SolverTask::from_iter(&repo_data)
     };
 
@@ -222,62 +283,39 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
     // Construct a transaction to
     let transaction = Transaction::from_current_and_desired(
         &installed_packages,
-        required_packages,
+        required_packages.clone(),
         install_platform,
     )?;
 
-    if opt.dry_run {
-        if transaction.operations.is_empty() {
-            println!("No operations necessary");
-        }
-
-        let format_record = |r: &RepoDataRecord| {
-            format!(
-                "{} {} {}",
-                r.package_record.name.as_normalized(),
-                r.package_record.version,
-                r.package_record.build
-            )
-        };
+    let mut summary = CreateSummary {
+        prefix: target_prefix.clone(),
+        platform: install_platform.to_string(),
+        dry_run: opt.dry_run,
+        operations: transaction
+            .operations
+            .iter()
+            .map(OperationSummary::from)
+            .collect(),
+        lock_file: None,
+    };
 
-        for operation in &transaction.operations {
-            match operation {
-                TransactionOperation::Install(r) => {
-                    println!("{} {}", console::style("+").green(), format_record(r));
-                }
-                TransactionOperation::Change { old, new } => {
-                    println!(
-                        "{} {} -> {}",
-                        console::style("~").yellow(),
-                        format_record(&old.repodata_record),
-                        format_record(new)
-                    );
-                }
-                TransactionOperation::Reinstall(r) => {
-                    println!(
-                        "{} {}",
-                        console::style("~").yellow(),
-                        format_record(&r.repodata_record)
-                    );
-                }
-                TransactionOperation::Remove(r) => {
-                    println!(
-                        "{} {}",
-                        console::style("-").red(),
-                        format_record(&r.repodata_record)
-                    );
-                }
-            }
-        }
+    if opt.dry_run && json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
 
+    if opt.dry_run {
+        print_transaction(&transaction);
         return Ok(());
     }
 
     if transaction.operations.is_empty() {
-        println!(
-            "{} Already up to date",
-            console::style(console::Emoji("✔", "")).green(),
-        );
+        if !json {
+            println!(
+                "{} Already up to date",
+                console::style(console::Emoji("✔", "")).green(),
+            );
+        }
     } else {
         // Execute the operations that are returned by the solver.
         let install_driver = InstallDriver::builder()
@@ -291,24 +329,126 @@ pub async fn create(opt: Opt) -> anyhow::Result<()> {
             target_prefix,
             cache_dir,
             download_client,
+            retry_policy_from_config(config),
+            &specs,
         )
         .await?;
-        println!(
-            "{} Successfully updated the environment",
-            console::style(console::Emoji("✔", "")).green(),
-        );
+        if !json {
+            println!(
+                "{} Successfully updated the environment",
+                console::style(console::Emoji("✔", "")).green(),
+            );
+        }
+    }
+
+    // Record exactly what is installed in the environment.
+    if let Some(lock_file_path) = opt.lock_file {
+        let lock_file = LockFile::builder()
+            .with_solved_environment(
+                SolvedEnvironment::new()
+                    .with_channels(lock_file_channels)
+                    .with_platform(install_platform, required_packages),
+            )
+            .finish();
+        lock_file
+            .to_path(&lock_file_path)
+            .with_context(|| format!("failed to write {}", lock_file_path.display()))?;
+        if !json {
+            println!(
+                "{} Wrote lock-file to {}",
+                console::style(console::Emoji("✔", "")).green(),
+                lock_file_path.display()
+            );
+        }
+        summary.lock_file = Some(lock_file_path);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
     }
 
     Ok(())
 }
 
-/// Executes the transaction on the given environment.
-async fn execute_transaction(
+/// Returns the directory in which packages and repodata are cached.
+pub(crate) fn cache_dir(config: &Config) -> anyhow::Result<PathBuf> {
+    config
+        .cache_dir()
+        .ok_or_else(|| anyhow::anyhow!("could not determine cache directory for current platform"))
+}
+
+/// Returns the client that is used to download repodata and packages, with the mirrors and
+/// credentials of the configuration.
+pub(crate) fn download_client(
+    config: &Config,
+) -> anyhow::Result<reqwest_middleware::ClientWithMiddleware> {
+    let download_client = Client::builder()
+        .no_gzip()
+        .build()
+        .expect("failed to create client");
+
+    Ok(reqwest_middleware::ClientBuilder::new(download_client)
+        .with(MirrorMiddleware::from_config(config))
+        .with_arc(Arc::new(AuthenticationMiddleware::from_config(config)?))
+        .build())
+}
+
+/// Prints the operations of a transaction without executing them.
+pub(crate) fn print_transaction(transaction: &Transaction<&PrefixRecord, RepoDataRecord>) {
+    if transaction.operations.is_empty() {
+        println!("No operations necessary");
+    }
+
+    let format_record = |r: &RepoDataRecord| {
+        format!(
+            "{} {} {}",
+            r.package_record.name.as_normalized(),
+            r.package_record.version,
+            r.package_record.build
+        )
+    };
+
+    for operation in &transaction.operations {
+        match operation {
+            TransactionOperation::Install(r) => {
+                println!("{} {}", console::style("+").green(), format_record(r));
+            }
+            TransactionOperation::Change { old, new } => {
+                println!(
+                    "{} {} -> {}",
+                    console::style("~").yellow(),
+                    format_record(&old.repodata_record),
+                    format_record(new)
+                );
+            }
+            TransactionOperation::Reinstall(r) => {
+                println!(
+                    "{} {}",
+                    console::style("~").yellow(),
+                    format_record(&r.repodata_record)
+                );
+            }
+            TransactionOperation::Remove(r) => {
+                println!(
+                    "{} {}",
+                    console::style("-").red(),
+                    format_record(&r.repodata_record)
+                );
+            }
+        }
+    }
+}
+
+/// Executes the transaction on the given environment. The `requested_specs` are recorded in the
+/// prefix records of the packages they select.
+pub(crate) async fn execute_transaction(
     install_driver: &InstallDriver,
     transaction: Transaction<&PrefixRecord, RepoDataRecord>,
     target_prefix: PathBuf,
     cache_dir: PathBuf,
     download_client: reqwest_middleware::ClientWithMiddleware,
+    retry_policy: ExponentialBackoff,
+    requested_specs: &[MatchSpec],
 ) -> anyhow::Result<()> {
     // Open the package cache
     let package_cache = PackageCache::new(cache_dir.join("pkgs"));
@@ -375,6 +515,8 @@ async fn execute_transaction(
                     link_pb,
                     op,
                     install_options,
+                    retry_policy,
+                    requested_specs,
                 )
                 .await
             }
@@ -403,6 +545,8 @@ async fn execute_operation(
     link_pb: &ProgressBar,
     op: TransactionOperation<&PrefixRecord, RepoDataRecord>,
     install_options: &InstallOptions,
+    retry_policy: ExponentialBackoff,
+    requested_specs: &[MatchSpec],
 ) -> anyhow::Result<()> {
     // Determine the package to install
     let install_record = op.record_to_install();
@@ -424,7 +568,7 @@ async fn execute_operation(
                     &install_record.package_record,
                     install_record.url.clone(),
                     download_client.clone(),
-                    default_retry_policy(),
+                    retry_policy,
                 )
                 .map_ok(|cache_dir| Some((install_record.clone(), cache_dir)))
                 .map_err(anyhow::Error::from)
@@ -450,10 +594,17 @@ async fn execute_operation(
 
     // If there is a package to install, do that now.
     if let Some((record, package_dir)) = install_package {
+        // Keep the spec that was requested for a package that is replaced.
+        let requested_spec = requested_specs
+            .iter()
+            .find(|spec| spec.name.as_ref() == Some(&record.package_record.name))
+            .map(ToString::to_string)
+            .or_else(|| remove_record.and_then(|record| record.requested_spec.clone()));
         install_package_to_environment(
             target_prefix,
             package_dir,
             record.clone(),
+            requested_spec,
             install_driver,
             install_options,
         )
@@ -475,6 +626,7 @@ async fn install_package_to_environment(
     target_prefix: &Path,
     package_dir: PathBuf,
     repodata_record: RepoDataRecord,
+    requested_spec: Option<String>,
     install_driver: &InstallDriver,
     install_options: &InstallOptions,
 ) -> anyhow::Result<()> {
@@ -498,14 +650,14 @@ async fn install_package_to_environment(
             .map(|entry| entry.relative_path.clone())
             .collect(),
         paths_data: paths.into(),
-        // TODO: Retrieve the requested spec for this package from the request
-        requested_spec: None,
+        requested_spec,
 
         link: Some(Link {
             source: package_dir,
             // TODO: compute the right value here based on the options and `can_hard_link` ...
             link_type: Some(LinkType::HardLink),
         }),
+        installed_system_menus: Vec::new(),
     };
 
     // Create the conda-meta directory if it doesnt exist yet.
@@ -550,7 +702,10 @@ async fn remove_package_from_environment(
 }
 
 /// Displays a spinner with the given message while running the specified function to completion.
-fn wrap_in_progress<T, F: FnOnce() -> T>(msg: impl Into<Cow<'static, str>>, func: F) -> T {
+pub(crate) fn wrap_in_progress<T, F: FnOnce() -> T>(
+    msg: impl Into<Cow<'static, str>>,
+    func: F,
+) -> T {
     let pb = ProgressBar::new_spinner();
     pb.enable_steady_tick(Duration::from_millis(100));
     pb.set_style(long_running_progress_style());
@@ -561,7 +716,7 @@ fn wrap_in_progress<T, F: FnOnce() -> T>(msg: impl Into<Cow<'static, str>>, func
 }
 
 /// Displays a spinner with the given message while running the specified function to completion.
-async fn wrap_in_async_progress<T, F: IntoFuture<Output = T>>(
+pub(crate) async fn wrap_in_async_progress<T, F: IntoFuture<Output = T>>(
     msg: impl Into<Cow<'static, str>>,
     fut: F,
 ) -> T {
@@ -739,7 +894,7 @@ fn long_running_progress_style() -> indicatif::ProgressStyle {
 
 /// Scans the conda-meta directory of an environment and returns all the [`PrefixRecord`]s found in
 /// there.
-async fn find_installed_packages(
+pub(crate) async fn find_installed_packages(
     target_prefix: &Path,
     concurrency_limit: usize,
 ) -> Result<Vec<PrefixRecord>, std::io::Error> {
@@ -752,7 +907,7 @@ async fn find_installed_packages(
     {
         let entry = entry?;
         let path = entry.path();
-        if !path.ends_with(".json") {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
             continue;
         }
 
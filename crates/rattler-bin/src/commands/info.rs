@@ -0,0 +1,133 @@
+use crate::commands::{
+    create::{cache_dir, download_client, wrap_in_async_progress},
+    search::query_records,
+};
+use rattler::package_cache::PackageCache;
+use rattler_conda_types::{
+    package::{AboutJson, PackageFile, RunExportsJson},
+    MatchSpec, ParseStrictness, RepoDataRecord,
+};
+use rattler_config::Config;
+use rattler_networking::retry_policies::retry_policy_from_config;
+use serde::Serialize;
+
+/// Shows detailed information about the newest package that matches a spec.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    #[clap(short)]
+    channels: Option<Vec<String>>,
+
+    /// The package to show, e.g. `numpy` or `numpy 1.26.*`.
+    spec: String,
+
+    #[clap(long)]
+    platform: Option<String>,
+
+    /// Print the information as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+/// The information that is printed with `--json`.
+#[derive(Debug, Serialize)]
+struct PackageInfo {
+    #[serde(flatten)]
+    record: RepoDataRecord,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run_exports: Option<RunExportsJson>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    about: Option<AboutJson>,
+}
+
+pub async fn info(opt: Opt, config: &Config) -> anyhow::Result<()> {
+    let spec = MatchSpec::from_str(&opt.spec, ParseStrictness::Lenient)?;
+    let Some(record) = query_records(config, opt.channels, opt.platform.as_deref(), &spec)
+        .await?
+        .pop()
+    else {
+        anyhow::bail!("no packages found matching '{spec}'");
+    };
+
+    // The run exports and about information are not part of the repodata, they are read from the
+    // package itself.
+    let package_cache = PackageCache::new(cache_dir(config)?.join("pkgs"));
+    let package_dir = wrap_in_async_progress(
+        "fetching package",
+        package_cache.get_or_fetch_from_url_with_retry(
+            &record.package_record,
+            record.url.clone(),
+            download_client(config)?,
+            retry_policy_from_config(config),
+        ),
+    )
+    .await?;
+    let info = PackageInfo {
+        run_exports: RunExportsJson::from_package_directory(&package_dir).ok(),
+        about: AboutJson::from_package_directory(&package_dir).ok(),
+        record,
+    };
+
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    let package = &info.record.package_record;
+    println!(
+        "{} {} {}",
+        package.name.as_source(),
+        package.version,
+        package.build
+    );
+    println!("{}", "-".repeat(40));
+    println!("file name   : {}", info.record.file_name);
+    println!("channel     : {}", info.record.channel);
+    println!("subdir      : {}", package.subdir);
+    println!("url         : {}", info.record.url);
+    if let Some(license) = &package.license {
+        println!("license     : {license}");
+    }
+    if let Some(size) = package.size {
+        println!("size        : {}", indicatif::HumanBytes(size));
+    }
+    if let Some(timestamp) = package.timestamp {
+        println!("timestamp   : {timestamp}");
+    }
+    if let Some(about) = &info.about {
+        if let Some(summary) = &about.summary {
+            println!("summary     : {summary}");
+        }
+        for home in &about.home {
+            println!("home        : {home}");
+        }
+    }
+
+    print_list("depends", &package.depends);
+    print_list("constrains", &package.constrains);
+    if let Some(run_exports) = &info.run_exports {
+        print_list("run_exports (weak)", &run_exports.weak);
+        print_list("run_exports (strong)", &run_exports.strong);
+        print_list("run_exports (noarch)", &run_exports.noarch);
+        print_list(
+            "run_exports (weak_constrains)",
+            &run_exports.weak_constrains,
+        );
+        print_list(
+            "run_exports (strong_constrains)",
+            &run_exports.strong_constrains,
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints a titled list, unless it is empty.
+fn print_list(title: &str, items: &[String]) {
+    if items.is_empty() {
+        return;
+    }
+    println!("\n{title}:");
+    for item in items {
+        println!("  - {item}");
+    }
+}
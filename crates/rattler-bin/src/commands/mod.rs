@@ -1,2 +1,9 @@
+pub mod clean;
 pub mod create;
+pub mod info;
+pub mod prefix;
+pub mod remove;
+pub mod run;
+pub mod search;
+pub mod update;
 pub mod virtual_packages;
@@ -0,0 +1,289 @@
+//! Functionality shared by the commands that modify an existing environment.
+
+use crate::commands::create::{
+    cache_dir, download_client, execute_transaction, print_transaction, wrap_in_async_progress,
+    wrap_in_progress,
+};
+use anyhow::Context;
+use rattler::install::{InstallDriver, Transaction};
+use rattler_conda_types::{
+    Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, PackageName, PackageRecord,
+    ParseStrictness, Platform, PrefixRecord, RepoDataRecord,
+};
+use rattler_config::Config;
+use rattler_networking::retry_policies::retry_policy_from_config;
+use rattler_repodata_gateway::Gateway;
+use rattler_solve::{libsolv_c, resolvo, SolverImpl, SolverTask};
+use std::{collections::HashSet, env, path::PathBuf, time::Duration};
+
+/// Options to locate an existing environment and re-solve it.
+#[derive(Debug, clap::Args)]
+pub struct PrefixOpt {
+    #[clap(short)]
+    channels: Option<Vec<String>>,
+
+    #[clap(long)]
+    dry_run: bool,
+
+    #[clap(long)]
+    use_resolvo: bool,
+
+    #[clap(long)]
+    timeout: Option<u64>,
+
+    #[clap(long)]
+    target_prefix: Option<PathBuf>,
+}
+
+impl PrefixOpt {
+    /// Returns the prefix of the environment to modify.
+    pub fn target_prefix(&self) -> anyhow::Result<PathBuf> {
+        match &self.target_prefix {
+            Some(target_prefix) => Ok(target_prefix.clone()),
+            None => Ok(env::current_dir()?.join(".prefix")),
+        }
+    }
+}
+
+/// Returns the names of the installed packages that the given package depends on.
+fn dependency_names(record: &PrefixRecord) -> impl Iterator<Item = PackageName> + '_ {
+    record
+        .repodata_record
+        .package_record
+        .depends
+        .iter()
+        .filter_map(|dependency| {
+            MatchSpec::from_str(dependency, ParseStrictness::Lenient)
+                .ok()?
+                .name
+        })
+}
+
+/// Returns the names of the installed packages that no other installed package depends on. These
+/// are the packages that were most likely requested explicitly when the environment was created.
+pub fn leaf_packages(installed_packages: &[PrefixRecord]) -> Vec<PackageName> {
+    let dependencies = installed_packages
+        .iter()
+        .flat_map(dependency_names)
+        .collect::<HashSet<_>>();
+    installed_packages
+        .iter()
+        .map(|record| record.repodata_record.package_record.name.clone())
+        .filter(|name| !dependencies.contains(name))
+        .collect()
+}
+
+/// Returns the specs of the packages that were explicitly requested by the user, keyed by the name
+/// of the installed package.
+///
+/// The spec is taken from the `requested_spec` that was recorded when the package was installed.
+/// Packages that were installed without recording a spec, e.g. by an older version, are only
+/// considered requested if no other installed package depends on them, see [`leaf_packages`].
+pub fn requested_specs(installed_packages: &[PrefixRecord]) -> Vec<(PackageName, MatchSpec)> {
+    let leaves = leaf_packages(installed_packages)
+        .into_iter()
+        .collect::<HashSet<_>>();
+    installed_packages
+        .iter()
+        .filter_map(|record| {
+            let name = &record.repodata_record.package_record.name;
+            let spec = match &record.requested_spec {
+                Some(spec) => MatchSpec::from_str(spec, ParseStrictness::Lenient)
+                    .ok()
+                    .filter(|spec| spec.name.as_ref() == Some(name))
+                    .unwrap_or_else(|| MatchSpec::from(name.clone())),
+                None if leaves.contains(name) => MatchSpec::from(name.clone()),
+                None => return None,
+            };
+            Some((name.clone(), spec))
+        })
+        .collect()
+}
+
+/// Returns the given package names together with the names of all installed packages that
+/// (transitively) depend on them.
+pub fn with_dependents(
+    installed_packages: &[PrefixRecord],
+    names: HashSet<PackageName>,
+) -> HashSet<PackageName> {
+    let mut result = names;
+    loop {
+        let dependents = installed_packages
+            .iter()
+            .filter(|record| !result.contains(&record.repodata_record.package_record.name))
+            .filter(|record| dependency_names(record).any(|name| result.contains(&name)))
+            .map(|record| record.repodata_record.package_record.name.clone())
+            .collect::<Vec<_>>();
+        if dependents.is_empty() {
+            return result;
+        }
+        result.extend(dependents);
+    }
+}
+
+/// Solves the environment for the given specs, preferring the versions of the locked packages, and
+/// applies the difference to the packages that are currently installed.
+pub async fn solve_and_apply(
+    opt: PrefixOpt,
+    config: &Config,
+    target_prefix: PathBuf,
+    installed_packages: &[PrefixRecord],
+    specs: Vec<MatchSpec>,
+    locked_packages: Vec<RepoDataRecord>,
+) -> anyhow::Result<()> {
+    let channel_config = ChannelConfig::default_with_root_dir(env::current_dir()?);
+    let install_platform = config.platform();
+
+    // Find the default cache directory. Create it if it doesnt exist yet.
+    let cache_dir = cache_dir(config)?;
+    std::fs::create_dir_all(&cache_dir)
+        .map_err(|e| anyhow::anyhow!("could not create cache directory: {e}"))?;
+
+    let channels = opt
+        .channels
+        .unwrap_or_else(|| config.default_channels())
+        .into_iter()
+        .map(|channel_str| Channel::from_str(channel_str, &channel_config))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let download_client = download_client(config)?;
+    let gateway = Gateway::builder()
+        .with_cache_dir(cache_dir.join("repodata"))
+        .with_client(download_client.clone())
+        .finish();
+
+    let repo_data = wrap_in_async_progress(
+        "loading repodata",
+        gateway
+            .query(
+                channels,
+                [install_platform, Platform::NoArch],
+                specs.clone(),
+            )
+            .recursive(true),
+    )
+    .await
+    .context("failed to load repodata")?;
+
+    let virtual_packages = rattler_virtual_packages::VirtualPackage::current()?
+        .iter()
+        .map(|vpkg| GenericVirtualPackage::from(vpkg.clone()))
+        .collect::<Vec<_>>();
+
+    let solver_task = SolverTask {
+        locked_packages,
+        virtual_packages,
+        specs: specs.clone(),
+        timeout: opt.timeout.map(Duration::from_millis),
+        channel_priority: config.channel_priority().into(),
+        ..SolverTask::from_iter(&repo_data)
+    };
+
+    let use_resolvo = opt.use_resolvo;
+    let required_packages = wrap_in_progress("solving", move || {
+        if use_resolvo {
+            resolvo::Solver.solve(solver_task)
+        } else {
+            libsolv_c::Solver.solve(solver_task)
+        }
+    })?;
+    let required_packages = PackageRecord::sort_topologically(required_packages);
+
+    let transaction = Transaction::from_current_and_desired(
+        installed_packages,
+        required_packages,
+        install_platform,
+    )?;
+
+    if opt.dry_run {
+        print_transaction(&transaction);
+        return Ok(());
+    }
+
+    if transaction.operations.is_empty() {
+        println!(
+            "{} Already up to date",
+            console::style(console::Emoji("✔", "")).green(),
+        );
+        return Ok(());
+    }
+
+    let install_driver = InstallDriver::builder()
+        .with_prefix_records(installed_packages)
+        .execute_link_scripts(true)
+        .with_io_concurrency_limit(100)
+        .finish();
+    execute_transaction(
+        &install_driver,
+        transaction,
+        target_prefix,
+        cache_dir,
+        download_client,
+        retry_policy_from_config(config),
+        &specs,
+    )
+    .await?;
+    println!(
+        "{} Successfully updated the environment",
+        console::style(console::Emoji("✔", "")).green(),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::requested_specs;
+    use rattler_conda_types::{PackageName, PackageRecord, PrefixRecord, RepoDataRecord, Version};
+    use std::str::FromStr;
+
+    fn prefix_record(name: &str, depends: &[&str], requested_spec: Option<&str>) -> PrefixRecord {
+        let mut package_record = PackageRecord::new(
+            PackageName::new_unchecked(name),
+            Version::from_str("1.0").unwrap(),
+            "0".to_string(),
+        );
+        package_record.depends = depends.iter().map(ToString::to_string).collect();
+        PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record,
+                file_name: format!("{name}-1.0-0.tar.bz2"),
+                url: format!("https://conda.anaconda.org/conda-forge/noarch/{name}-1.0-0.tar.bz2")
+                    .parse()
+                    .unwrap(),
+                channel: "https://conda.anaconda.org/conda-forge/".to_string(),
+            },
+            None,
+            None,
+            Vec::new(),
+            requested_spec.map(ToString::to_string),
+            None,
+        )
+    }
+
+    fn requested(installed_packages: &[PrefixRecord]) -> Vec<String> {
+        requested_specs(installed_packages)
+            .into_iter()
+            .map(|(_, spec)| spec.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_requested_specs() {
+        // Both python and numpy were requested, although numpy depends on python.
+        let installed_packages = [
+            prefix_record("python", &["libzlib"], Some("python >=3.11")),
+            prefix_record("numpy", &["python >=3.9"], Some("numpy")),
+            prefix_record("libzlib", &[], None),
+        ];
+        assert_eq!(requested(&installed_packages), ["python >=3.11", "numpy"]);
+
+        // Without recorded specs only the packages nothing depends on are considered requested.
+        let installed_packages = [
+            prefix_record("python", &["libzlib"], None),
+            prefix_record("numpy", &["python >=3.9"], None),
+            prefix_record("libzlib", &[], None),
+        ];
+        assert_eq!(requested(&installed_packages), ["numpy"]);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::commands::{
+    create::find_installed_packages,
+    prefix::{requested_specs, solve_and_apply, with_dependents, PrefixOpt},
+};
+use anyhow::Context;
+use rattler_conda_types::{MatchSpec, ParseStrictness};
+use rattler_config::Config;
+use std::collections::HashSet;
+
+/// Removes packages from an existing environment.
+///
+/// Packages that depend on the removed packages are removed as well. The remaining environment is
+/// re-solved so that dependencies that are no longer required are removed too.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    #[clap(required = true)]
+    specs: Vec<String>,
+
+    #[clap(flatten)]
+    prefix: PrefixOpt,
+}
+
+pub async fn remove(opt: Opt, config: &Config) -> anyhow::Result<()> {
+    let target_prefix = opt.prefix.target_prefix()?;
+    println!("Target prefix: {}", target_prefix.display());
+
+    let specs = opt
+        .specs
+        .iter()
+        .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Strict))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let installed_packages = find_installed_packages(&target_prefix, 100)
+        .await
+        .context("failed to determine currently installed packages")?;
+
+    // Determine the installed packages that match the specs.
+    let mut removed = HashSet::new();
+    for spec in &specs {
+        let matching = installed_packages
+            .iter()
+            .map(|record| &record.repodata_record.package_record)
+            .filter(|record| spec.matches(record))
+            .map(|record| record.name.clone())
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            anyhow::bail!("no installed package matches '{spec}'");
+        }
+        removed.extend(matching);
+    }
+    let removed = with_dependents(&installed_packages, removed);
+
+    // Keep all the packages that were explicitly installed, and any of their dependencies, except
+    // for the removed packages.
+    let remaining_specs = requested_specs(&installed_packages)
+        .into_iter()
+        .filter(|(name, _)| !removed.contains(name))
+        .map(|(_, spec)| spec)
+        .collect();
+    let locked_packages = installed_packages
+        .iter()
+        .filter(|record| !removed.contains(&record.repodata_record.package_record.name))
+        .map(|record| record.repodata_record.clone())
+        .collect();
+
+    solve_and_apply(
+        opt.prefix,
+        config,
+        target_prefix,
+        &installed_packages,
+        remaining_specs,
+        locked_packages,
+    )
+    .await
+}
@@ -0,0 +1,91 @@
+use anyhow::Context;
+use rattler_shell::{activated_command, ActivationMethod};
+use std::{path::PathBuf, process::ExitStatus};
+use tokio::process::{Child, Command};
+
+/// Runs a command in an activated environment.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The prefix of the environment to activate.
+    #[clap(short, long)]
+    prefix: PathBuf,
+
+    /// Compute the activated environment without running the activation scripts of the packages.
+    #[clap(long)]
+    emulate: bool,
+
+    /// The command to run followed by its arguments.
+    #[clap(required = true, last = true)]
+    command: Vec<String>,
+}
+
+pub async fn run(opt: Opt) -> anyhow::Result<()> {
+    let method = if opt.emulate {
+        ActivationMethod::Emulate
+    } else {
+        ActivationMethod::default()
+    };
+
+    let (program, args) = opt
+        .command
+        .split_first()
+        .expect("clap requires at least one argument");
+    let mut command = activated_command(&opt.prefix, program, &method)
+        .with_context(|| format!("failed to activate {}", opt.prefix.display()))?;
+    command.args(args);
+
+    let child = Command::from(command)
+        .spawn()
+        .with_context(|| format!("failed to run '{program}'"))?;
+    let status = wait_forwarding_signals(child).await?;
+
+    std::process::exit(exit_code(status))
+}
+
+/// Waits for the child to exit. The child receives an interrupt from the terminal itself, so
+/// interrupts are ignored to give it the chance to shut down. Termination requests are forwarded.
+#[cfg(unix)]
+async fn wait_forwarding_signals(mut child: Child) -> std::io::Result<ExitStatus> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    loop {
+        let signal = tokio::select! {
+            status = child.wait() => return status,
+            _ = interrupt.recv() => continue,
+            _ = terminate.recv() => libc::SIGTERM,
+            _ = hangup.recv() => libc::SIGHUP,
+        };
+        if let Some(pid) = child.id().and_then(|pid| libc::pid_t::try_from(pid).ok()) {
+            // SAFETY: `kill` has no memory safety requirements.
+            unsafe { libc::kill(pid, signal) };
+        }
+    }
+}
+
+/// Waits for the child to exit. The child receives Ctrl-C from the console itself, so it is
+/// ignored to give the child the chance to shut down.
+#[cfg(not(unix))]
+async fn wait_forwarding_signals(mut child: Child) -> std::io::Result<ExitStatus> {
+    loop {
+        tokio::select! {
+            status = child.wait() => return status,
+            _ = tokio::signal::ctrl_c() => {},
+        }
+    }
+}
+
+/// Returns the exit code to exit with for the exit status of the child. Like shells do, a child
+/// that was killed by a signal results in `128 + signal`.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
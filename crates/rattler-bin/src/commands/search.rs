@@ -0,0 +1,113 @@
+use crate::commands::create::{cache_dir, download_client, wrap_in_async_progress};
+use anyhow::Context;
+use indicatif::HumanBytes;
+use rattler_conda_types::{
+    Channel, ChannelConfig, MatchSpec, ParseStrictness, Platform, RepoDataRecord,
+};
+use rattler_config::Config;
+use rattler_repodata_gateway::Gateway;
+use std::{env, str::FromStr};
+
+/// Searches the channels for packages that match a spec.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    #[clap(short)]
+    channels: Option<Vec<String>>,
+
+    /// The spec to search for, e.g. `python >=3.11`.
+    spec: String,
+
+    #[clap(long)]
+    platform: Option<String>,
+
+    /// Print the matching records as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+pub async fn search(opt: Opt, config: &Config) -> anyhow::Result<()> {
+    let spec = MatchSpec::from_str(&opt.spec, ParseStrictness::Lenient)?;
+    let records = query_records(config, opt.channels, opt.platform.as_deref(), &spec).await?;
+
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    if records.is_empty() {
+        anyhow::bail!("no packages found matching '{spec}'");
+    }
+
+    for record in &records {
+        let package = &record.package_record;
+        println!(
+            "{:<24} {:<16} {:<28} {:<12} {:>10} {:<10} {}",
+            package.name.as_normalized(),
+            package.version,
+            package.build,
+            package.subdir,
+            package
+                .size
+                .map(|size| HumanBytes(size).to_string())
+                .unwrap_or_default(),
+            package
+                .timestamp
+                .map(|timestamp| timestamp.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+            record.channel,
+        );
+    }
+
+    Ok(())
+}
+
+/// Queries the channels for the records that match the given spec, for the given platform and
+/// `noarch`. The records are sorted from oldest to newest.
+pub(crate) async fn query_records(
+    config: &Config,
+    channels: Option<Vec<String>>,
+    platform: Option<&str>,
+    spec: &MatchSpec,
+) -> anyhow::Result<Vec<RepoDataRecord>> {
+    let channel_config = ChannelConfig::default_with_root_dir(env::current_dir()?);
+    let platform = match platform {
+        Some(platform) => Platform::from_str(platform)?,
+        None => config.platform(),
+    };
+    if spec.name.is_none() {
+        anyhow::bail!("'{spec}' does not specify a package name");
+    }
+
+    let channels = channels
+        .unwrap_or_else(|| config.default_channels())
+        .into_iter()
+        .map(|channel_str| Channel::from_str(channel_str, &channel_config))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let cache_dir = cache_dir(config)?;
+    let gateway = Gateway::builder()
+        .with_cache_dir(cache_dir.join("repodata"))
+        .with_client(download_client(config)?)
+        .finish();
+
+    let repo_data = wrap_in_async_progress(
+        "loading repodata",
+        gateway.query(channels, [platform, Platform::NoArch], [spec.clone()]),
+    )
+    .await
+    .context("failed to load repodata")?;
+
+    let mut records = repo_data
+        .iter()
+        .flat_map(|repo_data| repo_data.iter())
+        .filter(|record| spec.matches(&record.package_record))
+        .cloned()
+        .collect::<Vec<_>>();
+    records.sort_by(|a, b| {
+        let a = &a.package_record;
+        let b = &b.package_record;
+        (&a.version, a.build_number, a.timestamp).cmp(&(&b.version, b.build_number, b.timestamp))
+    });
+
+    Ok(records)
+}
@@ -0,0 +1,81 @@
+use crate::commands::{
+    create::find_installed_packages,
+    prefix::{requested_specs, solve_and_apply, PrefixOpt},
+};
+use anyhow::Context;
+use rattler_conda_types::{MatchSpec, ParseStrictness};
+use rattler_config::Config;
+use std::collections::HashSet;
+
+/// Updates packages in an existing environment to the newest versions that are available.
+///
+/// If no specs are given all packages are updated. Otherwise only the packages that match the
+/// specs are updated and all other packages are kept at their current version if possible.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    specs: Vec<String>,
+
+    #[clap(flatten)]
+    prefix: PrefixOpt,
+}
+
+pub async fn update(opt: Opt, config: &Config) -> anyhow::Result<()> {
+    let target_prefix = opt.prefix.target_prefix()?;
+    println!("Target prefix: {}", target_prefix.display());
+
+    let specs = opt
+        .specs
+        .iter()
+        .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Strict))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let installed_packages = find_installed_packages(&target_prefix, 100)
+        .await
+        .context("failed to determine currently installed packages")?;
+    if installed_packages.is_empty() {
+        anyhow::bail!("no packages are installed in {}", target_prefix.display());
+    }
+
+    // Determine the names of the packages to update.
+    let mut updated = HashSet::new();
+    for spec in &specs {
+        let Some(name) = spec.name.clone() else {
+            anyhow::bail!("'{spec}' does not specify a package name");
+        };
+        if !installed_packages
+            .iter()
+            .any(|record| record.repodata_record.package_record.name == name)
+        {
+            anyhow::bail!("'{}' is not installed", name.as_source());
+        }
+        updated.insert(name);
+    }
+
+    // Request all the packages that were explicitly installed. The specs that were passed replace
+    // the request for a package of the same name.
+    let mut update_specs = requested_specs(&installed_packages)
+        .into_iter()
+        .filter(|(name, _)| !updated.contains(name))
+        .map(|(_, spec)| spec)
+        .collect::<Vec<_>>();
+    update_specs.extend(specs);
+
+    // Without locking a package the solver favors its newest version.
+    let locked_packages = installed_packages
+        .iter()
+        .filter(|record| {
+            !updated.is_empty() && !updated.contains(&record.repodata_record.package_record.name)
+        })
+        .map(|record| record.repodata_record.clone())
+        .collect();
+
+    solve_and_apply(
+        opt.prefix,
+        config,
+        target_prefix,
+        &installed_packages,
+        update_specs,
+        locked_packages,
+    )
+    .await
+}
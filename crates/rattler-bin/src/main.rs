@@ -37,7 +37,13 @@ struct Opt {
 /// Different commands supported by `rattler`.
 #[derive(Debug, clap::Subcommand)]
 enum Command {
+    Clean(commands::clean::Opt),
     Create(commands::create::Opt),
+    Info(commands::info::Opt),
+    Remove(commands::remove::Opt),
+    Run(commands::run::Opt),
+    Search(commands::search::Opt),
+    Update(commands::update::Opt),
     VirtualPackages(commands::virtual_packages::Opt),
 }
 
@@ -68,9 +74,18 @@ async fn main() -> anyhow::Result<()> {
         .finish()
         .try_init()?;
 
+    // Load the configuration shared by all commands.
+    let config = rattler_config::Config::load()?;
+
     // Dispatch the selected comment
     match opt.command {
-        Command::Create(opts) => commands::create::create(opts).await,
+        Command::Clean(opts) => commands::clean::clean(opts, &config).await,
+        Command::Create(opts) => commands::create::create(opts, &config).await,
+        Command::Info(opts) => commands::info::info(opts, &config).await,
+        Command::Remove(opts) => commands::remove::remove(opts, &config).await,
+        Command::Run(opts) => commands::run::run(opts).await,
+        Command::Search(opts) => commands::search::search(opts, &config).await,
+        Command::Update(opts) => commands::update::update(opts, &config).await,
         Command::VirtualPackages(opts) => commands::virtual_packages::virtual_packages(opts),
     }
 }
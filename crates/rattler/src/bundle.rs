@@ -0,0 +1,336 @@
+//! Offline bundles of the packages of a lock file, see [`export_bundle`] and [`Bundle`].
+//!
+//! A bundle is a directory that contains the archives of all conda packages of a lock file,
+//! organized as a local channel: every subdirectory contains the archives of its platform and a
+//! `repodata.json` that describes them. The lock file itself is stored next to it. The directory
+//! can be copied, or archived with any tool, to a machine without network access and installed
+//! from there.
+//!
+//! Installing from a bundle uses the records of the lock file with their URLs pointing into the
+//! bundle, see [`Bundle::records`]. Because the bundle is a regular channel it can also be used to
+//! solve new environments that only use the bundled packages, see [`Bundle::channel`].
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use rattler_conda_types::{Channel, ChannelInfo, Platform, RepoData, RepoDataRecord};
+use rattler_lock::{ConversionError, LockFile, ParseCondaLockError};
+use url::Url;
+
+use crate::download::{DownloadError, DownloadManager, DownloadRequest};
+
+/// The name of the lock file in a bundle.
+const LOCK_FILE_NAME: &str = "bundle.lock";
+
+/// An error that can occur while exporting or reading a bundle.
+#[derive(Debug, thiserror::Error)]
+pub enum BundleError {
+    /// The records of the lock file could not be read.
+    #[error("failed to read the packages of the lock file")]
+    InvalidLockFile(#[source] ConversionError),
+
+    /// The lock file contains two different packages with the same file name for a platform.
+    #[error("the lock file contains different packages named '{0}'")]
+    ConflictingPackages(String),
+
+    /// A package could not be downloaded into the bundle.
+    #[error("failed to download the packages of the bundle")]
+    Download(#[source] Box<DownloadError>),
+
+    /// A file of the bundle could not be written.
+    #[error("failed to write '{}'", .0.display())]
+    Write(PathBuf, #[source] std::io::Error),
+
+    /// The lock file of the bundle could not be read.
+    #[error("failed to read the lock file of the bundle at '{}'", .0.display())]
+    ReadLockFile(PathBuf, #[source] Box<ParseCondaLockError>),
+
+    /// The lock file does not contain the requested environment.
+    #[error("the bundle does not contain the environment '{0}'")]
+    UnknownEnvironment(String),
+
+    /// The environment is not locked for the requested platform.
+    #[error("the environment is not locked for {0}")]
+    PlatformNotLocked(Platform),
+}
+
+/// Downloads all conda packages of all environments and platforms of `lock_file` into the
+/// directory `destination` and writes the repodata and the lock file next to them.
+///
+/// Downloads are verified against the hashes of the lock file. Archives that are already present
+/// in `destination` are not downloaded again, so an existing bundle can be updated with a newer
+/// lock file.
+pub async fn export_bundle(
+    lock_file: &LockFile,
+    destination: &Path,
+    download_manager: &DownloadManager,
+) -> Result<Bundle, BundleError> {
+    // Group the packages by subdirectory and file name, which is their location in the bundle.
+    let mut subdirs = BTreeMap::<String, BTreeMap<String, RepoDataRecord>>::new();
+    subdirs.insert(Platform::NoArch.to_string(), BTreeMap::new());
+    for (_, environment) in lock_file.environments() {
+        let records = environment
+            .conda_repodata_records()
+            .map_err(BundleError::InvalidLockFile)?;
+        for record in records.into_values().flatten() {
+            let packages = subdirs
+                .entry(record.package_record.subdir.clone())
+                .or_default();
+            match packages.get(&record.file_name) {
+                Some(existing) if existing.url == record.url => {}
+                Some(existing)
+                    if existing.package_record.sha256.is_some()
+                        && existing.package_record.sha256 == record.package_record.sha256 => {}
+                Some(_) => return Err(BundleError::ConflictingPackages(record.file_name)),
+                None => {
+                    packages.insert(record.file_name.clone(), record);
+                }
+            }
+        }
+    }
+
+    let requests = subdirs
+        .iter()
+        .flat_map(|(subdir, packages)| {
+            let directory = destination.join(subdir);
+            packages
+                .values()
+                .map(move |record| DownloadRequest::for_record(record, &directory))
+        })
+        .collect::<Vec<_>>();
+    for subdir in subdirs.keys() {
+        let directory = destination.join(subdir);
+        fs::create_dir_all(&directory).map_err(|e| BundleError::Write(directory, e))?;
+    }
+    download_manager
+        .download_all(requests)
+        .await
+        .map_err(|e| BundleError::Download(Box::new(e)))?;
+
+    for (subdir, packages) in subdirs {
+        let mut repodata = RepoData {
+            info: Some(ChannelInfo {
+                subdir: subdir.clone(),
+                base_url: None,
+            }),
+            packages: HashMap::default(),
+            conda_packages: HashMap::default(),
+            removed: HashSet::default(),
+            version: Some(1),
+        };
+        for (file_name, record) in packages {
+            if file_name.ends_with(".conda") {
+                repodata
+                    .conda_packages
+                    .insert(file_name, record.package_record);
+            } else {
+                repodata.packages.insert(file_name, record.package_record);
+            }
+        }
+        let path = destination.join(&subdir).join("repodata.json");
+        let contents = serde_json::to_vec(&repodata)
+            .map_err(|e| BundleError::Write(path.clone(), std::io::Error::other(e)))?;
+        fs::write(&path, contents).map_err(|e| BundleError::Write(path, e))?;
+    }
+
+    let path = destination.join(LOCK_FILE_NAME);
+    lock_file
+        .to_path(&path)
+        .map_err(|e| BundleError::Write(path, e))?;
+
+    Ok(Bundle {
+        path: destination.to_path_buf(),
+        lock_file: lock_file.clone(),
+    })
+}
+
+/// A bundle that was created with [`export_bundle`].
+#[derive(Clone)]
+pub struct Bundle {
+    path: PathBuf,
+    lock_file: LockFile,
+}
+
+impl Bundle {
+    /// Opens the bundle in the directory `path`.
+    pub fn open(path: &Path) -> Result<Self, BundleError> {
+        let lock_file_path = path.join(LOCK_FILE_NAME);
+        let lock_file = LockFile::from_path(&lock_file_path)
+            .map_err(|e| BundleError::ReadLockFile(lock_file_path, Box::new(e)))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            lock_file,
+        })
+    }
+
+    /// Returns the directory of the bundle.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the lock file the bundle was created from. The URLs of the packages in the lock
+    /// file refer to their original location.
+    pub fn lock_file(&self) -> &LockFile {
+        &self.lock_file
+    }
+
+    /// Returns the bundle as a local channel.
+    pub fn channel(&self) -> Channel {
+        Channel::from_directory(&self.path)
+    }
+
+    /// Returns the records of an environment of the lock file for the given platform. The URLs of
+    /// the records refer to the archives in the bundle, so they can be installed without network
+    /// access, for instance with [`crate::install::Installer::install`].
+    pub fn records(
+        &self,
+        environment: &str,
+        platform: Platform,
+    ) -> Result<Vec<RepoDataRecord>, BundleError> {
+        let records = self
+            .lock_file
+            .environment(environment)
+            .ok_or_else(|| BundleError::UnknownEnvironment(environment.to_string()))?
+            .conda_repodata_records_for_platform(platform)
+            .map_err(BundleError::InvalidLockFile)?
+            .ok_or(BundleError::PlatformNotLocked(platform))?;
+
+        let channel = self.channel();
+        Ok(records
+            .into_iter()
+            .map(|mut record| {
+                record.url = bundle_url(&channel.base_url, &record);
+                record.channel = channel.base_url.to_string();
+                record
+            })
+            .collect())
+    }
+}
+
+/// Returns the URL of the archive of `record` in the bundle at `base_url`.
+fn bundle_url(base_url: &Url, record: &RepoDataRecord) -> Url {
+    let mut url = base_url.clone();
+    url.path_segments_mut()
+        .expect("the bundle is a directory")
+        .pop_if_empty()
+        .extend([record.package_record.subdir.as_str(), &record.file_name]);
+    url
+}
+
+#[cfg(test)]
+mod test {
+    use std::{future::IntoFuture, net::SocketAddr, path::Path};
+
+    use axum::Router;
+    use rattler_conda_types::{
+        package::IndexJson, PackageRecord, Platform, RepoData, RepoDataRecord,
+    };
+    use rattler_lock::{LockFile, DEFAULT_ENVIRONMENT_NAME};
+    use tempfile::tempdir;
+    use tower_http::services::ServeDir;
+    use url::Url;
+
+    use super::{export_bundle, Bundle, BundleError};
+    use crate::{
+        download::DownloadManager,
+        install::{test_utils::build_package, Installer},
+        package_cache::PackageCache,
+    };
+
+    fn record(archive: &Path, base_url: &Url) -> RepoDataRecord {
+        let index_json =
+            rattler_package_streaming::seek::read_package_file::<IndexJson>(archive).unwrap();
+        let file_name = archive.file_name().unwrap().to_string_lossy().into_owned();
+        RepoDataRecord {
+            package_record: PackageRecord::from_index_json(
+                index_json,
+                Some(std::fs::metadata(archive).unwrap().len()),
+                Some(
+                    rattler_digest::compute_file_digest::<rattler_digest::Sha256>(archive).unwrap(),
+                ),
+                None,
+            )
+            .unwrap(),
+            url: base_url.join(&file_name).unwrap(),
+            file_name,
+            channel: base_url.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_and_install_bundle() {
+        let channel = tempdir().unwrap();
+        let noarch = channel.path().join("noarch");
+        std::fs::create_dir_all(&noarch).unwrap();
+        let foo = build_package(&noarch, "foo", "1.0", &["bar"], &[("foo.txt", "foo")]);
+        let bar = build_package(&noarch, "bar", "1.0", &[], &[("bar.txt", "bar")]);
+
+        let listener = tokio::net::TcpListener::bind(SocketAddr::new([127, 0, 0, 1].into(), 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let router = Router::new().route_service("/*key", ServeDir::new(channel.path()));
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        let base_url = Url::parse(&format!("http://localhost:{port}/noarch/")).unwrap();
+
+        let platform = Platform::current();
+        let lock_file = LockFile::builder()
+            .with_conda_package(
+                DEFAULT_ENVIRONMENT_NAME,
+                platform,
+                record(&foo, &base_url).into(),
+            )
+            .with_conda_package(
+                DEFAULT_ENVIRONMENT_NAME,
+                platform,
+                record(&bar, &base_url).into(),
+            )
+            .finish();
+
+        let bundle_dir = tempdir().unwrap();
+        let download_manager = DownloadManager::builder(reqwest::Client::default().into()).finish();
+        export_bundle(&lock_file, bundle_dir.path(), &download_manager)
+            .await
+            .unwrap();
+
+        // The bundle is a channel with repodata for the packages.
+        let repodata: RepoData = serde_json::from_str(
+            &std::fs::read_to_string(bundle_dir.path().join("noarch/repodata.json")).unwrap(),
+        )
+        .unwrap();
+        let mut packages = repodata.packages.keys().cloned().collect::<Vec<_>>();
+        packages.sort();
+        assert_eq!(packages, ["bar-1.0-0.tar.bz2", "foo-1.0-0.tar.bz2"]);
+        assert!(bundle_dir.path().join("noarch/foo-1.0-0.tar.bz2").is_file());
+
+        // The records of the bundle refer to the archives in the bundle.
+        let bundle = Bundle::open(bundle_dir.path()).unwrap();
+        let records = bundle.records(DEFAULT_ENVIRONMENT_NAME, platform).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.url.scheme() == "file"));
+        assert!(matches!(
+            bundle.records("unknown", platform),
+            Err(BundleError::UnknownEnvironment(_))
+        ));
+
+        // Install from the bundle with an empty package cache.
+        let prefix = tempdir().unwrap();
+        let cache = tempdir().unwrap();
+        Installer::new()
+            .with_package_cache(PackageCache::new(cache.path()))
+            .install(prefix.path(), records)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(prefix.path().join("foo.txt")).unwrap(),
+            "foo"
+        );
+        assert_eq!(
+            std::fs::read_to_string(prefix.path().join("bar.txt")).unwrap(),
+            "bar"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Disk usage of prefixes and package caches that accounts for hard links. See
+//! [`DiskUsageReport::compute`].
+//!
+//! Packages are usually hard linked from the package cache into the prefixes that use them, so
+//! the same file on disk shows up in several directories. Summing the sizes of the directories,
+//! like `du` does per directory, counts these files multiple times. This module identifies files
+//! by their device and inode (or volume and file index on Windows) and reports, for every
+//! directory, which part of its size is unique to it and which part is shared with other
+//! directories.
+//!
+//! Sizes are the apparent sizes of regular files. Directories and symbolic links are not counted.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// The disk usage of a single directory in a [`DiskUsageReport`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// The directory.
+    pub path: PathBuf,
+
+    /// The size of all files in the directory. Files that are hard linked multiple times within
+    /// the directory are only counted once.
+    pub total_bytes: u64,
+
+    /// The size of the files that are only linked from this directory. This is the space that is
+    /// freed when the directory is removed.
+    pub unique_bytes: u64,
+
+    /// The size of the files that are also linked from another directory, whether that directory
+    /// is part of the report or not.
+    pub shared_bytes: u64,
+}
+
+/// The disk usage of a set of directories, see [`DiskUsageReport::compute`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskUsageReport {
+    /// The disk usage of every directory, in the order they were passed.
+    pub directories: Vec<DiskUsage>,
+
+    /// The size of all files in all directories, counting every file on disk only once.
+    pub total_bytes: u64,
+}
+
+/// A file on disk that was found in one or more directories.
+struct FileUsage {
+    size: u64,
+    links: u64,
+    found: u64,
+    directories: Vec<usize>,
+}
+
+impl DiskUsageReport {
+    /// Computes the disk usage of the given directories, e.g. the package cache and the prefixes
+    /// that use it. Directories that do not exist are reported as empty.
+    ///
+    /// A file is unique to a directory if all of its hard links are located in that directory.
+    /// Because the number of links is known for every file, files that are shared with
+    /// directories that are not part of the report are also detected.
+    pub fn compute(
+        directories: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> std::io::Result<Self> {
+        let mut report = DiskUsageReport {
+            directories: directories
+                .into_iter()
+                .map(|path| DiskUsage {
+                    path: path.as_ref().to_path_buf(),
+                    ..DiskUsage::default()
+                })
+                .collect(),
+            total_bytes: 0,
+        };
+
+        let mut files = HashMap::<sys::FileId, FileUsage>::new();
+        for (idx, directory) in report.directories.iter().enumerate() {
+            if !directory.path.is_dir() {
+                continue;
+            }
+            for entry in walkdir::WalkDir::new(&directory.path) {
+                let entry = entry?;
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+                let metadata = entry.metadata()?;
+                let (id, links) = sys::file_id(entry.path(), &metadata)?;
+                let file = files.entry(id).or_insert_with(|| FileUsage {
+                    size: metadata.len(),
+                    links,
+                    found: 0,
+                    directories: Vec::new(),
+                });
+                file.found += 1;
+                if !file.directories.contains(&idx) {
+                    file.directories.push(idx);
+                }
+            }
+        }
+
+        for file in files.into_values() {
+            report.total_bytes += file.size;
+            let is_unique = file.directories.len() == 1 && file.found >= file.links;
+            for idx in file.directories {
+                let usage = &mut report.directories[idx];
+                usage.total_bytes += file.size;
+                if is_unique {
+                    usage.unique_bytes += file.size;
+                } else {
+                    usage.shared_bytes += file.size;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{fs::Metadata, io, os::unix::fs::MetadataExt, path::Path};
+
+    pub(super) type FileId = (u64, u64);
+
+    /// Returns the identity of a file and its number of hard links.
+    pub(super) fn file_id(_path: &Path, metadata: &Metadata) -> io::Result<(FileId, u64)> {
+        Ok(((metadata.dev(), metadata.ino()), metadata.nlink()))
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{fs::Metadata, io, os::windows::io::AsRawHandle, path::Path};
+
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION,
+    };
+
+    pub(super) type FileId = (u64, u64);
+
+    /// Returns the identity of a file and its number of hard links.
+    pub(super) fn file_id(path: &Path, _metadata: &Metadata) -> io::Result<(FileId, u64)> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the handle is valid for the lifetime of `file` and `info` is a plain struct
+        // that is filled in by the call.
+        let info = unsafe {
+            let mut info: BY_HANDLE_FILE_INFORMATION = std::mem::zeroed();
+            if GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            info
+        };
+        let index = (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow);
+        Ok((
+            (u64::from(info.dwVolumeSerialNumber), index),
+            u64::from(info.nNumberOfLinks),
+        ))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::{fs::Metadata, io, path::Path};
+
+    pub(super) type FileId = (u64, u64);
+
+    pub(super) fn file_id(_path: &Path, _metadata: &Metadata) -> io::Result<(FileId, u64)> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DiskUsageReport;
+
+    #[test]
+    fn test_disk_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("pkgs");
+        let env_a = dir.path().join("env-a");
+        let env_b = dir.path().join("env-b");
+        for path in [&cache, &env_a, &env_b] {
+            std::fs::create_dir_all(path).unwrap();
+        }
+
+        std::fs::write(cache.join("shared.txt"), [0u8; 100]).unwrap();
+        std::fs::write(cache.join("cached.txt"), [0u8; 50]).unwrap();
+        std::fs::hard_link(cache.join("shared.txt"), env_a.join("shared.txt")).unwrap();
+        std::fs::hard_link(cache.join("shared.txt"), env_b.join("shared.txt")).unwrap();
+        std::fs::write(env_b.join("own.txt"), [0u8; 10]).unwrap();
+        std::fs::hard_link(env_b.join("own.txt"), env_b.join("own-link.txt")).unwrap();
+
+        let report = DiskUsageReport::compute([&cache, &env_a, &env_b]).unwrap();
+        assert_eq!(report.total_bytes, 160);
+        let usage = report
+            .directories
+            .iter()
+            .map(|usage| (usage.total_bytes, usage.unique_bytes, usage.shared_bytes))
+            .collect::<Vec<_>>();
+        assert_eq!(usage, [(150, 50, 100), (100, 0, 100), (110, 10, 100)]);
+
+        // Links outside the reported directories are also detected.
+        let report = DiskUsageReport::compute([&env_b, &dir.path().join("missing")]).unwrap();
+        assert_eq!(report.total_bytes, 110);
+        assert_eq!(report.directories[0].unique_bytes, 10);
+        assert_eq!(report.directories[0].shared_bytes, 100);
+        assert_eq!(report.directories[1].total_bytes, 0);
+    }
+}
@@ -0,0 +1,1310 @@
+//! Downloading files, typically the package archives of a transaction. See [`DownloadManager`].
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use file_url::{to_long_path, url_to_path};
+use futures::{Stream, StreamExt};
+use rattler_conda_types::RepoDataRecord;
+use rattler_digest::{Md5, Md5Hash, Sha256, Sha256Hash};
+use rattler_networking::{
+    file_share::{connect_file_share, unc_share},
+    retry_policies::{default_retry_policy, retry_policy_from_config, RetryDecision, RetryPolicy},
+    AuthenticationStorage, Redact, RedactedUrl,
+};
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{broadcast, Semaphore},
+};
+use tokio_stream::wrappers::BroadcastStream;
+use url::Url;
+
+/// The default maximum number of files that are downloaded concurrently.
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 50;
+
+/// The default maximum number of files that are downloaded concurrently from a single host.
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS_PER_HOST: usize = 10;
+
+/// The name of the file in a subdirectory of a channel that advertises the binary deltas that are
+/// available for the packages in the subdirectory. See [`DeltaIndex`].
+pub const DELTA_INDEX_FILE_NAME: &str = "deltas.json";
+
+/// The extension that is appended to the destination of a download while it is in progress.
+const PARTIAL_EXTENSION: &str = "partial";
+
+/// The extension that is appended to the destination of a download for the binary delta that is
+/// used to reconstruct it.
+const DELTA_EXTENSION: &str = "delta";
+
+/// A file to download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadRequest {
+    /// The location to download the file from.
+    pub url: Url,
+
+    /// The path to store the file at.
+    pub destination: PathBuf,
+
+    /// The expected size of the file in bytes, if known.
+    pub size: Option<u64>,
+
+    /// The expected sha256 hash of the file, if known.
+    pub sha256: Option<Sha256Hash>,
+
+    /// The expected md5 hash of the file, if known. Only verified if `sha256` is not set.
+    pub md5: Option<Md5Hash>,
+
+    /// A binary delta that can be used to reconstruct the file instead of downloading it.
+    pub delta: Option<DownloadDelta>,
+}
+
+/// A binary delta in the `BSDIFF40` format that reconstructs a file from an older version of the
+/// file, see [`rattler_package_streaming::delta`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadDelta {
+    /// The location of the delta.
+    pub url: Url,
+
+    /// The older version of the file that the delta is applied to.
+    pub base: PathBuf,
+
+    /// The expected size of the delta in bytes, if known.
+    pub size: Option<u64>,
+
+    /// The expected sha256 hash of the delta, if known.
+    pub sha256: Option<Sha256Hash>,
+}
+
+impl DownloadRequest {
+    /// Constructs a request to download the file at `url` to `destination`.
+    pub fn new(url: Url, destination: impl Into<PathBuf>) -> Self {
+        Self {
+            url,
+            destination: destination.into(),
+            size: None,
+            sha256: None,
+            md5: None,
+            delta: None,
+        }
+    }
+
+    /// Constructs a request to download the archive of the record into `directory`. The size and
+    /// hashes of the record are verified.
+    pub fn for_record(record: &RepoDataRecord, directory: &Path) -> Self {
+        Self {
+            url: record.url.clone(),
+            destination: directory.join(&record.file_name),
+            size: record.package_record.size,
+            sha256: record.package_record.sha256,
+            md5: record.package_record.md5,
+            delta: None,
+        }
+    }
+
+    /// Sets the expected size of the file.
+    #[must_use]
+    pub fn with_size(self, size: u64) -> Self {
+        Self {
+            size: Some(size),
+            ..self
+        }
+    }
+
+    /// Sets the expected sha256 hash of the file.
+    #[must_use]
+    pub fn with_sha256(self, sha256: Sha256Hash) -> Self {
+        Self {
+            sha256: Some(sha256),
+            ..self
+        }
+    }
+
+    /// Sets the expected md5 hash of the file.
+    #[must_use]
+    pub fn with_md5(self, md5: Md5Hash) -> Self {
+        Self {
+            md5: Some(md5),
+            ..self
+        }
+    }
+
+    /// Sets the binary delta that is used to reconstruct the file. The delta is only used if the
+    /// expected hash of the file is known, otherwise the reconstructed file cannot be verified.
+    /// If anything goes wrong while reconstructing the file it is downloaded instead.
+    #[must_use]
+    pub fn with_delta(self, delta: DownloadDelta) -> Self {
+        Self {
+            delta: Some(delta),
+            ..self
+        }
+    }
+
+    fn partial_path(&self) -> PathBuf {
+        self.path_with_extension(PARTIAL_EXTENSION)
+    }
+
+    fn delta_path(&self) -> PathBuf {
+        self.path_with_extension(DELTA_EXTENSION)
+    }
+
+    fn path_with_extension(&self, extension: &str) -> PathBuf {
+        let mut file_name = self
+            .destination
+            .file_name()
+            .unwrap_or_default()
+            .to_os_string();
+        file_name.push(".");
+        file_name.push(extension);
+        self.destination.with_file_name(file_name)
+    }
+}
+
+/// The binary deltas that a server advertises for the packages in a subdirectory of a channel.
+///
+/// The index is stored as [`DELTA_INDEX_FILE_NAME`] next to the `repodata.json` of the
+/// subdirectory and maps the file name of a package to the deltas that reconstruct it:
+///
+/// ```json
+/// {
+///   "deltas": {
+///     "foo-1.1-0.conda": [
+///       {
+///         "from": "foo-1.0-0.conda",
+///         "path": "deltas/foo-1.0-0_foo-1.1-0.bsdiff",
+///         "size": 1234,
+///         "sha256": "..."
+///       }
+///     ]
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeltaIndex {
+    /// The available deltas indexed by the file name of the package they reconstruct.
+    #[serde(default)]
+    pub deltas: HashMap<String, Vec<DeltaIndexEntry>>,
+}
+
+/// A single delta in a [`DeltaIndex`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeltaIndexEntry {
+    /// The file name of the package that the delta is applied to.
+    pub from: String,
+
+    /// The location of the delta relative to the subdirectory.
+    pub path: String,
+
+    /// The size of the delta in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+
+    /// The sha256 hash of the delta.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "optional_sha256"
+    )]
+    pub sha256: Option<Sha256Hash>,
+}
+
+/// (De)serializes an optional sha256 hash as a hex string.
+mod optional_sha256 {
+    use rattler_digest::{serde::SerializableHash, Sha256, Sha256Hash};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        hash: &Option<Sha256Hash>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        hash.map(SerializableHash::<Sha256>::from)
+            .serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Sha256Hash>, D::Error> {
+        Ok(Option::<SerializableHash<Sha256>>::deserialize(deserializer)?.map(Into::into))
+    }
+}
+
+impl DeltaIndex {
+    /// Fetches the delta index of the subdirectory of a channel. Returns `None` if the server
+    /// does not advertise any deltas.
+    pub async fn fetch(
+        client: &reqwest_middleware::ClientWithMiddleware,
+        subdir_url: &Url,
+    ) -> Result<Option<Self>, DownloadError> {
+        let url = add_trailing_slash(subdir_url)
+            .join(DELTA_INDEX_FILE_NAME)
+            .expect("the file name is a valid url");
+        let request_error =
+            |e: reqwest_middleware::Error| DownloadError::RequestError(url.clone(), e.redact());
+        let response = client
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(request_error)?;
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN
+        ) {
+            return Ok(None);
+        }
+        let response = response
+            .error_for_status()
+            .map_err(|e| request_error(reqwest_middleware::Error::Reqwest(e)))?;
+        response
+            .json()
+            .await
+            .map(Some)
+            .map_err(|e| request_error(reqwest_middleware::Error::Reqwest(e)))
+    }
+
+    /// Returns a delta that reconstructs the package `file_name` from one of the archives in
+    /// `directory`, if there is one. The subdirectory url is used to resolve the location of the
+    /// delta.
+    pub fn find_delta(
+        &self,
+        subdir_url: &Url,
+        file_name: &str,
+        directory: &Path,
+    ) -> Option<DownloadDelta> {
+        let subdir_url = add_trailing_slash(subdir_url);
+        self.deltas
+            .get(file_name)?
+            .iter()
+            .filter(|entry| directory.join(&entry.from).is_file())
+            // Prefer the smallest delta.
+            .min_by_key(|entry| entry.size.unwrap_or(u64::MAX))
+            .and_then(|entry| {
+                Some(DownloadDelta {
+                    url: subdir_url.join(&entry.path).ok()?,
+                    base: directory.join(&entry.from),
+                    size: entry.size,
+                    sha256: entry.sha256,
+                })
+            })
+    }
+}
+
+/// An event that is emitted by a [`DownloadManager`]. Use [`DownloadManager::subscribe`] to
+/// receive them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadEvent {
+    /// A download started, possibly resuming a previous download.
+    Started {
+        /// The location that is downloaded.
+        url: Url,
+
+        /// The number of bytes that were already downloaded previously.
+        resumed_from: u64,
+
+        /// The total size of the file, if known.
+        total: Option<u64>,
+    },
+
+    /// More bytes of a file have been downloaded.
+    Progress {
+        /// The location that is downloaded.
+        url: Url,
+
+        /// The number of bytes of the file that have been downloaded so far.
+        downloaded: u64,
+
+        /// The total size of the file, if known.
+        total: Option<u64>,
+    },
+
+    /// A file was downloaded and verified.
+    Finished {
+        /// The location that was downloaded.
+        url: Url,
+    },
+
+    /// A download failed.
+    Failed {
+        /// The location that failed to download.
+        url: Url,
+    },
+}
+
+/// An error that can occur while downloading a file.
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    /// The request to the server failed.
+    #[error("failed to download {}", RedactedUrl::from(.0))]
+    RequestError(Url, #[source] reqwest_middleware::Error),
+
+    /// Reading or writing a file failed.
+    #[error("failed to write {}", .0.display())]
+    IoError(PathBuf, #[source] std::io::Error),
+
+    /// The downloaded file does not have the expected size.
+    #[error("the size of {} is {actual} bytes, expected {expected} bytes", RedactedUrl::from(.url))]
+    SizeMismatch {
+        /// The location that was downloaded.
+        url: Url,
+        /// The expected size.
+        expected: u64,
+        /// The actual size.
+        actual: u64,
+    },
+
+    /// The downloaded file does not have the expected hash.
+    #[error("the hash of {} is {actual}, expected {expected}", RedactedUrl::from(.url))]
+    HashMismatch {
+        /// The location that was downloaded.
+        url: Url,
+        /// The expected hash.
+        expected: String,
+        /// The actual hash.
+        actual: String,
+    },
+
+    /// There is not enough space on disk to store the downloads.
+    #[error("not enough disk space in {}: {required} bytes are required but only {available} bytes are available", .path.display())]
+    InsufficientDiskSpace {
+        /// The directory the files are downloaded to.
+        path: PathBuf,
+        /// The number of bytes that are required.
+        required: u64,
+        /// The number of bytes that are available.
+        available: u64,
+    },
+}
+
+/// Downloads files with global and per-host concurrency limits.
+///
+/// Files are first downloaded next to their destination with a `.partial` extension. If a
+/// download is interrupted it is resumed from where it left off, either when the request is
+/// retried or when the same file is requested again later, possibly by another process. Once the
+/// download completes its size and hash are verified and the file is moved into place.
+///
+/// The manager can be cheaply cloned, clones share the same limits.
+#[derive(Clone)]
+pub struct DownloadManager {
+    inner: Arc<DownloadManagerInner>,
+}
+
+struct DownloadManagerInner {
+    client: reqwest_middleware::ClientWithMiddleware,
+    downloads: Semaphore,
+    max_concurrent_downloads_per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    retry_policy: Box<dyn RetryPolicy + Send + Sync>,
+    check_disk_space: bool,
+    authentication_storage: Option<AuthenticationStorage>,
+    events: broadcast::Sender<DownloadEvent>,
+}
+
+/// A builder to construct a [`DownloadManager`].
+pub struct DownloadManagerBuilder {
+    client: reqwest_middleware::ClientWithMiddleware,
+    max_concurrent_downloads: usize,
+    max_concurrent_downloads_per_host: usize,
+    retry_policy: Box<dyn RetryPolicy + Send + Sync>,
+    check_disk_space: bool,
+    authentication_storage: Option<AuthenticationStorage>,
+}
+
+impl DownloadManagerBuilder {
+    /// Sets the maximum number of files that are downloaded concurrently.
+    #[must_use]
+    pub fn with_max_concurrent_downloads(self, limit: usize) -> Self {
+        Self {
+            max_concurrent_downloads: limit,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of files that are downloaded concurrently from a single host.
+    #[must_use]
+    pub fn with_max_concurrent_downloads_per_host(self, limit: usize) -> Self {
+        Self {
+            max_concurrent_downloads_per_host: limit,
+            ..self
+        }
+    }
+
+    /// Sets the policy that determines whether a failed download is retried. Retried downloads
+    /// resume where they left off. Defaults to [`default_retry_policy`].
+    #[must_use]
+    pub fn with_retry_policy(self, retry_policy: impl RetryPolicy + Send + Sync + 'static) -> Self {
+        Self {
+            retry_policy: Box::new(retry_policy),
+            ..self
+        }
+    }
+
+    /// Sets whether the available disk space is checked before files are downloaded. Enabled by
+    /// default.
+    #[must_use]
+    pub fn with_disk_space_check(self, check_disk_space: bool) -> Self {
+        Self {
+            check_disk_space,
+            ..self
+        }
+    }
+
+    /// Sets the storage that is used to look up the credentials of files on file shares, e.g.
+    /// `file://server/share/file.conda`. If not set, the default storage is used.
+    #[must_use]
+    pub fn with_authentication_storage(
+        self,
+        authentication_storage: AuthenticationStorage,
+    ) -> Self {
+        Self {
+            authentication_storage: Some(authentication_storage),
+            ..self
+        }
+    }
+
+    /// Applies the shared configuration: failed downloads are retried as configured.
+    ///
+    /// Credentials are not read from the configuration because that can fail, use
+    /// [`DownloadManagerBuilder::with_authentication_storage`] with
+    /// [`AuthenticationStorage::from_config`] for that.
+    #[must_use]
+    pub fn with_config(self, config: &rattler_config::Config) -> Self {
+        self.with_retry_policy(retry_policy_from_config(config))
+    }
+
+    /// Constructs the [`DownloadManager`].
+    pub fn finish(self) -> DownloadManager {
+        let (events, _) = broadcast::channel(1024);
+        DownloadManager {
+            inner: Arc::new(DownloadManagerInner {
+                client: self.client,
+                downloads: Semaphore::new(self.max_concurrent_downloads.max(1)),
+                max_concurrent_downloads_per_host: self.max_concurrent_downloads_per_host.max(1),
+                hosts: Mutex::default(),
+                retry_policy: self.retry_policy,
+                check_disk_space: self.check_disk_space,
+                authentication_storage: self.authentication_storage,
+                events,
+            }),
+        }
+    }
+}
+
+impl DownloadManager {
+    /// Constructs a [`DownloadManagerBuilder`] that downloads files with the given client.
+    pub fn builder(client: reqwest_middleware::ClientWithMiddleware) -> DownloadManagerBuilder {
+        DownloadManagerBuilder {
+            client,
+            max_concurrent_downloads: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            max_concurrent_downloads_per_host: DEFAULT_MAX_CONCURRENT_DOWNLOADS_PER_HOST,
+            retry_policy: Box::new(default_retry_policy()),
+            check_disk_space: true,
+            authentication_storage: None,
+        }
+    }
+
+    /// Returns a stream of the events of all downloads of this manager that start after this
+    /// call. Events are dropped if the receiver falls behind.
+    pub fn subscribe(&self) -> impl Stream<Item = DownloadEvent> {
+        BroadcastStream::new(self.inner.events.subscribe())
+            .filter_map(|event| futures::future::ready(event.ok()))
+    }
+
+    /// Returns an error if there is not enough disk space to store all files that still have to
+    /// be downloaded. Does nothing if the disk space check is disabled.
+    pub async fn check_disk_space(
+        &self,
+        requests: &[DownloadRequest],
+    ) -> Result<(), DownloadError> {
+        if !self.inner.check_disk_space {
+            return Ok(());
+        }
+        let mut required = HashMap::<&Path, u64>::new();
+        for request in requests {
+            if let Some(directory) = request.destination.parent() {
+                *required.entry(directory).or_default() += remaining_size(request).await;
+            }
+        }
+        for (directory, required) in required {
+            ensure_disk_space(directory, required).await?;
+        }
+        Ok(())
+    }
+
+    /// Downloads all files. The required disk space for all files is checked up front.
+    ///
+    /// Returns the destinations of the files in the same order as the requests.
+    pub async fn download_all(
+        &self,
+        requests: impl IntoIterator<Item = DownloadRequest>,
+    ) -> Result<Vec<PathBuf>, DownloadError> {
+        let requests = requests.into_iter().collect::<Vec<_>>();
+        self.check_disk_space(&requests).await?;
+        futures::future::try_join_all(requests.iter().map(|request| self.download(request))).await
+    }
+
+    /// Downloads a single file and returns its destination. If the destination already exists
+    /// and matches the expected size and hash it is not downloaded again.
+    pub async fn download(&self, request: &DownloadRequest) -> Result<PathBuf, DownloadError> {
+        let destination = &request.destination;
+        if destination.is_file() && verify(request, destination).await.is_ok() {
+            return Ok(destination.clone());
+        }
+
+        let _permit = self
+            .inner
+            .downloads
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+        let host = self.host_semaphore(&request.url);
+        let _host_permit = host.acquire().await.expect("the semaphore is never closed");
+
+        if let Some(directory) = destination.parent() {
+            tokio::fs::create_dir_all(directory)
+                .await
+                .map_err(|e| DownloadError::IoError(directory.to_path_buf(), e))?;
+            if self.inner.check_disk_space {
+                ensure_disk_space(directory, remaining_size(request).await).await?;
+            }
+        }
+
+        let result = match self.reconstruct_from_delta(request).await {
+            Some(Ok(())) => Ok(()),
+            Some(Err(err)) => {
+                tracing::warn!(
+                    "failed to reconstruct {} from a delta, downloading it instead: {err}",
+                    RedactedUrl::from(&request.url)
+                );
+                self.download_with_retry(request).await
+            }
+            None => self.download_with_retry(request).await,
+        };
+        let event = match &result {
+            Ok(()) => DownloadEvent::Finished {
+                url: request.url.clone(),
+            },
+            Err(_) => DownloadEvent::Failed {
+                url: request.url.clone(),
+            },
+        };
+        let _ = self.inner.events.send(event);
+        result.map(|()| destination.clone())
+    }
+
+    fn host_semaphore(&self, url: &Url) -> Arc<Semaphore> {
+        let host = url.host_str().unwrap_or_default().to_string();
+        self.inner
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| {
+                Arc::new(Semaphore::new(self.inner.max_concurrent_downloads_per_host))
+            })
+            .clone()
+    }
+
+    async fn download_with_retry(&self, request: &DownloadRequest) -> Result<(), DownloadError> {
+        let request_start = Utc::now();
+        let partial_path = request.partial_path();
+        let mut current_try = 0;
+        loop {
+            current_try += 1;
+            let err = match self.download_to_partial(request, &partial_path).await {
+                Ok(()) => break,
+                Err(err) => err,
+            };
+
+            let is_retryable = match &err {
+                DownloadError::RequestError(_, err) => {
+                    err.is_timeout()
+                        || err.is_connect()
+                        || err.is_body()
+                        || err.status().is_some_and(|status| {
+                            status.is_server_error()
+                                || status == StatusCode::TOO_MANY_REQUESTS
+                                || status == StatusCode::REQUEST_TIMEOUT
+                        })
+                }
+                DownloadError::IoError(..) => true,
+                _ => false,
+            };
+            if !is_retryable {
+                return Err(err);
+            }
+
+            let execute_after = match self
+                .inner
+                .retry_policy
+                .should_retry(request_start, current_try)
+            {
+                RetryDecision::Retry { execute_after } => execute_after,
+                RetryDecision::DoNotRetry => return Err(err),
+            };
+            let duration = (execute_after - Utc::now()).to_std().unwrap_or_default();
+            tracing::warn!(
+                "failed to download {}: {err}. Retry #{current_try}, sleeping {duration:?} until the next attempt...",
+                RedactedUrl::from(&request.url),
+            );
+            tokio::time::sleep(duration).await;
+        }
+
+        // Verify the complete file, including the parts that were downloaded previously.
+        if let Err(err) = verify(request, &partial_path).await {
+            let _ = tokio::fs::remove_file(&partial_path).await;
+            return Err(err);
+        }
+        tokio::fs::rename(&partial_path, &request.destination)
+            .await
+            .map_err(|e| DownloadError::IoError(request.destination.clone(), e))
+    }
+
+    /// Reconstructs the file of the request from its delta, if it has a delta that can be used.
+    async fn reconstruct_from_delta(
+        &self,
+        request: &DownloadRequest,
+    ) -> Option<Result<(), DownloadError>> {
+        let delta = request.delta.as_ref()?;
+        if request.sha256.is_none() && request.md5.is_none() {
+            tracing::debug!(
+                "not using a delta for {} because its hash is unknown",
+                RedactedUrl::from(&request.url)
+            );
+            return None;
+        }
+
+        let delta_request = DownloadRequest {
+            url: delta.url.clone(),
+            destination: request.delta_path(),
+            size: delta.size,
+            sha256: delta.sha256,
+            md5: None,
+            delta: None,
+        };
+        let partial_path = request.partial_path();
+        let result = async {
+            self.download_with_retry(&delta_request).await?;
+
+            let base = delta.base.clone();
+            let patch_path = delta_request.destination.clone();
+            let target = partial_path.clone();
+            let size = request.size;
+            tokio::task::spawn_blocking(move || {
+                let old = fs_err::read(&base)?;
+                let patch = fs_err::read(&patch_path)?;
+                let mut new = std::io::BufWriter::new(fs_err::File::create(&target)?);
+                rattler_package_streaming::delta::apply_bsdiff(&old, &patch, size, &mut new)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                std::io::Write::flush(&mut new)
+            })
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Interrupted, e))
+            .and_then(|result| result)
+            .map_err(|e| DownloadError::IoError(partial_path.clone(), e))?;
+
+            verify(request, &partial_path).await?;
+            tokio::fs::rename(&partial_path, &request.destination)
+                .await
+                .map_err(|e| DownloadError::IoError(request.destination.clone(), e))
+        }
+        .await;
+
+        let _ = tokio::fs::remove_file(&delta_request.destination).await;
+        if result.is_err() {
+            // Make sure that the full download does not resume from the reconstructed file.
+            let _ = tokio::fs::remove_file(&partial_path).await;
+        }
+        Some(result)
+    }
+
+    /// Returns the path of a `file://` URL. If the file is located on a file share, the share is
+    /// connected first with the credentials from the authentication storage.
+    pub(crate) async fn file_path(&self, url: &Url) -> Result<PathBuf, DownloadError> {
+        let path = url_to_path(url).ok_or_else(|| {
+            DownloadError::IoError(
+                PathBuf::from(url.path()),
+                std::io::ErrorKind::InvalidInput.into(),
+            )
+        })?;
+        if unc_share(url).is_some() {
+            let storage = self
+                .inner
+                .authentication_storage
+                .clone()
+                .unwrap_or_else(|| AuthenticationStorage::from_env().unwrap_or_default());
+            let share_url = url.clone();
+            tokio::task::spawn_blocking(move || connect_file_share(&share_url, &storage))
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Interrupted, e))
+                .and_then(|result| result)
+                .map_err(|e| DownloadError::IoError(path.clone(), e))?;
+        }
+        Ok(to_long_path(path))
+    }
+
+    /// Downloads the file to `partial_path`, appending to the file if it already exists.
+    async fn download_to_partial(
+        &self,
+        request: &DownloadRequest,
+        partial_path: &Path,
+    ) -> Result<(), DownloadError> {
+        let io_error = |e| DownloadError::IoError(partial_path.to_path_buf(), e);
+        let url = &request.url;
+
+        if url.scheme() == "file" {
+            let source = self.file_path(url).await?;
+            let _ = self.inner.events.send(DownloadEvent::Started {
+                url: url.clone(),
+                resumed_from: 0,
+                total: request.size,
+            });
+            tokio::fs::copy(&source, partial_path)
+                .await
+                .map_err(|e| DownloadError::IoError(source, e))?;
+            return Ok(());
+        }
+
+        let mut resumed_from = match tokio::fs::metadata(partial_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        if request.size.is_some_and(|size| resumed_from >= size) {
+            // The file is complete, or larger than expected in which case verification fails.
+            return Ok(());
+        }
+
+        let mut builder = self.inner.client.get(url.clone());
+        if resumed_from > 0 {
+            builder = builder.header(header::RANGE, format!("bytes={resumed_from}-"));
+        }
+        if let Some(sha256) = request.sha256 {
+            // This is used by the OCI registry middleware to verify the sha256 of the response
+            builder = builder.header("X-Expected-Sha256", format!("{sha256:x}"));
+        }
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| DownloadError::RequestError(url.clone(), e.redact()))?;
+        if resumed_from > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file does not match the file on the server, start over.
+            tokio::fs::remove_file(partial_path)
+                .await
+                .map_err(io_error)?;
+            return Box::pin(self.download_to_partial(request, partial_path)).await;
+        }
+        let response = response.error_for_status().map_err(|e| {
+            DownloadError::RequestError(url.clone(), reqwest_middleware::Error::Reqwest(e).redact())
+        })?;
+
+        // The server might not support range requests, in which case it sends the whole file.
+        let append = resumed_from > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+        if !append {
+            resumed_from = 0;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(partial_path)
+            .await
+            .map_err(io_error)?;
+
+        let total = request
+            .size
+            .or_else(|| response.content_length().map(|len| len + resumed_from));
+        let _ = self.inner.events.send(DownloadEvent::Started {
+            url: url.clone(),
+            resumed_from,
+            total,
+        });
+
+        let mut downloaded = resumed_from;
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| {
+                DownloadError::RequestError(
+                    url.clone(),
+                    reqwest_middleware::Error::Reqwest(e).redact(),
+                )
+            })?;
+            file.write_all(&chunk).await.map_err(io_error)?;
+            downloaded += chunk.len() as u64;
+            let _ = self.inner.events.send(DownloadEvent::Progress {
+                url: url.clone(),
+                downloaded,
+                total,
+            });
+        }
+        file.flush().await.map_err(io_error)?;
+        Ok(())
+    }
+}
+
+/// Returns the URL with a trailing slash if it doesn't already have one.
+fn add_trailing_slash(url: &Url) -> Cow<'_, Url> {
+    let path = url.path();
+    if path.ends_with('/') {
+        Cow::Borrowed(url)
+    } else {
+        let mut url = url.clone();
+        url.set_path(&format!("{path}/"));
+        Cow::Owned(url)
+    }
+}
+
+/// Returns the number of bytes that still have to be downloaded for the request.
+async fn remaining_size(request: &DownloadRequest) -> u64 {
+    let Some(size) = request.size else {
+        return 0;
+    };
+    if request.destination.is_file() {
+        return 0;
+    }
+    let downloaded = tokio::fs::metadata(request.partial_path())
+        .await
+        .map_or(0, |metadata| metadata.len());
+    size.saturating_sub(downloaded)
+}
+
+/// Verifies that the file at `path` matches the size and hash of the request.
+async fn verify(request: &DownloadRequest, path: &Path) -> Result<(), DownloadError> {
+    let io_error = |e| DownloadError::IoError(path.to_path_buf(), e);
+    if let Some(expected) = request.size {
+        let actual = tokio::fs::metadata(path).await.map_err(io_error)?.len();
+        if actual != expected {
+            return Err(DownloadError::SizeMismatch {
+                url: request.url.clone(),
+                expected,
+                actual,
+            });
+        }
+    }
+
+    let path_inner = path.to_path_buf();
+    let (sha256, md5) = (request.sha256, request.md5);
+    let mismatch = tokio::task::spawn_blocking(move || {
+        if let Some(expected) = sha256 {
+            let actual = rattler_digest::compute_file_digest::<Sha256>(&path_inner)?;
+            return Ok(
+                (actual != expected).then(|| (format!("{expected:x}"), format!("{actual:x}")))
+            );
+        }
+        if let Some(expected) = md5 {
+            let actual = rattler_digest::compute_file_digest::<Md5>(&path_inner)?;
+            return Ok(
+                (actual != expected).then(|| (format!("{expected:x}"), format!("{actual:x}")))
+            );
+        }
+        Ok(None)
+    })
+    .await
+    .map_err(|e| io_error(std::io::Error::new(std::io::ErrorKind::Interrupted, e)))?
+    .map_err(io_error)?;
+
+    match mismatch {
+        Some((expected, actual)) => Err(DownloadError::HashMismatch {
+            url: request.url.clone(),
+            expected,
+            actual,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Returns an error if there are less than `required` bytes available in `directory`.
+async fn ensure_disk_space(directory: &Path, required: u64) -> Result<(), DownloadError> {
+    if required == 0 {
+        return Ok(());
+    }
+    let directory_inner = directory.to_path_buf();
+    let available = tokio::task::spawn_blocking(move || available_space(&directory_inner))
+        .await
+        .ok()
+        .and_then(Result::ok);
+    match available {
+        Some(available) if available < required => Err(DownloadError::InsufficientDiskSpace {
+            path: directory.to_path_buf(),
+            required,
+            available,
+        }),
+        Some(_) => Ok(()),
+        None => {
+            tracing::debug!(
+                "could not determine the available disk space in {}",
+                directory.display()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Returns the number of bytes available to the current user on the filesystem that contains
+/// `path`, or the closest existing ancestor of `path`.
+fn available_space(path: &Path) -> std::io::Result<u64> {
+    let path = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+    sys::available_space(path)
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{ffi::CString, io, mem, os::unix::ffi::OsStrExt, path::Path};
+
+    pub(super) fn available_space(path: &Path) -> io::Result<u64> {
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: `path` is a valid null-terminated string and `stat` is only read when the call
+        // succeeds.
+        unsafe {
+            let mut stat: libc::statvfs = mem::zeroed();
+            if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            #[allow(clippy::unnecessary_cast)]
+            Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{io, os::windows::ffi::OsStrExt, path::Path};
+
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    pub(super) fn available_space(path: &Path) -> io::Result<u64> {
+        let path = path
+            .as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect::<Vec<_>>();
+        let mut available = 0u64;
+        // SAFETY: `path` is a valid null-terminated wide string.
+        let result = unsafe {
+            GetDiskFreeSpaceExW(
+                path.as_ptr(),
+                &mut available,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(available)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::{io, path::Path};
+
+    pub(super) fn available_space(_path: &Path) -> io::Result<u64> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::IntoFuture,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use assert_matches::assert_matches;
+    use axum::{
+        body::Body,
+        extract::{Request, State},
+        http::header,
+        middleware::{self, Next},
+        response::Response,
+        Router,
+    };
+    use futures::StreamExt;
+    use rattler_digest::Sha256;
+    use rattler_networking::retry_policies::{DoNotRetryPolicy, ExponentialBackoffBuilder};
+    use tower_http::services::ServeDir;
+    use url::Url;
+
+    use super::{
+        DeltaIndex, DownloadError, DownloadEvent, DownloadManager, DownloadRequest,
+        DELTA_INDEX_FILE_NAME,
+    };
+
+    /// The number of requests and the range headers of the requests that were received.
+    type RequestLog = (Arc<AtomicUsize>, Arc<std::sync::Mutex<Vec<String>>>);
+
+    /// Cuts off the body of the first response after 1000 bytes and records the range headers of
+    /// all requests.
+    async fn fail_first_response(
+        State((count, ranges)): State<RequestLog>,
+        req: Request,
+        next: Next,
+    ) -> Response {
+        if let Some(range) = req.headers().get(header::RANGE) {
+            ranges
+                .lock()
+                .unwrap()
+                .push(range.to_str().unwrap().to_string());
+        }
+        let response = next.run(req).await;
+        if count.fetch_add(1, Ordering::SeqCst) > 0 {
+            return response;
+        }
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let head = bytes.slice(..1000);
+        let stream =
+            futures::stream::once(async move { Ok(head) }).chain(futures::stream::once(async {
+                // Give the client time to receive the first part before the connection is reset.
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                Err(std::io::Error::other("connection reset"))
+            }));
+        Response::new(Body::from_stream(stream))
+    }
+
+    #[tokio::test]
+    async fn test_resume_download() {
+        let served = tempfile::tempdir().unwrap();
+        let content = (0..10_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        std::fs::write(served.path().join("archive.conda"), &content).unwrap();
+        let sha256 = rattler_digest::compute_bytes_digest::<Sha256>(&content);
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let ranges = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let router = Router::new()
+            .route_service("/*key", ServeDir::new(served.path()))
+            .layer(middleware::from_fn_with_state(
+                (count.clone(), ranges.clone()),
+                fail_first_response,
+            ));
+        let listener = tokio::net::TcpListener::bind(SocketAddr::new([127, 0, 0, 1].into(), 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        let url = Url::parse(&format!("http://localhost:{port}/archive.conda")).unwrap();
+
+        let downloads = tempfile::tempdir().unwrap();
+        let request = DownloadRequest::new(url.clone(), downloads.path().join("archive.conda"))
+            .with_size(content.len() as u64)
+            .with_sha256(sha256);
+
+        // Without retries the partial download is kept.
+        let manager = DownloadManager::builder(reqwest::Client::default().into())
+            .with_retry_policy(DoNotRetryPolicy)
+            .finish();
+        assert_matches!(
+            manager.download(&request).await,
+            Err(DownloadError::RequestError(..))
+        );
+        assert_eq!(
+            std::fs::metadata(request.partial_path()).unwrap().len(),
+            1000
+        );
+
+        // The next attempt resumes the download.
+        let manager = DownloadManager::builder(reqwest::Client::default().into())
+            .with_retry_policy(ExponentialBackoffBuilder::default().build_with_max_retries(3))
+            .finish();
+        let events = manager.subscribe();
+        let paths = manager.download_all([request.clone()]).await.unwrap();
+        assert_eq!(paths, std::slice::from_ref(&request.destination));
+        assert_eq!(std::fs::read(&request.destination).unwrap(), content);
+        assert!(!request.partial_path().exists());
+        assert_eq!(*ranges.lock().unwrap(), ["bytes=1000-"]);
+
+        drop(manager);
+        let events = events.collect::<Vec<_>>().await;
+        assert_eq!(
+            events.first(),
+            Some(&DownloadEvent::Started {
+                url: url.clone(),
+                resumed_from: 1000,
+                total: Some(content.len() as u64),
+            })
+        );
+        assert_eq!(events.last(), Some(&DownloadEvent::Finished { url }));
+
+        // A file that already exists is not downloaded again.
+        let requests = count.load(Ordering::SeqCst);
+        DownloadManager::builder(reqwest::Client::default().into())
+            .finish()
+            .download(&request)
+            .await
+            .unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), requests);
+    }
+
+    #[tokio::test]
+    async fn test_verify_download() {
+        let source = tempfile::tempdir().unwrap();
+        let path = source.path().join("archive.tar.bz2");
+        std::fs::write(&path, b"content").unwrap();
+        let url = Url::from_file_path(&path).unwrap();
+
+        let downloads = tempfile::tempdir().unwrap();
+        let manager = DownloadManager::builder(reqwest::Client::default().into()).finish();
+
+        let request = DownloadRequest::new(url.clone(), downloads.path().join("a.tar.bz2"))
+            .with_sha256(rattler_digest::compute_bytes_digest::<Sha256>(b"other"));
+        assert_matches!(
+            manager.download(&request).await,
+            Err(DownloadError::HashMismatch { .. })
+        );
+        assert!(!request.destination.exists());
+        assert!(!request.partial_path().exists());
+
+        let request = DownloadRequest::new(url.clone(), downloads.path().join("b.tar.bz2"))
+            .with_size(u64::MAX / 2);
+        assert_matches!(
+            manager.download_all([request]).await,
+            Err(DownloadError::InsufficientDiskSpace { .. })
+        );
+
+        let request = DownloadRequest::new(url, downloads.path().join("c.tar.bz2")).with_size(7);
+        assert_eq!(
+            manager.download(&request).await.unwrap(),
+            request.destination
+        );
+    }
+
+    /// Records the paths of all requests.
+    async fn record_paths(
+        State(paths): State<Arc<std::sync::Mutex<Vec<String>>>>,
+        req: Request,
+        next: Next,
+    ) -> Response {
+        paths.lock().unwrap().push(req.uri().path().to_string());
+        next.run(req).await
+    }
+
+    #[tokio::test]
+    async fn test_download_delta() {
+        let old = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        let mut new = old.clone();
+        new[50_000..50_010].copy_from_slice(b"0123456789");
+        new.extend_from_slice(b"some more bytes");
+        let mut delta = Vec::new();
+        rattler_package_streaming::delta::create_bsdiff(&old, &new, &mut delta).unwrap();
+
+        let served = tempfile::tempdir().unwrap();
+        let subdir = served.path().join("linux-64");
+        std::fs::create_dir_all(subdir.join("deltas")).unwrap();
+        std::fs::write(subdir.join("foo-1.1-0.conda"), &new).unwrap();
+        std::fs::write(subdir.join("deltas/foo-1.0-0_foo-1.1-0.bsdiff"), &delta).unwrap();
+        std::fs::write(subdir.join("deltas/bar.bsdiff"), &delta).unwrap();
+        std::fs::write(
+            subdir.join(DELTA_INDEX_FILE_NAME),
+            serde_json::json!({
+                "deltas": {
+                    "foo-1.1-0.conda": [
+                        {
+                            "from": "foo-1.0-0.conda",
+                            "path": "deltas/foo-1.0-0_foo-1.1-0.bsdiff",
+                            "size": delta.len(),
+                            "sha256": format!("{:x}", rattler_digest::compute_bytes_digest::<Sha256>(&delta)),
+                        },
+                        { "from": "foo-0.9-0.conda", "path": "deltas/foo-0.9-0_foo-1.1-0.bsdiff" }
+                    ],
+                    "bar-1.1-0.conda": [
+                        { "from": "bar-1.0-0.conda", "path": "deltas/bar.bsdiff" }
+                    ]
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let paths = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let router = Router::new()
+            .route_service("/*key", ServeDir::new(served.path()))
+            .layer(middleware::from_fn_with_state(paths.clone(), record_paths));
+        let listener = tokio::net::TcpListener::bind(SocketAddr::new([127, 0, 0, 1].into(), 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        let subdir_url = Url::parse(&format!("http://localhost:{port}/linux-64")).unwrap();
+
+        let client = reqwest_middleware::ClientWithMiddleware::from(reqwest::Client::default());
+        let index = DeltaIndex::fetch(&client, &subdir_url)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(index.deltas["foo-1.1-0.conda"].len(), 2);
+        assert_eq!(
+            DeltaIndex::fetch(&client, &subdir_url.join("noarch").unwrap())
+                .await
+                .unwrap(),
+            None
+        );
+
+        // Only deltas from archives that are available locally are used.
+        let downloads = tempfile::tempdir().unwrap();
+        assert_eq!(
+            index.find_delta(&subdir_url, "foo-1.1-0.conda", downloads.path()),
+            None
+        );
+        std::fs::write(downloads.path().join("foo-1.0-0.conda"), &old).unwrap();
+        std::fs::write(downloads.path().join("bar-1.0-0.conda"), b"other").unwrap();
+        let delta = index
+            .find_delta(&subdir_url, "foo-1.1-0.conda", downloads.path())
+            .unwrap();
+        assert_eq!(
+            delta.url.path(),
+            "/linux-64/deltas/foo-1.0-0_foo-1.1-0.bsdiff"
+        );
+
+        // The archive is reconstructed without downloading it.
+        let manager = DownloadManager::builder(client.clone()).finish();
+        let sha256 = rattler_digest::compute_bytes_digest::<Sha256>(&new);
+        let request = DownloadRequest::new(
+            subdir_url.join("linux-64/foo-1.1-0.conda").unwrap(),
+            downloads.path().join("foo-1.1-0.conda"),
+        )
+        .with_sha256(sha256)
+        .with_delta(delta);
+        paths.lock().unwrap().clear();
+        manager.download(&request).await.unwrap();
+        assert_eq!(std::fs::read(&request.destination).unwrap(), new);
+        assert_eq!(
+            *paths.lock().unwrap(),
+            ["/linux-64/deltas/foo-1.0-0_foo-1.1-0.bsdiff"]
+        );
+        assert!(!request.delta_path().exists());
+
+        // If the reconstructed archive does not match, it is downloaded instead.
+        let delta = index
+            .find_delta(&subdir_url, "bar-1.1-0.conda", downloads.path())
+            .unwrap();
+        let request = DownloadRequest::new(
+            subdir_url.join("linux-64/foo-1.1-0.conda").unwrap(),
+            downloads.path().join("bar-1.1-0.conda"),
+        )
+        .with_sha256(sha256)
+        .with_delta(delta);
+        paths.lock().unwrap().clear();
+        manager.download(&request).await.unwrap();
+        assert_eq!(std::fs::read(&request.destination).unwrap(), new);
+        assert_eq!(
+            *paths.lock().unwrap(),
+            ["/linux-64/deltas/bar.bsdiff", "/linux-64/foo-1.1-0.conda"]
+        );
+    }
+}
@@ -11,6 +11,38 @@ use rattler_conda_types::{
 };
 
 use fs_err as fs;
+
+/// Determines which package "wins" a path that is installed by multiple packages. The files of the
+/// other packages are kept next to the winning file with a `__clobber-from-<package>` suffix and are
+/// restored when the winning package is removed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum ClobberPolicy {
+    /// The package that comes last in topological order wins (default). This means that a package
+    /// always overwrites the files of its dependencies.
+    #[default]
+    TopologicalOrder,
+
+    /// The package that appears first in the list wins. If none of the clobbering packages is part
+    /// of the list the [`ClobberPolicy::TopologicalOrder`] is used instead.
+    Priority(Vec<PackageName>),
+}
+
+impl ClobberPolicy {
+    /// Selects the winner from the clobbering packages which are sorted topologically.
+    fn select_winner<'a>(
+        &self,
+        sorted_candidates: &'a [(usize, PackageName)],
+    ) -> Option<&'a (usize, PackageName)> {
+        match self {
+            ClobberPolicy::TopologicalOrder => sorted_candidates.last(),
+            ClobberPolicy::Priority(priority) => priority
+                .iter()
+                .find_map(|name| sorted_candidates.iter().find(|(_, n)| n == name))
+                .or_else(|| sorted_candidates.last()),
+        }
+    }
+}
+
 /// A registry for clobbering files
 /// The registry keeps track of all files that are installed by a package and
 /// can be used to rename files that are already installed by another package.
@@ -19,6 +51,7 @@ pub struct ClobberRegistry {
     paths_registry: HashMap<PathBuf, usize>,
     clobbers: HashMap<PathBuf, Vec<usize>>,
     package_names: Vec<PackageName>,
+    policy: ClobberPolicy,
 }
 
 static CLOBBER_TEMPLATE: &str = "__clobber-from-";
@@ -72,6 +105,12 @@ impl ClobberRegistry {
         registry
     }
 
+    /// Sets the policy that determines which package wins a clobbered path.
+    #[must_use]
+    pub fn with_policy(self, policy: ClobberPolicy) -> Self {
+        Self { policy, ..self }
+    }
+
     fn clobber_name(path: &Path, package_name: &PackageName) -> PathBuf {
         let file_name = path.file_name().unwrap_or_default();
         let mut new_path = path.to_path_buf();
@@ -161,7 +200,7 @@ impl ClobberRegistry {
                 .filter(|(_, n)| clobbered_by_names.contains(n))
                 .collect::<Vec<_>>();
 
-            let winner = match sorted_clobbered_by.last() {
+            let winner = match self.policy.select_winner(&sorted_clobbered_by) {
                 Some(winner) => winner,
                 // In this case, all files have been removed and we can skip any unclobbering
                 None => continue,
@@ -994,4 +1033,104 @@ mod tests {
 
         assert_check_files(target_prefix.path(), &[]);
     }
+
+    #[test]
+    fn test_clobber_policy_winner() {
+        use super::ClobberPolicy;
+        use rattler_conda_types::PackageName;
+
+        let candidates = ["clobber-1", "clobber-2", "clobber-3"]
+            .into_iter()
+            .map(PackageName::new_unchecked)
+            .enumerate()
+            .collect::<Vec<_>>();
+
+        let winner = ClobberPolicy::TopologicalOrder.select_winner(&candidates);
+        assert_eq!(winner.unwrap().1.as_normalized(), "clobber-3");
+
+        let policy = ClobberPolicy::Priority(vec![
+            PackageName::new_unchecked("unrelated"),
+            PackageName::new_unchecked("clobber-2"),
+            PackageName::new_unchecked("clobber-1"),
+        ]);
+        assert_eq!(
+            policy.select_winner(&candidates).unwrap().1.as_normalized(),
+            "clobber-2"
+        );
+
+        let policy = ClobberPolicy::Priority(vec![PackageName::new_unchecked("unrelated")]);
+        assert_eq!(
+            policy.select_winner(&candidates).unwrap().1.as_normalized(),
+            "clobber-3"
+        );
+        assert!(policy.select_winner(&[]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_install_with_clobber_policy_priority() {
+        use super::ClobberPolicy;
+        use crate::install::Installer;
+        use rattler_conda_types::PackageName;
+
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("prefix");
+        let clobber_1 = build_package(
+            dir.path(),
+            "clobber-1",
+            "0.1.0",
+            &[],
+            &[("clobber.txt", "clobber-1")],
+        );
+        let clobber_2 = build_package(
+            dir.path(),
+            "clobber-2",
+            "0.1.0",
+            &["clobber-1"],
+            &[("clobber.txt", "clobber-2")],
+        );
+        let installer = || {
+            Installer::new()
+                .with_package_cache(PackageCache::new(dir.path().join("pkgs")))
+                .with_clobber_policy(ClobberPolicy::Priority(vec![PackageName::new_unchecked(
+                    "clobber-1",
+                )]))
+        };
+
+        // `clobber-2` depends on `clobber-1` so its file would win, but the policy prefers
+        // `clobber-1`.
+        installer()
+            .install(&prefix, [clobber_1, clobber_2.clone()])
+            .await
+            .unwrap();
+        assert_check_files(
+            &prefix,
+            &["clobber.txt", "clobber.txt__clobber-from-clobber-2"],
+        );
+        assert_eq!(
+            fs::read_to_string(prefix.join("clobber.txt")).unwrap(),
+            "clobber-1"
+        );
+        let prefix_records = PrefixRecord::collect_from_prefix(&prefix).unwrap();
+        assert_eq!(
+            find_prefix_record(&prefix_records, "clobber-2")
+                .unwrap()
+                .files,
+            [PathBuf::from("clobber.txt__clobber-from-clobber-2")]
+        );
+
+        // Removing the winner moves the file of the remaining package into place.
+        installer().install(&prefix, [clobber_2]).await.unwrap();
+        assert_check_files(&prefix, &["clobber.txt"]);
+        assert_eq!(
+            fs::read_to_string(prefix.join("clobber.txt")).unwrap(),
+            "clobber-2"
+        );
+        let prefix_records = PrefixRecord::collect_from_prefix(&prefix).unwrap();
+        assert_eq!(
+            find_prefix_record(&prefix_records, "clobber-2")
+                .unwrap()
+                .files,
+            [PathBuf::from("clobber.txt")]
+        );
+    }
 }
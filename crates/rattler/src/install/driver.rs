@@ -1,16 +1,17 @@
-use super::clobber_registry::ClobberRegistry;
-use super::link_script::PrePostLinkResult;
+use super::clobber_registry::{ClobberPolicy, ClobberRegistry};
+use super::link_script::{LinkScriptOptions, LinkScriptPolicy, PrePostLinkResult};
 use super::unlink::{recursively_remove_empty_directories, UnlinkError};
-use super::{InstallError, Transaction};
+use super::{FilesystemId, InstallError, Transaction};
 use indexmap::IndexSet;
 use itertools::Itertools;
 use rattler_conda_types::prefix_record::PathType;
 use rattler_conda_types::{PackageRecord, PrefixRecord};
 use std::borrow::Borrow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::MutexGuard;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
 use tokio::task::JoinError;
 
@@ -22,7 +23,8 @@ use tokio::task::JoinError;
 pub struct InstallDriver {
     io_concurrency_semaphore: Option<Arc<Semaphore>>,
     clobber_registry: Arc<Mutex<ClobberRegistry>>,
-    execute_link_scripts: bool,
+    reflink_support: Mutex<HashMap<(FilesystemId, FilesystemId), bool>>,
+    link_script_options: LinkScriptOptions,
 }
 
 impl Default for InstallDriver {
@@ -39,7 +41,8 @@ impl Default for InstallDriver {
 pub struct InstallDriverBuilder {
     io_concurrency_semaphore: Option<Arc<Semaphore>>,
     clobber_registry: Option<ClobberRegistry>,
-    execute_link_scripts: bool,
+    clobber_policy: ClobberPolicy,
+    link_script_options: LinkScriptOptions,
 }
 
 impl InstallDriverBuilder {
@@ -72,10 +75,45 @@ impl InstallDriverBuilder {
         }
     }
 
-    /// Sets whether to execute link scripts or not.
+    /// Sets the policy that determines which package wins when multiple packages install the same
+    /// path. Defaults to [`ClobberPolicy::TopologicalOrder`].
+    pub fn with_clobber_policy(self, clobber_policy: ClobberPolicy) -> Self {
+        Self {
+            clobber_policy,
+            ..self
+        }
+    }
+
+    /// Sets whether to execute link scripts or not. This is a shorthand for setting the link
+    /// script policy to either [`LinkScriptPolicy::AllowAll`] or [`LinkScriptPolicy::Deny`].
     pub fn execute_link_scripts(self, execute_link_scripts: bool) -> Self {
+        self.with_link_script_policy(if execute_link_scripts {
+            LinkScriptPolicy::AllowAll
+        } else {
+            LinkScriptPolicy::Deny
+        })
+    }
+
+    /// Sets the policy that determines the packages whose link scripts are executed. Defaults to
+    /// [`LinkScriptPolicy::Deny`].
+    pub fn with_link_script_policy(self, policy: LinkScriptPolicy) -> Self {
         Self {
-            execute_link_scripts,
+            link_script_options: LinkScriptOptions {
+                policy,
+                ..self.link_script_options
+            },
+            ..self
+        }
+    }
+
+    /// Sets the maximum amount of time a single link script is allowed to run. Scripts that take
+    /// longer are killed and considered failed.
+    pub fn with_link_script_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            link_script_options: LinkScriptOptions {
+                timeout,
+                ..self.link_script_options
+            },
             ..self
         }
     }
@@ -83,12 +121,13 @@ impl InstallDriverBuilder {
     pub fn finish(self) -> InstallDriver {
         InstallDriver {
             io_concurrency_semaphore: self.io_concurrency_semaphore,
-            clobber_registry: self
-                .clobber_registry
-                .map(Mutex::new)
-                .map(Arc::new)
-                .unwrap_or_default(),
-            execute_link_scripts: self.execute_link_scripts,
+            clobber_registry: Arc::new(Mutex::new(
+                self.clobber_registry
+                    .unwrap_or_default()
+                    .with_policy(self.clobber_policy),
+            )),
+            reflink_support: Mutex::default(),
+            link_script_options: self.link_script_options,
         }
     }
 }
@@ -114,13 +153,26 @@ impl InstallDriver {
         self.clobber_registry.lock().unwrap()
     }
 
+    /// Returns a locked reference to the cache that records whether reflinks can be created from
+    /// one filesystem to another. Keys are the filesystems of the source and destination.
+    pub(crate) fn reflink_support(
+        &self,
+    ) -> MutexGuard<'_, HashMap<(FilesystemId, FilesystemId), bool>> {
+        self.reflink_support.lock().unwrap()
+    }
+
+    /// Returns the options that determine which link scripts are executed.
+    pub fn link_script_options(&self) -> &LinkScriptOptions {
+        &self.link_script_options
+    }
+
     /// Call this before any packages are installed to perform any pre processing that is required.
     pub fn pre_process<Old: Borrow<PrefixRecord>, New>(
         &self,
         transaction: &Transaction<Old, New>,
         target_prefix: &Path,
     ) -> Result<Option<PrePostLinkResult>, InstallError> {
-        if self.execute_link_scripts {
+        if !self.link_script_options.policy.is_deny() {
             match self.run_pre_unlink_scripts(transaction, target_prefix) {
                 Ok(res) => {
                     return Ok(Some(res));
@@ -188,7 +240,7 @@ impl InstallDriver {
                 tracing::error!("Error unclobbering packages: {:?}", e);
             });
 
-        if self.execute_link_scripts {
+        if !self.link_script_options.policy.is_deny() {
             match self.run_post_link_scripts(transaction, &required_packages, target_prefix) {
                 Ok(res) => {
                     return Ok(Some(res));
@@ -7,15 +7,70 @@ use rattler_conda_types::{
 };
 use rattler_digest::HashingWriter;
 use rattler_digest::Sha256;
-use std::{fs::File, io, io::Write, path::Path};
+use std::{
+    fs::File,
+    io,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 /// Get the bytes of the windows launcher executable.
+///
+/// # Panics
+///
+/// Panics if there is no launcher for the platform. Use [`try_get_windows_launcher`] to handle
+/// unsupported platforms gracefully.
 pub fn get_windows_launcher(platform: &Platform) -> &'static [u8] {
+    match try_get_windows_launcher(platform) {
+        Some(launcher) => launcher,
+        None => panic!("entry points are not supported for {platform}"),
+    }
+}
+
+/// Get the bytes of the windows launcher executable or `None` if there is no launcher for the
+/// platform. Launchers are available for 32 bit, 64 bit and ARM64 windows.
+pub fn try_get_windows_launcher(platform: &Platform) -> Option<&'static [u8]> {
+    match platform {
+        Platform::Win32 => Some(include_bytes!("../../resources/launcher32.exe")),
+        Platform::Win64 => Some(include_bytes!("../../resources/launcher64.exe")),
+        Platform::WinArm64 => Some(include_bytes!("../../resources/launcher_arm64.exe")),
+        _ => None,
+    }
+}
+
+/// Get the bytes of the windows launcher executable for graphical applications or `None` if there
+/// is no launcher for the platform. Unlike the launcher of [`try_get_windows_launcher`] this
+/// launcher does not open a console window.
+pub fn try_get_windows_gui_launcher(platform: &Platform) -> Option<&'static [u8]> {
     match platform {
-        Platform::Win32 => unimplemented!("32 bit windows is not supported for entry points"),
-        Platform::Win64 => include_bytes!("../../resources/launcher64.exe"),
-        Platform::WinArm64 => unimplemented!("arm64 windows is not supported for entry points"),
-        _ => panic!("unsupported platform"),
+        Platform::Win32 => Some(include_bytes!("../../resources/gui_launcher32.exe")),
+        Platform::Win64 => Some(include_bytes!("../../resources/gui_launcher64.exe")),
+        Platform::WinArm64 => Some(include_bytes!("../../resources/gui_launcher_arm64.exe")),
+        _ => None,
+    }
+}
+
+/// Returns the paths, relative to the prefix, of the files that are created for an entry point by
+/// [`create_windows_python_entry_point`], [`create_windows_python_gui_entry_point`] or
+/// [`create_unix_python_entry_point`].
+pub(crate) fn python_entry_point_paths(
+    entry_point: &EntryPoint,
+    python_info: &PythonInfo,
+    target_platform: &Platform,
+    gui: bool,
+) -> Vec<PathBuf> {
+    if target_platform.is_windows() {
+        let extension = if gui { "pyw" } else { "py" };
+        vec![
+            python_info
+                .bin_dir
+                .join(format!("{}-script.{extension}", &entry_point.command)),
+            python_info
+                .bin_dir
+                .join(format!("{}.exe", &entry_point.command)),
+        ]
+    } else {
+        vec![python_info.bin_dir.join(&entry_point.command)]
     }
 }
 
@@ -29,10 +84,12 @@ pub fn get_windows_launcher(platform: &Platform) -> &'static [u8] {
 /// extension. So if there is an entry point file called `foo.py` an executable is created called
 /// `foo.exe` that will automatically invoke `foo.py`.
 ///
-/// The special executable is embedded in the library. The source code for the launcher can be found
-/// here: <https://github.com/conda/conda-build/tree/master/conda_build/launcher_sources>.
+/// The special executable is embedded in the library. The launchers are the ones of `setuptools`
+/// 65.5.0 which conda-build uses as well, the source code can be found here:
+/// <https://github.com/conda/conda-build/tree/master/conda_build/launcher_sources>.
 ///
-/// See [`create_unix_python_entry_point`] for the unix variant of this function.
+/// See [`create_unix_python_entry_point`] for the unix variant of this function and
+/// [`create_windows_python_gui_entry_point`] for graphical applications.
 pub fn create_windows_python_entry_point(
     target_dir: &Path,
     target_prefix: &str,
@@ -40,10 +97,67 @@ pub fn create_windows_python_entry_point(
     python_info: &PythonInfo,
     target_platform: &Platform,
 ) -> Result<[PathsEntry; 2], std::io::Error> {
-    // Construct the path to where we will be creating the python entry point script.
+    create_windows_entry_point(
+        target_dir,
+        target_prefix,
+        entry_point,
+        python_info,
+        target_platform,
+        false,
+    )
+}
+
+/// Creates an "entry point" on disk for a Python entrypoint of a graphical application on
+/// windows. This works like [`create_windows_python_entry_point`] but the launcher does not open
+/// a console window and executes a `.pyw` file with `pythonw.exe`.
+///
+/// On unix there is no difference between console and graphical applications, use
+/// [`create_unix_python_entry_point`] for both.
+pub fn create_windows_python_gui_entry_point(
+    target_dir: &Path,
+    target_prefix: &str,
+    entry_point: &EntryPoint,
+    python_info: &PythonInfo,
+    target_platform: &Platform,
+) -> Result<[PathsEntry; 2], std::io::Error> {
+    create_windows_entry_point(
+        target_dir,
+        target_prefix,
+        entry_point,
+        python_info,
+        target_platform,
+        true,
+    )
+}
+
+fn create_windows_entry_point(
+    target_dir: &Path,
+    target_prefix: &str,
+    entry_point: &EntryPoint,
+    python_info: &PythonInfo,
+    target_platform: &Platform,
+    gui: bool,
+) -> Result<[PathsEntry; 2], std::io::Error> {
+    // Make sure there is a launcher for the platform before anything is written to disk.
+    let launcher_bytes = if gui {
+        try_get_windows_gui_launcher(target_platform)
+    } else {
+        try_get_windows_launcher(target_platform)
+    }
+    .ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("python entry points are not supported for {target_platform}"),
+        )
+    })?;
+
+    // Construct the path to where we will be creating the python entry point script. The
+    // launcher executes the file with the same name as the executable and the `-script.py`, or
+    // for graphical applications `-script.pyw`, suffix.
+    let extension = if gui { "pyw" } else { "py" };
     let relative_path_script_py = python_info
         .bin_dir
-        .join(format!("{}-script.py", &entry_point.command));
+        .join(format!("{}-script.{extension}", &entry_point.command));
 
     // Write the contents of the launcher script to disk
     let script_path = target_dir.join(&relative_path_script_py);
@@ -52,8 +166,13 @@ pub fn create_windows_python_entry_point(
             .parent()
             .expect("since we joined with target_dir there must be a parent"),
     )?;
-    let script_contents =
+    let mut script_contents =
         python_entry_point_template(target_prefix, true, entry_point, python_info);
+    if gui {
+        // Without a shebang the launcher uses `python.exe`, which opens a console window. A
+        // shebang without a path keeps working for prefixes with spaces.
+        script_contents.insert_str(0, "#!pythonw.exe");
+    }
     let (hash, size) = write_and_hash(&script_path, script_contents)?;
 
     // Construct a path to where we will create the python launcher executable.
@@ -61,14 +180,8 @@ pub fn create_windows_python_entry_point(
         .bin_dir
         .join(format!("{}.exe", &entry_point.command));
 
-    // Include the bytes of the launcher directly in the binary so we can write it to disk.
-    let launcher_bytes = get_windows_launcher(target_platform);
     std::fs::write(target_dir.join(&relative_path_script_exe), launcher_bytes)?;
-
-    let fixed_launcher_digest = rattler_digest::parse_digest_from_hex::<rattler_digest::Sha256>(
-        "28b001bb9a72ae7a24242bfab248d767a1ac5dec981c672a3944f7a072375e9a",
-    )
-    .unwrap();
+    let launcher_digest = rattler_digest::compute_bytes_digest::<Sha256>(launcher_bytes);
 
     Ok([
         PathsEntry {
@@ -88,7 +201,7 @@ pub fn create_windows_python_entry_point(
             original_path: None,
             path_type: PathType::WindowsPythonEntryPointExe,
             no_link: false,
-            sha256: Some(fixed_launcher_digest),
+            sha256: Some(launcher_digest),
             sha256_in_prefix: None,
             size_in_bytes: Some(launcher_bytes.len() as u64),
             prefix_placeholder: None,
@@ -219,4 +332,95 @@ mod test {
         );
         insta::assert_snapshot!("windows", script);
     }
+
+    #[test]
+    fn test_entry_point_long_shebang() {
+        let version = Version::from_str("3.11.0").unwrap();
+        let prefix = format!("/{}", "a".repeat(200));
+
+        // Linux limits shebangs to 127 characters
+        let linux = PythonInfo::from_version(&version, Platform::Linux64).unwrap();
+        assert!(linux.shebang(&prefix).starts_with("#!/bin/sh\n"));
+        assert_eq!(linux.shebang("/prefix"), "#!/prefix/bin/python3.11");
+
+        // macOS allows shebangs up to 512 characters
+        let osx = PythonInfo::from_version(&version, Platform::OsxArm64).unwrap();
+        assert_eq!(osx.shebang(&prefix), format!("#!{prefix}/bin/python3.11"));
+
+        // Spaces are never allowed
+        assert!(osx.shebang("/my prefix").starts_with("#!/bin/sh\n"));
+    }
+
+    #[test]
+    fn test_windows_entry_points() {
+        let entry_point = EntryPoint::from_str("jupyter-lab = jupyterlab.labapp:main").unwrap();
+        for platform in [Platform::Win32, Platform::Win64, Platform::WinArm64] {
+            let target_dir = tempfile::tempdir().unwrap();
+            let python_info =
+                PythonInfo::from_version(&Version::from_str("3.11.0").unwrap(), platform).unwrap();
+
+            let [script, exe] = super::create_windows_python_entry_point(
+                target_dir.path(),
+                "C:/prefix",
+                &entry_point,
+                &python_info,
+                &platform,
+            )
+            .unwrap();
+            assert_eq!(
+                script.relative_path.to_str(),
+                Some("Scripts/jupyter-lab-script.py")
+            );
+            let launcher = std::fs::read(target_dir.path().join(&exe.relative_path)).unwrap();
+            assert_eq!(launcher, super::get_windows_launcher(&platform));
+
+            // Graphical applications use another launcher that executes a `.pyw` script with
+            // `pythonw.exe`.
+            let [script, exe] = super::create_windows_python_gui_entry_point(
+                target_dir.path(),
+                "C:/prefix",
+                &entry_point,
+                &python_info,
+                &platform,
+            )
+            .unwrap();
+            assert_eq!(
+                script.relative_path.to_str(),
+                Some("Scripts/jupyter-lab-script.pyw")
+            );
+            let contents =
+                std::fs::read_to_string(target_dir.path().join(&script.relative_path)).unwrap();
+            assert!(contents.starts_with("#!pythonw.exe\n"));
+            let launcher = std::fs::read(target_dir.path().join(&exe.relative_path)).unwrap();
+            assert_eq!(
+                Some(launcher.as_slice()),
+                super::try_get_windows_gui_launcher(&platform)
+            );
+            assert_ne!(launcher, super::get_windows_launcher(&platform));
+            assert_eq!(
+                exe.sha256,
+                Some(rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(&launcher))
+            );
+        }
+    }
+
+    #[test]
+    fn test_windows_entry_point_unsupported_platform() {
+        let target_dir = tempfile::tempdir().unwrap();
+        let python_info =
+            PythonInfo::from_version(&Version::from_str("3.11.0").unwrap(), Platform::Linux64)
+                .unwrap();
+        let err = super::create_windows_python_entry_point(
+            target_dir.path(),
+            "/prefix",
+            &EntryPoint::from_str("jupyter-lab = jupyterlab.labapp:main").unwrap(),
+            &python_info,
+            &Platform::Linux64,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+
+        // Nothing should have been written
+        assert_eq!(std::fs::read_dir(target_dir.path()).unwrap().count(), 0);
+    }
 }
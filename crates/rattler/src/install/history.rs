@@ -0,0 +1,546 @@
+//! The history of a prefix. Every transaction that modifies the prefix is recorded as a revision in
+//! `conda-meta/history`, using the same format as conda:
+//!
+//! ```text
+//! ==> 2024-05-01 12:00:00 <==
+//! # cmd: rattler create python
+//! +https://conda.anaconda.org/conda-forge/linux-64::python-3.12.3-hab00c5b_0
+//! -https://conda.anaconda.org/conda-forge/linux-64::python-3.12.2-hab00c5b_0
+//! ```
+//!
+//! Revisions are numbered from zero in the order in which they were written. The packages that
+//! were installed after a revision are obtained by replaying the additions (`+`) and removals
+//! (`-`) of all revisions up to it, see [`History::state`]. The prefix is rolled back to a
+//! previous revision with [`super::Installer::rollback`].
+
+use std::{
+    fmt::{Display, Formatter},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{NaiveDateTime, Utc};
+use fs_err as fs;
+use rattler_conda_types::{PackageName, PackageRecord, PrefixRecord, RepoDataRecord};
+
+use super::{Transaction, TransactionOperation};
+
+/// The path of the history file, relative to the prefix.
+pub const HISTORY_FILE: &str = "conda-meta/history";
+
+/// The format of the timestamps in the history file.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// An error that can occur while reading the history of a prefix or while determining the
+/// packages of a revision.
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    /// The history file could not be read.
+    #[error("failed to read {}", .0.display())]
+    IoError(PathBuf, #[source] std::io::Error),
+
+    /// A line of the history file does not describe a package.
+    #[error("invalid package '{1}' on line {0} of the history")]
+    InvalidPackage(usize, String),
+
+    /// The requested revision does not exist.
+    #[error("the prefix has no revision {0}")]
+    RevisionNotFound(usize),
+
+    /// Some packages of a revision are neither installed nor available.
+    #[error("no record is available for {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    MissingRecords(Vec<HistoryPackage>),
+}
+
+/// A package as it is identified in the history: the channel it was installed from together with
+/// its name, version and build string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct HistoryPackage {
+    /// The URL of the subdirectory of the channel that the package was installed from. Older
+    /// histories do not record the channel.
+    pub channel: Option<String>,
+
+    /// The name of the package.
+    pub name: PackageName,
+
+    /// The version of the package.
+    pub version: String,
+
+    /// The build string of the package.
+    pub build: String,
+}
+
+impl HistoryPackage {
+    /// Returns true if `record` has the same name, version and build string as this package.
+    pub fn matches(&self, record: &PackageRecord) -> bool {
+        self.name == record.name
+            && self.build == record.build
+            && self.version == record.version.to_string()
+    }
+
+    /// Returns true if this and the `other` package have the same name, version and build string,
+    /// regardless of their channels.
+    fn same_package(&self, other: &HistoryPackage) -> bool {
+        self.name == other.name && self.version == other.version && self.build == other.build
+    }
+}
+
+impl From<&RepoDataRecord> for HistoryPackage {
+    fn from(record: &RepoDataRecord) -> Self {
+        let channel = record
+            .url
+            .as_str()
+            .rsplit_once('/')
+            .map(|(subdir, _)| subdir.to_string());
+        Self {
+            channel,
+            name: record.package_record.name.clone(),
+            version: record.package_record.version.to_string(),
+            build: record.package_record.build.clone(),
+        }
+    }
+}
+
+impl Display for HistoryPackage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(channel) = &self.channel {
+            write!(f, "{channel}::")?;
+        }
+        write!(
+            f,
+            "{}-{}-{}",
+            self.name.as_normalized(),
+            self.version,
+            self.build
+        )
+    }
+}
+
+impl FromStr for HistoryPackage {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, dist) = match s.rsplit_once("::") {
+            Some((channel, dist)) => (Some(channel.to_string()), dist),
+            None => (None, s),
+        };
+        let mut parts = dist.rsplitn(3, '-');
+        let (Some(build), Some(version), Some(name)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(());
+        };
+        Ok(Self {
+            channel,
+            name: PackageName::try_from(name).ok().ok_or(())?,
+            version: version.to_string(),
+            build: build.to_string(),
+        })
+    }
+}
+
+/// A single revision of the history of a prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Revision {
+    /// The time at which the revision was recorded, if it could be parsed. Rattler records the
+    /// time in UTC, conda uses the local time.
+    pub timestamp: Option<NaiveDateTime>,
+
+    /// The command that created the revision, if it was recorded.
+    pub command: Option<String>,
+
+    /// The packages that were installed.
+    pub added: Vec<HistoryPackage>,
+
+    /// The packages that were removed.
+    pub removed: Vec<HistoryPackage>,
+}
+
+impl Revision {
+    /// Constructs the revision that records the packages that are added and removed by the
+    /// `transaction`. The revision is timestamped with the current time.
+    pub fn from_transaction(
+        transaction: &Transaction<PrefixRecord, RepoDataRecord>,
+        command: Option<String>,
+    ) -> Self {
+        let mut revision = Self {
+            timestamp: Some(Utc::now().naive_utc()),
+            command,
+            ..Self::default()
+        };
+        for operation in &transaction.operations {
+            match operation {
+                TransactionOperation::Install(new) => revision.added.push(new.into()),
+                TransactionOperation::Change { old, new } => {
+                    revision.removed.push((&old.repodata_record).into());
+                    revision.added.push(new.into());
+                }
+                TransactionOperation::Remove(old) => {
+                    revision.removed.push((&old.repodata_record).into());
+                }
+                TransactionOperation::Reinstall(_) => {}
+            }
+        }
+        revision.added.sort();
+        revision.removed.sort();
+        revision
+    }
+
+    /// Returns true if the revision neither adds nor removes packages.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Display for Revision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let timestamp = self
+            .timestamp
+            .unwrap_or_else(|| Utc::now().naive_utc())
+            .format(TIMESTAMP_FORMAT);
+        writeln!(f, "==> {timestamp} <==")?;
+        if let Some(command) = &self.command {
+            writeln!(f, "# cmd: {command}")?;
+        }
+        for package in &self.removed {
+            writeln!(f, "-{package}")?;
+        }
+        for package in &self.added {
+            writeln!(f, "+{package}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The history of a prefix. See the [module documentation](self) for more information.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    revisions: Vec<Revision>,
+}
+
+impl History {
+    /// Returns the path of the history file of the prefix.
+    pub fn history_file_path(prefix: &Path) -> PathBuf {
+        prefix.join(HISTORY_FILE)
+    }
+
+    /// Reads the history of the prefix. A prefix without a history file has no revisions.
+    pub fn from_prefix(prefix: &Path) -> Result<Self, HistoryError> {
+        let path = Self::history_file_path(prefix);
+        match fs::read_to_string(&path) {
+            Ok(contents) => contents.parse(),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(HistoryError::IoError(path, e)),
+        }
+    }
+
+    /// Appends a revision to the history file of the prefix.
+    pub fn append(prefix: &Path, revision: &Revision) -> std::io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::history_file_path(prefix))?;
+        file.write_all(revision.to_string().as_bytes())?;
+        file.sync_all()
+    }
+
+    /// Returns all revisions, the index of a revision is its number.
+    pub fn revisions(&self) -> &[Revision] {
+        &self.revisions
+    }
+
+    /// Returns the packages that were installed in the prefix after the given revision, sorted
+    /// by name.
+    pub fn state(&self, revision: usize) -> Result<Vec<HistoryPackage>, HistoryError> {
+        let revisions = self
+            .revisions
+            .get(..=revision)
+            .ok_or(HistoryError::RevisionNotFound(revision))?;
+        let mut state: Vec<HistoryPackage> = Vec::new();
+        for revision in revisions {
+            state.retain(|package| {
+                !revision
+                    .removed
+                    .iter()
+                    .any(|removed| removed.same_package(package))
+            });
+            state.extend(revision.added.iter().cloned());
+        }
+        state.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(state)
+    }
+
+    /// Returns the records of the packages that were installed in the prefix after the given
+    /// revision. Installing these records with an [`super::Installer`] rolls the prefix back to
+    /// the revision.
+    ///
+    /// Records of packages that are still `installed` are reused. The records of other packages
+    /// are looked up in `available`, for instance the records of a channel. If several available
+    /// records match a package, the one from the channel that the package was originally
+    /// installed from is preferred.
+    pub fn records_for_revision(
+        &self,
+        revision: usize,
+        installed: &[PrefixRecord],
+        available: impl IntoIterator<Item = RepoDataRecord>,
+    ) -> Result<Vec<RepoDataRecord>, HistoryError> {
+        let state = self.state(revision)?;
+        let available = available.into_iter().collect::<Vec<_>>();
+
+        let mut records = Vec::with_capacity(state.len());
+        let mut missing = Vec::new();
+        for package in state {
+            let installed = installed
+                .iter()
+                .map(|record| &record.repodata_record)
+                .find(|record| package.matches(&record.package_record));
+            let mut candidates = available
+                .iter()
+                .filter(|record| package.matches(&record.package_record));
+            let candidate = candidates.clone().find(|record| {
+                package.channel.is_some()
+                    && HistoryPackage::from(*record).channel == package.channel
+            });
+            match installed.or(candidate).or_else(|| candidates.next()) {
+                Some(record) => records.push(record.clone()),
+                None => missing.push(package),
+            }
+        }
+
+        if missing.is_empty() {
+            Ok(records)
+        } else {
+            Err(HistoryError::MissingRecords(missing))
+        }
+    }
+}
+
+impl FromStr for History {
+    type Err = HistoryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut history = Self::default();
+        // Older versions of conda recorded the complete state of the prefix instead of the
+        // changes. These packages are converted into additions and removals once the revision is
+        // complete.
+        let mut snapshot: Option<Vec<HistoryPackage>> = None;
+        let mut state = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if let Some(timestamp) = line
+                .strip_prefix("==>")
+                .and_then(|line| line.strip_suffix("<=="))
+            {
+                finish_revision(&mut history, snapshot.take(), &mut state);
+                history.revisions.push(Revision {
+                    timestamp: NaiveDateTime::parse_from_str(timestamp.trim(), TIMESTAMP_FORMAT)
+                        .ok(),
+                    ..Revision::default()
+                });
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let Some(revision) = history.revisions.last_mut() else {
+                continue;
+            };
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some(command) = comment.trim_start().strip_prefix("cmd:") {
+                    revision.command = Some(command.trim().to_string());
+                }
+                continue;
+            }
+
+            let parse = |package: &str| {
+                package
+                    .parse::<HistoryPackage>()
+                    .map_err(|()| HistoryError::InvalidPackage(index + 1, line.to_string()))
+            };
+            if let Some(package) = line.strip_prefix('+') {
+                revision.added.push(parse(package)?);
+            } else if let Some(package) = line.strip_prefix('-') {
+                revision.removed.push(parse(package)?);
+            } else {
+                snapshot.get_or_insert_with(Vec::new).push(parse(line)?);
+            }
+        }
+        finish_revision(&mut history, snapshot, &mut state);
+        Ok(history)
+    }
+}
+
+/// Completes the last revision of the `history`. If the revision contains a `snapshot` of the
+/// complete state it is converted into additions and removals relative to the previous `state`.
+/// Afterwards `state` is updated to include the revision.
+fn finish_revision(
+    history: &mut History,
+    snapshot: Option<Vec<HistoryPackage>>,
+    state: &mut Vec<HistoryPackage>,
+) {
+    let Some(revision) = history.revisions.last_mut() else {
+        return;
+    };
+    if let Some(snapshot) = snapshot {
+        revision.removed.extend(
+            state
+                .iter()
+                .filter(|package| !snapshot.iter().any(|new| new.same_package(package)))
+                .cloned(),
+        );
+        revision.added.extend(
+            snapshot
+                .iter()
+                .filter(|package| !state.iter().any(|old| old.same_package(package)))
+                .cloned(),
+        );
+    }
+    state.retain(|package| {
+        !revision
+            .removed
+            .iter()
+            .any(|removed| removed.same_package(package))
+    });
+    state.extend(revision.added.iter().cloned());
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use assert_matches::assert_matches;
+    use rattler_conda_types::{PackageRecord, RepoDataRecord, Version};
+
+    use super::{History, HistoryError, HistoryPackage, Revision};
+
+    const HISTORY: &str = "\
+==> 2023-01-01 10:00:00 <==
+# cmd: conda create -n test
+conda-forge/linux-64::zlib-1.2.13-h166bdaf_4
+conda-forge/linux-64::libzlib-1.2.13-h166bdaf_4
+==> 2023-02-01 10:00:00 <==
+# cmd: conda install python
+# update specs: ['python']
++conda-forge/linux-64::python-3.11.0-h10a6764_1
+-conda-forge/linux-64::zlib-1.2.13-h166bdaf_4
+==> 2023-03-01 10:00:00 <==
+-conda-forge/linux-64::python-3.11.0-h10a6764_1
++conda-forge/linux-64::python-3.12.0-hab00c5b_0
+";
+
+    fn repodata_record(channel: &str, name: &str, version: &str) -> RepoDataRecord {
+        RepoDataRecord {
+            file_name: format!("{name}-{version}-0.conda"),
+            url: format!("https://conda.anaconda.org/{channel}/noarch/{name}-{version}-0.conda")
+                .parse()
+                .unwrap(),
+            channel: format!("https://conda.anaconda.org/{channel}/"),
+            package_record: PackageRecord::new(
+                name.parse().unwrap(),
+                Version::from_str(version).unwrap(),
+                "0".to_string(),
+            ),
+        }
+    }
+
+    fn names(packages: &[HistoryPackage]) -> Vec<String> {
+        packages.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_history() {
+        let history: History = HISTORY.parse().unwrap();
+        assert_eq!(history.revisions().len(), 3);
+        assert_eq!(
+            history.revisions()[1].command.as_deref(),
+            Some("conda install python")
+        );
+        assert_eq!(
+            history.revisions()[0].timestamp.unwrap().to_string(),
+            "2023-01-01 10:00:00"
+        );
+
+        assert_eq!(
+            names(&history.state(0).unwrap()),
+            [
+                "conda-forge/linux-64::libzlib-1.2.13-h166bdaf_4",
+                "conda-forge/linux-64::zlib-1.2.13-h166bdaf_4"
+            ]
+        );
+        assert_eq!(
+            names(&history.state(1).unwrap()),
+            [
+                "conda-forge/linux-64::libzlib-1.2.13-h166bdaf_4",
+                "conda-forge/linux-64::python-3.11.0-h10a6764_1"
+            ]
+        );
+        assert_eq!(
+            names(&history.state(2).unwrap()),
+            [
+                "conda-forge/linux-64::libzlib-1.2.13-h166bdaf_4",
+                "conda-forge/linux-64::python-3.12.0-hab00c5b_0"
+            ]
+        );
+        assert_matches!(history.state(3), Err(HistoryError::RevisionNotFound(3)));
+
+        assert_matches!(
+            "==> 2023-01-01 10:00:00 <==\n+not a package\n".parse::<History>(),
+            Err(HistoryError::InvalidPackage(2, _))
+        );
+    }
+
+    #[test]
+    fn test_write_history() {
+        let prefix = tempfile::tempdir().unwrap();
+        std::fs::create_dir(prefix.path().join("conda-meta")).unwrap();
+        assert!(History::from_prefix(prefix.path())
+            .unwrap()
+            .revisions()
+            .is_empty());
+
+        let record = repodata_record("conda-forge", "foo", "1.0");
+        let revision = Revision {
+            timestamp: None,
+            command: Some("rattler create".to_string()),
+            added: vec![HistoryPackage::from(&record)],
+            removed: Vec::new(),
+        };
+        History::append(prefix.path(), &revision).unwrap();
+        History::append(
+            prefix.path(),
+            &Revision {
+                added: Vec::new(),
+                removed: revision.added.clone(),
+                ..revision.clone()
+            },
+        )
+        .unwrap();
+
+        let history = History::from_prefix(prefix.path()).unwrap();
+        assert_eq!(history.revisions().len(), 2);
+        assert_eq!(history.revisions()[0].added, revision.added);
+        assert_eq!(history.revisions()[0].command, revision.command);
+        assert!(history.state(1).unwrap().is_empty());
+
+        // The record of a removed package has to be provided to restore the revision.
+        assert_matches!(
+            history.records_for_revision(0, &[], []),
+            Err(HistoryError::MissingRecords(missing)) if missing == revision.added
+        );
+        // Records from the channel the package was installed from are preferred.
+        let mirrored = repodata_record("mirror", "foo", "1.0");
+        let other = repodata_record("conda-forge", "foo", "2.0");
+        assert_eq!(
+            history
+                .records_for_revision(0, &[], [other, mirrored.clone(), record.clone()])
+                .unwrap(),
+            [record]
+        );
+        assert_eq!(
+            history
+                .records_for_revision(0, &[], [mirrored.clone()])
+                .unwrap(),
+            [mirrored]
+        );
+    }
+}
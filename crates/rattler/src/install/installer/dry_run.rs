@@ -0,0 +1,494 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use rattler_conda_types::package::{IndexJson, PackageFile, PathsJson};
+use rattler_conda_types::{PackageName, Platform, PrefixRecord, RepoDataRecord};
+use serde::Serialize;
+use url::Url;
+
+use super::super::link_script::{LinkScriptPolicy, LinkScriptType};
+use super::super::{compute_paths, Transaction, TransactionOperation};
+use crate::package_cache::PackageCache;
+
+/// A description of all the changes [`super::Installer::install`] would make to a prefix. Created
+/// by [`super::Installer::dry_run`].
+///
+/// The contents of packages that are not yet in the package cache are unknown. These packages are
+/// listed in [`DryRunReport::incomplete_packages`] and their files are not taken into account for
+/// the clobbers, the link scripts and the disk usage.
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunReport {
+    /// The prefix that would be modified.
+    pub prefix: PathBuf,
+
+    /// The platform for which the packages would be installed.
+    pub platform: Platform,
+
+    /// The operations of the transaction, in the order in which they would be executed.
+    pub operations: Vec<PlannedOperation>,
+
+    /// The packages that would have to be fetched.
+    pub downloads: Vec<PlannedDownload>,
+
+    /// The paths that are installed by more than one package.
+    pub clobbers: Vec<PlannedClobber>,
+
+    /// The link scripts that are part of the transaction.
+    pub scripts: Vec<PlannedScript>,
+
+    /// The change in disk usage of the prefix and the package cache.
+    pub disk_usage: DiskUsage,
+
+    /// The file names of the packages whose contents are unknown because they are not in the
+    /// package cache.
+    pub incomplete_packages: Vec<String>,
+}
+
+/// The kind of a [`PlannedOperation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlannedOperationKind {
+    /// A package is installed.
+    Install,
+    /// A package is replaced by another version or build.
+    Change,
+    /// A package is unlinked and linked again.
+    Reinstall,
+    /// A package is removed.
+    Remove,
+}
+
+/// A single operation of the transaction.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedOperation {
+    /// The kind of operation.
+    pub kind: PlannedOperationKind,
+
+    /// The package that is unlinked from the prefix.
+    pub unlink: Option<PackageSummary>,
+
+    /// The package that is linked into the prefix.
+    pub link: Option<PackageSummary>,
+}
+
+/// Identifies a package in a [`DryRunReport`].
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageSummary {
+    /// The name of the package.
+    pub name: PackageName,
+
+    /// The version of the package.
+    pub version: String,
+
+    /// The build string of the package.
+    pub build: String,
+
+    /// The channel the package comes from.
+    pub channel: String,
+}
+
+impl From<&RepoDataRecord> for PackageSummary {
+    fn from(record: &RepoDataRecord) -> Self {
+        Self {
+            name: record.package_record.name.clone(),
+            version: record.package_record.version.to_string(),
+            build: record.package_record.build.clone(),
+            channel: record.channel.clone(),
+        }
+    }
+}
+
+/// A package that has to be fetched before it can be linked.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedDownload {
+    /// The file name of the package archive.
+    pub file_name: String,
+
+    /// The URL the package is downloaded from.
+    pub url: Url,
+
+    /// The size of the archive in bytes, if known.
+    pub size: Option<u64>,
+
+    /// True if the package is already present in the package cache and doesn't have to be
+    /// downloaded.
+    pub cached: bool,
+}
+
+/// A path that is installed by more than one package.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedClobber {
+    /// The path relative to the prefix.
+    pub path: PathBuf,
+
+    /// The names of the packages that install the path.
+    pub packages: Vec<PackageName>,
+}
+
+/// A link script that is part of the transaction.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedScript {
+    /// The name of the package the script belongs to.
+    pub package: PackageName,
+
+    /// The type of the script.
+    pub script: LinkScriptType,
+
+    /// The path of the script relative to the prefix or the package.
+    pub path: String,
+
+    /// True if the script would be executed according to the [`LinkScriptPolicy`].
+    pub will_run: bool,
+}
+
+/// The change in disk usage caused by the transaction.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DiskUsage {
+    /// The number of bytes that would be downloaded.
+    pub download_bytes: u64,
+
+    /// The number of bytes of the files that would be linked into the prefix.
+    pub bytes_added: u64,
+
+    /// The number of bytes of the files that would be removed from the prefix.
+    pub bytes_removed: u64,
+
+    /// The net change of the size of the prefix in bytes. Hard links to the package cache are
+    /// counted as if they were copies.
+    pub delta: i64,
+}
+
+/// The contents of a package to install that are read from the package cache.
+struct CachedContents {
+    index_json: IndexJson,
+    paths_json: PathsJson,
+    package_dir: PathBuf,
+}
+
+/// Computes the [`DryRunReport`] for the given transaction without modifying the prefix or the
+/// package cache.
+pub(super) fn plan_transaction(
+    prefix: &Path,
+    installed: &[PrefixRecord],
+    transaction: &Transaction<PrefixRecord, RepoDataRecord>,
+    package_cache: &PackageCache,
+    link_script_policy: &LinkScriptPolicy,
+) -> DryRunReport {
+    let platform = transaction.platform;
+    let mut disk_usage = DiskUsage::default();
+    let mut operations = Vec::with_capacity(transaction.operations.len());
+    let mut downloads = Vec::new();
+    let mut scripts = Vec::new();
+    let mut incomplete_packages = Vec::new();
+
+    // Start with the paths of the packages that stay in the prefix.
+    let removed = transaction
+        .operations
+        .iter()
+        .filter_map(TransactionOperation::record_to_remove)
+        .map(|record| record.repodata_record.package_record.name.clone())
+        .collect::<HashSet<_>>();
+    let mut paths: BTreeMap<PathBuf, Vec<PackageName>> = BTreeMap::new();
+    for record in installed {
+        let name = &record.repodata_record.package_record.name;
+        if removed.contains(name) {
+            continue;
+        }
+        for entry in &record.paths_data.paths {
+            let path = entry.original_path.as_ref().unwrap_or(&entry.relative_path);
+            paths.entry(path.clone()).or_default().push(name.clone());
+        }
+    }
+
+    for operation in &transaction.operations {
+        let (kind, unlink, link) = match operation {
+            TransactionOperation::Install(new) => (PlannedOperationKind::Install, None, Some(new)),
+            TransactionOperation::Change { old, new } => {
+                (PlannedOperationKind::Change, Some(old), Some(new))
+            }
+            TransactionOperation::Reinstall(old) => (
+                PlannedOperationKind::Reinstall,
+                Some(old),
+                Some(&old.repodata_record),
+            ),
+            TransactionOperation::Remove(old) => (PlannedOperationKind::Remove, Some(old), None),
+        };
+
+        if let Some(old) = unlink {
+            let package_record = &old.repodata_record.package_record;
+            let script_path = LinkScriptType::PreUnlink.get_path(package_record, &platform);
+            if old.files.iter().any(|file| file == Path::new(&script_path)) {
+                scripts.push(PlannedScript {
+                    package: package_record.name.clone(),
+                    script: LinkScriptType::PreUnlink,
+                    path: script_path,
+                    will_run: link_script_policy.is_allowed(&package_record.name),
+                });
+            }
+            disk_usage.bytes_removed += removed_bytes(prefix, old);
+        }
+
+        if let Some(new) = link {
+            let cached_dir = package_cache.cached_package_dir(&new.package_record);
+            let size = new.package_record.size;
+            downloads.push(PlannedDownload {
+                file_name: new.file_name.clone(),
+                url: new.url.clone(),
+                size,
+                cached: cached_dir.is_some(),
+            });
+            if cached_dir.is_none() {
+                disk_usage.download_bytes += size.unwrap_or(0);
+            }
+
+            match cached_dir.and_then(|package_dir| read_cached_contents(&package_dir)) {
+                Some(contents) => {
+                    let name = &new.package_record.name;
+                    for script in [LinkScriptType::PreLink, LinkScriptType::PostLink] {
+                        let script_path = script.get_path(&new.package_record, &platform);
+                        if contents
+                            .paths_json
+                            .paths
+                            .iter()
+                            .any(|entry| entry.relative_path == Path::new(&script_path))
+                        {
+                            scripts.push(PlannedScript {
+                                package: name.clone(),
+                                script,
+                                path: script_path,
+                                will_run: link_script_policy.is_allowed(name),
+                            });
+                        }
+                    }
+
+                    // The paths of noarch python packages are unknown without python.
+                    let has_known_paths = !contents.index_json.noarch.is_python()
+                        || transaction.python_info.is_some();
+                    if has_known_paths {
+                        for (entry, path) in compute_paths(
+                            &contents.index_json,
+                            &contents.paths_json,
+                            transaction.python_info.as_ref(),
+                        ) {
+                            disk_usage.bytes_added += entry.size_in_bytes.unwrap_or_else(|| {
+                                file_size(&contents.package_dir.join(&entry.relative_path))
+                            });
+                            let packages = paths.entry(path).or_default();
+                            if !packages.contains(name) {
+                                packages.push(name.clone());
+                            }
+                        }
+                    }
+                }
+                None => incomplete_packages.push(new.file_name.clone()),
+            }
+        }
+
+        operations.push(PlannedOperation {
+            kind,
+            unlink: unlink.map(|old| PackageSummary::from(&old.repodata_record)),
+            link: link.map(PackageSummary::from),
+        });
+    }
+
+    let clobbers = paths
+        .into_iter()
+        .filter(|(_, packages)| packages.len() > 1)
+        .map(|(path, packages)| PlannedClobber { path, packages })
+        .collect();
+
+    disk_usage.delta = i64::try_from(disk_usage.bytes_added).unwrap_or(i64::MAX)
+        - i64::try_from(disk_usage.bytes_removed).unwrap_or(i64::MAX);
+
+    DryRunReport {
+        prefix: prefix.to_path_buf(),
+        platform,
+        operations,
+        downloads,
+        clobbers,
+        scripts,
+        disk_usage,
+        incomplete_packages,
+    }
+}
+
+/// Reads the metadata of a package from its directory in the package cache.
+fn read_cached_contents(package_dir: &Path) -> Option<CachedContents> {
+    let index_json = IndexJson::from_package_directory(package_dir).ok()?;
+    let paths_json =
+        PathsJson::from_package_directory_with_deprecated_fallback(package_dir).ok()?;
+    Some(CachedContents {
+        index_json,
+        paths_json,
+        package_dir: package_dir.to_path_buf(),
+    })
+}
+
+/// Returns the number of bytes the files of an installed package occupy in the prefix.
+fn removed_bytes(prefix: &Path, record: &PrefixRecord) -> u64 {
+    record
+        .paths_data
+        .paths
+        .iter()
+        .map(|entry| {
+            entry
+                .size_in_bytes
+                .unwrap_or_else(|| file_size(&prefix.join(&entry.relative_path)))
+        })
+        .sum()
+}
+
+/// Returns the size of the file at `path`, or 0 if it doesn't exist.
+fn file_size(path: &Path) -> u64 {
+    fs_err::symlink_metadata(path).map_or(0, |metadata| metadata.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{plan_transaction, PlannedOperationKind};
+    use crate::install::{LinkScriptPolicy, Transaction};
+    use crate::package_cache::PackageCache;
+    use rattler_conda_types::{
+        prefix_record::PathsEntry, PackageRecord, Platform, PrefixRecord, RepoDataRecord,
+    };
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn repodata_record(name: &str, version: &str) -> RepoDataRecord {
+        let mut package_record = PackageRecord::new(
+            name.parse().unwrap(),
+            rattler_conda_types::Version::from_str(version).unwrap(),
+            "0".to_string(),
+        );
+        package_record.size = Some(100);
+        RepoDataRecord {
+            file_name: format!("{name}-{version}-0.tar.bz2"),
+            url: format!(
+                "https://conda.anaconda.org/conda-forge/noarch/{name}-{version}-0.tar.bz2"
+            )
+            .parse()
+            .unwrap(),
+            channel: "https://conda.anaconda.org/conda-forge/".to_string(),
+            package_record,
+        }
+    }
+
+    fn prefix_record(name: &str, version: &str, files: &[&str]) -> PrefixRecord {
+        let paths = files
+            .iter()
+            .map(|file| PathsEntry {
+                relative_path: PathBuf::from(file),
+                original_path: None,
+                path_type: rattler_conda_types::prefix_record::PathType::HardLink,
+                no_link: false,
+                sha256: None,
+                sha256_in_prefix: None,
+                size_in_bytes: Some(10),
+                file_mode: None,
+                prefix_placeholder: None,
+            })
+            .collect::<Vec<_>>();
+        PrefixRecord::from_repodata_record(
+            repodata_record(name, version),
+            None,
+            None,
+            paths,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_plan_transaction() {
+        let prefix = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let installed = vec![
+            prefix_record("foo", "1.0", &["lib/foo.txt", "bin/.foo-pre-unlink.sh"]),
+            prefix_record("bar", "1.0", &["lib/bar.txt"]),
+        ];
+        let transaction = Transaction::from_current_and_desired(
+            installed.clone(),
+            vec![repodata_record("foo", "2.0"), repodata_record("baz", "1.0")],
+            Platform::Linux64,
+        )
+        .unwrap();
+
+        let report = plan_transaction(
+            prefix.path(),
+            &installed,
+            &transaction,
+            &PackageCache::new(cache.path()),
+            &LinkScriptPolicy::Deny,
+        );
+
+        let mut kinds = report
+            .operations
+            .iter()
+            .map(|operation| format!("{:?}", operation.kind))
+            .collect::<Vec<_>>();
+        kinds.sort();
+        assert_eq!(kinds, vec!["Change", "Install", "Remove"]);
+        assert!(report
+            .operations
+            .iter()
+            .any(|operation| operation.kind == PlannedOperationKind::Remove
+                && operation.unlink.as_ref().unwrap().name.as_normalized() == "bar"));
+        assert_eq!(report.downloads.len(), 2);
+        assert!(report.downloads.iter().all(|download| !download.cached));
+        assert_eq!(report.disk_usage.download_bytes, 200);
+        assert_eq!(report.disk_usage.bytes_removed, 30);
+        assert_eq!(report.disk_usage.delta, -30);
+        assert_eq!(report.incomplete_packages.len(), 2);
+        assert_eq!(report.scripts.len(), 1);
+        assert!(!report.scripts[0].will_run);
+
+        // The report is serializable.
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["scripts"][0]["script"], "pre-unlink");
+
+        // Nothing was written to the prefix.
+        assert_eq!(std::fs::read_dir(prefix.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_noarch_python_without_python() {
+        let prefix = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let package_dir = cache.path().join("pyfoo-1.0-0");
+        std::fs::create_dir_all(package_dir.join("info")).unwrap();
+        std::fs::write(
+            package_dir.join("info/index.json"),
+            r#"{"name": "pyfoo", "version": "1.0", "build": "0", "build_number": 0, "noarch": "python"}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            package_dir.join("info/paths.json"),
+            r#"{"paths_version": 1, "paths": [
+                {"_path": "site-packages/pyfoo.py", "path_type": "hardlink", "size_in_bytes": 10},
+                {"_path": "bin/.pyfoo-post-link.sh", "path_type": "hardlink", "size_in_bytes": 10}
+            ]}"#,
+        )
+        .unwrap();
+        let transaction = Transaction::from_current_and_desired(
+            Vec::<PrefixRecord>::new(),
+            vec![repodata_record("pyfoo", "1.0")],
+            Platform::Linux64,
+        )
+        .unwrap();
+
+        let report = plan_transaction(
+            prefix.path(),
+            &[],
+            &transaction,
+            &PackageCache::new(cache.path()),
+            &LinkScriptPolicy::Deny,
+        );
+
+        // The package is still part of the report, only its paths are unknown.
+        assert_eq!(report.operations.len(), 1);
+        assert_eq!(report.operations[0].kind, PlannedOperationKind::Install);
+        assert!(report.incomplete_packages.is_empty());
+        assert_eq!(report.scripts.len(), 1);
+        assert_eq!(report.disk_usage.bytes_added, 0);
+    }
+}
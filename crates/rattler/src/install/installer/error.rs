@@ -0,0 +1,119 @@
+use crate::download::DownloadError;
+use crate::install::history::HistoryError;
+use crate::install::link_script::LinkScriptError;
+use crate::install::prefix_lock::PrefixLockError;
+use crate::install::pyc::PycCompileError;
+use crate::install::{InstallError, TransactionError};
+use crate::package_cache::PackageCacheError;
+use tokio::task::JoinError;
+
+/// An error returned by the [`super::Installer`].
+#[derive(Debug, thiserror::Error)]
+pub enum InstallerError {
+    /// The prefix could not be locked.
+    #[error("failed to lock the prefix")]
+    FailedToLockPrefix(#[source] PrefixLockError),
+
+    /// The packages of the revision to roll back to could not be determined.
+    #[error("failed to determine the packages of the revision")]
+    FailedToDetermineRevision(#[source] HistoryError),
+
+    /// The packages that are currently installed in the prefix could not be determined.
+    #[error("failed to determine the currently installed packages")]
+    FailedToDetectInstalledPackages(#[source] std::io::Error),
+
+    /// No package cache was configured and the default cache directory could not be determined.
+    #[error("could not determine the default package cache directory: {0}")]
+    NoDefaultPackageCache(String),
+
+    /// The transaction could not be constructed.
+    #[error("failed to construct a transaction")]
+    FailedToConstructTransaction(#[from] TransactionError),
+
+    /// The package could not be placed in the package cache.
+    #[error("failed to fetch {0}")]
+    FailedToFetch(String, #[source] PackageCacheError),
+
+    /// There is not enough disk space to download the packages of the transaction.
+    #[error("not enough disk space to download the packages")]
+    InsufficientDiskSpace(#[source] Box<DownloadError>),
+
+    /// A package archive that was passed directly could not be read.
+    #[error("failed to read the package {0}")]
+    FailedToReadPackage(String, #[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// A package archive that was passed by URL could not be downloaded.
+    #[error("failed to download the package {0}")]
+    FailedToDownloadPackage(String, #[source] reqwest_middleware::Error),
+
+    /// The package could not be linked into the prefix.
+    #[error("failed to link {0}")]
+    LinkError(String, #[source] InstallError),
+
+    /// The package could not be removed from the prefix.
+    #[error("failed to unlink {0}")]
+    UnlinkError(String, #[source] std::io::Error),
+
+    /// Failed to write the `conda-meta` record of a package.
+    #[error("failed to write the prefix record of {0}")]
+    FailedToWritePrefixRecord(String, #[source] std::io::Error),
+
+    /// The pre-processing of the transaction failed.
+    #[error("pre-processing failed")]
+    PreProcessingFailed(#[source] InstallError),
+
+    /// The post-processing of the transaction failed.
+    #[error("post-processing failed")]
+    PostProcessingFailed(#[source] InstallError),
+
+    /// Failed to compile the python bytecode of the installed `noarch: python` packages.
+    #[error("failed to compile python bytecode")]
+    PycCompilationFailed(#[source] PycCompileError),
+
+    /// Failed to update the `conda-meta` record of a package.
+    #[error("failed to update the prefix record of {0}")]
+    FailedToUpdatePrefixRecord(String, #[source] std::io::Error),
+
+    /// The pre-link script of a package failed.
+    #[error("the pre-link script of {0} failed")]
+    PreLinkScriptFailed(String, #[source] Option<LinkScriptError>),
+
+    /// One or more post-link scripts failed.
+    #[error("the post-link scripts of {} failed", .0.join(", "))]
+    PostLinkScriptsFailed(Vec<String>),
+
+    /// Failed to read or write the journal of the transaction.
+    #[error("failed to update the transaction journal")]
+    TransactionJournalError(#[source] std::io::Error),
+
+    /// An [`super::InstallHook`] failed.
+    #[error("the install hook '{0}' failed")]
+    HookFailed(String, #[source] super::HookError),
+
+    /// The packages of a locked environment could not be read.
+    #[cfg(feature = "lock-file")]
+    #[error("failed to read the packages of the locked environment")]
+    InvalidLockedEnvironment(#[source] rattler_lock::ConversionError),
+
+    /// The locked environment does not contain packages for the requested platform.
+    #[error("the environment is not locked for {0}")]
+    PlatformNotLocked(rattler_conda_types::Platform),
+
+    /// A locked package does not specify the hash of its archive.
+    #[error("the locked package {0} does not specify a sha256 hash")]
+    MissingLockedHash(String),
+
+    /// The operation was cancelled.
+    #[error("the operation was cancelled")]
+    Cancelled,
+}
+
+impl From<JoinError> for InstallerError {
+    fn from(err: JoinError) -> Self {
+        if let Ok(panic) = err.try_into_panic() {
+            std::panic::resume_unwind(panic)
+        } else {
+            InstallerError::Cancelled
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::sync::Mutex;
+
+use rattler_conda_types::{PackageName, PrefixRecord, RepoDataRecord};
+use tokio::sync::mpsc;
+
+use super::Reporter;
+use crate::install::{Transaction, TransactionOperation};
+
+/// The phase that an operation of an installation is in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum InstallPhase {
+    /// The previously installed package is removed from the prefix.
+    Unlink,
+
+    /// The package cache is populated with the package. If the package is already cached it is
+    /// validated, otherwise it is downloaded and extracted.
+    Fetch,
+
+    /// The archive of the package is downloaded.
+    Download,
+
+    /// The downloaded archive is extracted into the package cache.
+    Extract,
+
+    /// The package is linked into the prefix.
+    Link,
+}
+
+/// An event that describes the progress of an [`super::Installer`].
+///
+/// Operations are identified by their index in [`Transaction::operations`]. Events of different
+/// operations are interleaved because operations are executed concurrently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallEvent {
+    /// The transaction has been computed and its operations are about to be executed.
+    TransactionStarted {
+        /// The number of operations of the transaction.
+        operations: usize,
+    },
+
+    /// The execution of an operation started.
+    PackageStarted {
+        /// The index of the operation.
+        operation: usize,
+        /// The name of the package that is installed or removed.
+        name: PackageName,
+    },
+
+    /// An operation entered a new phase.
+    PhaseChanged {
+        /// The index of the operation.
+        operation: usize,
+        /// The phase that was entered.
+        phase: InstallPhase,
+    },
+
+    /// More bytes of the archive of a package have been downloaded.
+    BytesProgressed {
+        /// The index of the operation.
+        operation: usize,
+        /// The number of bytes that have been downloaded so far.
+        downloaded: u64,
+        /// The total size of the archive, if known.
+        total: Option<u64>,
+    },
+
+    /// A problem occurred that does not fail the installation.
+    Warning {
+        /// The index of the operation the warning relates to, if any.
+        operation: Option<usize>,
+        /// A description of the problem.
+        message: String,
+    },
+
+    /// An operation has been executed.
+    PackageCompleted {
+        /// The index of the operation.
+        operation: usize,
+    },
+
+    /// All operations of the transaction have been executed.
+    TransactionCompleted,
+}
+
+/// A [`Reporter`] that converts the progress of an installation into [`InstallEvent`]s and passes
+/// them to a callback. This allows user interfaces to render the progress in their own way.
+///
+/// ```rust,no_run
+/// # use rattler::install::{installer::EventReporter, Installer};
+/// # async fn example(records: Vec<rattler_conda_types::RepoDataRecord>) {
+/// let (reporter, mut events) = EventReporter::channel();
+/// tokio::spawn(async move {
+///     while let Some(event) = events.recv().await {
+///         println!("{event:?}");
+///     }
+/// });
+/// Installer::new()
+///     .with_reporter(reporter)
+///     .install("/path/to/prefix", records)
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+pub struct EventReporter {
+    callback: Box<dyn Fn(InstallEvent) + Send + Sync>,
+    package_names: Mutex<Vec<PackageName>>,
+}
+
+impl EventReporter {
+    /// Constructs a reporter that invokes `callback` for every event. The callback is invoked
+    /// from the tasks that execute the installation so it should return quickly.
+    pub fn new(callback: impl Fn(InstallEvent) + Send + Sync + 'static) -> Self {
+        Self {
+            callback: Box::new(callback),
+            package_names: Mutex::default(),
+        }
+    }
+
+    /// Constructs a reporter that sends every event to `sender`. Events are dropped once the
+    /// receiver is closed.
+    pub fn from_sender(sender: mpsc::UnboundedSender<InstallEvent>) -> Self {
+        Self::new(move |event| {
+            let _ = sender.send(event);
+        })
+    }
+
+    /// Constructs a reporter together with the receiver of its events.
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<InstallEvent>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self::from_sender(sender), receiver)
+    }
+
+    fn emit(&self, event: InstallEvent) {
+        (self.callback)(event);
+    }
+
+    fn phase_changed(&self, operation: usize, phase: InstallPhase) -> usize {
+        self.emit(InstallEvent::PhaseChanged { operation, phase });
+        operation
+    }
+}
+
+impl Reporter for EventReporter {
+    fn on_transaction_start(&self, transaction: &Transaction<PrefixRecord, RepoDataRecord>) {
+        *self.package_names.lock().unwrap() = transaction
+            .operations
+            .iter()
+            .map(|operation| match operation {
+                TransactionOperation::Install(record)
+                | TransactionOperation::Change { new: record, .. } => {
+                    record.package_record.name.clone()
+                }
+                TransactionOperation::Reinstall(record) | TransactionOperation::Remove(record) => {
+                    record.repodata_record.package_record.name.clone()
+                }
+            })
+            .collect();
+        self.emit(InstallEvent::TransactionStarted {
+            operations: transaction.operations.len(),
+        });
+    }
+
+    fn on_transaction_operation_start(&self, operation: usize) {
+        let name = self.package_names.lock().unwrap().get(operation).cloned();
+        if let Some(name) = name {
+            self.emit(InstallEvent::PackageStarted { operation, name });
+        }
+    }
+
+    fn on_populate_cache_start(&self, operation: usize, _record: &RepoDataRecord) -> usize {
+        self.phase_changed(operation, InstallPhase::Fetch)
+    }
+
+    fn on_download_start(&self, cache_entry: usize) {
+        self.phase_changed(cache_entry, InstallPhase::Download);
+    }
+
+    fn on_download_progress(&self, cache_entry: usize, downloaded: u64, total: Option<u64>) {
+        self.emit(InstallEvent::BytesProgressed {
+            operation: cache_entry,
+            downloaded,
+            total,
+        });
+    }
+
+    fn on_download_complete(&self, cache_entry: usize) {
+        self.phase_changed(cache_entry, InstallPhase::Extract);
+    }
+
+    fn on_unlink_start(&self, operation: usize, _record: &PrefixRecord) -> usize {
+        self.phase_changed(operation, InstallPhase::Unlink)
+    }
+
+    fn on_link_start(&self, operation: usize, _record: &RepoDataRecord) -> usize {
+        self.phase_changed(operation, InstallPhase::Link)
+    }
+
+    fn on_transaction_operation_complete(&self, operation: usize) {
+        self.emit(InstallEvent::PackageCompleted { operation });
+    }
+
+    fn on_transaction_complete(&self) {
+        self.emit(InstallEvent::TransactionCompleted);
+    }
+
+    fn on_warning(&self, operation: Option<usize>, message: &str) {
+        self.emit(InstallEvent::Warning {
+            operation,
+            message: message.to_string(),
+        });
+    }
+}
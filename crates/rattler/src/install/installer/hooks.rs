@@ -0,0 +1,170 @@
+use std::path::Path;
+
+use rattler_conda_types::{PrefixRecord, RepoDataRecord};
+
+use crate::install::Transaction;
+
+/// The error returned by an [`InstallHook`].
+pub type HookError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// A trait that enables running custom code at defined phases of an installation performed by an
+/// [`super::Installer`], for instance to scan packages before they are linked or to record a
+/// software bill of materials of the prefix.
+///
+/// Unlike a [`super::Reporter`], a hook can fail the installation by returning an error. Errors
+/// returned by [`InstallHook::pre_link`], [`InstallHook::post_link`] and
+/// [`InstallHook::post_transaction`] roll back the transaction.
+///
+/// The methods are called from a thread on which blocking is allowed.
+pub trait InstallHook: Send + Sync {
+    /// Called with the records that are going to be installed, before the transaction is
+    /// computed and before the prefix is modified.
+    fn on_records(&self, _prefix: &Path, _records: &[RepoDataRecord]) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// Called before a package is linked into the prefix. `package_dir` is the directory in the
+    /// package cache that contains the extracted package.
+    fn pre_link(&self, _record: &RepoDataRecord, _package_dir: &Path) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// Called after a package has been linked into the prefix and its `conda-meta` record has been
+    /// written.
+    fn post_link(&self, _prefix: &Path, _record: &PrefixRecord) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// Called after all operations of the transaction have been executed and the post-link
+    /// scripts have run, right before the transaction is committed.
+    fn post_transaction(
+        &self,
+        _prefix: &Path,
+        _transaction: &Transaction<PrefixRecord, RepoDataRecord>,
+    ) -> Result<(), HookError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    use rattler_conda_types::{PrefixRecord, RepoDataRecord};
+
+    use super::{HookError, InstallHook};
+    use crate::{
+        install::{test_utils::build_package, Installer, InstallerError, Transaction},
+        package_cache::PackageCache,
+    };
+
+    /// A hook that records the phases it is called in and optionally fails in one of them.
+    #[derive(Default)]
+    struct RecordingHook {
+        calls: Arc<Mutex<Vec<String>>>,
+        fail_in: Option<&'static str>,
+    }
+
+    impl RecordingHook {
+        fn record(&self, call: String) -> Result<(), HookError> {
+            let phase = call.split(' ').next().unwrap().to_string();
+            self.calls.lock().unwrap().push(call);
+            if self.fail_in == Some(phase.as_str()) {
+                Err(format!("failed in {phase}").into())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl InstallHook for RecordingHook {
+        fn on_records(&self, _prefix: &Path, records: &[RepoDataRecord]) -> Result<(), HookError> {
+            self.record(format!("on_records {}", records.len()))
+        }
+
+        fn pre_link(&self, record: &RepoDataRecord, package_dir: &Path) -> Result<(), HookError> {
+            assert!(package_dir.join("info/index.json").is_file());
+            self.record(format!(
+                "pre_link {}",
+                record.package_record.name.as_normalized()
+            ))
+        }
+
+        fn post_link(&self, prefix: &Path, record: &PrefixRecord) -> Result<(), HookError> {
+            assert!(prefix.join("conda-meta").join(record.file_name()).is_file());
+            self.record(format!(
+                "post_link {}",
+                record.repodata_record.package_record.name.as_normalized()
+            ))
+        }
+
+        fn post_transaction(
+            &self,
+            _prefix: &Path,
+            transaction: &Transaction<PrefixRecord, RepoDataRecord>,
+        ) -> Result<(), HookError> {
+            self.record(format!("post_transaction {}", transaction.operations.len()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hooks_are_called_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("prefix");
+        let foo = build_package(dir.path(), "foo", "1.0", &[], &[("share/foo.txt", "foo")]);
+        let hook = RecordingHook::default();
+        let calls = hook.calls.clone();
+
+        Installer::new()
+            .with_package_cache(PackageCache::new(dir.path().join("pkgs")))
+            .with_hook(hook)
+            .install(&prefix, [foo])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "on_records 1",
+                "pre_link foo",
+                "post_link foo",
+                "post_transaction 1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failing_hook_rolls_back() {
+        for phase in ["on_records", "pre_link", "post_link", "post_transaction"] {
+            let dir = tempfile::tempdir().unwrap();
+            let prefix = dir.path().join("prefix");
+            let foo = build_package(dir.path(), "foo", "1.0", &[], &[("share/foo.txt", "foo")]);
+            let hook = RecordingHook {
+                fail_in: Some(phase),
+                ..RecordingHook::default()
+            };
+            let calls = hook.calls.clone();
+
+            let result = Installer::new()
+                .with_package_cache(PackageCache::new(dir.path().join("pkgs")))
+                .with_hook(hook)
+                .install(&prefix, [foo])
+                .await;
+            let Err(err @ InstallerError::HookFailed(..)) = result else {
+                panic!("expected the {phase} hook to fail the installation");
+            };
+            assert!(err.to_string().starts_with("the install hook '"));
+
+            // No phase after the failing one is called and the prefix is left untouched.
+            let calls = calls.lock().unwrap();
+            assert!(calls.last().unwrap().starts_with(phase));
+            assert!(!prefix.join("share/foo.txt").exists());
+            assert!(PrefixRecord::collect_from_prefix(&prefix)
+                .unwrap_or_default()
+                .is_empty());
+        }
+    }
+}
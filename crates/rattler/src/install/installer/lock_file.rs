@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use itertools::Itertools;
+use rattler_conda_types::{PackageName, Platform, PrefixRecord, RepoDataRecord};
+
+use super::{
+    detect_installed_packages, record_name, InstallationResult, Installer, InstallerError,
+};
+
+impl Installer {
+    /// Installs the conda packages of a locked environment for the given `platform` into the
+    /// `prefix`. Packages that are currently installed but that are not part of the environment
+    /// are removed.
+    ///
+    /// The solver is skipped entirely: the locked records are installed as they are and only the
+    /// packages that are not already in the package cache are downloaded. Every locked package
+    /// must specify a sha256 hash, the hash of every downloaded archive is verified against it.
+    ///
+    /// Installed packages are checked for consistency with the lock file. A package is reinstalled
+    /// if the installed package was created from a different archive or if any of its files are
+    /// missing from the prefix.
+    ///
+    /// Only conda packages are installed. A warning is emitted for the pypi packages of the
+    /// environment, they have to be installed by a python package installer.
+    pub async fn install_locked(
+        self,
+        prefix: impl AsRef<Path>,
+        environment: &rattler_lock::Environment,
+        platform: Platform,
+    ) -> Result<InstallationResult, InstallerError> {
+        let prefix = prefix.as_ref();
+        let records = environment
+            .conda_repodata_records_for_platform(platform)
+            .map_err(InstallerError::InvalidLockedEnvironment)?
+            .ok_or(InstallerError::PlatformNotLocked(platform))?;
+        let pypi_packages = environment
+            .pypi_packages_for_platform(platform)
+            .unwrap_or_default();
+        if !pypi_packages.is_empty() {
+            tracing::warn!(
+                "the locked environment contains pypi packages that are not installed: {}",
+                pypi_packages
+                    .iter()
+                    .map(|(package, _)| format!("{}=={}", package.name, package.version))
+                    .format(", ")
+            );
+        }
+        if let Some(record) = records
+            .iter()
+            .find(|record| record.package_record.sha256.is_none())
+        {
+            return Err(InstallerError::MissingLockedHash(record_name(record)));
+        }
+
+        let installed = detect_installed_packages(prefix, self.installed.clone()).await?;
+        let (installed, records, inconsistent) = tokio::task::spawn_blocking({
+            let prefix = prefix.to_path_buf();
+            move || {
+                let inconsistent = inconsistent_packages(&prefix, &installed, &records);
+                (installed, records, inconsistent)
+            }
+        })
+        .await?;
+
+        let mut reinstall_packages = self.reinstall_packages.clone();
+        reinstall_packages.extend(inconsistent);
+        self.with_installed_packages(installed)
+            .with_target_platform(platform)
+            .with_reinstall_packages(reinstall_packages)
+            .install(prefix, records)
+            .await
+    }
+}
+
+/// Returns the names of the installed packages that are part of the `locked` packages but that do
+/// not match them. This is the case if the installed package was created from an archive with a
+/// different hash or if files of the installed package are missing from the prefix.
+fn inconsistent_packages(
+    prefix: &Path,
+    installed: &[PrefixRecord],
+    locked: &[RepoDataRecord],
+) -> HashSet<PackageName> {
+    installed
+        .iter()
+        .filter_map(|installed| {
+            let installed_record = &installed.repodata_record.package_record;
+            let locked = locked
+                .iter()
+                .find(|locked| locked.package_record.name == installed_record.name)?;
+
+            if installed_record.sha256.is_none()
+                || installed_record.sha256 != locked.package_record.sha256
+            {
+                tracing::info!(
+                    "{} does not match the locked package, it will be reinstalled",
+                    installed.repodata_record.file_name
+                );
+                return Some(installed_record.name.clone());
+            }
+
+            let missing_file = installed
+                .files
+                .iter()
+                .find(|file| prefix.join(file).symlink_metadata().is_err());
+            if let Some(missing_file) = missing_file {
+                tracing::warn!(
+                    "{} is missing from the prefix, {} will be reinstalled",
+                    missing_file.display(),
+                    installed.repodata_record.file_name
+                );
+                return Some(installed_record.name.clone());
+            }
+
+            None
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::inconsistent_packages;
+    use crate::install::{installer::compute_transaction, Installer, TransactionOperation};
+    use crate::package_cache::PackageCache;
+    use rattler_conda_types::Platform;
+    use rattler_conda_types::{PackageRecord, PrefixRecord, RepoDataRecord, Version};
+    use rattler_digest::{parse_digest_from_hex, Sha256};
+    use rattler_lock::{
+        LockFile, PypiPackageData, PypiPackageEnvironmentData, UrlOrPath, DEFAULT_ENVIRONMENT_NAME,
+    };
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn repodata_record(name: &str, sha256: &str) -> RepoDataRecord {
+        let mut package_record = PackageRecord::new(
+            name.parse().unwrap(),
+            Version::from_str("1.0").unwrap(),
+            "0".to_string(),
+        );
+        package_record.sha256 = parse_digest_from_hex::<Sha256>(&sha256.repeat(64));
+        RepoDataRecord {
+            file_name: format!("{name}-1.0-0.conda"),
+            url: format!("https://conda.anaconda.org/conda-forge/noarch/{name}-1.0-0.conda")
+                .parse()
+                .unwrap(),
+            channel: "https://conda.anaconda.org/conda-forge/".to_string(),
+            package_record,
+        }
+    }
+
+    fn prefix_record(name: &str, sha256: &str, files: &[&str]) -> PrefixRecord {
+        let mut record = PrefixRecord::from_repodata_record(
+            repodata_record(name, sha256),
+            None,
+            None,
+            Vec::new(),
+            None,
+            None,
+        );
+        record.files = files.iter().map(PathBuf::from).collect();
+        record
+    }
+
+    #[test]
+    fn test_inconsistent_packages() {
+        let prefix = tempfile::tempdir().unwrap();
+        std::fs::write(prefix.path().join("foo.txt"), "").unwrap();
+
+        let installed = [
+            prefix_record("consistent", "a", &["foo.txt"]),
+            prefix_record("modified", "a", &["foo.txt"]),
+            prefix_record("missing-file", "a", &["foo.txt", "bar.txt"]),
+            prefix_record("not-locked", "a", &["bar.txt"]),
+        ];
+        let locked = [
+            repodata_record("consistent", "a"),
+            repodata_record("modified", "b"),
+            repodata_record("missing-file", "a"),
+            repodata_record("new", "a"),
+        ];
+
+        let mut inconsistent = inconsistent_packages(prefix.path(), &installed, &locked)
+            .into_iter()
+            .map(|name| name.as_normalized().to_string())
+            .collect::<Vec<_>>();
+        inconsistent.sort();
+        assert_eq!(inconsistent, ["missing-file", "modified"]);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_pypi_packages_are_reported() {
+        let lock_file = LockFile::builder()
+            .with_pypi_package(
+                DEFAULT_ENVIRONMENT_NAME,
+                Platform::Linux64,
+                PypiPackageData {
+                    name: "requests".parse().unwrap(),
+                    version: "2.31.0".parse().unwrap(),
+                    url_or_path: UrlOrPath::Url(
+                        "https://files.pythonhosted.org/requests-2.31.0-py3-none-any.whl"
+                            .parse()
+                            .unwrap(),
+                    ),
+                    hash: None,
+                    requires_dist: Vec::new(),
+                    requires_python: None,
+                    editable: false,
+                },
+                PypiPackageEnvironmentData::default(),
+            )
+            .finish();
+
+        let prefix = tempfile::tempdir().unwrap();
+        let result = Installer::new()
+            .with_package_cache(PackageCache::new(prefix.path().join("pkgs")))
+            .install_locked(
+                prefix.path().join("env"),
+                &lock_file.default_environment().unwrap(),
+                Platform::Linux64,
+            )
+            .await
+            .unwrap();
+        assert!(result.transaction.operations.is_empty());
+        assert!(logs_contain("requests==2.31.0"));
+    }
+
+    #[test]
+    fn test_reinstall_packages() {
+        let installed = [
+            prefix_record("foo", "a", &[]),
+            prefix_record("bar", "a", &[]),
+        ];
+        let locked = vec![repodata_record("foo", "a"), repodata_record("bar", "a")];
+
+        let transaction = compute_transaction(
+            &installed,
+            locked,
+            &HashSet::from(["foo".parse().unwrap()]),
+            Platform::Linux64,
+        )
+        .unwrap();
+        assert_eq!(transaction.operations.len(), 1);
+        assert!(matches!(
+            &transaction.operations[0],
+            TransactionOperation::Change { new, .. } if new.package_record.name.as_normalized() == "foo"
+        ));
+    }
+}
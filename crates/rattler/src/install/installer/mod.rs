@@ -0,0 +1,1687 @@
+//! A high-level interface to install packages into a prefix. See [`Installer`].
+
+mod dry_run;
+mod error;
+mod events;
+mod hooks;
+#[cfg(feature = "lock-file")]
+mod lock_file;
+mod package_reference;
+mod reporter;
+
+use std::collections::{HashMap, HashSet};
+use std::future::ready;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use dry_run::{
+    DiskUsage, DryRunReport, PackageSummary, PlannedClobber, PlannedDownload, PlannedOperation,
+    PlannedOperationKind, PlannedScript,
+};
+pub use error::InstallerError;
+pub use events::{EventReporter, InstallEvent, InstallPhase};
+use futures::{stream, FutureExt, StreamExt, TryFutureExt};
+pub use hooks::{HookError, InstallHook};
+pub use package_reference::{repodata_record_from_path, PackageReference};
+use rattler_conda_types::package::{IndexJson, PackageFile, PathsJson};
+use rattler_conda_types::prefix_record::{Link, PathType, PathsEntry};
+use rattler_conda_types::{
+    MatchSpec, PackageName, ParseStrictness, Platform, PrefixRecord, RepoDataRecord,
+};
+use rattler_networking::retry_policies::{retry_policy_from_config, ExponentialBackoff};
+pub use reporter::Reporter;
+use tokio::sync::Semaphore;
+
+use super::history::{History, Revision};
+use super::link_script::{run_pre_link_script, LinkScriptPolicy, PrePostLinkResult};
+use super::menuinst::{default_shortcut_directory, install_menu_items};
+use super::prefix_lock::PrefixLock;
+use super::pyc::{compile_pyc, pyc_path, PycCompilation};
+use super::trash::purge_trash;
+use super::{
+    link_package_with_link_type, ClobberPolicy, InstallDriver, InstallError, InstallOptions,
+    PythonInfo, Transaction, TransactionJournal, TransactionOperation,
+};
+use crate::default_cache_dir;
+use crate::download::{DownloadEvent, DownloadManager, DownloadRequest};
+use crate::package_cache::PackageCache;
+
+/// The default maximum number of packages that are downloaded and extracted concurrently.
+const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 50;
+
+/// The default maximum number of concurrent IO operations while linking files.
+const DEFAULT_IO_CONCURRENCY_LIMIT: usize = 100;
+
+/// An installer executes all the steps required to bring a prefix into a desired state: it
+/// computes the [`Transaction`], downloads and extracts the required packages into the package
+/// cache, removes packages that are no longer required, links the new packages into the prefix and
+/// writes their `conda-meta` records.
+///
+/// All modifications of the prefix are recorded in a [`TransactionJournal`]. If any step of the
+/// transaction fails, including a failing post-link script, the prefix is restored to its previous
+/// state. A transaction that was interrupted is rolled back before the next installation starts.
+///
+/// ```rust,no_run
+/// # use rattler::install::Installer;
+/// # async fn example(records: Vec<rattler_conda_types::RepoDataRecord>) {
+/// let result = Installer::new()
+///     .install("/path/to/prefix", records)
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Default)]
+pub struct Installer {
+    installed: Option<Vec<PrefixRecord>>,
+    package_cache: Option<PackageCache>,
+    download_client: Option<reqwest_middleware::ClientWithMiddleware>,
+    download_manager: Option<DownloadManager>,
+    retry_policy: Option<ExponentialBackoff>,
+    install_options: InstallOptions,
+    link_script_policy: LinkScriptPolicy,
+    link_script_timeout: Option<Duration>,
+    clobber_policy: ClobberPolicy,
+    pyc_compilation: Option<PycCompilation>,
+    install_menus: bool,
+    io_semaphore: Option<Arc<Semaphore>>,
+    io_concurrency_limit: Option<usize>,
+    max_concurrent_downloads: Option<usize>,
+    max_concurrent_links: Option<usize>,
+    reporter: Option<Arc<dyn Reporter>>,
+    hooks: Vec<Arc<dyn InstallHook>>,
+    target_platform: Option<Platform>,
+    reinstall_packages: HashSet<PackageName>,
+    prefix_lock_timeout: Option<Duration>,
+}
+
+/// The result of a successful [`Installer::install`] call.
+pub struct InstallationResult {
+    /// The transaction that was executed.
+    pub transaction: Transaction<PrefixRecord, RepoDataRecord>,
+
+    /// The result of running the pre-link scripts, `None` if scripts were not executed.
+    pub pre_link_script_result: Option<PrePostLinkResult>,
+
+    /// The result of running the post-link scripts, `None` if scripts were not executed.
+    pub post_link_script_result: Option<PrePostLinkResult>,
+}
+
+impl Installer {
+    /// Constructs a new installer with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the packages that are currently installed in the prefix. If this is not set the
+    /// installed packages are read from the `conda-meta` directory of the prefix.
+    #[must_use]
+    pub fn with_installed_packages(self, installed: Vec<PrefixRecord>) -> Self {
+        Self {
+            installed: Some(installed),
+            ..self
+        }
+    }
+
+    /// Sets the package cache to use. If this is not set a package cache in the
+    /// [`default_cache_dir`] is used.
+    #[must_use]
+    pub fn with_package_cache(self, package_cache: PackageCache) -> Self {
+        Self {
+            package_cache: Some(package_cache),
+            ..self
+        }
+    }
+
+    /// Sets the client that is used to download packages.
+    #[must_use]
+    pub fn with_download_client(self, client: reqwest_middleware::ClientWithMiddleware) -> Self {
+        Self {
+            download_client: Some(client),
+            ..self
+        }
+    }
+
+    /// Sets the [`DownloadManager`] that downloads the packages that are not in the package cache
+    /// yet. Sharing a manager between installers enforces its concurrency limits across all of
+    /// them. If this is not set a manager is constructed from the download client and the maximum
+    /// number of concurrent downloads.
+    #[must_use]
+    pub fn with_download_manager(self, download_manager: DownloadManager) -> Self {
+        Self {
+            download_manager: Some(download_manager),
+            ..self
+        }
+    }
+
+    /// Applies the shared configuration: packages are cached in the `pkgs` directory of the
+    /// configured cache directory, installed for the configured platform and failed downloads
+    /// are retried as configured. The retries do not apply to a manager that is set with
+    /// [`Installer::with_download_manager`].
+    #[must_use]
+    pub fn with_config(self, config: &rattler_config::Config) -> Self {
+        Self {
+            package_cache: PackageCache::from_config(config).or(self.package_cache),
+            target_platform: config.platform.or(self.target_platform),
+            retry_policy: Some(retry_policy_from_config(config)),
+            ..self
+        }
+    }
+
+    /// Sets the options that are passed to [`link_package`](crate::install::link_package) for
+    /// every package. The `python_info` and `platform` fields are always derived from the
+    /// transaction.
+    #[must_use]
+    pub fn with_install_options(self, install_options: InstallOptions) -> Self {
+        Self {
+            install_options,
+            ..self
+        }
+    }
+
+    /// Sets whether to execute the pre-link, pre-unlink and post-link scripts of all packages.
+    /// See [`Installer::with_link_script_policy`] to only allow the scripts of specific packages.
+    #[must_use]
+    pub fn with_execute_link_scripts(self, execute_link_scripts: bool) -> Self {
+        self.with_link_script_policy(if execute_link_scripts {
+            LinkScriptPolicy::AllowAll
+        } else {
+            LinkScriptPolicy::Deny
+        })
+    }
+
+    /// Sets the policy that determines the packages whose link scripts are executed. Link scripts
+    /// are not executed by default.
+    ///
+    /// A failing pre-link or post-link script fails the installation and rolls back the
+    /// transaction.
+    #[must_use]
+    pub fn with_link_script_policy(self, link_script_policy: LinkScriptPolicy) -> Self {
+        Self {
+            link_script_policy,
+            ..self
+        }
+    }
+
+    /// Sets the maximum amount of time a single link script is allowed to run. Scripts that take
+    /// longer are killed and considered failed. By default scripts are never killed.
+    #[must_use]
+    pub fn with_link_script_timeout(self, link_script_timeout: Option<Duration>) -> Self {
+        Self {
+            link_script_timeout,
+            ..self
+        }
+    }
+
+    /// Sets the policy that determines which package wins when multiple packages install the same
+    /// path.
+    #[must_use]
+    pub fn with_clobber_policy(self, clobber_policy: ClobberPolicy) -> Self {
+        Self {
+            clobber_policy,
+            ..self
+        }
+    }
+
+    /// Sets whether and how the python source files of `noarch: python` packages are compiled to
+    /// bytecode after they have been linked. Compilation is disabled by default.
+    ///
+    /// The bytecode files are recorded in the `conda-meta` records of the packages so they are
+    /// removed when the package is uninstalled.
+    #[must_use]
+    pub fn with_pyc_compilation(self, pyc_compilation: Option<PycCompilation>) -> Self {
+        Self {
+            pyc_compilation,
+            ..self
+        }
+    }
+
+    /// Sets whether to create the menu shortcuts described by the `Menu/*.json` files of packages.
+    /// Shortcuts are created in the [`default_shortcut_directory`] of the current user and are
+    /// removed when the package is uninstalled. Disabled by default.
+    #[must_use]
+    pub fn with_install_menus(self, install_menus: bool) -> Self {
+        Self {
+            install_menus,
+            ..self
+        }
+    }
+
+    /// Sets a semaphore that limits the number of concurrent IO operations.
+    #[must_use]
+    pub fn with_io_concurrency_semaphore(self, io_semaphore: Arc<Semaphore>) -> Self {
+        Self {
+            io_semaphore: Some(io_semaphore),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of concurrent IO operations while linking files. Ignored if a
+    /// semaphore is set with [`Installer::with_io_concurrency_semaphore`]. Defaults to 100.
+    #[must_use]
+    pub fn with_io_concurrency_limit(self, limit: usize) -> Self {
+        Self {
+            io_concurrency_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of packages that are downloaded and extracted into the package
+    /// cache concurrently. Defaults to 50.
+    #[must_use]
+    pub fn with_max_concurrent_downloads(self, limit: usize) -> Self {
+        Self {
+            max_concurrent_downloads: Some(limit),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of packages that are linked into the prefix concurrently. The
+    /// files of a package are linked concurrently as well, bounded by the IO concurrency limit.
+    /// Defaults to the number of available CPUs.
+    #[must_use]
+    pub fn with_max_concurrent_links(self, limit: usize) -> Self {
+        Self {
+            max_concurrent_links: Some(limit),
+            ..self
+        }
+    }
+
+    /// Sets how long to wait for other processes that are modifying the prefix. The prefix is
+    /// locked for the duration of the installation, see [`PrefixLock`]. By default this waits
+    /// indefinitely.
+    #[must_use]
+    pub fn with_prefix_lock_timeout(self, timeout: Duration) -> Self {
+        Self {
+            prefix_lock_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Sets a reporter that is notified of the progress of the installation.
+    #[must_use]
+    pub fn with_reporter<R: Reporter + 'static>(self, reporter: R) -> Self {
+        Self {
+            reporter: Some(Arc::new(reporter)),
+            ..self
+        }
+    }
+
+    /// Adds a hook that is invoked at defined phases of the installation. Hooks are invoked in
+    /// the order in which they were added. See [`InstallHook`].
+    #[must_use]
+    pub fn with_hook<H: InstallHook + 'static>(mut self, hook: H) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Sets the platform for which the packages are installed. Defaults to the current platform.
+    #[must_use]
+    pub fn with_target_platform(self, target_platform: Platform) -> Self {
+        Self {
+            target_platform: Some(target_platform),
+            ..self
+        }
+    }
+
+    /// Sets the packages that are reinstalled even if the installed package is identical to the
+    /// requested one. This can be used to repair packages that were modified after they were
+    /// installed.
+    #[must_use]
+    pub fn with_reinstall_packages(self, reinstall_packages: HashSet<PackageName>) -> Self {
+        Self {
+            reinstall_packages,
+            ..self
+        }
+    }
+
+    /// Computes everything [`Installer::install`] would do for the same arguments without
+    /// modifying the prefix: the operations of the transaction, the packages that have to be
+    /// downloaded, the paths that would be clobbered, the link scripts that would run and the
+    /// change in disk usage. The returned [`DryRunReport`] can be serialized.
+    ///
+    /// Packages that are referenced by URL are downloaded into the package cache to read their
+    /// metadata.
+    pub async fn dry_run(
+        self,
+        prefix: impl AsRef<Path>,
+        records: impl IntoIterator<Item = impl Into<PackageReference>>,
+    ) -> Result<DryRunReport, InstallerError> {
+        let prefix = prefix.as_ref();
+        let target_platform = self.target_platform.unwrap_or_else(Platform::current);
+        let download_client = self.download_client.unwrap_or_else(|| {
+            reqwest_middleware::ClientWithMiddleware::from(reqwest::Client::default())
+        });
+        let package_cache = resolve_package_cache(self.package_cache)?;
+        let installed = detect_installed_packages(prefix, self.installed).await?;
+        let records = futures::future::try_join_all(records.into_iter().map(|reference| {
+            reference
+                .into()
+                .into_record(&package_cache, &download_client)
+        }))
+        .await?;
+        let transaction = compute_transaction(
+            &installed,
+            records,
+            &self.reinstall_packages,
+            target_platform,
+        )?;
+
+        let prefix = prefix.to_path_buf();
+        let link_script_policy = self.link_script_policy;
+        Ok(tokio::task::spawn_blocking(move || {
+            dry_run::plan_transaction(
+                &prefix,
+                &installed,
+                &transaction,
+                &package_cache,
+                &link_script_policy,
+            )
+        })
+        .await?)
+    }
+
+    /// Rolls the `prefix` back to the given `revision` of its [`History`] by installing the
+    /// packages that were installed after that revision. Like conda, the rollback itself is
+    /// recorded as a new revision.
+    ///
+    /// Packages that are still installed are kept. Packages that have been removed since are
+    /// reinstalled from the package cache or downloaded again, their records are looked up in
+    /// `available`, for instance the records of the channels the prefix was created from. See
+    /// [`History::records_for_revision`].
+    pub async fn rollback(
+        self,
+        prefix: impl AsRef<Path>,
+        revision: usize,
+        available: impl IntoIterator<Item = RepoDataRecord>,
+    ) -> Result<InstallationResult, InstallerError> {
+        let prefix = prefix.as_ref();
+        let history =
+            History::from_prefix(prefix).map_err(InstallerError::FailedToDetermineRevision)?;
+        let installed = detect_installed_packages(prefix, self.installed.clone()).await?;
+        let records = history
+            .records_for_revision(revision, &installed, available)
+            .map_err(InstallerError::FailedToDetermineRevision)?;
+        self.with_installed_packages(installed)
+            .install(prefix, records)
+            .await
+    }
+
+    /// Installs the given `records` into the `prefix`. Packages that are currently installed but
+    /// are not part of `records` are removed.
+    ///
+    /// Besides solved [`RepoDataRecord`]s, local package archives and URLs of package archives can
+    /// be installed as well, see [`PackageReference`]. Because every [`RepoDataRecord`] converts
+    /// into a [`PackageReference`], a collection of records can still be passed directly. An empty
+    /// collection needs a type annotation, for instance `Vec::<RepoDataRecord>::new()`.
+    pub async fn install(
+        self,
+        prefix: impl AsRef<Path>,
+        records: impl IntoIterator<Item = impl Into<PackageReference>>,
+    ) -> Result<InstallationResult, InstallerError> {
+        let prefix = prefix.as_ref();
+        let target_platform = self.target_platform.unwrap_or_else(Platform::current);
+        let download_client = self.download_client.unwrap_or_else(|| {
+            reqwest_middleware::ClientWithMiddleware::from(reqwest::Client::default())
+        });
+        let package_cache = resolve_package_cache(self.package_cache)?;
+
+        // Make sure no other process modifies the prefix until the installation completes.
+        let _prefix_lock = PrefixLock::acquire_with_timeout(prefix, self.prefix_lock_timeout)
+            .await
+            .map_err(InstallerError::FailedToLockPrefix)?;
+
+        // Restore the prefix if a previous transaction was interrupted.
+        let interrupted_prefix = prefix.to_path_buf();
+        if tokio::task::spawn_blocking(move || {
+            TransactionJournal::rollback_interrupted(&interrupted_prefix)
+        })
+        .await?
+        .map_err(InstallerError::TransactionJournalError)?
+        {
+            warn(
+                self.reporter.as_deref(),
+                None,
+                format!(
+                    "rolled back an interrupted transaction in {}",
+                    prefix.display()
+                ),
+            );
+        }
+
+        // Delete the files that were in use during a previous transaction.
+        let trash_prefix = prefix.to_path_buf();
+        match tokio::task::spawn_blocking(move || purge_trash(&trash_prefix)).await? {
+            Ok(0) => {}
+            Ok(remaining) => tracing::debug!("{remaining} files in the trash are still in use"),
+            Err(e) => warn(
+                self.reporter.as_deref(),
+                None,
+                format!("failed to purge the trash of {}: {e}", prefix.display()),
+            ),
+        }
+
+        // Determine the currently installed packages.
+        let installed = detect_installed_packages(prefix, self.installed).await?;
+
+        // Construct the transaction and the driver that executes it.
+        let records = futures::future::try_join_all(records.into_iter().map(|reference| {
+            reference
+                .into()
+                .into_record(&package_cache, &download_client)
+        }))
+        .await?;
+        let records = run_hooks(&self.hooks, records, {
+            let prefix = prefix.to_path_buf();
+            move |hook, records| {
+                hook.on_records(&prefix, records).map_err(|e| {
+                    InstallerError::HookFailed("before computing the transaction".to_string(), e)
+                })
+            }
+        })
+        .await?;
+        let transaction = Arc::new(compute_transaction(
+            &installed,
+            records,
+            &self.reinstall_packages,
+            target_platform,
+        )?);
+
+        // Make sure there is enough space to download all packages that are not cached yet.
+        let max_concurrent_downloads = self
+            .max_concurrent_downloads
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_DOWNLOADS);
+        let download_manager = self.download_manager.unwrap_or_else(|| {
+            let builder = DownloadManager::builder(download_client.clone())
+                .with_max_concurrent_downloads(max_concurrent_downloads);
+            match self.retry_policy {
+                Some(retry_policy) => builder.with_retry_policy(retry_policy),
+                None => builder,
+            }
+            .finish()
+        });
+        let downloads_dir = package_cache.downloads_dir();
+        let download_requests = transaction
+            .installed_packages()
+            .filter(|record| record.url.scheme() != "file")
+            .filter(|record| {
+                package_cache
+                    .cached_package_dir(&record.package_record)
+                    .is_none()
+            })
+            .map(|record| DownloadRequest::for_record(record, &downloads_dir))
+            .collect::<Vec<_>>();
+        download_manager
+            .check_disk_space(&download_requests)
+            .await
+            .map_err(|e| InstallerError::InsufficientDiskSpace(Box::new(e)))?;
+
+        let mut driver = InstallDriver::builder()
+            .with_prefix_records(&installed)
+            .with_clobber_policy(self.clobber_policy)
+            .with_link_script_policy(self.link_script_policy)
+            .with_link_script_timeout(self.link_script_timeout);
+        driver = match self.io_semaphore {
+            Some(semaphore) => driver.with_io_concurrency_semaphore(semaphore),
+            None => driver.with_io_concurrency_limit(
+                self.io_concurrency_limit
+                    .unwrap_or(DEFAULT_IO_CONCURRENCY_LIMIT),
+            ),
+        };
+        let driver = driver.finish();
+
+        if let Some(reporter) = &self.reporter {
+            reporter.on_transaction_start(&transaction);
+        }
+
+        let pre_link_script_result = driver
+            .pre_process(&transaction, prefix)
+            .map_err(InstallerError::PreProcessingFailed)?;
+
+        let install_options = InstallOptions {
+            python_info: transaction.python_info.clone(),
+            platform: Some(transaction.platform),
+            ..self.install_options
+        };
+
+        // Record all modifications in a journal so they can be undone if anything fails.
+        let journal = Arc::new(
+            TransactionJournal::begin(prefix).map_err(InstallerError::TransactionJournalError)?,
+        );
+
+        let reporter = self.reporter.as_deref();
+        let pipeline = Pipeline::new(
+            max_concurrent_downloads,
+            self.max_concurrent_links.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+            }),
+        );
+        let link_order = LinkOrder::new(
+            &transaction.operations,
+            !driver.link_script_options().policy.is_deny(),
+        );
+        let result = async {
+            // Execute all operations concurrently. Every operation passes through the stages of
+            // the pipeline so packages are downloaded while others are being linked. Operations
+            // are not cancelled when another operation fails to make sure nothing modifies the
+            // prefix during a rollback.
+            let linked_records = stream::iter(transaction.operations.iter().enumerate())
+                .map(|(index, operation)| {
+                    execute_operation(
+                        prefix,
+                        index,
+                        operation,
+                        &download_manager,
+                        &package_cache,
+                        &driver,
+                        &install_options,
+                        self.install_menus,
+                        &journal,
+                        &pipeline,
+                        &link_order,
+                        &self.hooks,
+                        reporter,
+                    )
+                })
+                .buffer_unordered(pipeline.max_concurrent_operations())
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if let (Some(pyc_compilation), Some(python_info)) =
+                (&self.pyc_compilation, &transaction.python_info)
+            {
+                compile_noarch_python_packages(
+                    prefix,
+                    linked_records.into_iter().flatten(),
+                    python_info,
+                    pyc_compilation,
+                    &journal,
+                    reporter,
+                )
+                .await?;
+            }
+
+            let post_link_script_result = driver
+                .post_process(&transaction, prefix)
+                .map_err(InstallerError::PostProcessingFailed)?;
+            if let Some(result) = &post_link_script_result {
+                if !result.failed_packages.is_empty() {
+                    return Err(InstallerError::PostLinkScriptsFailed(
+                        result
+                            .failed_packages
+                            .iter()
+                            .map(|name| name.as_source().to_string())
+                            .collect(),
+                    ));
+                }
+            }
+
+            if !self.hooks.is_empty() {
+                run_hooks(&self.hooks, Arc::clone(&transaction), {
+                    let prefix = prefix.to_path_buf();
+                    move |hook, transaction| {
+                        hook.post_transaction(&prefix, transaction).map_err(|e| {
+                            InstallerError::HookFailed("after the transaction".to_string(), e)
+                        })
+                    }
+                })
+                .await?;
+            }
+
+            Ok(post_link_script_result)
+        }
+        .await;
+
+        let journal = Arc::into_inner(journal).expect("all operations have completed");
+        let post_link_script_result = match result {
+            Ok(post_link_script_result) => {
+                journal
+                    .commit()
+                    .map_err(InstallerError::TransactionJournalError)?;
+                post_link_script_result
+            }
+            Err(err) => {
+                if let Err(rollback_err) = journal.rollback() {
+                    tracing::error!("failed to roll back the transaction: {rollback_err}");
+                }
+                return Err(err);
+            }
+        };
+
+        // Record the transaction in the history of the prefix so it can be rolled back later.
+        let revision = Revision::from_transaction(
+            &transaction,
+            Some(std::env::args().collect::<Vec<_>>().join(" ")),
+        );
+        if !revision.is_empty() {
+            if let Err(e) = History::append(prefix, &revision) {
+                warn(
+                    reporter,
+                    None,
+                    format!("failed to update the history of {}: {e}", prefix.display()),
+                );
+            }
+        }
+
+        // Remember that the packages in the cache are used by this prefix so they are not garbage
+        // collected.
+        if let Err(e) = package_cache.register_prefix(prefix) {
+            warn(
+                reporter,
+                None,
+                format!("failed to register the prefix with the package cache: {e}"),
+            );
+        }
+
+        if let Some(reporter) = reporter {
+            reporter.on_transaction_complete();
+        }
+
+        Ok(InstallationResult {
+            transaction: Arc::into_inner(transaction).expect("all hooks have completed"),
+            pre_link_script_result,
+            post_link_script_result,
+        })
+    }
+}
+
+/// Returns the given package cache or the package cache in the [`default_cache_dir`].
+fn resolve_package_cache(
+    package_cache: Option<PackageCache>,
+) -> Result<PackageCache, InstallerError> {
+    if let Some(package_cache) = package_cache {
+        return Ok(package_cache);
+    }
+    Ok(PackageCache::new(
+        default_cache_dir()
+            .map_err(|e| InstallerError::NoDefaultPackageCache(e.to_string()))?
+            .join("pkgs"),
+    ))
+}
+
+/// Returns the given installed packages or reads them from the `conda-meta` directory of the
+/// prefix.
+async fn detect_installed_packages(
+    prefix: &Path,
+    installed: Option<Vec<PrefixRecord>>,
+) -> Result<Vec<PrefixRecord>, InstallerError> {
+    if let Some(installed) = installed {
+        return Ok(installed);
+    }
+    let prefix = prefix.to_path_buf();
+    tokio::task::spawn_blocking(move || PrefixRecord::collect_from_prefix(&prefix))
+        .await?
+        .map_err(InstallerError::FailedToDetectInstalledPackages)
+}
+
+/// Computes the transaction that turns the `installed` packages into the desired `records`. The
+/// packages in `reinstall_packages` are reinstalled even if they are not modified otherwise.
+fn compute_transaction(
+    installed: &[PrefixRecord],
+    records: Vec<RepoDataRecord>,
+    reinstall_packages: &HashSet<PackageName>,
+    target_platform: Platform,
+) -> Result<Transaction<PrefixRecord, RepoDataRecord>, InstallerError> {
+    let reinstall = records
+        .iter()
+        .filter(|record| reinstall_packages.contains(&record.package_record.name))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut transaction =
+        Transaction::from_current_and_desired(installed.to_vec(), records, target_platform)?;
+
+    for record in reinstall {
+        let name = &record.package_record.name;
+        let is_modified = transaction
+            .operations
+            .iter()
+            .any(|operation| match operation {
+                TransactionOperation::Install(new) | TransactionOperation::Change { new, .. } => {
+                    &new.package_record.name == name
+                }
+                TransactionOperation::Reinstall(old) | TransactionOperation::Remove(old) => {
+                    &old.repodata_record.package_record.name == name
+                }
+            });
+        let old = installed
+            .iter()
+            .find(|old| &old.repodata_record.package_record.name == name);
+        if let (false, Some(old)) = (is_modified, old) {
+            transaction.operations.push(TransactionOperation::Change {
+                old: old.clone(),
+                new: record,
+            });
+        }
+    }
+    transaction.sort_operations();
+
+    Ok(transaction)
+}
+
+/// Invokes `f` for every hook on a thread on which blocking is allowed. Returns `value`, which is
+/// passed to every invocation, after all hooks succeeded.
+async fn run_hooks<T: Send + 'static>(
+    hooks: &[Arc<dyn InstallHook>],
+    value: T,
+    f: impl Fn(&dyn InstallHook, &T) -> Result<(), InstallerError> + Send + 'static,
+) -> Result<T, InstallerError> {
+    if hooks.is_empty() {
+        return Ok(value);
+    }
+    let hooks = hooks.to_vec();
+    tokio::task::spawn_blocking(move || {
+        for hook in &hooks {
+            f(hook.as_ref(), &value)?;
+        }
+        Ok(value)
+    })
+    .await?
+}
+
+/// Bounds the number of operations that are in each stage of the installation. The stages are
+/// independent, so while some packages are being linked the next packages are already downloaded
+/// and extracted. Within the link stage the files of a package are linked, and their prefix
+/// placeholders replaced, concurrently.
+///
+/// The order in which packages are linked does not matter for files that are installed by
+/// multiple packages: the losing files are renamed while linking and the winner is determined by
+/// the clobber policy after all packages are linked.
+struct Pipeline {
+    downloads: Semaphore,
+    links: Semaphore,
+    max_concurrent_operations: usize,
+}
+
+impl Pipeline {
+    fn new(max_concurrent_downloads: usize, max_concurrent_links: usize) -> Self {
+        let max_concurrent_downloads = max_concurrent_downloads.max(1);
+        let max_concurrent_links = max_concurrent_links.max(1);
+        Self {
+            downloads: Semaphore::new(max_concurrent_downloads),
+            links: Semaphore::new(max_concurrent_links),
+            max_concurrent_operations: max_concurrent_downloads + max_concurrent_links,
+        }
+    }
+
+    /// The number of operations that have to be in flight to keep all stages busy.
+    fn max_concurrent_operations(&self) -> usize {
+        self.max_concurrent_operations
+    }
+}
+
+/// Links packages after their dependencies when link scripts are executed. Pre-link scripts run
+/// right before a package is linked, so this runs them in topological order. Without link scripts
+/// packages are linked in any order.
+struct LinkOrder {
+    /// For every operation, the indices of the operations that install its dependencies.
+    dependencies: Vec<Vec<usize>>,
+    /// For every operation, a semaphore without permits that is closed when the operation is
+    /// finished.
+    finished: Vec<Semaphore>,
+}
+
+impl LinkOrder {
+    fn new(
+        operations: &[TransactionOperation<PrefixRecord, RepoDataRecord>],
+        link_scripts: bool,
+    ) -> Self {
+        let finished = operations.iter().map(|_| Semaphore::new(0)).collect();
+        if !link_scripts {
+            return Self {
+                dependencies: vec![Vec::new(); operations.len()],
+                finished,
+            };
+        }
+
+        // The operations of a transaction are sorted topologically, which also breaks cycles
+        // between dependencies: a package only waits for the dependencies that precede it.
+        let index = operations
+            .iter()
+            .enumerate()
+            .filter_map(|(index, operation)| {
+                let record = operation.record_to_install()?;
+                Some((&record.package_record.name, index))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let dependencies = operations
+            .iter()
+            .enumerate()
+            .map(|(own_index, operation)| {
+                let Some(record) = operation.record_to_install() else {
+                    return Vec::new();
+                };
+                record
+                    .package_record
+                    .depends
+                    .iter()
+                    .filter_map(|spec| {
+                        MatchSpec::from_str(spec, ParseStrictness::Lenient)
+                            .ok()?
+                            .name
+                    })
+                    .filter_map(|name| index.get(&name).copied())
+                    .filter(|index| *index < own_index)
+                    .collect()
+            })
+            .collect();
+        Self {
+            dependencies,
+            finished,
+        }
+    }
+
+    /// Waits until the operations that install the dependencies of the given operation are
+    /// finished.
+    async fn wait_for_dependencies(&self, index: usize) {
+        for &dependency in &self.dependencies[index] {
+            // Acquiring fails once the semaphore is closed, it never succeeds.
+            let _ = self.finished[dependency].acquire().await;
+        }
+    }
+
+    /// Returns a guard that marks the operation as finished when it is dropped, also when the
+    /// operation fails.
+    fn finish_on_drop(&self, index: usize) -> impl Drop + '_ {
+        struct Finish<'a>(&'a Semaphore);
+        impl Drop for Finish<'_> {
+            fn drop(&mut self) {
+                self.0.close();
+            }
+        }
+        Finish(&self.finished[index])
+    }
+}
+
+/// Executes a single operation of a transaction.
+#[allow(clippy::too_many_arguments)]
+async fn execute_operation(
+    prefix: &Path,
+    index: usize,
+    operation: &TransactionOperation<PrefixRecord, RepoDataRecord>,
+    download_manager: &DownloadManager,
+    package_cache: &PackageCache,
+    driver: &InstallDriver,
+    install_options: &InstallOptions,
+    install_menus: bool,
+    journal: &Arc<TransactionJournal>,
+    pipeline: &Pipeline,
+    link_order: &LinkOrder,
+    hooks: &[Arc<dyn InstallHook>],
+    reporter: Option<&dyn Reporter>,
+) -> Result<Option<PrefixRecord>, InstallerError> {
+    let _finished = link_order.finish_on_drop(index);
+    if let Some(reporter) = reporter {
+        reporter.on_transaction_operation_start(index);
+    }
+
+    // Remove the old package and populate the cache with the new package concurrently.
+    let remove_future = match operation.record_to_remove() {
+        Some(record) => async move {
+            let reporter_index = reporter.map(|r| r.on_unlink_start(index, record));
+
+            // Instead of deleting the files and menu items of the package they are moved to the
+            // backup directory of the journal.
+            let prefix_record = record.clone();
+            let backup_journal = journal.clone();
+            tokio::task::spawn_blocking(move || backup_journal.backup_package(&prefix_record))
+                .await?
+                .map_err(|e| InstallerError::UnlinkError(record_name(record.as_ref()), e))?;
+            if let (Some(reporter), Some(reporter_index)) = (reporter, reporter_index) {
+                reporter.on_unlink_complete(reporter_index);
+            }
+            Ok::<_, InstallerError>(())
+        }
+        .left_future(),
+        None => ready(Ok(())).right_future(),
+    };
+
+    let fetch_future = match operation.record_to_install() {
+        Some(record) => async move {
+            let _permit = pipeline
+                .downloads
+                .acquire()
+                .await
+                .map_err(|_err| InstallerError::Cancelled)?;
+            let reporter_index = reporter.map(|r| r.on_populate_cache_start(index, record));
+            let fetch = package_cache
+                .get_or_fetch_with_download_manager(record, download_manager)
+                .map_err(|e| InstallerError::FailedToFetch(record_name(record), e));
+            let package_dir = match (reporter, reporter_index) {
+                (Some(reporter), Some(reporter_index)) => {
+                    report_download_progress(
+                        download_manager,
+                        &record.url,
+                        reporter,
+                        reporter_index,
+                        fetch,
+                    )
+                    .await?
+                }
+                _ => fetch.await?,
+            };
+            if let (Some(reporter), Some(reporter_index)) = (reporter, reporter_index) {
+                reporter.on_populate_cache_complete(reporter_index);
+            }
+            Ok(Some((record, package_dir)))
+        }
+        .left_future(),
+        None => ready(Ok(None)).right_future(),
+    };
+
+    let ((), package) = tokio::try_join!(remove_future, fetch_future)?;
+
+    // Link the new package into the prefix.
+    let mut linked_record = None;
+    if let Some((record, package_dir)) = package {
+        link_order.wait_for_dependencies(index).await;
+        let _permit = pipeline
+            .links
+            .acquire()
+            .await
+            .map_err(|_err| InstallerError::Cancelled)?;
+        let reporter_index = reporter.map(|r| r.on_link_start(index, record));
+        linked_record = Some(
+            link_and_write_prefix_record(
+                prefix,
+                index,
+                record,
+                package_dir,
+                driver,
+                install_options,
+                install_menus,
+                journal,
+                hooks,
+                reporter,
+            )
+            .await?,
+        );
+        if let (Some(reporter), Some(reporter_index)) = (reporter, reporter_index) {
+            reporter.on_link_complete(reporter_index);
+        }
+    }
+
+    if let Some(reporter) = reporter {
+        reporter.on_transaction_operation_complete(index);
+    }
+
+    Ok(linked_record)
+}
+
+/// Runs the pre-link script of the package in `package_dir`. Fails if the script fails.
+async fn run_pre_link_script_of(
+    prefix: &Path,
+    record: &RepoDataRecord,
+    package_dir: &Path,
+    install_options: &InstallOptions,
+    driver: &InstallDriver,
+) -> Result<(), InstallerError> {
+    let prefix = prefix.to_path_buf();
+    let package_record = record.package_record.clone();
+    let package_dir = package_dir.to_path_buf();
+    let platform = install_options.platform.unwrap_or_else(Platform::current);
+    let options = driver.link_script_options().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        run_pre_link_script(&package_record, &package_dir, &prefix, &platform, &options)
+    })
+    .await?
+    .map_err(|e| InstallerError::PreLinkScriptFailed(record_name(record), Some(e)))?;
+
+    match result {
+        Some(result) if !result.failed_packages.is_empty() => Err(
+            InstallerError::PreLinkScriptFailed(record_name(record), None),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Links the package into the prefix and writes the `conda-meta` record that describes how it was
+/// linked. The files that are created are recorded in the journal.
+#[allow(clippy::too_many_arguments)]
+async fn link_and_write_prefix_record(
+    prefix: &Path,
+    index: usize,
+    record: &RepoDataRecord,
+    package_dir: PathBuf,
+    driver: &InstallDriver,
+    install_options: &InstallOptions,
+    install_menus: bool,
+    journal: &Arc<TransactionJournal>,
+    hooks: &[Arc<dyn InstallHook>],
+    reporter: Option<&dyn Reporter>,
+) -> Result<PrefixRecord, InstallerError> {
+    // Read the package metadata to determine which files are going to be created.
+    let metadata_dir = package_dir.clone();
+    let (paths_json, index_json) = driver
+        .run_blocking_io_task(move || {
+            let paths_json =
+                PathsJson::from_package_directory_with_deprecated_fallback(&metadata_dir)
+                    .map_err(InstallError::FailedToReadPathsJson)?;
+            let index_json = IndexJson::from_package_directory(&metadata_dir)
+                .map_err(InstallError::FailedToReadIndexJson)?;
+            Ok::<_, InstallError>((paths_json, index_json))
+        })
+        .await
+        .map_err(|e| InstallerError::LinkError(record_name(record), e))?;
+
+    let (record, package_dir) = run_hooks(
+        hooks,
+        (record.clone(), package_dir),
+        |hook, (record, package_dir)| {
+            hook.pre_link(record, package_dir).map_err(|e| {
+                InstallerError::HookFailed(format!("before linking {}", record_name(record)), e)
+            })
+        },
+    )
+    .await?;
+    let record = &record;
+
+    // Run the pre-link script of the package, if it is allowed to.
+    if !driver.link_script_options().policy.is_deny() {
+        run_pre_link_script_of(prefix, record, &package_dir, install_options, driver).await?;
+    }
+
+    let (paths, link_type) = link_package_with_link_type(
+        &package_dir,
+        prefix,
+        driver,
+        InstallOptions {
+            paths_json: Some(paths_json),
+            index_json: Some(index_json),
+            journal: Some(journal.clone()),
+            ..install_options.clone()
+        },
+    )
+    .await
+    .map_err(|e| InstallerError::LinkError(record_name(record), e))?;
+
+    let mut prefix_record = PrefixRecord::from_repodata_record(
+        record.clone(),
+        None,
+        Some(package_dir.clone()),
+        paths,
+        None,
+        Some(Link {
+            source: package_dir,
+            link_type: Some(link_type),
+        }),
+    );
+
+    // Create the menu shortcuts of the package. Failing to do so does not fail the installation.
+    let platform = install_options.platform.unwrap_or_else(Platform::current);
+    if let Some(shortcut_directory) = default_shortcut_directory(platform).filter(|_| install_menus)
+    {
+        let menu_prefix = prefix.to_path_buf();
+        let paths = prefix_record.files.clone();
+        let menu_journal = journal.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            install_menu_items(
+                &menu_prefix,
+                paths.iter().map(PathBuf::as_path),
+                platform,
+                &shortcut_directory,
+                Some(&menu_journal),
+            )
+        })
+        .await?;
+        match result {
+            Ok(menus) => prefix_record.installed_system_menus = menus,
+            Err(e) => warn(
+                reporter,
+                Some(index),
+                format!(
+                    "failed to create the menu items of {}: {e}",
+                    record_name(record)
+                ),
+            ),
+        }
+    }
+
+    let conda_meta_path = prefix.join("conda-meta");
+    journal
+        .record_created([Path::new("conda-meta").join(prefix_record.file_name())])
+        .map_err(InstallerError::TransactionJournalError)?;
+    let prefix_record = tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(&conda_meta_path)?;
+        prefix_record.write_to_path(conda_meta_path.join(prefix_record.file_name()), true)?;
+        Ok(prefix_record)
+    })
+    .await?
+    .map_err(|e| InstallerError::FailedToWritePrefixRecord(record_name(record), e))?;
+
+    run_hooks(hooks, prefix_record, {
+        let prefix = prefix.to_path_buf();
+        move |hook, prefix_record| {
+            hook.post_link(&prefix, prefix_record).map_err(|e| {
+                InstallerError::HookFailed(
+                    format!(
+                        "after linking {}",
+                        record_name(&prefix_record.repodata_record)
+                    ),
+                    e,
+                )
+            })
+        }
+    })
+    .await
+}
+
+/// Drives `future` to completion while forwarding the download events of `url` to the reporter.
+async fn report_download_progress<T>(
+    download_manager: &DownloadManager,
+    url: &url::Url,
+    reporter: &dyn Reporter,
+    cache_entry: usize,
+    future: impl std::future::Future<Output = T>,
+) -> T {
+    let events = download_manager.subscribe();
+    let mut events = std::pin::pin!(events);
+    let mut future = std::pin::pin!(future);
+    loop {
+        // Events are handled first so that all events are reported before the future completes.
+        tokio::select! {
+            biased;
+            Some(event) = events.next() => match event {
+                DownloadEvent::Started { url: event_url, .. } if &event_url == url => {
+                    reporter.on_download_start(cache_entry);
+                }
+                DownloadEvent::Progress { url: event_url, downloaded, total }
+                    if &event_url == url =>
+                {
+                    reporter.on_download_progress(cache_entry, downloaded, total);
+                }
+                DownloadEvent::Finished { url: event_url } if &event_url == url => {
+                    reporter.on_download_complete(cache_entry);
+                }
+                _ => {}
+            },
+            result = &mut future => return result,
+        }
+    }
+}
+
+/// Logs a problem that does not fail the installation and passes it to the reporter.
+fn warn(reporter: Option<&dyn Reporter>, operation: Option<usize>, message: String) {
+    tracing::warn!("{message}");
+    if let Some(reporter) = reporter {
+        reporter.on_warning(operation, &message);
+    }
+}
+
+/// Compiles the python source files of the `noarch: python` packages that are installed by the
+/// transaction and adds the resulting bytecode files to the `conda-meta` records of the packages.
+async fn compile_noarch_python_packages(
+    prefix: &Path,
+    linked_records: impl IntoIterator<Item = PrefixRecord>,
+    python_info: &PythonInfo,
+    pyc_compilation: &PycCompilation,
+    journal: &TransactionJournal,
+    reporter: Option<&dyn Reporter>,
+) -> Result<(), InstallerError> {
+    // The records of the noarch python packages that were just linked.
+    let conda_meta_path = prefix.join("conda-meta");
+    let prefix_records = linked_records
+        .into_iter()
+        .filter(|record| record.repodata_record.package_record.noarch.is_python())
+        .map(|record| (conda_meta_path.join(record.file_name()), record))
+        .collect::<Vec<_>>();
+    if prefix_records.is_empty() {
+        return Ok(());
+    }
+
+    let python_path = prefix.join(python_info.path());
+    if !python_path.is_file() {
+        warn(
+            reporter,
+            None,
+            format!(
+                "not compiling python bytecode because '{}' does not exist",
+                python_path.display()
+            ),
+        );
+        return Ok(());
+    }
+
+    let sources = prefix_records
+        .iter()
+        .flat_map(|(_, prefix_record)| python_sources(prefix_record))
+        .cloned()
+        .collect::<Vec<_>>();
+    journal
+        .record_created(sources.iter().map(|source| pyc_path(source, python_info)))
+        .map_err(InstallerError::TransactionJournalError)?;
+
+    let compiled = compile_pyc(prefix, python_info, &sources, pyc_compilation)
+        .await
+        .map_err(InstallerError::PycCompilationFailed)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    // Record the bytecode files so they are removed when the package is removed.
+    for (path, mut prefix_record) in prefix_records {
+        let pyc_files = python_sources(&prefix_record)
+            .map(|source| pyc_path(source, python_info))
+            .filter(|pyc| compiled.contains(pyc))
+            .collect::<Vec<_>>();
+        if pyc_files.is_empty() {
+            continue;
+        }
+
+        for pyc in pyc_files {
+            prefix_record.files.push(pyc.clone());
+            prefix_record.paths_data.paths.push(PathsEntry {
+                relative_path: pyc,
+                original_path: None,
+                path_type: PathType::PycFile,
+                no_link: false,
+                sha256: None,
+                sha256_in_prefix: None,
+                size_in_bytes: None,
+                prefix_placeholder: None,
+                file_mode: None,
+            });
+        }
+        prefix_record.write_to_path(&path, true).map_err(|e| {
+            InstallerError::FailedToUpdatePrefixRecord(prefix_record.file_name(), e)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Returns the python source files that were linked from the package itself.
+fn python_sources(prefix_record: &PrefixRecord) -> impl Iterator<Item = &PathBuf> + '_ {
+    prefix_record
+        .paths_data
+        .paths
+        .iter()
+        .filter(|entry| matches!(entry.path_type, PathType::HardLink | PathType::SoftLink))
+        .map(|entry| &entry.relative_path)
+        .filter(|path| path.extension().is_some_and(|ext| ext == "py"))
+}
+
+fn record_name(record: &RepoDataRecord) -> String {
+    record.file_name.clone()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        repodata_record_from_path, EventReporter, HookError, InstallEvent, InstallHook,
+        InstallPhase, Installer, InstallerError,
+    };
+    use crate::install::{
+        test_utils::build_package, History, HistoryError, InstallOptions, LinkPolicy,
+        LinkScriptPolicy, LinkStrategy, TransactionJournal,
+    };
+    use crate::package_cache::PackageCache;
+    use rattler_conda_types::{prefix_record::LinkType, PrefixRecord, RepoDataRecord};
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    /// A hook that fails after the package with the given name has been linked.
+    struct FailAfterLinking(&'static str);
+
+    impl InstallHook for FailAfterLinking {
+        fn post_link(&self, _prefix: &Path, record: &PrefixRecord) -> Result<(), HookError> {
+            if record.repodata_record.package_record.name.as_normalized() == self.0 {
+                Err("the hook failed".into())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    /// Blocks linking a package until the given packages have been extracted into the package
+    /// cache.
+    struct WaitForCache(&'static [&'static str]);
+
+    impl InstallHook for WaitForCache {
+        fn pre_link(&self, _record: &RepoDataRecord, package_dir: &Path) -> Result<(), HookError> {
+            let cache_dir = package_dir.parent().unwrap();
+            let deadline = Instant::now() + Duration::from_secs(10);
+            for name in self.0 {
+                let index_json = cache_dir.join(format!("{name}-1.0-0/info/index.json"));
+                while !index_json.is_file() {
+                    if Instant::now() > deadline {
+                        return Err(format!("{name} was not fetched while linking").into());
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_install_local_package() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("prefix");
+        let package = build_package(
+            dir.path(),
+            "foo",
+            "1.0",
+            &[],
+            &[("share/foo/foo.txt", "foo"), ("bin/foo", "#!/bin/sh")],
+        );
+
+        for (link_policy, expected_link_type) in [
+            (LinkPolicy::default(), LinkType::HardLink),
+            (LinkPolicy::always_copy(), LinkType::Copy),
+            (
+                LinkPolicy {
+                    files: LinkStrategy::Softlink,
+                    ..LinkPolicy::default()
+                },
+                LinkType::SoftLink,
+            ),
+        ] {
+            Installer::new()
+                .with_package_cache(PackageCache::new(dir.path().join("pkgs")))
+                .with_install_options(InstallOptions {
+                    allow_hard_links: Some(true),
+                    allow_ref_links: Some(false),
+                    link_policy,
+                    ..InstallOptions::default()
+                })
+                .with_reinstall_packages(["foo".parse().unwrap()].into())
+                .install(&prefix, [package.clone()])
+                .await
+                .unwrap();
+
+            assert_eq!(
+                std::fs::read_to_string(prefix.join("share/foo/foo.txt")).unwrap(),
+                "foo"
+            );
+            let records = PrefixRecord::collect_from_prefix(&prefix).unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(
+                records[0].files,
+                ["share/foo/foo.txt", "bin/foo"].map(std::path::PathBuf::from)
+            );
+            assert_eq!(
+                records[0].link.as_ref().and_then(|link| link.link_type),
+                Some(expected_link_type)
+            );
+        }
+
+        // Removing all packages empties the prefix again.
+        Installer::new()
+            .with_package_cache(PackageCache::new(dir.path().join("pkgs")))
+            .install(&prefix, Vec::<RepoDataRecord>::new())
+            .await
+            .unwrap();
+        assert!(!prefix.join("share").exists());
+        assert!(PrefixRecord::collect_from_prefix(&prefix)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_install_with_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("prefix");
+        let package = build_package(dir.path(), "foo", "1.0", &[], &[("foo.txt", "foo")]);
+        let config = rattler_config::Config {
+            cache_dir: Some(dir.path().join("cache")),
+            ..rattler_config::Config::default()
+        };
+
+        Installer::new()
+            .with_config(&config)
+            .install(&prefix, [package])
+            .await
+            .unwrap();
+
+        // The package is extracted into the configured cache directory.
+        assert!(prefix.join("foo.txt").is_file());
+        assert!(dir.path().join("cache/pkgs/foo-1.0-0").is_dir());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pre_link_scripts_run_in_topological_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("prefix");
+        let script = |name: &str| format!("echo {name} >> \"$PREFIX/order.txt\"\n");
+        let packages =
+            [("c", vec!["b"]), ("b", vec!["a>=1"]), ("a", vec![])].map(|(name, depends)| {
+                build_package(
+                    dir.path(),
+                    name,
+                    "1.0",
+                    &depends,
+                    &[(&format!("bin/.{name}-pre-link.sh"), &script(name))],
+                )
+            });
+
+        Installer::new()
+            .with_package_cache(PackageCache::new(dir.path().join("pkgs")))
+            .with_link_script_policy(LinkScriptPolicy::AllowAll)
+            .install(&prefix, packages)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(prefix.join("order.txt")).unwrap(),
+            "a\nb\nc\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_while_linking() {
+        let dir = tempfile::tempdir().unwrap();
+        let packages =
+            ["a", "b"].map(|name| build_package(dir.path(), name, "1.0", &[], &[(name, name)]));
+
+        // Every package waits for the other package to be fetched before it is linked, which
+        // only completes if a package can be fetched while another one is being linked.
+        Installer::new()
+            .with_package_cache(PackageCache::new(dir.path().join("pkgs")))
+            .with_max_concurrent_downloads(1)
+            .with_max_concurrent_links(1)
+            .with_hook(WaitForCache(&["a", "b"]))
+            .install(&dir.path().join("prefix"), packages)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clobber_order_is_independent_of_link_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let x = build_package(dir.path(), "x", "1.0", &[], &[("share/file.txt", "x")]);
+        let y = build_package(dir.path(), "y", "1.0", &["x"], &[("share/file.txt", "y")]);
+
+        // `y` depends on `x` so its file wins, regardless of the order in which the packages are
+        // linked.
+        for (max_concurrent_links, packages) in [
+            (1, [x.clone(), y.clone()]),
+            (1, [y.clone(), x.clone()]),
+            (4, [y.clone(), x.clone()]),
+        ] {
+            let prefix = tempfile::tempdir().unwrap();
+            Installer::new()
+                .with_package_cache(PackageCache::new(dir.path().join("pkgs")))
+                .with_max_concurrent_links(max_concurrent_links)
+                .install(prefix.path(), packages)
+                .await
+                .unwrap();
+            assert_eq!(
+                std::fs::read_to_string(prefix.path().join("share/file.txt")).unwrap(),
+                "y"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("prefix");
+        let package_cache = PackageCache::new(dir.path().join("pkgs"));
+        let foo_1 = build_package(dir.path(), "foo", "1.0", &[], &[("share/foo/1.txt", "1")]);
+        let foo_2 = build_package(dir.path(), "foo", "2.0", &[], &[("share/foo/2.txt", "2")]);
+        let bar = build_package(dir.path(), "bar", "1.0", &[], &[("lib/bar/bar.txt", "bar")]);
+
+        Installer::new()
+            .with_package_cache(package_cache.clone())
+            .install(&prefix, [foo_1])
+            .await
+            .unwrap();
+
+        // Updating foo and installing bar fails after bar has been linked.
+        let result = Installer::new()
+            .with_package_cache(package_cache)
+            .with_hook(FailAfterLinking("bar"))
+            .install(&prefix, [foo_2, bar])
+            .await;
+        assert!(matches!(result, Err(InstallerError::HookFailed(..))));
+
+        // The prefix is in the state it was in before the transaction.
+        assert_eq!(
+            std::fs::read_to_string(prefix.join("share/foo/1.txt")).unwrap(),
+            "1"
+        );
+        assert!(!prefix.join("share/foo/2.txt").exists());
+        assert!(!prefix.join("lib").exists());
+        let records = PrefixRecord::collect_from_prefix(&prefix).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].repodata_record.package_record.version.as_str(),
+            "1.0"
+        );
+        assert!(!TransactionJournal::is_interrupted(&prefix));
+        assert!(!TransactionJournal::directory(&prefix).exists());
+    }
+
+    #[tokio::test]
+    async fn test_rollback_to_revision() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("prefix");
+        let package_cache = PackageCache::new(dir.path().join("pkgs"));
+        let foo_1 = build_package(dir.path(), "foo", "1.0", &[], &[("share/foo/1.txt", "1")]);
+        let foo_2 = build_package(dir.path(), "foo", "2.0", &[], &[("share/foo/2.txt", "2")]);
+        let bar = build_package(dir.path(), "bar", "1.0", &[], &[("lib/bar/bar.txt", "bar")]);
+        let foo_1 = repodata_record_from_path(&foo_1).await.unwrap();
+        let foo_2 = repodata_record_from_path(&foo_2).await.unwrap();
+        let bar = repodata_record_from_path(&bar).await.unwrap();
+
+        for records in [vec![foo_1.clone(), bar.clone()], vec![foo_2.clone()]] {
+            Installer::new()
+                .with_package_cache(package_cache.clone())
+                .install(&prefix, records)
+                .await
+                .unwrap();
+        }
+        let history = History::from_prefix(&prefix).unwrap();
+        assert_eq!(history.revisions().len(), 2);
+        assert_eq!(history.revisions()[1].added, [(&foo_2).into()]);
+        assert_eq!(history.revisions()[1].removed.len(), 2);
+
+        // bar is no longer installed, so its record has to be provided.
+        let result = Installer::new()
+            .with_package_cache(package_cache.clone())
+            .rollback(&prefix, 0, Vec::new())
+            .await;
+        assert!(matches!(
+            result,
+            Err(InstallerError::FailedToDetermineRevision(
+                HistoryError::MissingRecords(_)
+            ))
+        ));
+
+        Installer::new()
+            .with_package_cache(package_cache)
+            .rollback(&prefix, 0, [foo_1, foo_2, bar])
+            .await
+            .unwrap();
+        assert!(prefix.join("share/foo/1.txt").is_file());
+        assert!(prefix.join("lib/bar/bar.txt").is_file());
+        assert!(!prefix.join("share/foo/2.txt").exists());
+
+        // The rollback is recorded as a new revision.
+        let history = History::from_prefix(&prefix).unwrap();
+        assert_eq!(history.revisions().len(), 3);
+        assert_eq!(history.state(2).unwrap(), history.state(0).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_install_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let served = dir.path().join("served");
+        std::fs::create_dir_all(&served).unwrap();
+        let foo = build_package(&served, "foo", "1.0", &[], &[("foo.txt", "foo")]);
+        let bar = build_package(dir.path(), "bar", "1.0", &[], &[("bar.txt", "bar")]);
+
+        // Serve `foo` over http so that it is downloaded.
+        let listener =
+            tokio::net::TcpListener::bind(std::net::SocketAddr::new([127, 0, 0, 1].into(), 0))
+                .await
+                .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let router = axum::Router::new()
+            .route_service("/*key", tower_http::services::ServeDir::new(&served));
+        tokio::spawn(std::future::IntoFuture::into_future(axum::serve(
+            listener,
+            router.into_make_service(),
+        )));
+        let mut foo = repodata_record_from_path(&foo).await.unwrap();
+        foo.url = format!("http://localhost:{port}/{}", foo.file_name)
+            .parse()
+            .unwrap();
+        let bar = repodata_record_from_path(&bar).await.unwrap();
+
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let result = Installer::new()
+            .with_package_cache(PackageCache::new(dir.path().join("pkgs")))
+            .with_reporter(EventReporter::new({
+                let events = events.clone();
+                move |event| events.lock().unwrap().push(event)
+            }))
+            .install(&dir.path().join("prefix"), [foo.clone(), bar.clone()])
+            .await
+            .unwrap();
+        let events = events.lock().unwrap().clone();
+
+        assert_eq!(
+            events.first(),
+            Some(&InstallEvent::TransactionStarted { operations: 2 })
+        );
+        assert_eq!(events.last(), Some(&InstallEvent::TransactionCompleted));
+        for (operation, record) in result.transaction.operations.iter().enumerate() {
+            let record = record.record_to_install().unwrap();
+            let operation_events = events
+                .iter()
+                .filter(|event| match event {
+                    InstallEvent::PackageStarted { operation: o, .. }
+                    | InstallEvent::PhaseChanged { operation: o, .. }
+                    | InstallEvent::PackageCompleted { operation: o } => *o == operation,
+                    _ => false,
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            let phase = |phase| InstallEvent::PhaseChanged { operation, phase };
+            let mut expected = vec![
+                InstallEvent::PackageStarted {
+                    operation,
+                    name: record.package_record.name.clone(),
+                },
+                phase(InstallPhase::Fetch),
+            ];
+            if record.url.scheme() == "http" {
+                expected.extend([phase(InstallPhase::Download), phase(InstallPhase::Extract)]);
+                assert!(events.iter().any(|event| matches!(
+                    event,
+                    InstallEvent::BytesProgressed { operation: o, downloaded, .. }
+                        if *o == operation && *downloaded > 0
+                )));
+            }
+            expected.extend([
+                phase(InstallPhase::Link),
+                InstallEvent::PackageCompleted { operation },
+            ]);
+            assert_eq!(operation_events, expected);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use file_url::{to_long_path, url_to_path};
+use futures::StreamExt;
+use rattler_conda_types::{package::IndexJson, PackageRecord, RepoDataRecord};
+use rattler_digest::{Md5, Sha256};
+use rattler_networking::{
+    retry_policies::{default_retry_policy, RetryDecision, RetryPolicy},
+    Redact, RedactedUrl,
+};
+use reqwest::StatusCode;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use super::InstallerError;
+use crate::package_cache::PackageCache;
+
+/// A package that can be passed to [`super::Installer::install`].
+///
+/// Besides records that were solved from repodata, packages can also be referenced directly by
+/// the path of a local package archive or by the URL of an archive. The record of such a package
+/// is generated from the metadata in the archive.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum PackageReference {
+    /// A record, usually the result of solving an environment.
+    Record(RepoDataRecord),
+
+    /// The path to a local `.conda` or `.tar.bz2` archive.
+    Path(PathBuf),
+
+    /// The URL of a `.conda` or `.tar.bz2` archive.
+    Url(Url),
+}
+
+impl From<RepoDataRecord> for PackageReference {
+    fn from(record: RepoDataRecord) -> Self {
+        PackageReference::Record(record)
+    }
+}
+
+impl From<PathBuf> for PackageReference {
+    fn from(path: PathBuf) -> Self {
+        PackageReference::Path(path)
+    }
+}
+
+impl From<&Path> for PackageReference {
+    fn from(path: &Path) -> Self {
+        PackageReference::Path(path.to_path_buf())
+    }
+}
+
+impl From<Url> for PackageReference {
+    fn from(url: Url) -> Self {
+        match url_to_path(&url) {
+            Some(path) => PackageReference::Path(to_long_path(path)),
+            None => PackageReference::Url(url),
+        }
+    }
+}
+
+impl PackageReference {
+    /// Returns the record of the package. Archives that are referenced by a URL are downloaded
+    /// and extracted into the `package_cache`, so they are not downloaded again during
+    /// installation.
+    pub(super) async fn into_record(
+        self,
+        package_cache: &PackageCache,
+        client: &reqwest_middleware::ClientWithMiddleware,
+    ) -> Result<RepoDataRecord, InstallerError> {
+        match self {
+            PackageReference::Record(record) => Ok(record),
+            PackageReference::Path(path) => repodata_record_from_path(&path).await,
+            PackageReference::Url(url) => {
+                let temp_dir = tempfile::tempdir()
+                    .map_err(|e| InstallerError::FailedToReadPackage(url.to_string(), e.into()))?;
+                let file_name = url
+                    .path_segments()
+                    .and_then(Iterator::last)
+                    .unwrap_or_default();
+                if file_name.is_empty() {
+                    return Err(InstallerError::FailedToReadPackage(
+                        url.to_string(),
+                        "the URL does not refer to a package archive".into(),
+                    ));
+                }
+                let archive_path = temp_dir.path().join(file_name);
+                download(client, &url, &archive_path).await?;
+
+                let mut record = repodata_record_from_path(&archive_path).await?;
+                record.url = url.clone();
+                record.channel = channel_url(&url);
+
+                // Populate the cache from the downloaded archive.
+                package_cache
+                    .get_or_fetch(&record.package_record, move |destination| async move {
+                        let result = rattler_package_streaming::tokio::fs::extract(
+                            &archive_path,
+                            &destination,
+                        )
+                        .await
+                        .map(|_| ());
+                        drop(temp_dir);
+                        result
+                    })
+                    .await
+                    .map_err(|e| InstallerError::FailedToFetch(url.to_string(), e))?;
+
+                Ok(record)
+            }
+        }
+    }
+}
+
+/// Returns the URL of the directory that contains the package, which is used as its channel.
+fn channel_url(url: &Url) -> String {
+    url.join(".")
+        .map_or_else(|_| url.to_string(), |url| url.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Downloads the file at `url` to `destination`. Failed downloads are retried according to the
+/// [`default_retry_policy`].
+async fn download(
+    client: &reqwest_middleware::ClientWithMiddleware,
+    url: &Url,
+    destination: &Path,
+) -> Result<(), InstallerError> {
+    let retry_policy = default_retry_policy();
+    let request_start = Utc::now();
+    let mut current_try = 0;
+    loop {
+        current_try += 1;
+        let err = match download_once(client, url, destination).await {
+            Ok(()) => return Ok(()),
+            Err(DownloadError::Io(e)) => {
+                return Err(InstallerError::FailedToReadPackage(
+                    RedactedUrl::from(url).to_string(),
+                    e.into(),
+                ))
+            }
+            Err(DownloadError::Request(err)) => err.redact(),
+        };
+
+        // Only retry on errors that are likely to be transient.
+        let is_transient = match &err {
+            reqwest_middleware::Error::Reqwest(err) => {
+                err.is_timeout()
+                    || err.is_connect()
+                    || err.status().is_some_and(|status| {
+                        status.is_server_error()
+                            || status == StatusCode::TOO_MANY_REQUESTS
+                            || status == StatusCode::REQUEST_TIMEOUT
+                    })
+            }
+            reqwest_middleware::Error::Middleware(_) => false,
+        };
+        let execute_after = match retry_policy.should_retry(request_start, current_try) {
+            RetryDecision::Retry { execute_after } if is_transient => execute_after,
+            _ => {
+                return Err(InstallerError::FailedToDownloadPackage(
+                    RedactedUrl::from(url).to_string(),
+                    err,
+                ))
+            }
+        };
+        let duration = (execute_after - Utc::now()).to_std().unwrap_or_default();
+        tracing::warn!(
+            "failed to download {}: {err}. Retry #{current_try}, sleeping {duration:?} until the next attempt...",
+            RedactedUrl::from(url)
+        );
+        tokio::time::sleep(duration).await;
+    }
+}
+
+enum DownloadError {
+    Request(reqwest_middleware::Error),
+    Io(std::io::Error),
+}
+
+/// Downloads the file at `url` to `destination`, overwriting a previous partial download.
+async fn download_once(
+    client: &reqwest_middleware::ClientWithMiddleware,
+    url: &Url,
+    destination: &Path,
+) -> Result<(), DownloadError> {
+    let response = client
+        .get(url.clone())
+        .send()
+        .await
+        .and_then(|response| {
+            response
+                .error_for_status()
+                .map_err(reqwest_middleware::Error::Reqwest)
+        })
+        .map_err(DownloadError::Request)?;
+
+    let mut file = tokio::fs::File::create(destination)
+        .await
+        .map_err(DownloadError::Io)?;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| DownloadError::Request(e.into()))?;
+        file.write_all(&chunk).await.map_err(DownloadError::Io)?;
+    }
+    file.flush().await.map_err(DownloadError::Io)
+}
+
+/// Generates a [`RepoDataRecord`] for a local package archive from the `info/index.json` file
+/// in the archive. The URL of the record refers to the archive itself.
+pub async fn repodata_record_from_path(path: &Path) -> Result<RepoDataRecord, InstallerError> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let read_error = |e: rattler_package_streaming::ExtractError| {
+            InstallerError::FailedToReadPackage(path.display().to_string(), e.into())
+        };
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let index_json: IndexJson =
+            rattler_package_streaming::seek::read_package_file(&path).map_err(read_error)?;
+        let sha256 = rattler_digest::compute_file_digest::<Sha256>(&path)
+            .map_err(|e| read_error(e.into()))?;
+        let md5 =
+            rattler_digest::compute_file_digest::<Md5>(&path).map_err(|e| read_error(e.into()))?;
+        let size = fs_err::metadata(&path)
+            .map_err(|e| read_error(e.into()))?
+            .len();
+
+        let package_record =
+            PackageRecord::from_index_json(index_json, Some(size), Some(sha256), Some(md5))
+                .map_err(|e| {
+                    InstallerError::FailedToReadPackage(path.display().to_string(), e.into())
+                })?;
+
+        let absolute_path = fs_err::canonicalize(&path).map_err(|e| read_error(e.into()))?;
+        let url = Url::from_file_path(&absolute_path).map_err(|()| {
+            InstallerError::FailedToReadPackage(
+                path.display().to_string(),
+                "the path cannot be converted to a URL".into(),
+            )
+        })?;
+
+        Ok(RepoDataRecord {
+            package_record,
+            file_name,
+            channel: channel_url(&url),
+            url,
+        })
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod test {
+    use super::{channel_url, repodata_record_from_path, PackageReference};
+    use crate::install::{test_utils::build_package, InstallerError};
+    use crate::package_cache::PackageCache;
+    use url::Url;
+
+    #[test]
+    fn test_from_url() {
+        let path = std::env::temp_dir().join("foo-1.0-0.tar.bz2");
+        let file_url = Url::from_file_path(&path).unwrap();
+        assert!(matches!(PackageReference::from(file_url), PackageReference::Path(p) if p == path));
+
+        let url: Url = "https://example.com/conda-forge/noarch/foo-1.0-0.conda"
+            .parse()
+            .unwrap();
+        assert!(
+            matches!(PackageReference::from(url.clone()), PackageReference::Url(u) if u == url)
+        );
+        assert_eq!(channel_url(&url), "https://example.com/conda-forge/noarch");
+    }
+
+    #[tokio::test]
+    async fn test_record_from_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = build_package(
+            dir.path(),
+            "foo",
+            "1.0",
+            &["bar >=2"],
+            &[("foo.txt", "foo")],
+        );
+
+        let record = PackageReference::from(package.clone())
+            .into_record(
+                &PackageCache::new(dir.path().join("pkgs")),
+                &reqwest_middleware::ClientWithMiddleware::from(reqwest::Client::default()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(record, repodata_record_from_path(&package).await.unwrap());
+        assert_eq!(record.package_record.name.as_normalized(), "foo");
+        assert_eq!(record.package_record.depends, ["bar >=2"]);
+        assert_eq!(
+            record.package_record.sha256,
+            Some(rattler_digest::compute_file_digest::<rattler_digest::Sha256>(&package).unwrap())
+        );
+        assert_eq!(
+            record.file_name,
+            package.file_name().unwrap().to_str().unwrap()
+        );
+        assert_eq!(
+            record.url.to_file_path().unwrap(),
+            package.canonicalize().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_url_without_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = PackageReference::from(Url::parse("https://example.com/packages/").unwrap())
+            .into_record(
+                &PackageCache::new(dir.path().join("pkgs")),
+                &reqwest_middleware::ClientWithMiddleware::from(reqwest::Client::default()),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(InstallerError::FailedToReadPackage(..))
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+use rattler_conda_types::{PrefixRecord, RepoDataRecord};
+
+use crate::install::Transaction;
+
+/// A trait that enables being notified of the progress of an [`super::Installer`].
+///
+/// Operations are identified by their index in [`Transaction::operations`]. Methods that start a
+/// sub-task of an operation return an index that is passed to the corresponding completion method.
+pub trait Reporter: Send + Sync {
+    /// Called when the transaction has been computed but before any operation is executed.
+    fn on_transaction_start(&self, _transaction: &Transaction<PrefixRecord, RepoDataRecord>) {}
+
+    /// Called when the execution of an operation of the transaction starts.
+    fn on_transaction_operation_start(&self, _operation: usize) {}
+
+    /// Called when the package cache is being populated with the package of an operation. The
+    /// package is either validated if it already exists in the cache or downloaded and extracted.
+    ///
+    /// Returns an index that can be used to identify the task in subsequent calls.
+    fn on_populate_cache_start(&self, operation: usize, _record: &RepoDataRecord) -> usize {
+        operation
+    }
+
+    /// Called when the archive of a package starts downloading while populating the cache,
+    /// possibly resuming an earlier download. Not called if the package is already cached or
+    /// refers to a local file.
+    fn on_download_start(&self, _cache_entry: usize) {}
+
+    /// Called when more bytes of the archive of a package have been downloaded.
+    fn on_download_progress(&self, _cache_entry: usize, _downloaded: u64, _total: Option<u64>) {}
+
+    /// Called when the archive of a package has been downloaded, it is extracted into the cache
+    /// next.
+    fn on_download_complete(&self, _cache_entry: usize) {}
+
+    /// Called when the package cache contains the package.
+    fn on_populate_cache_complete(&self, _cache_entry: usize) {}
+
+    /// Called when a package starts being removed from the prefix.
+    ///
+    /// Returns an index that can be used to identify the task in subsequent calls.
+    fn on_unlink_start(&self, operation: usize, _record: &PrefixRecord) -> usize {
+        operation
+    }
+
+    /// Called when a package has been removed from the prefix.
+    fn on_unlink_complete(&self, _index: usize) {}
+
+    /// Called when a package starts being linked into the prefix.
+    ///
+    /// Returns an index that can be used to identify the task in subsequent calls.
+    fn on_link_start(&self, operation: usize, _record: &RepoDataRecord) -> usize {
+        operation
+    }
+
+    /// Called when a package has been linked into the prefix.
+    fn on_link_complete(&self, _index: usize) {}
+
+    /// Called when an operation of the transaction has been executed.
+    fn on_transaction_operation_complete(&self, _operation: usize) {}
+
+    /// Called when all operations of the transaction have been executed.
+    fn on_transaction_complete(&self) {}
+
+    /// Called when a problem occurred that does not fail the installation. `operation` is the
+    /// index of the operation the warning relates to, if any.
+    fn on_warning(&self, _operation: Option<usize>, _message: &str) {}
+}
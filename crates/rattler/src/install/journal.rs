@@ -0,0 +1,371 @@
+//! A journal that records the modifications that are made to a prefix while a transaction is
+//! executed. If the transaction fails, or if the process is interrupted, the journal is used to
+//! restore the prefix to the state it was in before the transaction started.
+//!
+//! Files of packages that are removed are not deleted but moved to a backup directory. Files that
+//! are created are recorded before they are written. Rolling back a transaction deletes the
+//! created files and moves the backed up files back into place. Committing a transaction deletes
+//! the backup directory. Files that cannot be deleted because they are in use on Windows are moved
+//! to the trash of the prefix, see [`super::trash`].
+
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use fs_err as fs;
+use rattler_conda_types::PrefixRecord;
+use serde::{Deserialize, Serialize};
+
+use super::menuinst::remove_shortcut;
+use super::trash::{remove_dir_all_or_trash, remove_file_or_trash};
+use super::unlink::recursively_remove_empty_directories;
+
+/// The directory, relative to the prefix, that holds the journal and the backed up files.
+pub const JOURNAL_DIR: &str = "conda-meta/.transaction";
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+const BACKUP_DIR: &str = "backup";
+
+/// A single modification of the prefix. Unless noted otherwise, all paths are relative to the
+/// prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalEntry {
+    /// The files are moved to the backup directory.
+    Backup {
+        /// The paths of the files that are backed up.
+        paths: Vec<PathBuf>,
+    },
+
+    /// The files are created in the prefix.
+    Create {
+        /// The paths of the files that are created.
+        paths: Vec<PathBuf>,
+    },
+
+    /// Menu shortcuts, which live outside of the prefix, are moved to a backup location next to
+    /// them, see [`menu_backup_path`].
+    BackupMenus {
+        /// The absolute paths of the shortcuts that are backed up.
+        paths: Vec<PathBuf>,
+    },
+
+    /// Menu shortcuts are created outside of the prefix.
+    CreateMenus {
+        /// The absolute paths of the shortcuts that are created.
+        paths: Vec<PathBuf>,
+    },
+}
+
+/// Returns the path to which a menu shortcut is moved when it is backed up. Shortcuts are not
+/// moved into the prefix because they usually live on another file system. The backup is hidden
+/// and does not have the extension of the shortcut so it is not picked up by the desktop.
+pub fn menu_backup_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{file_name}.rattler-backup"))
+}
+
+/// A journal of an ongoing transaction. See the [module documentation](self) for more
+/// information.
+///
+/// Every entry is written to disk before the modification it describes is performed. This ensures
+/// that the journal of an interrupted transaction can be used to restore the prefix with
+/// [`TransactionJournal::rollback_interrupted`].
+#[derive(Debug)]
+pub struct TransactionJournal {
+    prefix: PathBuf,
+    file: Mutex<fs::File>,
+}
+
+impl TransactionJournal {
+    /// Returns the directory that contains the journal of the transaction for the given prefix.
+    pub fn directory(prefix: &Path) -> PathBuf {
+        prefix.join(JOURNAL_DIR)
+    }
+
+    /// Returns true if the prefix contains the journal of a transaction that did not complete.
+    pub fn is_interrupted(prefix: &Path) -> bool {
+        Self::directory(prefix).join(JOURNAL_FILE).is_file()
+    }
+
+    /// Starts a new transaction for the given prefix. Fails with [`ErrorKind::AlreadyExists`] if
+    /// the prefix contains the journal of an interrupted transaction.
+    pub fn begin(prefix: &Path) -> std::io::Result<Self> {
+        let directory = Self::directory(prefix);
+        fs::create_dir_all(&directory)?;
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(directory.join(JOURNAL_FILE))?;
+        Ok(Self {
+            prefix: prefix.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Reads the entries of the journal in the given prefix. An incomplete last entry, which is
+    /// the result of an interruption while writing the entry, is ignored.
+    pub fn read_entries(prefix: &Path) -> std::io::Result<Vec<JournalEntry>> {
+        let file = fs::File::open(Self::directory(prefix).join(JOURNAL_FILE))?;
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            match serde_json::from_str(&line?) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    tracing::warn!("ignoring incomplete transaction journal entry: {e}");
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Rolls back the interrupted transaction in the given prefix, if any. Returns true if a
+    /// transaction was rolled back.
+    pub fn rollback_interrupted(prefix: &Path) -> std::io::Result<bool> {
+        if !Self::is_interrupted(prefix) {
+            return Ok(false);
+        }
+        let entries = Self::read_entries(prefix)?;
+        rollback_entries(prefix, &entries)?;
+        remove_dir_all_or_trash(prefix, &Self::directory(prefix))?;
+        Ok(true)
+    }
+
+    /// Appends an entry to the journal and flushes it to disk.
+    pub fn record(&self, entry: &JournalEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+
+    /// Records that the given files are about to be created.
+    pub fn record_created(&self, paths: impl IntoIterator<Item = PathBuf>) -> std::io::Result<()> {
+        self.record(&JournalEntry::Create {
+            paths: paths.into_iter().collect(),
+        })
+    }
+
+    /// Moves all the files of an installed package, including its `conda-meta` record, to the
+    /// backup directory. This removes the package from the prefix. The menu shortcuts of the
+    /// package are backed up as well.
+    pub fn backup_package(&self, prefix_record: &PrefixRecord) -> std::io::Result<()> {
+        if !prefix_record.installed_system_menus.is_empty() {
+            self.record(&JournalEntry::BackupMenus {
+                paths: prefix_record.installed_system_menus.clone(),
+            })?;
+            for path in &prefix_record.installed_system_menus {
+                match fs::rename(path, menu_backup_path(path)) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let paths = prefix_record
+            .paths_data
+            .paths
+            .iter()
+            .map(|entry| entry.relative_path.clone())
+            .chain(std::iter::once(
+                Path::new("conda-meta").join(prefix_record.file_name()),
+            ))
+            .collect::<Vec<_>>();
+        self.record(&JournalEntry::Backup {
+            paths: paths.clone(),
+        })?;
+
+        let backup_dir = Self::directory(&self.prefix).join(BACKUP_DIR);
+        for path in paths {
+            let backup_path = backup_dir.join(&path);
+            if let Some(parent) = backup_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            match fs::rename(self.prefix.join(&path), backup_path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    // Simply ignore if the file is already gone.
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Completes the transaction by deleting the journal and the backed up files. Backed up files
+    /// that are still in use on Windows are moved to the trash of the prefix. Directories that
+    /// became empty because their files were backed up are removed, unless they were created by
+    /// the transaction.
+    pub fn commit(self) -> std::io::Result<()> {
+        drop(self.file);
+        let entries = Self::read_entries(&self.prefix)?;
+        remove_dir_all_or_trash(&self.prefix, &Self::directory(&self.prefix))?;
+        remove_empty_backup_directories(&self.prefix, &entries);
+        for entry in &entries {
+            if let JournalEntry::BackupMenus { paths } = entry {
+                for path in paths {
+                    if let Err(e) = remove_shortcut(&menu_backup_path(path)) {
+                        tracing::warn!("failed to remove backed up menu item: {e}");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores the prefix to the state it was in before the transaction started. Like when
+    /// committing, files of the journal that are still in use on Windows are moved to the trash.
+    pub fn rollback(self) -> std::io::Result<()> {
+        drop(self.file);
+        let entries = Self::read_entries(&self.prefix)?;
+        rollback_entries(&self.prefix, &entries)?;
+        remove_dir_all_or_trash(&self.prefix, &Self::directory(&self.prefix))
+    }
+}
+
+/// Removes the directories that contained backed up files if they are empty now.
+fn remove_empty_backup_directories(prefix: &Path, entries: &[JournalEntry]) {
+    // The `conda-meta` directory identifies the prefix, it is kept even if it is empty.
+    let mut keep_directories = HashSet::from([prefix.join("conda-meta")]);
+    let mut directories = Vec::new();
+    for entry in entries {
+        match entry {
+            JournalEntry::Create { paths } => {
+                keep_directories.extend(paths.iter().map(|path| prefix.join(path)));
+            }
+            JournalEntry::Backup { paths } => directories.extend(
+                paths
+                    .iter()
+                    .filter_map(|path| path.parent())
+                    .map(|parent| prefix.join(parent)),
+            ),
+            JournalEntry::BackupMenus { .. } | JournalEntry::CreateMenus { .. } => {}
+        }
+    }
+
+    // Remove the deepest directories first.
+    directories.sort_unstable_by(|a, b| {
+        b.components()
+            .count()
+            .cmp(&a.components().count())
+            .then_with(|| a.cmp(b))
+    });
+    directories.dedup();
+    for directory in directories {
+        if let Err(e) =
+            recursively_remove_empty_directories(&directory, prefix, false, &keep_directories)
+        {
+            tracing::warn!("failed to remove empty directory: {e}");
+        }
+    }
+}
+
+/// Undoes the modifications described by the entries in reverse order.
+fn rollback_entries(prefix: &Path, entries: &[JournalEntry]) -> std::io::Result<()> {
+    let backup_dir = TransactionJournal::directory(prefix).join(BACKUP_DIR);
+    let mut directories = HashSet::new();
+
+    for entry in entries.iter().rev() {
+        match entry {
+            JournalEntry::Create { paths } => {
+                for path in paths {
+                    remove_file_or_trash(prefix, &prefix.join(path))?;
+                    if let Some(parent) = path.parent() {
+                        directories.insert(prefix.join(parent));
+                    }
+                }
+            }
+            JournalEntry::Backup { paths } => {
+                for path in paths {
+                    let target_path = prefix.join(path);
+                    if let Some(parent) = target_path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    match fs::rename(backup_dir.join(path), &target_path) {
+                        Ok(()) => {}
+                        // The file was not moved yet when the transaction was interrupted.
+                        Err(e) if e.kind() == ErrorKind::NotFound => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            JournalEntry::CreateMenus { paths } => {
+                for path in paths {
+                    remove_shortcut(path)?;
+                }
+            }
+            JournalEntry::BackupMenus { paths } => {
+                for path in paths {
+                    match fs::rename(menu_backup_path(path), path) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == ErrorKind::NotFound => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+    }
+
+    // Remove the directories that have become empty.
+    for directory in directories {
+        if let Err(e) =
+            recursively_remove_empty_directories(&directory, prefix, false, &HashSet::new())
+        {
+            tracing::warn!("failed to remove empty directory: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JournalEntry, TransactionJournal};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_rollback_interrupted() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        std::fs::create_dir_all(prefix.join("lib")).unwrap();
+        std::fs::write(prefix.join("lib/old.txt"), "old").unwrap();
+
+        let journal = TransactionJournal::begin(prefix).unwrap();
+        assert!(TransactionJournal::is_interrupted(prefix));
+
+        // Back up the old file and create a new one.
+        journal
+            .record(&JournalEntry::Backup {
+                paths: vec![PathBuf::from("lib/old.txt")],
+            })
+            .unwrap();
+        let backup_path = TransactionJournal::directory(prefix).join("backup/lib/old.txt");
+        std::fs::create_dir_all(backup_path.parent().unwrap()).unwrap();
+        std::fs::rename(prefix.join("lib/old.txt"), &backup_path).unwrap();
+        journal
+            .record_created([PathBuf::from("share/new/new.txt")])
+            .unwrap();
+        std::fs::create_dir_all(prefix.join("share/new")).unwrap();
+        std::fs::write(prefix.join("share/new/new.txt"), "new").unwrap();
+
+        // Simulate an interruption
+        drop(journal);
+
+        assert_eq!(TransactionJournal::read_entries(prefix).unwrap().len(), 2);
+        assert!(TransactionJournal::rollback_interrupted(prefix).unwrap());
+        assert!(!TransactionJournal::is_interrupted(prefix));
+        assert_eq!(
+            std::fs::read_to_string(prefix.join("lib/old.txt")).unwrap(),
+            "old"
+        );
+        assert!(!prefix.join("share").exists());
+        assert!(!TransactionJournal::rollback_interrupted(prefix).unwrap());
+    }
+}
@@ -16,6 +16,7 @@ use std::io::{ErrorKind, Read, Seek, Write};
 use std::path::{Path, PathBuf};
 
 use super::apple_codesign::{codesign, AppleCodeSignBehavior};
+use super::LinkStrategy;
 
 /// Describes the method to "link" a file from the source directory (or the cache directory) to the
 /// destination directory.
@@ -30,8 +31,10 @@ pub enum LinkMethod {
     /// it is also modified in the cache.
     Hardlink,
 
-    /// A soft link is created. The link does not refer to the original file in the cache directory
-    /// but instead it points to another file in the destination.
+    /// A soft link is created. For symbolic links in the package the link does not refer to the
+    /// original file in the cache directory but instead it points to another file in the
+    /// destination. Regular files that are soft linked (see [`LinkStrategy::Softlink`]) point to
+    /// the file in the cache directory.
     Softlink,
 
     /// A copy of a file is created from a file in the cache directory to a file in the destination
@@ -106,6 +109,33 @@ pub enum LinkFileError {
     /// The hash of the file could not be computed.
     #[error("failed to compute the sha256 hash of the file")]
     FailedToComputeSha(#[source] std::io::Error),
+
+    /// The target prefix is longer than the placeholder in a binary file. Replacing the
+    /// placeholder would require truncating strings in the binary.
+    #[error(
+        "the target prefix ({target_prefix_length} bytes) is longer than the placeholder in the binary file ({placeholder_length} bytes), install the package into a prefix with a shorter path"
+    )]
+    BinaryPrefixTooLong {
+        /// The length of the placeholder in bytes
+        placeholder_length: usize,
+        /// The length of the target prefix in bytes
+        target_prefix_length: usize,
+    },
+}
+
+/// Determines what happens when the placeholder in a binary file has to be replaced with a target
+/// prefix that is longer than the placeholder.
+///
+/// Binary files contain the placeholder as a nul-terminated c-string. The string cannot grow
+/// without corrupting the data that follows it, so a longer target prefix does not fit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BinaryPrefixOverflowBehavior {
+    /// Fail with [`LinkFileError::BinaryPrefixTooLong`] (default).
+    #[default]
+    Fail,
+
+    /// Truncate the strings to fit. This most likely results in a broken binary.
+    Truncate,
 }
 
 /// The successful result of calling [`link_file`].
@@ -130,6 +160,32 @@ pub struct LinkedFile {
     pub prefix_placeholder: Option<String>,
 }
 
+/// Options that determine how [`link_file_with_options`] places a file into the target directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkFileOptions {
+    /// Whether symbolic links in the package may be recreated as symbolic links.
+    pub allow_symbolic_links: bool,
+
+    /// Whether files may be hard linked.
+    pub allow_hard_links: bool,
+
+    /// Whether files may be reflinked.
+    pub allow_ref_links: bool,
+
+    /// The platform for which the file is installed.
+    pub target_platform: Platform,
+
+    /// What to do with binaries that need to be signed on macOS ARM64.
+    pub apple_codesign_behavior: AppleCodeSignBehavior,
+
+    /// What to do if the target prefix is longer than the placeholder in a binary file.
+    pub binary_prefix_overflow_behavior: BinaryPrefixOverflowBehavior,
+
+    /// How the file is placed into the target directory. The `allow_*` fields take precedence,
+    /// a strategy that is not allowed falls back to copying.
+    pub link_strategy: LinkStrategy,
+}
+
 /// Installs a single file from a `package_dir` to the the `target_dir`. Replaces any
 /// `prefix_placeholder` in the file with the `prefix`.
 ///
@@ -137,6 +193,8 @@ pub struct LinkedFile {
 ///
 /// Note that usually the `target_prefix` is equal to `target_dir` but it might differ. See
 /// [`crate::install::InstallOptions::target_prefix`] for more information.
+///
+/// See [`link_file_with_options`] for more control over how the file is linked.
 #[allow(clippy::too_many_arguments)] // TODO: Fix this properly
 pub fn link_file(
     path_json_entry: &PathsEntry,
@@ -150,6 +208,48 @@ pub fn link_file(
     target_platform: Platform,
     apple_codesign_behavior: AppleCodeSignBehavior,
 ) -> Result<LinkedFile, LinkFileError> {
+    link_file_with_options(
+        path_json_entry,
+        destination_relative_path,
+        package_dir,
+        target_dir,
+        target_prefix,
+        &LinkFileOptions {
+            allow_symbolic_links,
+            allow_hard_links,
+            allow_ref_links,
+            target_platform,
+            apple_codesign_behavior,
+            binary_prefix_overflow_behavior: BinaryPrefixOverflowBehavior::default(),
+            link_strategy: LinkStrategy::Hardlink,
+        },
+    )
+}
+
+/// Installs a single file from a `package_dir` to the the `target_dir` according to the given
+/// [`LinkFileOptions`]. See [`link_file`].
+pub fn link_file_with_options(
+    path_json_entry: &PathsEntry,
+    destination_relative_path: PathBuf,
+    package_dir: &Path,
+    target_dir: &Path,
+    target_prefix: &str,
+    options: &LinkFileOptions,
+) -> Result<LinkedFile, LinkFileError> {
+    let LinkFileOptions {
+        allow_symbolic_links,
+        allow_hard_links,
+        allow_ref_links,
+        target_platform,
+        apple_codesign_behavior,
+        binary_prefix_overflow_behavior,
+        link_strategy,
+    } = *options;
+    let (allow_symbolic_links, allow_hard_links, allow_ref_links) = match link_strategy {
+        LinkStrategy::Hardlink => (allow_symbolic_links, allow_hard_links, allow_ref_links),
+        LinkStrategy::Softlink => (allow_symbolic_links, false, false),
+        LinkStrategy::Copy => (false, false, false),
+    };
     let source_path = package_dir.join(&path_json_entry.relative_path);
 
     let destination_path = target_dir.join(&destination_relative_path);
@@ -173,6 +273,20 @@ pub fn link_file(
         // bytes which makes it easier to search for the placeholder prefix.
         let source = map_or_read_source_file(&source_path)?;
 
+        // Make sure that the target prefix fits in the place of the placeholder. Placeholders are
+        // not replaced in binaries on Windows.
+        if *file_mode == FileMode::Binary
+            && !target_platform.is_windows()
+            && target_prefix.len() > placeholder.len()
+            && binary_prefix_overflow_behavior == BinaryPrefixOverflowBehavior::Fail
+            && memchr::memmem::find(source.as_ref(), placeholder.as_bytes()).is_some()
+        {
+            return Err(LinkFileError::BinaryPrefixTooLong {
+                placeholder_length: placeholder.len(),
+                target_prefix_length: target_prefix.len(),
+            });
+        }
+
         // Open the destination file
         let destination = std::fs::File::create(&destination_path)
             .map_err(LinkFileError::FailedToOpenDestinationFile)?;
@@ -263,6 +377,11 @@ pub fn link_file(
             }
         }
         LinkMethod::Patched(*file_mode)
+    } else if path_json_entry.path_type == PathType::HardLink
+        && link_strategy == LinkStrategy::Softlink
+        && allow_symbolic_links
+    {
+        symlink_to_path(&source_path, &source_path, &destination_path)?
     } else if path_json_entry.path_type == PathType::HardLink && allow_ref_links {
         reflink_to_destination(&source_path, &destination_path, allow_hard_links)?
     } else if path_json_entry.path_type == PathType::HardLink && allow_hard_links {
@@ -276,7 +395,8 @@ pub fn link_file(
     // Compute the final SHA256 if we didnt already or if its not stored in the paths.json entry.
     let sha256 = if let Some(sha256) = sha256 {
         sha256
-    } else if link_method == LinkMethod::Softlink {
+    } else if link_method == LinkMethod::Softlink && path_json_entry.path_type == PathType::SoftLink
+    {
         // we hash the content of the symlink file. Note that this behavior is different from
         // conda or mamba (where the target of the symlink is hashed). However, hashing the target
         // of the symlink is more tricky in our case as we link everything in parallel and would have to
@@ -448,8 +568,19 @@ fn symlink_to_destination(
     let linked_path = source_path
         .read_link()
         .map_err(LinkFileError::FailedToReadSymlink)?;
+    symlink_to_path(&linked_path, source_path, destination_path)
+}
+
+/// Creates a symbolic link at `destination_path` that points to `linked_path`. If the file already
+/// exists it is removed and the operation is retried. Falls back to copying the `source_path` if
+/// symbolic links are not supported.
+fn symlink_to_path(
+    linked_path: &Path,
+    source_path: &Path,
+    destination_path: &Path,
+) -> Result<LinkMethod, LinkFileError> {
     loop {
-        match symlink(&linked_path, destination_path) {
+        match symlink(linked_path, destination_path) {
             Ok(_) => return Ok(LinkMethod::Softlink),
             Err(e) if e.kind() == ErrorKind::AlreadyExists => {
                 std::fs::remove_file(destination_path).map_err(|err| {
@@ -543,7 +674,7 @@ static SHEBANG_REGEX: Lazy<Regex> = Lazy::new(|| {
 });
 
 /// Finds if the shebang line length is valid.
-fn is_valid_shebang_length(shebang: &str, platform: &Platform) -> bool {
+pub(crate) fn is_valid_shebang_length(shebang: &str, platform: &Platform) -> bool {
     const MAX_SHEBANG_LENGTH_LINUX: usize = 127;
     const MAX_SHEBANG_LENGTH_MACOS: usize = 512;
 
@@ -783,6 +914,120 @@ mod test {
         assert_eq!(out.len(), input.len());
     }
 
+    #[test]
+    fn test_binary_prefix_too_long() {
+        use super::{
+            link_file_with_options, BinaryPrefixOverflowBehavior, LinkFileError, LinkFileOptions,
+        };
+        use crate::install::{AppleCodeSignBehavior, LinkStrategy};
+        use rattler_conda_types::package::{FileMode, PathType, PathsEntry, PrefixPlaceholder};
+        use std::path::PathBuf;
+
+        let package_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            package_dir.path().join("binary"),
+            b"data\x00/placeholder/lib\x00data",
+        )
+        .unwrap();
+        let entry = PathsEntry {
+            relative_path: PathBuf::from("binary"),
+            no_link: false,
+            path_type: PathType::HardLink,
+            prefix_placeholder: Some(PrefixPlaceholder {
+                file_mode: FileMode::Binary,
+                placeholder: String::from("/placeholder"),
+            }),
+            sha256: None,
+            size_in_bytes: None,
+        };
+
+        let link = |binary_prefix_overflow_behavior| {
+            link_file_with_options(
+                &entry,
+                PathBuf::from("binary"),
+                package_dir.path(),
+                target_dir.path(),
+                "/a/much/longer/prefix",
+                &LinkFileOptions {
+                    allow_symbolic_links: false,
+                    allow_hard_links: false,
+                    allow_ref_links: false,
+                    target_platform: Platform::Linux64,
+                    apple_codesign_behavior: AppleCodeSignBehavior::DoNothing,
+                    binary_prefix_overflow_behavior,
+                    link_strategy: LinkStrategy::Hardlink,
+                },
+            )
+        };
+
+        assert!(matches!(
+            link(BinaryPrefixOverflowBehavior::Fail),
+            Err(LinkFileError::BinaryPrefixTooLong {
+                placeholder_length: 12,
+                target_prefix_length: 21
+            })
+        ));
+
+        link(BinaryPrefixOverflowBehavior::Truncate).unwrap();
+        assert_eq!(
+            std::fs::read(target_dir.path().join("binary")).unwrap(),
+            b"data\x00/a/much/longer/p\x00data"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_softlink_regular_file() {
+        use super::{link_file_with_options, BinaryPrefixOverflowBehavior, LinkFileOptions};
+        use crate::install::{AppleCodeSignBehavior, LinkMethod, LinkStrategy};
+        use rattler_conda_types::package::{PathType, PathsEntry};
+        use std::path::PathBuf;
+
+        let package_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        std::fs::write(package_dir.path().join("file.txt"), "contents").unwrap();
+        let sha256 = rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>("contents");
+        let entry = PathsEntry {
+            relative_path: PathBuf::from("file.txt"),
+            no_link: false,
+            path_type: PathType::HardLink,
+            prefix_placeholder: None,
+            sha256: Some(sha256),
+            size_in_bytes: Some(8),
+        };
+
+        let linked = link_file_with_options(
+            &entry,
+            PathBuf::from("file.txt"),
+            package_dir.path(),
+            target_dir.path(),
+            "/prefix",
+            &LinkFileOptions {
+                allow_symbolic_links: true,
+                allow_hard_links: true,
+                allow_ref_links: true,
+                target_platform: Platform::Linux64,
+                apple_codesign_behavior: AppleCodeSignBehavior::DoNothing,
+                binary_prefix_overflow_behavior: BinaryPrefixOverflowBehavior::default(),
+                link_strategy: LinkStrategy::Softlink,
+            },
+        )
+        .unwrap();
+
+        // The link points to the file in the package directory and records the hash of the file.
+        assert_eq!(linked.method, LinkMethod::Softlink);
+        assert_eq!(linked.sha256, sha256);
+        assert_eq!(
+            std::fs::read_link(target_dir.path().join("file.txt")).unwrap(),
+            package_dir.path().join("file.txt")
+        );
+        assert_eq!(
+            std::fs::read_to_string(target_dir.path().join("file.txt")).unwrap(),
+            "contents"
+        );
+    }
+
     #[test]
     fn test_replace_long_shebang() {
         let short_shebang = "#!/path/to/python -x 123";
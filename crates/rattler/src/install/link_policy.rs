@@ -0,0 +1,141 @@
+//! Configuration of how the files of a package are placed into a prefix. See [`LinkPolicy`].
+
+use rattler_conda_types::package::{PathType, PathsEntry};
+
+/// The way a file of a package is placed into the prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkStrategy {
+    /// Create a hard link to the file in the package cache. Reflinks are used instead if they are
+    /// supported. Falls back to copying if neither is supported.
+    Hardlink,
+
+    /// Create a symbolic link that points to the file in the package cache. Symbolic links in the
+    /// package are recreated as symbolic links. Falls back to copying if symbolic links are not
+    /// supported.
+    Softlink,
+
+    /// Copy the file.
+    Copy,
+}
+
+/// Determines the [`LinkStrategy`] that is used for every file of a package.
+///
+/// Regardless of the policy, files that are marked with `no_link` in the `paths.json` of the
+/// package and files that contain a prefix placeholder that must be replaced are always copied.
+/// Link strategies that are not supported by the filesystem fall back to copying. The
+/// `allow_*_links` fields of [`super::InstallOptions`] can be used to disable specific link
+/// types entirely.
+#[derive(Debug, Clone)]
+pub struct LinkPolicy {
+    /// The strategy for regular files.
+    pub files: LinkStrategy,
+
+    /// The strategy for files that are symbolic links in the package. Only
+    /// [`LinkStrategy::Softlink`] and [`LinkStrategy::Copy`] are meaningful for symbolic links,
+    /// [`LinkStrategy::Hardlink`] is treated as [`LinkStrategy::Softlink`].
+    pub symlinks: LinkStrategy,
+
+    /// Strategies for specific files. The first pattern that matches the path of a file, relative
+    /// to the root of the package, determines the strategy of that file.
+    pub overrides: Vec<(glob::Pattern, LinkStrategy)>,
+
+    /// Always copy all files. This is useful for filesystems where links cause problems, like some
+    /// network shares. Takes precedence over all other fields.
+    pub always_copy: bool,
+}
+
+impl Default for LinkPolicy {
+    fn default() -> Self {
+        Self {
+            files: LinkStrategy::Hardlink,
+            symlinks: LinkStrategy::Softlink,
+            overrides: Vec::new(),
+            always_copy: false,
+        }
+    }
+}
+
+impl LinkPolicy {
+    /// Returns a policy that copies all files.
+    pub fn always_copy() -> Self {
+        Self {
+            always_copy: true,
+            ..Self::default()
+        }
+    }
+
+    /// Adds an override for all files that match the given pattern.
+    #[must_use]
+    pub fn with_override(mut self, pattern: glob::Pattern, strategy: LinkStrategy) -> Self {
+        self.overrides.push((pattern, strategy));
+        self
+    }
+
+    /// Returns the strategy for the given entry of the `paths.json` file of a package.
+    pub fn strategy_for(&self, entry: &PathsEntry) -> LinkStrategy {
+        if self.always_copy || entry.no_link {
+            return LinkStrategy::Copy;
+        }
+
+        if let Some((_, strategy)) = self
+            .overrides
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(&entry.relative_path))
+        {
+            return *strategy;
+        }
+
+        match entry.path_type {
+            PathType::SoftLink => self.symlinks,
+            PathType::HardLink | PathType::Directory => self.files,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LinkPolicy, LinkStrategy};
+    use rattler_conda_types::package::{PathType, PathsEntry};
+    use std::path::PathBuf;
+
+    fn entry(path: &str, path_type: PathType, no_link: bool) -> PathsEntry {
+        PathsEntry {
+            relative_path: PathBuf::from(path),
+            no_link,
+            path_type,
+            prefix_placeholder: None,
+            sha256: None,
+            size_in_bytes: None,
+        }
+    }
+
+    #[test]
+    fn test_strategy_for() {
+        let policy = LinkPolicy::default()
+            .with_override(glob::Pattern::new("etc/**").unwrap(), LinkStrategy::Copy);
+        assert_eq!(
+            policy.strategy_for(&entry("lib/libfoo.so", PathType::HardLink, false)),
+            LinkStrategy::Hardlink
+        );
+        assert_eq!(
+            policy.strategy_for(&entry("lib/libfoo.so.1", PathType::SoftLink, false)),
+            LinkStrategy::Softlink
+        );
+        assert_eq!(
+            policy.strategy_for(&entry("etc/foo/config.toml", PathType::HardLink, false)),
+            LinkStrategy::Copy
+        );
+        assert_eq!(
+            policy.strategy_for(&entry("lib/libfoo.so", PathType::HardLink, true)),
+            LinkStrategy::Copy
+        );
+        assert_eq!(
+            LinkPolicy::always_copy().strategy_for(&entry(
+                "lib/libfoo.so.1",
+                PathType::SoftLink,
+                false
+            )),
+            LinkStrategy::Copy
+        );
+    }
+}
@@ -1,5 +1,10 @@
-//! Functions for running link scripts (pre-unlink and post-link) for a package
+//! Functions for running link scripts (pre-link, post-link and pre-unlink) for a package.
+//!
+//! Link scripts are arbitrary shell scripts that are executed while a package is installed or
+//! removed. Because they can modify the environment in ways that cannot be tracked, they are not
+//! executed unless allowed by a [`LinkScriptPolicy`].
 use std::borrow::Borrow;
+use std::time::Duration;
 use std::{
     collections::{HashMap, HashSet},
     path::Path,
@@ -18,8 +23,43 @@ pub enum LinkScriptError {
     MessageError(#[from] std::io::Error),
 }
 
+/// Determines the packages whose link scripts are executed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LinkScriptPolicy {
+    /// Link scripts are never executed.
+    #[default]
+    Deny,
+
+    /// Only the link scripts of the given packages are executed.
+    AllowList(HashSet<PackageName>),
+
+    /// The link scripts of all packages are executed.
+    AllowAll,
+}
+
+impl LinkScriptPolicy {
+    /// Returns true if the link scripts of the package with the given name may be executed.
+    pub fn is_allowed(&self, name: &PackageName) -> bool {
+        match self {
+            LinkScriptPolicy::Deny => false,
+            LinkScriptPolicy::AllowList(names) => names.contains(name),
+            LinkScriptPolicy::AllowAll => true,
+        }
+    }
+
+    /// Returns true if no link script is ever executed.
+    pub fn is_deny(&self) -> bool {
+        matches!(self, LinkScriptPolicy::Deny)
+    }
+}
+
 /// The type of link script to run
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum LinkScriptType {
+    /// The pre-link script (run before the package is linked)
+    /// This is stored in the package as `bin/.{name}-pre-link.sh` or `Scripts/.{name}-pre-link.bat`
+    PreLink,
     /// The pre-unlink script (run before the package is unlinked)
     /// This is stored in the environment as `bin/.{name}-pre-unlink.sh` or `Scripts/.{name}-pre-unlink.bat`
     PreUnlink,
@@ -32,24 +72,11 @@ impl LinkScriptType {
     /// Get the path to the link script for a given package record and platform
     pub fn get_path(&self, package_record: &PackageRecord, platform: &Platform) -> String {
         let name = &package_record.name.as_normalized();
+        let script_type = self.to_string();
         if platform.is_windows() {
-            match self {
-                LinkScriptType::PreUnlink => {
-                    format!("Scripts/.{name}-pre-unlink.bat")
-                }
-                LinkScriptType::PostLink => {
-                    format!("Scripts/.{name}-post-link.bat")
-                }
-            }
+            format!("Scripts/.{name}-{script_type}.bat")
         } else {
-            match self {
-                LinkScriptType::PreUnlink => {
-                    format!("bin/.{name}-pre-unlink.sh")
-                }
-                LinkScriptType::PostLink => {
-                    format!("bin/.{name}-post-link.sh")
-                }
-            }
+            format!("bin/.{name}-{script_type}.sh")
         }
     }
 }
@@ -57,20 +84,150 @@ impl LinkScriptType {
 impl ToString for LinkScriptType {
     fn to_string(&self) -> String {
         match self {
+            LinkScriptType::PreLink => "pre-link".to_string(),
             LinkScriptType::PreUnlink => "pre-unlink".to_string(),
             LinkScriptType::PostLink => "post-link".to_string(),
         }
     }
 }
 
+/// The captured output of a single link script.
+#[derive(Debug, Clone, Default)]
+pub struct LinkScriptOutput {
+    /// The exit code of the script, `None` if the script did not run to completion.
+    pub exit_code: Option<i32>,
+    /// Everything the script wrote to stdout.
+    pub stdout: String,
+    /// Everything the script wrote to stderr.
+    pub stderr: String,
+}
+
 /// Records the results of running pre/post link scripts
+#[derive(Debug, Default)]
 pub struct PrePostLinkResult {
     /// Messages from the link scripts
     pub messages: HashMap<PackageName, String>,
+    /// The output of the link scripts that were executed
+    pub outputs: HashMap<PackageName, LinkScriptOutput>,
     /// Packages that failed to run the link scripts
     pub failed_packages: Vec<PackageName>,
 }
 
+/// Options that control which link scripts are executed and how.
+#[derive(Debug, Clone, Default)]
+pub struct LinkScriptOptions {
+    /// Determines the packages whose link scripts are executed.
+    pub policy: LinkScriptPolicy,
+    /// The maximum amount of time a single script is allowed to run. A script that takes longer
+    /// is killed, together with the processes it started, and considered failed. `None` means
+    /// scripts are never killed.
+    pub timeout: Option<Duration>,
+}
+
+/// Returns the environment variables that are set when the link script of a package is executed.
+fn link_script_env(target_prefix: &Path, record: &PackageRecord) -> HashMap<String, String> {
+    HashMap::from([
+        (
+            "PREFIX".to_string(),
+            target_prefix.to_string_lossy().to_string(),
+        ),
+        (
+            "PKG_NAME".to_string(),
+            record.name.as_normalized().to_string(),
+        ),
+        ("PKG_VERSION".to_string(), record.version.to_string()),
+        ("PKG_BUILDNUM".to_string(), record.build_number.to_string()),
+    ])
+}
+
+/// Runs a single link script in the activated `target_prefix` and captures its output. Returns
+/// the output and whether the script succeeded.
+fn run_link_script(
+    link_script_type: LinkScriptType,
+    link_file: &Path,
+    record: &PackageRecord,
+    target_prefix: &Path,
+    platform: &Platform,
+    timeout: Option<Duration>,
+) -> (LinkScriptOutput, bool) {
+    let shell = if platform.is_windows() {
+        ShellEnum::CmdExe(CmdExe)
+    } else {
+        ShellEnum::Bash(Bash)
+    };
+
+    tracing::info!(
+        "Running {} script for {}",
+        link_script_type.to_string(),
+        record.name.as_normalized()
+    );
+
+    let env = link_script_env(target_prefix, record);
+    match rattler_shell::run_in_environment_with_timeout(
+        target_prefix,
+        link_file,
+        shell,
+        &env,
+        timeout,
+    ) {
+        Ok(o) => {
+            let output = LinkScriptOutput {
+                exit_code: o.status.code(),
+                stdout: String::from_utf8_lossy(&o.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&o.stderr).into_owned(),
+            };
+            if !o.status.success() {
+                tracing::warn!(
+                    "Error running {} script. Status: {:?}",
+                    link_script_type.to_string(),
+                    o.status
+                );
+                tracing::warn!("  stdout: {}", output.stdout);
+                tracing::warn!("  stderr: {}", output.stderr);
+            }
+            (output, o.status.success())
+        }
+        Err(e) => {
+            tracing::error!(
+                "Error running {} script: {:?}",
+                link_script_type.to_string(),
+                e
+            );
+            (
+                LinkScriptOutput {
+                    stderr: e.to_string(),
+                    ..LinkScriptOutput::default()
+                },
+                false,
+            )
+        }
+    }
+}
+
+/// Reads and removes the `.messages.txt` file that link scripts can write to pass a message to
+/// the user.
+fn take_message(
+    link_script_type: LinkScriptType,
+    record: &PackageRecord,
+    target_prefix: &Path,
+) -> Result<String, LinkScriptError> {
+    let message_file = target_prefix.join(".messages.txt");
+    if !message_file.exists() {
+        return Ok(String::new());
+    }
+
+    let message = std::fs::read_to_string(&message_file)?;
+    tracing::info!(
+        "Message from {} for {}: {}",
+        link_script_type.to_string(),
+        record.name.as_normalized(),
+        message
+    );
+    // Remove the message file
+    std::fs::remove_file(&message_file)?;
+    Ok(message)
+}
+
 /// Run the link scripts for a given package
 pub fn run_link_scripts<'a>(
     link_script_type: LinkScriptType,
@@ -78,76 +235,100 @@ pub fn run_link_scripts<'a>(
     target_prefix: &Path,
     platform: &Platform,
 ) -> Result<PrePostLinkResult, LinkScriptError> {
-    let mut env = HashMap::new();
-    env.insert(
-        "PREFIX".to_string(),
-        target_prefix.to_string_lossy().to_string(),
-    );
+    run_link_scripts_with_options(
+        link_script_type,
+        prefix_records,
+        target_prefix,
+        platform,
+        &LinkScriptOptions {
+            policy: LinkScriptPolicy::AllowAll,
+            timeout: None,
+        },
+    )
+}
 
+/// Run the link scripts of the packages that are allowed by the policy in `options`.
+pub fn run_link_scripts_with_options<'a>(
+    link_script_type: LinkScriptType,
+    prefix_records: impl Iterator<Item = &'a PrefixRecord>,
+    target_prefix: &Path,
+    platform: &Platform,
+    options: &LinkScriptOptions,
+) -> Result<PrePostLinkResult, LinkScriptError> {
     // prefix records are topologically sorted, so we can be sure that all dependencies are
     // installed before the package itself.
-    let mut failed_packages = Vec::new();
-    let mut messages = HashMap::<PackageName, String>::new();
+    let mut result = PrePostLinkResult::default();
     for record in prefix_records {
         let prec = &record.repodata_record.package_record;
-        let link_file = target_prefix.join(&link_script_type.get_path(prec, platform));
-
-        if link_file.exists() {
-            env.insert(
-                "PKG_NAME".to_string(),
-                prec.name.as_normalized().to_string(),
-            );
-            env.insert("PKG_VERSION".to_string(), prec.version.to_string());
-            env.insert("PKG_BUILDNUM".to_string(), prec.build_number.to_string());
-
-            let shell = if platform.is_windows() {
-                ShellEnum::CmdExe(CmdExe)
-            } else {
-                ShellEnum::Bash(Bash)
-            };
+        let link_file = target_prefix.join(link_script_type.get_path(prec, platform));
+        if !link_file.exists() {
+            continue;
+        }
 
-            tracing::info!(
-                "Running {} script for {}",
+        if !options.policy.is_allowed(&prec.name) {
+            tracing::warn!(
+                "Not running {} script of {} because it is not allowed by the link script policy",
                 link_script_type.to_string(),
                 prec.name.as_normalized()
             );
+            continue;
+        }
 
-            match rattler_shell::run_in_environment(target_prefix, &link_file, shell, &env) {
-                Ok(o) if o.status.success() => {}
-                Ok(o) => {
-                    failed_packages.push(prec.name.clone());
-                    tracing::warn!("Error running post-link script. Status: {:?}", o.status);
-                    tracing::warn!("  stdout: {}", String::from_utf8_lossy(&o.stdout));
-                    tracing::warn!("  stderr: {}", String::from_utf8_lossy(&o.stderr));
-                }
-                Err(e) => {
-                    failed_packages.push(prec.name.clone());
-                    tracing::error!("Error running post-link script: {:?}", e);
-                }
-            }
-
-            let message_file = target_prefix.join(".messages.txt");
-            if message_file.exists() {
-                let message = std::fs::read_to_string(&message_file)?;
-                tracing::info!(
-                    "Message from {} for {}: {}",
-                    link_script_type.to_string(),
-                    prec.name.as_normalized(),
-                    message
-                );
-                messages.insert(prec.name.clone(), message);
-                // Remove the message file
-                std::fs::remove_file(&message_file)?;
-            } else {
-                messages.insert(prec.name.clone(), "".to_string());
-            }
+        let (output, success) = run_link_script(
+            link_script_type,
+            &link_file,
+            prec,
+            target_prefix,
+            platform,
+            options.timeout,
+        );
+        if !success {
+            result.failed_packages.push(prec.name.clone());
         }
+        result.outputs.insert(prec.name.clone(), output);
+
+        let message = take_message(link_script_type, prec, target_prefix)?;
+        result.messages.insert(prec.name.clone(), message);
+    }
+
+    Ok(result)
+}
+
+/// Runs the pre-link script of a package, if it has one and the policy allows it. Pre-link
+/// scripts are executed from the extracted package in `package_dir` before the package is linked
+/// into the `target_prefix`.
+///
+/// Returns `None` if no script was executed.
+pub fn run_pre_link_script(
+    record: &PackageRecord,
+    package_dir: &Path,
+    target_prefix: &Path,
+    platform: &Platform,
+    options: &LinkScriptOptions,
+) -> Result<Option<PrePostLinkResult>, LinkScriptError> {
+    let link_file = package_dir.join(LinkScriptType::PreLink.get_path(record, platform));
+    if !link_file.exists() || !options.policy.is_allowed(&record.name) {
+        return Ok(None);
     }
 
-    Ok(PrePostLinkResult {
-        messages,
-        failed_packages,
-    })
+    let (output, success) = run_link_script(
+        LinkScriptType::PreLink,
+        &link_file,
+        record,
+        target_prefix,
+        platform,
+        options.timeout,
+    );
+    let message = take_message(LinkScriptType::PreLink, record, target_prefix)?;
+    Ok(Some(PrePostLinkResult {
+        messages: HashMap::from([(record.name.clone(), message)]),
+        outputs: HashMap::from([(record.name.clone(), output)]),
+        failed_packages: if success {
+            Vec::new()
+        } else {
+            vec![record.name.clone()]
+        },
+    }))
 }
 
 impl InstallDriver {
@@ -172,15 +353,16 @@ impl InstallDriver {
             .filter(|r| to_install.contains(&r.repodata_record.package_record.name))
             .cloned();
 
-        run_link_scripts(
+        run_link_scripts_with_options(
             LinkScriptType::PostLink,
             filter_iter,
             target_prefix,
             &transaction.platform,
+            self.link_script_options(),
         )
     }
 
-    /// Run any post-link scripts that are part of the packages that are being installed.
+    /// Run any pre-unlink scripts that are part of the packages that are being removed.
     pub fn run_pre_unlink_scripts<Old, New>(
         &self,
         transaction: &Transaction<Old, New>,
@@ -189,18 +371,23 @@ impl InstallDriver {
     where
         Old: Borrow<PrefixRecord>,
     {
-        run_link_scripts(
+        run_link_scripts_with_options(
             LinkScriptType::PreUnlink,
             transaction.removed_packages().map(Borrow::borrow),
             target_prefix,
             &transaction.platform,
+            self.link_script_options(),
         )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use rattler_conda_types::{Platform, PrefixRecord, RepoDataRecord};
+    use std::{collections::HashSet, time::Duration};
+
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, PrefixRecord, RepoDataRecord};
+
+    use super::{run_pre_link_script, LinkScriptOptions, LinkScriptPolicy, LinkScriptType};
 
     use crate::{
         get_repodata_record,
@@ -270,4 +457,104 @@ mod tests {
         // check that the pre-unlink script was run
         assert!(!target_prefix.path().join("i-was-post-linked").exists());
     }
+
+    #[test]
+    fn test_link_script_policy() {
+        let foo = PackageName::new_unchecked("foo");
+        let bar = PackageName::new_unchecked("bar");
+        assert!(!LinkScriptPolicy::Deny.is_allowed(&foo));
+        assert!(LinkScriptPolicy::AllowAll.is_allowed(&foo));
+
+        let allow_list = LinkScriptPolicy::AllowList(HashSet::from([foo.clone()]));
+        assert!(allow_list.is_allowed(&foo));
+        assert!(!allow_list.is_allowed(&bar));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pre_link_script_output_and_timeout() {
+        let package_dir = tempfile::tempdir().unwrap();
+        let target_prefix = tempfile::tempdir().unwrap();
+        let record = PackageRecord::new(
+            PackageName::new_unchecked("foo"),
+            "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+            "0".to_string(),
+        );
+        let script = package_dir
+            .path()
+            .join(LinkScriptType::PreLink.get_path(&record, &Platform::Linux64));
+        std::fs::create_dir_all(script.parent().unwrap()).unwrap();
+
+        let options = LinkScriptOptions {
+            policy: LinkScriptPolicy::AllowAll,
+            timeout: Some(Duration::from_secs(1)),
+        };
+
+        // The script is not executed if the policy does not allow it.
+        std::fs::write(&script, "echo \"$PKG_NAME-$PKG_VERSION\"\necho oops >&2\n").unwrap();
+        let denied = LinkScriptOptions {
+            policy: LinkScriptPolicy::Deny,
+            ..options.clone()
+        };
+        assert!(run_pre_link_script(
+            &record,
+            package_dir.path(),
+            target_prefix.path(),
+            &Platform::Linux64,
+            &denied
+        )
+        .unwrap()
+        .is_none());
+
+        // The output of the script is captured.
+        let result = run_pre_link_script(
+            &record,
+            package_dir.path(),
+            target_prefix.path(),
+            &Platform::Linux64,
+            &options,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(result.failed_packages.is_empty());
+        let output = &result.outputs[&record.name];
+        assert_eq!(output.exit_code, Some(0));
+        assert!(output.stdout.contains("foo-1.0"));
+        assert!(output.stderr.contains("oops"));
+
+        // A script that takes too long is killed.
+        std::fs::write(&script, "sleep 10\n").unwrap();
+        let result = run_pre_link_script(
+            &record,
+            package_dir.path(),
+            target_prefix.path(),
+            &Platform::Linux64,
+            &options,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(result.failed_packages, vec![record.name.clone()]);
+        assert_eq!(result.outputs[&record.name].exit_code, None);
+
+        // The processes started by the script are killed as well.
+        std::fs::write(
+            &script,
+            "(sleep 2; touch \"$PREFIX/survived\") &\nsleep 10\n",
+        )
+        .unwrap();
+        let start = std::time::Instant::now();
+        let result = run_pre_link_script(
+            &record,
+            package_dir.path(),
+            target_prefix.path(),
+            &Platform::Linux64,
+            &options,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(result.failed_packages, vec![record.name.clone()]);
+        std::thread::sleep(Duration::from_secs(2));
+        assert!(!target_prefix.path().join("survived").exists());
+    }
 }
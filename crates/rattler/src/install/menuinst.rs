@@ -0,0 +1,957 @@
+//! Creation and removal of menu shortcuts that are described by the `Menu/*.json` files of a
+//! package.
+//!
+//! Packages can ship [menuinst](https://github.com/conda/menuinst) v2 definitions in their
+//! `Menu` directory. Every definition describes one or more menu items which are turned into
+//! `.desktop` files on Linux, application bundles on macOS and Start Menu shortcuts (`.lnk` files)
+//! on Windows. The shortcuts are created outside of the prefix, their paths are stored in
+//! [`PrefixRecord::installed_system_menus`] so they can be removed when the package is
+//! uninstalled.
+//!
+//! The file names of the shortcuts contain the name of the environment and a hash of its path, so
+//! the same package can be installed in multiple environments without their shortcuts replacing
+//! each other.
+
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use rattler_conda_types::{Platform, PrefixRecord};
+use serde::Deserialize;
+
+use super::journal::{JournalEntry, TransactionJournal};
+
+/// An error that can occur while installing menu items.
+#[derive(Debug, thiserror::Error)]
+pub enum MenuInstError {
+    /// An IO error occurred.
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    /// A menu definition could not be parsed.
+    #[error("failed to parse menu definition '{}'", .0.display())]
+    InvalidMenuDefinition(PathBuf, #[source] serde_json::Error),
+
+    /// The name of a menu or a menu item cannot be used as a file name.
+    #[error("invalid menu name '{0}'")]
+    InvalidName(String),
+
+    /// A menu item does not specify the command to execute.
+    #[error("the menu item '{0}' does not specify a command")]
+    MissingCommand(String),
+
+    /// Menu items cannot be created for the platform.
+    #[error("menu items are not supported on {0}")]
+    UnsupportedPlatform(Platform),
+}
+
+/// The contents of a `Menu/*.json` file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MenuDefinition {
+    /// The name of the menu that contains the items.
+    pub menu_name: String,
+
+    /// The items of the menu. Every item is a JSON object that contains the default values of the
+    /// item and, in the `platforms` key, the platform specific values. See [`MenuItem`].
+    pub menu_items: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// A single menu item after platform specific values have been applied.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MenuItem {
+    /// The name of the shortcut.
+    pub name: String,
+
+    /// A description of the shortcut.
+    #[serde(default)]
+    pub description: String,
+
+    /// The command to execute, the first element is the executable.
+    pub command: Vec<String>,
+
+    /// The path to an icon for the shortcut.
+    #[serde(default)]
+    pub icon: Option<String>,
+
+    /// Whether the command should be executed in a terminal.
+    #[serde(default)]
+    pub terminal: bool,
+
+    /// The desktop entry categories (Linux only).
+    #[serde(default, rename = "Categories")]
+    pub categories: Option<Vec<String>>,
+}
+
+impl MenuDefinition {
+    /// Reads a menu definition from a file.
+    pub fn from_path(path: &Path) -> Result<Self, MenuInstError> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents)
+            .map_err(|e| MenuInstError::InvalidMenuDefinition(path.to_path_buf(), e))
+    }
+
+    /// Returns the menu items that are available on the given platform. An item is only available
+    /// on a platform if its `platforms` object contains a key for the platform. The values of that
+    /// key override the default values of the item.
+    pub fn items_for_platform(
+        &self,
+        platform: Platform,
+        placeholders: &HashMap<&str, String>,
+    ) -> Result<Vec<MenuItem>, serde_json::Error> {
+        let key = platform_key(platform);
+        let mut items = Vec::new();
+        for item in &self.menu_items {
+            let Some(overrides) = item
+                .get("platforms")
+                .and_then(|platforms| platforms.get(key))
+            else {
+                continue;
+            };
+
+            let mut merged = item.clone();
+            merged.remove("platforms");
+            if let serde_json::Value::Object(overrides) = overrides {
+                merged.extend(overrides.clone());
+            }
+
+            let mut merged = serde_json::Value::Object(merged);
+            replace_placeholders(&mut merged, placeholders);
+            items.push(serde_json::from_value(merged)?);
+        }
+        Ok(items)
+    }
+}
+
+/// Returns the key that is used in the `platforms` object of a menu item for the given platform.
+fn platform_key(platform: Platform) -> &'static str {
+    if platform.is_windows() {
+        "win"
+    } else if platform.is_osx() {
+        "osx"
+    } else {
+        "linux"
+    }
+}
+
+/// Returns the placeholders that can be used in menu definitions and their values.
+pub fn placeholders(prefix: &Path, platform: Platform) -> HashMap<&'static str, String> {
+    let prefix_str = prefix.to_string_lossy().into_owned();
+    let env_name = prefix
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (bin_dir, python, icon_ext) = if platform.is_windows() {
+        (
+            prefix.join("Library").join("bin"),
+            prefix.join("python.exe"),
+            "ico",
+        )
+    } else if platform.is_osx() {
+        (
+            prefix.join("bin"),
+            prefix.join("bin").join("python"),
+            "icns",
+        )
+    } else {
+        (prefix.join("bin"), prefix.join("bin").join("python"), "png")
+    };
+    let scripts_dir = if platform.is_windows() {
+        prefix.join("Scripts")
+    } else {
+        prefix.join("bin")
+    };
+
+    HashMap::from([
+        ("PREFIX", prefix_str.clone()),
+        ("BASE_PREFIX", prefix_str),
+        ("DISTRIBUTION_NAME", env_name.clone()),
+        ("ENV_NAME", env_name),
+        ("BIN_DIR", bin_dir.to_string_lossy().into_owned()),
+        ("SCRIPTS_DIR", scripts_dir.to_string_lossy().into_owned()),
+        ("PYTHON", python.to_string_lossy().into_owned()),
+        (
+            "MENU_DIR",
+            prefix.join("Menu").to_string_lossy().into_owned(),
+        ),
+        ("ICON_EXT", icon_ext.to_string()),
+        (
+            "HOME",
+            dirs::home_dir()
+                .map(|home| home.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        ),
+    ])
+}
+
+/// Replaces all `{{ NAME }}` placeholders in the strings of the value.
+fn replace_placeholders(value: &mut serde_json::Value, placeholders: &HashMap<&str, String>) {
+    match value {
+        serde_json::Value::String(s) => {
+            for (key, replacement) in placeholders {
+                *s = s
+                    .replace(&format!("{{{{ {key} }}}}"), replacement)
+                    .replace(&format!("{{{{{key}}}}}"), replacement);
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                replace_placeholders(value, placeholders);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for value in map.values_mut() {
+                replace_placeholders(value, placeholders);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the directory in which shortcuts of the current user are created for the given
+/// platform, or `None` if menu items are not supported on the platform.
+pub fn default_shortcut_directory(platform: Platform) -> Option<PathBuf> {
+    if platform.is_osx() {
+        dirs::home_dir().map(|home| home.join("Applications"))
+    } else if platform.is_linux() {
+        dirs::data_dir().map(|data| data.join("applications"))
+    } else if platform.is_windows() {
+        dirs::data_dir().map(|data| {
+            data.join("Microsoft")
+                .join("Windows")
+                .join("Start Menu")
+                .join("Programs")
+        })
+    } else {
+        None
+    }
+}
+
+/// Creates the menu items of all `Menu/*.json` files in `paths` (relative to the `prefix`) in the
+/// `shortcut_directory`. Returns the absolute paths of the created shortcuts.
+///
+/// If a `journal` is given, every shortcut is recorded in it before it is created so a rollback of
+/// the transaction removes the shortcut again.
+pub fn install_menu_items<'a>(
+    prefix: &Path,
+    paths: impl IntoIterator<Item = &'a Path>,
+    platform: Platform,
+    shortcut_directory: &Path,
+    journal: Option<&TransactionJournal>,
+) -> Result<Vec<PathBuf>, MenuInstError> {
+    let menu_files = paths
+        .into_iter()
+        .filter(|path| path.starts_with("Menu") && path.extension() == Some("json".as_ref()))
+        .collect::<Vec<_>>();
+    if menu_files.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !(platform.is_linux() || platform.is_osx() || platform.is_windows()) {
+        return Err(MenuInstError::UnsupportedPlatform(platform));
+    }
+
+    let placeholders = placeholders(prefix, platform);
+    let environment_id = environment_id(prefix);
+    let mut created = Vec::new();
+    let result = menu_files.into_iter().try_for_each(|menu_file| {
+        let path = prefix.join(menu_file);
+        let definition = MenuDefinition::from_path(&path)?;
+        validate_name(&definition.menu_name)?;
+        let items = definition
+            .items_for_platform(platform, &placeholders)
+            .map_err(|e| MenuInstError::InvalidMenuDefinition(path.clone(), e))?;
+        for item in items {
+            validate_name(&item.name)?;
+            let shortcut = if platform.is_linux() {
+                shortcut_directory.join(format!(
+                    "{}_{}_{environment_id}.desktop",
+                    slugify(&definition.menu_name),
+                    slugify(&item.name)
+                ))
+            } else if platform.is_osx() {
+                shortcut_directory.join(format!("{} ({environment_id}).app", item.name))
+            } else {
+                shortcut_directory.join(format!("{} ({environment_id}).lnk", item.name))
+            };
+
+            if let Some(journal) = journal {
+                journal.record(&JournalEntry::CreateMenus {
+                    paths: vec![shortcut.clone()],
+                })?;
+            }
+            fs::create_dir_all(shortcut_directory)?;
+            if platform.is_linux() {
+                create_desktop_entry(&item, &shortcut)?;
+            } else if platform.is_osx() {
+                create_app_bundle(&item, &shortcut)?;
+            } else {
+                create_shell_link(&item, &shortcut)?;
+            }
+            created.push(shortcut);
+        }
+        Ok(())
+    });
+
+    // Do not leave a partial set of shortcuts behind.
+    if let Err(e) = result {
+        for shortcut in &created {
+            if let Err(e) = remove_shortcut(shortcut) {
+                tracing::warn!("failed to remove menu item '{}': {e}", shortcut.display());
+            }
+        }
+        return Err(e);
+    }
+
+    Ok(created)
+}
+
+/// Removes the menu shortcuts that were created for the package.
+pub fn remove_menu_items(prefix_record: &PrefixRecord) -> Result<(), MenuInstError> {
+    for path in &prefix_record.installed_system_menus {
+        remove_shortcut(path)?;
+    }
+    Ok(())
+}
+
+/// Removes a shortcut, which is either a file or a directory (an application bundle). A shortcut
+/// that does not exist is ignored.
+pub(crate) fn remove_shortcut(path: &Path) -> std::io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Checks that the name of a menu or menu item can safely be used as (part of) a file name.
+fn validate_name(name: &str) -> Result<(), MenuInstError> {
+    let is_valid = !name.trim().is_empty()
+        && !name.contains("..")
+        && !name.contains(['/', '\\', std::path::MAIN_SEPARATOR, '\0'])
+        && !name.chars().any(char::is_control);
+    if is_valid {
+        Ok(())
+    } else {
+        Err(MenuInstError::InvalidName(name.to_string()))
+    }
+}
+
+/// Returns an identifier of the environment at `prefix` that is used in the file names of its
+/// shortcuts: the name of the environment followed by a short hash of its path.
+fn environment_id(prefix: &Path) -> String {
+    let hash = rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(
+        prefix.to_string_lossy().as_bytes(),
+    );
+    let name = prefix
+        .file_name()
+        .map(|name| slugify(&name.to_string_lossy()))
+        .unwrap_or_default();
+    format!("{name}-{}", &format!("{hash:x}")[..8])
+}
+
+/// Converts a name to something that can safely be used as a file name.
+fn slugify(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Quotes an argument of the `Exec` key of a desktop entry if it contains reserved characters.
+fn quote_argument(argument: &str) -> String {
+    const RESERVED: &[char] = &[
+        ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(',
+        ')', '`',
+    ];
+    let argument = if argument.is_empty() || argument.contains(RESERVED) {
+        let mut quoted = String::from('"');
+        for c in argument.chars() {
+            if matches!(c, '"' | '`' | '$' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    } else {
+        argument.to_string()
+    };
+    // A literal percent sign has to be escaped because it starts a field code.
+    argument.replace('%', "%%")
+}
+
+/// Escapes a string value of a desktop entry.
+fn escape_desktop_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes a freedesktop.org desktop entry to `path`.
+fn create_desktop_entry(item: &MenuItem, path: &Path) -> Result<(), MenuInstError> {
+    let exec = item
+        .command
+        .iter()
+        .map(|argument| quote_argument(argument))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut contents = format!(
+        "[Desktop Entry]\nType=Application\nEncoding=UTF-8\nName={}\nComment={}\nExec={}\nTerminal={}\n",
+        escape_desktop_value(&item.name),
+        escape_desktop_value(&item.description),
+        escape_desktop_value(&exec),
+        item.terminal
+    );
+    if let Some(icon) = &item.icon {
+        contents.push_str(&format!("Icon={}\n", escape_desktop_value(icon)));
+    }
+    if let Some(categories) = &item.categories {
+        let categories = categories
+            .iter()
+            .map(|category| escape_desktop_value(category).replace(';', "\\;"))
+            .collect::<Vec<_>>();
+        contents.push_str(&format!("Categories={};\n", categories.join(";")));
+    }
+
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Creates a macOS application bundle at `bundle` that launches the command of the menu item.
+fn create_app_bundle(item: &MenuItem, bundle: &Path) -> Result<(), MenuInstError> {
+    let contents_dir = bundle.join("Contents");
+    let macos_dir = contents_dir.join("MacOS");
+    fs::create_dir_all(&macos_dir)?;
+
+    let executable = slugify(&item.name);
+    let launcher = macos_dir.join(&executable);
+    fs::write(
+        &launcher,
+        format!(
+            "#!/bin/sh\nexec {}\n",
+            item.command
+                .iter()
+                .map(|argument| format!("'{}'", argument.replace('\'', "'\\''")))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    )?;
+    #[cfg(unix)]
+    fs::set_permissions(
+        &launcher,
+        std::os::unix::fs::PermissionsExt::from_mode(0o755),
+    )?;
+
+    let mut icon_entry = String::new();
+    if let Some(icon) = item.icon.as_deref().map(Path::new).filter(|p| p.is_file()) {
+        let resources_dir = contents_dir.join("Resources");
+        fs::create_dir_all(&resources_dir)?;
+        let icon_name = icon.file_name().unwrap_or_default().to_string_lossy();
+        fs::copy(icon, resources_dir.join(&*icon_name))?;
+        icon_entry = format!(
+            "\t<key>CFBundleIconFile</key>\n\t<string>{}</string>\n",
+            xml_escape(&icon_name)
+        );
+    }
+
+    fs::write(
+        contents_dir.join("Info.plist"),
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+            <plist version=\"1.0\">\n<dict>\n\
+            \t<key>CFBundleName</key>\n\t<string>{name}</string>\n\
+            \t<key>CFBundleDisplayName</key>\n\t<string>{name}</string>\n\
+            \t<key>CFBundleExecutable</key>\n\t<string>{executable}</string>\n\
+            \t<key>CFBundleIdentifier</key>\n\t<string>com.rattler.{executable}</string>\n\
+            \t<key>CFBundlePackageType</key>\n\t<string>APPL</string>\n\
+            {icon_entry}\
+            </dict>\n</plist>\n",
+            name = xml_escape(&item.name),
+            executable = xml_escape(&executable),
+        ),
+    )?;
+
+    Ok(())
+}
+
+/// Quotes an argument so that `CommandLineToArgvW` parses it back into the same argument.
+fn quote_windows_argument(argument: &str) -> String {
+    if !argument.is_empty() && !argument.contains([' ', '\t', '\n', '"']) {
+        return argument.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in argument.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.extend(std::iter::repeat('\\').take(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            c => {
+                quoted.extend(std::iter::repeat('\\').take(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.extend(std::iter::repeat('\\').take(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// Writes a Windows shell link (`.lnk` file) to `path` that launches the command of the menu item.
+///
+/// The file follows the [Shell Link Binary File Format] and refers to the target by its absolute
+/// path, which Windows resolves when the shortcut is opened.
+///
+/// [Shell Link Binary File Format]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-shllink
+fn create_shell_link(item: &MenuItem, path: &Path) -> Result<(), MenuInstError> {
+    const HAS_LINK_INFO: u32 = 0x02;
+    const HAS_NAME: u32 = 0x04;
+    const HAS_ARGUMENTS: u32 = 0x20;
+    const HAS_ICON_LOCATION: u32 = 0x40;
+    const IS_UNICODE: u32 = 0x80;
+    const LINK_INFO_HEADER_SIZE: u32 = 0x24;
+    const LINK_CLSID: [u8; 16] = [
+        0x01, 0x14, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x46,
+    ];
+
+    let Some((target, arguments)) = item.command.split_first() else {
+        return Err(MenuInstError::MissingCommand(item.name.clone()));
+    };
+    let target = target.replace('/', "\\");
+    let arguments = arguments
+        .iter()
+        .map(|argument| quote_windows_argument(argument))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut flags = HAS_LINK_INFO | IS_UNICODE;
+    let mut strings = Vec::new();
+    for (flag, value) in [
+        (HAS_NAME, Some(item.description.as_str())),
+        (HAS_ARGUMENTS, Some(arguments.as_str())),
+        (HAS_ICON_LOCATION, item.icon.as_deref()),
+    ] {
+        let Some(value) = value.filter(|value| !value.is_empty()) else {
+            continue;
+        };
+        let value = value.encode_utf16().collect::<Vec<_>>();
+        let length = u16::try_from(value.len()).map_err(|_err| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("the menu item '{}' is too long for a shortcut", item.name),
+            )
+        })?;
+        flags |= flag;
+        strings.extend_from_slice(&length.to_le_bytes());
+        strings.extend(value.iter().flat_map(|c| c.to_le_bytes()));
+    }
+
+    // The header of the link.
+    let mut contents = Vec::new();
+    contents.extend_from_slice(&0x4cu32.to_le_bytes());
+    contents.extend_from_slice(&LINK_CLSID);
+    contents.extend_from_slice(&flags.to_le_bytes());
+    // FILE_ATTRIBUTE_NORMAL
+    contents.extend_from_slice(&0x80u32.to_le_bytes());
+    // Creation, access and write time, file size and icon index.
+    contents.extend_from_slice(&[0; 3 * 8 + 4 + 4]);
+    // SW_SHOWNORMAL
+    contents.extend_from_slice(&1u32.to_le_bytes());
+    // Hot key and reserved fields.
+    contents.extend_from_slice(&[0; 2 + 2 + 4 + 4]);
+
+    // The link info with the absolute path of the target, both as ANSI and as unicode string.
+    // Characters that cannot be represented in the ANSI string are replaced, Windows uses the
+    // unicode string if it is present.
+    // A volume id of a fixed drive without a label.
+    let volume_id = [0x11, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0x10, 0, 0, 0, 0];
+    let ansi_path = target
+        .chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+        .chain([0])
+        .collect::<Vec<_>>();
+    let unicode_path = target
+        .encode_utf16()
+        .chain([0])
+        .flat_map(u16::to_le_bytes)
+        .collect::<Vec<_>>();
+    let volume_id_offset = LINK_INFO_HEADER_SIZE;
+    let local_base_path_offset = volume_id_offset + volume_id.len() as u32;
+    let common_path_suffix_offset = local_base_path_offset + ansi_path.len() as u32;
+    let unicode_path_offset = common_path_suffix_offset + 1;
+    let unicode_suffix_offset = unicode_path_offset + unicode_path.len() as u32;
+    let link_info_size = unicode_suffix_offset + 2;
+    for value in [
+        link_info_size,
+        LINK_INFO_HEADER_SIZE,
+        // VolumeIDAndLocalBasePath
+        1,
+        volume_id_offset,
+        local_base_path_offset,
+        // No network location
+        0,
+        common_path_suffix_offset,
+        unicode_path_offset,
+        unicode_suffix_offset,
+    ] {
+        contents.extend_from_slice(&value.to_le_bytes());
+    }
+    contents.extend_from_slice(&volume_id);
+    contents.extend_from_slice(&ansi_path);
+    contents.push(0);
+    contents.extend_from_slice(&unicode_path);
+    contents.extend_from_slice(&[0, 0]);
+
+    contents.extend_from_slice(&strings);
+    // The terminal block.
+    contents.extend_from_slice(&[0; 4]);
+
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{environment_id, install_menu_items, remove_menu_items, MenuInstError};
+    use crate::install::TransactionJournal;
+    use rattler_conda_types::{PackageRecord, Platform, PrefixRecord, RepoDataRecord};
+    use std::path::Path;
+
+    const MENU: &str = r#"{
+        "menu_name": "My Tools",
+        "menu_items": [
+            {
+                "name": "My Tool",
+                "description": "Runs my tool",
+                "command": ["{{ PREFIX }}/bin/my tool", "--flag"],
+                "icon": "{{ MENU_DIR }}/tool.{{ ICON_EXT }}",
+                "platforms": {
+                    "linux": { "Categories": ["Science"] },
+                    "osx": {}
+                }
+            },
+            {
+                "name": "Windows Only",
+                "command": ["{{ PREFIX }}/tool.exe"],
+                "platforms": { "win": {} }
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_install_desktop_entry() {
+        let prefix = tempfile::tempdir().unwrap();
+        let shortcuts = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(prefix.path().join("Menu")).unwrap();
+        std::fs::write(prefix.path().join("Menu/tool.json"), MENU).unwrap();
+
+        let created = install_menu_items(
+            prefix.path(),
+            [Path::new("Menu/tool.json"), Path::new("bin/my tool")],
+            Platform::Linux64,
+            shortcuts.path(),
+            None,
+        )
+        .unwrap();
+        let id = environment_id(prefix.path());
+        assert_eq!(
+            created,
+            [shortcuts
+                .path()
+                .join(format!("my-tools_my-tool_{id}.desktop"))]
+        );
+
+        let contents = std::fs::read_to_string(&created[0]).unwrap();
+        let prefix_str = prefix.path().display();
+        assert!(contents.contains(&format!("Exec=\"{prefix_str}/bin/my tool\" --flag\n")));
+        assert!(contents.contains(&format!("Icon={prefix_str}/Menu/tool.png\n")));
+        assert!(contents.contains("Categories=Science;\n"));
+
+        let mut prefix_record = PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record: PackageRecord::new(
+                    "tool".parse().unwrap(),
+                    "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+                    "0".to_string(),
+                ),
+                file_name: "tool-1.0-0.conda".to_string(),
+                url: "https://example.com/tool-1.0-0.conda".parse().unwrap(),
+                channel: "https://example.com".to_string(),
+            },
+            None,
+            None,
+            Vec::new(),
+            None,
+            None,
+        );
+        prefix_record.installed_system_menus = created.clone();
+        remove_menu_items(&prefix_record).unwrap();
+        assert!(!created[0].exists());
+    }
+
+    #[test]
+    fn test_install_app_bundle() {
+        let prefix = tempfile::tempdir().unwrap();
+        let shortcuts = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(prefix.path().join("Menu")).unwrap();
+        std::fs::write(prefix.path().join("Menu/tool.json"), MENU).unwrap();
+
+        let created = install_menu_items(
+            prefix.path(),
+            [Path::new("Menu/tool.json")],
+            Platform::OsxArm64,
+            shortcuts.path(),
+            None,
+        )
+        .unwrap();
+        let id = environment_id(prefix.path());
+        assert_eq!(
+            created,
+            [shortcuts.path().join(format!("My Tool ({id}).app"))]
+        );
+        assert!(created[0].join("Contents/Info.plist").is_file());
+        assert!(created[0].join("Contents/MacOS/my-tool").is_file());
+    }
+
+    #[test]
+    fn test_install_shell_link() {
+        let prefix = tempfile::tempdir().unwrap();
+        let shortcuts = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(prefix.path().join("Menu")).unwrap();
+        let menu = r#"{
+            "menu_name": "Tools",
+            "menu_items": [{
+                "name": "Tool",
+                "description": "Runs the tool",
+                "command": ["C:/env/tool.exe", "a b", "c"],
+                "platforms": { "win": {} }
+            }]
+        }"#;
+        std::fs::write(prefix.path().join("Menu/tool.json"), menu).unwrap();
+
+        let created = install_menu_items(
+            prefix.path(),
+            [Path::new("Menu/tool.json")],
+            Platform::Win64,
+            shortcuts.path(),
+            None,
+        )
+        .unwrap();
+        let id = environment_id(prefix.path());
+        assert_eq!(created, [shortcuts.path().join(format!("Tool ({id}).lnk"))]);
+
+        let contents = std::fs::read(&created[0]).unwrap();
+        let utf16 = |s: &str| {
+            s.encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect::<Vec<_>>()
+        };
+        let contains = |needle: &[u8]| contents.windows(needle.len()).any(|w| w == needle);
+        assert_eq!(contents[..4], [0x4c, 0, 0, 0]);
+        // HasLinkInfo, HasName, HasArguments and IsUnicode
+        assert_eq!(contents[20..24], [0xa6, 0, 0, 0]);
+        assert!(contains(b"C:\\env\\tool.exe\0"));
+        assert!(contains(&utf16("C:\\env\\tool.exe")));
+        assert!(contains(&utf16("Runs the tool")));
+        assert!(contains(&utf16("\"a b\" c")));
+        assert!(contents.ends_with(&[0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_shortcuts_of_multiple_environments() {
+        let root = tempfile::tempdir().unwrap();
+        let shortcuts = tempfile::tempdir().unwrap();
+        for platform in [Platform::Linux64, Platform::OsxArm64] {
+            let mut created = Vec::new();
+            for prefix in [root.path().join("a/env"), root.path().join("b/env")] {
+                std::fs::create_dir_all(prefix.join("Menu")).unwrap();
+                std::fs::write(prefix.join("Menu/tool.json"), MENU).unwrap();
+                created.extend(
+                    install_menu_items(
+                        &prefix,
+                        [Path::new("Menu/tool.json")],
+                        platform,
+                        shortcuts.path(),
+                        None,
+                    )
+                    .unwrap(),
+                );
+            }
+            assert_eq!(created.len(), 2);
+            assert_ne!(created[0], created[1]);
+            assert!(created.iter().all(|shortcut| shortcut.exists()));
+        }
+    }
+
+    #[test]
+    fn test_unsupported_platform() {
+        let prefix = tempfile::tempdir().unwrap();
+        let shortcuts = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(prefix.path().join("Menu")).unwrap();
+        std::fs::write(prefix.path().join("Menu/tool.json"), MENU).unwrap();
+        assert!(matches!(
+            install_menu_items(
+                prefix.path(),
+                [Path::new("Menu/tool.json")],
+                Platform::EmscriptenWasm32,
+                shortcuts.path(),
+                None,
+            ),
+            Err(MenuInstError::UnsupportedPlatform(
+                Platform::EmscriptenWasm32
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let prefix = tempfile::tempdir().unwrap();
+        let shortcuts = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(prefix.path().join("Menu")).unwrap();
+        for name in ["../../evil", "a/b", "a\\b", ".."] {
+            let menu = MENU.replace("My Tool\"", &format!("{}\"", name.replace('\\', "\\\\")));
+            std::fs::write(prefix.path().join("Menu/tool.json"), menu).unwrap();
+            for platform in [Platform::Linux64, Platform::OsxArm64] {
+                let result = install_menu_items(
+                    prefix.path(),
+                    [Path::new("Menu/tool.json")],
+                    platform,
+                    shortcuts.path(),
+                    None,
+                );
+                assert!(
+                    matches!(result, Err(MenuInstError::InvalidName(ref n)) if n == name),
+                    "{name} was accepted on {platform}"
+                );
+            }
+        }
+        assert_eq!(std::fs::read_dir(shortcuts.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_desktop_entry_escaping() {
+        let prefix = tempfile::tempdir().unwrap();
+        let shortcuts = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(prefix.path().join("Menu")).unwrap();
+        let menu = r#"{
+            "menu_name": "Tools",
+            "menu_items": [{
+                "name": "Tool",
+                "description": "first\nExec=evil",
+                "command": ["tool", "$HOME", "100%", "a\\b"],
+                "platforms": { "linux": { "Categories": ["A;B"] } }
+            }]
+        }"#;
+        std::fs::write(prefix.path().join("Menu/tool.json"), menu).unwrap();
+
+        let created = install_menu_items(
+            prefix.path(),
+            [Path::new("Menu/tool.json")],
+            Platform::Linux64,
+            shortcuts.path(),
+            None,
+        )
+        .unwrap();
+        let contents = std::fs::read_to_string(&created[0]).unwrap();
+        assert!(contents.contains("Comment=first\\nExec=evil\n"));
+        assert_eq!(
+            contents.lines().filter(|l| l.starts_with("Exec=")).count(),
+            1
+        );
+        assert!(contents.contains(r#"Exec=tool "\\$HOME" 100%% "a\\\\b""#));
+        assert!(contents.contains("Categories=A\\;B;\n"));
+    }
+
+    #[test]
+    fn test_rollback_menu_items() {
+        let prefix = tempfile::tempdir().unwrap();
+        let shortcuts = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(prefix.path().join("Menu")).unwrap();
+        std::fs::write(prefix.path().join("Menu/tool.json"), MENU).unwrap();
+
+        // Menu items that are created during a transaction are removed by a rollback.
+        let journal = TransactionJournal::begin(prefix.path()).unwrap();
+        let created = install_menu_items(
+            prefix.path(),
+            [Path::new("Menu/tool.json")],
+            Platform::Linux64,
+            shortcuts.path(),
+            Some(&journal),
+        )
+        .unwrap();
+        assert!(created[0].is_file());
+        journal.rollback().unwrap();
+        assert!(!created[0].exists());
+
+        // Menu items of a package that is removed are restored by a rollback.
+        let created = install_menu_items(
+            prefix.path(),
+            [Path::new("Menu/tool.json")],
+            Platform::Linux64,
+            shortcuts.path(),
+            None,
+        )
+        .unwrap();
+        let mut prefix_record = PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record: PackageRecord::new(
+                    "tool".parse().unwrap(),
+                    "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+                    "0".to_string(),
+                ),
+                file_name: "tool-1.0-0.conda".to_string(),
+                url: "https://example.com/tool-1.0-0.conda".parse().unwrap(),
+                channel: "https://example.com".to_string(),
+            },
+            None,
+            None,
+            Vec::new(),
+            None,
+            None,
+        );
+        prefix_record.installed_system_menus = created.clone();
+        let journal = TransactionJournal::begin(prefix.path()).unwrap();
+        journal.backup_package(&prefix_record).unwrap();
+        assert!(!created[0].exists());
+        journal.rollback().unwrap();
+        assert!(created[0].is_file());
+
+        // Committing the removal deletes the backup.
+        let journal = TransactionJournal::begin(prefix.path()).unwrap();
+        journal.backup_package(&prefix_record).unwrap();
+        journal.commit().unwrap();
+        assert_eq!(std::fs::read_dir(shortcuts.path()).unwrap().count(), 0);
+    }
+}
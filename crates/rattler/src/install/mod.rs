@@ -17,29 +17,55 @@ pub mod apple_codesign;
 mod clobber_registry;
 mod driver;
 mod entry_point;
+pub mod history;
+pub mod installer;
+pub mod journal;
 pub mod link;
+mod link_policy;
 pub mod link_script;
+pub mod menuinst;
+pub mod prefix_lock;
+pub mod pyc;
 mod python;
+pub mod relocate;
+pub mod signature;
 mod transaction;
+pub mod trash;
 pub mod unlink;
 
 #[cfg(test)]
-mod test_utils;
+pub(crate) mod test_utils;
 
-pub use crate::install::entry_point::{get_windows_launcher, python_entry_point_template};
+pub use crate::install::entry_point::{
+    get_windows_launcher, python_entry_point_template, try_get_windows_gui_launcher,
+    try_get_windows_launcher,
+};
 pub use driver::InstallDriver;
-pub use link::{link_file, LinkFileError, LinkMethod};
-use rattler_conda_types::prefix_record::PathsEntry;
+pub use history::{History, HistoryError};
+pub use installer::{InstallationResult, Installer, InstallerError};
+pub use journal::TransactionJournal;
+pub use link::{
+    link_file, link_file_with_options, BinaryPrefixOverflowBehavior, LinkFileError,
+    LinkFileOptions, LinkMethod,
+};
+pub use link_policy::{LinkPolicy, LinkStrategy};
+pub use link_script::{LinkScriptOptions, LinkScriptPolicy};
+pub use prefix_lock::{PrefixLock, PrefixLockError};
+use rattler_conda_types::prefix_record::{LinkType, PathsEntry};
 pub use transaction::{Transaction, TransactionError, TransactionOperation};
 pub use unlink::unlink_package;
 
 use crate::install::entry_point::{
     create_unix_python_entry_point, create_windows_python_entry_point,
+    create_windows_python_gui_entry_point, python_entry_point_paths,
 };
 pub use apple_codesign::AppleCodeSignBehavior;
+pub use clobber_registry::ClobberPolicy;
 use futures::{FutureExt, StreamExt};
+pub use pyc::PycCompilation;
 pub use python::PythonInfo;
-use rattler_conda_types::package::{IndexJson, LinkJson, NoArchLinks, PackageFile};
+use rattler_conda_types::package::{IndexJson, LinkJson, NoArchLinks, PackageFile, PathType};
+pub use signature::{SignatureVerification, SignatureVerificationPolicy};
 
 use futures::stream::FuturesUnordered;
 use rattler_conda_types::{package::PathsJson, Platform};
@@ -98,6 +124,17 @@ pub enum InstallError {
     /// Post-processing involves removing clobbered paths.
     #[error("failed to post process the environment (unclobbering)")]
     PostProcessFailed(#[source] std::io::Error),
+
+    /// The paths that are about to be created could not be recorded in the transaction journal.
+    #[error("failed to record the created paths in the transaction journal")]
+    FailedToRecordCreatedPaths(#[source] std::io::Error),
+
+    /// The signature of the package could not be verified.
+    #[error("failed to verify the signature of '{0}'")]
+    SignatureVerificationFailed(
+        PathBuf,
+        #[source] rattler_package_streaming::signing::SignatureError,
+    ),
 }
 
 impl From<JoinError> for InstallError {
@@ -170,14 +207,22 @@ pub struct InstallOptions {
     pub allow_hard_links: Option<bool>,
 
     /// Whether or not to use ref links where possible. If this is set to `Some(false)` the use of
-    /// hard links is disabled, if set to `Some(true)` ref links are always used when hard links are
+    /// ref links is disabled, if set to `Some(true)` ref links are always used when hard links are
     /// specified in the [`info/paths.json`] file even if this is not supported. If the value is set
-    /// to `None` ref links are only used if they are supported.
+    /// to `None` ref links are only used if they are supported. Support is determined by cloning a
+    /// file from the package directory into the target directory, the result is cached by the
+    /// [`InstallDriver`] for every pair of filesystems.
     ///
-    /// Ref links are only support by a small number of OSes and filesystems. If reflinking fails
-    /// for whatever reason the files are hardlinked instead (if allowed).
+    /// Ref links (copy-on-write clones) are supported by a small number of filesystems like APFS,
+    /// btrfs, XFS and `ReFS`. They are as fast as hard links but modifying a file in the prefix does
+    /// not modify the file in the package cache. If reflinking fails for whatever reason the files
+    /// are hardlinked instead (if allowed) or copied.
     pub allow_ref_links: Option<bool>,
 
+    /// Determines how every file of the package is placed into the prefix. The link types that
+    /// are disabled by the `allow_*_links` fields, or that are not supported, are never used.
+    pub link_policy: LinkPolicy,
+
     /// The platform for which the package is installed. Some operations like signing require
     /// different behavior depending on the platform. If the field is set to `None` the current
     /// platform is used.
@@ -204,19 +249,47 @@ pub struct InstallOptions {
     /// the `--sign -` argument is used to sign with an ad-hoc certificate.
     /// Ad-hoc signing does not use an identity at all, and identifies exactly one instance of code.
     pub apple_codesign_behavior: AppleCodeSignBehavior,
+
+    /// Binary files store the prefix placeholder as a nul-terminated string which cannot grow.
+    /// This field determines what happens if the target prefix is longer than the placeholder. By
+    /// default linking fails with [`LinkFileError::BinaryPrefixTooLong`].
+    pub binary_prefix_overflow_behavior: BinaryPrefixOverflowBehavior,
+
+    /// Packages can be signed by the keys delegated to by a channel. When this field is set the
+    /// signature of the package is verified before any file is linked and the configured
+    /// [`SignatureVerificationPolicy`] determines what happens if the verification fails. If the
+    /// field is `None` signatures are not verified.
+    pub signature_verification: Option<SignatureVerification>,
+
+    /// When set, every path that is created in the prefix is recorded in this journal before it
+    /// is written, so it can be removed again if the transaction is rolled back.
+    pub journal: Option<Arc<TransactionJournal>>,
 }
 
 /// Given an extracted package archive (`package_dir`), installs its files to the `target_dir`.
 ///
 /// Returns a [`PathsEntry`] for every file that was linked into the target directory. The entries
 /// are ordered in the same order as they appear in the `paths.json` file of the package.
-#[instrument(skip_all, fields(package_dir = %package_dir.display()))]
 pub async fn link_package(
     package_dir: &Path,
     target_dir: &Path,
     driver: &InstallDriver,
     options: InstallOptions,
 ) -> Result<Vec<PathsEntry>, InstallError> {
+    link_package_with_link_type(package_dir, target_dir, driver, options)
+        .await
+        .map(|(paths, _)| paths)
+}
+
+/// Same as [`link_package`] but also returns the [`LinkType`] that describes how the files of the
+/// package were actually placed into the prefix. See [`package_link_type`].
+#[instrument(skip_all, fields(package_dir = %package_dir.display()))]
+pub(crate) async fn link_package_with_link_type(
+    package_dir: &Path,
+    target_dir: &Path,
+    driver: &InstallDriver,
+    options: InstallOptions,
+) -> Result<(Vec<PathsEntry>, LinkType), InstallError> {
     // Determine the target prefix for linking
     let target_prefix = options
         .target_prefix
@@ -236,6 +309,14 @@ pub async fn link_package(
     let index_json = read_index_json(package_dir, driver, options.index_json);
     let (paths_json, index_json) = tokio::try_join!(paths_json, index_json)?;
 
+    // Verify the signature of the package before anything is written to the prefix.
+    if let Some(signature_verification) = options.signature_verification.clone() {
+        let package_dir = package_dir.to_owned();
+        driver
+            .run_blocking_io_task(move || signature_verification.verify(&package_dir))
+            .await?;
+    }
+
     // Error out if this is a noarch python package but the python information is missing.
     if index_json.noarch.is_python() && options.python_info.is_none() {
         return Err(InstallError::MissingPythonInfo);
@@ -261,7 +342,13 @@ pub async fn link_package(
             None => can_create_hardlinks(target_dir, package_dir).right_future(),
         }
     );
-    let allow_ref_links = options.allow_ref_links.unwrap_or(allow_hard_links);
+
+    // Determine whether we can use reflinks. Support is only detected once for every pair of
+    // filesystems of the package cache and the target directory.
+    let allow_ref_links = match options.allow_ref_links {
+        Some(value) => value,
+        None => can_create_reflinks(target_dir, package_dir, driver).await,
+    };
 
     // Determine the platform to use
     let platform = options.platform.unwrap_or(Platform::current());
@@ -282,8 +369,34 @@ pub async fn link_package(
         }
     }
 
+    // Record the paths that are going to be created, including the entry points of noarch python
+    // packages, before anything is written to the prefix.
+    if let Some(journal) = options.journal.clone() {
+        let mut created_paths = final_paths
+            .iter()
+            .map(|(_, path)| path.clone())
+            .collect::<Vec<_>>();
+        if let (Some(link_json), Some(python_info)) = (&link_json, &options.python_info) {
+            if let NoArchLinks::Python(entry_points) = &link_json.noarch {
+                let entry_points = (entry_points.entry_points.iter().map(|e| (e, false)))
+                    .chain(entry_points.gui_entry_points.iter().map(|e| (e, true)));
+                created_paths.extend(entry_points.flat_map(|(entry_point, gui)| {
+                    python_entry_point_paths(entry_point, python_info, &platform, gui)
+                }));
+            }
+        }
+        driver
+            .run_blocking_io_task(move || {
+                journal
+                    .record_created(created_paths)
+                    .map_err(InstallError::FailedToRecordCreatedPaths)
+            })
+            .await?;
+    }
+
     // Wrap the python info in an `Arc` so we can more easily share it with async tasks.
     let python_info = options.python_info.map(Arc::new);
+    let link_policy = &options.link_policy;
 
     // Start linking all package files in parallel
     let mut pending_futures = FuturesUnordered::new();
@@ -301,18 +414,23 @@ pub async fn link_package(
             // filesystem access is blocking anyway so its more efficient to group them together in
             // a single blocking call.
             let cloned_entry = entry.clone();
+            let link_strategy = link_policy.strategy_for(&entry);
             let result = match tokio::task::spawn_blocking(move || {
-                link_file(
+                link_file_with_options(
                     &cloned_entry,
                     computed_path,
                     &package_dir,
                     &target_dir,
                     &target_prefix,
-                    allow_symbolic_links && !cloned_entry.no_link,
-                    allow_hard_links && !cloned_entry.no_link,
-                    allow_ref_links && !cloned_entry.no_link,
-                    platform,
-                    options.apple_codesign_behavior,
+                    &LinkFileOptions {
+                        allow_symbolic_links,
+                        allow_hard_links,
+                        allow_ref_links,
+                        target_platform: platform,
+                        apple_codesign_behavior: options.apple_codesign_behavior,
+                        binary_prefix_overflow_behavior: options.binary_prefix_overflow_behavior,
+                        link_strategy,
+                    },
                 )
             })
             .await
@@ -349,7 +467,8 @@ pub async fn link_package(
                     .map(|p| p.placeholder.clone()),
             };
 
-            Ok(vec![(number_of_paths_entries, paths_entry)])
+            let link_method = (entry.path_type == PathType::HardLink).then_some(result.method);
+            Ok((vec![(number_of_paths_entries, paths_entry)], link_method))
         };
 
         pending_futures.push(install_future.boxed());
@@ -363,7 +482,12 @@ pub async fn link_package(
     if let Some(link_json) = link_json {
         // Parse the `link.json` file and extract entry points from it.
         let entry_points = match link_json.noarch {
-            NoArchLinks::Python(entry_points) => entry_points.entry_points,
+            NoArchLinks::Python(entry_points) => (entry_points.entry_points.into_iter())
+                .map(|entry_point| (entry_point, false))
+                .chain(
+                    (entry_points.gui_entry_points.into_iter())
+                        .map(|entry_point| (entry_point, true)),
+                ),
             NoArchLinks::Generic => {
                 unreachable!("we only use link.json for noarch: python packages")
             }
@@ -376,7 +500,8 @@ pub async fn link_package(
 
         // Create entry points for each listed item. This is different between Windows and unix
         // because on Windows, two PathEntry's are created whereas on Linux only one is created.
-        for entry_point in entry_points {
+        // Graphical applications only differ on Windows, where they use another launcher.
+        for (entry_point, gui) in entry_points {
             let python_info = python_info.clone();
             let target_dir = target_dir.to_owned();
             let target_prefix = target_prefix.clone();
@@ -386,7 +511,12 @@ pub async fn link_package(
                 let _permit = driver.acquire_io_permit().await;
 
                 let entries = if platform.is_windows() {
-                    match create_windows_python_entry_point(
+                    let create_entry_point = if gui {
+                        create_windows_python_gui_entry_point
+                    } else {
+                        create_windows_python_entry_point
+                    };
+                    match create_entry_point(
                         &target_dir,
                         &target_prefix,
                         &entry_point,
@@ -411,7 +541,7 @@ pub async fn link_package(
                     }
                 };
 
-                Ok(entries)
+                Ok((entries, None))
             };
 
             pending_futures.push(entry_point_fut.boxed());
@@ -428,9 +558,12 @@ pub async fn link_package(
     // What makes this loop special is that it also aborts if any of the returned results indicate
     // a failure.
     let mut paths = Vec::with_capacity(number_of_paths_entries);
+    let mut link_methods = Vec::new();
     let mut out_of_order_queue = BinaryHeap::<OrderWrapper<PathsEntry>>::with_capacity(100);
     while let Some(link_result) = pending_futures.next().await {
-        for (index, data) in link_result? {
+        let (entries, link_method) = link_result?;
+        link_methods.extend(link_method);
+        for (index, data) in entries {
             if index == paths.len() {
                 // If this is the next element expected in the sorted list, add it immediately. This
                 // basically means the future finished in order.
@@ -458,7 +591,36 @@ pub async fn link_package(
         "some futures where not added to the result"
     );
 
-    Ok(paths)
+    Ok((paths, package_link_type(link_methods)))
+}
+
+/// Determines the [`LinkType`] of a package from the methods that were used to link its regular
+/// files. A single copied file makes the package a copy. Files that had to be patched are always
+/// copied, they only determine the link type if no other file was linked.
+fn package_link_type(link_methods: impl IntoIterator<Item = LinkMethod>) -> LinkType {
+    let mut link_type = None;
+    let mut patched = false;
+    for method in link_methods {
+        let method_type = match method {
+            LinkMethod::Hardlink => LinkType::HardLink,
+            LinkMethod::Softlink => LinkType::SoftLink,
+            LinkMethod::Copy | LinkMethod::Reflink => LinkType::Copy,
+            LinkMethod::Patched(_) => {
+                patched = true;
+                continue;
+            }
+        };
+        link_type = match (link_type, method_type) {
+            (Some(LinkType::Copy), _) | (_, LinkType::Copy) => Some(LinkType::Copy),
+            (Some(LinkType::SoftLink), _) | (_, LinkType::SoftLink) => Some(LinkType::SoftLink),
+            _ => Some(method_type),
+        };
+    }
+    link_type.unwrap_or(if patched {
+        LinkType::Copy
+    } else {
+        LinkType::HardLink
+    })
 }
 
 fn compute_paths(
@@ -613,25 +775,87 @@ async fn can_create_hardlinks(target_dir: &Path, package_dir: &Path) -> bool {
     paths_have_same_filesystem(target_dir, package_dir).await
 }
 
-/// Returns true if two paths share the same filesystem
-#[cfg(unix)]
-async fn paths_have_same_filesystem(a: &Path, b: &Path) -> bool {
-    use std::os::unix::fs::MetadataExt;
-    match tokio::join!(tokio::fs::metadata(a), tokio::fs::metadata(b)) {
-        (Ok(a), Ok(b)) => a.dev() == b.dev(),
-        _ => false,
+/// Returns true if it is possible to create reflinks from the package cache directory to the target
+/// directory. Support is determined by cloning the `index.json` of the package into the target
+/// directory. The result is cached in the `driver` for the pair of filesystems of both paths.
+async fn can_create_reflinks(
+    target_dir: &Path,
+    package_dir: &Path,
+    driver: &InstallDriver,
+) -> bool {
+    let (Some(source_fs), Some(target_fs)) =
+        tokio::join!(filesystem_id(package_dir), filesystem_id(target_dir))
+    else {
+        return false;
+    };
+
+    if let Some(supported) = driver.reflink_support().get(&(source_fs, target_fs)) {
+        return *supported;
     }
+
+    let source_path = package_dir.join(IndexJson::package_path());
+    let reflink_path = target_dir.join(format!("reflinktest_{}", uuid::Uuid::new_v4()));
+    let result = tokio::task::spawn_blocking(move || {
+        reflink_copy::reflink(&source_path, &reflink_path)?;
+        if let Err(e) = std::fs::remove_file(&reflink_path) {
+            tracing::warn!(
+                "failed to delete temporary file '{}': {e}",
+                reflink_path.display()
+            );
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await;
+
+    let supported = match result {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            tracing::debug!(
+                "failed to create reflink in target directory: {e}. Disabling use of reflinks."
+            );
+            false
+        }
+        Err(_) => false,
+    };
+
+    driver
+        .reflink_support()
+        .insert((source_fs, target_fs), supported);
+    supported
 }
 
 /// Returns true if two paths share the same filesystem
-#[cfg(not(unix))]
 async fn paths_have_same_filesystem(a: &Path, b: &Path) -> bool {
-    match (a.canonicalize(), b.canonicalize()) {
-        (Ok(a), Ok(b)) => a.components().next() == b.components().next(),
+    match tokio::join!(filesystem_id(a), filesystem_id(b)) {
+        (Some(a), Some(b)) => a == b,
         _ => false,
     }
 }
 
+/// Identifies the filesystem a path resides on.
+#[cfg(unix)]
+pub(crate) type FilesystemId = u64;
+
+/// Identifies the filesystem a path resides on.
+#[cfg(not(unix))]
+pub(crate) type FilesystemId = PathBuf;
+
+/// Returns an identifier of the filesystem the path resides on.
+#[cfg(unix)]
+async fn filesystem_id(path: &Path) -> Option<FilesystemId> {
+    use std::os::unix::fs::MetadataExt;
+    tokio::fs::metadata(path).await.ok().map(|m| m.dev())
+}
+
+/// Returns an identifier of the filesystem the path resides on.
+#[cfg(not(unix))]
+async fn filesystem_id(path: &Path) -> Option<FilesystemId> {
+    let path = path.canonicalize().ok()?;
+    path.components()
+        .next()
+        .map(|component| PathBuf::from(component.as_os_str()))
+}
+
 #[cfg(test)]
 mod test {
     use crate::install::{InstallDriver, PythonInfo};
@@ -790,4 +1014,26 @@ mod test {
 
         insta::assert_yaml_snapshot!(paths);
     }
+
+    #[tokio::test]
+    async fn test_reflink_support_is_cached() {
+        let target_dir = tempfile::TempDir::new().unwrap();
+        let package_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(package_dir.path().join("info")).unwrap();
+        std::fs::write(package_dir.path().join("info/index.json"), "{}").unwrap();
+
+        let install_driver = InstallDriver::default();
+        let supported =
+            super::can_create_reflinks(target_dir.path(), package_dir.path(), &install_driver)
+                .await;
+        assert_eq!(install_driver.reflink_support().len(), 1);
+        assert_eq!(
+            super::can_create_reflinks(target_dir.path(), package_dir.path(), &install_driver)
+                .await,
+            supported
+        );
+
+        // The probe must not leave any files behind
+        assert_eq!(std::fs::read_dir(target_dir.path()).unwrap().count(), 0);
+    }
 }
@@ -0,0 +1,204 @@
+//! An advisory lock that prevents multiple processes from modifying the same prefix concurrently.
+//!
+//! The lock is an exclusive file lock on [`PREFIX_LOCK_FILE`] inside the prefix. The operating
+//! system releases the lock when the process that holds it exits, so a crashed installer never
+//! leaves a prefix locked. The lock file also records the id of the process that holds the lock,
+//! which is only used to report who is holding it. A lock that is held is never broken based on
+//! that id: the process may run in another PID namespace or on another host that shares the
+//! prefix over a network file system, where the id says nothing about whether it is still alive.
+//!
+//! As a last resort [`PrefixLock::force_unlock`] removes the lock file, for instance on file
+//! systems that do not release the locks of crashed processes. Processes that lock the prefix
+//! afterwards no longer wait for the process that held the lock.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use fs_err as fs;
+use fslock::LockFile;
+
+/// The path of the lock file, relative to the prefix.
+pub const PREFIX_LOCK_FILE: &str = "conda-meta/.prefix.lock";
+
+/// The interval at which a locked prefix is checked while waiting for the lock.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An error that can occur while locking a prefix.
+#[derive(Debug, thiserror::Error)]
+pub enum PrefixLockError {
+    /// The lock file could not be created or locked.
+    #[error("failed to lock {}", .0.display())]
+    IoError(PathBuf, #[source] std::io::Error),
+
+    /// The prefix is still locked by another process after the timeout elapsed.
+    #[error("timed out waiting for the lock on {}{}", .path.display(), .owner.map(|pid| format!(" held by process {pid}")).unwrap_or_default())]
+    Timeout {
+        /// The path of the lock file.
+        path: PathBuf,
+        /// The id of the process that holds the lock, if it is known.
+        owner: Option<u32>,
+    },
+}
+
+/// An exclusive lock on a prefix. The lock is released when this value is dropped.
+#[derive(Debug)]
+pub struct PrefixLock {
+    path: PathBuf,
+    _lock: LockFile,
+}
+
+impl PrefixLock {
+    /// Returns the path of the lock file of the prefix.
+    pub fn lock_file_path(prefix: &Path) -> PathBuf {
+        prefix.join(PREFIX_LOCK_FILE)
+    }
+
+    /// Returns the path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Tries to lock the prefix without waiting. Returns `None` if the prefix is locked by another
+    /// process.
+    pub fn try_acquire(prefix: &Path) -> Result<Option<Self>, PrefixLockError> {
+        let path = Self::lock_file_path(prefix);
+        let io_error = |e| PrefixLockError::IoError(path.clone(), e);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+
+        let mut lock = LockFile::open(&path).map_err(io_error)?;
+        Ok(lock
+            .try_lock_with_pid()
+            .map_err(io_error)?
+            .then_some(Self { path, _lock: lock }))
+    }
+
+    /// Locks the prefix, waiting until other processes release the lock.
+    pub async fn acquire(prefix: &Path) -> Result<Self, PrefixLockError> {
+        Self::acquire_with_timeout(prefix, None).await
+    }
+
+    /// Locks the prefix, waiting at most `timeout` for other processes to release the lock. If
+    /// `timeout` is `None` this waits indefinitely.
+    pub async fn acquire_with_timeout(
+        prefix: &Path,
+        timeout: Option<Duration>,
+    ) -> Result<Self, PrefixLockError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut logged = false;
+        loop {
+            let lock_prefix = prefix.to_path_buf();
+            let lock = tokio::task::spawn_blocking(move || Self::try_acquire(&lock_prefix))
+                .await
+                .unwrap_or_else(|e| match e.try_into_panic() {
+                    Ok(panic) => std::panic::resume_unwind(panic),
+                    Err(e) => Err(PrefixLockError::IoError(
+                        Self::lock_file_path(prefix),
+                        std::io::Error::new(std::io::ErrorKind::Interrupted, e),
+                    )),
+                })?;
+            if let Some(lock) = lock {
+                return Ok(lock);
+            }
+
+            let owner = Self::owner(prefix);
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(PrefixLockError::Timeout {
+                    path: Self::lock_file_path(prefix),
+                    owner,
+                });
+            }
+            if !logged {
+                if let Some(pid) = owner {
+                    tracing::info!(
+                        "waiting for process {pid} to release the lock on {}",
+                        prefix.display()
+                    );
+                } else {
+                    tracing::info!(
+                        "waiting for another process to release the lock on {}",
+                        prefix.display()
+                    );
+                }
+                logged = true;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Returns the id of the process that holds, or last held, the lock of the prefix if it can
+    /// be determined.
+    pub fn owner(prefix: &Path) -> Option<u32> {
+        std::fs::read_to_string(Self::lock_file_path(prefix))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Removes the lock file of the prefix, regardless of whether another process holds the lock.
+    ///
+    /// This is an escape hatch for locks that are never released, for instance on file systems
+    /// that do not release the locks of crashed processes. Only use this if no other process is
+    /// modifying the prefix.
+    pub fn force_unlock(prefix: &Path) -> std::io::Result<()> {
+        match fs::remove_file(Self::lock_file_path(prefix)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use assert_matches::assert_matches;
+
+    use super::{PrefixLock, PrefixLockError};
+
+    #[tokio::test]
+    async fn test_prefix_lock() {
+        let prefix = tempfile::tempdir().unwrap();
+
+        let lock = PrefixLock::acquire(prefix.path()).await.unwrap();
+        assert!(lock.path().is_file());
+        assert_eq!(PrefixLock::owner(prefix.path()), Some(std::process::id()));
+
+        // The lock is exclusive, also within the same process.
+        assert!(PrefixLock::try_acquire(prefix.path()).unwrap().is_none());
+        assert_matches!(
+            PrefixLock::acquire_with_timeout(prefix.path(), Some(Duration::from_millis(200))).await,
+            Err(PrefixLockError::Timeout { owner: Some(pid), .. }) if pid == std::process::id()
+        );
+
+        // A held lock is never broken, even if the recorded process looks dead, e.g. because it
+        // runs in another PID namespace.
+        std::fs::write(lock.path(), format!("{}\n", u32::MAX)).unwrap();
+        assert!(PrefixLock::try_acquire(prefix.path()).unwrap().is_none());
+        assert!(lock.path().is_file());
+
+        // Waiting for the lock succeeds once it is released.
+        let waiting = tokio::spawn({
+            let prefix = prefix.path().to_path_buf();
+            async move { PrefixLock::acquire(&prefix).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!waiting.is_finished());
+        drop(lock);
+        let lock = tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        // Forcing the lock open allows another lock to be acquired.
+        PrefixLock::force_unlock(prefix.path()).unwrap();
+        let forced = PrefixLock::try_acquire(prefix.path()).unwrap();
+        assert!(forced.is_some());
+        drop(lock);
+    }
+}
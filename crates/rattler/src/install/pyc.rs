@@ -0,0 +1,188 @@
+//! Compilation of Python source files to bytecode (`.pyc` files).
+//!
+//! `noarch: python` packages only ship Python source files because the bytecode depends on the
+//! version of the interpreter. After such a package has been linked into an environment the source
+//! files can be compiled by the interpreter of that environment with [`compile_pyc`].
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use futures::{stream, StreamExt, TryStreamExt};
+use tokio::io::AsyncWriteExt;
+
+use super::PythonInfo;
+
+/// Options that control how Python bytecode is compiled. See [`compile_pyc`].
+#[derive(Debug, Clone)]
+pub struct PycCompilation {
+    /// The maximum number of source files that are compiled by a single interpreter process.
+    pub batch_size: usize,
+
+    /// The maximum number of interpreter processes that run at the same time.
+    pub max_concurrent_processes: usize,
+
+    /// The maximum amount of time a single interpreter process is allowed to run. If the process
+    /// takes longer it is killed and [`PycCompileError::Timeout`] is returned. `None` means the
+    /// process is never killed.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for PycCompilation {
+    fn default() -> Self {
+        Self {
+            batch_size: 250,
+            max_concurrent_processes: std::thread::available_parallelism()
+                .map_or(1, std::num::NonZeroUsize::get),
+            timeout: Some(Duration::from_secs(300)),
+        }
+    }
+}
+
+/// An error that can occur while compiling Python bytecode.
+#[derive(Debug, thiserror::Error)]
+pub enum PycCompileError {
+    /// The Python interpreter could not be started.
+    #[error("failed to start the python interpreter at '{}'", .0.display())]
+    FailedToSpawn(PathBuf, #[source] std::io::Error),
+
+    /// An IO error occurred while communicating with the Python interpreter.
+    #[error("failed to communicate with the python interpreter")]
+    IoError(#[source] std::io::Error),
+
+    /// The Python interpreter did not finish in time.
+    #[error("compiling python bytecode took longer than {0:?}")]
+    Timeout(Duration),
+}
+
+/// Returns the path of the bytecode file that the interpreter described by `python_info` creates
+/// for the given source file.
+///
+/// Python 3 stores bytecode in a `__pycache__` directory next to the source file (PEP 3147),
+/// Python 2 stores it directly next to the source file.
+pub fn pyc_path(source: &Path, python_info: &PythonInfo) -> PathBuf {
+    let (major, minor) = python_info.short_version;
+    if major < 3 {
+        return source.with_extension("pyc");
+    }
+
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    source
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join("__pycache__")
+        .join(format!("{stem}.cpython-{major}{minor}.pyc"))
+}
+
+/// Compiles the Python source files, relative to the `target_prefix`, with the interpreter of the
+/// prefix. The files are split into batches that are compiled by separate interpreter processes.
+///
+/// Files that fail to compile, for instance because they contain syntax that is not supported by
+/// the interpreter, are skipped. Returns the paths of the bytecode files that were created,
+/// relative to the `target_prefix`.
+pub async fn compile_pyc(
+    target_prefix: &Path,
+    python_info: &PythonInfo,
+    sources: &[PathBuf],
+    options: &PycCompilation,
+) -> Result<Vec<PathBuf>, PycCompileError> {
+    let python = target_prefix.join(python_info.path());
+    stream::iter(sources.chunks(options.batch_size.max(1)))
+        .map(|batch| compile_batch(&python, target_prefix, batch, options.timeout))
+        .buffer_unordered(options.max_concurrent_processes.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    Ok(sources
+        .iter()
+        .map(|source| pyc_path(source, python_info))
+        .filter(|pyc| target_prefix.join(pyc).is_file())
+        .collect())
+}
+
+/// Compiles a single batch of source files with a new interpreter process.
+async fn compile_batch(
+    python: &Path,
+    target_prefix: &Path,
+    batch: &[PathBuf],
+    timeout: Option<Duration>,
+) -> Result<(), PycCompileError> {
+    let mut child = tokio::process::Command::new(python)
+        .args(["-Wi", "-m", "compileall", "-q", "-l", "-i", "-"])
+        .current_dir(target_prefix)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| PycCompileError::FailedToSpawn(python.to_path_buf(), e))?;
+
+    // Pass the files to compile through stdin, one file per line.
+    let mut file_list = String::new();
+    for source in batch {
+        file_list.push_str(&target_prefix.join(source).to_string_lossy());
+        file_list.push('\n');
+    }
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin
+        .write_all(file_list.as_bytes())
+        .await
+        .map_err(PycCompileError::IoError)?;
+    drop(stdin);
+
+    let output = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, child.wait_with_output())
+            .await
+            .map_err(|_elapsed| PycCompileError::Timeout(timeout))?,
+        None => child.wait_with_output().await,
+    }
+    .map_err(PycCompileError::IoError)?;
+
+    // `compileall` exits with a non-zero exit code if any of the files failed to compile. This is
+    // not an error because packages often contain files that are not meant for this version of
+    // Python.
+    if !output.status.success() {
+        tracing::debug!(
+            "some python files could not be compiled: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::pyc_path;
+    use crate::install::PythonInfo;
+    use rattler_conda_types::{Platform, Version};
+    use std::path::Path;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_pyc_path() {
+        let python_info =
+            PythonInfo::from_version(&Version::from_str("3.11.4").unwrap(), Platform::Linux64)
+                .unwrap();
+        assert_eq!(
+            pyc_path(
+                Path::new("lib/python3.11/site-packages/foo/bar.py"),
+                &python_info
+            ),
+            Path::new("lib/python3.11/site-packages/foo/__pycache__/bar.cpython-311.pyc")
+        );
+
+        let python_info =
+            PythonInfo::from_version(&Version::from_str("2.7").unwrap(), Platform::Linux64)
+                .unwrap();
+        assert_eq!(
+            pyc_path(
+                Path::new("lib/python2.7/site-packages/foo/bar.py"),
+                &python_info
+            ),
+            Path::new("lib/python2.7/site-packages/foo/bar.pyc")
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use super::link::is_valid_shebang_length;
 use rattler_conda_types::{Platform, Version};
 use std::borrow::Cow;
 use std::path::{Path, PathBuf};
@@ -73,19 +74,22 @@ impl PythonInfo {
     }
 
     /// Constructs a shebang that will run the rest of the script as Python.
+    ///
+    /// Shebangs cannot exceed a platform specific length and executables with spaces are
+    /// problematic. In these cases a shebang is returned that runs `/bin/sh` which in turn executes
+    /// the script with the Python interpreter.
     pub fn shebang(&self, target_prefix: &str) -> String {
         let target_path = Path::new(target_prefix).join(self.path());
         let target_path = target_path.as_os_str().to_string_lossy().replace('\\', "/");
 
-        // Shebangs cannot be larger than 127 characters and executables with spaces are
-        // problematic.
-        if target_path.len() > 127 - 2 || target_path.contains(' ') {
+        let shebang = format!("#!{}", &target_path);
+        if !is_valid_shebang_length(&shebang, &self.platform) || target_path.contains(' ') {
             format!(
                 "#!/bin/sh\n'''exec' \"{}\" \"$0\" \"$@\" #'''",
                 &target_path
             )
         } else {
-            format!("#!{}", &target_path)
+            shebang
         }
     }
 
@@ -0,0 +1,623 @@
+//! Functions to clone an environment to a new location and to repair an environment that was
+//! moved.
+//!
+//! When a package is linked into a prefix, the prefix placeholders in its files are replaced with
+//! the path of the prefix. The `conda-meta` records of the prefix describe which files were
+//! modified this way. This information is used to replace the old prefix with the new one.
+
+use std::{
+    borrow::Cow,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use futures::future::try_join_all;
+use rattler_conda_types::{
+    package::FileMode,
+    prefix_record::{Link, PathType, PathsEntry},
+    PackageRecord, Platform, PrefixRecord,
+};
+use rattler_digest::Sha256;
+
+use super::{
+    apple_codesign::codesign, link::copy_and_replace_placeholders, link_package_with_link_type,
+    InstallDriver, InstallError, InstallOptions, PythonInfo,
+};
+
+/// An error that can occur while cloning or relocating an environment.
+#[derive(Debug, thiserror::Error)]
+pub enum RelocateError {
+    /// The `conda-meta` records of the prefix could not be read.
+    #[error("failed to read the packages installed in '{}'", .0.display())]
+    FailedToReadPrefixRecords(PathBuf, #[source] std::io::Error),
+
+    /// A prefix is not valid UTF-8.
+    #[error("the prefix '{}' is not UTF-8", .0.display())]
+    PrefixIsNotUtf8(PathBuf),
+
+    /// The python version of the environment could not be determined.
+    #[error("failed to determine the python version of the environment")]
+    InvalidPythonVersion(#[source] super::python::PythonInfoError),
+
+    /// A file could not be copied or rewritten.
+    #[error("failed to relocate '{}'", .0.display())]
+    FailedToRelocateFile(PathBuf, #[source] std::io::Error),
+
+    /// The new prefix does not fit in the place of the old prefix in a binary file.
+    #[error(
+        "cannot relocate '{}' because the new prefix is longer than the old prefix",
+        .0.display()
+    )]
+    BinaryPrefixTooLong(PathBuf),
+
+    /// A package could not be linked from the package cache.
+    #[error("failed to link {0}")]
+    LinkError(String, #[source] InstallError),
+
+    /// The `conda-meta` record of a package could not be written.
+    #[error("failed to write the prefix record of {0}")]
+    FailedToWritePrefixRecord(String, #[source] std::io::Error),
+}
+
+fn prefix_to_str(prefix: &Path) -> Result<&str, RelocateError> {
+    prefix
+        .to_str()
+        .ok_or_else(|| RelocateError::PrefixIsNotUtf8(prefix.to_path_buf()))
+}
+
+/// Repairs an environment that was moved from `old_prefix` to `prefix`. All references to the
+/// old prefix in files that had their prefix placeholder replaced, in python entry points and in
+/// the targets of symbolic links are replaced with the new prefix. The `conda-meta` records are
+/// updated with the new hashes of the modified files.
+///
+/// Like when linking, the prefix in binary files is not replaced on Windows and the new prefix
+/// must not be longer than the old prefix on other platforms.
+pub fn relocate_prefix(
+    prefix: &Path,
+    old_prefix: &Path,
+    platform: Platform,
+) -> Result<Vec<PrefixRecord>, RelocateError> {
+    let old_prefix = prefix_to_str(old_prefix)?;
+    let new_prefix = prefix_to_str(prefix)?;
+    let mut records = PrefixRecord::collect_from_prefix(prefix)
+        .map_err(|e| RelocateError::FailedToReadPrefixRecords(prefix.to_path_buf(), e))?;
+
+    // Make sure all binaries can be relocated before anything is modified.
+    for record in &records {
+        for entry in &record.paths_data.paths {
+            check_binary_prefix(prefix, entry, old_prefix, new_prefix, platform)?;
+        }
+    }
+
+    for record in &mut records {
+        for entry in &mut record.paths_data.paths {
+            rewrite_prefix(prefix, entry, old_prefix, new_prefix, platform)?;
+        }
+        write_prefix_record(prefix, record)?;
+    }
+
+    Ok(records)
+}
+
+/// Clones the environment at `source_prefix` to `target_prefix`.
+///
+/// Packages that are still available in the package cache they were originally linked from are
+/// linked again from the cache, using the `options` for every package. The files of other
+/// packages are copied from the source prefix and references to the source prefix are replaced
+/// like [`relocate_prefix`] does. Menu shortcuts are not cloned.
+///
+/// Returns the records of the packages in the new environment.
+pub async fn clone_prefix(
+    source_prefix: &Path,
+    target_prefix: &Path,
+    driver: &InstallDriver,
+    options: InstallOptions,
+) -> Result<Vec<PrefixRecord>, RelocateError> {
+    prefix_to_str(source_prefix)?;
+    prefix_to_str(target_prefix)?;
+    let records = PrefixRecord::collect_from_prefix(source_prefix)
+        .map_err(|e| RelocateError::FailedToReadPrefixRecords(source_prefix.to_path_buf(), e))?;
+
+    let platform = options.platform.unwrap_or_else(Platform::current);
+    let python_info = match options.python_info.clone() {
+        Some(python_info) => Some(python_info),
+        None => records
+            .iter()
+            .find(|r| r.repodata_record.package_record.name.as_normalized() == "python")
+            .map(|r| PythonInfo::from_version(&r.repodata_record.package_record.version, platform))
+            .transpose()
+            .map_err(RelocateError::InvalidPythonVersion)?,
+    };
+    let options = InstallOptions {
+        python_info,
+        platform: Some(platform),
+        ..options
+    };
+
+    let cloned = try_join_all(records.iter().map(|record| {
+        clone_record(
+            source_prefix,
+            target_prefix,
+            record,
+            driver,
+            options.clone(),
+        )
+    }))
+    .await?;
+
+    // Packages that are linked from the cache might have clobbered each other, resolve this the
+    // same way an installation does.
+    let sorted_records = PackageRecord::sort_topologically(cloned.iter().collect::<Vec<_>>());
+    driver
+        .clobber_registry()
+        .unclobber(&sorted_records, target_prefix)
+        .map_err(|e| RelocateError::FailedToRelocateFile(target_prefix.to_path_buf(), e))?;
+
+    Ok(cloned)
+}
+
+/// Clones a single package to the target prefix and writes its `conda-meta` record.
+async fn clone_record(
+    source_prefix: &Path,
+    target_prefix: &Path,
+    record: &PrefixRecord,
+    driver: &InstallDriver,
+    options: InstallOptions,
+) -> Result<PrefixRecord, RelocateError> {
+    let name = record.repodata_record.file_name.clone();
+    let package_dir = record
+        .extracted_package_dir
+        .clone()
+        .or_else(|| record.link.as_ref().map(|link| link.source.clone()))
+        .filter(|dir| dir.join("info/index.json").is_file());
+
+    let mut cloned = if let Some(package_dir) = package_dir {
+        let (mut paths, link_type) =
+            link_package_with_link_type(&package_dir, target_prefix, driver, options)
+                .await
+                .map_err(|e| RelocateError::LinkError(name.clone(), e))?;
+
+        // Compiled python files are not part of the package, copy them from the source prefix.
+        for entry in record
+            .paths_data
+            .paths
+            .iter()
+            .filter(|entry| entry.path_type == PathType::PycFile)
+        {
+            let target_path = target_prefix.join(&entry.relative_path);
+            let result = target_path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::copy(source_prefix.join(&entry.relative_path), &target_path));
+            match result {
+                Ok(_) => paths.push(entry.clone()),
+                Err(e) => tracing::warn!("failed to copy '{}': {e}", entry.relative_path.display()),
+            }
+        }
+
+        PrefixRecord::from_repodata_record(
+            record.repodata_record.clone(),
+            record.package_tarball_full_path.clone(),
+            Some(package_dir.clone()),
+            paths,
+            record.requested_spec.clone(),
+            Some(Link {
+                source: package_dir,
+                link_type: Some(link_type),
+            }),
+        )
+    } else {
+        let source_prefix = source_prefix.to_path_buf();
+        let target_prefix = target_prefix.to_path_buf();
+        let record = record.clone();
+        let platform = options.platform.unwrap_or_else(Platform::current);
+        tokio::task::spawn_blocking(move || {
+            copy_record(&source_prefix, &target_prefix, record, platform)
+        })
+        .await
+        .map_err(|e| match e.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(_) => RelocateError::LinkError(name.clone(), InstallError::Cancelled),
+        })??
+    };
+
+    cloned.installed_system_menus.clear();
+    write_prefix_record(target_prefix, &cloned)?;
+    Ok(cloned)
+}
+
+/// Copies the files of a package from the source prefix and replaces the references to the source
+/// prefix.
+fn copy_record(
+    source_prefix: &Path,
+    target_prefix: &Path,
+    mut record: PrefixRecord,
+    platform: Platform,
+) -> Result<PrefixRecord, RelocateError> {
+    let old_prefix = prefix_to_str(source_prefix)?;
+    let new_prefix = prefix_to_str(target_prefix)?;
+
+    // Make sure all binaries can be relocated before anything is copied.
+    for entry in &record.paths_data.paths {
+        check_binary_prefix(source_prefix, entry, old_prefix, new_prefix, platform)?;
+    }
+
+    for entry in &mut record.paths_data.paths {
+        let source_path = source_prefix.join(&entry.relative_path);
+        let target_path = target_prefix.join(&entry.relative_path);
+        let copy_error = |e| RelocateError::FailedToRelocateFile(entry.relative_path.clone(), e);
+
+        if entry.path_type == PathType::Directory {
+            fs::create_dir_all(&target_path).map_err(copy_error)?;
+            continue;
+        }
+        if let Some(parent) = target_path.parent() {
+            fs::create_dir_all(parent).map_err(copy_error)?;
+        }
+
+        if source_path.is_symlink() {
+            let link_target = fs::read_link(&source_path).map_err(copy_error)?;
+            symlink(&link_target, &target_path).map_err(copy_error)?;
+        } else {
+            fs::copy(&source_path, &target_path).map_err(copy_error)?;
+        }
+        rewrite_prefix(target_prefix, entry, old_prefix, new_prefix, platform)?;
+    }
+
+    Ok(record)
+}
+
+/// Returns the mode in which the prefix is replaced in the file described by `entry`, or `None` if
+/// the file does not contain the prefix.
+fn relocation_file_mode(entry: &PathsEntry) -> Option<FileMode> {
+    match entry.path_type {
+        PathType::UnixPythonEntryPoint | PathType::WindowsPythonEntryPointScript => {
+            Some(FileMode::Text)
+        }
+        _ => entry.file_mode,
+    }
+}
+
+/// Fails if the file described by `entry` is a binary that contains the `old_prefix` and the
+/// `new_prefix` does not fit in its place.
+fn check_binary_prefix(
+    prefix: &Path,
+    entry: &PathsEntry,
+    old_prefix: &str,
+    new_prefix: &str,
+    platform: Platform,
+) -> Result<(), RelocateError> {
+    if relocation_file_mode(entry) != Some(FileMode::Binary)
+        || platform.is_windows()
+        || new_prefix.len() <= old_prefix.len()
+    {
+        return Ok(());
+    }
+
+    let path = prefix.join(&entry.relative_path);
+    if path.is_symlink() {
+        return Ok(());
+    }
+    let contents = fs::read(&path)
+        .map_err(|e| RelocateError::FailedToRelocateFile(entry.relative_path.clone(), e))?;
+    if memchr::memmem::find(&contents, old_prefix.as_bytes()).is_some() {
+        return Err(RelocateError::BinaryPrefixTooLong(
+            entry.relative_path.clone(),
+        ));
+    }
+    Ok(())
+}
+
+/// Replaces the `old_prefix` with the `new_prefix` in the file described by `entry`. The hash and
+/// size of the entry are updated if the file was modified.
+fn rewrite_prefix(
+    prefix: &Path,
+    entry: &mut PathsEntry,
+    old_prefix: &str,
+    new_prefix: &str,
+    platform: Platform,
+) -> Result<(), RelocateError> {
+    let path = prefix.join(&entry.relative_path);
+    let relocate_error = |e| RelocateError::FailedToRelocateFile(entry.relative_path.clone(), e);
+
+    // Symbolic links that point into the old prefix are recreated.
+    if path.is_symlink() {
+        let link_target = fs::read_link(&path).map_err(relocate_error)?;
+        if let Ok(rest) = link_target.strip_prefix(old_prefix) {
+            fs::remove_file(&path).map_err(relocate_error)?;
+            symlink(&Path::new(new_prefix).join(rest), &path).map_err(relocate_error)?;
+        }
+        return Ok(());
+    }
+
+    let Some(file_mode) = relocation_file_mode(entry) else {
+        return Ok(());
+    };
+    check_binary_prefix(prefix, entry, old_prefix, new_prefix, platform)?;
+
+    // On Windows the prefix is written to text files with forward slashes, see `link_file`.
+    let (old_prefix, new_prefix) = if platform.is_windows() && file_mode == FileMode::Text {
+        (
+            Cow::Owned(old_prefix.replace('\\', "/")),
+            Cow::Owned(new_prefix.replace('\\', "/")),
+        )
+    } else {
+        (Cow::Borrowed(old_prefix), Cow::Borrowed(new_prefix))
+    };
+
+    let source = fs::read(&path).map_err(relocate_error)?;
+    if memchr::memmem::find(&source, old_prefix.as_bytes()).is_none() {
+        return Ok(());
+    }
+
+    let mut contents = Vec::with_capacity(source.len());
+    copy_and_replace_placeholders(
+        &source,
+        &mut contents,
+        &old_prefix,
+        &new_prefix,
+        &platform,
+        file_mode,
+    )
+    .map_err(relocate_error)?;
+
+    // Write the new contents to a temporary file first. Other files might be hard linked to the
+    // existing file.
+    let permissions = fs::metadata(&path).map_err(relocate_error)?.permissions();
+    let parent = path.parent().unwrap_or(prefix);
+    let mut temp_file = tempfile::NamedTempFile::new_in(parent).map_err(relocate_error)?;
+    temp_file.write_all(&contents).map_err(relocate_error)?;
+    std::fs::set_permissions(temp_file.path(), permissions).map_err(relocate_error)?;
+    temp_file
+        .persist(&path)
+        .map_err(|e| relocate_error(e.error))?;
+
+    // Modified binaries have to be signed again on Apple Silicon.
+    if platform == Platform::OsxArm64 && file_mode == FileMode::Binary {
+        if let Err(e) = codesign(&path) {
+            tracing::warn!("failed to sign '{}': {e}", entry.relative_path.display());
+        }
+    }
+
+    let contents = fs::read(&path).map_err(relocate_error)?;
+    entry.sha256_in_prefix = Some(rattler_digest::compute_bytes_digest::<Sha256>(&contents));
+    entry.size_in_bytes = Some(contents.len() as u64);
+    Ok(())
+}
+
+fn write_prefix_record(prefix: &Path, record: &PrefixRecord) -> Result<(), RelocateError> {
+    let conda_meta = prefix.join("conda-meta");
+    fs::create_dir_all(&conda_meta)
+        .and_then(|()| record.write_to_path(conda_meta.join(record.file_name()), true))
+        .map_err(|e| {
+            RelocateError::FailedToWritePrefixRecord(record.repodata_record.file_name.clone(), e)
+        })
+}
+
+fn symlink(source_path: &Path, destination_path: &Path) -> std::io::Result<()> {
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(source_path, destination_path);
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(source_path, destination_path);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clone_prefix, relocate_prefix, RelocateError};
+    use crate::install::{
+        test_utils::build_package, InstallDriver, InstallOptions, Installer, LinkPolicy,
+    };
+    use crate::package_cache::PackageCache;
+    use rattler_conda_types::{
+        package::FileMode,
+        prefix_record::{LinkType, PathType, PathsEntry},
+        PackageName, PackageRecord, Platform, PrefixRecord, RepoDataRecord,
+    };
+    use std::path::{Path, PathBuf};
+
+    fn paths_entry(path: &str, path_type: PathType, file_mode: Option<FileMode>) -> PathsEntry {
+        PathsEntry {
+            relative_path: PathBuf::from(path),
+            original_path: None,
+            path_type,
+            no_link: false,
+            sha256: None,
+            sha256_in_prefix: None,
+            size_in_bytes: None,
+            file_mode,
+            prefix_placeholder: file_mode.map(|_| "/placeholder".to_string()),
+        }
+    }
+
+    /// Creates a prefix at `prefix` that looks like it was installed at `old_prefix`.
+    fn create_moved_prefix(prefix: &Path, old_prefix: &str) {
+        std::fs::create_dir_all(prefix.join("bin")).unwrap();
+        std::fs::create_dir_all(prefix.join("lib")).unwrap();
+        std::fs::write(
+            prefix.join("bin/foo"),
+            format!("#!{old_prefix}/bin/python\nprint('{old_prefix}')\n"),
+        )
+        .unwrap();
+        std::fs::write(
+            prefix.join("lib/libfoo.so"),
+            format!("\x7fELF{old_prefix}/lib\0rest"),
+        )
+        .unwrap();
+
+        let record = PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record: PackageRecord::new(
+                    PackageName::new_unchecked("foo"),
+                    "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+                    "0".to_string(),
+                ),
+                file_name: "foo-1.0-0.conda".to_string(),
+                url: "https://example.com/foo-1.0-0.conda".parse().unwrap(),
+                channel: "https://example.com".to_string(),
+            },
+            None,
+            None,
+            vec![
+                paths_entry("bin/foo", PathType::HardLink, Some(FileMode::Text)),
+                paths_entry("lib/libfoo.so", PathType::HardLink, Some(FileMode::Binary)),
+            ],
+            None,
+            None,
+        );
+        std::fs::create_dir_all(prefix.join("conda-meta")).unwrap();
+        record
+            .write_to_path(prefix.join("conda-meta").join(record.file_name()), true)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_relocate_prefix() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        // The old prefix has to be longer than the new prefix to fit in the binary.
+        let old_prefix = format!("/{}", "x".repeat(prefix.as_os_str().len()));
+        let old_prefix = old_prefix.as_str();
+        create_moved_prefix(prefix, old_prefix);
+
+        let records = relocate_prefix(prefix, Path::new(old_prefix), Platform::Linux64).unwrap();
+
+        let new_prefix = prefix.to_str().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(prefix.join("bin/foo")).unwrap(),
+            format!("#!{new_prefix}/bin/python\nprint('{new_prefix}')\n")
+        );
+        let binary = std::fs::read(prefix.join("lib/libfoo.so")).unwrap();
+        assert_eq!(binary.len(), 4 + old_prefix.len() + 4 + 1 + 4);
+        assert!(binary.starts_with(format!("\x7fELF{new_prefix}/lib\0").as_bytes()));
+        assert!(binary.ends_with(b"\0rest"));
+
+        // The records, also the ones on disk, are updated with the new hashes.
+        assert_eq!(PrefixRecord::collect_from_prefix(prefix).unwrap(), records);
+        for (entry, path) in records[0]
+            .paths_data
+            .paths
+            .iter()
+            .zip(["bin/foo", "lib/libfoo.so"])
+        {
+            let contents = std::fs::read(prefix.join(path)).unwrap();
+            assert_eq!(entry.size_in_bytes, Some(contents.len() as u64));
+            assert_eq!(
+                entry.sha256_in_prefix,
+                Some(rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(&contents))
+            );
+        }
+    }
+
+    #[test]
+    fn test_relocate_windows_text_prefix() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        // Text files on Windows contain the prefix with forward slashes.
+        create_moved_prefix(prefix, "C:/Users/me/old-env");
+
+        relocate_prefix(prefix, Path::new("C:\\Users\\me\\old-env"), Platform::Win64).unwrap();
+
+        let new_prefix = prefix.to_str().unwrap().replace('\\', "/");
+        assert_eq!(
+            std::fs::read_to_string(prefix.join("bin/foo")).unwrap(),
+            format!("#!{new_prefix}/bin/python\nprint('{new_prefix}')\n")
+        );
+    }
+
+    #[test]
+    fn test_relocate_binary_prefix_too_long() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        create_moved_prefix(prefix, "/p");
+        assert!(matches!(
+            relocate_prefix(prefix, Path::new("/p"), Platform::Linux64),
+            Err(RelocateError::BinaryPrefixTooLong(_))
+        ));
+
+        // Nothing is modified, also not the text file that could be relocated.
+        assert_eq!(
+            std::fs::read_to_string(prefix.join("bin/foo")).unwrap(),
+            "#!/p/bin/python\nprint('/p')\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clone_prefix_without_cache() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source_prefix = temp_dir.path().join("a-long-name-for-the-source-prefix");
+        let target_prefix = temp_dir.path().join("target");
+        create_moved_prefix(&source_prefix, source_prefix.to_str().unwrap());
+
+        let records = clone_prefix(
+            &source_prefix,
+            &target_prefix,
+            &InstallDriver::default(),
+            InstallOptions {
+                platform: Some(Platform::Linux64),
+                ..InstallOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(records.len(), 1);
+
+        let target = target_prefix.to_str().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(target_prefix.join("bin/foo")).unwrap(),
+            format!("#!{target}/bin/python\nprint('{target}')\n")
+        );
+        assert_eq!(
+            PrefixRecord::collect_from_prefix(&target_prefix)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // The source prefix is left untouched.
+        let source = source_prefix.to_str().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(source_prefix.join("bin/foo")).unwrap(),
+            format!("#!{source}/bin/python\nprint('{source}')\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clone_prefix_records_link_type() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let source_prefix = temp_dir.path().join("source");
+        let target_prefix = temp_dir.path().join("target");
+        let package = build_package(
+            temp_dir.path(),
+            "foo",
+            "1.0",
+            &[],
+            &[("share/foo.txt", "foo")],
+        );
+        Installer::new()
+            .with_package_cache(PackageCache::new(temp_dir.path().join("pkgs")))
+            .install(&source_prefix, [package])
+            .await
+            .unwrap();
+
+        let records = clone_prefix(
+            &source_prefix,
+            &target_prefix,
+            &InstallDriver::default(),
+            InstallOptions {
+                link_policy: LinkPolicy::always_copy(),
+                ..InstallOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(target_prefix.join("share/foo.txt")).unwrap(),
+            "foo"
+        );
+        assert_eq!(
+            records[0].link.as_ref().and_then(|link| link.link_type),
+            Some(LinkType::Copy)
+        );
+    }
+}
@@ -0,0 +1,56 @@
+//! Verification of package signatures during installation.
+
+use super::InstallError;
+use rattler_package_streaming::signing::{verify_package_directory, TrustedKeys};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Controls the behavior of the [`super::link_package`] function when the signature of a package
+/// cannot be verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureVerificationPolicy {
+    /// Do not verify signatures, the same as leaving
+    /// [`super::InstallOptions::signature_verification`] unset
+    Ignore,
+    /// Log a warning if the signature of a package cannot be verified
+    Warn,
+    /// Fail the installation if the signature of a package cannot be verified (default)
+    #[default]
+    Enforce,
+}
+
+/// Describes how the signatures of packages should be verified. See
+/// [`super::InstallOptions::signature_verification`].
+#[derive(Debug, Clone)]
+pub struct SignatureVerification {
+    /// What to do when a signature cannot be verified.
+    pub policy: SignatureVerificationPolicy,
+
+    /// The keys that are trusted to sign packages. These are usually obtained by verifying the
+    /// delegations of a channel with [`TrustedKeys::fetch`].
+    pub trusted_keys: Arc<TrustedKeys>,
+}
+
+impl SignatureVerification {
+    /// Verifies the signature of the extracted package at `package_dir` and applies the policy.
+    pub(crate) fn verify(&self, package_dir: &Path) -> Result<(), InstallError> {
+        if self.policy == SignatureVerificationPolicy::Ignore {
+            return Ok(());
+        }
+
+        match verify_package_directory(package_dir, &self.trusted_keys) {
+            Ok(()) => Ok(()),
+            Err(e) if self.policy == SignatureVerificationPolicy::Warn => {
+                tracing::warn!(
+                    "failed to verify the signature of {}: {e}",
+                    package_dir.display()
+                );
+                Ok(())
+            }
+            Err(e) => Err(InstallError::SignatureVerificationFailed(
+                package_dir.to_path_buf(),
+                e,
+            )),
+        }
+    }
+}
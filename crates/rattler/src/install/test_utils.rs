@@ -40,6 +40,7 @@ pub async fn install_package_to_environment(
         paths_data: paths.into(),
         requested_spec: None,
         link: None,
+        installed_system_menus: Vec::new(),
     };
 
     // Create the conda-meta directory if it doesnt exist yet.
@@ -149,3 +150,64 @@ pub fn find_prefix_record<'a>(
         .iter()
         .find(|r| r.repodata_record.package_record.name.as_normalized() == name)
 }
+
+/// Builds a `.tar.bz2` package archive in `dir` that contains the given files and returns its
+/// path. The `info/index.json` and `info/paths.json` files are generated.
+pub fn build_package(
+    dir: &Path,
+    name: &str,
+    version: &str,
+    depends: &[&str],
+    files: &[(&str, &str)],
+) -> PathBuf {
+    let staging = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(staging.path().join("info")).unwrap();
+    std::fs::write(
+        staging.path().join("info/index.json"),
+        serde_json::json!({
+            "name": name,
+            "version": version,
+            "build": "0",
+            "build_number": 0,
+            "depends": depends,
+            "subdir": "noarch",
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let mut paths = vec![
+        staging.path().join("info/index.json"),
+        staging.path().join("info/paths.json"),
+    ];
+    let mut paths_json = Vec::new();
+    for (path, contents) in files {
+        let file_path = staging.path().join(path);
+        std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        std::fs::write(&file_path, contents).unwrap();
+        paths.push(file_path);
+        paths_json.push(serde_json::json!({
+            "_path": path,
+            "path_type": "hardlink",
+            "sha256": format!("{:x}", rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(contents)),
+            "size_in_bytes": contents.len(),
+        }));
+    }
+    std::fs::write(
+        staging.path().join("info/paths.json"),
+        serde_json::json!({ "paths_version": 1, "paths": paths_json }).to_string(),
+    )
+    .unwrap();
+
+    let archive_path = dir.join(format!("{name}-{version}-0.tar.bz2"));
+    rattler_package_streaming::write::write_tar_bz2_package(
+        std::fs::File::create(&archive_path).unwrap(),
+        staging.path(),
+        &paths,
+        rattler_package_streaming::write::CompressionLevel::Default,
+        None,
+        None,
+    )
+    .unwrap();
+    archive_path
+}
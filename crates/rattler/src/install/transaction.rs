@@ -1,8 +1,8 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::install::python::PythonInfoError;
 use crate::install::PythonInfo;
-use rattler_conda_types::{PackageRecord, Platform};
+use rattler_conda_types::{DependencyGraph, PackageName, PackageRecord, Platform};
 
 /// Error that occurred during creation of a Transaction
 #[derive(Debug, thiserror::Error)]
@@ -48,6 +48,20 @@ impl<Old: AsRef<New>, New> TransactionOperation<Old, New> {
     }
 }
 
+impl<Old: AsRef<PackageRecord>, New: AsRef<PackageRecord>> TransactionOperation<Old, New> {
+    /// Returns the record of the package this operation applies to.
+    fn package_record(&self) -> &PackageRecord {
+        match self {
+            TransactionOperation::Install(new) | TransactionOperation::Change { new, .. } => {
+                new.as_ref()
+            }
+            TransactionOperation::Reinstall(old) | TransactionOperation::Remove(old) => {
+                old.as_ref()
+            }
+        }
+    }
+}
+
 impl<Old, New> TransactionOperation<Old, New> {
     /// Returns the record of the package to remove for this operation. If this operation does not
     /// refer to an removable package, `None` is returned.
@@ -63,7 +77,10 @@ impl<Old, New> TransactionOperation<Old, New> {
 
 /// Describes the operations to perform to bring an environment from one state into another.
 pub struct Transaction<Old, New> {
-    /// A list of operations to update an environment
+    /// A list of operations to update an environment. Removals come first, ordered such that
+    /// packages are removed before their dependencies. They are followed by the other operations,
+    /// ordered such that dependencies are installed before the packages that depend on them. See
+    /// [`Transaction::sort_operations`].
     pub operations: Vec<TransactionOperation<Old, New>>,
 
     /// The python version of the target state, or None if python doesnt exist in the environment.
@@ -133,17 +150,14 @@ impl<Old: AsRef<PackageRecord>, New: AsRef<PackageRecord>> Transaction<Old, New>
             .map(|r| r.as_ref().name.clone())
             .collect::<HashSet<_>>();
 
-        // Remove all current packages that are not in desired (but keep order of current)
+        // Remove all current packages that are not in desired
         for record in current_iter {
             if !desired_names.contains(&record.as_ref().name) {
                 operations.push(TransactionOperation::Remove(record));
             }
         }
 
-        // reverse all removals, last in first out
-        operations.reverse();
-
-        // Figure out the operations to perform, but keep the order of the original "desired" iterator
+        // Figure out the operations to perform
         for record in desired_iter {
             let name = &record.as_ref().name;
             let old_record = current_map.remove(name);
@@ -166,13 +180,68 @@ impl<Old: AsRef<PackageRecord>, New: AsRef<PackageRecord>> Transaction<Old, New>
             }
         }
 
-        Ok(Self {
+        let mut transaction = Self {
             operations,
             python_info: desired_python_info,
             current_python_info,
             platform,
-        })
+        };
+        transaction.sort_operations();
+        Ok(transaction)
     }
+
+    /// Sorts the operations topologically based on the dependencies between the packages they
+    /// apply to. Removals are ordered first, packages before their dependencies, so that
+    /// pre-unlink scripts can still use their dependencies. All other operations follow,
+    /// dependencies before the packages that depend on them, so that post-link scripts and entry
+    /// points can rely on their dependencies being present.
+    ///
+    /// This is done automatically by [`Transaction::from_current_and_desired`] but should be
+    /// called again after modifying [`Transaction::operations`].
+    pub fn sort_operations(&mut self) {
+        let removal_ranks = topological_ranks(
+            self.operations
+                .iter()
+                .filter(|operation| matches!(operation, TransactionOperation::Remove(_)))
+                .map(TransactionOperation::package_record),
+        );
+        let install_ranks = topological_ranks(
+            self.operations
+                .iter()
+                .filter(|operation| !matches!(operation, TransactionOperation::Remove(_)))
+                .map(TransactionOperation::package_record),
+        );
+
+        self.operations.sort_by_key(|operation| {
+            let name = &operation.package_record().name;
+            match operation {
+                TransactionOperation::Remove(_) => (0, usize::MAX - removal_ranks[name]),
+                _ => (1, install_ranks[name]),
+            }
+        });
+    }
+}
+
+/// Returns the position of every package in a topological ordering of the records, dependencies
+/// first. The ordering uses the dependency edges that match the records and falls back to an
+/// ordering that breaks cycles if there are any.
+fn topological_ranks<'a>(
+    records: impl IntoIterator<Item = &'a PackageRecord>,
+) -> HashMap<PackageName, usize> {
+    let records = records.into_iter().collect::<Vec<_>>();
+    let graph = DependencyGraph::from_records(records.iter().copied());
+    let sorted = match graph.topological_order() {
+        Ok(sorted) => sorted.into_iter().copied().collect(),
+        Err(err) => {
+            tracing::debug!("{err}, falling back to an order that breaks cycles");
+            PackageRecord::sort_topologically(records)
+        }
+    };
+    sorted
+        .into_iter()
+        .enumerate()
+        .map(|(rank, record)| (record.name.clone(), rank))
+        .collect()
 }
 
 /// Determine the version of Python used by a set of packages. Returns `None` if none of the
@@ -211,3 +280,77 @@ fn describe_same_content(from: &PackageRecord, to: &PackageRecord) -> bool {
     // Otherwise, just check that the name, version and build string match
     from.name == to.name && from.version == to.version && from.build == to.build
 }
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, Version};
+
+    use super::{Transaction, TransactionOperation};
+
+    fn record(name: &str, version: &str, depends: &[&str]) -> PackageRecord {
+        PackageRecord {
+            depends: depends.iter().map(ToString::to_string).collect(),
+            ..PackageRecord::new(
+                PackageName::new_unchecked(name),
+                Version::from_str(version).unwrap(),
+                String::from("0"),
+            )
+        }
+    }
+
+    fn describe(operation: &TransactionOperation<PackageRecord, PackageRecord>) -> String {
+        match operation {
+            TransactionOperation::Install(new) => format!("+{}", new.name.as_normalized()),
+            TransactionOperation::Change { new, .. } => format!("~{}", new.name.as_normalized()),
+            TransactionOperation::Reinstall(old) => format!("={}", old.name.as_normalized()),
+            TransactionOperation::Remove(old) => format!("-{}", old.name.as_normalized()),
+        }
+    }
+
+    #[test]
+    fn test_operations_are_ordered_topologically() {
+        let current = vec![
+            record("libzlib", "1.2", &[]),
+            record("openssl", "3.0", &["libzlib"]),
+            record("curl", "8.0", &["openssl", "libzlib"]),
+            record("git", "2.0", &["curl"]),
+        ];
+        let desired = vec![
+            record("app", "1.0", &["openssl >=3.1", "libzlib"]),
+            record("openssl", "3.1", &["libzlib"]),
+            record("libzlib", "1.2", &[]),
+        ];
+
+        let transaction =
+            Transaction::from_current_and_desired(current, desired, Platform::Linux64).unwrap();
+        assert_eq!(
+            transaction
+                .operations
+                .iter()
+                .map(describe)
+                .collect::<Vec<_>>(),
+            ["-git", "-curl", "~openssl", "+app"]
+        );
+    }
+
+    #[test]
+    fn test_sort_operations_with_cycle() {
+        let mut transaction = Transaction::<PackageRecord, PackageRecord> {
+            operations: vec![
+                TransactionOperation::Install(record("python", "3.12", &["pip"])),
+                TransactionOperation::Install(record("app", "1.0", &["python"])),
+                TransactionOperation::Install(record("pip", "24.0", &["python"])),
+            ],
+            python_info: None,
+            current_python_info: None,
+            platform: Platform::Linux64,
+        };
+        transaction.sort_operations();
+        assert_eq!(
+            transaction.operations.last().map(describe).as_deref(),
+            Some("+app")
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Deletion of files that may be in use.
+//!
+//! On Windows a file that is opened by a running process, like the executable of a running
+//! program or a loaded DLL, cannot be deleted. It can however be renamed. Files that cannot be
+//! deleted are therefore moved to a trash directory inside the prefix, which is on the same volume
+//! so renaming is always possible. The trash directory is purged at the start of the next
+//! transaction, when the processes that used the files have most likely exited.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+/// The directory, relative to the prefix, that holds files that could not be deleted.
+pub const TRASH_DIR: &str = ".trash";
+
+/// Returns the trash directory of the given prefix.
+pub fn trash_directory(prefix: &Path) -> PathBuf {
+    prefix.join(TRASH_DIR)
+}
+
+/// Moves the file or directory at `path` to the trash directory of the prefix. The entry is given
+/// a unique name so entries with the same file name never collide.
+pub fn move_to_trash(prefix: &Path, path: &Path) -> std::io::Result<()> {
+    let trash_dir = trash_directory(prefix);
+    fs::create_dir_all(&trash_dir)?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let trash_path = trash_dir.join(format!("{}-{file_name}", uuid::Uuid::new_v4().simple()));
+    fs::rename(path, trash_path)
+}
+
+/// Deletes the file at `path`. If the file is in use on Windows it is moved to the trash
+/// directory of the prefix instead. A file that doesn't exist is not an error.
+pub fn remove_file_or_trash(prefix: &Path, path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) if cfg!(windows) => {
+            tracing::debug!("{e}, moving it to the trash instead");
+            move_to_trash(prefix, path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Deletes the directory at `path` and all its contents. Files that are in use on Windows are
+/// moved to the trash directory of the prefix.
+pub fn remove_dir_all_or_trash(prefix: &Path, path: &Path) -> std::io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) if !cfg!(windows) => return Err(e),
+        Err(_) => {}
+    }
+
+    // Move the files that could not be deleted out of the way and try again.
+    for entry in walkdir::WalkDir::new(path).contents_first(true) {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            let _ = std::fs::remove_dir(entry.path());
+        } else {
+            remove_file_or_trash(prefix, entry.path())?;
+        }
+    }
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Deletes the entries of the trash directory of the prefix. Entries that are still in use are
+/// kept and retried during the next purge. Returns the number of entries that could not be
+/// deleted.
+pub fn purge_trash(prefix: &Path) -> std::io::Result<usize> {
+    let trash_dir = trash_directory(prefix);
+    let entries = match fs::read_dir(&trash_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut remaining = 0;
+    for entry in entries {
+        let entry = entry?;
+        let result = if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())
+        } else {
+            fs::remove_file(entry.path())
+        };
+        if let Err(e) = result {
+            tracing::debug!("failed to purge {}: {e}", entry.path().display());
+            remaining += 1;
+        }
+    }
+
+    if remaining == 0 {
+        fs::remove_dir(&trash_dir)?;
+    }
+
+    Ok(remaining)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{move_to_trash, purge_trash, remove_file_or_trash, trash_directory};
+
+    #[test]
+    fn test_trash() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        for name in ["a.dll", "b.exe"] {
+            std::fs::write(prefix.join(name), "content").unwrap();
+        }
+
+        move_to_trash(prefix, &prefix.join("a.dll")).unwrap();
+        remove_file_or_trash(prefix, &prefix.join("b.exe")).unwrap();
+        remove_file_or_trash(prefix, &prefix.join("missing.txt")).unwrap();
+        assert!(!prefix.join("a.dll").exists());
+        assert!(!prefix.join("b.exe").exists());
+        assert_eq!(
+            std::fs::read_dir(trash_directory(prefix)).unwrap().count(),
+            1
+        );
+
+        assert_eq!(purge_trash(prefix).unwrap(), 0);
+        assert!(!trash_directory(prefix).exists());
+        assert_eq!(purge_trash(prefix).unwrap(), 0);
+    }
+}
@@ -2,7 +2,6 @@
 
 use std::{
     collections::HashSet,
-    io::ErrorKind,
     path::{Path, PathBuf},
 };
 
@@ -103,20 +102,28 @@ pub async fn unlink_package(
     target_prefix: &Path,
     prefix_record: &PrefixRecord,
 ) -> Result<(), UnlinkError> {
-    // Remove all entries
+    // Remove all entries. Files that are in use on Windows are moved to the trash of the prefix
+    // instead.
     for paths in prefix_record.paths_data.paths.iter() {
-        match tokio::fs::remove_file(target_prefix.join(&paths.relative_path)).await {
-            Ok(_) => {}
-            Err(e) if e.kind() == ErrorKind::NotFound => {
-                // Simply ignore if the file is already gone.
-            }
-            Err(e) => {
-                return Err(UnlinkError::FailedToDeleteFile(
+        let prefix = target_prefix.to_path_buf();
+        let path = target_prefix.join(&paths.relative_path);
+        tokio::task::spawn_blocking(move || super::trash::remove_file_or_trash(&prefix, &path))
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+            .map_err(|e| {
+                UnlinkError::FailedToDeleteFile(
                     paths.relative_path.to_string_lossy().to_string(),
                     e,
-                ))
-            }
-        }
+                )
+            })?;
+    }
+
+    // Remove the menu shortcuts that were created outside of the prefix
+    if let Err(e) = super::menuinst::remove_menu_items(prefix_record) {
+        tracing::warn!(
+            "failed to remove the menu items of {}: {e}",
+            prefix_record.repodata_record.file_name
+        );
     }
 
     // Remove the conda-meta file
@@ -12,10 +12,17 @@
 
 use std::path::PathBuf;
 
+#[cfg(feature = "lock-file")]
+pub mod bundle;
 #[cfg(feature = "cli-tools")]
 pub mod cli;
+pub mod disk_usage;
+pub mod download;
 pub mod install;
+pub mod pack;
 pub mod package_cache;
+pub mod prefix;
+pub mod sbom;
 pub mod validation;
 
 /// A helper function that returns a [`Channel`] instance that points to an empty channel on disk
@@ -38,11 +45,12 @@ pub(crate) fn get_test_data_dir() -> PathBuf {
     std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data")
 }
 
-/// Returns the default cache directory used by rattler.
+/// Returns the default cache directory used by rattler. This ignores the configuration, use
+/// [`rattler_config::Config::cache_dir`] to respect it.
 pub fn default_cache_dir() -> anyhow::Result<PathBuf> {
-    Ok(dirs::cache_dir()
-        .ok_or_else(|| anyhow::anyhow!("could not determine cache directory for current platform"))?
-        .join("rattler/cache"))
+    rattler_config::Config::default()
+        .cache_dir()
+        .ok_or_else(|| anyhow::anyhow!("could not determine cache directory for current platform"))
 }
 
 #[cfg(test)]
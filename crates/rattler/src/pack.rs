@@ -0,0 +1,481 @@
+//! Packing of an installed prefix into a relocatable archive, similar to `conda-pack`. See
+//! [`pack`] and [`unpack`].
+//!
+//! The archive is a zstd compressed tarball that contains all the files of the packages that are
+//! installed in the prefix, together with the `conda-meta` directory. Hard links and links into the
+//! package cache are resolved, so the archive is self-contained and can be unpacked on a machine
+//! without network access.
+//!
+//! Files that contain the path of the prefix are made relocatable. In text files the prefix is
+//! replaced with [`PREFIX_TEMPLATE`]. Binary files cannot be rewritten without knowing the final
+//! location, so they keep the original prefix which is replaced when the archive is unpacked. This
+//! requires that the new prefix is not longer than the original one.
+//!
+//! The archive can be unpacked and relocated with [`unpack`]. For unix platforms the archive also
+//! contains a `bin/rattler-unpack` script that relocates the environment after the archive has been
+//! extracted with `tar`. The script requires `perl`.
+
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use rattler_conda_types::{package::FileMode, Platform};
+use rattler_conda_types::{prefix_record::PathType, PrefixRecord};
+use serde::{Deserialize, Serialize};
+
+use crate::install::link::{
+    copy_and_replace_cstring_placeholder, copy_and_replace_textual_placeholder,
+};
+
+/// The text that replaces the path of the prefix in packed text files.
+pub const PREFIX_TEMPLATE: &str = "/__rattler_pack_prefix_placeholder__";
+
+/// The path of the manifest in the archive that describes how to relocate the files.
+const MANIFEST_PATH: &str = ".rattler-pack.json";
+
+/// The path of the script that relocates an unpacked environment on unix platforms.
+const UNPACK_SCRIPT_PATH: &str = "bin/rattler-unpack";
+
+/// Options to configure [`pack`].
+#[derive(Debug, Clone)]
+pub struct PackOptions {
+    /// The platform of the packages in the prefix. This determines how files are relocated.
+    pub platform: Platform,
+
+    /// The zstd compression level of the archive.
+    pub compression_level: i32,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            platform: Platform::current(),
+            compression_level: 15,
+        }
+    }
+}
+
+/// An error that can occur while packing or unpacking an environment.
+#[derive(Debug, thiserror::Error)]
+pub enum PackError {
+    /// The records of the installed packages could not be read.
+    #[error("failed to read the packages installed in the prefix")]
+    ReadRecords(#[source] std::io::Error),
+
+    /// A file could not be added to the archive.
+    #[error("failed to pack '{0}'")]
+    PackFile(PathBuf, #[source] std::io::Error),
+
+    /// The archive could not be written.
+    #[error("failed to write the archive")]
+    WriteArchive(#[source] std::io::Error),
+
+    /// The archive could not be extracted.
+    #[error("failed to extract the archive")]
+    ExtractArchive(#[source] std::io::Error),
+
+    /// The archive was not created by [`pack`].
+    #[error("the archive does not contain a valid manifest")]
+    InvalidManifest(#[source] std::io::Error),
+
+    /// A file could not be relocated to the new prefix.
+    #[error("failed to relocate '{0}'")]
+    RelocateFile(PathBuf, #[source] std::io::Error),
+}
+
+/// Describes the files in a packed environment that refer to the original prefix.
+#[derive(Debug, Serialize, Deserialize)]
+struct PackManifest {
+    /// The path of the prefix that was packed.
+    original_prefix: String,
+
+    /// The platform of the packages in the prefix.
+    platform: Platform,
+
+    /// Text files in which the prefix was replaced with [`PREFIX_TEMPLATE`].
+    text_files: Vec<PathBuf>,
+
+    /// Binary files that still contain the original prefix.
+    binary_files: Vec<PathBuf>,
+}
+
+/// Packs the environment installed in `prefix` into a relocatable `.tar.zst` archive that is
+/// written to `writer`. See the [module documentation](self) for more information.
+pub fn pack(prefix: &Path, writer: impl Write, options: &PackOptions) -> Result<(), PackError> {
+    let original_prefix = prefix.to_string_lossy().into_owned();
+    let mut records = PrefixRecord::collect_from_prefix(prefix).map_err(PackError::ReadRecords)?;
+    records.sort_by(|a, b| {
+        a.repodata_record
+            .package_record
+            .name
+            .cmp(&b.repodata_record.package_record.name)
+    });
+
+    let encoder =
+        zstd::Encoder::new(writer, options.compression_level).map_err(PackError::WriteArchive)?;
+    let mut archive = tar::Builder::new(encoder);
+    archive.follow_symlinks(false);
+
+    let mut manifest = PackManifest {
+        original_prefix: original_prefix.clone(),
+        platform: options.platform,
+        text_files: Vec::new(),
+        binary_files: Vec::new(),
+    };
+
+    // Add the records of the installed packages.
+    archive
+        .append_dir_all("conda-meta", prefix.join("conda-meta"))
+        .map_err(|e| PackError::PackFile(PathBuf::from("conda-meta"), e))?;
+
+    for record in &records {
+        for entry in &record.paths_data.paths {
+            let relative_path = &entry.relative_path;
+            let path = prefix.join(relative_path);
+            let metadata =
+                fs::symlink_metadata(&path).map_err(|e| PackError::PackFile(path.clone(), e))?;
+
+            // Keep relative symlinks, they point to files in the environment. Absolute symlinks
+            // point into the package cache and are resolved below.
+            if metadata.is_symlink() {
+                let target =
+                    fs::read_link(&path).map_err(|e| PackError::PackFile(path.clone(), e))?;
+                if target.is_relative() {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    header.set_mode(0o777);
+                    archive
+                        .append_link(&mut header, relative_path, &target)
+                        .map_err(|e| PackError::PackFile(path.clone(), e))?;
+                    continue;
+                }
+            }
+
+            let metadata = fs::metadata(&path).map_err(|e| PackError::PackFile(path.clone(), e))?;
+            let mut header = tar::Header::new_gnu();
+            header.set_metadata_in_mode(&metadata, tar::HeaderMode::Deterministic);
+            if metadata.is_dir() {
+                archive
+                    .append_data(&mut header, relative_path, std::io::empty())
+                    .map_err(|e| PackError::PackFile(path.clone(), e))?;
+                continue;
+            }
+
+            let contents = fs::read(&path).map_err(|e| PackError::PackFile(path.clone(), e))?;
+            let file_mode = match (entry.path_type, entry.prefix_placeholder.as_ref()) {
+                (PathType::UnixPythonEntryPoint, _) => Some(FileMode::Text),
+                (_, Some(_)) => Some(entry.file_mode.unwrap_or(FileMode::Text)),
+                (_, None) => None,
+            };
+            let contents = match file_mode {
+                Some(FileMode::Text) => {
+                    manifest.text_files.push(relative_path.clone());
+                    let mut relocated = Vec::with_capacity(contents.len());
+                    copy_and_replace_textual_placeholder(
+                        &contents,
+                        &mut relocated,
+                        &original_prefix,
+                        PREFIX_TEMPLATE,
+                        &options.platform,
+                    )
+                    .map_err(|e| PackError::PackFile(path.clone(), e))?;
+                    relocated
+                }
+                Some(FileMode::Binary) => {
+                    manifest.binary_files.push(relative_path.clone());
+                    contents
+                }
+                None => contents,
+            };
+
+            header.set_size(contents.len() as u64);
+            archive
+                .append_data(&mut header, relative_path, contents.as_slice())
+                .map_err(|e| PackError::PackFile(path.clone(), e))?;
+        }
+    }
+
+    if options.platform.is_unix() {
+        append_generated_file(
+            &mut archive,
+            UNPACK_SCRIPT_PATH,
+            unpack_script(&manifest).as_bytes(),
+            0o755,
+        )?;
+    }
+    let manifest = serde_json::to_vec_pretty(&manifest).expect("the manifest is serializable");
+    append_generated_file(&mut archive, MANIFEST_PATH, &manifest, 0o644)?;
+
+    archive
+        .into_inner()
+        .and_then(zstd::Encoder::finish)
+        .and_then(|mut writer| writer.flush())
+        .map_err(PackError::WriteArchive)
+}
+
+/// Adds a file that does not exist in the prefix to the archive.
+fn append_generated_file(
+    archive: &mut tar::Builder<impl Write>,
+    path: &str,
+    contents: &[u8],
+    mode: u32,
+) -> Result<(), PackError> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(contents.len() as u64);
+    header.set_mode(mode);
+    archive
+        .append_data(&mut header, path, contents)
+        .map_err(|e| PackError::PackFile(PathBuf::from(path), e))
+}
+
+/// Returns a shell script that relocates the environment to the directory it is unpacked in.
+fn unpack_script(manifest: &PackManifest) -> String {
+    fn quote(value: &str) -> String {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+    fn lines(paths: &[PathBuf]) -> String {
+        paths
+            .iter()
+            .map(|path| format!("{}\n", path.display()))
+            .collect()
+    }
+
+    format!(
+        r#"#!/bin/sh
+# Relocates an environment that was packed by rattler to the directory it was unpacked in.
+set -e
+
+prefix="$(cd "$(dirname "$0")/.." && pwd)"
+template={template}
+original={original}
+
+while IFS= read -r file; do
+    perl -pi -e 'BEGIN {{ $old = shift; $new = shift }} s/\Q$old\E/$new/g' "$template" "$prefix" "$prefix/$file"
+done <<'EOF'
+{text_files}EOF
+
+# Binary files contain nul-terminated strings that must keep their length.
+while IFS= read -r file; do
+    perl -0777 -pi -e 'BEGIN {{ $old = shift; $new = shift }} s/(\Q$old\E[^\0]*)/my $s = $1; my $l = length $s; $s =~ s{{\Q$old\E}}{{$new}}g; length($s) > $l ? substr($s, 0, $l) : $s . ("\0" x ($l - length $s))/ge' "$original" "$prefix" "$prefix/$file"
+done <<'EOF'
+{binary_files}EOF
+"#,
+        template = quote(PREFIX_TEMPLATE),
+        original = quote(&manifest.original_prefix),
+        text_files = lines(&manifest.text_files),
+        binary_files = lines(&manifest.binary_files),
+    )
+}
+
+/// Extracts an archive that was created with [`pack`] into `target_prefix` and relocates the
+/// files that refer to the original prefix.
+pub fn unpack(archive: impl Read, target_prefix: &Path) -> Result<(), PackError> {
+    let decoder = zstd::Decoder::new(archive).map_err(PackError::ExtractArchive)?;
+    tar::Archive::new(decoder)
+        .unpack(target_prefix)
+        .map_err(PackError::ExtractArchive)?;
+
+    let manifest_path = target_prefix.join(MANIFEST_PATH);
+    let manifest: PackManifest = fs::read(&manifest_path)
+        .and_then(|contents| serde_json::from_slice(&contents).map_err(Into::into))
+        .map_err(PackError::InvalidManifest)?;
+
+    let new_prefix = target_prefix.to_string_lossy();
+    for relative_path in &manifest.text_files {
+        relocate_file(target_prefix, relative_path, |contents, destination| {
+            copy_and_replace_textual_placeholder(
+                contents,
+                destination,
+                PREFIX_TEMPLATE,
+                &new_prefix,
+                &manifest.platform,
+            )
+        })?;
+    }
+
+    // conda does not replace the prefix in binary files on windows, neither do we.
+    if !manifest.platform.is_windows() {
+        for relative_path in &manifest.binary_files {
+            relocate_file(target_prefix, relative_path, |contents, destination| {
+                copy_and_replace_cstring_placeholder(
+                    contents,
+                    destination,
+                    &manifest.original_prefix,
+                    &new_prefix,
+                )
+            })?;
+        }
+    }
+
+    // The environment has been relocated, the manifest and the script are no longer needed.
+    fs::remove_file(&manifest_path).map_err(PackError::InvalidManifest)?;
+    let script_path = target_prefix.join(UNPACK_SCRIPT_PATH);
+    if script_path.is_file() {
+        fs::remove_file(&script_path).map_err(|e| PackError::RelocateFile(script_path, e))?;
+    }
+
+    Ok(())
+}
+
+/// Rewrites the contents of a file in place.
+fn relocate_file(
+    prefix: &Path,
+    relative_path: &Path,
+    replace: impl FnOnce(&[u8], &mut Vec<u8>) -> std::io::Result<()>,
+) -> Result<(), PackError> {
+    let path = prefix.join(relative_path);
+    let contents = fs::read(&path).map_err(|e| PackError::RelocateFile(path.clone(), e))?;
+    let mut relocated = Vec::with_capacity(contents.len());
+    replace(&contents, &mut relocated).map_err(|e| PackError::RelocateFile(path.clone(), e))?;
+    fs::write(&path, relocated).map_err(|e| PackError::RelocateFile(path, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pack, unpack, PackOptions, PREFIX_TEMPLATE};
+    use rattler_conda_types::Platform;
+    use std::path::Path;
+
+    /// Creates a prefix with a single package that contains a text file and a binary file that
+    /// refer to the prefix, a file that is hard linked and a relative symlink.
+    fn create_prefix(prefix: &Path) {
+        let prefix_str = prefix.to_string_lossy();
+        std::fs::create_dir_all(prefix.join("conda-meta")).unwrap();
+        std::fs::create_dir_all(prefix.join("bin")).unwrap();
+        std::fs::create_dir_all(prefix.join("lib")).unwrap();
+
+        std::fs::write(
+            prefix.join("bin/tool"),
+            format!("#!{prefix_str}/bin/sh\necho {prefix_str}/share\n"),
+        )
+        .unwrap();
+        let mut binary = b"\x7fELF\0".to_vec();
+        binary.extend_from_slice(format!("{prefix_str}/lib:/usr/lib\0\0\0\0tail").as_bytes());
+        std::fs::write(prefix.join("lib/libfoo.so.1"), binary).unwrap();
+        std::fs::write(prefix.join("lib/data.txt"), "data").unwrap();
+        std::fs::hard_link(
+            prefix.join("lib/data.txt"),
+            prefix.join("lib/data-link.txt"),
+        )
+        .unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("libfoo.so.1", prefix.join("lib/libfoo.so")).unwrap();
+
+        let mut paths = vec![
+            serde_json::json!({ "_path": "bin/tool", "path_type": "hardlink", "file_mode": "text", "prefix_placeholder": "/opt/placeholder" }),
+            serde_json::json!({ "_path": "lib/libfoo.so.1", "path_type": "hardlink", "file_mode": "binary", "prefix_placeholder": "/opt/placeholder" }),
+            serde_json::json!({ "_path": "lib/data.txt", "path_type": "hardlink" }),
+            serde_json::json!({ "_path": "lib/data-link.txt", "path_type": "hardlink" }),
+        ];
+        if cfg!(unix) {
+            paths.push(serde_json::json!({ "_path": "lib/libfoo.so", "path_type": "softlink" }));
+        }
+        let record = serde_json::json!({
+            "name": "foo",
+            "version": "1.0",
+            "build": "0",
+            "build_number": 0,
+            "depends": [],
+            "fn": "foo-1.0-0.conda",
+            "url": "https://conda.anaconda.org/conda-forge/linux-64/foo-1.0-0.conda",
+            "channel": "https://conda.anaconda.org/conda-forge",
+            "subdir": "linux-64",
+            "files": paths.iter().map(|path| path["_path"].clone()).collect::<Vec<_>>(),
+            "paths_data": { "paths_version": 1, "paths": paths },
+        });
+        std::fs::write(
+            prefix.join("conda-meta/foo-1.0-0.json"),
+            serde_json::to_string(&record).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let root = tempfile::tempdir().unwrap();
+        let original = root.path().join("env-a");
+        let relocated = root.path().join("env-b");
+        create_prefix(&original);
+
+        let options = PackOptions {
+            platform: Platform::Linux64,
+            ..PackOptions::default()
+        };
+        let mut archive = Vec::new();
+        pack(&original, &mut archive, &options).unwrap();
+
+        // Inspect the archive before it is relocated.
+        let inspect = root.path().join("env-c");
+        tar::Archive::new(zstd::Decoder::new(archive.as_slice()).unwrap())
+            .unpack(&inspect)
+            .unwrap();
+        let tool = std::fs::read_to_string(inspect.join("bin/tool")).unwrap();
+        assert_eq!(
+            tool,
+            format!("#!{PREFIX_TEMPLATE}/bin/sh\necho {PREFIX_TEMPLATE}/share\n")
+        );
+        let script = std::fs::read_to_string(inspect.join("bin/rattler-unpack")).unwrap();
+        assert!(script.contains("bin/tool\n"));
+        assert!(script.contains("lib/libfoo.so.1\n"));
+
+        // The script relocates the extracted archive, if perl is available.
+        #[cfg(unix)]
+        if std::process::Command::new("perl")
+            .arg("-v")
+            .output()
+            .is_ok_and(|output| output.status.success())
+        {
+            let status = std::process::Command::new("sh")
+                .arg(inspect.join("bin/rattler-unpack"))
+                .status()
+                .unwrap();
+            assert!(status.success());
+            let inspect_str = inspect.to_string_lossy();
+            assert_eq!(
+                std::fs::read_to_string(inspect.join("bin/tool")).unwrap(),
+                format!("#!{inspect_str}/bin/sh\necho {inspect_str}/share\n")
+            );
+            let binary = std::fs::read(inspect.join("lib/libfoo.so.1")).unwrap();
+            let expected = std::fs::read_to_string(original.join("lib/libfoo.so.1"))
+                .unwrap()
+                .replace(&*original.to_string_lossy(), &inspect_str);
+            assert_eq!(binary, expected.as_bytes());
+        }
+
+        unpack(archive.as_slice(), &relocated).unwrap();
+        let relocated_str = relocated.to_string_lossy();
+        assert_eq!(
+            std::fs::read_to_string(relocated.join("bin/tool")).unwrap(),
+            format!("#!{relocated_str}/bin/sh\necho {relocated_str}/share\n")
+        );
+
+        // The binary keeps its length and refers to the new prefix.
+        let binary = std::fs::read(relocated.join("lib/libfoo.so.1")).unwrap();
+        assert_eq!(
+            binary.len(),
+            std::fs::read(original.join("lib/libfoo.so.1"))
+                .unwrap()
+                .len()
+        );
+        assert!(memchr::memmem::find(&binary, format!("{relocated_str}/lib").as_bytes()).is_some());
+
+        // Hard links are resolved into regular files.
+        assert_eq!(
+            std::fs::read_to_string(relocated.join("lib/data-link.txt")).unwrap(),
+            "data"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_link(relocated.join("lib/libfoo.so")).unwrap(),
+            Path::new("libfoo.so.1")
+        );
+
+        assert!(relocated.join("conda-meta/foo-1.0-0.json").is_file());
+        assert!(!relocated.join(".rattler-pack.json").exists());
+        assert!(!relocated.join("bin/rattler-unpack").exists());
+    }
+}
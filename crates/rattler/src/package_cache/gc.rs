@@ -0,0 +1,514 @@
+//! Garbage collection of the [`PackageCache`].
+//!
+//! Every time a package is linked into a prefix the files in the cache are hard linked, soft
+//! linked or copied into the prefix. Once no prefix uses a package anymore its directory in the
+//! cache is no longer required. To be able to determine which packages are still in use the cache
+//! keeps a registry of the prefixes it populated, see [`PackageCache::register_prefix`]. The
+//! `conda-meta` records of these prefixes describe which package directories they were linked
+//! from.
+//!
+//! On unix, files that still have more than one hard link are also considered to be in use. This
+//! catches prefixes that are not in the registry, for instance because they were created by
+//! another tool.
+
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use fs_err as fs;
+use fslock::LockFile;
+use rattler_conda_types::PrefixRecord;
+
+use super::{lock_file_path, PackageCache, TEMP_DIR_PREFIX};
+use crate::disk_usage::DiskUsageReport;
+
+/// The name of the file, relative to the root of the cache, that contains the registered
+/// prefixes. Every line contains the absolute path of a prefix.
+pub const PREFIX_REGISTRY_FILE: &str = ".prefixes.txt";
+
+/// Options that control which packages are removed by [`PackageCache::collect_garbage`].
+#[derive(Debug, Clone)]
+pub struct GarbageCollectionOptions {
+    /// Unreferenced packages that have not been modified for at least this long are removed.
+    /// `None` means packages are never removed because of their age. Defaults to
+    /// [`Duration::ZERO`] which removes all unreferenced packages.
+    pub max_age: Option<Duration>,
+
+    /// If the total size of the cache in bytes exceeds this budget, the oldest unreferenced
+    /// packages are removed until the cache fits in the budget. Referenced packages are never
+    /// removed so the cache can still exceed the budget.
+    pub max_size: Option<u64>,
+
+    /// Package directories that are incomplete, because a download or extraction was interrupted,
+    /// are removed once they are older than this. Younger directories are left alone because they
+    /// might still be written to.
+    pub partial_download_age: Duration,
+
+    /// Whether files with more than one hard link mark a package as in use. Only supported on
+    /// unix.
+    pub detect_hardlinks: bool,
+
+    /// Prefixes, in addition to the registered prefixes, whose packages are in use.
+    pub additional_prefixes: Vec<PathBuf>,
+
+    /// Only determine what would be removed, without removing anything.
+    pub dry_run: bool,
+}
+
+impl Default for GarbageCollectionOptions {
+    fn default() -> Self {
+        Self {
+            max_age: Some(Duration::ZERO),
+            max_size: None,
+            partial_download_age: Duration::from_secs(24 * 60 * 60),
+            detect_hardlinks: true,
+            additional_prefixes: Vec::new(),
+            dry_run: false,
+        }
+    }
+}
+
+/// Describes what was removed by [`PackageCache::collect_garbage`]. With
+/// [`GarbageCollectionOptions::dry_run`] it describes what would have been removed.
+#[derive(Debug, Clone, Default)]
+pub struct GarbageCollectionReport {
+    /// All the complete package directories in the cache, including the removed ones, from
+    /// oldest to newest.
+    pub packages: Vec<PackageCacheEntry>,
+
+    /// The package directories that were removed.
+    pub removed_packages: Vec<PathBuf>,
+
+    /// The incomplete package directories that were removed.
+    pub removed_partial_downloads: Vec<PathBuf>,
+
+    /// The number of bytes that were freed.
+    pub freed_bytes: u64,
+
+    /// The size in bytes of the packages that remain in the cache.
+    pub remaining_bytes: u64,
+}
+
+/// A package directory in the cache.
+#[derive(Debug, Clone)]
+pub struct PackageCacheEntry {
+    /// The path of the package directory.
+    pub path: PathBuf,
+
+    /// The total size in bytes of the files in the directory.
+    pub size: u64,
+
+    /// The time the directory was last modified.
+    pub modified: SystemTime,
+
+    /// Whether the package is used by a prefix or by this process.
+    pub referenced: bool,
+
+    /// Whether the package was removed.
+    pub removed: bool,
+}
+
+impl PackageCache {
+    /// Records that packages from this cache are linked into the given prefix. The packages that
+    /// are used by registered prefixes are not removed by [`PackageCache::collect_garbage`].
+    pub fn register_prefix(&self, prefix: &Path) -> std::io::Result<()> {
+        let cache_dir = self.inner.lock().unwrap().path.clone();
+        let prefix = if prefix.is_absolute() {
+            prefix.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(prefix)
+        };
+
+        let mut prefixes = read_registered_prefixes(&cache_dir)?;
+        if prefixes.contains(&prefix) {
+            return Ok(());
+        }
+        prefixes.push(prefix);
+        write_registered_prefixes(&cache_dir, &prefixes)
+    }
+
+    /// Returns the prefixes that were registered with [`PackageCache::register_prefix`].
+    pub fn registered_prefixes(&self) -> std::io::Result<Vec<PathBuf>> {
+        let cache_dir = self.inner.lock().unwrap().path.clone();
+        read_registered_prefixes(&cache_dir)
+    }
+
+    /// Computes the disk usage of the cache and of the registered prefixes, accounting for the
+    /// files that are hard linked between them. The cache is the first directory of the report,
+    /// followed by the registered prefixes. See [`DiskUsageReport::compute`].
+    pub async fn disk_usage(&self) -> std::io::Result<DiskUsageReport> {
+        let cache_dir = self.inner.lock().unwrap().path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let prefixes = read_registered_prefixes(&cache_dir)?;
+            DiskUsageReport::compute(std::iter::once(cache_dir).chain(prefixes))
+        })
+        .await;
+        match result {
+            Ok(report) => report,
+            Err(err) => match err.try_into_panic() {
+                Ok(panic) => std::panic::resume_unwind(panic),
+                Err(_) => Err(std::io::Error::new(
+                    ErrorKind::Interrupted,
+                    "computing the disk usage was cancelled",
+                )),
+            },
+        }
+    }
+
+    /// Removes the packages from the cache that are no longer used by any prefix and the remains
+    /// of interrupted downloads. See [`GarbageCollectionOptions`] for which packages are
+    /// removed. Registered prefixes that no longer exist are removed from the registry.
+    ///
+    /// Packages that were returned by this instance are always considered to be in use.
+    pub async fn collect_garbage(
+        &self,
+        options: GarbageCollectionOptions,
+    ) -> std::io::Result<GarbageCollectionReport> {
+        let (cache_dir, in_use) = {
+            let inner = self.inner.lock().unwrap();
+            let in_use = inner
+                .packages
+                .keys()
+                .map(|key| inner.path.join(key.to_string()))
+                .collect::<HashSet<_>>();
+            (inner.path.clone(), in_use)
+        };
+
+        let result =
+            tokio::task::spawn_blocking(move || collect_garbage(&cache_dir, &in_use, &options))
+                .await;
+        match result {
+            Ok(report) => report,
+            Err(err) => match err.try_into_panic() {
+                Ok(panic) => std::panic::resume_unwind(panic),
+                Err(_) => Err(std::io::Error::new(
+                    ErrorKind::Interrupted,
+                    "garbage collection was cancelled",
+                )),
+            },
+        }
+    }
+}
+
+fn read_registered_prefixes(cache_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    match fs::read_to_string(cache_dir.join(PREFIX_REGISTRY_FILE)) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(PathBuf::from)
+            .collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_registered_prefixes(cache_dir: &Path, prefixes: &[PathBuf]) -> std::io::Result<()> {
+    fs::create_dir_all(cache_dir)?;
+    let mut contents = String::new();
+    for prefix in prefixes {
+        contents.push_str(&prefix.to_string_lossy());
+        contents.push('\n');
+    }
+
+    // Write to a temporary file first to make sure the registry is never left half written.
+    let temp_file = tempfile::NamedTempFile::new_in(cache_dir)?;
+    fs::write(temp_file.path(), contents)?;
+    temp_file
+        .persist(cache_dir.join(PREFIX_REGISTRY_FILE))
+        .map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Returns true if the name of a directory looks like a [`super::CacheKey`]
+/// (`name-version-build`).
+pub(super) fn is_package_directory_name(name: &str) -> bool {
+    !name.starts_with('.') && name.rsplitn(3, '-').count() == 3
+}
+
+/// Tries to acquire the lock of a package directory without blocking. Returns `None` if the
+/// package is locked by another process, which means it is currently being fetched or validated.
+pub(super) fn try_lock_package(path: &Path) -> Option<LockFile> {
+    let mut lock = LockFile::open(&lock_file_path(path)).ok()?;
+    lock.try_lock_with_pid().ok()?.then_some(lock)
+}
+
+/// Returns the canonical path if it can be determined, otherwise the path itself.
+fn canonicalize(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Returns the package directories that are used by the given prefix.
+fn referenced_package_dirs(prefix: &Path) -> std::io::Result<Vec<PathBuf>> {
+    Ok(PrefixRecord::collect_from_prefix(prefix)?
+        .into_iter()
+        .flat_map(|record| {
+            record
+                .extracted_package_dir
+                .into_iter()
+                .chain(record.link.map(|link| link.source))
+        })
+        .collect())
+}
+
+/// Returns true if any file in the directory has more than one hard link.
+#[cfg(unix)]
+fn has_hardlinked_files(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .any(|entry| entry.metadata().is_ok_and(|m| m.nlink() > 1))
+}
+
+#[cfg(not(unix))]
+fn has_hardlinked_files(_path: &Path) -> bool {
+    false
+}
+
+/// Returns the total size of the files in the directory.
+fn directory_size(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+fn collect_garbage(
+    cache_dir: &Path,
+    in_use: &HashSet<PathBuf>,
+    options: &GarbageCollectionOptions,
+) -> std::io::Result<GarbageCollectionReport> {
+    let mut report = GarbageCollectionReport::default();
+    if !cache_dir.is_dir() {
+        return Ok(report);
+    }
+
+    // Determine the packages that are referenced by the registered prefixes and drop the
+    // prefixes that no longer exist from the registry.
+    let registered = read_registered_prefixes(cache_dir)?;
+    let (existing, removed): (Vec<_>, Vec<_>) = registered
+        .into_iter()
+        .partition(|prefix| prefix.join("conda-meta").is_dir());
+    if !removed.is_empty() && !options.dry_run {
+        write_registered_prefixes(cache_dir, &existing)?;
+    }
+
+    let mut referenced = in_use
+        .iter()
+        .map(|p| canonicalize(p))
+        .collect::<HashSet<_>>();
+    for prefix in existing.iter().chain(&options.additional_prefixes) {
+        match referenced_package_dirs(prefix) {
+            Ok(dirs) => referenced.extend(dirs.iter().map(|p| canonicalize(p))),
+            Err(e) => tracing::warn!(
+                "failed to read the packages installed in {}: {e}",
+                prefix.display()
+            ),
+        }
+    }
+
+    let now = SystemTime::now();
+    let age = |modified: SystemTime| now.duration_since(modified).unwrap_or_default();
+
+    // Find all package directories and remove the incomplete ones.
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(cache_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        // Temporary directories of extractions that were interrupted.
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(TEMP_DIR_PREFIX) {
+            let path = entry.path();
+            if age(entry.metadata()?.modified()?) >= options.partial_download_age {
+                report.freed_bytes += directory_size(&path);
+                if !options.dry_run {
+                    fs::remove_dir_all(&path)?;
+                }
+                report.removed_partial_downloads.push(path);
+            }
+            continue;
+        }
+        if !is_package_directory_name(&name) {
+            continue;
+        }
+
+        let path = entry.path();
+        let mut cache_entry = PackageCacheEntry {
+            size: directory_size(&path),
+            modified: entry.metadata()?.modified()?,
+            path,
+            referenced: false,
+            removed: false,
+        };
+
+        if !cache_entry.path.join("info/index.json").is_file() {
+            if age(cache_entry.modified) >= options.partial_download_age {
+                let Some(_lock) = try_lock_package(&cache_entry.path) else {
+                    continue;
+                };
+                if !options.dry_run {
+                    fs::remove_dir_all(&cache_entry.path)?;
+                }
+                report.freed_bytes += cache_entry.size;
+                report.removed_partial_downloads.push(cache_entry.path);
+            }
+            continue;
+        }
+
+        cache_entry.referenced = referenced.contains(&canonicalize(&cache_entry.path))
+            || (options.detect_hardlinks && has_hardlinked_files(&cache_entry.path));
+        entries.push(cache_entry);
+    }
+
+    // Remove the oldest packages first.
+    entries.sort_by_key(|entry| entry.modified);
+    let mut total_size = entries.iter().map(|entry| entry.size).sum::<u64>();
+    for entry in entries.iter_mut().filter(|entry| !entry.referenced) {
+        let too_old = options
+            .max_age
+            .is_some_and(|max_age| age(entry.modified) >= max_age);
+        let over_budget = options
+            .max_size
+            .is_some_and(|max_size| total_size > max_size);
+        if too_old || over_budget {
+            // Skip packages that are currently being used by another process.
+            let Some(_lock) = try_lock_package(&entry.path) else {
+                continue;
+            };
+            if !options.dry_run {
+                fs::remove_dir_all(&entry.path)?;
+            }
+            entry.removed = true;
+            total_size -= entry.size;
+            report.freed_bytes += entry.size;
+            report.removed_packages.push(entry.path.clone());
+        }
+    }
+
+    report.packages = entries;
+    report.remaining_bytes = total_size;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GarbageCollectionOptions, PackageCache};
+    use std::{path::Path, time::Duration};
+
+    fn create_package(cache_dir: &Path, name: &str, size: usize) {
+        let package_dir = cache_dir.join(name);
+        std::fs::create_dir_all(package_dir.join("info")).unwrap();
+        std::fs::write(package_dir.join("info/index.json"), "{}").unwrap();
+        std::fs::write(package_dir.join("data.bin"), vec![0u8; size]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_collect_garbage() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_dir = cache_dir.path();
+        create_package(cache_dir, "foo-1.0-0", 100);
+        create_package(cache_dir, "bar-1.0-0", 100);
+        create_package(cache_dir, "linked-1.0-0", 100);
+        std::fs::create_dir_all(cache_dir.join("partial-1.0-0/info")).unwrap();
+
+        // Hard link a file of a package into a prefix.
+        let prefix = tempfile::tempdir().unwrap();
+        std::fs::hard_link(
+            cache_dir.join("linked-1.0-0/data.bin"),
+            prefix.path().join("data.bin"),
+        )
+        .unwrap();
+
+        let cache = PackageCache::new(cache_dir);
+
+        // A budget that fits two packages only removes a single package. Partial downloads are
+        // not removed because they are too young.
+        let report = cache
+            .collect_garbage(GarbageCollectionOptions {
+                max_age: None,
+                max_size: Some(250),
+                ..GarbageCollectionOptions::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.removed_packages.len(), 1);
+        assert!(report.removed_partial_downloads.is_empty());
+        assert!(cfg!(not(unix)) || cache_dir.join("linked-1.0-0").is_dir());
+
+        // A dry run reports the packages it would remove but leaves them in place.
+        let report = cache
+            .collect_garbage(GarbageCollectionOptions {
+                dry_run: true,
+                ..GarbageCollectionOptions::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.packages.len(), 2);
+        for entry in &report.packages {
+            assert_eq!(entry.removed, !entry.referenced);
+            assert!(entry.path.is_dir());
+        }
+
+        // Remove everything that is not referenced.
+        let report = cache
+            .collect_garbage(GarbageCollectionOptions {
+                partial_download_age: Duration::ZERO,
+                ..GarbageCollectionOptions::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.removed_partial_downloads.len(), 1);
+        assert!(!cache_dir.join("foo-1.0-0").exists());
+        assert!(!cache_dir.join("bar-1.0-0").exists());
+        if cfg!(unix) {
+            assert!(cache_dir.join("linked-1.0-0").is_dir());
+            assert_eq!(report.remaining_bytes, 102);
+        }
+    }
+
+    #[test]
+    fn test_register_prefix() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let prefix = tempfile::tempdir().unwrap();
+        let cache = PackageCache::new(cache_dir.path());
+        cache.register_prefix(prefix.path()).unwrap();
+        cache.register_prefix(prefix.path()).unwrap();
+        assert_eq!(
+            cache.registered_prefixes().unwrap(),
+            vec![prefix.path().to_path_buf()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disk_usage() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let prefix = tempfile::tempdir().unwrap();
+        create_package(cache_dir.path(), "linked-1.0-0", 100);
+        std::fs::hard_link(
+            cache_dir.path().join("linked-1.0-0/data.bin"),
+            prefix.path().join("data.bin"),
+        )
+        .unwrap();
+        let cache = PackageCache::new(cache_dir.path());
+        cache.register_prefix(prefix.path()).unwrap();
+
+        let report = cache.disk_usage().await.unwrap();
+        assert_eq!(report.directories.len(), 2);
+        assert_eq!(report.directories[0].path, cache_dir.path());
+        assert_eq!(report.directories[0].shared_bytes, 100);
+        assert_eq!(report.directories[1].total_bytes, 100);
+        assert_eq!(report.directories[1].unique_bytes, 0);
+        assert_eq!(report.total_bytes, report.directories[0].total_bytes);
+    }
+}
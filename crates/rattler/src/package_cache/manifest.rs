@@ -0,0 +1,318 @@
+//! Checksum manifests of the packages in the [`PackageCache`].
+//!
+//! When a package is extracted into the cache the size and sha256 hash of every file in its
+//! directory are recorded in a manifest inside that directory. Unlike `info/paths.json` the
+//! manifest covers all files, also those of old packages that do not record hashes, and it does
+//! not require the archive the package was extracted from. Before a cached package is used its
+//! files are checked against the manifest. This detects entries that were corrupted after they
+//! were extracted, e.g. by bit rot or by an interrupted write, before they are linked into another
+//! environment. [`PackageCache::verify`] and [`PackageCache::repair`] check the whole cache.
+
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use rattler_digest::{Sha256, Sha256Hash};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    gc::{is_package_directory_name, try_lock_package},
+    PackageCache, SHA256_FILE,
+};
+use crate::validation::{
+    validate_package_directory, PackageEntryValidationError, PackageValidationError,
+};
+
+/// The name of the file, inside a package directory, that contains the [`PackageManifest`].
+pub const MANIFEST_FILE: &str = ".manifest.json";
+
+/// The sizes and hashes of the files in a package directory of the cache.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageManifest {
+    /// The files of the package, relative to the package directory.
+    pub files: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+/// A single file in a [`PackageManifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The size of the file in bytes.
+    pub size: u64,
+
+    /// The sha256 hash of the file.
+    #[serde(
+        serialize_with = "rattler_digest::serde::serialize::<_, Sha256>",
+        deserialize_with = "rattler_digest::serde::deserialize::<_, Sha256>"
+    )]
+    pub sha256: Sha256Hash,
+}
+
+impl PackageManifest {
+    /// Computes the manifest of the regular files in a package directory. Symbolic links are
+    /// not followed.
+    pub fn from_package_directory(package_dir: &Path) -> std::io::Result<Self> {
+        let mut files = BTreeMap::new();
+        for entry in walkdir::WalkDir::new(package_dir).min_depth(1) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative_path = entry
+                .path()
+                .strip_prefix(package_dir)
+                .expect("walkdir only returns paths inside the directory")
+                .to_path_buf();
+            if relative_path == Path::new(MANIFEST_FILE) || relative_path == Path::new(SHA256_FILE)
+            {
+                continue;
+            }
+            let sha256 = rattler_digest::compute_file_digest::<Sha256>(entry.path())?;
+            files.insert(
+                relative_path,
+                ManifestEntry {
+                    size: entry.metadata()?.len(),
+                    sha256,
+                },
+            );
+        }
+        Ok(Self { files })
+    }
+
+    /// Reads the manifest that is stored in a package directory.
+    pub fn read(package_dir: &Path) -> std::io::Result<Self> {
+        let contents = fs::read(package_dir.join(MANIFEST_FILE))?;
+        serde_json::from_slice(&contents)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Stores the manifest in a package directory.
+    pub fn write(&self, package_dir: &Path) -> std::io::Result<()> {
+        let contents = serde_json::to_vec(self).map_err(std::io::Error::other)?;
+        fs::write(package_dir.join(MANIFEST_FILE), contents)
+    }
+
+    /// Checks that all files of the manifest exist in the package directory and have the recorded
+    /// size and hash. Files that are not part of the manifest are ignored.
+    pub fn validate(
+        &self,
+        package_dir: &Path,
+    ) -> Result<(), (PathBuf, PackageEntryValidationError)> {
+        for (relative_path, expected) in &self.files {
+            validate_manifest_entry(&package_dir.join(relative_path), expected)
+                .map_err(|e| (relative_path.clone(), e))?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_manifest_entry(
+    path: &Path,
+    expected: &ManifestEntry,
+) -> Result<(), PackageEntryValidationError> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(PackageEntryValidationError::NotFound)
+        }
+        Err(e) => return Err(PackageEntryValidationError::GetMetadataFailed(e)),
+    };
+    if metadata.len() != expected.size {
+        return Err(PackageEntryValidationError::IncorrectSize(
+            expected.size,
+            metadata.len(),
+        ));
+    }
+    let hash = rattler_digest::compute_file_digest::<Sha256>(path)?;
+    if hash != expected.sha256 {
+        return Err(PackageEntryValidationError::HashMismatch(
+            format!("{:x}", expected.sha256),
+            format!("{hash:x}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Validates the contents of a package directory in the cache against its manifest. Directories
+/// without a manifest, e.g. because they were extracted by an older version, are validated with
+/// their `info/paths.json` and a manifest is stored for them afterwards.
+pub(super) fn validate_cached_package(package_dir: &Path) -> Result<(), PackageValidationError> {
+    match PackageManifest::read(package_dir) {
+        Ok(manifest) => manifest
+            .validate(package_dir)
+            .map_err(|(path, e)| PackageValidationError::CorruptedEntry(path, e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            validate_package_directory(package_dir)?;
+            if let Err(e) = PackageManifest::from_package_directory(package_dir)
+                .and_then(|manifest| manifest.write(package_dir))
+            {
+                tracing::debug!(
+                    "failed to store the manifest of {}: {e}",
+                    package_dir.display()
+                );
+            }
+            Ok(())
+        }
+        Err(e) => Err(PackageValidationError::ReadManifestError(e)),
+    }
+}
+
+/// The result of [`PackageCache::verify`] and [`PackageCache::repair`].
+#[derive(Debug, Default)]
+pub struct CacheVerificationReport {
+    /// The package directories whose contents are intact.
+    pub valid: Vec<PathBuf>,
+
+    /// The package directories whose contents are corrupted, together with the reason. After a
+    /// [`PackageCache::repair`] these directories have been removed.
+    pub corrupted: Vec<(PathBuf, PackageValidationError)>,
+
+    /// The package directories that were skipped because another process is currently fetching
+    /// or validating them.
+    pub skipped: Vec<PathBuf>,
+}
+
+impl CacheVerificationReport {
+    /// Returns true if no corrupted packages were found.
+    pub fn is_valid(&self) -> bool {
+        self.corrupted.is_empty()
+    }
+}
+
+impl PackageCache {
+    /// Checks the contents of all packages in the cache against their manifests. Corrupted
+    /// packages are reported but not removed, see [`PackageCache::repair`].
+    pub async fn verify(&self) -> std::io::Result<CacheVerificationReport> {
+        self.verify_packages(false).await
+    }
+
+    /// Checks the contents of all packages in the cache against their manifests and removes the
+    /// packages that are corrupted, so they are fetched again the next time they are requested.
+    pub async fn repair(&self) -> std::io::Result<CacheVerificationReport> {
+        let report = self.verify_packages(true).await?;
+        if !report.corrupted.is_empty() {
+            let mut inner = self.inner.lock().unwrap();
+            let cache_dir = inner.path.clone();
+            inner.packages.retain(|key, _| {
+                let path = cache_dir.join(key.to_string());
+                !report
+                    .corrupted
+                    .iter()
+                    .any(|(corrupted, _)| *corrupted == path)
+            });
+        }
+        Ok(report)
+    }
+
+    async fn verify_packages(
+        &self,
+        remove_corrupted: bool,
+    ) -> std::io::Result<CacheVerificationReport> {
+        let cache_dir = self.inner.lock().unwrap().path.clone();
+        let result =
+            tokio::task::spawn_blocking(move || verify_packages(&cache_dir, remove_corrupted))
+                .await;
+        match result {
+            Ok(report) => report,
+            Err(err) => match err.try_into_panic() {
+                Ok(panic) => std::panic::resume_unwind(panic),
+                Err(_) => Err(std::io::Error::new(
+                    ErrorKind::Interrupted,
+                    "verification was cancelled",
+                )),
+            },
+        }
+    }
+}
+
+fn verify_packages(
+    cache_dir: &Path,
+    remove_corrupted: bool,
+) -> std::io::Result<CacheVerificationReport> {
+    let mut report = CacheVerificationReport::default();
+    if !cache_dir.is_dir() {
+        return Ok(report);
+    }
+
+    for entry in fs::read_dir(cache_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir()
+            || !is_package_directory_name(&entry.file_name().to_string_lossy())
+        {
+            continue;
+        }
+
+        let path = entry.path();
+        let Some(_lock) = try_lock_package(&path) else {
+            report.skipped.push(path);
+            continue;
+        };
+        match validate_cached_package(&path) {
+            Ok(()) => report.valid.push(path),
+            Err(e) => {
+                tracing::warn!("the package in {} is corrupted: {e}", path.display());
+                if remove_corrupted {
+                    fs::remove_dir_all(&path)?;
+                }
+                report.corrupted.push((path, e));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PackageManifest, MANIFEST_FILE};
+    use crate::{
+        install::test_utils::build_package, package_cache::PackageCache,
+        validation::PackageValidationError,
+    };
+    use rattler_conda_types::package::ArchiveIdentifier;
+
+    #[tokio::test]
+    async fn test_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = build_package(dir.path(), "foo", "1.0", &[], &[("foo.txt", "foo")]);
+        let cache = PackageCache::new(dir.path().join("pkgs"));
+        let key = ArchiveIdentifier::try_from_path(&archive).unwrap();
+        let package_dir = cache
+            .get_or_fetch(key.clone(), move |destination| async move {
+                rattler_package_streaming::tokio::fs::extract(&archive, &destination)
+                    .await
+                    .map(|_| ())
+            })
+            .await
+            .unwrap();
+
+        // A manifest is stored when the package is extracted.
+        let manifest = PackageManifest::read(&package_dir).unwrap();
+        assert_eq!(manifest.files[std::path::Path::new("foo.txt")].size, 3);
+        assert!(manifest
+            .files
+            .contains_key(std::path::Path::new("info/index.json")));
+        assert!(!manifest
+            .files
+            .contains_key(std::path::Path::new(MANIFEST_FILE)));
+        assert!(cache.verify().await.unwrap().is_valid());
+
+        // Corrupt a file without changing its size.
+        std::fs::write(package_dir.join("foo.txt"), "bar").unwrap();
+        let report = cache.verify().await.unwrap();
+        assert_eq!(report.corrupted.len(), 1);
+        assert!(matches!(
+            &report.corrupted[0].1,
+            PackageValidationError::CorruptedEntry(path, _) if path == std::path::Path::new("foo.txt")
+        ));
+        assert!(package_dir.is_dir());
+
+        // Repairing removes the corrupted package so it is fetched again.
+        let report = cache.repair().await.unwrap();
+        assert_eq!(report.corrupted.len(), 1);
+        assert!(!package_dir.exists());
+        assert!(cache.cached_package_dir(key).is_none());
+    }
+}
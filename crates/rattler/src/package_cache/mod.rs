@@ -1,25 +1,42 @@
 //! This module provides functionality to cache extracted Conda packages. See [`PackageCache`].
 
+use crate::download::{DownloadError, DownloadManager, DownloadRequest};
 use crate::validation::validate_package_directory;
 use chrono::Utc;
+use fs_err as fs;
+use fslock::LockFile;
 use fxhash::FxHashMap;
 use itertools::Itertools;
-use rattler_conda_types::{package::ArchiveIdentifier, PackageRecord};
+use manifest::validate_cached_package;
+use rattler_conda_types::{package::ArchiveIdentifier, PackageRecord, RepoDataRecord};
 use rattler_digest::Sha256Hash;
-use rattler_networking::retry_policies::{DoNotRetryPolicy, RetryDecision, RetryPolicy};
+use rattler_networking::{
+    retry_policies::{DoNotRetryPolicy, RetryDecision, RetryPolicy},
+    RedactedUrl,
+};
 use rattler_package_streaming::ExtractError;
 use reqwest::StatusCode;
 use std::error::Error;
 use std::{
     fmt::{Display, Formatter},
     future::Future,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 use tokio::sync::broadcast;
 use tracing::Instrument;
 use url::Url;
 
+mod gc;
+mod manifest;
+mod prefetch;
+
+pub use gc::{
+    GarbageCollectionOptions, GarbageCollectionReport, PackageCacheEntry, PREFIX_REGISTRY_FILE,
+};
+pub use manifest::{CacheVerificationReport, ManifestEntry, PackageManifest, MANIFEST_FILE};
+pub use prefetch::{PrefetchError, PrefetchResult};
+
 /// A [`PackageCache`] manages a cache of extracted Conda packages on disk.
 ///
 /// The store does not provide an implementation to get the data into the store. Instead this is
@@ -95,6 +112,14 @@ pub enum PackageCacheError {
     /// An error occurred while fetching the package.
     #[error(transparent)]
     FetchError(#[from] Arc<dyn std::error::Error + Send + Sync + 'static>),
+
+    /// Failed to acquire the lock of a package directory.
+    #[error("failed to acquire a lock on {0}")]
+    LockError(PathBuf, #[source] Arc<std::io::Error>),
+
+    /// An IO error occurred while moving the package into place.
+    #[error("failed to store the package in the cache")]
+    IoError(#[source] Arc<std::io::Error>),
 }
 
 impl PackageCache {
@@ -108,6 +133,42 @@ impl PackageCache {
         }
     }
 
+    /// Constructs the [`PackageCache`] in the `pkgs` directory of the configured cache directory.
+    /// Returns `None` if the cache directory cannot be determined.
+    pub fn from_config(config: &rattler_config::Config) -> Option<Self> {
+        config
+            .cache_dir()
+            .map(|cache_dir| Self::new(cache_dir.join("pkgs")))
+    }
+
+    /// Returns the directory of the specified package if it is already present in the cache,
+    /// without fetching it. Only a quick check of the package directory is performed, the
+    /// contents of the files are not validated.
+    pub fn cached_package_dir(&self, pkg: impl Into<CacheKey>) -> Option<PathBuf> {
+        let cache_key = pkg.into();
+        let (package, pkg_cache_dir) = {
+            let inner = self.inner.lock().unwrap();
+            let destination = inner.path.join(cache_key.to_string());
+            (inner.packages.get(&cache_key).cloned(), destination)
+        };
+
+        if let Some(path) = package.and_then(|package| package.lock().unwrap().path.clone()) {
+            return Some(path);
+        }
+
+        if !pkg_cache_dir.join("info/index.json").is_file() {
+            return None;
+        }
+        if let Some(expected_sha256) = cache_key.sha256() {
+            if let Ok(sha256) = fs::read_to_string(pkg_cache_dir.join(SHA256_FILE)) {
+                if sha256.trim() != format!("{expected_sha256:x}") {
+                    return None;
+                }
+            }
+        }
+        Some(pkg_cache_dir)
+    }
+
     /// Returns the directory that contains the specified package.
     ///
     /// If the package was previously successfully fetched and stored in the cache the directory
@@ -128,6 +189,7 @@ impl PackageCache {
         E: std::error::Error + Send + Sync + 'static,
     {
         let cache_key = pkg.into();
+        let sha256 = cache_key.sha256();
 
         // Get the package entry
         let (package, pkg_cache_dir) = {
@@ -156,7 +218,7 @@ impl PackageCache {
 
                 let package = package.clone();
                 tokio::spawn(async move {
-                    let result = validate_or_fetch_to_cache(pkg_cache_dir.clone(), fetch)
+                    let result = validate_or_fetch_to_cache(pkg_cache_dir.clone(), sha256, fetch)
                         .instrument(
                             tracing::debug_span!("validating", path = %pkg_cache_dir.display()),
                         )
@@ -218,7 +280,11 @@ impl PackageCache {
             let mut current_try = 0;
             loop {
                 current_try += 1;
-                tracing::debug!("downloading {} to {}", &url, destination.display());
+                tracing::debug!(
+                    "downloading {} to {}",
+                    RedactedUrl::from(&url),
+                    destination.display()
+                );
                 let result = rattler_package_streaming::reqwest::tokio::extract(
                     client.clone(),
                     url.clone(),
@@ -255,7 +321,7 @@ impl PackageCache {
                 // chance of success.
                 tracing::warn!(
                     "failed to download and extract {} to {}: {}. Retry #{}, Sleeping {:?} until the next attempt...",
-                    &url,
+                    RedactedUrl::from(&url),
                     destination.display(),
                     err,
                     current_try,
@@ -268,10 +334,168 @@ impl PackageCache {
     }
 }
 
+/// An error that can occur while downloading and extracting a package with a
+/// [`DownloadManager`].
+#[derive(Debug, thiserror::Error)]
+enum DownloadAndExtractError {
+    #[error(transparent)]
+    Download(#[from] DownloadError),
+
+    #[error(transparent)]
+    Extract(#[from] ExtractError),
+}
+
+impl PackageCache {
+    /// Returns the directory that contains the package of the record.
+    ///
+    /// If the package is not in the cache its archive is downloaded with the [`DownloadManager`]
+    /// into the `.downloads` directory of the cache and extracted from there. Interrupted
+    /// downloads are resumed the next time the package is requested. Archives that are referenced
+    /// by a `file://` URL are extracted directly.
+    pub async fn get_or_fetch_with_download_manager(
+        &self,
+        record: &RepoDataRecord,
+        download_manager: &DownloadManager,
+    ) -> Result<PathBuf, PackageCacheError> {
+        let request = DownloadRequest::for_record(record, &self.downloads_dir());
+        let download_manager = download_manager.clone();
+        self.get_or_fetch(&record.package_record, move |destination| async move {
+            let local_path = if request.url.scheme() == "file" {
+                Some(download_manager.file_path(&request.url).await?)
+            } else {
+                None
+            };
+            let archive = match &local_path {
+                Some(path) => path.clone(),
+                None => download_manager.download(&request).await?,
+            };
+
+            let result =
+                rattler_package_streaming::tokio::fs::extract(&archive, &destination).await;
+            if local_path.is_none() {
+                let _ = tokio::fs::remove_file(&archive).await;
+            }
+            let result = result?;
+            match request.sha256 {
+                Some(expected) if expected != result.sha256 => Err(
+                    DownloadAndExtractError::Extract(ExtractError::HashMismatch {
+                        expected,
+                        actual: result.sha256,
+                    }),
+                ),
+                _ => Ok(()),
+            }
+        })
+        .await
+    }
+
+    /// Returns the directory that archives are downloaded to before they are extracted.
+    pub fn downloads_dir(&self) -> PathBuf {
+        self.inner.lock().unwrap().path.join(DOWNLOADS_DIR)
+    }
+}
+
+/// The name of the directory, inside the cache, that archives are downloaded to.
+const DOWNLOADS_DIR: &str = ".downloads";
+
+/// The name of the file, inside a package directory, that contains the sha256 hash of the archive
+/// the directory was extracted from.
+const SHA256_FILE: &str = ".sha256";
+
+/// The prefix of the temporary directories that packages are extracted to before they are moved
+/// into place.
+const TEMP_DIR_PREFIX: &str = ".tmp-";
+
+/// Returns the path of the file that is used to lock the package directory at `path`.
+fn lock_file_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".lock");
+    path.with_file_name(file_name)
+}
+
+/// Acquires an exclusive lock on the lock file of the package at `path`. The lock is shared by all
+/// processes that use the same cache and is released when the returned value is dropped.
+async fn lock_package_directory(path: &Path) -> Result<LockFile, PackageCacheError> {
+    let lock_path = lock_file_path(path);
+    tokio::task::spawn_blocking(move || {
+        let lock_error = |e| PackageCacheError::LockError(lock_path.clone(), Arc::new(e));
+        if let Some(parent) = lock_path.parent() {
+            fs::create_dir_all(parent).map_err(lock_error)?;
+        }
+        let mut lock = LockFile::open(&lock_path).map_err(lock_error)?;
+
+        // First try to lock the file without blocking. If we can't immediately get the lock we
+        // block and issue a debug message.
+        if !lock.try_lock_with_pid().map_err(lock_error)? {
+            tracing::debug!("waiting for lock on {}", lock_path.display());
+            lock.lock_with_pid().map_err(lock_error)?;
+        }
+        Ok(lock)
+    })
+    .await
+    .unwrap_or_else(|e| match e.try_into_panic() {
+        Ok(panic) => std::panic::resume_unwind(panic),
+        Err(e) => Err(PackageCacheError::IoError(Arc::new(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            e,
+        )))),
+    })
+}
+
+/// Validates the contents of an existing package directory. Returns false if the directory is
+/// incomplete or corrupted, or if it was extracted from an archive with a different sha256 hash
+/// than the `expected_sha256`.
+fn validate_existing_package(path: &Path, expected_sha256: Option<Sha256Hash>) -> bool {
+    if let Some(expected_sha256) = expected_sha256 {
+        match fs::read_to_string(path.join(SHA256_FILE)) {
+            Ok(sha256) if sha256.trim() != format!("{expected_sha256:x}") => {
+                tracing::warn!(
+                    "the package in {path:?} was extracted from an archive with sha256 {}, but \
+                    {expected_sha256:x} was expected",
+                    sha256.trim()
+                );
+                return false;
+            }
+            _ => {}
+        }
+    }
+
+    match validate_cached_package(path) {
+        Ok(()) => {
+            tracing::debug!("validation succeeded");
+            true
+        }
+        Err(e) => {
+            tracing::warn!("validation for {path:?} failed: {e}");
+            if let Some(cause) = e.source() {
+                tracing::debug!(
+                    "  Caused by: {}",
+                    std::iter::successors(Some(cause), |e| (*e).source()).format("\n  Caused by: ")
+                );
+            }
+            false
+        }
+    }
+}
+
+/// Removes a directory, ignoring the error if it doesn't exist.
+fn remove_dir_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
 /// Validates that the package that is currently stored is a valid package and otherwise calls the
 /// `fetch` method to populate the cache.
+///
+/// The package directory is locked while it is validated and populated. This makes it safe for
+/// multiple processes to use the same cache. The package is first fetched into a temporary
+/// directory that is moved into place once its contents have been validated, so a package
+/// directory is never observed half-extracted.
 async fn validate_or_fetch_to_cache<F, Fut, E>(
     path: PathBuf,
+    expected_sha256: Option<Sha256Hash>,
     fetch: F,
 ) -> Result<(), PackageCacheError>
 where
@@ -279,41 +503,79 @@ where
     Fut: Future<Output = Result<(), E>> + 'static,
     E: std::error::Error + Send + Sync + 'static,
 {
-    // If the directory already exists validate the contents of the package
-    if path.is_dir() {
-        let path_inner = path.clone();
-        match tokio::task::spawn_blocking(move || validate_package_directory(&path_inner)).await {
-            Ok(Ok(_)) => {
-                tracing::debug!("validation succeeded");
-                return Ok(());
-            }
-            Ok(Err(e)) => {
-                tracing::warn!("validation for {path:?} failed: {e}");
-                if let Some(cause) = e.source() {
-                    tracing::debug!(
-                        "  Caused by: {}",
-                        std::iter::successors(Some(cause), |e| (*e).source())
-                            .format("\n  Caused by: ")
-                    );
-                }
-            }
-            Err(e) => {
-                if let Ok(panic) = e.try_into_panic() {
-                    std::panic::resume_unwind(panic)
-                }
+    let _lock = lock_package_directory(&path).await?;
+    let io_error = |e| PackageCacheError::IoError(Arc::new(e));
+
+    // If the directory already exists validate the contents of the package. Directories that fail
+    // to validate, for instance because another process was interrupted while extracting the
+    // package, are removed.
+    let path_inner = path.clone();
+    let is_valid = tokio::task::spawn_blocking(move || {
+        if !path_inner.is_dir() {
+            return Ok(false);
+        }
+        if validate_existing_package(&path_inner, expected_sha256) {
+            return Ok(true);
+        }
+        fs::remove_dir_all(&path_inner).map(|_| false)
+    });
+    match is_valid.await {
+        Ok(Ok(true)) => return Ok(()),
+        Ok(Ok(false)) => {}
+        Ok(Err(e)) => return Err(io_error(e)),
+        Err(e) => {
+            if let Ok(panic) = e.try_into_panic() {
+                std::panic::resume_unwind(panic)
             }
         }
     }
 
-    // Otherwise, defer to populate method to fill our cache.
-    fetch(path)
-        .await
-        .map_err(|e| PackageCacheError::FetchError(Arc::new(e)))
+    // Otherwise, defer to populate method to fill a temporary directory.
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_dir = path.with_file_name(format!(
+        "{TEMP_DIR_PREFIX}{file_name}-{}",
+        uuid::Uuid::new_v4().simple()
+    ));
+    if let Err(e) = fetch(temp_dir.clone()).await {
+        let _ = remove_dir_if_exists(&temp_dir);
+        return Err(PackageCacheError::FetchError(Arc::new(e)));
+    }
+
+    // Validate the extracted package and move it into place.
+    let temp_dir_inner = temp_dir.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        validate_package_directory(&temp_dir_inner)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+        if let Some(sha256) = expected_sha256 {
+            fs::write(temp_dir_inner.join(SHA256_FILE), format!("{sha256:x}"))?;
+        }
+        PackageManifest::from_package_directory(&temp_dir_inner)?.write(&temp_dir_inner)?;
+        remove_dir_if_exists(&path)?;
+        fs::rename(&temp_dir_inner, &path)
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => {
+            let _ = remove_dir_if_exists(&temp_dir);
+            Err(io_error(e))
+        }
+        Err(e) => {
+            let _ = remove_dir_if_exists(&temp_dir);
+            match e.try_into_panic() {
+                Ok(panic) => std::panic::resume_unwind(panic),
+                Err(e) => Err(io_error(std::io::Error::new(
+                    std::io::ErrorKind::Interrupted,
+                    e,
+                ))),
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::PackageCache;
+    use super::{CacheKey, PackageCache};
     use crate::{get_test_data_dir, validation::validate_package_directory};
     use assert_matches::assert_matches;
     use axum::{
@@ -331,7 +593,15 @@ mod test {
     use rattler_conda_types::package::{ArchiveIdentifier, PackageFile, PathsJson};
     use rattler_networking::retry_policies::{DoNotRetryPolicy, ExponentialBackoffBuilder};
     use std::{
-        convert::Infallible, fs::File, future::IntoFuture, net::SocketAddr, path::Path, sync::Arc,
+        convert::Infallible,
+        fs::File,
+        future::IntoFuture,
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
     };
     use tempfile::tempdir;
     use tokio::sync::Mutex;
@@ -380,6 +650,139 @@ mod test {
         assert_eq!(current_paths, paths);
     }
 
+    /// Writes a minimal valid package to `destination` and counts the number of invocations.
+    async fn fetch_minimal_package(
+        destination: PathBuf,
+        fetch_count: Arc<AtomicUsize>,
+    ) -> Result<(), std::io::Error> {
+        fetch_count.fetch_add(1, Ordering::SeqCst);
+        std::fs::create_dir_all(destination.join("info"))?;
+        std::fs::write(
+            destination.join("info/index.json"),
+            r#"{"name": "foo", "version": "1.0", "build": "0", "build_number": 0}"#,
+        )?;
+        std::fs::write(
+            destination.join("info/paths.json"),
+            r#"{"paths": [], "paths_version": 1}"#,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_refetch_invalid_entries() {
+        let packages_dir = tempdir().unwrap();
+        let fetch_count = Arc::new(AtomicUsize::new(0));
+
+        // A half extracted package is replaced.
+        let package_dir = packages_dir.path().join("foo-1.0-0");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        std::fs::write(package_dir.join("half-extracted"), "").unwrap();
+
+        let sha256 = rattler_digest::parse_digest_from_hex::<rattler_digest::Sha256>(
+            "4f4b1dca2a9df6b6d5e7a0b5c5d5e1f1e7b4c8a2d3b0a6f5e8d9c1b2a3f4e5d6",
+        )
+        .unwrap();
+        let cache_key = CacheKey {
+            name: "foo".to_string(),
+            version: "1.0".to_string(),
+            build_string: "0".to_string(),
+            sha256: Some(sha256),
+        };
+        let count = fetch_count.clone();
+        let path = PackageCache::new(packages_dir.path())
+            .get_or_fetch(cache_key.clone(), move |destination| {
+                fetch_minimal_package(destination, count)
+            })
+            .await
+            .unwrap();
+        assert_eq!(path, package_dir);
+        assert_eq!(fetch_count.load(Ordering::SeqCst), 1);
+        assert!(!package_dir.join("half-extracted").exists());
+        assert_eq!(
+            std::fs::read_dir(packages_dir.path())
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().file_type().unwrap().is_dir())
+                .count(),
+            1,
+            "the temporary directory should have been moved into place"
+        );
+
+        // A valid package is reused by another cache instance.
+        let count = fetch_count.clone();
+        PackageCache::new(packages_dir.path())
+            .get_or_fetch(cache_key.clone(), move |destination| {
+                fetch_minimal_package(destination, count)
+            })
+            .await
+            .unwrap();
+        assert_eq!(fetch_count.load(Ordering::SeqCst), 1);
+
+        // A package that was extracted from a different archive is fetched again.
+        std::fs::write(package_dir.join(super::SHA256_FILE), "0000").unwrap();
+        let count = fetch_count.clone();
+        PackageCache::new(packages_dir.path())
+            .get_or_fetch(cache_key, move |destination| {
+                fetch_minimal_package(destination, count)
+            })
+            .await
+            .unwrap();
+        assert_eq!(fetch_count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_download_manager() {
+        let channel = tempdir().unwrap();
+        let archive = crate::install::test_utils::build_package(
+            channel.path(),
+            "foo",
+            "1.0",
+            &[],
+            &[("foo.txt", "foo")],
+        );
+        let index_json = rattler_package_streaming::seek::read_package_file::<
+            rattler_conda_types::package::IndexJson,
+        >(&archive)
+        .unwrap();
+
+        let listener = tokio::net::TcpListener::bind(SocketAddr::new([127, 0, 0, 1].into(), 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let router = Router::new().route_service("/*key", ServeDir::new(channel.path()));
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+
+        let record = rattler_conda_types::RepoDataRecord {
+            package_record: rattler_conda_types::PackageRecord::from_index_json(
+                index_json,
+                Some(std::fs::metadata(&archive).unwrap().len()),
+                Some(
+                    rattler_digest::compute_file_digest::<rattler_digest::Sha256>(&archive)
+                        .unwrap(),
+                ),
+                None,
+            )
+            .unwrap(),
+            file_name: String::from("foo-1.0-0.tar.bz2"),
+            url: Url::parse(&format!("http://localhost:{port}/foo-1.0-0.tar.bz2")).unwrap(),
+            channel: String::from("test"),
+        };
+
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path());
+        let download_manager =
+            crate::download::DownloadManager::builder(reqwest::Client::default().into()).finish();
+        let package_dir = cache
+            .get_or_fetch_with_download_manager(&record, &download_manager)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(package_dir.join("foo.txt")).unwrap(),
+            "foo"
+        );
+
+        // The archive is removed once it has been extracted.
+        assert_eq!(std::fs::read_dir(cache.downloads_dir()).unwrap().count(), 0);
+    }
+
     /// A helper middleware function that fails the first two requests.
     async fn fail_the_first_two_requests(
         State(count): State<Arc<Mutex<i32>>>,
@@ -0,0 +1,199 @@
+//! Pre-warming of the [`PackageCache`].
+//!
+//! Prefetching downloads and extracts a set of packages into the cache without linking them into
+//! a prefix. This is useful to prepare a cache that is used offline later on, or to warm up the
+//! cache of a CI job before the environments are installed.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use rattler_conda_types::RepoDataRecord;
+use url::Url;
+
+use super::{CacheKey, PackageCache, PackageCacheError};
+use crate::download::{DownloadError, DownloadManager, DownloadRequest};
+
+/// The result of [`PackageCache::prefetch`].
+#[derive(Debug, Default)]
+pub struct PrefetchResult {
+    /// The directories of the packages that were already in the cache.
+    pub cached: Vec<PathBuf>,
+
+    /// The directories of the packages that were downloaded and extracted.
+    pub fetched: Vec<PathBuf>,
+
+    /// The packages that could not be fetched.
+    pub failed: Vec<(Url, PackageCacheError)>,
+}
+
+impl PrefetchResult {
+    /// Returns true if all packages are available in the cache.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// An error that can occur while prefetching packages.
+#[derive(Debug, thiserror::Error)]
+pub enum PrefetchError {
+    /// The packages could not be downloaded at all, e.g. because there is not enough disk space.
+    #[error(transparent)]
+    Download(#[from] DownloadError),
+
+    /// The records of the lock file could not be read.
+    #[cfg(feature = "lock-file")]
+    #[error("failed to read the packages of the lock file")]
+    InvalidLockFile(#[source] rattler_lock::ConversionError),
+}
+
+impl PackageCache {
+    /// Downloads and extracts all packages into the cache without installing them into a prefix.
+    ///
+    /// Packages that are already in the cache are skipped. The disk space required for the
+    /// remaining packages is checked up front, after which they are fetched concurrently and
+    /// verified against the hashes of their records. A package that fails to fetch does not stop
+    /// the other packages, the failures are reported in the [`PrefetchResult`].
+    pub async fn prefetch(
+        &self,
+        records: impl IntoIterator<Item = RepoDataRecord>,
+        download_manager: &DownloadManager,
+    ) -> Result<PrefetchResult, PrefetchError> {
+        let mut result = PrefetchResult::default();
+        let mut seen = HashSet::new();
+        let mut missing = Vec::new();
+        for record in records {
+            if !seen.insert(CacheKey::from(&record.package_record)) {
+                continue;
+            }
+            match self.cached_package_dir(&record.package_record) {
+                Some(path) => result.cached.push(path),
+                None => missing.push(record),
+            }
+        }
+
+        let downloads_dir = self.downloads_dir();
+        let requests = missing
+            .iter()
+            .map(|record| DownloadRequest::for_record(record, &downloads_dir))
+            .collect::<Vec<_>>();
+        download_manager.check_disk_space(&requests).await?;
+
+        let fetched = futures::future::join_all(
+            missing
+                .iter()
+                .map(|record| self.get_or_fetch_with_download_manager(record, download_manager)),
+        )
+        .await;
+        for (record, fetched) in missing.into_iter().zip(fetched) {
+            match fetched {
+                Ok(path) => result.fetched.push(path),
+                Err(err) => result.failed.push((record.url, err)),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Prefetches the conda packages of all environments and platforms of a lock file, see
+    /// [`PackageCache::prefetch`].
+    #[cfg(feature = "lock-file")]
+    pub async fn prefetch_lock_file(
+        &self,
+        lock_file: &rattler_lock::LockFile,
+        download_manager: &DownloadManager,
+    ) -> Result<PrefetchResult, PrefetchError> {
+        let mut records = Vec::new();
+        for (_, environment) in lock_file.environments() {
+            let platforms = environment
+                .conda_repodata_records()
+                .map_err(PrefetchError::InvalidLockFile)?;
+            records.extend(platforms.into_values().flatten());
+        }
+        self.prefetch(records, download_manager).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{future::IntoFuture, net::SocketAddr, path::Path};
+
+    use axum::Router;
+    use rattler_conda_types::{package::IndexJson, PackageRecord, RepoDataRecord};
+    use tempfile::tempdir;
+    use tower_http::services::ServeDir;
+    use url::Url;
+
+    use crate::{
+        download::DownloadManager, install::test_utils::build_package, package_cache::PackageCache,
+    };
+
+    fn record(archive: &Path, base_url: &Url) -> RepoDataRecord {
+        let index_json =
+            rattler_package_streaming::seek::read_package_file::<IndexJson>(archive).unwrap();
+        let file_name = archive.file_name().unwrap().to_string_lossy().into_owned();
+        RepoDataRecord {
+            package_record: PackageRecord::from_index_json(
+                index_json,
+                Some(std::fs::metadata(archive).unwrap().len()),
+                Some(
+                    rattler_digest::compute_file_digest::<rattler_digest::Sha256>(archive).unwrap(),
+                ),
+                None,
+            )
+            .unwrap(),
+            url: base_url.join(&file_name).unwrap(),
+            file_name,
+            channel: base_url.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        let channel = tempdir().unwrap();
+        let foo = build_package(channel.path(), "foo", "1.0", &[], &[("foo.txt", "foo")]);
+        let bar = build_package(channel.path(), "bar", "1.0", &[], &[("bar.txt", "bar")]);
+
+        let listener = tokio::net::TcpListener::bind(SocketAddr::new([127, 0, 0, 1].into(), 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let router = Router::new().route_service("/*key", ServeDir::new(channel.path()));
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        let base_url = Url::parse(&format!("http://localhost:{port}/")).unwrap();
+
+        let foo = record(&foo, &base_url);
+        let bar = record(&bar, &base_url);
+        let mut missing = record(
+            &build_package(tempdir().unwrap().path(), "baz", "1.0", &[], &[]),
+            &base_url,
+        );
+        missing.package_record.name = "missing".parse().unwrap();
+
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path());
+        let download_manager = DownloadManager::builder(reqwest::Client::default().into()).finish();
+
+        // Duplicate records are only fetched once, a failure does not stop the other packages.
+        let result = cache
+            .prefetch(
+                [foo.clone(), foo.clone(), bar.clone(), missing.clone()],
+                &download_manager,
+            )
+            .await
+            .unwrap();
+        assert!(result.cached.is_empty());
+        assert_eq!(result.fetched.len(), 2);
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].0, missing.url);
+        assert!(!result.is_complete());
+        assert_eq!(
+            std::fs::read_to_string(result.fetched[0].join("foo.txt")).unwrap(),
+            "foo"
+        );
+
+        // A second prefetch finds everything in the cache.
+        let result = cache.prefetch([foo, bar], &download_manager).await.unwrap();
+        assert_eq!(result.cached.len(), 2);
+        assert!(result.fetched.is_empty());
+        assert!(result.is_complete());
+    }
+}
@@ -0,0 +1,321 @@
+//! Inspection of the packages that are installed in a prefix. See [`Prefix`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use rattler_conda_types::{PackageName, PrefixRecord, RepoDataRecord};
+use serde::Deserialize;
+
+use crate::install::{journal::JOURNAL_DIR, trash::TRASH_DIR};
+
+/// Provides fast, read-only access to the packages that are installed in a prefix.
+///
+/// Installed packages are enumerated from the file names of the records in the `conda-meta`
+/// directory. The records themselves are only parsed when they are requested, either completely
+/// with [`InstalledPackage::read_record`] or without the potentially large lists of files with
+/// [`InstalledPackage::read_repodata_record`].
+#[derive(Debug, Clone)]
+pub struct Prefix {
+    root: PathBuf,
+}
+
+/// A package that is installed in a [`Prefix`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InstalledPackage {
+    record_path: PathBuf,
+    name: PackageName,
+    version: String,
+    build: String,
+}
+
+/// The files of an installed package as stored in its `conda-meta` record. Deserializing only
+/// these fields is much cheaper than deserializing a complete [`PrefixRecord`].
+#[derive(Deserialize)]
+struct InstalledFiles {
+    #[serde(default)]
+    files: Vec<PathBuf>,
+    #[serde(default)]
+    paths_data: InstalledPaths,
+}
+
+#[derive(Default, Deserialize)]
+struct InstalledPaths {
+    #[serde(default)]
+    paths: Vec<InstalledPath>,
+}
+
+#[derive(Deserialize)]
+struct InstalledPath {
+    #[serde(rename = "_path")]
+    relative_path: PathBuf,
+}
+
+impl InstalledPackage {
+    /// Parses the name, version and build string from the file name of a `conda-meta` record,
+    /// which takes the form `<name>-<version>-<build>.json`.
+    fn from_record_path(record_path: PathBuf) -> Option<Self> {
+        let stem = record_path.file_name()?.to_str()?.strip_suffix(".json")?;
+        let mut parts = stem.rsplitn(3, '-');
+        let build = parts.next()?.to_string();
+        let version = parts.next()?.to_string();
+        let name = PackageName::try_from(parts.next()?).ok()?;
+        Some(Self {
+            record_path,
+            name,
+            version,
+            build,
+        })
+    }
+
+    /// The name of the package.
+    pub fn name(&self) -> &PackageName {
+        &self.name
+    }
+
+    /// The version of the package.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The build string of the package.
+    pub fn build(&self) -> &str {
+        &self.build
+    }
+
+    /// The path of the `conda-meta` record of the package.
+    pub fn record_path(&self) -> &Path {
+        &self.record_path
+    }
+
+    /// Reads the complete `conda-meta` record of the package.
+    pub fn read_record(&self) -> std::io::Result<PrefixRecord> {
+        PrefixRecord::from_path(&self.record_path)
+    }
+
+    /// Reads the metadata of the package from its `conda-meta` record without the lists of files
+    /// that the package installed.
+    pub fn read_repodata_record(&self) -> std::io::Result<RepoDataRecord> {
+        Ok(serde_json::from_str(&fs::read_to_string(
+            &self.record_path,
+        )?)?)
+    }
+
+    /// Reads the paths, relative to the prefix, of the files that the package installed.
+    pub fn read_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let files: InstalledFiles = serde_json::from_str(&fs::read_to_string(&self.record_path)?)?;
+        let mut paths = files.files;
+        paths.extend(
+            files
+                .paths_data
+                .paths
+                .into_iter()
+                .map(|path| path.relative_path),
+        );
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+}
+
+impl Prefix {
+    /// Constructs a new instance for the prefix at the given path.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The root directory of the prefix.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the packages that are installed in the prefix, sorted by name. None of the
+    /// `conda-meta` records are parsed.
+    pub fn packages(&self) -> std::io::Result<Vec<InstalledPackage>> {
+        let entries = match fs::read_dir(self.root.join("conda-meta")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut packages = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            match InstalledPackage::from_record_path(entry.path()) {
+                Some(package) => packages.push(package),
+                None if entry.path().extension().is_some_and(|ext| ext == "json") => {
+                    tracing::warn!(
+                        "ignoring {} because it is not a valid record name",
+                        entry.path().display()
+                    );
+                }
+                None => {}
+            }
+        }
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(packages)
+    }
+
+    /// Returns the installed package with the given name, if any.
+    pub fn package(&self, name: &PackageName) -> std::io::Result<Option<InstalledPackage>> {
+        Ok(self
+            .packages()?
+            .into_iter()
+            .find(|package| &package.name == name))
+    }
+
+    /// Returns the package that installed the file at `path`, which is either absolute or
+    /// relative to the prefix. To look up many paths use [`Prefix::file_owners`] instead.
+    pub fn owner_of(&self, path: &Path) -> std::io::Result<Option<InstalledPackage>> {
+        let path = path.strip_prefix(&self.root).unwrap_or(path);
+        for package in self.packages()? {
+            if package.read_files()?.iter().any(|file| file == path) {
+                return Ok(Some(package));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns a map from the paths of all files that are installed by packages, relative to the
+    /// prefix, to the name of the package that installed them.
+    pub fn file_owners(&self) -> std::io::Result<HashMap<PathBuf, PackageName>> {
+        let mut owners = HashMap::new();
+        for package in self.packages()? {
+            for file in package.read_files()? {
+                owners.insert(file, package.name.clone());
+            }
+        }
+        Ok(owners)
+    }
+
+    /// Returns the paths, relative to the prefix, of the files in the prefix that are not
+    /// installed by any package. The `conda-meta` directory and the directories that are used
+    /// during transactions are skipped. Files that are created at runtime, like python bytecode
+    /// caches, are reported as well.
+    pub fn unowned_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let owned = self.file_owners()?.into_keys().collect::<HashSet<_>>();
+        // The journal directory is inside `conda-meta` but it is listed explicitly so it stays
+        // skipped if either of them moves.
+        let skipped = ["conda-meta", JOURNAL_DIR, TRASH_DIR].map(|dir| self.root.join(dir));
+
+        let mut unowned = Vec::new();
+        let walker = walkdir::WalkDir::new(&self.root)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| !skipped.iter().any(|path| path == entry.path()));
+        for entry in walker {
+            let entry = entry?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let relative_path = entry
+                .path()
+                .strip_prefix(&self.root)
+                .expect("walked paths are inside the prefix");
+            if !owned.contains(relative_path) {
+                unowned.push(relative_path.to_path_buf());
+            }
+        }
+        unowned.sort();
+        Ok(unowned)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Prefix;
+    use rattler_conda_types::PackageName;
+    use std::path::{Path, PathBuf};
+
+    fn write_record(prefix: &Path, file_name: &str, files: &[&str]) {
+        let conda_meta = prefix.join("conda-meta");
+        std::fs::create_dir_all(&conda_meta).unwrap();
+        let (name, version, build) = {
+            let mut parts = file_name.rsplitn(3, '-');
+            let build = parts.next().unwrap();
+            let version = parts.next().unwrap();
+            (parts.next().unwrap(), version, build)
+        };
+        let record = serde_json::json!({
+            "name": name,
+            "version": version,
+            "build": build,
+            "build_number": 0,
+            "depends": [],
+            "fn": format!("{file_name}.tar.bz2"),
+            "url": format!("https://conda.anaconda.org/conda-forge/linux-64/{file_name}.tar.bz2"),
+            "channel": "https://conda.anaconda.org/conda-forge",
+            "subdir": "linux-64",
+            "files": files,
+            "paths_data": { "paths_version": 1, "paths": [] },
+        });
+        std::fs::write(
+            conda_meta.join(format!("{file_name}.json")),
+            serde_json::to_string(&record).unwrap(),
+        )
+        .unwrap();
+        for file in files {
+            let path = prefix.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_prefix_introspection() {
+        let root = tempfile::tempdir().unwrap();
+        write_record(root.path(), "foo-bar-1.0-h123_0", &["lib/libfoo.so"]);
+        write_record(root.path(), "baz-2.0-0", &["bin/baz", "share/baz/data.txt"]);
+        std::fs::write(root.path().join("lib/untracked.txt"), "").unwrap();
+        std::fs::write(root.path().join("conda-meta/history"), "").unwrap();
+
+        // The files of the trash and of an ongoing transaction are not reported.
+        for file in [
+            ".trash/0123-libold.so",
+            "conda-meta/.transaction/backup/bin/old",
+        ] {
+            let path = root.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let prefix = Prefix::new(root.path());
+        let packages = prefix.packages().unwrap();
+        assert_eq!(
+            packages
+                .iter()
+                .map(|package| package.name().as_normalized())
+                .collect::<Vec<_>>(),
+            vec!["baz", "foo-bar"]
+        );
+        assert_eq!(packages[1].version(), "1.0");
+        assert_eq!(packages[1].build(), "h123_0");
+
+        let foo_bar = PackageName::try_from("foo-bar").unwrap();
+        let record = prefix
+            .package(&foo_bar)
+            .unwrap()
+            .unwrap()
+            .read_repodata_record()
+            .unwrap();
+        assert_eq!(record.package_record.name, foo_bar);
+
+        let owner = prefix
+            .owner_of(&root.path().join("lib/libfoo.so"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(owner.name(), &foo_bar);
+        assert!(prefix.owner_of(Path::new("bin/missing")).unwrap().is_none());
+
+        assert_eq!(
+            prefix.unowned_files().unwrap(),
+            vec![PathBuf::from("lib/untracked.txt")]
+        );
+    }
+}
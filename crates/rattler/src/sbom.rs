@@ -0,0 +1,516 @@
+//! Generation of a software bill of materials (SBOM) for the packages that are installed in a
+//! prefix. See [`Sbom`].
+//!
+//! Both [SPDX](https://spdx.github.io/spdx-spec/v2.3/) and
+//! [CycloneDX](https://cyclonedx.org/docs/1.5/json/) documents can be generated.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use rattler_conda_types::{MatchSpec, PackageName, ParseStrictness, RepoDataRecord};
+use serde_json::{json, Value};
+
+use crate::prefix::{InstalledPackage, Prefix};
+
+/// The format of an SBOM document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli-tools", derive(clap::ValueEnum))]
+pub enum SbomFormat {
+    /// An SPDX 2.3 JSON document.
+    Spdx,
+
+    /// A `CycloneDX` 1.5 JSON document.
+    CycloneDx,
+}
+
+/// A software bill of materials that describes a set of installed conda packages.
+///
+/// Every package is described by its name, version, license, hashes and the URL it was downloaded
+/// from. The dependencies between the packages are recorded as well, dependencies on packages that
+/// are not part of the SBOM (like virtual packages) are omitted.
+#[derive(Debug, Clone)]
+pub struct Sbom {
+    name: String,
+    created: DateTime<Utc>,
+    serial_number: uuid::Uuid,
+    packages: Vec<RepoDataRecord>,
+}
+
+impl Sbom {
+    /// Constructs an SBOM with the given name from a set of records.
+    pub fn from_records(
+        name: impl Into<String>,
+        records: impl IntoIterator<Item = RepoDataRecord>,
+    ) -> Self {
+        let mut packages = records.into_iter().collect::<Vec<_>>();
+        packages.sort_by(|a, b| a.package_record.name.cmp(&b.package_record.name));
+        Self {
+            name: name.into(),
+            created: Utc::now(),
+            serial_number: uuid::Uuid::new_v4(),
+            packages,
+        }
+    }
+
+    /// Constructs an SBOM from the packages that are installed in the given prefix. The SBOM is
+    /// named after the directory of the prefix.
+    pub fn from_prefix(prefix: &Prefix) -> std::io::Result<Self> {
+        let records = prefix
+            .packages()?
+            .iter()
+            .map(InstalledPackage::read_repodata_record)
+            .collect::<Result<Vec<_>, _>>()?;
+        let name = prefix.root().file_name().map_or_else(
+            || String::from("prefix"),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        Ok(Self::from_records(name, records))
+    }
+
+    /// Returns the packages that are described by this SBOM, sorted by name.
+    pub fn packages(&self) -> &[RepoDataRecord] {
+        &self.packages
+    }
+
+    /// Converts the SBOM to a JSON document in the given format.
+    pub fn to_json(&self, format: SbomFormat) -> Value {
+        match format {
+            SbomFormat::Spdx => self.to_spdx(),
+            SbomFormat::CycloneDx => self.to_cyclonedx(),
+        }
+    }
+
+    /// Writes the SBOM as a pretty-printed JSON document in the given format.
+    pub fn write(&self, format: SbomFormat, writer: impl std::io::Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.to_json(format))
+    }
+
+    fn to_spdx(&self) -> Value {
+        let names = self
+            .packages
+            .iter()
+            .map(|record| &record.package_record.name)
+            .collect::<HashSet<_>>();
+        let ids = spdx_ids(&self.packages);
+
+        let packages = self
+            .packages
+            .iter()
+            .map(|record| {
+                let package = &record.package_record;
+                let mut checksums = Vec::new();
+                if let Some(sha256) = &package.sha256 {
+                    checksums.push(
+                        json!({ "algorithm": "SHA256", "checksumValue": format!("{sha256:x}") }),
+                    );
+                }
+                if let Some(md5) = &package.md5 {
+                    checksums
+                        .push(json!({ "algorithm": "MD5", "checksumValue": format!("{md5:x}") }));
+                }
+                let license = package
+                    .license
+                    .as_deref()
+                    .filter(|license| is_spdx_expression(license));
+                let mut spdx_package = json!({
+                    "SPDXID": ids[&package.name],
+                    "name": package.name.as_source(),
+                    "versionInfo": package.version.to_string(),
+                    "downloadLocation": record.url.as_str(),
+                    "filesAnalyzed": false,
+                    "licenseConcluded": "NOASSERTION",
+                    "licenseDeclared": license.unwrap_or("NOASSERTION"),
+                    "copyrightText": "NOASSERTION",
+                    "checksums": checksums,
+                    "externalRefs": [{
+                        "referenceCategory": "PACKAGE-MANAGER",
+                        "referenceType": "purl",
+                        "referenceLocator": purl(record),
+                    }],
+                });
+                if let (None, Some(license)) = (license, &package.license) {
+                    spdx_package["licenseComments"] =
+                        json!(format!("The package declares the license '{license}'"));
+                }
+                spdx_package
+            })
+            .collect::<Vec<_>>();
+
+        let mut relationships = self
+            .packages
+            .iter()
+            .map(|record| {
+                json!({
+                    "spdxElementId": "SPDXRef-DOCUMENT",
+                    "relationshipType": "DESCRIBES",
+                    "relatedSpdxElement": ids[&record.package_record.name],
+                })
+            })
+            .collect::<Vec<_>>();
+        for record in &self.packages {
+            for dependency in dependencies(record, &names) {
+                relationships.push(json!({
+                    "spdxElementId": ids[&record.package_record.name],
+                    "relationshipType": "DEPENDS_ON",
+                    "relatedSpdxElement": ids[&dependency],
+                }));
+            }
+        }
+
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.name,
+            "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{}", self.name, self.serial_number),
+            "creationInfo": {
+                "created": self.created.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                "creators": [format!("Tool: rattler-{}", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        })
+    }
+
+    fn to_cyclonedx(&self) -> Value {
+        let names = self
+            .packages
+            .iter()
+            .map(|record| &record.package_record.name)
+            .collect::<HashSet<_>>();
+        let refs = self
+            .packages
+            .iter()
+            .map(|record| (&record.package_record.name, purl(record)))
+            .collect::<HashMap<_, _>>();
+
+        let components = self
+            .packages
+            .iter()
+            .map(|record| {
+                let package = &record.package_record;
+                let mut hashes = Vec::new();
+                if let Some(sha256) = &package.sha256 {
+                    hashes.push(json!({ "alg": "SHA-256", "content": format!("{sha256:x}") }));
+                }
+                if let Some(md5) = &package.md5 {
+                    hashes.push(json!({ "alg": "MD5", "content": format!("{md5:x}") }));
+                }
+                let mut component = json!({
+                    "type": "library",
+                    "bom-ref": refs[&package.name],
+                    "name": package.name.as_source(),
+                    "version": package.version.to_string(),
+                    "purl": refs[&package.name],
+                    "hashes": hashes,
+                    "externalReferences": [{ "type": "distribution", "url": record.url.as_str() }],
+                });
+                if let Some(license) = &package.license {
+                    component["licenses"] = if is_spdx_expression(license) {
+                        json!([{ "expression": license }])
+                    } else {
+                        json!([{ "license": { "name": license } }])
+                    };
+                }
+                component
+            })
+            .collect::<Vec<_>>();
+
+        let dependencies = self
+            .packages
+            .iter()
+            .map(|record| {
+                json!({
+                    "ref": refs[&record.package_record.name],
+                    "dependsOn": dependencies(record, &names)
+                        .map(|dependency| refs[&dependency].clone())
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "serialNumber": format!("urn:uuid:{}", self.serial_number),
+            "version": 1,
+            "metadata": {
+                "timestamp": self.created.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": "rattler",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": { "type": "application", "name": self.name },
+            },
+            "components": components,
+            "dependencies": dependencies,
+        })
+    }
+}
+
+/// Returns the names of the packages in `names` that the given package depends on.
+fn dependencies<'a>(
+    record: &'a RepoDataRecord,
+    names: &'a HashSet<&PackageName>,
+) -> impl Iterator<Item = PackageName> + 'a {
+    record
+        .package_record
+        .depends
+        .iter()
+        .filter_map(|dependency| {
+            MatchSpec::from_str(dependency, ParseStrictness::Lenient)
+                .ok()?
+                .name
+        })
+        .filter(|name| names.contains(name))
+}
+
+/// Returns the package URL of a record, see
+/// <https://github.com/package-url/purl-spec/blob/master/PURL-TYPES.rst#conda>.
+fn purl(record: &RepoDataRecord) -> String {
+    let package = &record.package_record;
+    let archive_type = if record.file_name.ends_with(".conda") {
+        "conda"
+    } else {
+        "tar.bz2"
+    };
+    format!(
+        "pkg:conda/{}@{}?build={}&subdir={}&type={archive_type}",
+        package.name.as_normalized(),
+        package.version,
+        package.build,
+        package.subdir,
+    )
+}
+
+/// Returns a unique SPDX identifier for every package. Different package names can map to the same
+/// identifier (e.g. `typing_extensions` and `typing-extensions`), in which case a numeric suffix
+/// is added to the identifiers of the later packages.
+fn spdx_ids(packages: &[RepoDataRecord]) -> HashMap<&PackageName, String> {
+    let mut used = HashSet::new();
+    packages
+        .iter()
+        .map(|record| {
+            let name = &record.package_record.name;
+            let base = spdx_id(name);
+            let id = std::iter::once(base.clone())
+                .chain((2..).map(|suffix| format!("{base}-{suffix}")))
+                .find(|id| !used.contains(id))
+                .expect("there is always an unused suffix");
+            used.insert(id.clone());
+            (name, id)
+        })
+        .collect()
+}
+
+/// Returns a valid SPDX identifier for a package. Identifiers may only contain letters, numbers,
+/// `.` and `-`.
+fn spdx_id(name: &PackageName) -> String {
+    let name = name
+        .as_normalized()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    format!("SPDXRef-Package-{name}")
+}
+
+/// Commonly used SPDX license identifiers that do not contain a version.
+const UNVERSIONED_SPDX_LICENSES: &[&str] = &[
+    "MIT",
+    "ISC",
+    "Zlib",
+    "Unlicense",
+    "WTFPL",
+    "NCSA",
+    "X11",
+    "curl",
+    "Beerware",
+    "Ruby",
+    "Vim",
+];
+
+/// Returns true if the license of a package looks like an SPDX license expression. Conda packages
+/// often use free-form license descriptions like `BSD` or `Apache 2.0` which are not valid in an
+/// SPDX document.
+///
+/// Identifiers are not validated against the SPDX license list, an identifier is accepted if it
+/// contains a version (like `Apache-2.0`), is a `LicenseRef-`, or is a well-known identifier
+/// without a version.
+fn is_spdx_expression(license: &str) -> bool {
+    let mut has_license = false;
+    for token in license.split(|c: char| c.is_whitespace() || c == '(' || c == ')') {
+        match token {
+            "" | "AND" | "OR" | "WITH" => {}
+            token if is_spdx_identifier(token.trim_end_matches('+')) => {
+                has_license = true;
+            }
+            _ => return false,
+        }
+    }
+    has_license
+}
+
+fn is_spdx_identifier(token: &str) -> bool {
+    if UNVERSIONED_SPDX_LICENSES.contains(&token) {
+        return true;
+    }
+    let valid_chars = token
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == ':');
+    let starts_with_letter = token.starts_with(|c: char| c.is_ascii_alphabetic());
+    let is_versioned = token.contains('-') && token.contains(|c: char| c.is_ascii_digit());
+    valid_chars && starts_with_letter && (is_versioned || token.starts_with("LicenseRef-"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_spdx_expression, Sbom, SbomFormat};
+    use crate::prefix::Prefix;
+    use std::path::Path;
+
+    fn write_record(prefix: &Path, name: &str, license: &str, depends: &[&str]) {
+        let conda_meta = prefix.join("conda-meta");
+        std::fs::create_dir_all(&conda_meta).unwrap();
+        let record = serde_json::json!({
+            "name": name,
+            "version": "1.0",
+            "build": "h123_0",
+            "build_number": 0,
+            "depends": depends,
+            "license": license,
+            "sha256": "4c4b1ad0e0ad8e39b1af1d1aad88af0a1e5c9b8bb8b1d38e0ab1a2c1dd2a3bb7",
+            "fn": format!("{name}-1.0-h123_0.conda"),
+            "url": format!("https://conda.anaconda.org/conda-forge/linux-64/{name}-1.0-h123_0.conda"),
+            "channel": "https://conda.anaconda.org/conda-forge",
+            "subdir": "linux-64",
+            "files": [],
+            "paths_data": { "paths_version": 1, "paths": [] },
+        });
+        std::fs::write(
+            conda_meta.join(format!("{name}-1.0-h123_0.json")),
+            serde_json::to_string(&record).unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn prefix() -> (tempfile::TempDir, Sbom) {
+        let root = tempfile::tempdir().unwrap();
+        write_record(root.path(), "libfoo", "MIT", &["__glibc >=2.17"]);
+        write_record(
+            root.path(),
+            "foo_bar",
+            "BSD",
+            &["libfoo >=1", "__glibc >=2.17"],
+        );
+        let sbom = Sbom::from_prefix(&Prefix::new(root.path())).unwrap();
+        (root, sbom)
+    }
+
+    #[test]
+    fn test_spdx() {
+        let (_root, sbom) = prefix();
+        let document = sbom.to_json(SbomFormat::Spdx);
+        assert_eq!(document["spdxVersion"], "SPDX-2.3");
+
+        let packages = document["packages"].as_array().unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0]["SPDXID"], "SPDXRef-Package-foo-bar");
+        assert_eq!(packages[0]["licenseDeclared"], "NOASSERTION");
+        assert_eq!(packages[1]["licenseDeclared"], "MIT");
+        assert_eq!(
+            packages[1]["externalRefs"][0]["referenceLocator"],
+            "pkg:conda/libfoo@1.0?build=h123_0&subdir=linux-64&type=conda"
+        );
+        assert_eq!(packages[1]["checksums"][0]["algorithm"], "SHA256");
+
+        let dependencies = document["relationships"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|relationship| relationship["relationshipType"] == "DEPENDS_ON")
+            .collect::<Vec<_>>();
+        assert_eq!(dependencies.len(), 1);
+        assert_eq!(dependencies[0]["spdxElementId"], "SPDXRef-Package-foo-bar");
+        assert_eq!(
+            dependencies[0]["relatedSpdxElement"],
+            "SPDXRef-Package-libfoo"
+        );
+    }
+
+    #[test]
+    fn test_spdx_ids_are_unique() {
+        let root = tempfile::tempdir().unwrap();
+        write_record(root.path(), "typing_extensions", "MIT", &[]);
+        write_record(root.path(), "typing-extensions", "MIT", &[]);
+        write_record(root.path(), "foo", "MIT", &["typing_extensions"]);
+        let sbom = Sbom::from_prefix(&Prefix::new(root.path())).unwrap();
+        let document = sbom.to_json(SbomFormat::Spdx);
+
+        let ids = document["packages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|package| {
+                (
+                    package["name"].as_str().unwrap(),
+                    package["SPDXID"].as_str().unwrap(),
+                )
+            })
+            .collect::<std::collections::HashMap<_, _>>();
+        assert_eq!(
+            ids["typing-extensions"],
+            "SPDXRef-Package-typing-extensions"
+        );
+        assert_eq!(
+            ids["typing_extensions"],
+            "SPDXRef-Package-typing-extensions-2"
+        );
+
+        // The relationships refer to the package that is actually depended on.
+        let dependency = document["relationships"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|relationship| relationship["relationshipType"] == "DEPENDS_ON")
+            .unwrap();
+        assert_eq!(dependency["spdxElementId"], ids["foo"]);
+        assert_eq!(dependency["relatedSpdxElement"], ids["typing_extensions"]);
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let (_root, sbom) = prefix();
+        let document = sbom.to_json(SbomFormat::CycloneDx);
+        assert_eq!(document["bomFormat"], "CycloneDX");
+
+        let components = document["components"].as_array().unwrap();
+        assert_eq!(components[0]["licenses"][0]["license"]["name"], "BSD");
+        assert_eq!(components[1]["licenses"][0]["expression"], "MIT");
+
+        let dependencies = document["dependencies"].as_array().unwrap();
+        assert_eq!(dependencies[0]["dependsOn"][0], components[1]["bom-ref"]);
+        assert_eq!(dependencies[1]["dependsOn"].as_array().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_is_spdx_expression() {
+        assert!(is_spdx_expression("MIT"));
+        assert!(is_spdx_expression("Apache-2.0 OR BSD-3-Clause"));
+        assert!(is_spdx_expression(
+            "(GPL-2.0-or-later WITH Classpath-exception-2.0) AND MIT"
+        ));
+        assert!(is_spdx_expression("LicenseRef-Proprietary"));
+        assert!(!is_spdx_expression("BSD"));
+        assert!(!is_spdx_expression("Apache 2.0"));
+        assert!(!is_spdx_expression(""));
+    }
+}
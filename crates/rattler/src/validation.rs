@@ -41,6 +41,10 @@ pub enum PackageValidationError {
     /// An error occurred while reading the `index.json` file.
     #[error("failed to read 'index.json'")]
     ReadIndexJsonError(#[source] std::io::Error),
+
+    /// An error occurred while reading the manifest of a package in the package cache.
+    #[error("failed to read the manifest of the package")]
+    ReadManifestError(#[source] std::io::Error),
 }
 
 /// An error that indicates that a specific file in a package archive directory seems to be corrupted.
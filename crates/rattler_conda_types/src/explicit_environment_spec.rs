@@ -11,7 +11,13 @@
 
 use crate::{ParsePlatformError, Platform};
 use serde::{Deserialize, Serialize};
-use std::{fs::File, io::Read, path::Path, str::FromStr};
+use std::{
+    fmt::{Display, Formatter},
+    fs::File,
+    io::Read,
+    path::Path,
+    str::FromStr,
+};
 use url::Url;
 
 /// An [`ExplicitEnvironmentSpec`] represents an explicit environment specification. Packages are
@@ -150,6 +156,26 @@ impl ExplicitEnvironmentSpec {
     pub fn from_path(path: &Path) -> Result<Self, ParseExplicitEnvironmentSpecError> {
         Self::from_reader(File::open(path)?)
     }
+
+    /// Writes the explicit environment file to a file.
+    pub fn to_path(&self, path: &Path) -> Result<(), std::io::Error> {
+        std::fs::write(path, self.to_string())
+    }
+}
+
+/// Formats the specification in the format of an explicit environment file that can be parsed
+/// again with [`ExplicitEnvironmentSpec::from_str`] and installed with `conda create --file`.
+impl Display for ExplicitEnvironmentSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(platform) = self.platform {
+            writeln!(f, "# platform: {platform}")?;
+        }
+        writeln!(f, "@EXPLICIT")?;
+        for package in &self.packages {
+            writeln!(f, "{}", package.url)?;
+        }
+        Ok(())
+    }
 }
 
 impl FromStr for ExplicitEnvironmentSpec {
@@ -213,6 +239,26 @@ mod test {
         insta::assert_yaml_snapshot!(path, env);
     }
 
+    #[rstest]
+    #[case::ros_noetic_linux_64("explicit-envs/ros-noetic_linux-64.txt")]
+    #[case::xtensor_linux_64("explicit-envs/xtensor_linux-64.txt")]
+    fn test_round_trip(#[case] path: &str) {
+        let env = ExplicitEnvironmentSpec::from_path(&get_test_data_dir().join(path)).unwrap();
+        let reparsed = ExplicitEnvironmentSpec::from_str(&env.to_string()).unwrap();
+        assert_eq!(reparsed.platform, env.platform);
+        assert_eq!(
+            reparsed
+                .packages
+                .iter()
+                .map(|package| &package.url)
+                .collect::<Vec<_>>(),
+            env.packages
+                .iter()
+                .map(|package| &package.url)
+                .collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_parse_empty() {
         assert_matches!(
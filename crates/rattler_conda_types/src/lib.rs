@@ -6,6 +6,7 @@ mod build_spec;
 mod channel;
 mod channel_data;
 mod explicit_environment_spec;
+mod license;
 mod match_spec;
 mod no_arch_type;
 mod parse_mode;
@@ -30,9 +31,12 @@ pub use explicit_environment_spec::{
     ParseExplicitEnvironmentSpecError, ParsePackageArchiveHashError,
 };
 pub use generic_virtual_package::GenericVirtualPackage;
+pub use license::{
+    LicenseExpression, LicensePolicy, LicenseViolation, LicenseViolationKind, ParseLicenseError,
+};
 pub use match_spec::matcher::{StringMatcher, StringMatcherParseError};
 pub use match_spec::parse::ParseMatchSpecError;
-pub use match_spec::{MatchSpec, NamelessMatchSpec};
+pub use match_spec::{MatchSpec, MatchSpecBuilder, NamelessMatchSpec};
 pub use no_arch_type::{NoArchKind, NoArchType};
 pub use package_name::{InvalidPackageNameError, PackageName};
 pub use parse_mode::ParseStrictness;
@@ -41,7 +45,9 @@ pub use prefix_record::PrefixRecord;
 pub use repo_data::patches::{PackageRecordPatch, PatchInstructions, RepoDataPatch};
 pub use repo_data::sharded::{Shard, ShardedRepodata, ShardedSubdirInfo};
 pub use repo_data::{
-    compute_package_url, ChannelInfo, ConvertSubdirError, PackageRecord, RepoData,
+    compute_package_url, ChannelInfo, ChannelReport, ConvertSubdirError, DependencyCycleError,
+    DependencyGraph, DependencyKind, DuplicateSha256, OrphanedDependency, PackageRecord,
+    RecordFilter, RecordLocation, RepoData, SubdirStatistics,
 };
 pub use repo_data_record::RepoDataRecord;
 pub use run_export::RunExportKind;
@@ -0,0 +1,685 @@
+//! Parsing and normalization of the `license` field of packages as [SPDX license expressions](https://spdx.github.io/spdx-spec/v2.3/SPDX-license-expressions/)
+//! and checking sets of packages against a [`LicensePolicy`].
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::{MatchSpec, PackageName, PackageRecord, ParseStrictness, StringMatcher};
+
+/// Commonly used license names that are not valid SPDX identifiers together with the identifier
+/// they refer to. Keys are lowercase.
+const LICENSE_ALIASES: &[(&str, &str)] = &[
+    ("mit license", "MIT"),
+    ("apache 2.0", "Apache-2.0"),
+    ("apache-2", "Apache-2.0"),
+    ("apache 2", "Apache-2.0"),
+    ("apache license 2.0", "Apache-2.0"),
+    ("apache license, version 2.0", "Apache-2.0"),
+    ("asl 2.0", "Apache-2.0"),
+    ("bsd 2-clause", "BSD-2-Clause"),
+    ("bsd-2", "BSD-2-Clause"),
+    ("2-clause bsd", "BSD-2-Clause"),
+    ("bsd 3-clause", "BSD-3-Clause"),
+    ("bsd-3", "BSD-3-Clause"),
+    ("3-clause bsd", "BSD-3-Clause"),
+    ("gplv2", "GPL-2.0-only"),
+    ("gpl-2", "GPL-2.0-only"),
+    ("gpl-2.0", "GPL-2.0-only"),
+    ("gplv2+", "GPL-2.0-or-later"),
+    ("gpl-2.0+", "GPL-2.0-or-later"),
+    ("gplv3", "GPL-3.0-only"),
+    ("gpl-3", "GPL-3.0-only"),
+    ("gpl-3.0", "GPL-3.0-only"),
+    ("gplv3+", "GPL-3.0-or-later"),
+    ("gpl-3.0+", "GPL-3.0-or-later"),
+    ("lgpl-2.1", "LGPL-2.1-only"),
+    ("lgpl-2.1+", "LGPL-2.1-or-later"),
+    ("lgpl-3", "LGPL-3.0-only"),
+    ("lgpl-3.0", "LGPL-3.0-only"),
+    ("lgpl-3.0+", "LGPL-3.0-or-later"),
+    ("mpl 2.0", "MPL-2.0"),
+    ("mpl-2", "MPL-2.0"),
+    ("psf", "PSF-2.0"),
+    ("public domain", "LicenseRef-Public-Domain"),
+];
+
+/// SPDX identifiers in their canonical casing. Identifiers in a license expression that match one
+/// of these case-insensitively are rewritten to the canonical casing.
+const KNOWN_LICENSES: &[&str] = &[
+    "0BSD",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+    "Apache-2.0",
+    "Artistic-2.0",
+    "BSD-2-Clause",
+    "BSD-3-Clause",
+    "BSL-1.0",
+    "CC0-1.0",
+    "CC-BY-4.0",
+    "EPL-2.0",
+    "GPL-2.0-only",
+    "GPL-2.0-or-later",
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "ISC",
+    "LGPL-2.1-only",
+    "LGPL-2.1-or-later",
+    "LGPL-3.0-only",
+    "LGPL-3.0-or-later",
+    "MIT",
+    "MPL-2.0",
+    "NCSA",
+    "PSF-2.0",
+    "Python-2.0",
+    "Unlicense",
+    "Zlib",
+];
+
+/// A parsed and normalized SPDX license expression.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LicenseExpression {
+    /// A single license, e.g. `GPL-2.0-only WITH Classpath-exception-2.0`.
+    License {
+        /// The SPDX identifier of the license, or a `LicenseRef-` reference.
+        id: String,
+
+        /// True if the identifier was followed by `+`.
+        or_later: bool,
+
+        /// The exception that was specified with `WITH`.
+        exception: Option<String>,
+    },
+
+    /// All of the expressions apply.
+    And(Vec<LicenseExpression>),
+
+    /// The user can choose one of the expressions.
+    Or(Vec<LicenseExpression>),
+}
+
+/// An error that can occur when parsing a [`LicenseExpression`].
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum ParseLicenseError {
+    /// The license is empty.
+    #[error("the license expression is empty")]
+    Empty,
+
+    /// A license identifier contains characters that are not allowed.
+    #[error("'{0}' is not a valid license identifier")]
+    InvalidIdentifier(String),
+
+    /// A token was encountered where it was not expected.
+    #[error("unexpected '{0}' in license expression")]
+    UnexpectedToken(String),
+
+    /// The expression ended while more input was expected.
+    #[error("unexpected end of license expression")]
+    UnexpectedEnd,
+}
+
+impl LicenseExpression {
+    /// Returns the identifiers of all licenses in the expression.
+    pub fn license_ids(&self) -> Vec<&str> {
+        match self {
+            Self::License { id, .. } => vec![id.as_str()],
+            Self::And(expressions) | Self::Or(expressions) => expressions
+                .iter()
+                .flat_map(LicenseExpression::license_ids)
+                .collect(),
+        }
+    }
+
+    /// Returns true if the expression can be satisfied using only licenses for which `allowed`
+    /// returns true. For `OR` expressions one of the alternatives must be allowed, for `AND`
+    /// expressions all of them.
+    pub fn is_satisfied_by(&self, allowed: &impl Fn(&str) -> bool) -> bool {
+        match self {
+            Self::License { id, .. } => allowed(id),
+            Self::And(expressions) => expressions.iter().all(|e| e.is_satisfied_by(allowed)),
+            Self::Or(expressions) => expressions.iter().any(|e| e.is_satisfied_by(allowed)),
+        }
+    }
+
+    fn fmt_nested(&self, f: &mut Formatter<'_>, parenthesize_or: bool) -> std::fmt::Result {
+        match self {
+            Self::License {
+                id,
+                or_later,
+                exception,
+            } => {
+                write!(f, "{id}")?;
+                if *or_later {
+                    write!(f, "+")?;
+                }
+                if let Some(exception) = exception {
+                    write!(f, " WITH {exception}")?;
+                }
+                Ok(())
+            }
+            Self::And(expressions) => {
+                for (idx, expression) in expressions.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, " AND ")?;
+                    }
+                    expression.fmt_nested(f, true)?;
+                }
+                Ok(())
+            }
+            Self::Or(expressions) => {
+                if parenthesize_or {
+                    write!(f, "(")?;
+                }
+                for (idx, expression) in expressions.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, " OR ")?;
+                    }
+                    expression.fmt_nested(f, false)?;
+                }
+                if parenthesize_or {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for LicenseExpression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_nested(f, false)
+    }
+}
+
+impl FromStr for LicenseExpression {
+    type Err = ParseLicenseError;
+
+    /// Parses a license expression. Besides valid SPDX expressions this also accepts lowercase
+    /// operators, identifiers in the wrong casing and a number of commonly used aliases like
+    /// `Apache 2.0` or `BSD 3-Clause`, which are all normalized.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ParseLicenseError::Empty);
+        }
+
+        let lowercase = s.to_lowercase();
+        if let Some((_, id)) = LICENSE_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == lowercase)
+        {
+            return Ok(Self::License {
+                id: (*id).to_string(),
+                or_later: false,
+                exception: None,
+            });
+        }
+
+        let tokens = tokenize(s);
+        let mut parser = Parser { tokens, pos: 0 };
+        let expression = parser.parse_or()?;
+        match parser.next() {
+            None => Ok(expression),
+            Some(token) => Err(ParseLicenseError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (idx, c) in s.char_indices() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if let Some(start) = start.take() {
+                tokens.push(&s[start..idx]);
+            }
+            if !c.is_whitespace() {
+                tokens.push(&s[idx..idx + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(idx);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&s[start..]);
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn next_is_operator(&self, operator: &str) -> bool {
+        self.peek()
+            .is_some_and(|token| token.eq_ignore_ascii_case(operator))
+    }
+
+    fn parse_or(&mut self) -> Result<LicenseExpression, ParseLicenseError> {
+        let mut expressions = vec![self.parse_and()?];
+        while self.next_is_operator("OR") {
+            self.pos += 1;
+            expressions.push(self.parse_and()?);
+        }
+        Ok(flatten(expressions, LicenseExpression::Or))
+    }
+
+    fn parse_and(&mut self) -> Result<LicenseExpression, ParseLicenseError> {
+        let mut expressions = vec![self.parse_primary()?];
+        while self.next_is_operator("AND") {
+            self.pos += 1;
+            expressions.push(self.parse_primary()?);
+        }
+        Ok(flatten(expressions, LicenseExpression::And))
+    }
+
+    fn parse_primary(&mut self) -> Result<LicenseExpression, ParseLicenseError> {
+        match self.next().ok_or(ParseLicenseError::UnexpectedEnd)? {
+            "(" => {
+                let expression = self.parse_or()?;
+                match self.next() {
+                    Some(")") => Ok(expression),
+                    Some(token) => Err(ParseLicenseError::UnexpectedToken(token.to_string())),
+                    None => Err(ParseLicenseError::UnexpectedEnd),
+                }
+            }
+            token
+                if token == ")"
+                    || ["AND", "OR", "WITH"]
+                        .iter()
+                        .any(|operator| token.eq_ignore_ascii_case(operator)) =>
+            {
+                Err(ParseLicenseError::UnexpectedToken(token.to_string()))
+            }
+            token => {
+                let (id, or_later) = match token.strip_suffix('+') {
+                    Some(id) => (id, true),
+                    None => (token, false),
+                };
+                let (id, or_later) = normalize_identifier(id, or_later)?;
+                let exception = if self.next_is_operator("WITH") {
+                    self.pos += 1;
+                    let exception = self.next().ok_or(ParseLicenseError::UnexpectedEnd)?;
+                    validate_identifier(exception)?;
+                    Some(exception.to_string())
+                } else {
+                    None
+                };
+                Ok(LicenseExpression::License {
+                    id,
+                    or_later,
+                    exception,
+                })
+            }
+        }
+    }
+}
+
+/// Combines the expressions with an operator, merging nested operators of the same kind.
+fn flatten(
+    expressions: Vec<LicenseExpression>,
+    operator: fn(Vec<LicenseExpression>) -> LicenseExpression,
+) -> LicenseExpression {
+    if expressions.len() == 1 {
+        return expressions
+            .into_iter()
+            .next()
+            .expect("there is one element");
+    }
+    let combined = operator(Vec::new());
+    let mut result = Vec::new();
+    for expression in expressions {
+        match (&combined, expression) {
+            (LicenseExpression::And(_), LicenseExpression::And(inner))
+            | (LicenseExpression::Or(_), LicenseExpression::Or(inner)) => result.extend(inner),
+            (_, expression) => result.push(expression),
+        }
+    }
+    operator(result)
+}
+
+fn validate_identifier(id: &str) -> Result<(), ParseLicenseError> {
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == ':')
+    {
+        return Err(ParseLicenseError::InvalidIdentifier(id.to_string()));
+    }
+    Ok(())
+}
+
+fn normalize_identifier(id: &str, or_later: bool) -> Result<(String, bool), ParseLicenseError> {
+    validate_identifier(id)?;
+
+    // Deprecated identifiers like `GPL-2.0` or `GPL-2.0+` are rewritten to their `-only` and
+    // `-or-later` counterparts.
+    let lookup = if or_later {
+        format!("{id}+").to_lowercase()
+    } else {
+        id.to_lowercase()
+    };
+    if let Some((_, canonical)) = LICENSE_ALIASES.iter().find(|(alias, _)| *alias == lookup) {
+        return Ok(((*canonical).to_string(), false));
+    }
+
+    let id = KNOWN_LICENSES
+        .iter()
+        .find(|known| known.eq_ignore_ascii_case(id))
+        .map_or_else(|| id.to_string(), |known| (*known).to_string());
+    Ok((id, or_later))
+}
+
+impl PackageRecord {
+    /// Parses the `license` field as an SPDX license expression. Returns `None` if the record
+    /// does not specify a license.
+    pub fn spdx_license(&self) -> Option<Result<LicenseExpression, ParseLicenseError>> {
+        self.license.as_deref().map(LicenseExpression::from_str)
+    }
+}
+
+/// A set of rules that determine which licenses are acceptable for the packages in an
+/// environment.
+///
+/// A license is acceptable if it is not denied and, when any allowed patterns are specified,
+/// matches one of them. Patterns are matched against the normalized SPDX identifiers.
+///
+/// ```rust
+/// use rattler_conda_types::{LicensePolicy, StringMatcher};
+/// use std::str::FromStr;
+///
+/// let policy = LicensePolicy::new()
+///     .with_denied(StringMatcher::from_str("*GPL*").unwrap())
+///     .with_allow_unknown(false);
+/// ```
+#[derive(Debug, Clone)]
+pub struct LicensePolicy {
+    allowed: Vec<StringMatcher>,
+    denied: Vec<StringMatcher>,
+    allow_unknown: bool,
+}
+
+impl Default for LicensePolicy {
+    fn default() -> Self {
+        Self {
+            allowed: Vec::new(),
+            denied: Vec::new(),
+            allow_unknown: true,
+        }
+    }
+}
+
+/// The reason why a package violates a [`LicensePolicy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LicenseViolationKind {
+    /// The package does not specify a license.
+    Missing,
+
+    /// The license of the package could not be parsed.
+    Invalid(ParseLicenseError),
+
+    /// The license expression cannot be satisfied with the allowed licenses.
+    NotAllowed(LicenseExpression),
+}
+
+/// A package that violates a [`LicensePolicy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LicenseViolation {
+    /// The name of the package.
+    pub package: PackageName,
+
+    /// The license as specified in the package record.
+    pub license: Option<String>,
+
+    /// Why the package violates the policy.
+    pub kind: LicenseViolationKind,
+
+    /// The chain of dependencies through which the package is included in the environment,
+    /// starting at a package that no other package depends on and ending with the package itself.
+    pub dependency_chain: Vec<PackageName>,
+}
+
+impl LicensePolicy {
+    /// Constructs a policy that accepts every license.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a pattern of licenses that are allowed. Once a pattern is added, licenses that do not
+    /// match any of the allowed patterns are rejected.
+    #[must_use]
+    pub fn with_allowed(mut self, pattern: StringMatcher) -> Self {
+        self.allowed.push(pattern);
+        self
+    }
+
+    /// Adds a pattern of licenses that are denied. Denied patterns take precedence over allowed
+    /// patterns.
+    #[must_use]
+    pub fn with_denied(mut self, pattern: StringMatcher) -> Self {
+        self.denied.push(pattern);
+        self
+    }
+
+    /// Sets whether packages with a missing or unparsable license are accepted. Defaults to
+    /// `true`.
+    #[must_use]
+    pub fn with_allow_unknown(mut self, allow_unknown: bool) -> Self {
+        self.allow_unknown = allow_unknown;
+        self
+    }
+
+    /// Returns true if the license identifier is acceptable under this policy.
+    pub fn is_allowed(&self, id: &str) -> bool {
+        !self.denied.iter().any(|pattern| pattern.matches(id))
+            && (self.allowed.is_empty() || self.allowed.iter().any(|pattern| pattern.matches(id)))
+    }
+
+    /// Checks a single record against the policy.
+    pub fn check_record(&self, record: &PackageRecord) -> Option<LicenseViolationKind> {
+        let kind = match record.spdx_license() {
+            None => LicenseViolationKind::Missing,
+            Some(Err(err)) => LicenseViolationKind::Invalid(err),
+            Some(Ok(expression)) => {
+                return (!expression.is_satisfied_by(&|id| self.is_allowed(id)))
+                    .then_some(LicenseViolationKind::NotAllowed(expression));
+            }
+        };
+        (!self.allow_unknown).then_some(kind)
+    }
+
+    /// Checks all records of a solved environment against the policy and returns the records that
+    /// violate it, together with the chain of dependencies that pulls them into the environment.
+    pub fn check<T: AsRef<PackageRecord>>(&self, records: &[T]) -> Vec<LicenseViolation> {
+        let records = records.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        let parents = dependency_parents(&records);
+
+        records
+            .iter()
+            .filter_map(|record| {
+                let kind = self.check_record(record)?;
+                let mut dependency_chain = vec![record.name.clone()];
+                while let Some(parent) = parents.get(dependency_chain.last().expect("not empty")) {
+                    dependency_chain.push(parent.clone());
+                }
+                dependency_chain.reverse();
+                Some(LicenseViolation {
+                    package: record.name.clone(),
+                    license: record.license.clone(),
+                    kind,
+                    dependency_chain,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Performs a breadth-first search from the packages that no other package depends on and
+/// returns, for each reachable package, the package through which it was first reached.
+fn dependency_parents(records: &[&PackageRecord]) -> HashMap<PackageName, PackageName> {
+    let names = records
+        .iter()
+        .map(|record| &record.name)
+        .collect::<HashSet<_>>();
+    let dependencies = records
+        .iter()
+        .map(|record| {
+            let depends = record
+                .depends
+                .iter()
+                .filter_map(|dep| {
+                    MatchSpec::from_str(dep, ParseStrictness::Lenient)
+                        .ok()?
+                        .name
+                })
+                .filter(|name| names.contains(name))
+                .collect::<Vec<_>>();
+            (&record.name, depends)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let dependents = dependencies.values().flatten().collect::<HashSet<_>>();
+    let mut queue = records
+        .iter()
+        .map(|record| &record.name)
+        .filter(|name| !dependents.contains(name))
+        .collect::<VecDeque<_>>();
+    let mut visited = queue.iter().copied().collect::<HashSet<_>>();
+    let mut parents = HashMap::new();
+    while let Some(name) = queue.pop_front() {
+        for dependency in dependencies.get(name).into_iter().flatten() {
+            if visited.insert(dependency) {
+                parents.insert(dependency.clone(), name.clone());
+                queue.push_back(dependency);
+            }
+        }
+    }
+    parents
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::{LicenseExpression, LicensePolicy, LicenseViolationKind, ParseLicenseError};
+    use crate::{PackageName, PackageRecord, StringMatcher, Version};
+
+    #[test]
+    fn test_parse_license_expression() {
+        let normalized = |s: &str| LicenseExpression::from_str(s).unwrap().to_string();
+        assert_eq!(normalized("MIT"), "MIT");
+        assert_eq!(normalized("mit"), "MIT");
+        assert_eq!(normalized("Apache 2.0"), "Apache-2.0");
+        assert_eq!(normalized("BSD 3-Clause"), "BSD-3-Clause");
+        assert_eq!(normalized("GPL-2.0+"), "GPL-2.0-or-later");
+        assert_eq!(normalized("gpl-3.0"), "GPL-3.0-only");
+        assert_eq!(normalized("mit or apache-2.0"), "MIT OR Apache-2.0");
+        assert_eq!(
+            normalized("(MIT OR Apache-2.0) AND Zlib"),
+            "(MIT OR Apache-2.0) AND Zlib"
+        );
+        assert_eq!(normalized("MIT AND (Zlib AND ISC)"), "MIT AND Zlib AND ISC");
+        assert_eq!(
+            normalized("GPL-2.0-only with Classpath-exception-2.0"),
+            "GPL-2.0-only WITH Classpath-exception-2.0"
+        );
+        assert_eq!(
+            normalized("LicenseRef-Proprietary"),
+            "LicenseRef-Proprietary"
+        );
+
+        assert_eq!(
+            LicenseExpression::from_str("  "),
+            Err(ParseLicenseError::Empty)
+        );
+        assert_eq!(
+            LicenseExpression::from_str("MIT AND"),
+            Err(ParseLicenseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            LicenseExpression::from_str("(MIT"),
+            Err(ParseLicenseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            LicenseExpression::from_str("MIT)"),
+            Err(ParseLicenseError::UnexpectedToken(String::from(")")))
+        );
+        assert_eq!(
+            LicenseExpression::from_str("BSD, MIT"),
+            Err(ParseLicenseError::InvalidIdentifier(String::from("BSD,")))
+        );
+    }
+
+    fn record(name: &str, license: Option<&str>, depends: &[&str]) -> PackageRecord {
+        PackageRecord {
+            license: license.map(str::to_string),
+            depends: depends.iter().map(ToString::to_string).collect(),
+            ..PackageRecord::new(
+                PackageName::new_unchecked(name),
+                Version::from_str("1.0").unwrap(),
+                String::from("0"),
+            )
+        }
+    }
+
+    #[test]
+    fn test_license_policy() {
+        let records = vec![
+            record("app", Some("MIT"), &["lib >=1", "other"]),
+            record("lib", Some("Apache-2.0"), &["gpl-lib"]),
+            record("other", Some("MIT OR GPL-3.0-only"), &[]),
+            record("gpl-lib", Some("GPL-3.0-or-later"), &["unknown"]),
+            record("unknown", None, &[]),
+        ];
+
+        let policy = LicensePolicy::new().with_denied(StringMatcher::from_str("*GPL*").unwrap());
+        let violations = policy.check(&records);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].package.as_normalized(), "gpl-lib");
+        assert_eq!(
+            violations[0]
+                .dependency_chain
+                .iter()
+                .map(PackageName::as_normalized)
+                .collect::<Vec<_>>(),
+            ["app", "lib", "gpl-lib"]
+        );
+
+        let policy = LicensePolicy::new()
+            .with_allowed(StringMatcher::from_str("MIT").unwrap())
+            .with_allow_unknown(false);
+        let violations = policy
+            .check(&records)
+            .into_iter()
+            .map(|violation| {
+                (
+                    violation.package.as_normalized().to_string(),
+                    violation.kind,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(violations.len(), 3);
+        assert_eq!(violations[0].0, "lib");
+        assert_eq!(violations[1].0, "gpl-lib");
+        assert_eq!(
+            violations[2],
+            (String::from("unknown"), LicenseViolationKind::Missing)
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use rattler_digest::{Md5Hash, Sha256Hash};
+
+use super::{matcher::StringMatcher, MatchSpec};
+use crate::{build_spec::BuildNumberSpec, Channel, PackageName, VersionSpec};
+
+/// A struct to construct a [`MatchSpec`] field by field. Use [`MatchSpec::builder`] to create
+/// one.
+///
+/// ```rust
+/// use rattler_conda_types::{MatchSpec, PackageName, ParseStrictness::Strict, VersionSpec};
+///
+/// let spec = MatchSpec::builder()
+///     .with_name(PackageName::new_unchecked("foo"))
+///     .with_version(VersionSpec::from_str(">=1.0", Strict).unwrap())
+///     .with_subdir("linux-64")
+///     .finish();
+/// assert_eq!(spec.to_string(), "foo >=1.0[subdir=linux-64]");
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct MatchSpecBuilder {
+    spec: MatchSpec,
+}
+
+impl MatchSpecBuilder {
+    /// Constructs a builder for a [`MatchSpec`] that matches any package.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name of the package.
+    #[must_use]
+    pub fn with_name(mut self, name: PackageName) -> Self {
+        self.spec.name = Some(name);
+        self
+    }
+
+    /// Sets the version spec of the package.
+    #[must_use]
+    pub fn with_version(mut self, version: VersionSpec) -> Self {
+        self.spec.version = Some(version);
+        self
+    }
+
+    /// Sets the build string of the package.
+    #[must_use]
+    pub fn with_build(mut self, build: StringMatcher) -> Self {
+        self.spec.build = Some(build);
+        self
+    }
+
+    /// Sets the build number of the package.
+    #[must_use]
+    pub fn with_build_number(mut self, build_number: BuildNumberSpec) -> Self {
+        self.spec.build_number = Some(build_number);
+        self
+    }
+
+    /// Sets the file name of the package.
+    #[must_use]
+    pub fn with_file_name(mut self, file_name: impl Into<String>) -> Self {
+        self.spec.file_name = Some(file_name.into());
+        self
+    }
+
+    /// Sets the channel of the package.
+    #[must_use]
+    pub fn with_channel(mut self, channel: impl Into<Arc<Channel>>) -> Self {
+        self.spec.channel = Some(channel.into());
+        self
+    }
+
+    /// Sets the subdir of the channel.
+    #[must_use]
+    pub fn with_subdir(mut self, subdir: impl Into<String>) -> Self {
+        self.spec.subdir = Some(subdir.into());
+        self
+    }
+
+    /// Sets the namespace of the package.
+    #[must_use]
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.spec.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Sets the md5 hash of the package.
+    #[must_use]
+    pub fn with_md5(mut self, md5: Md5Hash) -> Self {
+        self.spec.md5 = Some(md5);
+        self
+    }
+
+    /// Sets the sha256 hash of the package.
+    #[must_use]
+    pub fn with_sha256(mut self, sha256: Sha256Hash) -> Self {
+        self.spec.sha256 = Some(sha256);
+        self
+    }
+
+    /// Returns the constructed [`MatchSpec`].
+    pub fn finish(self) -> MatchSpec {
+        self.spec
+    }
+}
+
+impl MatchSpec {
+    /// Constructs a new [`MatchSpecBuilder`] to construct a [`MatchSpec`] field by field.
+    pub fn builder() -> MatchSpecBuilder {
+        MatchSpecBuilder::new()
+    }
+}
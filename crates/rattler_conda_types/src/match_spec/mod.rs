@@ -9,9 +9,11 @@ use std::sync::Arc;
 use crate::Channel;
 use crate::ChannelConfig;
 
+mod builder;
 pub mod matcher;
 pub mod parse;
 
+pub use builder::MatchSpecBuilder;
 use matcher::StringMatcher;
 
 /// A [`MatchSpec`] is, fundamentally, a query language for conda packages. Any of the fields that
@@ -55,10 +57,15 @@ use matcher::StringMatcher;
 ///    `channel` with a `/` separator.  Otherwise, `subdir` is included in the key-value brackets.
 /// 7. Key-value brackets can be delimited by comma, space, or comma+space.  Value can optionally
 ///    be wrapped in single or double quotes, but must be wrapped if `value` contains a comma,
-///    space, or equal sign.  The canonical format uses comma delimiters and single quotes.
+///    space, or equal sign.  The canonical format uses comma+space delimiters, sorts the keys
+///    alphabetically and uses single quotes.
 /// 8. When constructing a `MatchSpec` instance from a string, any key-value pair given
 ///    inside the key-value brackets overrides any matching parameter given outside the brackets.
 ///
+/// The [`Display`] implementation renders this canonical form, except that the version and build
+/// string are separated from the name by spaces. The result can always be parsed back into an equal
+/// [`MatchSpec`]. Use [`MatchSpec::builder`] to construct a [`MatchSpec`] field by field.
+///
 /// When `MatchSpec` attribute values are simple strings, the are interpreted using the
 /// following conventions:
 ///   - If the string begins with `^` and ends with `$`, it is converted to a regex.
@@ -157,7 +164,7 @@ impl Display for MatchSpec {
 
         if let Some(namespace) = &self.namespace {
             write!(f, ":{namespace}:")?;
-        } else if self.channel.is_some() || self.subdir.is_some() {
+        } else if self.channel.is_some() {
             write!(f, "::")?;
         }
 
@@ -170,26 +177,78 @@ impl Display for MatchSpec {
             write!(f, " {version}")?;
         }
 
-        if let Some(build) = &self.build {
-            write!(f, " {build}")?;
-        }
+        write_build_and_keys(
+            f,
+            self.version.is_some(),
+            self.build.as_ref(),
+            self.build_number.as_ref(),
+            self.file_name.as_deref(),
+            self.md5.as_ref(),
+            self.sha256.as_ref(),
+            self.subdir.as_deref().filter(|_| self.channel.is_none()),
+        )
+    }
+}
 
-        let mut keys = Vec::new();
+/// Writes the build string and the key-value brackets of a match spec in their canonical form.
+///
+/// The build string is written after the version. If there is no version it is written inside the
+/// brackets instead, otherwise it would be read back as a version. The keys inside the brackets are
+/// sorted alphabetically and values that contain a delimiter are single quoted.
+#[allow(clippy::too_many_arguments)]
+fn write_build_and_keys(
+    f: &mut Formatter<'_>,
+    has_version: bool,
+    build: Option<&StringMatcher>,
+    build_number: Option<&BuildNumberSpec>,
+    file_name: Option<&str>,
+    md5: Option<&Md5Hash>,
+    sha256: Option<&Sha256Hash>,
+    subdir: Option<&str>,
+) -> std::fmt::Result {
+    let mut keys = Vec::new();
+
+    match build {
+        Some(build) if has_version => write!(f, " {build}")?,
+        Some(build) => keys.push(("build", build.to_string())),
+        None => {}
+    }
 
-        if let Some(md5) = &self.md5 {
-            keys.push(format!("md5={md5:x}"));
-        }
+    if let Some(build_number) = build_number {
+        keys.push(("build_number", build_number.to_string()));
+    }
 
-        if let Some(sha256) = &self.sha256 {
-            keys.push(format!("sha256={sha256:x}"));
-        }
+    if let Some(file_name) = file_name {
+        keys.push(("fn", file_name.to_string()));
+    }
 
-        if !keys.is_empty() {
-            write!(f, "[{}]", keys.join(", "))?;
-        }
+    if let Some(md5) = md5 {
+        keys.push(("md5", format!("{md5:x}")));
+    }
+
+    if let Some(sha256) = sha256 {
+        keys.push(("sha256", format!("{sha256:x}")));
+    }
+
+    if let Some(subdir) = subdir {
+        keys.push(("subdir", subdir.to_string()));
+    }
 
-        Ok(())
+    if !keys.is_empty() {
+        let keys = keys
+            .into_iter()
+            .map(|(key, value)| {
+                if value.contains([',', ' ', '=', '[', ']', '"']) {
+                    format!("{key}='{value}'")
+                } else {
+                    format!("{key}={value}")
+                }
+            })
+            .collect::<Vec<_>>();
+        write!(f, "[{}]", keys.join(", "))?;
     }
+
+    Ok(())
 }
 
 impl MatchSpec {
@@ -332,25 +391,16 @@ impl Display for NamelessMatchSpec {
             None => write!(f, "*")?,
         }
 
-        if let Some(build) = &self.build {
-            write!(f, " {build}")?;
-        }
-
-        let mut keys = Vec::new();
-
-        if let Some(md5) = &self.md5 {
-            keys.push(format!("md5={md5:x}"));
-        }
-
-        if let Some(sha256) = &self.sha256 {
-            keys.push(format!("sha256={sha256:x}"));
-        }
-
-        if !keys.is_empty() {
-            write!(f, "[{}]", keys.join(", "))?;
-        }
-
-        Ok(())
+        write_build_and_keys(
+            f,
+            true,
+            self.build.as_ref(),
+            self.build_number.as_ref(),
+            self.file_name.as_deref(),
+            self.md5.as_ref(),
+            self.sha256.as_ref(),
+            self.subdir.as_deref(),
+        )
     }
 }
 
@@ -418,7 +468,8 @@ mod tests {
     use rattler_digest::{parse_digest_from_hex, Md5, Sha256};
 
     use crate::{
-        MatchSpec, NamelessMatchSpec, PackageName, PackageRecord, ParseStrictness::*, Version,
+        BuildNumberSpec, Channel, ChannelConfig, MatchSpec, NamelessMatchSpec, PackageName,
+        PackageRecord, ParseStrictness::*, StringMatcher, Version, VersionSpec,
     };
     use insta::assert_snapshot;
     use std::hash::{Hash, Hasher};
@@ -441,6 +492,59 @@ mod tests {
         assert_eq!(spec, rebuild_spec);
     }
 
+    #[test]
+    fn test_matchspec_canonical_round_trip() {
+        let specs = [
+            "foo",
+            "foo >=1.0,<2",
+            "foo[build=py37_0]",
+            "foo ==1.0 py37_0[build_number='>=3', fn=foo-1.0-py37_0.conda]",
+            "conda-forge/linux-64::foo 1.0.*",
+            "foo[subdir=linux-64]",
+            "foo[md5=dede6252c964db3f3e41c7d30d07f6bf, sha256=aaac4bc9c6916ecc0e33137431645b029ade22190c7144eead61446dcbcc6f97]",
+        ];
+        for spec in specs {
+            let parsed = MatchSpec::from_str(spec, Strict).unwrap();
+            assert_eq!(parsed.to_string(), spec);
+            assert_eq!(
+                MatchSpec::from_str(&parsed.to_string(), Strict).unwrap(),
+                parsed
+            );
+        }
+
+        // Keys are always rendered in the same order, regardless of the input.
+        let spec = MatchSpec::from_str(
+            "foo[sha256=aaac4bc9c6916ecc0e33137431645b029ade22190c7144eead61446dcbcc6f97, fn=foo.conda, build_number=1]",
+            Strict,
+        )
+        .unwrap();
+        assert_eq!(
+            spec.to_string(),
+            "foo[build_number='==1', fn=foo.conda, sha256=aaac4bc9c6916ecc0e33137431645b029ade22190c7144eead61446dcbcc6f97]"
+        );
+    }
+
+    #[test]
+    fn test_matchspec_builder() {
+        let channel_config = ChannelConfig::default_with_root_dir(std::env::current_dir().unwrap());
+        let spec = MatchSpec::builder()
+            .with_name(PackageName::new_unchecked("foo"))
+            .with_version(VersionSpec::from_str("1.0.*", Strict).unwrap())
+            .with_build(StringMatcher::from_str("py*").unwrap())
+            .with_build_number(BuildNumberSpec::from_str(">=2").unwrap())
+            .with_channel(Channel::from_str("conda-forge", &channel_config).unwrap())
+            .with_subdir("linux-64")
+            .finish();
+        assert_eq!(
+            spec.to_string(),
+            "conda-forge/linux-64::foo 1.0.* py*[build_number='>=2']"
+        );
+        assert_eq!(
+            MatchSpec::from_str(&spec.to_string(), Strict).unwrap(),
+            spec
+        );
+    }
+
     #[test]
     fn test_hash_match() {
         let spec1 = MatchSpec::from_str("tensorflow 2.6.*", Strict).unwrap();
@@ -230,6 +230,7 @@ fn parse_bracket_vec_into_components(
                 );
             }
             "fn" => match_spec.file_name = Some(value.to_string()),
+            "subdir" => match_spec.subdir = Some(value.to_string()),
             _ => Err(ParseMatchSpecError::InvalidBracketKey(key.to_owned()))?,
         }
     }
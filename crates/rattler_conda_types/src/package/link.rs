@@ -11,6 +11,11 @@ pub struct PythonEntryPoints {
     /// A list of commands that should execute certain python commands.
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub entry_points: Vec<EntryPoint>,
+
+    /// Like `entry_points` but for graphical applications. On Windows these commands are started
+    /// without a console window.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gui_entry_points: Vec<EntryPoint>,
 }
 
 /// Links for specific types of noarch packages.
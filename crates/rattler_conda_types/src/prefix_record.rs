@@ -169,6 +169,12 @@ pub struct PrefixRecord {
     /// currently another spec was used. Note: conda seems to serialize a "None" string value instead of `null`.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub requested_spec: Option<String>,
+
+    /// Absolute paths of the menu shortcuts (e.g. `.desktop` files or application bundles) that
+    /// were created outside of the prefix when the package was installed. These are removed when
+    /// the package is uninstalled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub installed_system_menus: Vec<PathBuf>,
 }
 
 impl PrefixRecord {
@@ -199,6 +205,7 @@ impl PrefixRecord {
             paths_data: paths.into(),
             link,
             requested_spec,
+            installed_system_menus: Vec::new(),
         }
     }
 
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use petgraph::{
+    algo::toposort,
+    graph::{DiGraph, NodeIndex},
+    visit::{Bfs, EdgeFiltered, EdgeRef, Reversed},
+    Direction,
+};
+use thiserror::Error;
+
+use crate::{MatchSpec, PackageName, PackageRecord, ParseStrictness};
+
+/// The kind of relation an edge in a [`DependencyGraph`] represents.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DependencyKind {
+    /// The source package depends on the target package.
+    Depends,
+
+    /// The source package constrains the target package.
+    Constrains,
+}
+
+/// Error that is returned when a [`DependencyGraph`] cannot be ordered because its dependencies
+/// form a cycle.
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+#[error("the dependencies of '{}' form a cycle", .0.as_source())]
+pub struct DependencyCycleError(pub PackageName);
+
+/// A graph of packages and the relations between them, built from records that were solved
+/// together, e.g. [`crate::RepoDataRecord`]s or installed [`crate::PrefixRecord`]s.
+///
+/// Each package is a node. An edge from `a` to `b` is added for every spec in the `depends` or
+/// `constrains` of `a` that names `b` and that `b` matches. The underlying [`petgraph`] graph is
+/// available through [`DependencyGraph::graph`] for algorithms not covered here.
+///
+/// Note that the records are expected to have unique names.
+#[derive(Debug, Clone)]
+pub struct DependencyGraph<T> {
+    graph: DiGraph<T, DependencyKind>,
+    nodes: HashMap<PackageName, NodeIndex>,
+}
+
+impl<T: AsRef<PackageRecord>> DependencyGraph<T> {
+    /// Builds the graph from a set of records.
+    ///
+    /// The graph is deterministic, it does not depend on the order of `records`.
+    pub fn from_records(records: impl IntoIterator<Item = T>) -> Self {
+        let mut records = records.into_iter().collect::<Vec<_>>();
+        records.sort_by(|a, b| a.as_ref().name.cmp(&b.as_ref().name));
+
+        let mut graph = DiGraph::with_capacity(records.len(), 0);
+        let mut nodes = HashMap::with_capacity(records.len());
+        for record in records {
+            let name = record.as_ref().name.clone();
+            nodes.insert(name, graph.add_node(record));
+        }
+
+        let mut edges = Vec::new();
+        for source in graph.node_indices() {
+            let record = graph[source].as_ref();
+            let specs = record
+                .depends
+                .iter()
+                .map(|spec| (spec, DependencyKind::Depends))
+                .chain(
+                    record
+                        .constrains
+                        .iter()
+                        .map(|spec| (spec, DependencyKind::Constrains)),
+                );
+            for (spec, kind) in specs {
+                let Ok(spec) = MatchSpec::from_str(spec, ParseStrictness::Lenient) else {
+                    tracing::debug!("failed to parse '{spec}' of {}", record.name.as_source());
+                    continue;
+                };
+                let Some(&target) = spec.name.as_ref().and_then(|name| nodes.get(name)) else {
+                    continue;
+                };
+                if spec.matches(graph[target].as_ref()) {
+                    edges.push((source, target, kind));
+                }
+            }
+        }
+        for (source, target, kind) in edges {
+            graph.add_edge(source, target, kind);
+        }
+
+        Self { graph, nodes }
+    }
+
+    /// Returns the underlying graph.
+    pub fn graph(&self) -> &DiGraph<T, DependencyKind> {
+        &self.graph
+    }
+
+    /// Returns the number of packages in the graph.
+    pub fn len(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    /// Returns true if the graph contains no packages.
+    pub fn is_empty(&self) -> bool {
+        self.graph.node_count() == 0
+    }
+
+    /// Returns the record of the package with the given name.
+    pub fn get(&self, name: &PackageName) -> Option<&T> {
+        self.nodes.get(name).map(|&index| &self.graph[index])
+    }
+
+    /// Returns the direct dependencies of the package with the given name.
+    pub fn dependencies(&self, name: &PackageName) -> Vec<&T> {
+        self.neighbors(name, Direction::Outgoing, DependencyKind::Depends)
+    }
+
+    /// Returns the packages that directly depend on the package with the given name.
+    pub fn dependents(&self, name: &PackageName) -> Vec<&T> {
+        self.neighbors(name, Direction::Incoming, DependencyKind::Depends)
+    }
+
+    /// Returns the packages that constrain the package with the given name.
+    pub fn constrained_by(&self, name: &PackageName) -> Vec<&T> {
+        self.neighbors(name, Direction::Incoming, DependencyKind::Constrains)
+    }
+
+    /// Returns all packages that directly or indirectly depend on the package with the given
+    /// name, i.e. the packages that would be affected if it is removed. The packages are sorted
+    /// by name.
+    pub fn transitive_dependents(&self, name: &PackageName) -> Vec<&T> {
+        let Some(&start) = self.nodes.get(name) else {
+            return Vec::new();
+        };
+        let depends = EdgeFiltered::from_fn(&self.graph, |edge| {
+            *edge.weight() == DependencyKind::Depends
+        });
+        let reversed = Reversed(&depends);
+        let mut bfs = Bfs::new(reversed, start);
+        let mut dependents = Vec::new();
+        while let Some(index) = bfs.next(reversed) {
+            if index != start {
+                dependents.push(index);
+            }
+        }
+        dependents.sort();
+        dependents
+            .into_iter()
+            .map(|index| &self.graph[index])
+            .collect()
+    }
+
+    /// Returns the packages that no other package depends on.
+    pub fn roots(&self) -> Vec<&T> {
+        self.graph
+            .node_indices()
+            .filter(|&index| {
+                !self
+                    .graph
+                    .edges_directed(index, Direction::Incoming)
+                    .any(|edge| *edge.weight() == DependencyKind::Depends)
+            })
+            .map(|index| &self.graph[index])
+            .collect()
+    }
+
+    /// Returns the packages ordered such that every package comes after its dependencies, which
+    /// is the order in which packages should be linked. Constraints do not affect the order.
+    ///
+    /// Use [`PackageRecord::sort_topologically`] if the records may contain cycles.
+    pub fn topological_order(&self) -> Result<Vec<&T>, DependencyCycleError> {
+        let depends = EdgeFiltered::from_fn(&self.graph, |edge| {
+            *edge.weight() == DependencyKind::Depends
+        });
+        let order = toposort(&depends, None).map_err(|cycle| {
+            DependencyCycleError(self.graph[cycle.node_id()].as_ref().name.clone())
+        })?;
+        Ok(order
+            .into_iter()
+            .rev()
+            .map(|index| &self.graph[index])
+            .collect())
+    }
+
+    fn neighbors(&self, name: &PackageName, direction: Direction, kind: DependencyKind) -> Vec<&T> {
+        let Some(&index) = self.nodes.get(name) else {
+            return Vec::new();
+        };
+        let mut neighbors = self
+            .graph
+            .edges_directed(index, direction)
+            .filter(|edge| *edge.weight() == kind)
+            .map(|edge| match direction {
+                Direction::Outgoing => edge.target(),
+                Direction::Incoming => edge.source(),
+            })
+            .collect::<Vec<_>>();
+        neighbors.sort();
+        neighbors.dedup();
+        neighbors
+            .into_iter()
+            .map(|index| &self.graph[index])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::{DependencyCycleError, DependencyGraph};
+    use crate::{PackageName, PackageRecord, Version};
+
+    fn record(name: &str, version: &str, depends: &[&str], constrains: &[&str]) -> PackageRecord {
+        PackageRecord {
+            depends: depends.iter().map(ToString::to_string).collect(),
+            constrains: constrains.iter().map(ToString::to_string).collect(),
+            ..PackageRecord::new(
+                PackageName::new_unchecked(name),
+                Version::from_str(version).unwrap(),
+                String::from("0"),
+            )
+        }
+    }
+
+    fn names<'a>(records: impl IntoIterator<Item = &'a PackageRecord>) -> Vec<&'a str> {
+        records
+            .into_iter()
+            .map(|record| record.name.as_normalized())
+            .collect()
+    }
+
+    #[test]
+    fn test_dependency_graph() {
+        let name = PackageName::new_unchecked;
+        let graph = DependencyGraph::from_records(vec![
+            record("python", "3.12", &["libzlib >=1.2", "openssl"], &[]),
+            record("numpy", "1.26", &["python >=3.9", "libblas"], &[]),
+            record("libzlib", "1.3", &[], &[]),
+            record("openssl", "3.2", &["libzlib"], &[]),
+            record("pandas", "2.2", &["numpy", "python"], &["pyarrow >=10"]),
+            record("libzlib-dev", "1.3", &["libzlib <1.3"], &[]),
+        ]);
+
+        assert_eq!(graph.len(), 6);
+        assert_eq!(
+            names(graph.dependencies(&name("python"))),
+            ["libzlib", "openssl"]
+        );
+        assert_eq!(
+            names(graph.dependents(&name("python"))),
+            ["numpy", "pandas"]
+        );
+        // The spec of `libzlib-dev` does not match the `libzlib` in the graph.
+        assert_eq!(
+            names(graph.dependents(&name("libzlib"))),
+            ["openssl", "python"]
+        );
+        assert_eq!(
+            names(graph.transitive_dependents(&name("openssl"))),
+            ["numpy", "pandas", "python"]
+        );
+        assert_eq!(names(graph.roots()), ["libzlib-dev", "pandas"]);
+        assert_eq!(
+            names(graph.topological_order().unwrap()),
+            [
+                "libzlib",
+                "libzlib-dev",
+                "openssl",
+                "python",
+                "numpy",
+                "pandas"
+            ]
+        );
+        assert!(graph.constrained_by(&name("pyarrow")).is_empty());
+    }
+
+    #[test]
+    fn test_dependency_graph_cycle() {
+        let graph = DependencyGraph::from_records(vec![
+            record("a", "1", &["b"], &[]),
+            record("b", "1", &["a"], &["c <2"]),
+            record("c", "1", &[], &[]),
+        ]);
+        assert!(matches!(
+            graph.topological_order(),
+            Err(DependencyCycleError(_))
+        ));
+        assert_eq!(
+            names(graph.constrained_by(&PackageName::new_unchecked("c"))),
+            ["b"]
+        );
+        assert_eq!(names(graph.roots()), ["c"]);
+    }
+}
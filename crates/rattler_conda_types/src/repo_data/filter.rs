@@ -0,0 +1,246 @@
+//! Filtering of repodata records before they are handed to a solver. See [`RecordFilter`].
+
+use std::ops::Not;
+
+use chrono::{DateTime, Utc};
+
+use crate::{MatchSpec, PackageRecord, RepoData, StringMatcher};
+
+/// A composable predicate over [`PackageRecord`]s that can be used to enforce policies on the
+/// packages that are available to a solver, e.g. "no GPL licensed packages" or "no packages that
+/// were uploaded after a certain date".
+///
+/// Filters are combined with [`RecordFilter::and`], [`RecordFilter::or`] and negated with `!`.
+///
+/// ```rust
+/// use rattler_conda_types::{RecordFilter, StringMatcher};
+/// use std::str::FromStr;
+///
+/// // Only allow packages that are not GPL licensed and that are smaller than 100 MB.
+/// let no_gpl = !RecordFilter::License(vec![StringMatcher::from_str("*GPL*").unwrap()]);
+/// let filter = no_gpl.and(RecordFilter::MaxSize(100 * 1024 * 1024));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordFilter {
+    /// Matches records that match the spec.
+    MatchSpec(Box<MatchSpec>),
+
+    /// Matches records that were created within the time window. Both bounds are inclusive.
+    /// Records without a timestamp always match.
+    Timestamp {
+        /// The earliest time a record may have been created.
+        from: Option<DateTime<Utc>>,
+
+        /// The latest time a record may have been created.
+        until: Option<DateTime<Utc>>,
+    },
+
+    /// Matches records whose license matches one of the patterns. Records without a license never
+    /// match.
+    License(Vec<StringMatcher>),
+
+    /// Matches records whose build string matches the pattern.
+    Build(StringMatcher),
+
+    /// Matches records that track a feature that matches the pattern.
+    TrackFeature(StringMatcher),
+
+    /// Matches records whose archive is at most the given number of bytes. Records without a size
+    /// always match.
+    MaxSize(u64),
+
+    /// Matches records that match all of the filters.
+    All(Vec<RecordFilter>),
+
+    /// Matches records that match any of the filters.
+    Any(Vec<RecordFilter>),
+
+    /// Matches records that do not match the filter.
+    Not(Box<RecordFilter>),
+}
+
+impl RecordFilter {
+    /// Returns a filter that matches records that were created at or before the given time.
+    pub fn created_until(until: DateTime<Utc>) -> Self {
+        Self::Timestamp {
+            from: None,
+            until: Some(until),
+        }
+    }
+
+    /// Returns a filter that matches records that match both this filter and `other`.
+    #[must_use]
+    pub fn and(self, other: RecordFilter) -> Self {
+        match self {
+            Self::All(mut filters) => {
+                filters.push(other);
+                Self::All(filters)
+            }
+            filter => Self::All(vec![filter, other]),
+        }
+    }
+
+    /// Returns a filter that matches records that match either this filter or `other`.
+    #[must_use]
+    pub fn or(self, other: RecordFilter) -> Self {
+        match self {
+            Self::Any(mut filters) => {
+                filters.push(other);
+                Self::Any(filters)
+            }
+            filter => Self::Any(vec![filter, other]),
+        }
+    }
+
+    /// Returns true if the record matches this filter.
+    pub fn matches(&self, record: &PackageRecord) -> bool {
+        match self {
+            Self::MatchSpec(spec) => spec.matches(record),
+            Self::Timestamp { from, until } => record.timestamp.map_or(true, |timestamp| {
+                from.map_or(true, |from| timestamp >= from)
+                    && until.map_or(true, |until| timestamp <= until)
+            }),
+            Self::License(patterns) => record
+                .license
+                .as_deref()
+                .is_some_and(|license| patterns.iter().any(|pattern| pattern.matches(license))),
+            Self::Build(pattern) => pattern.matches(&record.build),
+            Self::TrackFeature(pattern) => record
+                .track_features
+                .iter()
+                .any(|feature| pattern.matches(feature)),
+            Self::MaxSize(max_size) => record.size.map_or(true, |size| size <= *max_size),
+            Self::All(filters) => filters.iter().all(|filter| filter.matches(record)),
+            Self::Any(filters) => filters.iter().any(|filter| filter.matches(record)),
+            Self::Not(filter) => !filter.matches(record),
+        }
+    }
+
+    /// Returns the records that match this filter.
+    pub fn apply<'a, T: AsRef<PackageRecord> + 'a>(
+        &'a self,
+        records: impl IntoIterator<Item = T> + 'a,
+    ) -> impl Iterator<Item = T> + 'a {
+        records
+            .into_iter()
+            .filter(|record| self.matches(record.as_ref()))
+    }
+}
+
+impl Not for RecordFilter {
+    type Output = RecordFilter;
+
+    fn not(self) -> Self::Output {
+        match self {
+            Self::Not(filter) => *filter,
+            filter => Self::Not(Box::new(filter)),
+        }
+    }
+}
+
+impl RepoData {
+    /// Removes all packages that do not match the filter.
+    pub fn retain_matching(&mut self, filter: &RecordFilter) {
+        self.packages.retain(|_, record| filter.matches(record));
+        self.conda_packages
+            .retain(|_, record| filter.matches(record));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::RecordFilter;
+    use crate::{
+        MatchSpec, PackageName, PackageRecord, ParseStrictness, RepoData, StringMatcher, Version,
+    };
+
+    fn record(name: &str, license: Option<&str>, timestamp: Option<i64>) -> PackageRecord {
+        PackageRecord {
+            license: license.map(str::to_string),
+            timestamp: timestamp.map(|seconds| Utc.timestamp_opt(seconds, 0).unwrap()),
+            size: Some(1000),
+            ..PackageRecord::new(
+                PackageName::new_unchecked(name),
+                Version::from_str("1.0").unwrap(),
+                String::from("py_0"),
+            )
+        }
+    }
+
+    fn matcher(pattern: &str) -> StringMatcher {
+        StringMatcher::from_str(pattern).unwrap()
+    }
+
+    #[test]
+    fn test_filters() {
+        let gpl = record("gpl", Some("GPL-3.0-only"), Some(100));
+        let mit = record("mit", Some("MIT"), Some(200));
+        let unknown = record("unknown", None, None);
+
+        let no_gpl = !RecordFilter::License(vec![matcher("*GPL*")]);
+        assert!(!no_gpl.matches(&gpl));
+        assert!(no_gpl.matches(&mit));
+        assert!(no_gpl.matches(&unknown));
+
+        let only_mit = RecordFilter::License(vec![matcher("MIT"), matcher("BSD-*")]);
+        assert!(only_mit.matches(&mit));
+        assert!(!only_mit.matches(&unknown));
+
+        let until = RecordFilter::created_until(Utc.timestamp_opt(150, 0).unwrap());
+        assert!(until.matches(&gpl));
+        assert!(!until.matches(&mit));
+        assert!(until.matches(&unknown));
+
+        let spec = RecordFilter::MatchSpec(Box::new(
+            MatchSpec::from_str("mit >=1", ParseStrictness::Strict).unwrap(),
+        ));
+        assert!(spec.matches(&mit));
+        assert!(!spec.matches(&gpl));
+
+        assert!(RecordFilter::Build(matcher("py*")).matches(&mit));
+        assert!(RecordFilter::MaxSize(1000).matches(&mit));
+        assert!(!RecordFilter::MaxSize(999).matches(&mit));
+
+        let mut cuda = record("cuda", None, None);
+        cuda.track_features = vec![String::from("cuda")];
+        let no_features = !RecordFilter::TrackFeature(matcher("*"));
+        assert!(!no_features.matches(&cuda));
+        assert!(no_features.matches(&mit));
+
+        let combined = no_gpl.clone().and(until.clone());
+        assert!(!combined.matches(&gpl));
+        assert!(!combined.matches(&mit));
+        assert!(combined.matches(&unknown));
+        assert_eq!(!!no_gpl.clone(), no_gpl);
+
+        let either = spec.or(RecordFilter::MatchSpec(Box::new(
+            MatchSpec::from_str("gpl", ParseStrictness::Strict).unwrap(),
+        )));
+        assert!(either.matches(&mit) && either.matches(&gpl) && !either.matches(&unknown));
+    }
+
+    #[test]
+    fn test_retain_matching() {
+        let mut repo_data: RepoData = serde_json::from_value(serde_json::json!({
+            "packages": {
+                "gpl-1.0-py_0.tar.bz2": record("gpl", Some("GPL-3.0-only"), None),
+                "mit-1.0-py_0.tar.bz2": record("mit", Some("MIT"), None),
+            },
+            "packages.conda": {
+                "gpl-1.0-py_0.conda": record("gpl", Some("GPL-3.0-only"), None),
+            },
+        }))
+        .unwrap();
+
+        repo_data.retain_matching(&!RecordFilter::License(vec![matcher("GPL*")]));
+        assert_eq!(
+            repo_data.packages.keys().collect::<Vec<_>>(),
+            ["mit-1.0-py_0.tar.bz2"]
+        );
+        assert!(repo_data.conda_packages.is_empty());
+    }
+}
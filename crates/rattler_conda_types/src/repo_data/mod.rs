@@ -1,10 +1,19 @@
 //! Defines [`RepoData`]. `RepoData` stores information of all packages present in a subdirectory
 //! of a channel. It provides indexing functionality.
 
+mod dependency_graph;
+mod filter;
 pub mod patches;
+mod report;
 pub mod sharded;
 mod topological_sort;
 
+pub use dependency_graph::{DependencyCycleError, DependencyGraph, DependencyKind};
+pub use filter::RecordFilter;
+pub use report::{
+    ChannelReport, DuplicateSha256, OrphanedDependency, RecordLocation, SubdirStatistics,
+};
+
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Display, Formatter};
@@ -166,6 +175,12 @@ pub struct PackageRecord {
     //pub package_type: ?
 }
 
+impl AsRef<PackageRecord> for PackageRecord {
+    fn as_ref(&self) -> &PackageRecord {
+        self
+    }
+}
+
 impl Display for PackageRecord {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         if self.build.is_empty() {
@@ -0,0 +1,338 @@
+//! Statistics and health checks of the repodata of a channel. See [`ChannelReport`].
+
+use std::collections::{BTreeMap, HashMap};
+
+use rattler_digest::{serde::SerializableHash, Sha256Hash};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::{MatchSpec, PackageName, PackageRecord, ParseStrictness, RepoData};
+
+/// A report about the repodata of one or more subdirectories of a channel, intended for channel
+/// maintainers and mirrors.
+///
+/// Besides statistics, the report lists problems that make records of the channel hard or
+/// impossible to install:
+///
+/// * dependencies that do not match any record of the channel, virtual packages (e.g. `__glibc`)
+///   are not checked,
+/// * archives that are published under more than one file name,
+/// * records without a timestamp, which breaks solving for the newest packages and filtering by
+///   upload date.
+///
+/// ```rust
+/// # use rattler_conda_types::{ChannelReport, RepoData};
+/// # fn example(linux_64: RepoData, noarch: RepoData) {
+/// let report = ChannelReport::from_repodata([&linux_64, &noarch]);
+/// println!("{}", serde_json::to_string_pretty(&report).unwrap());
+/// # }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelReport {
+    /// The statistics of each subdirectory.
+    pub subdirs: BTreeMap<String, SubdirStatistics>,
+
+    /// The number of records of each package, per subdirectory.
+    pub packages: BTreeMap<String, BTreeMap<String, usize>>,
+
+    /// The total number of records.
+    pub total_records: usize,
+
+    /// The total size of all archives in bytes. Records without a size are not counted.
+    pub total_size: u64,
+
+    /// Dependencies that do not match any record of the subdirectory of the record or of
+    /// `noarch`. Dependencies that cannot be parsed are reported as well.
+    pub orphaned_depends: Vec<OrphanedDependency>,
+
+    /// Archives with the same sha256 hash that are published under more than one file name.
+    pub duplicate_sha256: Vec<DuplicateSha256>,
+
+    /// Records that do not have a timestamp.
+    pub missing_timestamps: Vec<RecordLocation>,
+}
+
+/// Statistics of a single subdirectory of a channel.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubdirStatistics {
+    /// The number of records.
+    pub records: usize,
+
+    /// The number of `.tar.bz2` records.
+    pub tar_bz2_records: usize,
+
+    /// The number of `.conda` records.
+    pub conda_records: usize,
+
+    /// The number of distinct package names.
+    pub packages: usize,
+
+    /// The number of records that have been removed from the index.
+    pub removed: usize,
+
+    /// The total size of the archives in bytes. Records without a size are not counted.
+    pub total_size: u64,
+}
+
+/// Identifies a record in a [`ChannelReport`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RecordLocation {
+    /// The subdirectory that contains the record.
+    pub subdir: String,
+
+    /// The file name of the archive of the record.
+    pub file_name: String,
+}
+
+/// A dependency that does not match any record of the channel.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct OrphanedDependency {
+    /// The record that has the dependency.
+    #[serde(flatten)]
+    pub record: RecordLocation,
+
+    /// The dependency as it is specified in the record.
+    pub dependency: String,
+}
+
+/// An archive that is published under more than one file name.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DuplicateSha256 {
+    /// The hash of the archives.
+    #[serde_as(as = "SerializableHash::<rattler_digest::Sha256>")]
+    pub sha256: Sha256Hash,
+
+    /// The records of the archives, sorted.
+    pub records: Vec<RecordLocation>,
+}
+
+impl ChannelReport {
+    /// Computes the report of the given repodata, typically of all subdirectories of a channel.
+    /// Dependencies are resolved against the records of the same subdirectory and of `noarch`, so
+    /// the repodata of `noarch` should be included.
+    ///
+    /// The subdirectory of a record is taken from the `info` section of its repodata, or from the
+    /// record itself if the repodata does not have one.
+    pub fn from_repodata<'a>(repodata: impl IntoIterator<Item = &'a RepoData>) -> Self {
+        let mut report = Self::default();
+
+        // All records of the channel, indexed by subdirectory and name.
+        let mut records_by_name: HashMap<&str, HashMap<&PackageName, Vec<&PackageRecord>>> =
+            HashMap::new();
+        let mut records = Vec::new();
+        for repodata in repodata {
+            let info_subdir = repodata.info.as_ref().map(|info| info.subdir.as_str());
+            for (file_name, record, is_conda) in repodata
+                .packages
+                .iter()
+                .map(|(file_name, record)| (file_name, record, false))
+                .chain(
+                    repodata
+                        .conda_packages
+                        .iter()
+                        .map(|(file_name, record)| (file_name, record, true)),
+                )
+            {
+                let subdir = info_subdir.unwrap_or(record.subdir.as_str());
+                let statistics = report.subdirs.entry(subdir.to_string()).or_default();
+                statistics.records += 1;
+                if is_conda {
+                    statistics.conda_records += 1;
+                } else {
+                    statistics.tar_bz2_records += 1;
+                }
+                statistics.total_size += record.size.unwrap_or(0);
+                *report
+                    .packages
+                    .entry(record.name.as_normalized().to_string())
+                    .or_default()
+                    .entry(subdir.to_string())
+                    .or_default() += 1;
+
+                records_by_name
+                    .entry(subdir)
+                    .or_default()
+                    .entry(&record.name)
+                    .or_default()
+                    .push(record);
+                records.push((subdir, file_name, record));
+            }
+            if let Some(subdir) = info_subdir {
+                report
+                    .subdirs
+                    .entry(subdir.to_string())
+                    .or_default()
+                    .removed += repodata.removed.len();
+            }
+        }
+
+        for (subdir, statistics) in &mut report.subdirs {
+            statistics.packages = records_by_name.get(subdir.as_str()).map_or(0, HashMap::len);
+            report.total_records += statistics.records;
+            report.total_size += statistics.total_size;
+        }
+
+        // Whether a dependency can be satisfied only depends on the dependency and on the
+        // subdirectory so the result is cached.
+        let mut satisfiable: HashMap<(&str, &str), bool> = HashMap::new();
+        let mut by_sha256: HashMap<Sha256Hash, Vec<RecordLocation>> = HashMap::new();
+        for (subdir, file_name, record) in records {
+            let location = || RecordLocation {
+                subdir: subdir.to_string(),
+                file_name: file_name.clone(),
+            };
+
+            for dependency in &record.depends {
+                let is_satisfiable = *satisfiable
+                    .entry((subdir, dependency.as_str()))
+                    .or_insert_with(|| is_satisfiable(dependency, subdir, &records_by_name));
+                if !is_satisfiable {
+                    report.orphaned_depends.push(OrphanedDependency {
+                        record: location(),
+                        dependency: dependency.clone(),
+                    });
+                }
+            }
+
+            if let Some(sha256) = record.sha256 {
+                by_sha256.entry(sha256).or_default().push(location());
+            }
+            if record.timestamp.is_none() {
+                report.missing_timestamps.push(location());
+            }
+        }
+
+        report.duplicate_sha256 = by_sha256
+            .into_iter()
+            .filter(|(_, records)| records.len() > 1)
+            .map(|(sha256, mut records)| {
+                records.sort();
+                DuplicateSha256 { sha256, records }
+            })
+            .collect();
+        report
+            .duplicate_sha256
+            .sort_by(|a, b| a.records.cmp(&b.records));
+        report.orphaned_depends.sort();
+        report.missing_timestamps.sort();
+        report
+    }
+
+    /// Returns true if the report did not find any problems.
+    pub fn is_healthy(&self) -> bool {
+        self.orphaned_depends.is_empty()
+            && self.duplicate_sha256.is_empty()
+            && self.missing_timestamps.is_empty()
+    }
+}
+
+/// Returns true if the `dependency` of a record in `subdir` matches a record of the same
+/// subdirectory or of `noarch`. Dependencies on virtual packages are always satisfiable.
+fn is_satisfiable(
+    dependency: &str,
+    subdir: &str,
+    records_by_name: &HashMap<&str, HashMap<&PackageName, Vec<&PackageRecord>>>,
+) -> bool {
+    let Ok(spec) = MatchSpec::from_str(dependency, ParseStrictness::Lenient) else {
+        return false;
+    };
+    let Some(name) = &spec.name else {
+        return false;
+    };
+    if name.as_normalized().starts_with("__") {
+        return true;
+    }
+    [subdir, "noarch"]
+        .into_iter()
+        .filter_map(|subdir| records_by_name.get(subdir)?.get(name))
+        .flatten()
+        .any(|record| spec.matches(record))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChannelReport, OrphanedDependency, RecordLocation};
+    use crate::RepoData;
+
+    const LINUX_64: &str = r#"{
+        "info": { "subdir": "linux-64" },
+        "packages": {
+            "foo-1.0-0.tar.bz2": { "name": "foo", "version": "1.0", "build": "0", "build_number": 0, "depends": ["__glibc >=2.17", "bar >=1"], "size": 100, "timestamp": 1700000000000, "sha256": "2a7e72ad9c03b6e7d1c3a4fa6d4e8d0a1b56fb1a42a3e0e9b5f0c3c0b8cf0d11" }
+        },
+        "packages.conda": {
+            "foo-1.0-0.conda": { "name": "foo", "version": "1.0", "build": "0", "build_number": 0, "depends": ["bar >=2", "baz"], "size": 50, "timestamp": 1700000000000, "sha256": "8a7e72ad9c03b6e7d1c3a4fa6d4e8d0a1b56fb1a42a3e0e9b5f0c3c0b8cf0d22" },
+            "foo-2.0-0.conda": { "name": "foo", "version": "2.0", "build": "0", "build_number": 0, "depends": ["bar"], "size": 60, "sha256": "8a7e72ad9c03b6e7d1c3a4fa6d4e8d0a1b56fb1a42a3e0e9b5f0c3c0b8cf0d22" }
+        },
+        "removed": ["foo-0.1-0.tar.bz2"]
+    }"#;
+
+    const NOARCH: &str = r#"{
+        "info": { "subdir": "noarch" },
+        "packages": {},
+        "packages.conda": {
+            "bar-1.5-0.conda": { "name": "bar", "version": "1.5", "build": "0", "build_number": 0, "depends": [], "size": 10, "timestamp": 1700000000000 }
+        }
+    }"#;
+
+    fn location(subdir: &str, file_name: &str) -> RecordLocation {
+        RecordLocation {
+            subdir: subdir.to_string(),
+            file_name: file_name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_channel_report() {
+        let linux_64: RepoData = serde_json::from_str(LINUX_64).unwrap();
+        let noarch: RepoData = serde_json::from_str(NOARCH).unwrap();
+        let report = ChannelReport::from_repodata([&linux_64, &noarch]);
+
+        assert_eq!(report.total_records, 4);
+        assert_eq!(report.total_size, 220);
+        let statistics = &report.subdirs["linux-64"];
+        assert_eq!(statistics.records, 3);
+        assert_eq!(statistics.tar_bz2_records, 1);
+        assert_eq!(statistics.conda_records, 2);
+        assert_eq!(statistics.packages, 1);
+        assert_eq!(statistics.removed, 1);
+        assert_eq!(report.packages["foo"]["linux-64"], 3);
+        assert_eq!(report.packages["bar"]["noarch"], 1);
+
+        assert_eq!(
+            report.orphaned_depends,
+            [
+                OrphanedDependency {
+                    record: location("linux-64", "foo-1.0-0.conda"),
+                    dependency: "bar >=2".to_string(),
+                },
+                OrphanedDependency {
+                    record: location("linux-64", "foo-1.0-0.conda"),
+                    dependency: "baz".to_string(),
+                },
+            ]
+        );
+        assert_eq!(report.duplicate_sha256.len(), 1);
+        assert_eq!(
+            report.duplicate_sha256[0].records,
+            [
+                location("linux-64", "foo-1.0-0.conda"),
+                location("linux-64", "foo-2.0-0.conda")
+            ]
+        );
+        assert_eq!(
+            report.missing_timestamps,
+            [location("linux-64", "foo-2.0-0.conda")]
+        );
+        assert!(!report.is_healthy());
+
+        // The report can be serialized and deserialized.
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ChannelReport>(&json).unwrap(),
+            report
+        );
+
+        assert!(ChannelReport::from_repodata([&noarch]).is_healthy());
+    }
+}
@@ -326,6 +326,37 @@ impl VersionSpec {
             }
         }
     }
+
+    /// Returns an equivalent specification in a canonical form. Nested groups with the same
+    /// operator are flattened, the members of groups are sorted and deduplicated, and groups with
+    /// a single member are replaced by that member.
+    ///
+    /// Specifications that only differ in the order of their constraints, like `>=1.0,<2` and
+    /// `<2,>=1`, have the same normalized form.
+    #[must_use]
+    pub fn normalized(&self) -> VersionSpec {
+        let VersionSpec::Group(op, group) = self else {
+            return self.clone();
+        };
+
+        let mut members = Vec::with_capacity(group.len());
+        for spec in group {
+            match spec.normalized() {
+                VersionSpec::Group(nested_op, nested) if nested_op == *op => {
+                    members.extend(nested);
+                }
+                spec => members.push(spec),
+            }
+        }
+        members.sort();
+        members.dedup();
+
+        if members.len() == 1 {
+            members.pop().expect("there is exactly one member")
+        } else {
+            VersionSpec::Group(*op, members)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -400,6 +431,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_normalized() {
+        let normalized = |spec: &str| {
+            VersionSpec::from_str(spec, ParseStrictness::Strict)
+                .unwrap()
+                .normalized()
+        };
+
+        assert_eq!(normalized(">=1.0,<2"), normalized("<2,>=1"));
+        assert_eq!(normalized(">=1.0,<2"), normalized("<2.0,>=1,<2"));
+        assert_eq!(normalized("(>=1,<2),!=1.5"), normalized("!=1.5,>=1,<2"));
+        assert_eq!(normalized("1.2|1.3"), normalized("1.3|1.2"));
+        assert_eq!(normalized(">=1,>=1"), normalized(">=1"));
+        assert_ne!(normalized(">=1,<2"), normalized(">=1|<2"));
+        assert_ne!(normalized("(>=1|<2),!=1.5"), normalized(">=1|<2|!=1.5"));
+    }
+
     #[test]
     fn test_matches() {
         let v1 = Version::from_str("1.2.0").unwrap();
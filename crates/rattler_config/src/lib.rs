@@ -0,0 +1,411 @@
+#![deny(missing_docs)]
+
+//! Shared configuration of the rattler crates.
+//!
+//! A [`Config`] is read from a TOML file and can be overridden with environment variables. The
+//! builders of the other rattler crates accept a [`Config`] so that channels, mirrors,
+//! authentication, cache locations and retries are configured in a single place instead of
+//! through per-crate options.
+//!
+//! ```toml
+//! default-channels = ["conda-forge", "bioconda"]
+//! channel-priority = "strict"
+//! cache-dir = "/var/cache/rattler"
+//! platform = "linux-64"
+//!
+//! [mirrors]
+//! "https://conda.anaconda.org/conda-forge" = ["https://prefix.dev/conda-forge"]
+//!
+//! [auth]
+//! backends = ["keyring", "file", "netrc"]
+//! authentication-file = "/etc/rattler/credentials.json"
+//!
+//! [retry]
+//! max-retries = 5
+//! ```
+//!
+//! All fields are optional. Fields that are not set fall back to the defaults that are returned
+//! by the accessor methods of [`Config`].
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use rattler_conda_types::Platform;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// The environment variable that contains the path of the configuration file.
+pub const CONFIG_FILE_ENV: &str = "RATTLER_CONFIG";
+
+/// The channels that are used if no channels are configured.
+pub const DEFAULT_CHANNELS: &[&str] = &["conda-forge"];
+
+/// The number of times a failed request is retried if not configured otherwise.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// An error that can occur while loading a [`Config`].
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    /// The configuration file could not be read.
+    #[error("failed to read {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    /// The configuration file is not valid.
+    #[error("failed to parse {0}")]
+    Parse(PathBuf, #[source] toml::de::Error),
+
+    /// An environment variable contains an invalid value.
+    #[error("invalid value '{value}' for environment variable {name}")]
+    InvalidEnvVar {
+        /// The name of the environment variable.
+        name: &'static str,
+
+        /// The value of the environment variable.
+        value: String,
+    },
+}
+
+/// Determines how packages that are available in multiple channels are selected by the solver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelPriority {
+    /// A package is only taken from the first channel that contains it.
+    #[default]
+    Strict,
+
+    /// Packages can be taken from any channel, the version of a package takes precedence.
+    Disabled,
+}
+
+impl FromStr for ChannelPriority {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "disabled" => Ok(Self::Disabled),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A storage backend for credentials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthBackend {
+    /// The keyring of the operating system.
+    Keyring,
+
+    /// A JSON file with credentials, see [`AuthConfig::authentication_file`].
+    File,
+
+    /// The `.netrc` file.
+    Netrc,
+}
+
+/// Configures where credentials are read from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AuthConfig {
+    /// The backends that are queried for credentials, in order. Defaults to the keyring, the
+    /// credentials file and the `.netrc` file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backends: Option<Vec<AuthBackend>>,
+
+    /// The path of the credentials file of the [`AuthBackend::File`] backend. If this is set
+    /// and no backends are configured, only this file is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authentication_file: Option<PathBuf>,
+}
+
+/// Configures how failed requests are retried.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RetryConfig {
+    /// The number of times a failed request is retried. Defaults to [`DEFAULT_MAX_RETRIES`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+}
+
+/// The configuration shared by the rattler crates.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// The channels that are used when no channels are specified explicitly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_channels: Option<Vec<String>>,
+
+    /// How packages that are available in multiple channels are selected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_priority: Option<ChannelPriority>,
+
+    /// Mirrors of channels. Requests to a url that starts with a key are sent to the mirrors
+    /// instead, in order.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub mirrors: HashMap<Url, Vec<Url>>,
+
+    /// Where credentials are read from.
+    #[serde(default)]
+    pub auth: AuthConfig,
+
+    /// The directory in which packages and repodata are cached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// How failed requests are retried.
+    #[serde(default)]
+    pub retry: RetryConfig,
+
+    /// The platform to install packages for if no platform is specified explicitly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+}
+
+impl Config {
+    /// Parses a configuration from the contents of a TOML file.
+    pub fn from_toml_str(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// Reads the configuration from a TOML file.
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        Self::from_toml_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Returns the path of the configuration file: the value of the `RATTLER_CONFIG`
+    /// environment variable, or `rattler/config.toml` in the configuration directory of the
+    /// user.
+    pub fn default_path() -> Option<PathBuf> {
+        match std::env::var_os(CONFIG_FILE_ENV) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::config_dir().map(|dir| dir.join("rattler/config.toml")),
+        }
+    }
+
+    /// Loads the configuration from the file at [`Config::default_path`], if it exists, and
+    /// applies the overrides from the environment, see [`Config::with_env_overrides`].
+    pub fn load() -> Result<Self, ConfigError> {
+        let config = match Self::default_path() {
+            Some(path) if path.is_file() => Self::from_path(&path)?,
+            _ => Self::default(),
+        };
+        config.with_env_overrides()
+    }
+
+    /// Merges two configurations. The fields that are set in `other` take precedence.
+    #[must_use]
+    pub fn merge(mut self, other: Config) -> Self {
+        self.default_channels = other.default_channels.or(self.default_channels);
+        self.channel_priority = other.channel_priority.or(self.channel_priority);
+        self.mirrors.extend(other.mirrors);
+        self.auth.backends = other.auth.backends.or(self.auth.backends);
+        self.auth.authentication_file = other
+            .auth
+            .authentication_file
+            .or(self.auth.authentication_file);
+        self.cache_dir = other.cache_dir.or(self.cache_dir);
+        self.retry.max_retries = other.retry.max_retries.or(self.retry.max_retries);
+        self.platform = other.platform.or(self.platform);
+        self
+    }
+
+    /// Overrides the configuration with the values of the environment variables of the current
+    /// process:
+    ///
+    /// * `RATTLER_DEFAULT_CHANNELS`: a comma separated list of channels.
+    /// * `RATTLER_CHANNEL_PRIORITY`: `strict` or `disabled`.
+    /// * `RATTLER_CACHE_DIR`: the cache directory.
+    /// * `RATTLER_PLATFORM`: the platform to install packages for.
+    /// * `RATTLER_MAX_RETRIES`: the number of times failed requests are retried.
+    /// * `RATTLER_AUTH_FILE`: the credentials file.
+    pub fn with_env_overrides(self) -> Result<Self, ConfigError> {
+        self.with_overrides_from(|name| std::env::var(name).ok())
+    }
+
+    /// Overrides the configuration with the variables returned by `var`, see
+    /// [`Config::with_env_overrides`].
+    pub fn with_overrides_from(
+        mut self,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        fn parse<T: FromStr>(name: &'static str, value: String) -> Result<T, ConfigError> {
+            value
+                .parse()
+                .map_err(|_err| ConfigError::InvalidEnvVar { name, value })
+        }
+
+        if let Some(channels) = var("RATTLER_DEFAULT_CHANNELS") {
+            self.default_channels = Some(
+                channels
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|channel| !channel.is_empty())
+                    .map(ToString::to_string)
+                    .collect(),
+            );
+        }
+        if let Some(priority) = var("RATTLER_CHANNEL_PRIORITY") {
+            self.channel_priority = Some(parse("RATTLER_CHANNEL_PRIORITY", priority)?);
+        }
+        if let Some(cache_dir) = var("RATTLER_CACHE_DIR") {
+            self.cache_dir = Some(PathBuf::from(cache_dir));
+        }
+        if let Some(platform) = var("RATTLER_PLATFORM") {
+            self.platform = Some(parse("RATTLER_PLATFORM", platform)?);
+        }
+        if let Some(max_retries) = var("RATTLER_MAX_RETRIES") {
+            self.retry.max_retries = Some(parse("RATTLER_MAX_RETRIES", max_retries)?);
+        }
+        if let Some(auth_file) = var("RATTLER_AUTH_FILE") {
+            self.auth.authentication_file = Some(PathBuf::from(auth_file));
+        }
+        Ok(self)
+    }
+
+    /// Returns the channels that are used when no channels are specified explicitly.
+    pub fn default_channels(&self) -> Vec<String> {
+        self.default_channels
+            .clone()
+            .unwrap_or_else(|| DEFAULT_CHANNELS.iter().map(ToString::to_string).collect())
+    }
+
+    /// Returns how packages that are available in multiple channels are selected.
+    pub fn channel_priority(&self) -> ChannelPriority {
+        self.channel_priority.unwrap_or_default()
+    }
+
+    /// Returns the directory in which packages and repodata are cached. Defaults to
+    /// `rattler/cache` in the cache directory of the user. Returns `None` if no cache directory
+    /// is configured and the platform has no cache directory.
+    pub fn cache_dir(&self) -> Option<PathBuf> {
+        self.cache_dir
+            .clone()
+            .or_else(|| dirs::cache_dir().map(|dir| dir.join("rattler/cache")))
+    }
+
+    /// Returns the number of times a failed request is retried.
+    pub fn max_retries(&self) -> u32 {
+        self.retry.max_retries.unwrap_or(DEFAULT_MAX_RETRIES)
+    }
+
+    /// Returns the platform to install packages for if no platform is specified explicitly.
+    pub fn platform(&self) -> Platform {
+        self.platform.unwrap_or_else(Platform::current)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        default-channels = ["conda-forge", "bioconda"]
+        channel-priority = "disabled"
+        cache-dir = "/var/cache/rattler"
+        platform = "linux-64"
+
+        [mirrors]
+        "https://conda.anaconda.org/conda-forge" = ["https://prefix.dev/conda-forge"]
+
+        [auth]
+        backends = ["file", "netrc"]
+        authentication-file = "/etc/rattler/credentials.json"
+
+        [retry]
+        max-retries = 5
+    "#;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::from_toml_str(CONFIG).unwrap();
+        assert_eq!(config.default_channels(), ["conda-forge", "bioconda"]);
+        assert_eq!(config.channel_priority(), ChannelPriority::Disabled);
+        assert_eq!(
+            config.cache_dir(),
+            Some(PathBuf::from("/var/cache/rattler"))
+        );
+        assert_eq!(config.platform(), Platform::Linux64);
+        assert_eq!(config.max_retries(), 5);
+        assert_eq!(
+            config.auth.backends,
+            Some(vec![AuthBackend::File, AuthBackend::Netrc])
+        );
+        assert_eq!(
+            config.mirrors[&Url::parse("https://conda.anaconda.org/conda-forge").unwrap()],
+            [Url::parse("https://prefix.dev/conda-forge").unwrap()]
+        );
+
+        // Unknown fields are most likely typos.
+        assert!(Config::from_toml_str("default-channel = [\"conda-forge\"]").is_err());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::default();
+        assert_eq!(config.default_channels(), DEFAULT_CHANNELS);
+        assert_eq!(config.channel_priority(), ChannelPriority::Strict);
+        assert_eq!(config.max_retries(), DEFAULT_MAX_RETRIES);
+        assert_eq!(config.platform(), Platform::current());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let env = HashMap::from([
+            ("RATTLER_DEFAULT_CHANNELS", "pytorch, conda-forge"),
+            ("RATTLER_PLATFORM", "osx-arm64"),
+            ("RATTLER_MAX_RETRIES", "0"),
+        ]);
+        let config = Config::from_toml_str(CONFIG)
+            .unwrap()
+            .with_overrides_from(|name| env.get(name).map(ToString::to_string))
+            .unwrap();
+        assert_eq!(config.default_channels(), ["pytorch", "conda-forge"]);
+        assert_eq!(config.platform(), Platform::OsxArm64);
+        assert_eq!(config.max_retries(), 0);
+        assert_eq!(config.channel_priority(), ChannelPriority::Disabled);
+
+        let err = Config::default()
+            .with_overrides_from(|name| {
+                (name == "RATTLER_CHANNEL_PRIORITY").then(|| "flexible".to_string())
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::InvalidEnvVar {
+                name: "RATTLER_CHANNEL_PRIORITY",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_merge() {
+        let project = Config {
+            default_channels: Some(vec!["my-channel".to_string()]),
+            ..Config::default()
+        };
+        let config = Config::from_toml_str(CONFIG).unwrap().merge(project);
+        assert_eq!(config.default_channels(), ["my-channel"]);
+        assert_eq!(config.max_retries(), 5);
+    }
+
+    #[test]
+    fn test_from_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, CONFIG).unwrap();
+        assert_eq!(
+            Config::from_path(&path).unwrap(),
+            Config::from_toml_str(CONFIG).unwrap()
+        );
+        assert!(matches!(
+            Config::from_path(&dir.path().join("missing.toml")),
+            Err(ConfigError::Io(..))
+        ));
+    }
+}
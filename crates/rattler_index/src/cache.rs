@@ -0,0 +1,149 @@
+//! Reusing the metadata of packages that did not change since the previous index.
+
+use std::{collections::HashMap, time::SystemTime};
+
+use rattler_conda_types::{PackageRecord, RepoData};
+use rattler_digest::Sha256Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::{channeldata::PackageMetadata, ChannelStorage, FROM_PACKAGES_FILE_NAME};
+
+/// The name of the file, relative to the subdir, in which the metadata cache is stored.
+pub(crate) const INDEX_CACHE_FILE_NAME: &str = ".index-cache.json";
+
+/// The metadata of packages whose metadata is not known.
+static EMPTY_METADATA: PackageMetadata = PackageMetadata {
+    about: None,
+    run_exports: None,
+    has_activate_scripts: false,
+    has_deactivate_scripts: false,
+    has_post_link_scripts: false,
+    has_pre_link_scripts: false,
+    has_pre_unlink_scripts: false,
+    binary_prefix: false,
+    text_prefix: false,
+};
+
+/// A cached package record and metadata together with the size and modification time of the
+/// archive it was extracted from. The metadata is `None` for records that were taken from the
+/// previous repodata, which does not contain it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedRecord {
+    size: u64,
+    mtime: SystemTime,
+    record: PackageRecord,
+    #[serde(default)]
+    metadata: Option<PackageMetadata>,
+}
+
+/// The metadata of the packages of a single subdir of a previous index, keyed by file name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct IndexCache {
+    packages: HashMap<String, CachedRecord>,
+}
+
+impl IndexCache {
+    /// Reads the metadata cache and the previous unpatched repodata of a subdir. Records from the
+    /// previous repodata that are not in the cache are added without a modification time and
+    /// without metadata so the record can still be reused if the hash of the archive matches. A
+    /// missing or corrupt cache results in an empty cache.
+    pub fn load(storage: &dyn ChannelStorage, subdir: &str) -> Self {
+        let mut cache = storage
+            .open(&format!("{subdir}/{INDEX_CACHE_FILE_NAME}"))
+            .ok()
+            .and_then(|file| serde_json::from_reader::<_, IndexCache>(file).ok())
+            .unwrap_or_default();
+
+        // Patches must not end up in the cache so prefer the unpatched repodata.
+        let repodata = storage
+            .open(&format!("{subdir}/{FROM_PACKAGES_FILE_NAME}"))
+            .or_else(|_| storage.open(&format!("{subdir}/repodata.json")))
+            .ok()
+            .and_then(|file| serde_json::from_reader::<_, RepoData>(file).ok());
+        if let Some(repodata) = repodata {
+            for (file_name, record) in repodata.packages.into_iter().chain(repodata.conda_packages)
+            {
+                if let Some(size) = record.size {
+                    cache.packages.entry(file_name).or_insert(CachedRecord {
+                        size,
+                        mtime: SystemTime::UNIX_EPOCH,
+                        record,
+                        metadata: None,
+                    });
+                }
+            }
+        }
+
+        cache
+    }
+
+    /// Returns the cached record and metadata of an archive if its size and modification time did
+    /// not change. The metadata is `None` if it is not known yet.
+    pub fn get(
+        &self,
+        file_name: &str,
+        size: u64,
+        mtime: Option<SystemTime>,
+    ) -> Option<(&PackageRecord, Option<&PackageMetadata>)> {
+        self.packages
+            .get(file_name)
+            .filter(|cached| cached.size == size && Some(cached.mtime) == mtime)
+            .map(|cached| (&cached.record, cached.metadata.as_ref()))
+    }
+
+    /// Returns the cached record of an archive if its contents did not change, even though its
+    /// modification time did. The metadata is `None` if it is not known yet.
+    pub fn get_by_hash(
+        &self,
+        file_name: &str,
+        size: u64,
+        sha256: &Sha256Hash,
+    ) -> Option<(&PackageRecord, Option<&PackageMetadata>)> {
+        self.packages
+            .get(file_name)
+            .filter(|cached| cached.size == size && cached.record.sha256.as_ref() == Some(sha256))
+            .map(|cached| (&cached.record, cached.metadata.as_ref()))
+    }
+
+    /// Returns the records and metadata of all packages in the cache. Packages without metadata
+    /// get empty metadata.
+    pub fn packages(&self) -> impl Iterator<Item = (&PackageRecord, &PackageMetadata)> {
+        self.packages.values().map(|cached| {
+            (
+                &cached.record,
+                cached.metadata.as_ref().unwrap_or(&EMPTY_METADATA),
+            )
+        })
+    }
+
+    /// Adds the record and metadata of an archive to the cache.
+    pub fn insert(
+        &mut self,
+        file_name: String,
+        size: u64,
+        mtime: Option<SystemTime>,
+        record: PackageRecord,
+        metadata: PackageMetadata,
+    ) {
+        // Without a modification time the entry can only be reused based on its hash.
+        let mtime = mtime.unwrap_or(SystemTime::UNIX_EPOCH);
+        self.packages.insert(
+            file_name,
+            CachedRecord {
+                size,
+                mtime,
+                record,
+                metadata: Some(metadata),
+            },
+        );
+    }
+
+    /// Writes the cache to a subdir.
+    pub fn write(&self, storage: &dyn ChannelStorage, subdir: &str) -> Result<(), std::io::Error> {
+        let contents = serde_json::to_vec(self)?;
+        storage.write(
+            &format!("{subdir}/{INDEX_CACHE_FILE_NAME}"),
+            &mut contents.as_slice(),
+        )
+    }
+}
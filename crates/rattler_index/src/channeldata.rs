@@ -0,0 +1,159 @@
+//! Generating the `channeldata.json` file of a channel.
+
+use std::collections::{BTreeSet, HashMap};
+
+use rattler_conda_types::{
+    package::{AboutJson, FileMode, PathsJson, RunExportsJson},
+    ChannelData, ChannelDataPackage, PackageRecord,
+};
+use serde::{Deserialize, Serialize};
+
+/// The version of the `channeldata.json` format that is written.
+const CHANNELDATA_VERSION: u32 = 1;
+
+/// Metadata of a package that is not part of its [`PackageRecord`] but is required for the
+/// `channeldata.json` file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct PackageMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub about: Option<AboutJson>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_exports: Option<RunExportsJson>,
+    #[serde(default)]
+    pub has_activate_scripts: bool,
+    #[serde(default)]
+    pub has_deactivate_scripts: bool,
+    #[serde(default)]
+    pub has_post_link_scripts: bool,
+    #[serde(default)]
+    pub has_pre_link_scripts: bool,
+    #[serde(default)]
+    pub has_pre_unlink_scripts: bool,
+    #[serde(default)]
+    pub binary_prefix: bool,
+    #[serde(default)]
+    pub text_prefix: bool,
+}
+
+impl PackageMetadata {
+    /// Determines which scripts and prefix placeholders the package of the given name contains.
+    pub fn set_paths(&mut self, name: &str, paths: &PathsJson) {
+        let is_link_script = |path: &str, action: &str| {
+            [
+                format!("bin/.{name}-{action}.sh"),
+                format!("Scripts/.{name}-{action}.bat"),
+            ]
+            .iter()
+            .any(|script| script == path)
+        };
+
+        for entry in &paths.paths {
+            let path = entry
+                .relative_path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            self.has_activate_scripts |= path.starts_with("etc/conda/activate.d/");
+            self.has_deactivate_scripts |= path.starts_with("etc/conda/deactivate.d/");
+            self.has_post_link_scripts |= is_link_script(&path, "post-link");
+            self.has_pre_link_scripts |= is_link_script(&path, "pre-link");
+            self.has_pre_unlink_scripts |= is_link_script(&path, "pre-unlink");
+            match entry.prefix_placeholder.as_ref().map(|p| p.file_mode) {
+                Some(FileMode::Binary) => self.binary_prefix = true,
+                Some(FileMode::Text) => self.text_prefix = true,
+                None => {}
+            }
+        }
+    }
+}
+
+/// Aggregates the packages of all subdirs of a channel into a `channeldata.json`.
+///
+/// The descriptive metadata of a package is taken from its latest version. The run exports are
+/// recorded for every version. Packages whose metadata is unknown, because they were taken from a
+/// `repodata.json` that was not written by this crate, only contribute the information in their
+/// record.
+pub(crate) fn channeldata<'a>(
+    subdirs: impl IntoIterator<Item = String>,
+    packages: impl IntoIterator<Item = (&'a PackageRecord, &'a PackageMetadata)>,
+) -> ChannelData {
+    let mut by_name: HashMap<&str, Vec<(&PackageRecord, &PackageMetadata)>> = HashMap::new();
+    for (record, metadata) in packages {
+        by_name
+            .entry(record.name.as_normalized())
+            .or_default()
+            .push((record, metadata));
+    }
+
+    let packages = by_name
+        .into_iter()
+        .filter_map(|(name, mut packages)| {
+            packages.sort_by(|(a, _), (b, _)| {
+                (&a.version, a.build_number, a.timestamp).cmp(&(
+                    &b.version,
+                    b.build_number,
+                    b.timestamp,
+                ))
+            });
+            let (latest, metadata) = *packages.last()?;
+            let about = metadata.about.as_ref();
+
+            // Later builds of a version take precedence.
+            let run_exports = packages
+                .iter()
+                .filter_map(|(record, metadata)| {
+                    Some((
+                        record.version.version().clone(),
+                        metadata.run_exports.clone()?,
+                    ))
+                })
+                .collect();
+
+            let package = ChannelDataPackage {
+                has_activate_scripts: metadata.has_activate_scripts,
+                has_deactivate_scripts: metadata.has_deactivate_scripts,
+                binary_prefix: metadata.binary_prefix,
+                description: about.and_then(|about| about.description.clone()),
+                dev_url: about.map(|about| about.dev_url.clone()).unwrap_or_default(),
+                doc_url: about.map(|about| about.doc_url.clone()).unwrap_or_default(),
+                home: about.map(|about| about.home.clone()).unwrap_or_default(),
+                source_url: about
+                    .and_then(|about| about.source_url.clone())
+                    .into_iter()
+                    .collect(),
+                license: latest
+                    .license
+                    .clone()
+                    .or_else(|| about.and_then(|about| about.license.clone())),
+                has_post_link_scripts: metadata.has_post_link_scripts,
+                has_pre_link_scripts: metadata.has_pre_link_scripts,
+                has_pre_unlink_scripts: metadata.has_pre_unlink_scripts,
+                run_exports,
+                subdirs: packages
+                    .iter()
+                    .map(|(record, _)| record.subdir.clone())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect(),
+                summary: about.and_then(|about| about.summary.clone()),
+                text_prefix: metadata.text_prefix,
+                timestamp: packages
+                    .iter()
+                    .filter_map(|(record, _)| record.timestamp)
+                    .max()
+                    .and_then(|timestamp| u64::try_from(timestamp.timestamp()).ok()),
+                version: Some(latest.version.version().clone()),
+            };
+            Some((name.to_string(), package))
+        })
+        .collect();
+
+    let mut subdirs = subdirs.into_iter().collect::<Vec<_>>();
+    subdirs.sort();
+    ChannelData {
+        channeldata_version: CHANNELDATA_VERSION,
+        packages,
+        subdirs,
+    }
+}
@@ -0,0 +1,178 @@
+//! Generating the trimmed `current_repodata.json` of a subdir.
+
+use std::collections::{HashMap, HashSet};
+
+use rattler_conda_types::{MatchSpec, PackageRecord, ParseStrictness, RepoData};
+
+/// Identifies a package independently of its archive type.
+type PackageKey<'a> = (&'a str, String, &'a str);
+
+fn package_key(record: &PackageRecord) -> PackageKey<'_> {
+    (
+        record.name.as_normalized(),
+        record.version.to_string(),
+        &record.build,
+    )
+}
+
+/// Returns the build string without the trailing build number, which identifies the variant of a
+/// build, e.g. `py38haa244fe` for `py38haa244fe_1`.
+fn build_variant(record: &PackageRecord) -> &str {
+    let suffix = format!("_{}", record.build_number);
+    record
+        .build
+        .strip_suffix(suffix.as_str())
+        .unwrap_or(&record.build)
+}
+
+/// Returns the repodata with only the latest version of every package.
+///
+/// Of the latest version the highest build number of every variant is kept. Packages that are
+/// required to satisfy the dependencies of the kept packages are added as well, so the trimmed
+/// repodata can be solved on its own. Packages available in both archive formats are kept in
+/// both.
+pub(crate) fn current_repodata(repodata: &RepoData) -> RepoData {
+    let records = repodata
+        .packages
+        .values()
+        .chain(repodata.conda_packages.values())
+        .collect::<Vec<_>>();
+
+    let mut by_name: HashMap<&str, Vec<&PackageRecord>> = HashMap::new();
+    for record in &records {
+        by_name
+            .entry(record.name.as_normalized())
+            .or_default()
+            .push(record);
+    }
+    for records in by_name.values_mut() {
+        // The best record comes first.
+        records.sort_by(|a, b| {
+            (&b.version, b.build_number, b.timestamp).cmp(&(
+                &a.version,
+                a.build_number,
+                a.timestamp,
+            ))
+        });
+    }
+
+    let mut kept: Vec<&PackageRecord> = Vec::new();
+    for records in by_name.values() {
+        let latest = &records[0].version;
+        let mut variants = HashSet::new();
+        for record in records
+            .iter()
+            .take_while(|record| &record.version == latest)
+        {
+            if variants.insert(build_variant(record)) {
+                kept.push(record);
+            }
+        }
+    }
+
+    // Add the best matching package for every dependency that the kept packages do not satisfy.
+    let mut idx = 0;
+    while idx < kept.len() {
+        for dependency in &kept[idx].depends {
+            let Ok(spec) = MatchSpec::from_str(dependency, ParseStrictness::Lenient) else {
+                continue;
+            };
+            let Some(candidates) = spec
+                .name
+                .as_ref()
+                .and_then(|name| by_name.get(name.as_normalized()))
+            else {
+                continue;
+            };
+            if kept.iter().any(|record| spec.matches(record)) {
+                continue;
+            }
+            if let Some(best) = candidates.iter().find(|record| spec.matches(record)) {
+                kept.push(best);
+            }
+        }
+        idx += 1;
+    }
+
+    let kept = kept.into_iter().map(package_key).collect::<HashSet<_>>();
+    let mut packages = repodata.packages.clone();
+    packages.retain(|_, record| kept.contains(&package_key(record)));
+    let mut conda_packages = repodata.conda_packages.clone();
+    conda_packages.retain(|_, record| kept.contains(&package_key(record)));
+
+    RepoData {
+        info: repodata.info.clone(),
+        packages,
+        conda_packages,
+        removed: HashSet::default(),
+        version: repodata.version,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rattler_conda_types::{ChannelInfo, Version};
+    use std::str::FromStr;
+
+    fn record(name: &str, version: &str, build: &str, build_number: u64) -> PackageRecord {
+        let mut record = PackageRecord::new(
+            name.parse().unwrap(),
+            Version::from_str(version).unwrap(),
+            build.to_string(),
+        );
+        record.build_number = build_number;
+        record
+    }
+
+    #[test]
+    fn test_current_repodata() {
+        let mut foo = record("foo", "2.0", "py38_1", 1);
+        foo.depends = vec!["bar <2".to_string()];
+        let records = [
+            record("foo", "1.0", "py38_0", 0),
+            record("foo", "2.0", "py38_0", 0),
+            foo,
+            record("foo", "2.0", "py39_0", 0),
+            record("bar", "1.0", "0", 0),
+            record("bar", "1.5", "0", 0),
+            record("bar", "2.0", "0", 0),
+        ];
+        let repodata = RepoData {
+            info: Some(ChannelInfo {
+                subdir: "noarch".to_string(),
+                base_url: None,
+            }),
+            packages: HashMap::default(),
+            conda_packages: records
+                .into_iter()
+                .map(|record| {
+                    let file_name = format!(
+                        "{}-{}-{}.conda",
+                        record.name.as_normalized(),
+                        record.version,
+                        record.build
+                    );
+                    (file_name, record)
+                })
+                .collect(),
+            removed: HashSet::default(),
+            version: Some(2),
+        };
+
+        let mut file_names = current_repodata(&repodata)
+            .conda_packages
+            .into_keys()
+            .collect::<Vec<_>>();
+        file_names.sort();
+        assert_eq!(
+            file_names,
+            [
+                "bar-1.5-0.conda",
+                "bar-2.0-0.conda",
+                "foo-2.0-py38_1.conda",
+                "foo-2.0-py39_0.conda"
+            ]
+        );
+    }
+}
@@ -1,39 +1,86 @@
-//! Indexing of packages in a output folder to create up to date repodata.json files
+//! Indexing of packages in a output folder to create up to date repodata.json files.
+//!
+//! Besides `repodata.json` a `bz2` and a `zst` compressed variant and a trimmed
+//! `current_repodata.json` are written for every subdir, so the folder can be served as a channel
+//! by a plain static file server. A `channeldata.json` with aggregated metadata of every package is
+//! written to the root of the channel.
+//!
+//! Repodata patches, see [`IndexOptions::repodata_patch`], are applied to the generated repodata
+//! while the unpatched repodata is kept in `repodata_from_packages.json`.
+//!
+//! The packages are read from and the repodata is written to a [`ChannelStorage`]. Use [`index`]
+//! for a channel in a local directory or [`index_storage`] for any other storage backend. With the
+//! `s3` or `gcs` features enabled, `ObjectStorage` indexes a channel in a bucket directly.
 #![deny(missing_docs)]
 
+mod cache;
+mod channeldata;
+mod current_repodata;
+#[cfg(feature = "object-store")]
+mod object_store;
+mod storage;
+
+use cache::IndexCache;
+use channeldata::{channeldata, PackageMetadata};
+use current_repodata::current_repodata;
 use rattler_conda_types::{
-    package::ArchiveType, package::IndexJson, package::PackageFile, ChannelInfo, PackageRecord,
-    Platform, RepoData,
+    package::{AboutJson, ArchiveType, IndexJson, PackageFile, PathsJson, RunExportsJson},
+    ChannelInfo, PackageRecord, Platform, RepoData, RepoDataPatch,
 };
+use rattler_digest::{HashingWriter, Md5, Md5Hash, Sha256, Sha256Hash};
 use rattler_package_streaming::{read, seek};
+use rayon::prelude::*;
 use std::{
     collections::{HashMap, HashSet},
-    ffi::OsStr,
-    io::{Read, Write},
-    path::{Path, PathBuf},
+    io::{Read, SeekFrom, Write},
+    path::Path,
 };
 
-use fs_err::File;
-use walkdir::WalkDir;
+pub use storage::{ChannelStorage, FileSystemStorage, ReadSeek, StorageEntry};
+
+#[cfg(feature = "object-store")]
+pub use object_store::ObjectStorage;
+#[cfg(feature = "object-store")]
+pub use opendal;
 
-fn package_record_from_index_json<T: Read>(
-    file: &Path,
-    index_json_reader: &mut T,
-) -> Result<PackageRecord, std::io::Error> {
-    let index = IndexJson::from_reader(index_json_reader)?;
+/// The name of the file that contains the repodata of a subdir before patches were applied.
+const FROM_PACKAGES_FILE_NAME: &str = "repodata_from_packages.json";
+
+/// Options that control how a channel is indexed.
+#[derive(Debug, Clone, Default)]
+pub struct IndexOptions {
+    /// Hotfixes that are applied to the repodata, e.g. loaded with [`load_repodata_patch`].
+    pub repodata_patch: Option<RepoDataPatch>,
+}
 
-    let sha256_result = rattler_digest::compute_file_digest::<rattler_digest::Sha256>(file)?;
-    let md5_result = rattler_digest::compute_file_digest::<rattler_digest::Md5>(file)?;
-    let size = std::fs::metadata(file)?.len();
+/// Loads repodata patches from a patch package, or from a directory that contains a
+/// `<subdir>/patch_instructions.json` file for every patched subdir, like an extracted patch
+/// package.
+pub fn load_repodata_patch(path: &Path) -> Result<RepoDataPatch, std::io::Error> {
+    if path.is_dir() {
+        return RepoDataPatch::from_package(path);
+    }
+
+    let temp_dir = tempfile::tempdir()?;
+    rattler_package_streaming::fs::extract(path, temp_dir.path())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    RepoDataPatch::from_package(temp_dir.path())
+}
 
-    let package_record = PackageRecord {
+fn package_record_from_index_json(
+    index: IndexJson,
+    size: u64,
+    sha256: Sha256Hash,
+    md5: Md5Hash,
+) -> PackageRecord {
+    PackageRecord {
         name: index.name,
         version: index.version,
         build: index.build,
         build_number: index.build_number,
         subdir: index.subdir.unwrap_or_else(|| "unknown".to_string()),
-        md5: Some(md5_result),
-        sha256: Some(sha256_result),
+        md5: Some(md5),
+        sha256: Some(sha256),
         size: Some(size),
         arch: index.arch,
         platform: index.platform,
@@ -48,99 +95,168 @@ fn package_record_from_index_json<T: Read>(
         legacy_bz2_md5: None,
         legacy_bz2_size: None,
         purls: Vec::default(),
-    };
-    Ok(package_record)
+    }
 }
 
-fn package_record_from_tar_bz2(file: &Path) -> Result<PackageRecord, std::io::Error> {
-    let reader = std::fs::File::open(file)?;
-    let mut archive = read::stream_tar_bz2(reader);
+/// Reads the metadata files from the `info` directory of a package archive.
+fn package_info_from_archive(
+    mut archive: tar::Archive<impl Read>,
+) -> Result<(IndexJson, PackageMetadata), std::io::Error> {
+    let mut index_json = None;
+    let mut paths_json = None;
+    let mut metadata = PackageMetadata::default();
     for entry in archive.entries()?.flatten() {
         let mut entry = entry;
-        let path = entry.path()?;
-        if path.as_os_str().eq("info/index.json") {
-            return package_record_from_index_json(file, &mut entry);
+        let path = entry.path()?.to_string_lossy().replace('\\', "/");
+        match path.as_str() {
+            "info/index.json" => index_json = Some(IndexJson::from_reader(&mut entry)?),
+            "info/about.json" => metadata.about = AboutJson::from_reader(&mut entry).ok(),
+            "info/run_exports.json" => {
+                metadata.run_exports = RunExportsJson::from_reader(&mut entry).ok();
+            }
+            "info/paths.json" => paths_json = PathsJson::from_reader(&mut entry).ok(),
+            // The info directory comes first in a `.tar.bz2` archive.
+            _ if !path.starts_with("info/") && index_json.is_some() => break,
+            _ => {}
         }
     }
-    Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "No index.json found",
-    ))
+
+    let index_json = index_json
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No index.json found"))?;
+    if let Some(paths_json) = paths_json {
+        metadata.set_paths(index_json.name.as_normalized(), &paths_json);
+    }
+    Ok((index_json, metadata))
+}
+
+fn package_info_from_tar_bz2(
+    reader: impl Read,
+) -> Result<(IndexJson, PackageMetadata), std::io::Error> {
+    package_info_from_archive(read::stream_tar_bz2(reader))
 }
 
-fn package_record_from_conda(file: &Path) -> Result<PackageRecord, std::io::Error> {
-    let reader = std::fs::File::open(file)?;
-    let mut archive = seek::stream_conda_info(reader).expect("Could not open conda file");
+fn package_info_from_conda(
+    reader: impl ReadSeek,
+) -> Result<(IndexJson, PackageMetadata), std::io::Error> {
+    let archive = seek::stream_conda_info(reader)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    package_info_from_archive(archive)
+}
 
-    for entry in archive.entries()?.flatten() {
-        let mut entry = entry;
-        let path = entry.path()?;
-        if path.as_os_str().eq("info/index.json") {
-            return package_record_from_index_json(file, &mut entry);
-        }
+/// Computes the SHA256 and MD5 hashes of the contents of a reader in a single pass.
+fn compute_hashes(reader: &mut dyn Read) -> Result<(Sha256Hash, Md5Hash), std::io::Error> {
+    let mut writer = HashingWriter::<_, Sha256>::new(HashingWriter::<_, Md5>::new(std::io::sink()));
+    std::io::copy(reader, &mut writer)?;
+    let (writer, sha256) = writer.finalize();
+    let (_, md5) = writer.finalize();
+    Ok((sha256, md5))
+}
+
+/// Returns the record and metadata of a package. They are taken from the cache if the archive did
+/// not change.
+fn cached_package_record(
+    storage: &dyn ChannelStorage,
+    entry: &StorageEntry,
+    archive_type: ArchiveType,
+    file_name: &str,
+    cache: &IndexCache,
+) -> Result<(PackageRecord, PackageMetadata), std::io::Error> {
+    if let Some((record, Some(metadata))) = cache.get(file_name, entry.size, entry.modified) {
+        return Ok((record.clone(), metadata.clone()));
     }
-    Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "No index.json found",
-    ))
+
+    // The archive was touched or replaced. Hashing is much cheaper than extracting so check
+    // whether the contents actually changed.
+    let mut reader = storage.open(&entry.path)?;
+    let (sha256, md5) = compute_hashes(&mut reader)?;
+    let cached = cache.get_by_hash(file_name, entry.size, &sha256);
+    if let Some((record, Some(metadata))) = cached {
+        return Ok((record.clone(), metadata.clone()));
+    }
+
+    reader.seek(SeekFrom::Start(0))?;
+    let (index_json, metadata) = match archive_type {
+        ArchiveType::TarBz2 => package_info_from_tar_bz2(reader),
+        ArchiveType::Conda => package_info_from_conda(reader),
+    }?;
+
+    // Records from the previous repodata are kept, only their metadata is extracted once.
+    let record = match cached {
+        Some((record, None)) => record.clone(),
+        _ => package_record_from_index_json(index_json, entry.size, sha256, md5),
+    };
+    Ok((record, metadata))
 }
 
 /// Create a new `repodata.json` for all packages in the given output folder. If `target_platform` is
 /// `Some`, only that specific subdir is indexed. Otherwise indexes all subdirs and creates a
 /// `repodata.json` for each.
+///
+/// Indexing is incremental: the metadata of packages whose archive did not change since the
+/// previous index is taken from a metadata cache that is stored in the subdir, so only added and
+/// changed packages are extracted. This is done in parallel. Packages that are only in the
+/// previous `repodata.json` keep their record, but are extracted once for the `channeldata.json`.
+/// Next to every `repodata.json` a `repodata.json.bz2` and `repodata.json.zst` with the same
+/// contents are written, as well as a `current_repodata.json` that only contains the latest
+/// version of every package and the packages required by them. Finally the `channeldata.json` of
+/// the channel is updated.
 pub fn index(
     output_folder: &Path,
     target_platform: Option<&Platform>,
 ) -> Result<(), std::io::Error> {
-    let entries = WalkDir::new(output_folder).into_iter();
-    let entries: Vec<(PathBuf, ArchiveType)> = entries
-        .filter_entry(|e| e.depth() <= 2)
-        .filter_map(Result::ok)
-        .filter_map(|e| {
-            ArchiveType::split_str(e.path().to_string_lossy().as_ref())
-                .map(|(p, t)| (PathBuf::from(format!("{}{}", p, t.extension())), t))
+    index_storage(
+        &FileSystemStorage::new(output_folder),
+        target_platform,
+        &IndexOptions::default(),
+    )
+}
+
+/// Indexes a channel in the given storage backend. See [`index`].
+///
+/// If the options contain repodata patches, they are applied to the `repodata.json` and
+/// `current_repodata.json` of every subdir they contain instructions for. The unpatched repodata
+/// is always written to `repodata_from_packages.json`.
+pub fn index_storage(
+    storage: &dyn ChannelStorage,
+    target_platform: Option<&Platform>,
+    options: &IndexOptions,
+) -> Result<(), std::io::Error> {
+    let entries: Vec<(String, StorageEntry, ArchiveType)> = storage
+        .list()?
+        .into_iter()
+        .filter_map(|entry| {
+            let (subdir, file_name) = entry.path.split_once('/')?;
+            let (_, archive_type) = ArchiveType::split_str(file_name)?;
+            (subdir != "src_cache").then(|| (subdir.to_string(), entry.clone(), archive_type))
         })
         .collect();
 
     // find all subdirs
     let mut platforms = entries
         .iter()
-        .filter_map(|(p, _)| {
-            p.parent().and_then(Path::file_name).and_then(|file_name| {
-                let name = file_name.to_string_lossy().to_string();
-                if name == "src_cache" {
-                    None
-                } else {
-                    Some(name)
-                }
-            })
-        })
-        .collect::<std::collections::HashSet<_>>();
+        .map(|(subdir, _, _)| subdir.clone())
+        .collect::<HashSet<_>>();
 
     // Always create noarch subdir
-    if !output_folder.join("noarch").exists() {
-        std::fs::create_dir(output_folder.join("noarch"))?;
+    if !storage.exists("noarch/repodata.json")? {
         platforms.insert("noarch".to_string());
     }
 
     // Create target platform dir if needed
     if let Some(target_platform) = target_platform {
-        let platform_str = target_platform.to_string();
-        if !output_folder.join(&platform_str).exists() {
-            std::fs::create_dir(output_folder.join(&platform_str))?;
-            platforms.insert(platform_str);
-        }
+        platforms.insert(target_platform.to_string());
     }
 
+    // The packages of every subdir, including the ones that are not indexed again, are needed for
+    // the `channeldata.json`.
+    let mut caches = HashMap::new();
     for platform in platforms {
         if let Some(target_platform) = target_platform {
             if platform != target_platform.to_string() {
-                if platform == "noarch" {
-                    // check that noarch is already indexed if it is not the target platform
-                    if output_folder.join("noarch/repodata.json").exists() {
-                        continue;
-                    }
-                } else {
+                // check that noarch is already indexed if it is not the target platform
+                if platform != "noarch" || storage.exists("noarch/repodata.json")? {
+                    let cache = IndexCache::load(storage, &platform);
+                    caches.insert(platform, cache);
                     continue;
                 }
             }
@@ -157,40 +273,132 @@ pub fn index(
             version: Some(2),
         };
 
-        for (p, t) in entries.iter().filter_map(|(p, t)| {
-            p.parent().and_then(|parent| {
-                parent.file_name().and_then(|file_name| {
-                    if file_name == OsStr::new(&platform) {
-                        // If the file_name is the platform we're looking for, return Some((p, t))
-                        Some((p, t))
-                    } else {
-                        // Otherwise, we return None to filter out this item
-                        None
-                    }
-                })
+        // Only packages that were added or changed since the previous index are extracted. This
+        // requires hashing every new archive so do it in parallel.
+        let cache = IndexCache::load(storage, &platform);
+        let records = entries
+            .par_iter()
+            .filter(|(subdir, _, _)| subdir == &platform)
+            .filter_map(|(_, entry, archive_type)| {
+                let file_name = entry.path.rsplit('/').next()?.to_string();
+                let Ok((record, metadata)) =
+                    cached_package_record(storage, entry, *archive_type, &file_name, &cache)
+                else {
+                    tracing::info!("Could not read package record from {:?}", entry.path);
+                    return None;
+                };
+                Some((*archive_type, file_name, entry, record, metadata))
             })
-        }) {
-            let record = match t {
-                ArchiveType::TarBz2 => package_record_from_tar_bz2(p),
-                ArchiveType::Conda => package_record_from_conda(p),
-            };
-            let (Ok(record), Some(file_name)) = (record, p.file_name()) else {
-                tracing::info!("Could not read package record from {:?}", p);
-                continue;
-            };
-            match t {
-                ArchiveType::TarBz2 => repodata
-                    .packages
-                    .insert(file_name.to_string_lossy().to_string(), record),
-                ArchiveType::Conda => repodata
-                    .conda_packages
-                    .insert(file_name.to_string_lossy().to_string(), record),
+            .collect::<Vec<_>>();
+
+        let mut new_cache = IndexCache::default();
+        for (archive_type, file_name, entry, record, metadata) in records {
+            new_cache.insert(
+                file_name.clone(),
+                entry.size,
+                entry.modified,
+                record.clone(),
+                metadata,
+            );
+            match archive_type {
+                ArchiveType::TarBz2 => repodata.packages.insert(file_name, record),
+                ArchiveType::Conda => repodata.conda_packages.insert(file_name, record),
             };
         }
-        let out_file = output_folder.join(platform).join("repodata.json");
-        File::create(&out_file)?.write_all(serde_json::to_string_pretty(&repodata)?.as_bytes())?;
+        new_cache.write(storage, &platform)?;
+
+        // The unpatched repodata is kept so the patches can be changed or removed later.
+        write_repodata(storage, &platform, FROM_PACKAGES_FILE_NAME, &repodata)?;
+        if let Some(instructions) = options
+            .repodata_patch
+            .as_ref()
+            .and_then(|patch| patch.subdirs.get(&platform))
+        {
+            repodata.apply_patches(instructions);
+        }
+        write_repodata(storage, &platform, "repodata.json", &repodata)?;
+
+        let current_repodata = serde_json::to_vec_pretty(&current_repodata(&repodata))?;
+        storage.write(
+            &format!("{platform}/current_repodata.json"),
+            &mut current_repodata.as_slice(),
+        )?;
+        caches.insert(platform, new_cache);
     }
 
+    let channeldata = channeldata(
+        caches.keys().cloned(),
+        caches.values().flat_map(IndexCache::packages),
+    );
+    storage.write(
+        "channeldata.json",
+        &mut serde_json::to_vec_pretty(&channeldata)?.as_slice(),
+    )?;
+
+    Ok(())
+}
+
+/// Uploads a package archive to the subdir of the channel that is recorded in its metadata. The
+/// contents are streamed from the file. Returns the path of the package in the channel.
+///
+/// The channel has to be indexed afterwards for the package to become available.
+pub fn upload_package(
+    storage: &dyn ChannelStorage,
+    package: &Path,
+) -> Result<String, std::io::Error> {
+    let file_name = package
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing file name")
+        })?;
+    let (_, archive_type) = ArchiveType::split_str(&file_name).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{file_name} is not a conda package"),
+        )
+    })?;
+
+    let (index_json, _) = match archive_type {
+        ArchiveType::TarBz2 => package_info_from_tar_bz2(fs_err::File::open(package)?),
+        ArchiveType::Conda => package_info_from_conda(fs_err::File::open(package)?),
+    }?;
+    let subdir = index_json.subdir.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{file_name} does not specify a subdir"),
+        )
+    })?;
+
+    let path = format!("{subdir}/{file_name}");
+    storage.write(&path, &mut fs_err::File::open(package)?)?;
+    Ok(path)
+}
+
+/// Writes repodata and its `bz2` and `zst` compressed variants to the given subdir.
+fn write_repodata(
+    storage: &dyn ChannelStorage,
+    subdir: &str,
+    file_name: &str,
+    repodata: &RepoData,
+) -> Result<(), std::io::Error> {
+    let contents = serde_json::to_vec_pretty(repodata)?;
+    storage.write(&format!("{subdir}/{file_name}"), &mut contents.as_slice())?;
+
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+    encoder.write_all(&contents)?;
+    storage.write(
+        &format!("{subdir}/{file_name}.bz2"),
+        &mut encoder.finish()?.as_slice(),
+    )?;
+
+    let mut encoder = zstd::Encoder::new(Vec::new(), 19)?;
+    encoder.write_all(&contents)?;
+    storage.write(
+        &format!("{subdir}/{file_name}.zst"),
+        &mut encoder.finish()?.as_slice(),
+    )?;
+
     Ok(())
 }
 
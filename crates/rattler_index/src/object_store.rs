@@ -0,0 +1,94 @@
+//! A [`ChannelStorage`] for channels in object stores like S3 or GCS, backed by
+//! [OpenDAL](https://opendal.apache.org).
+
+use std::{
+    io::{Cursor, Read},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use opendal::{layers::BlockingLayer, BlockingOperator, EntryMode, Metakey, Operator};
+
+use crate::{ChannelStorage, ReadSeek, StorageEntry};
+
+/// A channel that is stored in an object store, e.g. a bucket on S3 or GCS.
+///
+/// The storage is configured with an [`Operator`] of `opendal`, for instance:
+///
+/// ```no_run
+/// # #[cfg(feature = "s3")]
+/// # fn example() -> std::io::Result<()> {
+/// use rattler_index::{opendal, ObjectStorage};
+///
+/// let mut builder = opendal::services::S3::default();
+/// builder.bucket("my-channel").region("eu-west-1");
+/// let storage = ObjectStorage::new(opendal::Operator::new(builder)?.finish())?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The indexer is synchronous while `opendal` is asynchronous. Requests are executed on a runtime
+/// that is owned by the storage, so its methods must not be called from within an asynchronous
+/// context. Use `tokio::task::spawn_blocking` to index a channel from asynchronous code.
+#[derive(Clone)]
+pub struct ObjectStorage {
+    operator: BlockingOperator,
+    _runtime: Arc<tokio::runtime::Runtime>,
+}
+
+impl ObjectStorage {
+    /// Constructs a new instance for the channel at the root of the given operator.
+    pub fn new(operator: Operator) -> Result<Self, std::io::Error> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        let layer = {
+            let _guard = runtime.enter();
+            BlockingLayer::create()?
+        };
+        Ok(Self {
+            operator: operator.layer(layer).blocking(),
+            _runtime: Arc::new(runtime),
+        })
+    }
+}
+
+impl ChannelStorage for ObjectStorage {
+    fn list(&self) -> Result<Vec<StorageEntry>, std::io::Error> {
+        let entries = self
+            .operator
+            .list_with("/")
+            .recursive(true)
+            .metakey(Metakey::Mode | Metakey::ContentLength | Metakey::LastModified)
+            .call()?;
+        Ok(entries
+            .into_iter()
+            .filter(|entry| {
+                entry.metadata().mode() == EntryMode::FILE
+                    && entry.path().trim_start_matches('/').matches('/').count() == 1
+            })
+            .map(|entry| StorageEntry {
+                path: entry.path().trim_start_matches('/').to_string(),
+                size: entry.metadata().content_length(),
+                modified: entry.metadata().last_modified().map(SystemTime::from),
+            })
+            .collect())
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, std::io::Error> {
+        Ok(self.operator.is_exist(path)?)
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, std::io::Error> {
+        // Archives are read with many small seeks, which are expensive requests on an object
+        // store. Download the whole object instead.
+        Ok(Box::new(Cursor::new(self.operator.read(path)?)))
+    }
+
+    fn write(&self, path: &str, contents: &mut dyn Read) -> Result<(), std::io::Error> {
+        let mut writer = self.operator.writer(path)?;
+        std::io::copy(contents, &mut writer)?;
+        Ok(writer.close()?)
+    }
+}
@@ -0,0 +1,113 @@
+//! Storage backends that the indexer reads packages from and writes repodata to. See
+//! [`ChannelStorage`].
+
+use std::{
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use fs_err::File;
+use walkdir::WalkDir;
+
+/// A reader that can also seek, which is required to read the metadata of `.conda` archives.
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// A file in a channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageEntry {
+    /// The path of the file relative to the root of the channel, e.g. `noarch/foo-1.0-0.conda`.
+    /// Components are always separated by a `/`.
+    pub path: String,
+
+    /// The size of the file in bytes.
+    pub size: u64,
+
+    /// The time the file was last modified, if the backend knows it. Without it the indexer has
+    /// to hash a package to find out whether it changed since the previous index.
+    pub modified: Option<SystemTime>,
+}
+
+/// The storage of a channel, e.g. a local directory or a bucket of an object store.
+///
+/// All paths are relative to the root of the channel and use `/` as separator. Implementations
+/// for object stores can list a bucket, download objects to a temporary file or an in-memory
+/// buffer in [`ChannelStorage::open`] and upload objects in [`ChannelStorage::write`], which
+/// lets CI jobs index and publish a bucket-hosted channel without mirroring it locally first.
+pub trait ChannelStorage: Send + Sync {
+    /// Returns all the files that are directly contained in a subdirectory of the channel.
+    fn list(&self) -> Result<Vec<StorageEntry>, std::io::Error>;
+
+    /// Returns true if the file at the given path exists.
+    fn exists(&self, path: &str) -> Result<bool, std::io::Error>;
+
+    /// Opens the file at the given path for reading.
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, std::io::Error>;
+
+    /// Writes the file at the given path, replacing any existing file. The contents are streamed
+    /// from `contents`.
+    fn write(&self, path: &str, contents: &mut dyn Read) -> Result<(), std::io::Error>;
+}
+
+/// A channel that is stored in a directory on the local filesystem.
+#[derive(Debug, Clone)]
+pub struct FileSystemStorage {
+    root: PathBuf,
+}
+
+impl FileSystemStorage {
+    /// Constructs a new instance for the channel in the given directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Returns the directory that contains the channel.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl ChannelStorage for FileSystemStorage {
+    fn list(&self) -> Result<Vec<StorageEntry>, std::io::Error> {
+        let mut entries = Vec::new();
+        for entry in WalkDir::new(&self.root).min_depth(2).max_depth(2) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(path) = entry.path().strip_prefix(&self.root) else {
+                continue;
+            };
+            let metadata = entry.metadata()?;
+            entries.push(StorageEntry {
+                path: path
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn exists(&self, path: &str) -> Result<bool, std::io::Error> {
+        Ok(self.root.join(path).exists())
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, std::io::Error> {
+        Ok(Box::new(File::open(self.root.join(path))?))
+    }
+
+    fn write(&self, path: &str, contents: &mut dyn Read) -> Result<(), std::io::Error> {
+        let path = self.root.join(path);
+        if let Some(parent) = path.parent() {
+            fs_err::create_dir_all(parent)?;
+        }
+        std::io::copy(contents, &mut File::create(path)?)?;
+        Ok(())
+    }
+}
@@ -1,9 +1,15 @@
 use rattler_conda_types::Platform;
-use rattler_index::index;
+use rattler_index::{
+    index, index_storage, load_repodata_patch, ChannelStorage, FileSystemStorage, IndexOptions,
+    ReadSeek, StorageEntry,
+};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
+use std::io::{Cursor, Read};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 fn test_data_dir() -> PathBuf {
     Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data")
@@ -70,3 +76,287 @@ fn test_index_empty_directory() {
     assert!(res.is_ok());
     assert_eq!(fs::read_dir(temp_dir).unwrap().count(), 0);
 }
+
+#[test]
+fn test_index_compressed_variants() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    index(temp_dir.path(), Some(&Platform::Linux64)).unwrap();
+
+    for subdir in ["noarch", "linux-64"] {
+        let subdir = temp_dir.path().join(subdir);
+        let repodata = fs::read(subdir.join("repodata.json")).unwrap();
+        let repodata_json: Value = serde_json::from_slice(&repodata).unwrap();
+        assert!(repodata_json.get("packages").is_some());
+
+        let mut bz2 = Vec::new();
+        bzip2::read::BzDecoder::new(File::open(subdir.join("repodata.json.bz2")).unwrap())
+            .read_to_end(&mut bz2)
+            .unwrap();
+        assert_eq!(bz2, repodata);
+
+        let zst = zstd::decode_all(File::open(subdir.join("repodata.json.zst")).unwrap()).unwrap();
+        assert_eq!(zst, repodata);
+    }
+}
+
+/// Writes an archive that is not a valid package to the subdir together with a `repodata.json`
+/// that contains a record for it. The package can only end up in the index if the record from the
+/// previous repodata is reused.
+/// Returns a `.tar.bz2` archive that contains the given files.
+fn tar_bz2_archive(files: &[(&str, Value)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(bzip2::write::BzEncoder::new(
+        Vec::new(),
+        bzip2::Compression::default(),
+    ));
+    for (path, contents) in files {
+        let contents = contents.to_string();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, path, contents.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+fn write_previously_indexed_package(subdir: &Path) -> &'static str {
+    let file_name = "foo-1.0-0.tar.bz2";
+    let contents = tar_bz2_archive(&[
+        (
+            "info/index.json",
+            serde_json::json!({
+                "name": "foo",
+                "version": "1.0",
+                "build": "0",
+                "build_number": 0,
+                "depends": [],
+                "subdir": "noarch",
+            }),
+        ),
+        ("info/about.json", serde_json::json!({ "summary": "Foo" })),
+    ]);
+    fs::write(subdir.join(file_name), &contents).unwrap();
+    let sha256 = rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(&contents);
+    let previous = serde_json::json!({
+        "info": { "subdir": "noarch" },
+        "packages": {
+            file_name: {
+                "name": "foo",
+                "version": "1.0",
+                "build": "0",
+                "build_number": 0,
+                "depends": [],
+                "subdir": "noarch",
+                "license": "MIT",
+                "size": contents.len(),
+                "sha256": format!("{sha256:x}"),
+            }
+        },
+        "packages.conda": {},
+        "repodata_version": 2,
+    });
+    fs::write(subdir.join("repodata.json"), previous.to_string()).unwrap();
+    file_name
+}
+
+#[test]
+fn test_index_reuses_previous_repodata() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let subdir = temp_dir.path().join("noarch");
+    fs::create_dir(&subdir).unwrap();
+    let file_name = write_previously_indexed_package(&subdir);
+
+    let indexed_packages = || {
+        index(temp_dir.path(), None).unwrap();
+        let repodata: Value =
+            serde_json::from_reader(File::open(subdir.join("repodata.json")).unwrap()).unwrap();
+        repodata
+            .get("packages")
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(indexed_packages(), [file_name]);
+    assert!(subdir.join(".index-cache.json").is_file());
+    let repodata: Value =
+        serde_json::from_reader(File::open(subdir.join("repodata.json")).unwrap()).unwrap();
+    assert_eq!(repodata["packages"][file_name]["license"], "MIT");
+
+    let channeldata: Value =
+        serde_json::from_reader(File::open(temp_dir.path().join("channeldata.json")).unwrap())
+            .unwrap();
+    let foo = channeldata.get("packages").unwrap().get("foo").unwrap();
+    assert_eq!(foo.get("version").unwrap(), "1.0");
+    assert_eq!(foo.get("subdirs").unwrap(), &serde_json::json!(["noarch"]));
+    // The metadata is extracted even though the record is taken from the previous repodata.
+    assert_eq!(foo.get("summary").unwrap(), "Foo");
+
+    // Subsequent runs use the cache.
+    assert_eq!(indexed_packages(), [file_name]);
+
+    // Changed archives are extracted again.
+    fs::write(subdir.join(file_name), b"not a real package either").unwrap();
+    assert!(indexed_packages().is_empty());
+}
+
+/// A storage backend that keeps the channel in memory, similar to an object store.
+#[derive(Default)]
+struct InMemoryStorage {
+    files: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl ChannelStorage for InMemoryStorage {
+    fn list(&self) -> std::io::Result<Vec<StorageEntry>> {
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, contents)| StorageEntry {
+                path: path.clone(),
+                size: contents.len() as u64,
+                modified: None,
+            })
+            .collect())
+    }
+
+    fn exists(&self, path: &str) -> std::io::Result<bool> {
+        Ok(self.files.lock().unwrap().contains_key(path))
+    }
+
+    fn open(&self, path: &str) -> std::io::Result<Box<dyn ReadSeek>> {
+        let contents = self.files.lock().unwrap().get(path).cloned();
+        contents
+            .map(|contents| Box::new(Cursor::new(contents)) as Box<dyn ReadSeek>)
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+
+    fn write(&self, path: &str, contents: &mut dyn Read) -> std::io::Result<()> {
+        let mut buffer = Vec::new();
+        contents.read_to_end(&mut buffer)?;
+        self.files.lock().unwrap().insert(path.to_string(), buffer);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_index_storage() {
+    let storage = InMemoryStorage::default();
+    index_storage(&storage, Some(&Platform::Linux64), &IndexOptions::default()).unwrap();
+
+    let mut paths = storage
+        .files
+        .lock()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+        paths,
+        [
+            "channeldata.json",
+            "linux-64/.index-cache.json",
+            "linux-64/current_repodata.json",
+            "linux-64/repodata.json",
+            "linux-64/repodata.json.bz2",
+            "linux-64/repodata.json.zst",
+            "linux-64/repodata_from_packages.json",
+            "linux-64/repodata_from_packages.json.bz2",
+            "linux-64/repodata_from_packages.json.zst",
+            "noarch/.index-cache.json",
+            "noarch/current_repodata.json",
+            "noarch/repodata.json",
+            "noarch/repodata.json.bz2",
+            "noarch/repodata.json.zst",
+            "noarch/repodata_from_packages.json",
+            "noarch/repodata_from_packages.json.bz2",
+            "noarch/repodata_from_packages.json.zst",
+        ]
+    );
+}
+
+#[cfg(feature = "object-store")]
+#[test]
+fn test_index_object_storage() {
+    use rattler_index::{opendal, ObjectStorage};
+
+    let operator = opendal::Operator::new(opendal::services::Memory::default())
+        .unwrap()
+        .finish();
+    let storage = ObjectStorage::new(operator).unwrap();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let file_name = write_previously_indexed_package(temp_dir.path());
+    for name in [file_name, "repodata.json"] {
+        let contents = fs::read(temp_dir.path().join(name)).unwrap();
+        storage
+            .write(&format!("noarch/{name}"), &mut contents.as_slice())
+            .unwrap();
+    }
+
+    index_storage(&storage, None, &IndexOptions::default()).unwrap();
+
+    assert!(storage.exists("channeldata.json").unwrap());
+    let mut repodata = String::new();
+    storage
+        .open("noarch/repodata.json")
+        .unwrap()
+        .read_to_string(&mut repodata)
+        .unwrap();
+    let repodata: Value = serde_json::from_str(&repodata).unwrap();
+    assert!(repodata.get("packages").unwrap().get(file_name).is_some());
+    assert!(storage
+        .list()
+        .unwrap()
+        .iter()
+        .any(|entry| entry.path == format!("noarch/{file_name}")
+            && entry.size == fs::metadata(temp_dir.path().join(file_name)).unwrap().len()));
+}
+
+#[test]
+fn test_index_repodata_patch() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let subdir = temp_dir.path().join("noarch");
+    fs::create_dir(&subdir).unwrap();
+    let file_name = write_previously_indexed_package(&subdir);
+
+    let patch_dir = tempfile::tempdir().unwrap();
+    fs::create_dir(patch_dir.path().join("noarch")).unwrap();
+    fs::write(
+        patch_dir.path().join("noarch/patch_instructions.json"),
+        serde_json::json!({
+            "packages": { file_name: { "depends": ["bar"] } },
+            "patch_instructions_version": 1,
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let options = IndexOptions {
+        repodata_patch: Some(load_repodata_patch(patch_dir.path()).unwrap()),
+    };
+    let depends = |repodata: &str| {
+        let repodata: Value =
+            serde_json::from_reader(File::open(subdir.join(repodata)).unwrap()).unwrap();
+        repodata["packages"][file_name]["depends"].clone()
+    };
+
+    // Indexing again must not apply the patch to already patched records.
+    for _ in 0..2 {
+        index_storage(&FileSystemStorage::new(temp_dir.path()), None, &options).unwrap();
+        assert_eq!(depends("repodata.json"), serde_json::json!(["bar"]));
+        assert_eq!(depends("current_repodata.json"), serde_json::json!(["bar"]));
+        assert_eq!(
+            depends("repodata_from_packages.json"),
+            serde_json::json!([])
+        );
+    }
+
+    // Without the patch the original record is restored.
+    index(temp_dir.path(), None).unwrap();
+    assert_eq!(depends("repodata.json"), serde_json::json!([]));
+}
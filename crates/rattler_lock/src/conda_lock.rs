@@ -0,0 +1,421 @@
+//! Reading and writing of the unified lock-file format of
+//! [`conda-lock`](https://github.com/conda/conda-lock), version 1.
+//!
+//! [`LockFile::from_str`] can already read these files but only retains the information that is
+//! part of the rattler lock-file format. [`CondaLockFile`] models the conda-lock format itself, so
+//! categories, optional packages and the metadata are preserved and a file can be read and written
+//! without losing information. Hashes and URLs are stored verbatim.
+
+use std::{collections::BTreeSet, path::Path, str::FromStr};
+
+use indexmap::IndexMap;
+use pep508_rs::VersionOrUrl;
+use rattler_conda_types::Platform;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{Channel, LockFile, Package, ParseCondaLockError, UrlOrPath};
+
+/// The category of packages that are installed by default.
+pub const DEFAULT_CATEGORY: &str = "main";
+
+/// A lock-file in the unified format of conda-lock, version 1.
+///
+/// The fields are serialized in alphabetical order, like conda-lock does.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CondaLockFile {
+    /// Information about how the lock-file was created.
+    pub metadata: CondaLockMetadata,
+
+    /// The locked packages of all platforms.
+    #[serde(default)]
+    pub package: Vec<CondaLockPackage>,
+
+    /// The version of the format, always 1.
+    pub version: u64,
+}
+
+/// The metadata of a [`CondaLockFile`].
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CondaLockMetadata {
+    /// The channels that were used to solve the environment, in order of priority.
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+
+    /// The hash of the inputs of the lock-file, per platform.
+    #[serde(default)]
+    pub content_hash: IndexMap<Platform, String>,
+
+    /// Arbitrary metadata added by the user.
+    pub custom_metadata: Option<serde_yaml::Value>,
+
+    /// Information about the git repository the lock-file was created in.
+    pub git_metadata: Option<serde_yaml::Value>,
+
+    /// Information about the input files.
+    pub inputs_metadata: Option<serde_yaml::Value>,
+
+    /// The platforms the lock-file supports.
+    #[serde(default)]
+    pub platforms: Vec<Platform>,
+
+    /// The paths of the input files.
+    #[serde(default)]
+    pub sources: Vec<String>,
+
+    /// The time the lock-file was created.
+    pub time_metadata: Option<serde_yaml::Value>,
+}
+
+/// The package manager that installs a [`CondaLockPackage`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum CondaLockManager {
+    /// A conda package.
+    Conda,
+
+    /// A python package that is installed with pip.
+    Pip,
+}
+
+/// The hashes of a [`CondaLockPackage`] as hex strings.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CondaLockHash {
+    /// The MD5 hash of the package.
+    pub md5: Option<String>,
+
+    /// The SHA256 hash of the package.
+    pub sha256: Option<String>,
+}
+
+/// A locked package for a single platform.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CondaLockPackage {
+    /// The category of the package, e.g. `main` or `dev`.
+    #[serde(default = "default_category")]
+    pub category: String,
+
+    /// The dependencies of the package, a map from package name to version specification.
+    #[serde(default)]
+    pub dependencies: IndexMap<String, String>,
+
+    /// The hashes of the package.
+    #[serde(default)]
+    pub hash: CondaLockHash,
+
+    /// The package manager that installs the package.
+    pub manager: CondaLockManager,
+
+    /// The name of the package.
+    pub name: String,
+
+    /// Whether the package is only installed when its category is requested.
+    #[serde(default)]
+    pub optional: bool,
+
+    /// The platform the package is locked for.
+    pub platform: Platform,
+
+    /// Where the package was built from, only used for pip packages.
+    pub source: Option<serde_yaml::Value>,
+
+    /// The URL of the package.
+    pub url: String,
+
+    /// The version of the package.
+    pub version: String,
+}
+
+fn default_category() -> String {
+    DEFAULT_CATEGORY.to_string()
+}
+
+impl FromStr for CondaLockFile {
+    type Err = ParseCondaLockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conda_lock: Self = serde_yaml::from_str(s)?;
+        if conda_lock.version != 1 {
+            return Err(ParseCondaLockError::UnsupportedCondaLockVersion(
+                conda_lock.version,
+            ));
+        }
+        Ok(conda_lock)
+    }
+}
+
+impl CondaLockFile {
+    /// Parses a conda-lock file from a file.
+    pub fn from_path(path: &Path) -> Result<Self, ParseCondaLockError> {
+        Self::from_str(&std::fs::read_to_string(path)?)
+    }
+
+    /// Writes the conda-lock file to a file.
+    pub fn to_path(&self, path: &Path) -> Result<(), std::io::Error> {
+        std::fs::write(path, self.render())
+    }
+
+    /// Returns the conda-lock file as YAML.
+    pub fn render(&self) -> String {
+        serde_yaml::to_string(self).expect("serializing a conda-lock file cannot fail")
+    }
+
+    /// Returns the categories of the packages in the file.
+    pub fn categories(&self) -> BTreeSet<&str> {
+        self.package
+            .iter()
+            .map(|package| package.category.as_str())
+            .collect()
+    }
+
+    /// Returns a copy that only contains the packages of the given categories. Packages in the
+    /// [`DEFAULT_CATEGORY`] are always included.
+    #[must_use]
+    pub fn with_categories(&self, categories: &[&str]) -> Self {
+        Self {
+            package: self
+                .package
+                .iter()
+                .filter(|package| {
+                    package.category == DEFAULT_CATEGORY
+                        || categories.contains(&package.category.as_str())
+                })
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Converts the file to a [`LockFile`] with a single default environment. Categories are not
+    /// represented in a [`LockFile`], use [`CondaLockFile::with_categories`] to select the
+    /// packages first.
+    pub fn to_lock_file(&self) -> Result<LockFile, ParseCondaLockError> {
+        LockFile::from_str(&self.render())
+    }
+
+    /// Creates a conda-lock file from an environment of a [`LockFile`]. Returns `None` if the
+    /// environment does not exist.
+    ///
+    /// All packages are placed in the [`DEFAULT_CATEGORY`]. The content hashes of conda-lock cannot
+    /// be computed without the inputs of the lock-file, so they are left empty.
+    pub fn from_lock_file(lock_file: &LockFile, environment: &str) -> Option<Self> {
+        let environment = lock_file.environment(environment)?;
+        let mut platforms = environment.platforms().collect::<Vec<_>>();
+        platforms.sort();
+
+        let mut package = Vec::new();
+        for platform in &platforms {
+            for locked in environment.packages(*platform).into_iter().flatten() {
+                package.push(CondaLockPackage::from_package(&locked, *platform));
+            }
+        }
+
+        Some(Self {
+            metadata: CondaLockMetadata {
+                channels: environment.channels().to_vec(),
+                content_hash: IndexMap::new(),
+                custom_metadata: None,
+                git_metadata: None,
+                inputs_metadata: None,
+                platforms,
+                sources: Vec::new(),
+                time_metadata: None,
+            },
+            package,
+            version: 1,
+        })
+    }
+}
+
+impl CondaLockPackage {
+    fn from_package(package: &Package, platform: Platform) -> Self {
+        match package {
+            Package::Conda(conda) => {
+                let record = conda.package_record();
+                let dependencies = record
+                    .depends
+                    .iter()
+                    .map(|spec| match spec.split_once(' ') {
+                        Some((name, constraint)) => {
+                            (name.to_string(), constraint.trim().to_string())
+                        }
+                        None => (spec.clone(), String::from("*")),
+                    })
+                    .collect();
+                Self {
+                    category: default_category(),
+                    dependencies,
+                    hash: CondaLockHash {
+                        md5: record.md5.map(|hash| format!("{hash:x}")),
+                        sha256: record.sha256.map(|hash| format!("{hash:x}")),
+                    },
+                    manager: CondaLockManager::Conda,
+                    name: record.name.as_source().to_string(),
+                    optional: false,
+                    platform,
+                    source: None,
+                    url: conda.url().to_string(),
+                    version: record.version.as_str().to_string(),
+                }
+            }
+            Package::Pypi(pypi) => {
+                let data = pypi.data().package;
+                let dependencies = data
+                    .requires_dist
+                    .iter()
+                    .map(|requirement| {
+                        let constraint = match &requirement.version_or_url {
+                            Some(VersionOrUrl::VersionSpecifier(specifiers)) => {
+                                specifiers.to_string()
+                            }
+                            Some(VersionOrUrl::Url(url)) => url.to_string(),
+                            None => String::from("*"),
+                        };
+                        (requirement.name.to_string(), constraint)
+                    })
+                    .collect();
+                let url = match &data.url_or_path {
+                    UrlOrPath::Url(url) => url.to_string(),
+                    UrlOrPath::Path(path) => path.to_string_lossy().into_owned(),
+                };
+                Self {
+                    category: default_category(),
+                    dependencies,
+                    hash: CondaLockHash {
+                        md5: data
+                            .hash
+                            .as_ref()
+                            .and_then(|hash| hash.md5())
+                            .map(|hash| format!("{hash:x}")),
+                        sha256: data
+                            .hash
+                            .as_ref()
+                            .and_then(|hash| hash.sha256())
+                            .map(|hash| format!("{hash:x}")),
+                    },
+                    manager: CondaLockManager::Pip,
+                    name: data.name.to_string(),
+                    optional: false,
+                    platform,
+                    source: None,
+                    url,
+                    version: data.version.to_string(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEFAULT_ENVIRONMENT_NAME;
+
+    fn test_data(file_name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock")
+            .join(file_name)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = test_data("v0/pypi-matplotlib-conda-lock.yml");
+        let conda_lock = CondaLockFile::from_path(&path).unwrap();
+        assert_eq!(conda_lock.categories(), BTreeSet::from(["main"]));
+
+        let reparsed = CondaLockFile::from_str(&conda_lock.render()).unwrap();
+        assert_eq!(reparsed, conda_lock);
+
+        // Hashes and urls are preserved verbatim.
+        let original: serde_yaml::Value =
+            serde_yaml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        for (package, original) in conda_lock
+            .package
+            .iter()
+            .zip(original["package"].as_sequence().unwrap())
+        {
+            assert_eq!(package.url, original["url"].as_str().unwrap());
+            assert_eq!(
+                package.hash.sha256.as_deref(),
+                original["hash"]["sha256"].as_str()
+            );
+        }
+
+        let lock_file = conda_lock.to_lock_file().unwrap();
+        let environment = lock_file.default_environment().unwrap();
+        assert_eq!(
+            environment
+                .packages(Platform::Linux64)
+                .unwrap()
+                .filter(Package::is_pypi)
+                .count(),
+            conda_lock
+                .package
+                .iter()
+                .filter(|package| package.manager == CondaLockManager::Pip
+                    && package.platform == Platform::Linux64)
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let contents = std::fs::read_to_string(test_data("v0/pypi-matplotlib-conda-lock.yml"))
+            .unwrap()
+            .replace("\nversion: 1\n", "\nversion: 2\n");
+        assert!(matches!(
+            CondaLockFile::from_str(&contents),
+            Err(ParseCondaLockError::UnsupportedCondaLockVersion(2))
+        ));
+    }
+
+    #[test]
+    fn test_categories() {
+        let mut conda_lock =
+            CondaLockFile::from_path(&test_data("v0/python-conda-lock.yml")).unwrap();
+        let total = conda_lock.package.len();
+        conda_lock.package[0].category = String::from("dev");
+        conda_lock.package[0].optional = true;
+
+        assert_eq!(conda_lock.categories(), BTreeSet::from(["dev", "main"]));
+        assert_eq!(conda_lock.with_categories(&[]).package.len(), total - 1);
+        assert_eq!(conda_lock.with_categories(&["dev"]).package.len(), total);
+    }
+
+    #[test]
+    fn test_from_lock_file() {
+        let lock_file = LockFile::from_path(&test_data("v4/pypi-matplotlib-lock.yml")).unwrap();
+        let conda_lock =
+            CondaLockFile::from_lock_file(&lock_file, DEFAULT_ENVIRONMENT_NAME).unwrap();
+        assert!(CondaLockFile::from_lock_file(&lock_file, "missing").is_none());
+
+        let environment = lock_file.default_environment().unwrap();
+        let package_count = environment
+            .platforms()
+            .map(|platform| environment.packages(platform).unwrap().len())
+            .sum::<usize>();
+        assert_eq!(conda_lock.package.len(), package_count);
+
+        // The conda-lock file can be read back as a lock-file with the same packages.
+        let round_tripped = conda_lock.to_lock_file().unwrap();
+        let round_tripped_environment = round_tripped.default_environment().unwrap();
+        for platform in environment.platforms() {
+            let mut expected = environment
+                .packages(platform)
+                .unwrap()
+                .map(|package| package.url_or_path().into_owned())
+                .collect::<Vec<_>>();
+            let mut actual = round_tripped_environment
+                .packages(platform)
+                .unwrap()
+                .map(|package| package.url_or_path().into_owned())
+                .collect::<Vec<_>>();
+            expected.sort_by_key(ToString::to_string);
+            actual.sort_by_key(ToString::to_string);
+            assert_eq!(actual, expected);
+        }
+    }
+}
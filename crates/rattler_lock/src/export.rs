@@ -0,0 +1,222 @@
+//! Exporting locked environments to formats that plain conda and micromamba can install: explicit
+//! environment files (`@EXPLICIT`) and pinned `environment.yml` files.
+
+use std::collections::HashMap;
+
+use rattler_conda_types::{ExplicitEnvironmentEntry, ExplicitEnvironmentSpec, Platform};
+use serde::Serialize;
+
+use crate::{CondaPackage, Environment, Package};
+
+/// The contents of an `environment.yml` file.
+#[derive(Serialize)]
+struct EnvironmentYaml {
+    name: String,
+    channels: Vec<String>,
+    dependencies: Vec<EnvironmentYamlDependency>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum EnvironmentYamlDependency {
+    Conda(String),
+    Pip { pip: Vec<String> },
+}
+
+impl Environment {
+    /// Converts the conda packages of the given platform to an explicit environment file. Returns
+    /// `None` if the environment is not locked for the platform.
+    ///
+    /// The packages are ordered such that dependencies come before the packages that depend on
+    /// them, which is the order in which conda installs them. The MD5 hash of a package, or its
+    /// SHA256 hash if it has no MD5 hash, is added as the fragment of its url so the download is
+    /// verified. Pypi packages cannot be represented in an explicit environment file and are
+    /// ignored.
+    pub fn to_explicit_environment_spec(
+        &self,
+        platform: Platform,
+    ) -> Option<ExplicitEnvironmentSpec> {
+        let packages = install_order(
+            self.packages(platform)?
+                .filter_map(Package::into_conda)
+                .collect(),
+        );
+
+        Some(ExplicitEnvironmentSpec {
+            platform: Some(platform),
+            packages: packages
+                .iter()
+                .map(|package| {
+                    let record = package.package_record();
+                    let mut url = package.url().clone();
+                    if let Some(md5) = record.md5 {
+                        url.set_fragment(Some(&format!("{md5:x}")));
+                    } else if let Some(sha256) = record.sha256 {
+                        url.set_fragment(Some(&format!("sha256:{sha256:x}")));
+                    }
+                    ExplicitEnvironmentEntry { url }
+                })
+                .collect(),
+        })
+    }
+
+    /// Renders the packages of the given platform as an `environment.yml` file with the given
+    /// name. Returns `None` if the environment is not locked for the platform.
+    ///
+    /// Conda packages are pinned to their exact version and, if known, build. Pypi packages are listed in a
+    /// `pip` section as direct references with their hashes, see
+    /// [`crate::PypiPackageData::pip_requirement`]. Note that, unlike an explicit environment
+    /// file, conda still runs a solve to install an `environment.yml`.
+    pub fn to_environment_yaml(&self, name: &str, platform: Platform) -> Option<String> {
+        let mut conda = Vec::new();
+        let mut pip = Vec::new();
+        for package in self.packages(platform)? {
+            match package {
+                Package::Conda(package) => {
+                    // Lock-files converted from older formats may lack the build string.
+                    let record = package.package_record();
+                    let mut spec = format!("{}={}", record.name.as_normalized(), record.version);
+                    if !record.build.is_empty() {
+                        spec = format!("{spec}={}", record.build);
+                    }
+                    conda.push(spec);
+                }
+                Package::Pypi(package) => pip.push(package.pip_requirement()),
+            }
+        }
+        conda.sort();
+        pip.sort();
+
+        let mut dependencies = conda
+            .into_iter()
+            .map(EnvironmentYamlDependency::Conda)
+            .collect::<Vec<_>>();
+        if !pip.is_empty() {
+            dependencies.push(EnvironmentYamlDependency::Pip { pip });
+        }
+
+        let environment = EnvironmentYaml {
+            name: name.to_string(),
+            channels: self
+                .channels()
+                .iter()
+                .map(|channel| channel.url.clone())
+                .collect(),
+            dependencies,
+        };
+        Some(
+            serde_yaml::to_string(&environment)
+                .expect("serializing an environment.yml cannot fail"),
+        )
+    }
+}
+
+/// Orders the packages such that every package comes after its dependencies. Packages are visited
+/// in alphabetical order so the result is deterministic. Dependency cycles are broken arbitrarily.
+fn install_order(mut packages: Vec<CondaPackage>) -> Vec<CondaPackage> {
+    fn visit(
+        idx: usize,
+        packages: &[CondaPackage],
+        indices: &HashMap<String, usize>,
+        visited: &mut [bool],
+        order: &mut Vec<usize>,
+    ) {
+        if visited[idx] {
+            return;
+        }
+        visited[idx] = true;
+        for dependency in &packages[idx].package_record().depends {
+            let name = dependency
+                .split(|c: char| c.is_whitespace() || "=<>!~[".contains(c))
+                .next()
+                .unwrap_or_default();
+            if let Some(&dependency_idx) = indices.get(name) {
+                visit(dependency_idx, packages, indices, visited, order);
+            }
+        }
+        order.push(idx);
+    }
+
+    packages.sort_by(|a, b| a.package_record().name.cmp(&b.package_record().name));
+    let indices = packages
+        .iter()
+        .enumerate()
+        .map(|(idx, package)| {
+            (
+                package.package_record().name.as_normalized().to_string(),
+                idx,
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut visited = vec![false; packages.len()];
+    let mut order = Vec::with_capacity(packages.len());
+    for idx in 0..packages.len() {
+        visit(idx, &packages, &indices, &mut visited, &mut order);
+    }
+
+    let mut packages = packages.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .into_iter()
+        .filter_map(|idx| packages[idx].take())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::LockFile;
+    use rattler_conda_types::Platform;
+    use std::path::Path;
+
+    fn lock_file(file_name: &str) -> LockFile {
+        LockFile::from_path(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../../test-data/conda-lock")
+                .join(file_name),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_explicit_environment_spec() {
+        let lock_file = lock_file("v4/python-lock.yml");
+        let environment = lock_file.default_environment().unwrap();
+        let spec = environment
+            .to_explicit_environment_spec(Platform::Linux64)
+            .unwrap();
+        assert_eq!(
+            spec.packages.len(),
+            environment.packages(Platform::Linux64).unwrap().len()
+        );
+
+        // Dependencies are installed before the packages that depend on them.
+        let position = |name: &str| {
+            spec.packages
+                .iter()
+                .position(|package| {
+                    package
+                        .url
+                        .path_segments()
+                        .and_then(Iterator::last)
+                        .is_some_and(|file_name| file_name.starts_with(&format!("{name}-")))
+                })
+                .unwrap()
+        };
+        assert!(position("openssl") < position("python"));
+        assert!(position("ca-certificates") < position("openssl"));
+
+        insta::assert_snapshot!(spec.to_string());
+        assert!(environment
+            .to_explicit_environment_spec(Platform::LinuxS390X)
+            .is_none());
+    }
+
+    #[test]
+    fn test_environment_yaml() {
+        let lock_file = lock_file("v4/pypi-matplotlib-lock.yml");
+        let environment = lock_file.default_environment().unwrap();
+        insta::assert_snapshot!(environment
+            .to_environment_yaml("matplotlib", Platform::Linux64)
+            .unwrap());
+    }
+}
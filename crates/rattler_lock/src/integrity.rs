@@ -0,0 +1,274 @@
+//! Verifying that a lock-file was not modified after it was generated.
+//!
+//! Two complementary mechanisms are provided:
+//!
+//! * An embedded content hash. [`LockFile::render_with_content_hash`] appends a `content_hash`
+//!   field with the SHA256 hash of the canonical serialization of the lock-file, and
+//!   [`LockFile::from_str_verified`] checks it while parsing. This detects accidental or careless
+//!   modifications but anyone who can modify the file can also update the hash.
+//! * Detached signatures. [`sign_lock_file`] signs the bytes of a lock-file on disk with one or
+//!   more ed25519 keys. The signatures are stored next to the lock-file (see [`signatures_path`])
+//!   in the same format as package signatures, see [`rattler_package_streaming::signing`].
+//!   [`LockFile::from_path_signed`] checks them against a set of [`TrustedKeys`] before parsing
+//!   the lock-file.
+//!
+//! The content hash is computed over the canonical serialization of the lock-file and not over the
+//! bytes of the file, so it only changes when the contents of the lock-file change. Signatures
+//! cover the exact bytes that are read, so any modification of the file invalidates them.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rattler_digest::{compute_bytes_digest, Sha256, Sha256Hash};
+use rattler_package_streaming::signing::{sign_payload, SignatureError};
+use serde::Deserialize;
+
+use crate::{LockFile, ParseCondaLockError};
+
+pub use rattler_package_streaming::signing::{
+    Signature, Signatures, SigningKey, TrustedKeys, VerifyingKey,
+};
+
+/// The name of the field that contains the embedded content hash.
+const CONTENT_HASH_FIELD: &str = "content_hash";
+
+/// The prefix of the embedded content hash that identifies the hash algorithm.
+const CONTENT_HASH_PREFIX: &str = "sha256:";
+
+/// An error that can occur when verifying the integrity of a lock-file.
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum IntegrityError {
+    #[error(transparent)]
+    ParseError(#[from] ParseCondaLockError),
+
+    #[error("an io error occurred")]
+    IoError(#[from] std::io::Error),
+
+    #[error("the lock-file does not contain a content hash")]
+    MissingContentHash,
+
+    #[error("invalid content hash '{0}'")]
+    InvalidContentHash(String),
+
+    #[error("the content hash of the lock-file is {actual} but {expected} was recorded")]
+    ContentHashMismatch { expected: String, actual: String },
+
+    #[error(transparent)]
+    SignatureError(#[from] SignatureError),
+}
+
+/// The fields of a document that are needed to verify the embedded content hash.
+#[derive(Deserialize)]
+struct EmbeddedContentHash {
+    content_hash: Option<String>,
+}
+
+impl LockFile {
+    /// Returns the SHA256 hash of the canonical serialization of the lock-file. The embedded
+    /// content hash itself is not part of the hash.
+    pub fn content_hash(&self) -> Sha256Hash {
+        compute_bytes_digest::<Sha256>(self.render())
+    }
+
+    /// Returns the canonical serialization of the lock-file.
+    fn render(&self) -> String {
+        serde_yaml::to_string(self).expect("serializing a lock-file cannot fail")
+    }
+
+    /// Returns the serialization of the lock-file with the content hash embedded in it.
+    ///
+    /// The result is still a valid lock-file. Readers that don't know about the content hash
+    /// ignore it.
+    pub fn render_with_content_hash(&self) -> String {
+        let rendered = self.render();
+        let hash = compute_bytes_digest::<Sha256>(&rendered);
+        format!("{rendered}{CONTENT_HASH_FIELD}: \"{CONTENT_HASH_PREFIX}{hash:x}\"\n")
+    }
+
+    /// Writes the lock-file with an embedded content hash to a file. See
+    /// [`LockFile::render_with_content_hash`].
+    pub fn to_path_with_content_hash(&self, path: &Path) -> Result<(), std::io::Error> {
+        std::fs::write(path, self.render_with_content_hash())
+    }
+
+    /// Parses a lock-file and verifies that its contents match the embedded content hash.
+    pub fn from_str_verified(source: &str) -> Result<Self, IntegrityError> {
+        let embedded: EmbeddedContentHash =
+            serde_yaml::from_str(source).map_err(ParseCondaLockError::ParseError)?;
+        let expected = embedded
+            .content_hash
+            .ok_or(IntegrityError::MissingContentHash)?;
+        let expected_hash = expected
+            .strip_prefix(CONTENT_HASH_PREFIX)
+            .and_then(rattler_digest::parse_digest_from_hex::<Sha256>)
+            .ok_or_else(|| IntegrityError::InvalidContentHash(expected.clone()))?;
+
+        let lock_file = LockFile::from_str(source)?;
+        let actual = lock_file.content_hash();
+        if actual != expected_hash {
+            return Err(IntegrityError::ContentHashMismatch {
+                expected,
+                actual: format!("{CONTENT_HASH_PREFIX}{actual:x}"),
+            });
+        }
+        Ok(lock_file)
+    }
+
+    /// Reads a lock-file from a file and verifies its embedded content hash. See
+    /// [`LockFile::from_str_verified`].
+    pub fn from_path_verified(path: &Path) -> Result<Self, IntegrityError> {
+        Self::from_str_verified(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reads the lock-file at `path` after verifying that its contents were signed by the
+    /// `trusted_keys`, see [`sign_lock_file`].
+    pub fn from_path_signed(
+        path: &Path,
+        trusted_keys: &TrustedKeys,
+    ) -> Result<Self, IntegrityError> {
+        let contents = std::fs::read(path)?;
+        trusted_keys.verify(&contents, &read_signatures(&signatures_path(path))?)?;
+        let source = String::from_utf8(contents)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        Ok(LockFile::from_str(&source)?)
+    }
+}
+
+/// Signs the contents of the lock-file at `path` with the given keys and writes the signatures to
+/// the [`signatures_path`] of the lock-file. The bytes of the file are signed as they are, so the
+/// lock-file must not be rewritten afterwards. Existing signatures of other keys are retained.
+pub fn sign_lock_file(path: &Path, keys: &[SigningKey]) -> Result<PathBuf, IntegrityError> {
+    let contents = std::fs::read(path)?;
+    let signatures_path = signatures_path(path);
+    let mut signatures = match read_signatures(&signatures_path) {
+        Ok(signatures) => signatures,
+        Err(IntegrityError::SignatureError(SignatureError::MissingSignatures)) => Signatures::new(),
+        Err(e) => return Err(e),
+    };
+    sign_payload(&contents, keys, &mut signatures);
+    write_signatures(&signatures_path, &signatures)?;
+    Ok(signatures_path)
+}
+
+/// Returns the path of the file that contains the detached signatures of the lock-file at the
+/// given path, e.g. `pixi.lock.sig` for `pixi.lock`.
+pub fn signatures_path(lock_file_path: &Path) -> PathBuf {
+    let mut path = lock_file_path.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Reads the detached signatures from a file.
+pub fn read_signatures(path: &Path) -> Result<Signatures, IntegrityError> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(SignatureError::MissingSignatures.into())
+        }
+        Err(e) => return Err(e.into()),
+    };
+    serde_json::from_str(&contents)
+        .map_err(|e| SignatureError::ParseError(path.display().to_string(), e).into())
+}
+
+/// Writes detached signatures to a file.
+pub fn write_signatures(path: &Path, signatures: &Signatures) -> Result<(), std::io::Error> {
+    let contents =
+        serde_json::to_string_pretty(signatures).expect("serializing signatures cannot fail");
+    std::fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn lock_file() -> LockFile {
+        LockFile::from_path(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../../test-data/conda-lock/v4/python-lock.yml"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_embedded_content_hash() {
+        let lock_file = lock_file();
+        let rendered = lock_file.render_with_content_hash();
+        let verified = LockFile::from_str_verified(&rendered).unwrap();
+        assert_eq!(verified.content_hash(), lock_file.content_hash());
+
+        // The lock-file can still be read without verification.
+        assert!(LockFile::from_str(&rendered).is_ok());
+
+        let tampered = rendered.replacen(
+            "d7c89558ba9fa0495403155b64376d81",
+            "00000000000000000000000000000000",
+            1,
+        );
+        assert_matches!(
+            LockFile::from_str_verified(&tampered).err(),
+            Some(IntegrityError::ContentHashMismatch { .. })
+        );
+        assert_matches!(
+            LockFile::from_str_verified(&lock_file.render()).err(),
+            Some(IntegrityError::MissingContentHash)
+        );
+    }
+
+    #[test]
+    fn test_signatures() {
+        let (key, other_key) = (
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pixi.lock");
+        lock_file().to_path(&path).unwrap();
+
+        assert_matches!(
+            LockFile::from_path_signed(&path, &TrustedKeys::new([key.verifying_key()], 1)).err(),
+            Some(IntegrityError::SignatureError(
+                SignatureError::MissingSignatures
+            ))
+        );
+
+        let signatures_path = sign_lock_file(&path, std::slice::from_ref(&key)).unwrap();
+        assert_eq!(signatures_path.file_name().unwrap(), "pixi.lock.sig");
+        sign_lock_file(&path, std::slice::from_ref(&other_key)).unwrap();
+        assert_eq!(read_signatures(&signatures_path).unwrap().len(), 2);
+
+        let lock_file = LockFile::from_path_signed(
+            &path,
+            &TrustedKeys::new([key.verifying_key(), other_key.verifying_key()], 2),
+        )
+        .unwrap();
+        assert_eq!(lock_file.content_hash(), self::lock_file().content_hash());
+
+        // Listing the same key twice does not count its signature twice.
+        assert_matches!(
+            LockFile::from_path_signed(
+                &path,
+                &TrustedKeys::new([key.verifying_key(), key.verifying_key()], 2)
+            )
+            .err(),
+            Some(IntegrityError::SignatureError(
+                SignatureError::ThresholdNotMet { found: 1, .. }
+            ))
+        );
+
+        // The signatures cover the bytes of the file, so even a change that does not modify the
+        // contents of the lock-file invalidates them.
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.push_str("# comment\n");
+        std::fs::write(&path, contents).unwrap();
+        assert_matches!(
+            LockFile::from_path_signed(&path, &TrustedKeys::new([key.verifying_key()], 1)).err(),
+            Some(IntegrityError::SignatureError(
+                SignatureError::ThresholdNotMet { found: 0, .. }
+            ))
+        );
+    }
+}
@@ -42,7 +42,8 @@
 //! but over time significant changes have been made compared to the original conda-lock format.
 //! Conda-lock files (e.g. `conda-lock.yml` files) can still be parsed by this crate but the
 //! serialization format changed significantly. This means files created by this crate are not
-//! compatible with conda-lock.
+//! compatible with conda-lock. To read and write files in the format of conda-lock use
+//! [`CondaLockFile`].
 //!
 //! Conda-lock stores a lot of metadata to be able to verify if the lock-file is still valid given
 //! the sources/inputs. For example conda-lock contains a `content-hash` which is a hash of all the
@@ -78,22 +79,41 @@ use url::Url;
 mod builder;
 mod channel;
 mod conda;
+mod conda_lock;
+mod export;
 mod file_format_version;
 mod hash;
+mod integrity;
+mod merge;
 mod parse;
 mod pypi;
 mod pypi_indexes;
+mod satisfiability;
+mod selective_update;
+mod solved_environment;
 mod url_or_path;
 mod utils;
 
 pub use builder::LockFileBuilder;
 pub use channel::Channel;
 pub use conda::{CondaPackageData, ConversionError};
+pub use conda_lock::{
+    CondaLockFile, CondaLockHash, CondaLockManager, CondaLockMetadata, CondaLockPackage,
+    DEFAULT_CATEGORY,
+};
 pub use file_format_version::FileFormatVersion;
 pub use hash::PackageHashes;
+pub use integrity::{
+    read_signatures, sign_lock_file, signatures_path, write_signatures, IntegrityError, Signature,
+    Signatures, SigningKey, TrustedKeys, VerifyingKey,
+};
+pub use merge::{LockFileMerge, MergeConflict};
 pub use parse::ParseCondaLockError;
-pub use pypi::{PypiPackageData, PypiPackageEnvironmentData, PypiSourceTreeHashable};
+pub use pypi::{PypiArtifact, PypiPackageData, PypiPackageEnvironmentData, PypiSourceTreeHashable};
 pub use pypi_indexes::{FindLinksUrlOrPath, PypiIndexes};
+pub use satisfiability::{SatisfiabilityCheck, SatisfiabilityMismatch};
+pub use selective_update::{KeepLocked, SelectiveUpdate, UpdateConstraints};
+pub use solved_environment::SolvedEnvironment;
 pub use url_or_path::UrlOrPath;
 
 /// The name of the default environment in a [`LockFile`]. This is the environment name that is used
@@ -592,6 +612,18 @@ impl PypiPackage {
     pub fn is_editable(&self) -> bool {
         self.package_data().editable
     }
+
+    /// Returns the kind of artifact this package refers to.
+    pub fn artifact(&self) -> PypiArtifact<'_> {
+        self.package_data().artifact()
+    }
+
+    /// Returns a requirement, including the extras of this environment, that can be passed to a
+    /// Python package installer to install exactly this package. See
+    /// [`PypiPackageData::pip_requirement`].
+    pub fn pip_requirement(&self) -> String {
+        self.package_data().pip_requirement(self.extras())
+    }
 }
 
 /// A helper struct to group package and environment data together.
@@ -606,7 +638,7 @@ pub struct PypiPackageDataRef<'p> {
 
 #[cfg(test)]
 mod test {
-    use super::{LockFile, DEFAULT_ENVIRONMENT_NAME};
+    use super::{LockFile, PypiArtifact, DEFAULT_ENVIRONMENT_NAME};
     use rattler_conda_types::Platform;
     use rstest::*;
     use std::path::Path;
@@ -665,4 +697,41 @@ mod test {
             .map(|p| p.url_or_path().into_owned())
             .collect::<Vec<_>>());
     }
+
+    #[rstest]
+    #[case("v4/pypi-matplotlib-lock.yml")]
+    #[case("v4/path-based-lock.yml")]
+    #[case("v5/flat-index-lock.yml")]
+    fn test_pip_requirements(#[case] file_name: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock")
+            .join(file_name);
+        let conda_lock = LockFile::from_path(&path).unwrap();
+        let mut pypi_packages = conda_lock
+            .default_environment()
+            .unwrap()
+            .pypi_packages()
+            .into_iter()
+            .collect::<Vec<_>>();
+        pypi_packages.sort_by_key(|(platform, _)| *platform);
+        let requirements = pypi_packages
+            .into_iter()
+            .flat_map(|(platform, packages)| {
+                packages.into_iter().map(move |(package, environment)| {
+                    let kind = match package.artifact() {
+                        PypiArtifact::Wheel(_) => "wheel",
+                        PypiArtifact::SourceDistribution(_) => "sdist",
+                        PypiArtifact::SourceTree { .. } => "source tree",
+                        PypiArtifact::Vcs(_) => "vcs",
+                        PypiArtifact::DirectUrl(_) => "direct url",
+                    };
+                    format!(
+                        "{platform} {kind}: {}",
+                        package.pip_requirement(&environment.extras)
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        insta::assert_yaml_snapshot!(format!("pip-requirements-{file_name}"), requirements);
+    }
 }
@@ -0,0 +1,335 @@
+//! Semantic three-way merging of lock-files, e.g. to resolve git merge conflicts without locking
+//! the environments again. See [`LockFile::merge`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use pep508_rs::ExtraName;
+use rattler_conda_types::Platform;
+
+use crate::{
+    Channel, CondaPackageData, LockFile, LockFileBuilder, Package, PypiIndexes, PypiPackageData,
+    PypiPackageEnvironmentData,
+};
+
+/// A change that was made on both sides of a merge and that could not be reconciled. The lock-file
+/// of a merge contains "our" side of every conflict.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum MergeConflict {
+    /// Both sides changed the channels of an environment.
+    #[error("the channels of environment '{environment}' were changed on both sides")]
+    Channels {
+        /// The name of the environment.
+        environment: String,
+    },
+
+    /// Both sides changed the pypi indexes of an environment.
+    #[error("the pypi indexes of environment '{environment}' were changed on both sides")]
+    PypiIndexes {
+        /// The name of the environment.
+        environment: String,
+    },
+
+    /// Both sides changed the same locked package.
+    #[error("{name} for {platform} in environment '{environment}' was changed on both sides (ours: {}, theirs: {})", ours.as_deref().unwrap_or("removed"), theirs.as_deref().unwrap_or("removed"))]
+    Package {
+        /// The name of the environment.
+        environment: String,
+        /// The platform of the package.
+        platform: Platform,
+        /// The name of the package.
+        name: String,
+        /// The version of the package on our side or `None` if we removed it.
+        ours: Option<String>,
+        /// The version of the package on their side or `None` if they removed it.
+        theirs: Option<String>,
+    },
+}
+
+/// The result of [`LockFile::merge`].
+#[derive(Clone)]
+pub struct LockFileMerge {
+    /// The merged lock-file.
+    pub lock_file: LockFile,
+
+    /// The changes that were made on both sides. For each conflict the lock-file contains our
+    /// side.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl LockFileMerge {
+    /// Returns true if the merge did not result in any conflicts.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Identifies a locked package within an environment.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum PackageKey {
+    Conda(String),
+    Pypi(String),
+}
+
+/// The data of a locked package that is compared during a merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LockedPackage {
+    Conda(Box<CondaPackageData>),
+    Pypi(Box<PypiPackageData>, BTreeSet<ExtraName>),
+}
+
+impl LockedPackage {
+    fn version(&self) -> String {
+        match self {
+            LockedPackage::Conda(data) => data.package_record.version.to_string(),
+            LockedPackage::Pypi(data, _) => data.version.to_string(),
+        }
+    }
+}
+
+/// The contents of a single environment of a lock-file.
+#[derive(Default)]
+struct EnvironmentContents {
+    channels: Option<Vec<Channel>>,
+    indexes: Option<PypiIndexes>,
+    packages: BTreeMap<(Platform, PackageKey), LockedPackage>,
+}
+
+impl EnvironmentContents {
+    fn from_lock_file(lock_file: &LockFile) -> BTreeMap<String, Self> {
+        lock_file
+            .environments()
+            .map(|(name, environment)| {
+                let packages = environment
+                    .packages_by_platform()
+                    .flat_map(|(platform, packages)| {
+                        packages.map(move |package| match package {
+                            Package::Conda(conda) => {
+                                let data = Box::new(conda.package_data().clone());
+                                let key = PackageKey::Conda(
+                                    data.package_record.name.as_normalized().to_string(),
+                                );
+                                ((platform, key), LockedPackage::Conda(data))
+                            }
+                            Package::Pypi(pypi) => {
+                                let data = Box::new(pypi.package_data().clone());
+                                let key = PackageKey::Pypi(data.name.to_string());
+                                let extras = pypi.environment_data().extras.clone();
+                                ((platform, key), LockedPackage::Pypi(data, extras))
+                            }
+                        })
+                    })
+                    .collect();
+                (
+                    name.to_string(),
+                    Self {
+                        channels: Some(environment.channels().to_vec()),
+                        indexes: environment.pypi_indexes().cloned(),
+                        packages,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+/// Merges a value that was changed on either or both sides. Returns `None` if both sides changed
+/// the value differently.
+fn merge_value<T: PartialEq + Clone>(ancestor: &T, ours: &T, theirs: &T) -> Option<T> {
+    if ours == theirs || theirs == ancestor {
+        Some(ours.clone())
+    } else if ours == ancestor {
+        Some(theirs.clone())
+    } else {
+        None
+    }
+}
+
+impl LockFile {
+    /// Merges two lock-files that were both derived from a common ancestor.
+    ///
+    /// Every environment is merged on the level of channels, pypi indexes and individual packages
+    /// per platform. A change that was only made on one side is taken over, as is a change that
+    /// was made identically on both sides. If both sides made a different change, for instance
+    /// when both sides updated the same package to a different version, our side is kept and a
+    /// [`MergeConflict`] is reported. A clean merge does not guarantee that the merged
+    /// environments are consistent, so validating them with a [`crate::SatisfiabilityCheck`] is
+    /// recommended.
+    pub fn merge(ancestor: &LockFile, ours: &LockFile, theirs: &LockFile) -> LockFileMerge {
+        let ancestor = EnvironmentContents::from_lock_file(ancestor);
+        let ours = EnvironmentContents::from_lock_file(ours);
+        let theirs = EnvironmentContents::from_lock_file(theirs);
+
+        let missing = EnvironmentContents::default();
+        let environments = ancestor
+            .keys()
+            .chain(ours.keys())
+            .chain(theirs.keys())
+            .collect::<BTreeSet<_>>();
+
+        let mut builder = LockFileBuilder::new();
+        let mut conflicts = Vec::new();
+        for name in environments {
+            let ancestor = ancestor.get(name).unwrap_or(&missing);
+            let ours = ours.get(name).unwrap_or(&missing);
+            let theirs = theirs.get(name).unwrap_or(&missing);
+
+            let channels = merge_value(&ancestor.channels, &ours.channels, &theirs.channels)
+                .unwrap_or_else(|| {
+                    conflicts.push(MergeConflict::Channels {
+                        environment: name.clone(),
+                    });
+                    ours.channels.clone()
+                });
+            if let Some(channels) = channels {
+                builder.set_channels(name.clone(), channels);
+            }
+
+            let indexes = merge_value(&ancestor.indexes, &ours.indexes, &theirs.indexes)
+                .unwrap_or_else(|| {
+                    conflicts.push(MergeConflict::PypiIndexes {
+                        environment: name.clone(),
+                    });
+                    ours.indexes.clone()
+                });
+            if let Some(indexes) = indexes {
+                builder.set_pypi_indexes(name.clone(), indexes);
+            }
+
+            let keys = ancestor
+                .packages
+                .keys()
+                .chain(ours.packages.keys())
+                .chain(theirs.packages.keys())
+                .collect::<BTreeSet<_>>();
+            for key in keys {
+                let (ancestor_package, our_package, their_package) = (
+                    ancestor.packages.get(key),
+                    ours.packages.get(key),
+                    theirs.packages.get(key),
+                );
+                let package = merge_value(&ancestor_package, &our_package, &their_package)
+                    .unwrap_or_else(|| {
+                        let (platform, PackageKey::Conda(package) | PackageKey::Pypi(package)) =
+                            key;
+                        conflicts.push(MergeConflict::Package {
+                            environment: name.clone(),
+                            platform: *platform,
+                            name: package.clone(),
+                            ours: our_package.map(LockedPackage::version),
+                            theirs: their_package.map(LockedPackage::version),
+                        });
+                        our_package
+                    });
+
+                let platform = key.0;
+                match package {
+                    Some(LockedPackage::Conda(data)) => {
+                        builder.add_conda_package(name.clone(), platform, data.as_ref().clone());
+                    }
+                    Some(LockedPackage::Pypi(data, extras)) => {
+                        builder.add_pypi_package(
+                            name.clone(),
+                            platform,
+                            data.as_ref().clone(),
+                            PypiPackageEnvironmentData {
+                                extras: extras.clone(),
+                            },
+                        );
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        LockFileMerge {
+            lock_file: builder.finish(),
+            conflicts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEFAULT_ENVIRONMENT_NAME;
+    use rattler_conda_types::{PackageRecord, RepoDataRecord, Version};
+    use std::str::FromStr;
+
+    fn package(name: &str, version: &str) -> CondaPackageData {
+        let file_name = format!("{name}-{version}-0.conda");
+        RepoDataRecord {
+            package_record: PackageRecord::new(
+                name.parse().unwrap(),
+                Version::from_str(version).unwrap(),
+                "0".to_string(),
+            ),
+            url: format!("https://conda.anaconda.org/conda-forge/linux-64/{file_name}")
+                .parse()
+                .unwrap(),
+            file_name,
+            channel: String::from("https://conda.anaconda.org/conda-forge/"),
+        }
+        .into()
+    }
+
+    fn lock_file(channel: &str, packages: &[(&str, &str)]) -> LockFile {
+        let mut builder = LockFileBuilder::new();
+        builder.set_channels(DEFAULT_ENVIRONMENT_NAME, [channel]);
+        for (name, version) in packages {
+            builder.add_conda_package(
+                DEFAULT_ENVIRONMENT_NAME,
+                Platform::Linux64,
+                package(name, version),
+            );
+        }
+        builder.finish()
+    }
+
+    fn versions(lock_file: &LockFile) -> Vec<String> {
+        let mut versions = lock_file
+            .default_environment()
+            .unwrap()
+            .packages(Platform::Linux64)
+            .unwrap()
+            .map(|package| format!("{}={}", package.name(), package.version()))
+            .collect::<Vec<_>>();
+        versions.sort();
+        versions
+    }
+
+    #[test]
+    fn test_clean_merge() {
+        let ancestor = lock_file(
+            "conda-forge",
+            &[("a", "1"), ("b", "1"), ("c", "1"), ("d", "1")],
+        );
+        let ours = lock_file(
+            "conda-forge",
+            &[("a", "2"), ("b", "1"), ("c", "1"), ("d", "2"), ("e", "1")],
+        );
+        let theirs = lock_file("bioconda", &[("a", "1"), ("b", "3"), ("d", "2")]);
+
+        let merge = LockFile::merge(&ancestor, &ours, &theirs);
+        assert!(merge.is_clean());
+        assert_eq!(versions(&merge.lock_file), ["a=2", "b=3", "d=2", "e=1"]);
+        assert_eq!(
+            merge.lock_file.default_environment().unwrap().channels()[0].url,
+            "bioconda"
+        );
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let ancestor = lock_file("conda-forge", &[("a", "1"), ("b", "1")]);
+        let ours = lock_file("bioconda", &[("a", "2")]);
+        let theirs = lock_file("robostack", &[("a", "3"), ("b", "2")]);
+
+        let merge = LockFile::merge(&ancestor, &ours, &theirs);
+        assert_eq!(versions(&merge.lock_file), ["a=2"]);
+        insta::assert_yaml_snapshot!(merge
+            .conflicts
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>());
+    }
+}
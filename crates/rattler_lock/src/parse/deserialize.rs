@@ -10,7 +10,6 @@ use itertools::{Either, Itertools};
 use pep508_rs::ExtraName;
 use rattler_conda_types::Platform;
 use serde::Deserialize;
-use serde_yaml::Value;
 use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 use url::Url;
@@ -63,13 +62,13 @@ impl From<DeserializablePypiPackageEnvironmentData> for PypiPackageEnvironmentDa
     }
 }
 
-/// Parses a [`LockFile`] from a [`serde_yaml::Value`].
-pub fn parse_from_document(
-    document: Value,
+/// Parses a [`LockFile`] directly from the contents of a lock-file.
+pub fn parse_from_str(
+    source: &str,
     version: FileFormatVersion,
 ) -> Result<LockFile, ParseCondaLockError> {
     let raw: DeserializableLockFile<'_> =
-        serde_yaml::from_value(document).map_err(ParseCondaLockError::ParseError)?;
+        serde_yaml::from_str(source).map_err(ParseCondaLockError::ParseError)?;
 
     // Split the packages into conda and pypi packages.
     let (conda_packages, pypi_packages): (Vec<_>, Vec<_>) =
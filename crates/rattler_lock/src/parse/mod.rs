@@ -5,7 +5,7 @@ mod v3;
 use super::{LockFile, UrlOrPath};
 use crate::file_format_version::FileFormatVersion;
 use rattler_conda_types::Platform;
-use serde::de::Error;
+use serde::{de::Error, Deserialize};
 use serde_yaml::Value;
 use std::str::FromStr;
 use v3::parse_v3_or_lower;
@@ -25,6 +25,9 @@ pub enum ParseCondaLockError {
         max_supported_version: FileFormatVersion,
     },
 
+    #[error("unsupported conda-lock file version {0}, only version 1 is supported")]
+    UnsupportedCondaLockVersion(u64),
+
     #[error("environment {0} and platform {1} refers to a package that does not exist: {2}")]
     MissingPackage(String, Platform, UrlOrPath),
 
@@ -32,16 +35,21 @@ pub enum ParseCondaLockError {
     InvalidPypiPackageName(#[from] pep508_rs::InvalidNameError),
 }
 
+/// Only the version of a lock-file, used to determine how to parse the rest of the document.
+#[derive(Deserialize)]
+struct DocumentVersion {
+    version: Option<Value>,
+}
+
 impl FromStr for LockFile {
     type Err = ParseCondaLockError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // First parse the document to a `serde_yaml::Value`.
-        let document: Value = serde_yaml::from_str(s).map_err(ParseCondaLockError::ParseError)?;
-
-        // Read the version number from the document
-        let version: FileFormatVersion = document
-            .get("version")
+        // First only read the version number from the document. The other fields are skipped
+        // without being materialized.
+        let version: FileFormatVersion = serde_yaml::from_str::<DocumentVersion>(s)
+            .map_err(ParseCondaLockError::ParseError)?
+            .version
             .ok_or_else(|| {
                 ParseCondaLockError::ParseError(serde_yaml::Error::custom(
                     "missing `version` field in lock file",
@@ -58,9 +66,13 @@ impl FromStr for LockFile {
             })?;
 
         if version <= FileFormatVersion::V3 {
+            let document: Value =
+                serde_yaml::from_str(s).map_err(ParseCondaLockError::ParseError)?;
             parse_v3_or_lower(document, version)
         } else {
-            deserialize::parse_from_document(document, version)
+            // Newer documents are deserialized directly into their final representation without
+            // building an intermediate document first.
+            deserialize::parse_from_str(s, version)
         }
     }
 }
@@ -81,4 +93,69 @@ mod test {
 
         insta::assert_snapshot!(format!("{}", err), @"found newer lockfile format version 1000, but only up to including version 5 is supported.");
     }
+
+    #[test]
+    fn test_missing_version() {
+        let err = LockFile::from_str("environments: {}\npackages: []\n")
+            .err()
+            .unwrap();
+        insta::assert_snapshot!(format!("{}", err), @"missing `version` field in lock file");
+    }
+
+    /// Serializing a lock-file must not depend on the order in which packages were added, and
+    /// packages shared by environments must only be written once.
+    #[test]
+    fn test_canonical_serialization() {
+        let lock_file = LockFile::from_path(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../../test-data/conda-lock/v4/python-lock.yml"),
+        )
+        .unwrap();
+        let environment = lock_file.default_environment().unwrap();
+        let mut records = environment
+            .conda_repodata_records_for_platform(Platform::Linux64)
+            .unwrap()
+            .unwrap();
+
+        // The same package from another channel.
+        let mut mirrored = records[0].clone();
+        mirrored.url = mirrored
+            .url
+            .as_str()
+            .replace("conda.anaconda.org", "mirror.example.com")
+            .parse()
+            .unwrap();
+        records.push(mirrored);
+
+        let build = |records: Vec<rattler_conda_types::RepoDataRecord>| {
+            let mut builder = LockFile::builder();
+            for environment in ["default", "test"] {
+                builder.set_channels(environment, environment_channels(&lock_file));
+                for record in &records {
+                    builder.add_conda_package(
+                        environment,
+                        Platform::Linux64,
+                        record.clone().into(),
+                    );
+                }
+            }
+            serde_yaml::to_string(&builder.finish()).unwrap()
+        };
+
+        let forward = build(records.clone());
+        records.reverse();
+        let backward = build(records.clone());
+        assert_eq!(forward, backward);
+
+        // Every package is written once, although both environments refer to it.
+        assert_eq!(forward.matches("- kind: conda").count(), records.len());
+
+        // Parsing and serializing again produces the exact same document.
+        let reparsed = LockFile::from_str(&forward).unwrap();
+        assert_eq!(serde_yaml::to_string(&reparsed).unwrap(), forward);
+    }
+
+    fn environment_channels(lock_file: &LockFile) -> Vec<crate::Channel> {
+        lock_file.default_environment().unwrap().channels().to_vec()
+    }
 }
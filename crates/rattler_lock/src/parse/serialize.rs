@@ -87,16 +87,21 @@ impl<'a> Ord for SerializablePackageSelector<'a> {
             (
                 SerializablePackageSelector::Pypi { pypi: a, .. },
                 SerializablePackageSelector::Pypi { pypi: b, .. },
-            ) => match (a, b) {
-                (UrlOrPath::Url(a), UrlOrPath::Url(b)) => compare_url_by_filename(a, b),
-                (UrlOrPath::Url(_), UrlOrPath::Path(_)) => Ordering::Less,
-                (UrlOrPath::Path(_), UrlOrPath::Url(_)) => Ordering::Greater,
-                (UrlOrPath::Path(a), UrlOrPath::Path(b)) => a.cmp(b),
-            },
+            ) => compare_url_or_path(a, b),
         }
     }
 }
 
+/// Sorts urls before paths, urls by their filename and paths by their components.
+fn compare_url_or_path(a: &UrlOrPath, b: &UrlOrPath) -> Ordering {
+    match (a, b) {
+        (UrlOrPath::Url(a), UrlOrPath::Url(b)) => compare_url_by_filename(a, b),
+        (UrlOrPath::Url(_), UrlOrPath::Path(_)) => Ordering::Less,
+        (UrlOrPath::Path(_), UrlOrPath::Url(_)) => Ordering::Greater,
+        (UrlOrPath::Path(a), UrlOrPath::Path(b)) => a.cmp(b),
+    }
+}
+
 /// First sort packages just by their filename. Since most of the time the urls end
 /// in the packages filename this causes the urls to be sorted by package name.
 fn compare_url_by_filename(a: &Url, b: &Url) -> Ordering {
@@ -154,6 +159,9 @@ impl Ord for SerializablePackageData<'_> {
                 (Pypi(_), _) => Ordering::Less,
                 (_, Pypi(_)) => Ordering::Greater,
             })
+            // Packages that are otherwise identical but come from a different location are
+            // sorted by their location so the order never depends on the order of insertion.
+            .then_with(|| compare_url_or_path(&self.url(), &other.url()))
     }
 }
 
@@ -244,6 +252,9 @@ impl Serialize for LockFile {
         // information.
         packages.sort();
 
+        // Emit every package only once, even if it was added multiple times.
+        packages.dedup();
+
         let raw = SerializableLockFile {
             version: FileFormatVersion::LATEST,
             environments,
@@ -1,4 +1,5 @@
 use crate::{PackageHashes, UrlOrPath};
+use itertools::Itertools;
 use pep440_rs::VersionSpecifiers;
 use pep508_rs::{ExtraName, PackageName, Requirement};
 use rattler_digest::{digest::Digest, Sha256};
@@ -8,6 +9,7 @@ use std::cmp::Ordering;
 use std::collections::BTreeSet;
 use std::fs;
 use std::path::Path;
+use url::Url;
 
 /// A pinned Pypi package
 #[serde_as]
@@ -85,6 +87,123 @@ impl PypiPackageData {
     }
 }
 
+/// The kind of artifact that a locked pypi package refers to. Returned by
+/// [`PypiPackageData::artifact`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PypiArtifact<'a> {
+    /// A built distribution (a `.whl` file) at a url or path.
+    Wheel(&'a UrlOrPath),
+
+    /// A source distribution archive, e.g. a `.tar.gz` or `.zip` file, at a url or path.
+    SourceDistribution(&'a UrlOrPath),
+
+    /// A local directory that contains the sources of a project.
+    SourceTree {
+        /// The path of the directory, relative paths are relative to the lock-file.
+        path: &'a Path,
+
+        /// Whether the project should be installed in editable mode.
+        editable: bool,
+    },
+
+    /// A version control url, e.g. `git+https://github.com/owner/repo@rev`. Urls with a
+    /// `direct+` scheme prefix are direct downloads and are classified by their file name instead.
+    Vcs(&'a Url),
+
+    /// A url that does not point to a file that can be recognized as a distribution.
+    DirectUrl(&'a Url),
+}
+
+/// The scheme prefix that marks a url as a direct download, e.g. `direct+https://`.
+const DIRECT_URL_PREFIX: &str = "direct+";
+
+/// The file extensions of source distributions.
+const SOURCE_DISTRIBUTION_EXTENSIONS: [&str; 6] =
+    [".tar.gz", ".tgz", ".tar.bz2", ".tar.xz", ".tar", ".zip"];
+
+impl PypiPackageData {
+    /// Returns the kind of artifact this package refers to.
+    pub fn artifact(&self) -> PypiArtifact<'_> {
+        let file_name = match &self.url_or_path {
+            UrlOrPath::Url(url) => {
+                if url.scheme().contains('+') && !url.scheme().starts_with(DIRECT_URL_PREFIX) {
+                    return PypiArtifact::Vcs(url);
+                }
+                url.path_segments()
+                    .and_then(Iterator::last)
+                    .unwrap_or_default()
+                    .to_string()
+            }
+            UrlOrPath::Path(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+
+        let file_name = file_name.to_lowercase();
+        if file_name.ends_with(".whl") {
+            PypiArtifact::Wheel(&self.url_or_path)
+        } else if SOURCE_DISTRIBUTION_EXTENSIONS
+            .iter()
+            .any(|extension| file_name.ends_with(extension))
+        {
+            PypiArtifact::SourceDistribution(&self.url_or_path)
+        } else {
+            match &self.url_or_path {
+                UrlOrPath::Url(url) => PypiArtifact::DirectUrl(url),
+                UrlOrPath::Path(path) => PypiArtifact::SourceTree {
+                    path,
+                    editable: self.editable,
+                },
+            }
+        }
+    }
+
+    /// Returns a requirement that can be passed to a Python package installer like pip or uv to
+    /// install exactly this package with the given extras.
+    ///
+    /// Urls of wheels and source distributions include the SHA256 hash of the file as a
+    /// `#sha256=` fragment so the installer verifies the download. Local paths are returned as is,
+    /// relative paths are relative to the directory of the lock-file. Editable source trees are
+    /// prefixed with `-e`.
+    pub fn pip_requirement(&self, extras: &BTreeSet<ExtraName>) -> String {
+        let extras = if extras.is_empty() {
+            String::new()
+        } else {
+            format!("[{}]", extras.iter().map(ExtraName::as_ref).join(","))
+        };
+
+        match (self.artifact(), &self.url_or_path) {
+            (PypiArtifact::SourceTree { path, editable }, _) => {
+                let editable = if editable { "-e " } else { "" };
+                format!("{editable}{}{extras}", path.display())
+            }
+            (_, UrlOrPath::Path(path)) => format!("{}{extras}", path.display()),
+            (PypiArtifact::Wheel(_) | PypiArtifact::SourceDistribution(_), UrlOrPath::Url(url)) => {
+                let mut url = strip_direct_url_prefix(url);
+                if let Some(sha256) = self.hash.as_ref().and_then(PackageHashes::sha256) {
+                    if url.fragment().is_none() {
+                        url.set_fragment(Some(&format!("sha256={sha256:x}")));
+                    }
+                }
+                format!("{}{extras} @ {url}", self.name)
+            }
+            (PypiArtifact::DirectUrl(url), _) => {
+                format!("{}{extras} @ {}", self.name, strip_direct_url_prefix(url))
+            }
+            (_, UrlOrPath::Url(url)) => format!("{}{extras} @ {url}", self.name),
+        }
+    }
+}
+
+/// Removes the `direct+` prefix from the scheme of a url because installers don't understand it.
+fn strip_direct_url_prefix(url: &Url) -> Url {
+    url.as_str()
+        .strip_prefix(DIRECT_URL_PREFIX)
+        .and_then(|url| Url::parse(url).ok())
+        .unwrap_or_else(|| url.clone())
+}
+
 /// Used in `skip_serializing_if` to skip serializing the `editable` field if it is `false`.
 fn should_skip_serializing_editable(editable: &bool) -> bool {
     !*editable
@@ -0,0 +1,292 @@
+//! Checks whether an environment in a lock-file still satisfies its inputs. See
+//! [`SatisfiabilityCheck`].
+
+use rattler_conda_types::{MatchSpec, PackageName, ParseStrictness, Platform};
+
+use crate::{CondaPackage, Environment};
+
+/// A reason why a locked environment does not satisfy its inputs.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SatisfiabilityMismatch {
+    /// The channels of the environment differ from the requested channels.
+    #[error("the locked channels [{}] differ from the requested channels [{}]", locked.join(", "), requested.join(", "))]
+    ChannelsMismatch {
+        /// The urls of the channels in the lock-file.
+        locked: Vec<String>,
+        /// The urls of the requested channels.
+        requested: Vec<String>,
+    },
+
+    /// The environment is not locked for a requested platform.
+    #[error("the environment is not locked for {0}")]
+    MissingPlatform(Platform),
+
+    /// The environment is locked for a platform that was not requested.
+    #[error("the environment is locked for {0} which was not requested")]
+    ExtraPlatform(Platform),
+
+    /// No locked package matches a requested spec.
+    #[error("no locked package for {platform} matches '{spec}'")]
+    MissingPackage {
+        /// The platform for which the package is missing.
+        platform: Platform,
+        /// The requested spec.
+        spec: String,
+    },
+
+    /// A locked package with the name of a requested spec exists but it does not match the spec.
+    #[error("the locked package {locked} for {platform} does not match '{spec}'")]
+    SpecMismatch {
+        /// The platform of the locked package.
+        platform: Platform,
+        /// The requested spec.
+        spec: String,
+        /// The name, version and build of the locked package.
+        locked: String,
+    },
+
+    /// A dependency of a locked package is not satisfied by any other locked package.
+    #[error("the dependency '{dependency}' of {package} for {platform} is not locked")]
+    MissingDependency {
+        /// The platform of the locked package.
+        platform: Platform,
+        /// The name of the package with the dependency.
+        package: String,
+        /// The dependency that is not satisfied.
+        dependency: String,
+    },
+}
+
+/// Validates whether a locked [`Environment`] still satisfies the specs, channels and platforms it
+/// was locked for. If it does not, the environment has to be locked again.
+///
+/// Only conda packages are validated. Dependencies on virtual packages, whose names start with
+/// `__`, are not part of a lock-file and are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct SatisfiabilityCheck {
+    specs: Vec<MatchSpec>,
+    channels: Option<Vec<String>>,
+    platforms: Option<Vec<Platform>>,
+}
+
+impl SatisfiabilityCheck {
+    /// Constructs a check without any requirements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the specs that the locked packages of every platform must satisfy.
+    #[must_use]
+    pub fn with_specs(self, specs: impl IntoIterator<Item = MatchSpec>) -> Self {
+        Self {
+            specs: specs.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Sets the urls of the channels, in order of priority, that the environment must be locked
+    /// from. If not set the channels are not validated.
+    #[must_use]
+    pub fn with_channels(self, channels: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            channels: Some(channels.into_iter().map(Into::into).collect()),
+            ..self
+        }
+    }
+
+    /// Sets the platforms the environment must be locked for. If not set the platforms are not
+    /// validated and the specs are checked for all locked platforms.
+    #[must_use]
+    pub fn with_platforms(self, platforms: impl IntoIterator<Item = Platform>) -> Self {
+        Self {
+            platforms: Some(platforms.into_iter().collect()),
+            ..self
+        }
+    }
+
+    /// Returns true if the environment satisfies all requirements.
+    pub fn is_satisfied(&self, environment: &Environment) -> bool {
+        self.validate(environment).is_empty()
+    }
+
+    /// Validates the environment and returns all the reasons why it does not satisfy the
+    /// requirements. An empty list means the environment is up-to-date.
+    pub fn validate(&self, environment: &Environment) -> Vec<SatisfiabilityMismatch> {
+        let mut mismatches = Vec::new();
+
+        if let Some(requested) = &self.channels {
+            let locked = environment
+                .channels()
+                .iter()
+                .map(|channel| channel.url.clone())
+                .collect::<Vec<_>>();
+            let normalize = |url: &String| url.trim_end_matches('/').to_string();
+            if !locked
+                .iter()
+                .map(normalize)
+                .eq(requested.iter().map(normalize))
+            {
+                mismatches.push(SatisfiabilityMismatch::ChannelsMismatch {
+                    locked,
+                    requested: requested.clone(),
+                });
+            }
+        }
+
+        let mut locked_platforms = environment.platforms().collect::<Vec<_>>();
+        locked_platforms.sort();
+        let platforms = match &self.platforms {
+            Some(requested) => {
+                for platform in requested {
+                    if !locked_platforms.contains(platform) {
+                        mismatches.push(SatisfiabilityMismatch::MissingPlatform(*platform));
+                    }
+                }
+                for platform in &locked_platforms {
+                    if !requested.contains(platform) {
+                        mismatches.push(SatisfiabilityMismatch::ExtraPlatform(*platform));
+                    }
+                }
+                requested
+                    .iter()
+                    .copied()
+                    .filter(|platform| locked_platforms.contains(platform))
+                    .collect()
+            }
+            None => locked_platforms,
+        };
+
+        for platform in platforms {
+            let packages = environment
+                .packages(platform)
+                .into_iter()
+                .flatten()
+                .filter_map(crate::Package::into_conda)
+                .collect::<Vec<_>>();
+            self.validate_specs(platform, &packages, &mut mismatches);
+            validate_dependencies(platform, &packages, &mut mismatches);
+        }
+
+        mismatches
+    }
+
+    fn validate_specs(
+        &self,
+        platform: Platform,
+        packages: &[CondaPackage],
+        mismatches: &mut Vec<SatisfiabilityMismatch>,
+    ) {
+        for spec in &self.specs {
+            if packages.iter().any(|package| package.satisfies(spec)) {
+                continue;
+            }
+
+            let locked = spec.name.as_ref().and_then(|name| {
+                packages
+                    .iter()
+                    .find(|package| &package.package_record().name == name)
+            });
+            mismatches.push(match locked {
+                Some(locked) => {
+                    let record = locked.package_record();
+                    SatisfiabilityMismatch::SpecMismatch {
+                        platform,
+                        spec: spec.to_string(),
+                        locked: format!(
+                            "{}={}={}",
+                            record.name.as_normalized(),
+                            record.version,
+                            record.build
+                        ),
+                    }
+                }
+                None => SatisfiabilityMismatch::MissingPackage {
+                    platform,
+                    spec: spec.to_string(),
+                },
+            });
+        }
+    }
+}
+
+/// Checks that the dependencies of every package are satisfied by the other packages.
+fn validate_dependencies(
+    platform: Platform,
+    packages: &[CondaPackage],
+    mismatches: &mut Vec<SatisfiabilityMismatch>,
+) {
+    for package in packages {
+        for dependency in &package.package_record().depends {
+            // A dependency that cannot be parsed can never be satisfied.
+            let satisfied = match MatchSpec::from_str(dependency, ParseStrictness::Lenient) {
+                Ok(spec) if spec.name.as_ref().is_some_and(is_virtual_package) => true,
+                Ok(spec) => packages
+                    .iter()
+                    .any(|package| spec.matches(package.package_record())),
+                Err(_) => false,
+            };
+            if !satisfied {
+                mismatches.push(SatisfiabilityMismatch::MissingDependency {
+                    platform,
+                    package: package.package_record().name.as_normalized().to_string(),
+                    dependency: dependency.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn is_virtual_package(name: &PackageName) -> bool {
+    name.as_normalized().starts_with("__")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LockFile;
+    use std::path::Path;
+
+    fn lock_file() -> LockFile {
+        LockFile::from_path(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../../test-data/conda-lock/v4/turtlesim-lock.yml"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_satisfied() {
+        let lock_file = lock_file();
+        let environment = lock_file.default_environment().unwrap();
+        let platforms = environment.platforms().collect::<Vec<_>>();
+        let check = SatisfiabilityCheck::new()
+            .with_specs([MatchSpec::from_str(
+                "ros-humble-turtlesim 1.4.*",
+                ParseStrictness::Lenient,
+            )
+            .unwrap()])
+            .with_channels(environment.channels().iter().map(|c| c.url.clone()))
+            .with_platforms(platforms);
+        assert_eq!(check.validate(&environment), []);
+        assert!(check.is_satisfied(&environment));
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let lock_file = lock_file();
+        let environment = lock_file.default_environment().unwrap();
+        let check = SatisfiabilityCheck::new()
+            .with_specs([
+                MatchSpec::from_str("ros-humble-turtlesim >=2", ParseStrictness::Lenient).unwrap(),
+                MatchSpec::from_str("pytorch", ParseStrictness::Lenient).unwrap(),
+            ])
+            .with_channels(["https://conda.anaconda.org/conda-forge"])
+            .with_platforms([Platform::Linux64, Platform::LinuxAarch64]);
+        let mismatches = check
+            .validate(&environment)
+            .into_iter()
+            .map(|mismatch| mismatch.to_string())
+            .collect::<Vec<_>>();
+        insta::assert_yaml_snapshot!(mismatches);
+    }
+}
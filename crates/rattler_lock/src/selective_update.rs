@@ -0,0 +1,280 @@
+//! Re-locking a subset of the packages of an environment, e.g. for `update --only foo`. See
+//! [`SelectiveUpdate`].
+
+use std::collections::HashSet;
+
+use rattler_conda_types::{PackageName, Platform, RepoDataRecord};
+
+use crate::{
+    CondaPackage, CondaPackageData, ConversionError, Environment, LockFile, LockFileBuilder,
+    Package,
+};
+
+/// How the locked packages that are not updated are passed to the solver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum KeepLocked {
+    /// The locked packages are favored by the solver but may still change if the updated packages
+    /// require it.
+    #[default]
+    Favored,
+
+    /// The locked packages cannot change. If the updated packages require a different version of
+    /// a locked package the solve fails.
+    Pinned,
+}
+
+/// The records of a locked environment that should be passed to the solver when only a subset of
+/// the packages is updated. The fields correspond to the fields of the same name of the
+/// `SolverTask` of `rattler_solve`.
+#[derive(Debug, Clone, Default)]
+pub struct UpdateConstraints {
+    /// Records that the solver should favor.
+    pub locked_packages: Vec<RepoDataRecord>,
+
+    /// Records that the solver must select if the package is part of the solution.
+    pub pinned_packages: Vec<RepoDataRecord>,
+}
+
+/// Re-locks only a subset of the packages of an environment while keeping the lock-file as
+/// stable as possible.
+///
+/// Use [`SelectiveUpdate::constraints`] to determine the locked and pinned records for the solve
+/// of each platform and [`SelectiveUpdate::apply`] to replace the locked packages with the result
+/// of the solves. All other environments, platforms and pypi packages are left untouched.
+#[derive(Debug, Clone, Default)]
+pub struct SelectiveUpdate {
+    packages: HashSet<PackageName>,
+    keep_locked: KeepLocked,
+}
+
+impl SelectiveUpdate {
+    /// Constructs an update of the given conda packages.
+    pub fn new(packages: impl IntoIterator<Item = PackageName>) -> Self {
+        Self {
+            packages: packages.into_iter().collect(),
+            keep_locked: KeepLocked::default(),
+        }
+    }
+
+    /// Sets how the packages that are not updated are passed to the solver. Defaults to
+    /// [`KeepLocked::Favored`].
+    #[must_use]
+    pub fn with_keep_locked(self, keep_locked: KeepLocked) -> Self {
+        Self {
+            keep_locked,
+            ..self
+        }
+    }
+
+    /// Returns true if the package with the given name is updated.
+    pub fn is_updated(&self, name: &PackageName) -> bool {
+        self.packages.contains(name)
+    }
+
+    /// Returns the records of the environment that should be passed to the solver for the given
+    /// platform. The records of the updated packages are left out so the solver is free to select
+    /// any version of them.
+    pub fn constraints(
+        &self,
+        environment: &Environment,
+        platform: Platform,
+    ) -> Result<UpdateConstraints, ConversionError> {
+        let records = environment
+            .conda_repodata_records_for_platform(platform)?
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|record| !self.is_updated(&record.package_record.name))
+            .collect();
+
+        Ok(match self.keep_locked {
+            KeepLocked::Favored => UpdateConstraints {
+                locked_packages: records,
+                pinned_packages: Vec::new(),
+            },
+            KeepLocked::Pinned => UpdateConstraints {
+                locked_packages: Vec::new(),
+                pinned_packages: records,
+            },
+        })
+    }
+
+    /// Returns a copy of the lock-file where the conda packages of the given environment are
+    /// replaced by the solved records for each platform.
+    ///
+    /// Solved records that are identical to a locked package keep the exact locked data, so
+    /// packages that did not change produce no difference in the lock-file.
+    pub fn apply(
+        &self,
+        lock_file: &LockFile,
+        environment: &str,
+        solved: impl IntoIterator<Item = (Platform, Vec<RepoDataRecord>)>,
+    ) -> LockFile {
+        let solved = solved.into_iter().collect::<Vec<_>>();
+        let mut builder = LockFileBuilder::new();
+
+        for (name, locked_environment) in lock_file.environments() {
+            builder.set_channels(name, locked_environment.channels().iter().cloned());
+            if let Some(indexes) = locked_environment.pypi_indexes() {
+                builder.set_pypi_indexes(name, indexes.clone());
+            }
+
+            for (platform, packages) in locked_environment.packages_by_platform() {
+                let is_solved = name == environment
+                    && solved
+                        .iter()
+                        .any(|(solved_platform, _)| *solved_platform == platform);
+                for package in packages {
+                    match package {
+                        Package::Conda(_) if is_solved => {}
+                        Package::Conda(conda) => {
+                            builder.add_conda_package(name, platform, conda.package_data().clone());
+                        }
+                        Package::Pypi(pypi) => {
+                            builder.add_pypi_package(
+                                name,
+                                platform,
+                                pypi.package_data().clone(),
+                                pypi.environment_data().clone(),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        let locked_environment = lock_file.environment(environment);
+        for (platform, records) in solved {
+            let locked = locked_environment
+                .as_ref()
+                .and_then(|environment| environment.packages(platform))
+                .into_iter()
+                .flatten()
+                .filter_map(Package::into_conda)
+                .collect::<Vec<_>>();
+
+            for record in records {
+                let package = CondaPackageData::from(record);
+                let package = locked
+                    .iter()
+                    .map(CondaPackage::package_data)
+                    .find(|locked| {
+                        locked.url == package.url && locked.package_record == package.package_record
+                    })
+                    .cloned()
+                    .unwrap_or(package);
+                builder.add_conda_package(environment, platform, package);
+            }
+        }
+
+        builder.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEFAULT_ENVIRONMENT_NAME;
+    use rattler_conda_types::Version;
+    use std::{path::Path, str::FromStr};
+
+    fn lock_file() -> LockFile {
+        LockFile::from_path(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../../test-data/conda-lock/v4/pypi-matplotlib-lock.yml"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_constraints() {
+        let lock_file = lock_file();
+        let environment = lock_file.default_environment().unwrap();
+        let python = PackageName::from_str("python").unwrap();
+        let locked_count = environment
+            .conda_repodata_records_for_platform(Platform::Linux64)
+            .unwrap()
+            .unwrap()
+            .len();
+
+        let update = SelectiveUpdate::new([python.clone()]);
+        let constraints = update.constraints(&environment, Platform::Linux64).unwrap();
+        assert_eq!(constraints.locked_packages.len(), locked_count - 1);
+        assert!(constraints.pinned_packages.is_empty());
+        assert!(constraints
+            .locked_packages
+            .iter()
+            .all(|record| record.package_record.name != python));
+
+        let constraints = update
+            .with_keep_locked(KeepLocked::Pinned)
+            .constraints(&environment, Platform::Linux64)
+            .unwrap();
+        assert!(constraints.locked_packages.is_empty());
+        assert_eq!(constraints.pinned_packages.len(), locked_count - 1);
+    }
+
+    #[test]
+    fn test_apply() {
+        let lock_file = lock_file();
+        let environment = lock_file.default_environment().unwrap();
+        let python = PackageName::from_str("python").unwrap();
+
+        // Simulate a solve that only updated python.
+        let mut solved = environment
+            .conda_repodata_records_for_platform(Platform::Linux64)
+            .unwrap()
+            .unwrap();
+        let updated = solved
+            .iter_mut()
+            .find(|record| record.package_record.name == python)
+            .unwrap();
+        updated.package_record.version = Version::from_str("3.9.99").unwrap().into();
+        updated.url = "https://conda.anaconda.org/conda-forge/linux-64/python-3.9.99-0.conda"
+            .parse()
+            .unwrap();
+
+        let updated_lock_file = SelectiveUpdate::new([python.clone()]).apply(
+            &lock_file,
+            DEFAULT_ENVIRONMENT_NAME,
+            [(Platform::Linux64, solved)],
+        );
+        let updated_environment = updated_lock_file.default_environment().unwrap();
+
+        let conda_packages = |environment: &Environment, platform| {
+            let mut packages = environment
+                .packages(platform)
+                .unwrap()
+                .filter_map(Package::into_conda)
+                .map(|package| package.package_data().clone())
+                .collect::<Vec<_>>();
+            packages.sort();
+            packages
+        };
+        let before = conda_packages(&environment, Platform::Linux64);
+        let after = conda_packages(&updated_environment, Platform::Linux64);
+        assert_eq!(before.len(), after.len());
+        let changed = before
+            .iter()
+            .zip(&after)
+            .filter(|(before, after)| before != after)
+            .map(|(_, after)| after.package_record.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(changed, [python]);
+
+        // Other platforms and the pypi packages are untouched.
+        assert_eq!(
+            conda_packages(&environment, Platform::Osx64),
+            conda_packages(&updated_environment, Platform::Osx64)
+        );
+        assert_eq!(
+            environment
+                .pypi_packages_for_platform(Platform::Linux64)
+                .unwrap()
+                .len(),
+            updated_environment
+                .pypi_packages_for_platform(Platform::Linux64)
+                .unwrap()
+                .len()
+        );
+    }
+}
@@ -1,5 +1,5 @@
 //! `reqwest` middleware that authenticates requests with data from the `AuthenticationStorage`
-use crate::{Authentication, AuthenticationStorage};
+use crate::{channel_server::QUETZ_API_KEY_HEADER, Authentication, AuthenticationStorage};
 use async_trait::async_trait;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
@@ -23,8 +23,10 @@ impl Middleware for AuthenticationMiddleware {
         extensions: &mut http::Extensions,
         next: Next<'_>,
     ) -> reqwest_middleware::Result<Response> {
-        // If an `Authorization` header is already present, don't authenticate
-        if req.headers().get(reqwest::header::AUTHORIZATION).is_some() {
+        // If an `Authorization` header or a Quetz API key is already present, don't authenticate
+        if req.headers().contains_key(reqwest::header::AUTHORIZATION)
+            || req.headers().contains_key(QUETZ_API_KEY_HEADER)
+        {
             return next.run(req, extensions).await;
         }
 
@@ -53,6 +55,12 @@ impl AuthenticationMiddleware {
         Self { auth_storage }
     }
 
+    /// Create a new authentication middleware that reads credentials from the backends of the
+    /// configuration, see [`AuthenticationStorage::from_config`].
+    pub fn from_config(config: &rattler_config::Config) -> anyhow::Result<Self> {
+        Ok(Self::new(AuthenticationStorage::from_config(&config.auth)?))
+    }
+
     /// Authenticate the given URL with the given authentication information
     fn authenticate_url(url: Url, auth: &Option<Authentication>) -> Url {
         if let Some(credentials) = auth {
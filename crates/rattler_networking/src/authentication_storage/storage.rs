@@ -1,6 +1,7 @@
 //! Storage and access of authentication information
 
 use anyhow::{anyhow, Result};
+use rattler_config::{AuthBackend, AuthConfig};
 use reqwest::IntoUrl;
 use std::{
     collections::HashMap,
@@ -85,6 +86,53 @@ impl AuthenticationStorage {
         Ok(storage)
     }
 
+    /// Create a new authentication storage with the backends of the configuration.
+    ///
+    /// Without configured backends the default backends are used, unless a credentials file is
+    /// configured in which case only that file is used, like with `RATTLER_AUTH_FILE`.
+    pub fn from_config(config: &AuthConfig) -> Result<Self> {
+        let backends = match (&config.backends, &config.authentication_file) {
+            (Some(backends), _) => backends.clone(),
+            (None, Some(path)) => return Self::from_file(path),
+            (None, None) => return Ok(Self::default()),
+        };
+
+        let mut storage = Self::new();
+        for backend in backends {
+            match backend {
+                AuthBackend::Keyring => {
+                    storage.add_backend(Arc::from(KeyringAuthenticationStorage::default()));
+                }
+                AuthBackend::File => {
+                    let file_storage = match &config.authentication_file {
+                        Some(path) => FileStorage::new(path.clone()).map_err(|e| {
+                            anyhow!(
+                                "Error creating file storage backend from file ({}): {}",
+                                path.display(),
+                                e
+                            )
+                        })?,
+                        None => FileStorage::default(),
+                    };
+                    storage.add_backend(Arc::from(file_storage));
+                }
+                AuthBackend::Netrc => {
+                    storage.add_backend(Arc::from(NetRcStorage::from_env().unwrap_or_else(
+                        |(path, err)| {
+                            tracing::warn!(
+                                "error reading netrc file from {}: {}",
+                                path.display(),
+                                err
+                            );
+                            NetRcStorage::default()
+                        },
+                    )));
+                }
+            }
+        }
+        Ok(storage)
+    }
+
     /// Add a new storage backend to the authentication storage
     /// (backends are tried in the order they are added)
     pub fn add_backend(&mut self, backend: Arc<dyn StorageBackend + Send + Sync>) {
@@ -0,0 +1,503 @@
+//! Support for servers that host conda channels behind an API instead of as static files, e.g.
+//! [Quetz](https://github.com/mamba-org/quetz) and
+//! [JFrog Artifactory](https://jfrog.com/help/r/jfrog-artifactory-documentation/conda-repositories).
+//!
+//! A [`ChannelServer`] knows where these servers serve channels from, how they expect to be
+//! authenticated and how packages are uploaded to them.
+
+use reqwest::header::HeaderValue;
+use reqwest_middleware::RequestBuilder;
+use serde::Deserialize;
+use url::Url;
+
+use crate::{Authentication, RedactedUrl};
+
+/// The header that Quetz reads API keys from.
+pub const QUETZ_API_KEY_HEADER: &str = "X-API-Key";
+
+/// The kind of server that hosts conda channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelServerKind {
+    /// A Quetz server. Channels are served from `<server>/get/<channel>`.
+    Quetz,
+
+    /// A `JFrog` Artifactory instance. Channels are conda repositories that are served from
+    /// `<server>/artifactory/api/conda/<repository>`.
+    Artifactory,
+}
+
+/// An error that can occur when talking to a [`ChannelServer`].
+#[derive(Debug, thiserror::Error)]
+pub enum ChannelServerError {
+    /// The request failed.
+    #[error(transparent)]
+    RequestError(#[from] reqwest_middleware::Error),
+
+    /// The server responded with an error.
+    #[error("the server responded with {status} for {}: {body}", RedactedUrl::from(.url))]
+    ServerError {
+        /// The URL of the request.
+        url: Url,
+        /// The status of the response.
+        status: reqwest::StatusCode,
+        /// The body of the response.
+        body: String,
+    },
+}
+
+impl From<reqwest::Error> for ChannelServerError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::RequestError(err.into())
+    }
+}
+
+/// A server that hosts conda channels.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChannelServer {
+    kind: ChannelServerKind,
+    url: Url,
+}
+
+impl ChannelServer {
+    /// Constructs a new instance for the server of the given kind at the given URL, e.g.
+    /// `https://quetz.example.com` or `https://example.jfrog.io`.
+    pub fn new(kind: ChannelServerKind, mut url: Url) -> Self {
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        Self { kind, url }
+    }
+
+    /// Constructs a new instance for the Quetz server at the given URL.
+    pub fn quetz(url: Url) -> Self {
+        Self::new(ChannelServerKind::Quetz, url)
+    }
+
+    /// Constructs a new instance for the Artifactory instance at the given URL.
+    pub fn artifactory(url: Url) -> Self {
+        Self::new(ChannelServerKind::Artifactory, url)
+    }
+
+    /// Determines the server and the name of the channel from the URL of a channel, e.g.
+    /// `https://quetz.example.com/get/my-channel` or
+    /// `https://example.jfrog.io/artifactory/api/conda/my-repository`. Returns `None` if the URL
+    /// does not look like a channel of a known server.
+    pub fn from_channel_url(channel_url: &Url) -> Option<(Self, String)> {
+        let segments = channel_url
+            .path_segments()?
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+
+        let (kind, prefix) = match segments.as_slice() {
+            [prefix @ .., "artifactory", "api", "conda", _] => {
+                (ChannelServerKind::Artifactory, prefix)
+            }
+            [prefix @ .., "get", _] => (ChannelServerKind::Quetz, prefix),
+            _ => return None,
+        };
+
+        let mut url = channel_url.clone();
+        url.set_path(&prefix.join("/"));
+        url.set_query(None);
+        url.set_fragment(None);
+        let channel = (*segments.last()?).to_string();
+        Some((Self::new(kind, url), channel))
+    }
+
+    /// Returns the kind of the server.
+    pub fn kind(&self) -> ChannelServerKind {
+        self.kind
+    }
+
+    /// Returns the URL of the server.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Returns the URL that the given channel is served from. This is the URL that should be used
+    /// to fetch repodata and packages from the channel.
+    pub fn channel_url(&self, channel: &str) -> Url {
+        match self.kind {
+            ChannelServerKind::Quetz => self.join(&["get", channel, ""]),
+            ChannelServerKind::Artifactory => {
+                self.join(&["artifactory", "api", "conda", channel, ""])
+            }
+        }
+    }
+
+    /// Returns the URL that lists the channels on the server.
+    pub fn channels_url(&self) -> Url {
+        match self.kind {
+            ChannelServerKind::Quetz => self.join(&["api", "channels"]),
+            ChannelServerKind::Artifactory => {
+                let mut url = self.join(&["artifactory", "api", "repositories"]);
+                url.set_query(Some("packageType=conda"));
+                url
+            }
+        }
+    }
+
+    /// Returns the URL that a package is uploaded to. Quetz determines the subdir of a package
+    /// from its contents so `subdir` is only used for Artifactory.
+    pub fn upload_url(&self, channel: &str, subdir: &str, file_name: &str) -> Url {
+        match self.kind {
+            ChannelServerKind::Quetz => {
+                self.join(&["api", "channels", channel, "upload", file_name])
+            }
+            ChannelServerKind::Artifactory => {
+                self.join(&["artifactory", channel, subdir, file_name])
+            }
+        }
+    }
+
+    /// Adds the credentials to a request in the way the server expects them. Quetz expects API
+    /// keys in the [`QUETZ_API_KEY_HEADER`] header, all other credentials are sent in the
+    /// `Authorization` header.
+    pub fn authenticate(
+        &self,
+        request: RequestBuilder,
+        authentication: Option<&Authentication>,
+    ) -> RequestBuilder {
+        match (self.kind, authentication) {
+            (_, None) => request,
+            (
+                ChannelServerKind::Quetz,
+                Some(Authentication::BearerToken(token) | Authentication::CondaToken(token)),
+            ) => match HeaderValue::from_str(token) {
+                Ok(mut value) => {
+                    value.set_sensitive(true);
+                    request.header(QUETZ_API_KEY_HEADER, value)
+                }
+                Err(_) => request,
+            },
+            (
+                ChannelServerKind::Artifactory,
+                Some(Authentication::BearerToken(token) | Authentication::CondaToken(token)),
+            ) => request.bearer_auth(token),
+            (_, Some(Authentication::BasicHTTP { username, password })) => {
+                request.basic_auth(username, Some(password))
+            }
+        }
+    }
+
+    /// Returns the names of the channels on the server that are visible with the given
+    /// credentials.
+    pub async fn list_channels(
+        &self,
+        client: &reqwest_middleware::ClientWithMiddleware,
+        authentication: Option<&Authentication>,
+    ) -> Result<Vec<String>, ChannelServerError> {
+        #[derive(Deserialize)]
+        struct QuetzChannel {
+            name: String,
+        }
+
+        #[derive(Deserialize)]
+        struct ArtifactoryRepository {
+            key: String,
+        }
+
+        let url = self.channels_url();
+        let request = self.authenticate(client.get(url.clone()), authentication);
+        let response = error_for_status(url, request.send().await?).await?;
+        Ok(match self.kind {
+            ChannelServerKind::Quetz => response
+                .json::<Vec<QuetzChannel>>()
+                .await?
+                .into_iter()
+                .map(|channel| channel.name)
+                .collect(),
+            ChannelServerKind::Artifactory => response
+                .json::<Vec<ArtifactoryRepository>>()
+                .await?
+                .into_iter()
+                .map(|repository| repository.key)
+                .collect(),
+        })
+    }
+
+    /// Uploads a package to a channel on the server. `sha256` is the hex encoded SHA256 hash of
+    /// the package, which the server uses to verify the upload.
+    ///
+    /// Returns the URL of the package in the channel.
+    pub async fn upload(
+        &self,
+        client: &reqwest_middleware::ClientWithMiddleware,
+        authentication: Option<&Authentication>,
+        package: PackageUpload<'_>,
+        contents: impl Into<reqwest::Body>,
+    ) -> Result<Url, ChannelServerError> {
+        let url = self.upload_url(package.channel, package.subdir, package.file_name);
+        let request = match self.kind {
+            ChannelServerKind::Quetz => client
+                .post(url.clone())
+                .query(&[("sha256", package.sha256), ("force", "false")]),
+            ChannelServerKind::Artifactory => client
+                .put(url.clone())
+                .header("X-Checksum-Sha256", package.sha256),
+        };
+        let request = self.authenticate(request, authentication).body(contents);
+        error_for_status(url, request.send().await?).await?;
+
+        Ok(self
+            .channel_url(package.channel)
+            .join(&format!("{}/{}", package.subdir, package.file_name))
+            .expect("the file name must be a valid url segment"))
+    }
+
+    fn join(&self, segments: &[&str]) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("the server url must be a base")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+}
+
+/// Describes a package that is uploaded with [`ChannelServer::upload`].
+#[derive(Debug, Clone, Copy)]
+pub struct PackageUpload<'a> {
+    /// The channel to upload the package to.
+    pub channel: &'a str,
+
+    /// The subdir of the package, e.g. `linux-64`.
+    pub subdir: &'a str,
+
+    /// The file name of the package, e.g. `foo-1.0-0.conda`.
+    pub file_name: &'a str,
+
+    /// The hex encoded SHA256 hash of the package.
+    pub sha256: &'a str,
+}
+
+async fn error_for_status(
+    url: Url,
+    response: reqwest::Response,
+) -> Result<reqwest::Response, ChannelServerError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(ChannelServerError::ServerError { url, status, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::IntoFuture,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::{RawQuery, State},
+        http::{HeaderMap, Method, StatusCode, Uri},
+        Router,
+    };
+    use url::Url;
+
+    use super::{ChannelServer, ChannelServerKind, PackageUpload};
+    use crate::Authentication;
+
+    #[test]
+    fn test_channel_server_urls() {
+        let quetz = ChannelServer::quetz("https://quetz.example.com".parse().unwrap());
+        assert_eq!(
+            quetz.channel_url("robotics").as_str(),
+            "https://quetz.example.com/get/robotics/"
+        );
+        assert_eq!(
+            quetz.channels_url().as_str(),
+            "https://quetz.example.com/api/channels"
+        );
+        assert_eq!(
+            quetz
+                .upload_url("robotics", "linux-64", "foo-1.0-0.conda")
+                .as_str(),
+            "https://quetz.example.com/api/channels/robotics/upload/foo-1.0-0.conda"
+        );
+
+        let artifactory = ChannelServer::artifactory("https://example.jfrog.io/".parse().unwrap());
+        assert_eq!(
+            artifactory.channel_url("conda-local").as_str(),
+            "https://example.jfrog.io/artifactory/api/conda/conda-local/"
+        );
+        assert_eq!(
+            artifactory.channels_url().as_str(),
+            "https://example.jfrog.io/artifactory/api/repositories?packageType=conda"
+        );
+        assert_eq!(
+            artifactory
+                .upload_url("conda-local", "linux-64", "foo-1.0-0.conda")
+                .as_str(),
+            "https://example.jfrog.io/artifactory/conda-local/linux-64/foo-1.0-0.conda"
+        );
+    }
+
+    #[test]
+    fn test_from_channel_url() {
+        let detect = |url: &str| {
+            ChannelServer::from_channel_url(&url.parse().unwrap())
+                .map(|(server, channel)| (server.kind(), server.url().to_string(), channel))
+        };
+        assert_eq!(
+            detect("https://quetz.example.com/get/robotics/"),
+            Some((
+                ChannelServerKind::Quetz,
+                "https://quetz.example.com/".to_string(),
+                "robotics".to_string()
+            ))
+        );
+        assert_eq!(
+            detect("https://example.com/quetz/get/robotics"),
+            Some((
+                ChannelServerKind::Quetz,
+                "https://example.com/quetz/".to_string(),
+                "robotics".to_string()
+            ))
+        );
+        assert_eq!(
+            detect("https://example.jfrog.io/artifactory/api/conda/conda-local"),
+            Some((
+                ChannelServerKind::Artifactory,
+                "https://example.jfrog.io/".to_string(),
+                "conda-local".to_string()
+            ))
+        );
+        assert_eq!(detect("https://conda.anaconda.org/conda-forge"), None);
+    }
+
+    /// The method, path, query, headers and body of a request.
+    type Request = (Method, String, Option<String>, HeaderMap, Bytes);
+
+    #[derive(Default)]
+    struct Requests(Mutex<Vec<Request>>);
+
+    async fn handle(
+        State(requests): State<Arc<Requests>>,
+        method: Method,
+        uri: Uri,
+        RawQuery(query): RawQuery,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> (StatusCode, &'static str) {
+        let path = uri.path().to_string();
+        let response = match path.as_str() {
+            "/api/channels" => r#"[{"name": "robotics", "private": false}]"#,
+            "/artifactory/api/repositories" => {
+                r#"[{"key": "conda-local", "type": "LOCAL", "packageType": "Conda"}]"#
+            }
+            _ => "{}",
+        };
+        requests
+            .0
+            .lock()
+            .unwrap()
+            .push((method, path, query, headers, body));
+        (StatusCode::OK, response)
+    }
+
+    async fn test_server() -> (Url, Arc<Requests>) {
+        let requests = Arc::new(Requests::default());
+        let router = Router::new().fallback(handle).with_state(requests.clone());
+
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        (
+            format!("http://{}:{}", addr.ip(), addr.port())
+                .parse()
+                .unwrap(),
+            requests,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_quetz() {
+        let (url, requests) = test_server().await;
+        let client = reqwest_middleware::ClientWithMiddleware::from(reqwest::Client::new());
+        let server = ChannelServer::quetz(url.clone());
+        let token = Authentication::CondaToken("secret".to_string());
+
+        let channels = server.list_channels(&client, Some(&token)).await.unwrap();
+        assert_eq!(channels, ["robotics"]);
+
+        let package_url = server
+            .upload(
+                &client,
+                Some(&token),
+                PackageUpload {
+                    channel: "robotics",
+                    subdir: "linux-64",
+                    file_name: "foo-1.0-0.conda",
+                    sha256: "abcdef",
+                },
+                b"package".to_vec(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            package_url,
+            url.join("get/robotics/linux-64/foo-1.0-0.conda").unwrap()
+        );
+
+        let requests = requests.0.lock().unwrap();
+        let (method, path, query, headers, body) = &requests[1];
+        assert_eq!(method, Method::POST);
+        assert_eq!(path, "/api/channels/robotics/upload/foo-1.0-0.conda");
+        assert_eq!(query.as_deref(), Some("sha256=abcdef&force=false"));
+        assert_eq!(headers.get("x-api-key").unwrap(), "secret");
+        assert_eq!(body.as_ref(), b"package");
+    }
+
+    #[tokio::test]
+    async fn test_artifactory() {
+        let (url, requests) = test_server().await;
+        let client = reqwest_middleware::ClientWithMiddleware::from(reqwest::Client::new());
+        let server = ChannelServer::artifactory(url.clone());
+        let credentials = Authentication::BasicHTTP {
+            username: "user".to_string(),
+            password: "password".to_string(),
+        };
+
+        let channels = server
+            .list_channels(&client, Some(&credentials))
+            .await
+            .unwrap();
+        assert_eq!(channels, ["conda-local"]);
+
+        server
+            .upload(
+                &client,
+                Some(&credentials),
+                PackageUpload {
+                    channel: "conda-local",
+                    subdir: "noarch",
+                    file_name: "foo-1.0-0.tar.bz2",
+                    sha256: "abcdef",
+                },
+                b"package".to_vec(),
+            )
+            .await
+            .unwrap();
+
+        let requests = requests.0.lock().unwrap();
+        let (method, path, query, headers, _) = &requests[0];
+        assert_eq!(method, Method::GET);
+        assert_eq!(path, "/artifactory/api/repositories");
+        assert_eq!(query.as_deref(), Some("packageType=conda"));
+        assert_eq!(
+            headers.get("authorization").unwrap(),
+            "Basic dXNlcjpwYXNzd29yZA=="
+        );
+
+        let (method, path, _, headers, _) = &requests[1];
+        assert_eq!(method, Method::PUT);
+        assert_eq!(path, "/artifactory/conda-local/noarch/foo-1.0-0.tar.bz2");
+        assert_eq!(headers.get("x-checksum-sha256").unwrap(), "abcdef");
+    }
+}
@@ -0,0 +1,143 @@
+//! Support for channels and packages that are hosted on Windows (SMB) file shares, e.g.
+//! `file://server/share/channel`.
+
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::AuthenticationStorage;
+
+/// Returns the UNC root (`\\server\share`) of a `file://` URL that refers to a file share, or
+/// `None` if the URL refers to a local file.
+pub fn unc_share(url: &Url) -> Option<String> {
+    if url.scheme() != "file" {
+        return None;
+    }
+    let host = match url.host_str() {
+        None | Some("localhost") => return None,
+        Some(host) => host,
+    };
+    let share = url
+        .path_segments()?
+        .next()
+        .filter(|share| !share.is_empty())?;
+    let share = percent_decode_str(share).decode_utf8().ok()?;
+    Some(format!("\\\\{host}\\{share}"))
+}
+
+/// Connects to the file share that a `file://` URL refers to, using the credentials that are
+/// stored for the server in `storage`.
+///
+/// On Windows, a share that requires other credentials than those of the current user must be
+/// connected before the files on it can be accessed. Only [`crate::Authentication::BasicHTTP`]
+/// credentials are used, if no credentials are stored for the server the share is accessed as the
+/// current user. On other operating systems file shares are mounted by the system and this
+/// function does nothing.
+pub fn connect_file_share(url: &Url, storage: &AuthenticationStorage) -> std::io::Result<()> {
+    #[cfg(windows)]
+    if let Some(share) = unc_share(url) {
+        let host = url.host_str().expect("a share always has a host");
+        match storage.get(host) {
+            Ok(Some(crate::Authentication::BasicHTTP { username, password })) => {
+                return windows::connect(&share, &username, &password);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                tracing::warn!("failed to retrieve the credentials for {share}: {err}");
+            }
+        }
+    }
+
+    #[cfg(not(windows))]
+    let _ = (url, storage);
+
+    Ok(())
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{
+        collections::HashSet,
+        sync::{Mutex, OnceLock},
+    };
+
+    use windows_sys::Win32::{
+        Foundation::{ERROR_ALREADY_ASSIGNED, ERROR_SESSION_CREDENTIAL_CONFLICT, NO_ERROR},
+        NetworkManagement::WNet::{
+            WNetAddConnection2W, CONNECT_TEMPORARY, NETRESOURCEW, RESOURCETYPE_DISK,
+        },
+    };
+
+    /// The shares that have been connected by this process.
+    static CONNECTED_SHARES: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+    fn to_wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    pub(super) fn connect(share: &str, username: &str, password: &str) -> std::io::Result<()> {
+        let mut connected = CONNECTED_SHARES.get_or_init(Mutex::default).lock().unwrap();
+        if connected.contains(share) {
+            return Ok(());
+        }
+
+        let mut remote_name = to_wide(share);
+        let username = to_wide(username);
+        let password = to_wide(password);
+        let resource = NETRESOURCEW {
+            dwScope: 0,
+            dwType: RESOURCETYPE_DISK,
+            dwDisplayType: 0,
+            dwUsage: 0,
+            lpLocalName: std::ptr::null_mut(),
+            lpRemoteName: remote_name.as_mut_ptr(),
+            lpComment: std::ptr::null_mut(),
+            lpProvider: std::ptr::null_mut(),
+        };
+
+        // SAFETY: all pointers refer to null terminated buffers that outlive the call.
+        let result = unsafe {
+            WNetAddConnection2W(
+                &resource,
+                password.as_ptr(),
+                username.as_ptr(),
+                CONNECT_TEMPORARY,
+            )
+        };
+        match result {
+            NO_ERROR | ERROR_ALREADY_ASSIGNED => {}
+            ERROR_SESSION_CREDENTIAL_CONFLICT => {
+                // The share is already connected with other credentials, Windows only allows a
+                // single set of credentials per server so use the existing connection.
+                tracing::debug!("{share} is already connected with other credentials");
+            }
+            code => return Err(std::io::Error::from_raw_os_error(code as i32)),
+        }
+
+        connected.insert(share.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::unc_share;
+
+    #[test]
+    fn test_unc_share() {
+        let share = |url: &str| unc_share(&Url::parse(url).unwrap());
+        assert_eq!(
+            share("file://server/share/channel/noarch/repodata.json").as_deref(),
+            Some("\\\\server\\share")
+        );
+        assert_eq!(
+            share("file://server/my%20share/").as_deref(),
+            Some("\\\\server\\my share")
+        );
+        assert_eq!(share("file://server/"), None);
+        assert_eq!(share("file:///home/bob/channel"), None);
+        assert_eq!(share("file://localhost/home/bob/channel"), None);
+        assert_eq!(share("https://server/share"), None);
+    }
+}
@@ -3,6 +3,7 @@
 //! Networking utilities for Rattler, specifically authenticating requests
 pub use authentication_middleware::AuthenticationMiddleware;
 pub use authentication_storage::{authentication::Authentication, storage::AuthenticationStorage};
+pub use channel_server::{ChannelServer, ChannelServerKind};
 pub use mirror_middleware::MirrorMiddleware;
 pub use oci_middleware::OciMiddleware;
 
@@ -13,6 +14,8 @@ pub use gcs_middleware::GCSMiddleware;
 
 pub mod authentication_middleware;
 pub mod authentication_storage;
+pub mod channel_server;
+pub mod file_share;
 
 pub mod mirror_middleware;
 pub mod oci_middleware;
@@ -20,4 +23,4 @@ pub mod retry_policies;
 
 mod redaction;
 
-pub use redaction::{redact_known_secrets_from_url, Redact, DEFAULT_REDACTION_STR};
+pub use redaction::{redact_known_secrets_from_url, Redact, RedactedUrl, DEFAULT_REDACTION_STR};
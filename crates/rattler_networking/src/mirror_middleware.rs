@@ -73,6 +73,28 @@ impl MirrorMiddleware {
         }
     }
 
+    /// Create a new `MirrorMiddleware` from the mirrors in the configuration.
+    pub fn from_config(config: &rattler_config::Config) -> Self {
+        let mirror_map = config
+            .mirrors
+            .iter()
+            .map(|(url, mirrors)| {
+                let mirrors = mirrors
+                    .iter()
+                    .map(|url| Mirror {
+                        url: ensure_trailing_slash(url),
+                        no_zstd: false,
+                        no_bz2: false,
+                        no_jlap: false,
+                        max_failures: None,
+                    })
+                    .collect();
+                (url.clone(), mirrors)
+            })
+            .collect();
+        Self::from_map(mirror_map)
+    }
+
     /// Get sorted keys. The keys are sorted by length of the path,
     /// so the longest path comes first.
     pub fn keys(&self) -> &[(String, Url)] {
@@ -80,6 +102,18 @@ impl MirrorMiddleware {
     }
 }
 
+/// Paths are joined to the url of a mirror, which requires a trailing slash to keep the last
+/// segment of its path.
+fn ensure_trailing_slash(url: &Url) -> Url {
+    if url.path().ends_with('/') {
+        url.clone()
+    } else {
+        let mut url = url.clone();
+        url.set_path(&format!("{}/", url.path()));
+        url
+    }
+}
+
 fn select_mirror(mirrors: &[MirrorState]) -> Option<&MirrorState> {
     let mut min_failures = usize::MAX;
     let mut min_failures_index = usize::MAX;
@@ -290,6 +324,31 @@ mod test {
         assert!(res.text().await.unwrap() == "Hi from counter: server 2");
     }
 
+    #[tokio::test]
+    async fn test_mirror_middleware_from_config() {
+        let addr = test_server("server 1", false).await;
+
+        // The mirror is configured without a trailing slash but still keeps its path.
+        let mirror = addr.join("mirror").unwrap();
+        let config = rattler_config::Config {
+            mirrors: [("http://bla.com/channel".parse().unwrap(), vec![mirror])].into(),
+            ..rattler_config::Config::default()
+        };
+        let middleware = MirrorMiddleware::from_config(&config);
+        let mirrors = &middleware.mirror_map[&"http://bla.com/channel".parse().unwrap()];
+        assert_eq!(mirrors[0].mirror.url, addr.join("mirror/").unwrap());
+
+        let config = rattler_config::Config {
+            mirrors: [("http://bla.com".parse().unwrap(), vec![addr])].into(),
+            ..rattler_config::Config::default()
+        };
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(MirrorMiddleware::from_config(&config))
+            .build();
+        let res = client.get("http://bla.com/count").send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), "Hi from counter: server 1");
+    }
+
     #[test]
     fn test_mirror_sort() {
         let keys: Vec<Url> = vec![
@@ -9,6 +9,7 @@ use serde::Deserialize;
 use url::{ParseError, Url};
 
 use crate::mirror_middleware::create_404_response;
+use crate::RedactedUrl;
 
 #[derive(thiserror::Error, Debug)]
 enum OciMiddlewareError {
@@ -54,7 +55,10 @@ impl ToString for OciAction {
 async fn get_token(url: &OCIUrl, action: OciAction) -> Result<String, OciMiddlewareError> {
     let token_url = url.token_url(action)?;
 
-    tracing::trace!("OCI Mirror: requesting token from {}", token_url);
+    tracing::trace!(
+        "OCI Mirror: requesting token from {}",
+        RedactedUrl::from(&token_url)
+    );
 
     let token = reqwest::get(token_url)
         .await?
@@ -22,3 +22,8 @@ impl RetryPolicy for DoNotRetryPolicy {
 pub fn default_retry_policy() -> ExponentialBackoff {
     ExponentialBackoff::builder().build_with_max_retries(3)
 }
+
+/// Returns the retry policy with the number of retries of the configuration.
+pub fn retry_policy_from_config(config: &rattler_config::Config) -> ExponentialBackoff {
+    ExponentialBackoff::builder().build_with_max_retries(config.max_retries())
+}
@@ -0,0 +1,405 @@
+//! Functionality to reconstruct a package archive from an older version of the package and a
+//! binary delta.
+//!
+//! Deltas use the `BSDIFF40` format of [bsdiff](https://www.daemonology.net/bsdiff/), which is
+//! widely supported by existing tooling. A delta is applied with [`apply_bsdiff`] and can be
+//! created with [`create_bsdiff`].
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// The magic bytes at the start of a `BSDIFF40` patch.
+const MAGIC: &[u8] = b"BSDIFF40";
+
+/// The size of the header of a patch: the magic bytes followed by the length of the control and
+/// diff blocks and the size of the new file.
+const HEADER_SIZE: usize = 32;
+
+/// The number of bytes that are used to find matching regions of the old and new file.
+const WINDOW_SIZE: usize = 8;
+
+/// Matches that are shorter than this are stored as extra bytes instead.
+const MIN_MATCH_LENGTH: usize = 3 * WINDOW_SIZE;
+
+/// The maximum number of bytes that are decompressed at once when a patch is applied.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// An error that can occur when applying a binary delta.
+#[derive(thiserror::Error, Debug)]
+pub enum PatchError {
+    /// Reading the patch or writing the new file failed.
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    /// The patch is not a valid `BSDIFF40` patch.
+    #[error("invalid patch: {0}")]
+    InvalidPatch(&'static str),
+
+    /// The patch creates a file of a different size than expected.
+    #[error("the patch creates a file of {actual} bytes instead of the expected {expected} bytes")]
+    UnexpectedSize {
+        /// The expected size of the new file.
+        expected: u64,
+        /// The size of the new file according to the patch.
+        actual: u64,
+    },
+}
+
+/// Reconstructs a file from the `old` file and a `BSDIFF40` patch and writes it to `new`.
+/// Returns the number of bytes that were written.
+///
+/// If the size of the new file is known it should be passed as `expected_size`, a patch that
+/// creates a file of a different size is rejected before anything is written. The patch itself
+/// carries no checksum of the new file, callers should verify the hash of the reconstructed file.
+pub fn apply_bsdiff(
+    old: &[u8],
+    patch: &[u8],
+    expected_size: Option<u64>,
+    mut new: impl Write,
+) -> Result<u64, PatchError> {
+    if patch.len() < HEADER_SIZE || &patch[..MAGIC.len()] != MAGIC {
+        return Err(PatchError::InvalidPatch("missing BSDIFF40 header"));
+    }
+    let control_length = read_offset(&patch[8..16]);
+    let diff_length = read_offset(&patch[16..24]);
+    let new_size = read_offset(&patch[24..32]);
+    let (Ok(control_length), Ok(diff_length), Ok(new_size)) = (
+        usize::try_from(control_length),
+        usize::try_from(diff_length),
+        u64::try_from(new_size),
+    ) else {
+        return Err(PatchError::InvalidPatch("negative length in header"));
+    };
+    if let Some(expected) = expected_size {
+        if expected != new_size {
+            return Err(PatchError::UnexpectedSize {
+                expected,
+                actual: new_size,
+            });
+        }
+    }
+
+    let control_end = HEADER_SIZE
+        .checked_add(control_length)
+        .filter(|end| *end <= patch.len())
+        .ok_or(PatchError::InvalidPatch("truncated control block"))?;
+    let diff_end = control_end
+        .checked_add(diff_length)
+        .filter(|end| *end <= patch.len())
+        .ok_or(PatchError::InvalidPatch("truncated diff block"))?;
+    let mut control = bzip2::read::BzDecoder::new(&patch[HEADER_SIZE..control_end]);
+    let mut diff = bzip2::read::BzDecoder::new(&patch[control_end..diff_end]);
+    let mut extra = bzip2::read::BzDecoder::new(&patch[diff_end..]);
+
+    let mut new_position = 0u64;
+    let mut old_position = 0i64;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    while new_position < new_size {
+        let mut triple = [0u8; 24];
+        control.read_exact(&mut triple)?;
+        let add_length = u64::try_from(read_offset(&triple[0..8]));
+        let copy_length = u64::try_from(read_offset(&triple[8..16]));
+        let seek = read_offset(&triple[16..24]);
+        let (Ok(add_length), Ok(copy_length)) = (add_length, copy_length) else {
+            return Err(PatchError::InvalidPatch("negative length in control block"));
+        };
+        let next_position = new_position
+            .checked_add(add_length)
+            .and_then(|position| position.checked_add(copy_length))
+            .filter(|position| *position <= new_size)
+            .ok_or(PatchError::InvalidPatch("control block exceeds new size"))?;
+
+        // Add the diff bytes to the bytes of the old file.
+        let mut remaining = add_length;
+        while remaining > 0 {
+            let chunk = &mut buffer[..chunk_length(remaining)];
+            diff.read_exact(chunk)?;
+            for byte in chunk.iter_mut() {
+                let old_byte = usize::try_from(old_position)
+                    .ok()
+                    .and_then(|position| old.get(position));
+                if let Some(old_byte) = old_byte {
+                    *byte = byte.wrapping_add(*old_byte);
+                }
+                old_position = old_position
+                    .checked_add(1)
+                    .ok_or(PatchError::InvalidPatch("offset in old file overflows"))?;
+            }
+            new.write_all(chunk)?;
+            remaining -= chunk.len() as u64;
+        }
+
+        // Copy the extra bytes verbatim.
+        let mut remaining = copy_length;
+        while remaining > 0 {
+            let chunk = &mut buffer[..chunk_length(remaining)];
+            extra.read_exact(chunk)?;
+            new.write_all(chunk)?;
+            remaining -= chunk.len() as u64;
+        }
+
+        new_position = next_position;
+        old_position = old_position
+            .checked_add(seek)
+            .ok_or(PatchError::InvalidPatch("offset in old file overflows"))?;
+    }
+
+    Ok(new_size)
+}
+
+/// Returns the number of bytes of the next chunk when `remaining` bytes still have to be read.
+fn chunk_length(remaining: u64) -> usize {
+    usize::try_from(remaining).map_or(CHUNK_SIZE, |remaining| remaining.min(CHUNK_SIZE))
+}
+
+/// Creates a `BSDIFF40` patch that transforms `old` into `new`.
+///
+/// Unlike the reference implementation, which uses suffix sorting to find approximate matches,
+/// this only finds exact matches of the regions that did not change. This is fast and works well
+/// for package archives where most of the compressed members are unchanged between versions.
+pub fn create_bsdiff(old: &[u8], new: &[u8], mut patch: impl Write) -> std::io::Result<()> {
+    // Index the non-overlapping windows of the old file. Every match that is at least twice the
+    // window size contains one of these windows.
+    let mut windows = HashMap::new();
+    for (index, window) in old.chunks_exact(WINDOW_SIZE).enumerate() {
+        windows.entry(window).or_insert(index * WINDOW_SIZE);
+    }
+
+    let mut control = Vec::new();
+    let mut diff = Vec::new();
+    let mut extra = Vec::new();
+
+    // The previous match, its length is emitted together with the extra bytes that follow it.
+    let mut match_length = 0;
+    let mut old_end = 0;
+    let mut extra_start = 0;
+    let mut position = 0;
+    while position + WINDOW_SIZE <= new.len() {
+        let Some(&(mut old_position)) = windows.get(&new[position..position + WINDOW_SIZE]) else {
+            position += 1;
+            continue;
+        };
+
+        // Extend the match backwards into the bytes that did not match yet and forwards as far
+        // as possible.
+        let backwards = old[..old_position]
+            .iter()
+            .rev()
+            .zip(new[extra_start..position].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let forwards = old[old_position..]
+            .iter()
+            .zip(&new[position..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        let length = backwards + forwards;
+        if length < MIN_MATCH_LENGTH {
+            position += 1;
+            continue;
+        }
+        old_position -= backwards;
+        position -= backwards;
+
+        write_triple(
+            &mut control,
+            match_length,
+            position - extra_start,
+            old_position as i64 - old_end as i64,
+        );
+        diff.resize(diff.len() + length, 0);
+        extra.extend_from_slice(&new[extra_start..position]);
+
+        match_length = length;
+        old_end = old_position + length;
+        position += length;
+        extra_start = position;
+    }
+    if match_length > 0 || extra_start < new.len() {
+        write_triple(&mut control, match_length, new.len() - extra_start, 0);
+        extra.extend_from_slice(&new[extra_start..]);
+    }
+
+    let control = compress(&control)?;
+    let diff = compress(&diff)?;
+    let extra = compress(&extra)?;
+    patch.write_all(MAGIC)?;
+    patch.write_all(&write_offset(control.len() as i64))?;
+    patch.write_all(&write_offset(diff.len() as i64))?;
+    patch.write_all(&write_offset(new.len() as i64))?;
+    patch.write_all(&control)?;
+    patch.write_all(&diff)?;
+    patch.write_all(&extra)
+}
+
+fn write_triple(control: &mut Vec<u8>, add_length: usize, copy_length: usize, seek: i64) {
+    control.extend_from_slice(&write_offset(add_length as i64));
+    control.extend_from_slice(&write_offset(copy_length as i64));
+    control.extend_from_slice(&write_offset(seek));
+}
+
+fn compress(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+/// Reads a bsdiff offset: a little endian sign-magnitude integer.
+fn read_offset(bytes: &[u8]) -> i64 {
+    let value = u64::from_le_bytes(bytes.try_into().expect("offsets are 8 bytes"));
+    let magnitude = (value & !(1 << 63)) as i64;
+    if value & (1 << 63) == 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
+
+fn write_offset(value: i64) -> [u8; 8] {
+    let mut bytes = value.unsigned_abs().to_le_bytes();
+    if value < 0 {
+        bytes[7] |= 0x80;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::{
+        apply_bsdiff, compress, create_bsdiff, read_offset, write_offset, PatchError, MAGIC,
+    };
+
+    /// Returns `len` bytes that do not compress.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        std::iter::successors(Some(seed), |x| {
+            let x = x ^ (x << 13);
+            let x = x ^ (x >> 7);
+            Some(x ^ (x << 17))
+        })
+        .flat_map(u64::to_le_bytes)
+        .take(len)
+        .collect()
+    }
+
+    #[test]
+    fn test_offset() {
+        for value in [0, 1, -1, 1 << 40, -(1 << 40), i64::MAX] {
+            assert_eq!(read_offset(&write_offset(value)), value);
+        }
+        // Negative zero is a valid encoding of zero.
+        assert_eq!(read_offset(&[0, 0, 0, 0, 0, 0, 0, 0x80]), 0);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let old = [noise(1, 40_000), noise(2, 30_000), noise(3, 20_000)].concat();
+        // Remove a section, insert new data and move the other sections around.
+        let new = [
+            noise(3, 20_000),
+            noise(4, 1_000),
+            noise(1, 40_000)[..35_000].to_vec(),
+            b"changed".to_vec(),
+        ]
+        .concat();
+
+        let mut patch = Vec::new();
+        create_bsdiff(&old, &new, &mut patch).unwrap();
+        assert!(patch.len() < 2_000, "the patch is {} bytes", patch.len());
+
+        let mut reconstructed = Vec::new();
+        assert_eq!(
+            apply_bsdiff(&old, &patch, Some(new.len() as u64), &mut reconstructed).unwrap(),
+            new.len() as u64
+        );
+        assert_eq!(reconstructed, new);
+    }
+
+    #[test]
+    fn test_roundtrip_edge_cases() {
+        let cases: [(&[u8], &[u8]); 4] = [
+            (b"", b""),
+            (b"", b"new file"),
+            (b"old file", b""),
+            (b"short", b"shorter"),
+        ];
+        for (old, new) in cases {
+            let mut patch = Vec::new();
+            create_bsdiff(old, new, &mut patch).unwrap();
+            let mut reconstructed = Vec::new();
+            apply_bsdiff(old, &patch, None, &mut reconstructed).unwrap();
+            assert_eq!(reconstructed, new);
+        }
+    }
+
+    #[test]
+    fn test_invalid_patch() {
+        assert_matches!(
+            apply_bsdiff(b"old", b"not a patch", None, Vec::new()),
+            Err(PatchError::InvalidPatch(_))
+        );
+
+        let mut patch = Vec::new();
+        create_bsdiff(&noise(1, 1_000), &noise(2, 1_000), &mut patch).unwrap();
+        assert!(apply_bsdiff(b"old", &patch[..patch.len() - 200], None, Vec::new()).is_err());
+        assert_matches!(
+            apply_bsdiff(b"old", &patch, Some(999), Vec::new()),
+            Err(PatchError::UnexpectedSize {
+                expected: 999,
+                actual: 1_000
+            })
+        );
+    }
+
+    /// Returns a patch with the given header size of the new file and control block.
+    fn patch_with_control(new_size: i64, control: &[[i64; 3]]) -> Vec<u8> {
+        let control = compress(
+            &control
+                .iter()
+                .flatten()
+                .flat_map(|value| write_offset(*value))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let empty = compress(&[]).unwrap();
+        [
+            MAGIC,
+            &write_offset(control.len() as i64),
+            &write_offset(empty.len() as i64),
+            &write_offset(new_size),
+            &control,
+            &empty,
+            &empty,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_malicious_control_block() {
+        // Lengths that overflow when they are added up.
+        let patch = patch_with_control(i64::MAX, &[[i64::MAX, i64::MAX, 0]]);
+        assert_matches!(
+            apply_bsdiff(b"old", &patch, None, Vec::new()),
+            Err(PatchError::InvalidPatch(_))
+        );
+
+        // A huge length is read in chunks instead of allocating a buffer for it up front.
+        let patch = patch_with_control(1 << 50, &[[1 << 50, 0, 0]]);
+        assert_matches!(
+            apply_bsdiff(b"old", &patch, None, Vec::new()),
+            Err(PatchError::IoError(_))
+        );
+        assert_matches!(
+            apply_bsdiff(b"old", &patch, Some(100), Vec::new()),
+            Err(PatchError::UnexpectedSize { .. })
+        );
+
+        // Seeking beyond the range of offsets.
+        let patch = patch_with_control(1, &[[0, 0, i64::MAX], [0, 0, 1]]);
+        assert_matches!(
+            apply_bsdiff(b"old", &patch, None, Vec::new()),
+            Err(PatchError::InvalidPatch(_))
+        );
+    }
+}
@@ -0,0 +1,524 @@
+//! Functionality to compare the contents of package archives with each other or with the files of
+//! a package that has been installed into a prefix.
+//!
+//! The entry point of this module is [`PackageContents`] which describes the files and the
+//! `info/index.json` of a package. Two [`PackageContents`] can be compared with
+//! [`PackageContents::diff`] which returns a [`PackageDiff`].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
+use std::mem::ManuallyDrop;
+use std::path::{Path, PathBuf};
+
+use rattler_conda_types::package::{ArchiveType, IndexJson};
+use rattler_conda_types::PrefixRecord;
+use rattler_digest::{HashingReader, Sha256, Sha256Hash};
+use zip::read::read_zipfile_from_stream;
+
+use crate::read::{stream_tar_bz2, stream_tar_zst};
+use crate::ExtractError;
+
+/// Describes a single file in a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    /// The size of the file in bytes. This is `0` for symbolic links.
+    pub size: u64,
+
+    /// The SHA256 hash of the contents of the file. For symbolic links this is the hash of the
+    /// link target.
+    pub sha256: Sha256Hash,
+
+    /// If this entry is a symbolic link, the path it points to.
+    pub link_target: Option<PathBuf>,
+}
+
+/// The contents of a package that are relevant when comparing packages.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackageContents {
+    /// All files of the package (excluding the `info/` directory) indexed by their path relative
+    /// to the root of the package.
+    pub files: BTreeMap<PathBuf, FileEntry>,
+
+    /// The contents of `info/index.json` if it is available.
+    pub index_json: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// A difference between the files of two packages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChange {
+    /// The file only exists in the new package.
+    Added {
+        /// The path of the file relative to the root of the package
+        path: PathBuf,
+        /// The file in the new package
+        new: FileEntry,
+    },
+
+    /// The file only exists in the old package.
+    Removed {
+        /// The path of the file relative to the root of the package
+        path: PathBuf,
+        /// The file in the old package
+        old: FileEntry,
+    },
+
+    /// The file exists in both packages but its contents differ.
+    Changed {
+        /// The path of the file relative to the root of the package
+        path: PathBuf,
+        /// The file in the old package
+        old: FileEntry,
+        /// The file in the new package
+        new: FileEntry,
+    },
+}
+
+impl FileChange {
+    /// Returns the path of the file that changed.
+    pub fn path(&self) -> &Path {
+        match self {
+            FileChange::Added { path, .. }
+            | FileChange::Removed { path, .. }
+            | FileChange::Changed { path, .. } => path,
+        }
+    }
+
+    /// Returns the change in size in bytes of the file.
+    pub fn size_delta(&self) -> i64 {
+        match self {
+            FileChange::Added { new, .. } => new.size as i64,
+            FileChange::Removed { old, .. } => -(old.size as i64),
+            FileChange::Changed { old, new, .. } => new.size as i64 - old.size as i64,
+        }
+    }
+}
+
+/// A difference between a field in the `index.json` of two packages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataChange {
+    /// The key of the field in `index.json`.
+    pub key: String,
+
+    /// The value in the old package or `None` if the field is missing.
+    pub old: Option<serde_json::Value>,
+
+    /// The value in the new package or `None` if the field is missing.
+    pub new: Option<serde_json::Value>,
+}
+
+/// The result of comparing two [`PackageContents`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageDiff {
+    /// All files that were added, removed or changed, sorted by path.
+    pub files: Vec<FileChange>,
+
+    /// All fields of `index.json` that differ, sorted by key.
+    pub metadata: Vec<MetadataChange>,
+}
+
+impl PackageDiff {
+    /// Returns true if there are no differences between the packages.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.metadata.is_empty()
+    }
+
+    /// Returns the total change in size in bytes of all files.
+    pub fn size_delta(&self) -> i64 {
+        self.files.iter().map(FileChange::size_delta).sum()
+    }
+
+    /// Returns an iterator over all added files.
+    pub fn added(&self) -> impl Iterator<Item = &FileChange> + '_ {
+        self.files
+            .iter()
+            .filter(|change| matches!(change, FileChange::Added { .. }))
+    }
+
+    /// Returns an iterator over all removed files.
+    pub fn removed(&self) -> impl Iterator<Item = &FileChange> + '_ {
+        self.files
+            .iter()
+            .filter(|change| matches!(change, FileChange::Removed { .. }))
+    }
+
+    /// Returns an iterator over all changed files.
+    pub fn changed(&self) -> impl Iterator<Item = &FileChange> + '_ {
+        self.files
+            .iter()
+            .filter(|change| matches!(change, FileChange::Changed { .. }))
+    }
+}
+
+impl PackageContents {
+    /// Reads the contents of the package archive at the specified path. The type of the archive is
+    /// determined from the file extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ExtractError> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        match ArchiveType::try_from(path).ok_or(ExtractError::UnsupportedArchiveType)? {
+            ArchiveType::TarBz2 => Self::from_tar_bz2(file),
+            ArchiveType::Conda => Self::from_conda(file),
+        }
+    }
+
+    /// Reads the contents of a `.tar.bz2` package archive.
+    pub fn from_tar_bz2(reader: impl Read) -> Result<Self, ExtractError> {
+        let mut contents = Self::default();
+        contents.read_tar_entries(stream_tar_bz2(reader))?;
+        Ok(contents)
+    }
+
+    /// Reads the contents of a `.conda` package archive.
+    pub fn from_conda(mut reader: impl Read) -> Result<Self, ExtractError> {
+        let mut contents = Self::default();
+        while let Some(file) = read_zipfile_from_stream(&mut reader)? {
+            // See [`crate::read::extract_conda`] for why [`ManuallyDrop`] is used here.
+            let mut file = ManuallyDrop::new(file);
+
+            if file
+                .mangled_name()
+                .file_name()
+                .map(OsStr::to_string_lossy)
+                .is_some_and(|file_name| file_name.ends_with(".tar.zst"))
+            {
+                contents.read_tar_entries(stream_tar_zst(&mut *file)?)?;
+            } else {
+                std::io::copy(&mut *file, &mut std::io::sink())?;
+            }
+
+            let _ = ManuallyDrop::into_inner(file);
+        }
+        Ok(contents)
+    }
+
+    /// Reads the contents of a package that has been installed into the prefix at `prefix`.
+    ///
+    /// The files are determined from the `paths_data` of the `record`. Files that had their prefix
+    /// replaced during installation are reported with the hash and size of the original file in
+    /// the package if their contents have not been modified since, this ensures that a pristine
+    /// installation has no differences with the archive it was installed from. The `index.json`
+    /// is reconstructed from the package record.
+    pub fn from_prefix(prefix: &Path, record: &PrefixRecord) -> Result<Self, ExtractError> {
+        let mut files = BTreeMap::new();
+        for entry in &record.paths_data.paths {
+            let path = prefix.join(&entry.relative_path);
+            let metadata = std::fs::symlink_metadata(&path)?;
+            let file_entry = if metadata.file_type().is_symlink() {
+                let target = std::fs::read_link(&path)?;
+                symlink_entry(target)
+            } else {
+                let (sha256, size) = hash_reader(File::open(&path)?)?;
+                match (entry.sha256_in_prefix, entry.sha256, entry.size_in_bytes) {
+                    (Some(sha256_in_prefix), Some(original), Some(original_size))
+                        if sha256_in_prefix == sha256 =>
+                    {
+                        FileEntry {
+                            size: original_size,
+                            sha256: original,
+                            link_target: None,
+                        }
+                    }
+                    _ => FileEntry {
+                        size,
+                        sha256,
+                        link_target: None,
+                    },
+                }
+            };
+            files.insert(entry.relative_path.clone(), file_entry);
+        }
+
+        // Round-trip the package record through `IndexJson` to only retain the fields that are
+        // part of the `index.json` file.
+        let index_json = serde_json::to_value(&record.repodata_record.package_record)
+            .and_then(serde_json::from_value::<IndexJson>)
+            .and_then(serde_json::to_value)
+            .map_err(|e| {
+                ExtractError::ArchiveMemberParseError(PathBuf::from("info/index.json"), e.into())
+            })?;
+
+        Ok(Self {
+            files,
+            index_json: match index_json {
+                serde_json::Value::Object(map) => Some(map),
+                _ => None,
+            },
+        })
+    }
+
+    /// Restricts the files of this instance to the paths that are also present in `other`. This is
+    /// useful to compare a package with an installed subset of a prefix.
+    pub fn retain_paths_of(&mut self, other: &PackageContents) {
+        self.files.retain(|path, _| other.files.contains_key(path));
+    }
+
+    /// Computes the differences between this instance (the old package) and `new`.
+    pub fn diff(&self, new: &PackageContents) -> PackageDiff {
+        let paths: BTreeSet<&PathBuf> = self.files.keys().chain(new.files.keys()).collect();
+        let files = paths
+            .into_iter()
+            .filter_map(|path| match (self.files.get(path), new.files.get(path)) {
+                (Some(old), Some(new)) if old == new => None,
+                (Some(old), Some(new)) => Some(FileChange::Changed {
+                    path: path.clone(),
+                    old: old.clone(),
+                    new: new.clone(),
+                }),
+                (Some(old), None) => Some(FileChange::Removed {
+                    path: path.clone(),
+                    old: old.clone(),
+                }),
+                (None, Some(new)) => Some(FileChange::Added {
+                    path: path.clone(),
+                    new: new.clone(),
+                }),
+                (None, None) => unreachable!("path must exist in either package"),
+            })
+            .collect();
+
+        let empty = serde_json::Map::new();
+        let old_index = self.index_json.as_ref().unwrap_or(&empty);
+        let new_index = new.index_json.as_ref().unwrap_or(&empty);
+        let keys: BTreeSet<&String> = old_index.keys().chain(new_index.keys()).collect();
+        let metadata = keys
+            .into_iter()
+            .filter_map(|key| {
+                let old = old_index.get(key);
+                let new = new_index.get(key);
+                (old != new).then(|| MetadataChange {
+                    key: key.clone(),
+                    old: old.cloned(),
+                    new: new.cloned(),
+                })
+            })
+            .collect();
+
+        PackageDiff { files, metadata }
+    }
+
+    fn read_tar_entries(
+        &mut self,
+        mut archive: tar::Archive<impl Read>,
+    ) -> Result<(), ExtractError> {
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            let entry_type = entry.header().entry_type();
+
+            if path == Path::new("info/index.json") {
+                let mut buf = Vec::new();
+                entry.read_to_end(&mut buf)?;
+                let index_json = serde_json::from_slice(&buf)
+                    .map_err(|e| ExtractError::ArchiveMemberParseError(path, e.into()))?;
+                self.index_json = Some(index_json);
+            } else if path.starts_with("info") {
+                // Other files in the `info/` directory are not part of the package contents.
+            } else if entry_type.is_symlink() {
+                let target = entry
+                    .link_name()?
+                    .map(std::borrow::Cow::into_owned)
+                    .unwrap_or_default();
+                self.files.insert(path, symlink_entry(target));
+            } else if entry_type.is_hard_link() {
+                // A hard link refers to an earlier entry of the archive and has the same contents.
+                let target = entry
+                    .link_name()?
+                    .map(std::borrow::Cow::into_owned)
+                    .unwrap_or_default();
+                let Some(file) = self.files.get(&target).cloned() else {
+                    return Err(ExtractError::ArchiveMemberParseError(
+                        path,
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("hard link to unknown entry {}", target.display()),
+                        ),
+                    ));
+                };
+                self.files.insert(path, file);
+            } else if entry_type.is_file() {
+                let (sha256, size) = hash_reader(&mut entry)?;
+                self.files.insert(
+                    path,
+                    FileEntry {
+                        size,
+                        sha256,
+                        link_target: None,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn symlink_entry(target: PathBuf) -> FileEntry {
+    FileEntry {
+        size: 0,
+        sha256: rattler_digest::compute_bytes_digest::<Sha256>(target.to_string_lossy().as_bytes()),
+        link_target: Some(target),
+    }
+}
+
+fn hash_reader(reader: impl Read) -> Result<(Sha256Hash, u64), std::io::Error> {
+    let mut reader = HashingReader::<_, Sha256>::new(reader);
+    let size = std::io::copy(&mut reader, &mut std::io::sink())?;
+    let (_, sha256) = reader.finalize();
+    Ok((sha256, size))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileChange, PackageContents};
+    use crate::write::{write_conda_package, write_tar_bz2_package, CompressionLevel};
+    use std::path::{Path, PathBuf};
+
+    fn write_package(dir: &Path, files: &[(&str, &str)], conda: bool) -> PackageContents {
+        let base_path = dir.join("package");
+        let _ = std::fs::remove_dir_all(&base_path);
+        let mut paths = Vec::new();
+        for (path, contents) in files {
+            let path = base_path.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+            paths.push(path);
+        }
+
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        if conda {
+            write_conda_package(
+                &mut buffer,
+                &base_path,
+                &paths,
+                CompressionLevel::Lowest,
+                None,
+                "package",
+                None,
+                None,
+            )
+            .unwrap();
+            PackageContents::from_conda(std::io::Cursor::new(buffer.into_inner())).unwrap()
+        } else {
+            write_tar_bz2_package(
+                &mut buffer,
+                &base_path,
+                &paths,
+                CompressionLevel::Lowest,
+                None,
+                None,
+            )
+            .unwrap();
+            PackageContents::from_tar_bz2(std::io::Cursor::new(buffer.into_inner())).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_diff_packages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let old = write_package(
+            temp_dir.path(),
+            &[
+                ("info/index.json", r#"{"name": "foo", "build_number": 0}"#),
+                ("lib/unchanged.txt", "same"),
+                ("lib/changed.txt", "old"),
+                ("lib/removed.txt", "removed"),
+            ],
+            true,
+        );
+        let new = write_package(
+            temp_dir.path(),
+            &[
+                ("info/index.json", r#"{"name": "foo", "build_number": 1}"#),
+                ("lib/unchanged.txt", "same"),
+                ("lib/changed.txt", "newer"),
+                ("lib/added.txt", "added"),
+            ],
+            false,
+        );
+
+        let diff = old.diff(&new);
+        let paths = |changes: Vec<&FileChange>| {
+            changes
+                .into_iter()
+                .map(|c| c.path().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(diff.added().collect()),
+            vec![PathBuf::from("lib/added.txt")]
+        );
+        assert_eq!(
+            paths(diff.removed().collect()),
+            vec![PathBuf::from("lib/removed.txt")]
+        );
+        assert_eq!(
+            paths(diff.changed().collect()),
+            vec![PathBuf::from("lib/changed.txt")]
+        );
+        assert_eq!(diff.size_delta(), 5 - 7 + 5 - 3);
+
+        assert_eq!(diff.metadata.len(), 1);
+        assert_eq!(diff.metadata[0].key, "build_number");
+        assert_eq!(diff.metadata[0].old, Some(0.into()));
+        assert_eq!(diff.metadata[0].new, Some(1.into()));
+
+        assert!(old.diff(&old).is_empty());
+    }
+
+    /// Returns a `.tar.bz2` package with the given files and a hard link to the first file.
+    fn tar_bz2_with_hard_link(files: &[(&str, &str)], link: &str) -> PackageContents {
+        let mut builder = tar::Builder::new(bzip2::write::BzEncoder::new(
+            Vec::new(),
+            bzip2::Compression::default(),
+        ));
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder.append_link(&mut header, link, files[0].0).unwrap();
+        let archive = builder.into_inner().unwrap().finish().unwrap();
+        PackageContents::from_tar_bz2(std::io::Cursor::new(archive)).unwrap()
+    }
+
+    #[test]
+    fn test_diff_hard_links() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let regular = write_package(
+            temp_dir.path(),
+            &[("lib/a.txt", "contents"), ("lib/b.txt", "contents")],
+            false,
+        );
+
+        // A hard link has the contents of its target.
+        let linked = tar_bz2_with_hard_link(&[("lib/a.txt", "contents")], "lib/b.txt");
+        assert!(regular.diff(&linked).is_empty());
+
+        // Changing the target changes the link as well.
+        let changed = tar_bz2_with_hard_link(&[("lib/a.txt", "changed")], "lib/b.txt");
+        let diff = linked.diff(&changed);
+        assert_eq!(diff.changed().count(), 2);
+        assert_eq!(diff.size_delta(), -2);
+
+        // A link to an entry that is not in the archive is an error.
+        let mut builder = tar::Builder::new(bzip2::write::BzEncoder::new(
+            Vec::new(),
+            bzip2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "lib/b.txt", "lib/missing.txt")
+            .unwrap();
+        let archive = builder.into_inner().unwrap().finish().unwrap();
+        assert!(PackageContents::from_tar_bz2(std::io::Cursor::new(archive)).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 //! Functions to extracting or stream a Conda package from a file on disk.
 
+use crate::sanitize::{ExtractionPolicy, SanitizationReport};
 use crate::{ExtractError, ExtractResult};
 use rattler_conda_types::package::ArchiveType;
 use std::fs::File;
@@ -52,3 +53,18 @@ pub fn extract(archive: &Path, destination: &Path) -> Result<ExtractResult, Extr
         ArchiveType::Conda => extract_conda(archive, destination),
     }
 }
+
+/// Extracts the contents a package archive at the specified path to a directory while applying
+/// the given safety `policy` to every entry. The type of package is determined based on the file
+/// extension of the archive path. See [`crate::sanitize`] for more information.
+pub fn extract_with_policy(
+    archive: &Path,
+    destination: &Path,
+    policy: &ExtractionPolicy,
+) -> Result<(ExtractResult, SanitizationReport), ExtractError> {
+    let file = File::open(archive)?;
+    match ArchiveType::try_from(archive).ok_or(ExtractError::UnsupportedArchiveType)? {
+        ArchiveType::TarBz2 => crate::read::extract_tar_bz2_with_policy(file, destination, policy),
+        ArchiveType::Conda => crate::read::extract_conda_with_policy(file, destination, policy),
+    }
+}
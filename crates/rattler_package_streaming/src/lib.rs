@@ -11,11 +11,15 @@ use rattler_digest::{Md5Hash, Sha256Hash};
 use rattler_networking::Redact;
 
 pub mod read;
+pub mod sanitize;
 pub mod seek;
+pub mod signing;
 
 #[cfg(feature = "reqwest")]
 pub mod reqwest;
 
+pub mod delta;
+pub mod diff;
 pub mod fs;
 pub mod tokio;
 pub mod write;
@@ -51,6 +55,15 @@ pub enum ExtractError {
 
     #[error("could not parse archive member {0}: {1}")]
     ArchiveMemberParseError(PathBuf, #[source] std::io::Error),
+
+    #[error("refusing to extract unsafe archive member {0}: {1}")]
+    UnsafeArchiveEntry(PathBuf, sanitize::SafetyIssue),
+
+    #[error("the sha256 hash of the archive is {actual:x} but {expected:x} was expected")]
+    HashMismatch {
+        expected: rattler_digest::Sha256Hash,
+        actual: rattler_digest::Sha256Hash,
+    },
 }
 
 impl From<ZipError> for ExtractError {
@@ -2,6 +2,7 @@
 //! [`std::io::Read`] trait.
 
 use super::{ExtractError, ExtractResult};
+use crate::sanitize::{unpack_sanitized, ExtractionPolicy, SanitizationReport};
 use std::mem::ManuallyDrop;
 use std::{ffi::OsStr, io::Read, path::Path};
 use zip::read::read_zipfile_from_stream;
@@ -86,3 +87,71 @@ pub fn extract_conda(reader: impl Read, destination: &Path) -> Result<ExtractRes
 
     Ok(ExtractResult { sha256, md5 })
 }
+
+/// Extracts the contents a `.tar.bz2` package archive while applying the given safety `policy` to
+/// every entry. Use this function instead of [`extract_tar_bz2`] when extracting packages from
+/// untrusted sources. See [`crate::sanitize`] for more information.
+pub fn extract_tar_bz2_with_policy(
+    reader: impl Read,
+    destination: &Path,
+    policy: &ExtractionPolicy,
+) -> Result<(ExtractResult, SanitizationReport), ExtractError> {
+    let sha256_reader = rattler_digest::HashingReader::<_, rattler_digest::Sha256>::new(reader);
+    let mut md5_reader =
+        rattler_digest::HashingReader::<_, rattler_digest::Md5>::new(sha256_reader);
+
+    let report = unpack_sanitized(stream_tar_bz2(&mut md5_reader), destination, policy)?;
+
+    // Read the file to the end to make sure the hash is properly computed.
+    std::io::copy(&mut md5_reader, &mut std::io::sink())?;
+
+    let (sha256_reader, md5) = md5_reader.finalize();
+    let (_, sha256) = sha256_reader.finalize();
+
+    Ok((ExtractResult { sha256, md5 }, report))
+}
+
+/// Extracts the contents of a `.conda` package archive while applying the given safety `policy`
+/// to every entry. Use this function instead of [`extract_conda`] when extracting packages from
+/// untrusted sources. See [`crate::sanitize`] for more information.
+pub fn extract_conda_with_policy(
+    reader: impl Read,
+    destination: &Path,
+    policy: &ExtractionPolicy,
+) -> Result<(ExtractResult, SanitizationReport), ExtractError> {
+    std::fs::create_dir_all(destination).map_err(ExtractError::CouldNotCreateDestination)?;
+
+    let sha256_reader = rattler_digest::HashingReader::<_, rattler_digest::Sha256>::new(reader);
+    let mut md5_reader =
+        rattler_digest::HashingReader::<_, rattler_digest::Md5>::new(sha256_reader);
+
+    let mut report = SanitizationReport::default();
+    while let Some(file) = read_zipfile_from_stream(&mut md5_reader)? {
+        // See [`extract_conda`] for why [`ManuallyDrop`] is used here.
+        let mut file = ManuallyDrop::new(file);
+
+        if file
+            .mangled_name()
+            .file_name()
+            .map(OsStr::to_string_lossy)
+            .is_some_and(|file_name| file_name.ends_with(".tar.zst"))
+        {
+            report.extend(unpack_sanitized(
+                stream_tar_zst(&mut *file)?,
+                destination,
+                policy,
+            )?);
+        } else {
+            std::io::copy(&mut *file, &mut std::io::sink())?;
+        }
+
+        let _ = ManuallyDrop::into_inner(file);
+    }
+
+    std::io::copy(&mut md5_reader, &mut std::io::sink())?;
+
+    let (sha256_reader, md5) = md5_reader.finalize();
+    let (_, sha256) = sha256_reader.finalize();
+
+    Ok((ExtractResult { sha256, md5 }, report))
+}
@@ -0,0 +1,500 @@
+//! Functionality to read the metadata of a remote package without downloading the entire archive.
+
+use std::io::{Cursor, Read};
+
+use rattler_conda_types::package::{
+    AboutJson, ArchiveType, IndexJson, PackageFile, RunExportsJson,
+};
+use reqwest::{header, StatusCode};
+use reqwest_middleware::ClientWithMiddleware;
+use url::Url;
+
+use crate::{
+    read::{stream_tar_bz2, stream_tar_zst},
+    seek::stream_conda_info,
+    ExtractError,
+};
+
+/// The number of bytes at the end of a `.conda` archive that are requested first. This contains
+/// the entire central directory of the zip file for all regular packages.
+const TAIL_SIZE: u64 = 8 * 1024;
+
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: &[u8] = b"PK\x05\x06";
+const CENTRAL_DIRECTORY_SIGNATURE: &[u8] = b"PK\x01\x02";
+const LOCAL_FILE_HEADER_SIGNATURE: &[u8] = b"PK\x03\x04";
+
+/// The metadata from the `info` section of a package.
+#[derive(Debug, Clone)]
+pub struct PackageMetadata {
+    /// The `info/index.json` file of the package.
+    pub index_json: IndexJson,
+
+    /// The `info/about.json` file of the package, if it has one.
+    pub about_json: Option<AboutJson>,
+
+    /// The `info/run_exports.json` file of the package, if it has one.
+    pub run_exports_json: Option<RunExportsJson>,
+}
+
+/// Reads the metadata of the package at the given URL.
+///
+/// For `.conda` archives only the parts of the archive that are required are requested with HTTP
+/// range requests: the central directory of the zip file and the `info-*.tar.zst` member, which
+/// is typically a tiny fraction of the package. If the server does not support range requests
+/// the entire archive is downloaded. `.tar.bz2` archives cannot be read partially so they are
+/// always downloaded entirely.
+///
+/// ```rust,no_run
+/// # #[tokio::main]
+/// # async fn main() {
+/// # use reqwest_middleware::ClientWithMiddleware;
+/// use rattler_package_streaming::reqwest::fetch_package_metadata;
+/// let metadata = fetch_package_metadata(
+///     &ClientWithMiddleware::from(reqwest::Client::new()),
+///     "https://conda.anaconda.org/conda-forge/win-64/python-3.11.0-hcf16a7b_0_cpython.conda"
+///         .parse()
+///         .unwrap(),
+/// )
+/// .await
+/// .unwrap();
+/// # }
+/// ```
+pub async fn fetch_package_metadata(
+    client: &ClientWithMiddleware,
+    url: Url,
+) -> Result<PackageMetadata, ExtractError> {
+    match ArchiveType::try_from(url.path()).ok_or(ExtractError::UnsupportedArchiveType)? {
+        ArchiveType::TarBz2 => {
+            let bytes = fetch(client, &url, None).await?.bytes;
+            spawn_blocking(move || read_metadata(stream_tar_bz2(Cursor::new(bytes)))).await
+        }
+        ArchiveType::Conda => fetch_conda_metadata(client, &url).await,
+    }
+}
+
+async fn fetch_conda_metadata(
+    client: &ClientWithMiddleware,
+    url: &Url,
+) -> Result<PackageMetadata, ExtractError> {
+    let tail = fetch(client, url, Some(format!("-{TAIL_SIZE}"))).await?;
+    if tail.offset == 0 {
+        // The response contains the entire archive.
+        let bytes = tail.bytes;
+        return spawn_blocking(move || read_metadata(stream_conda_info(Cursor::new(bytes))?)).await;
+    }
+
+    // Locate the central directory, it is usually contained in the tail.
+    let (directory_offset, directory_size) = end_of_central_directory(&tail.bytes)?;
+    let directory = if directory_offset >= tail.offset {
+        let start = (directory_offset - tail.offset) as usize;
+        tail.bytes
+            .get(start..start + directory_size as usize)
+            .ok_or_else(invalid_archive)?
+            .to_vec()
+    } else {
+        let range = format!(
+            "{directory_offset}-{}",
+            directory_offset + directory_size - 1
+        );
+        fetch(client, url, Some(range)).await?.bytes
+    };
+    let info = find_info_entry(&directory)?;
+
+    // Fetch the local header and the data of the member in a single request. The size of the
+    // extra field of the local header is not known up front, assume it is the same as in the
+    // central directory.
+    let header_size = 30 + info.name_length + info.extra_length;
+    let range = format!(
+        "{}-{}",
+        info.local_header_offset,
+        info.local_header_offset + header_size + info.size - 1
+    );
+    let mut entry = fetch(client, url, Some(range)).await?.bytes;
+    if !entry.starts_with(LOCAL_FILE_HEADER_SIGNATURE) || entry.len() < 30 {
+        return Err(invalid_archive());
+    }
+    let data_start = 30 + u64::from(read_u16(&entry, 26)) + u64::from(read_u16(&entry, 28));
+    let data_range = data_start as usize..(data_start + info.size) as usize;
+    let data = if data_range.end <= entry.len() {
+        entry.drain(data_range).collect()
+    } else {
+        // The local header has a larger extra field than the central directory entry.
+        let start = info.local_header_offset + data_start;
+        let range = format!("{start}-{}", start + info.size - 1);
+        fetch(client, url, Some(range)).await?.bytes
+    };
+
+    spawn_blocking(move || read_metadata(stream_tar_zst(Cursor::new(data))?)).await
+}
+
+/// The bytes of a (partial) response.
+struct Fetched {
+    /// The offset of the first byte in the file.
+    offset: u64,
+
+    /// The bytes of the response.
+    bytes: Vec<u8>,
+}
+
+/// Requests the given range of the file. Servers that do not support range requests respond with
+/// the entire file.
+async fn fetch(
+    client: &ClientWithMiddleware,
+    url: &Url,
+    range: Option<String>,
+) -> Result<Fetched, ExtractError> {
+    let mut request = client.get(url.clone());
+    if let Some(range) = range {
+        request = request.header(header::RANGE, format!("bytes={range}"));
+    }
+    let response = request
+        .send()
+        .await
+        .and_then(|response| {
+            response
+                .error_for_status()
+                .map_err(reqwest_middleware::Error::Reqwest)
+        })
+        .map_err(ExtractError::from)?;
+
+    let offset = if response.status() == StatusCode::PARTIAL_CONTENT {
+        // The header has the form `bytes <start>-<end>/<size>`
+        response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("bytes "))
+            .and_then(|value| value.split_once('-'))
+            .and_then(|(start, _)| start.parse().ok())
+            .ok_or_else(invalid_archive)?
+    } else {
+        0
+    };
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| ExtractError::from(reqwest_middleware::Error::Reqwest(e)))?
+        .into();
+
+    Ok(Fetched { offset, bytes })
+}
+
+/// The location of the `info-*.tar.zst` member of a `.conda` archive.
+struct InfoEntry {
+    local_header_offset: u64,
+    size: u64,
+    name_length: u64,
+    extra_length: u64,
+}
+
+/// Returns the offset and size of the central directory from the end of a zip archive.
+fn end_of_central_directory(tail: &[u8]) -> Result<(u64, u64), ExtractError> {
+    // The record is at least 22 bytes and is followed by a comment of at most 64KiB.
+    let position = tail
+        .windows(END_OF_CENTRAL_DIRECTORY_SIGNATURE.len())
+        .rposition(|window| window == END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+        .filter(|position| position + 22 <= tail.len())
+        .ok_or_else(invalid_archive)?;
+    let size = read_u32(tail, position + 12);
+    let offset = read_u32(tail, position + 16);
+    if offset == u32::MAX || size == u32::MAX {
+        // Zip64 archives are not used for conda packages.
+        return Err(invalid_archive());
+    }
+    Ok((u64::from(offset), u64::from(size)))
+}
+
+/// Finds the `info-*.tar.zst` member in the central directory of a `.conda` archive.
+fn find_info_entry(mut directory: &[u8]) -> Result<InfoEntry, ExtractError> {
+    while directory.len() >= 46 && directory.starts_with(CENTRAL_DIRECTORY_SIGNATURE) {
+        let compression_method = read_u16(directory, 10);
+        let size = read_u32(directory, 20);
+        let name_length = usize::from(read_u16(directory, 28));
+        let extra_length = usize::from(read_u16(directory, 30));
+        let comment_length = usize::from(read_u16(directory, 32));
+        let local_header_offset = read_u32(directory, 42);
+        let name = directory
+            .get(46..46 + name_length)
+            .ok_or_else(invalid_archive)?;
+
+        if name.starts_with(b"info-") && name.ends_with(b".tar.zst") {
+            if compression_method != 0 {
+                return Err(ExtractError::UnsupportedCompressionMethod);
+            }
+            return Ok(InfoEntry {
+                local_header_offset: u64::from(local_header_offset),
+                size: u64::from(size),
+                name_length: name_length as u64,
+                extra_length: extra_length as u64,
+            });
+        }
+
+        directory = directory
+            .get(46 + name_length + extra_length + comment_length..)
+            .ok_or_else(invalid_archive)?;
+    }
+    Err(ExtractError::MissingComponent)
+}
+
+/// Reads the metadata files from the `info` section of a package.
+fn read_metadata(mut archive: tar::Archive<impl Read>) -> Result<PackageMetadata, ExtractError> {
+    fn parse<P: PackageFile>(contents: &str) -> Result<P, ExtractError> {
+        P::from_str(contents)
+            .map_err(|e| ExtractError::ArchiveMemberParseError(P::package_path().to_owned(), e))
+    }
+
+    let mut index_json = None;
+    let mut about_json = None;
+    let mut run_exports_json = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path != IndexJson::package_path()
+            && path != AboutJson::package_path()
+            && path != RunExportsJson::package_path()
+        {
+            continue;
+        }
+
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents)?;
+        if path == IndexJson::package_path() {
+            index_json = Some(parse(&contents)?);
+        } else if path == AboutJson::package_path() {
+            about_json = Some(parse(&contents)?);
+        } else {
+            run_exports_json = Some(parse(&contents)?);
+        }
+
+        if index_json.is_some() && about_json.is_some() && run_exports_json.is_some() {
+            break;
+        }
+    }
+
+    Ok(PackageMetadata {
+        index_json: index_json.ok_or(ExtractError::MissingComponent)?,
+        about_json,
+        run_exports_json,
+    })
+}
+
+async fn spawn_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, ExtractError> + Send + 'static,
+) -> Result<T, ExtractError> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(err) => {
+            if let Ok(reason) = err.try_into_panic() {
+                std::panic::resume_unwind(reason);
+            }
+            Err(ExtractError::Cancelled)
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn invalid_archive() -> ExtractError {
+    ExtractError::ZipError(zip::result::ZipError::InvalidArchive(
+        "invalid central directory",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::IntoFuture,
+        net::SocketAddr,
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{
+        extract::State,
+        http::{header, HeaderMap, StatusCode},
+        response::IntoResponse,
+        Router,
+    };
+    use reqwest_middleware::ClientWithMiddleware;
+    use url::Url;
+
+    use super::fetch_package_metadata;
+    use crate::write::{write_conda_package, write_tar_bz2_package, CompressionLevel};
+
+    struct Package {
+        contents: Vec<u8>,
+        supports_ranges: AtomicBool,
+        bytes_served: AtomicUsize,
+    }
+
+    async fn serve_package(
+        State(package): State<Arc<Package>>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        let len = package.contents.len();
+        let range = headers
+            .get(header::RANGE)
+            .and_then(|range| range.to_str().ok()?.strip_prefix("bytes="))
+            .filter(|_| package.supports_ranges.load(Ordering::SeqCst))
+            .map(|range| match range.split_once('-').unwrap() {
+                ("", suffix) => len.saturating_sub(suffix.parse().unwrap())..len,
+                (start, end) => {
+                    start.parse().unwrap()..(end.parse::<usize>().unwrap() + 1).min(len)
+                }
+            });
+
+        if let Some(range) = range {
+            package
+                .bytes_served
+                .fetch_add(range.len(), Ordering::SeqCst);
+            let content_range = format!("bytes {}-{}/{len}", range.start, range.end - 1);
+            (
+                StatusCode::PARTIAL_CONTENT,
+                [(header::CONTENT_RANGE, content_range)],
+                package.contents[range].to_vec(),
+            )
+                .into_response()
+        } else {
+            package.bytes_served.fetch_add(len, Ordering::SeqCst);
+            package.contents.clone().into_response()
+        }
+    }
+
+    async fn test_server(file_name: &str, package: Arc<Package>) -> Url {
+        let router = Router::new()
+            .route(&format!("/{file_name}"), axum::routing::get(serve_package))
+            .with_state(package);
+
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        format!("http://{}:{}/{file_name}", addr.ip(), addr.port())
+            .parse()
+            .unwrap()
+    }
+
+    /// Writes a package with metadata and a large file that does not compress.
+    fn write_package(dir: &Path, conda: bool) -> Vec<u8> {
+        let files: [(&str, Vec<u8>); 4] = [
+            (
+                "info/index.json",
+                br#"{"name": "foo", "version": "1.0", "build": "h123_0", "build_number": 0, "depends": ["bar >=2"]}"#.to_vec(),
+            ),
+            (
+                "info/about.json",
+                br#"{"summary": "The foo package", "license": "MIT"}"#.to_vec(),
+            ),
+            (
+                "info/run_exports.json",
+                br#"{"weak": ["foo >=1.0,<2"]}"#.to_vec(),
+            ),
+            (
+                "lib/data.bin",
+                std::iter::successors(Some(0x2545_f491_4f6c_dd1d_u64), |x| {
+                    let x = x ^ (x << 13);
+                    let x = x ^ (x >> 7);
+                    Some(x ^ (x << 17))
+                })
+                .flat_map(u64::to_le_bytes)
+                .take(1024 * 1024)
+                .collect(),
+            ),
+        ];
+
+        let mut paths = Vec::new();
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+            paths.push(path);
+        }
+
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        if conda {
+            write_conda_package(
+                &mut buffer,
+                dir,
+                &paths,
+                CompressionLevel::Lowest,
+                None,
+                "foo-1.0-h123_0",
+                None,
+                None,
+            )
+            .unwrap();
+        } else {
+            write_tar_bz2_package(
+                &mut buffer,
+                dir,
+                &paths,
+                CompressionLevel::Lowest,
+                None,
+                None,
+            )
+            .unwrap();
+        }
+        buffer.into_inner()
+    }
+
+    async fn fetch_metadata(conda: bool, supports_ranges: bool) -> usize {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let package = Arc::new(Package {
+            contents: write_package(temp_dir.path(), conda),
+            supports_ranges: AtomicBool::new(supports_ranges),
+            bytes_served: AtomicUsize::new(0),
+        });
+        let file_name = if conda {
+            "foo-1.0-h123_0.conda"
+        } else {
+            "foo-1.0-h123_0.tar.bz2"
+        };
+        let url = test_server(file_name, package.clone()).await;
+
+        let client = ClientWithMiddleware::from(reqwest::Client::new());
+        let metadata = fetch_package_metadata(&client, url).await.unwrap();
+        assert_eq!(metadata.index_json.name.as_normalized(), "foo");
+        assert_eq!(metadata.index_json.version.as_str(), "1.0");
+        assert_eq!(metadata.index_json.depends, vec!["bar >=2"]);
+        assert_eq!(
+            metadata.about_json.unwrap().summary.as_deref(),
+            Some("The foo package")
+        );
+        assert_eq!(
+            metadata.run_exports_json.unwrap().weak,
+            vec!["foo >=1.0,<2"]
+        );
+
+        let bytes_served = package.bytes_served.load(Ordering::SeqCst);
+        assert!(bytes_served <= package.contents.len());
+        bytes_served
+    }
+
+    #[tokio::test]
+    async fn test_fetch_conda_metadata() {
+        let bytes_served = fetch_metadata(true, true).await;
+        assert!(
+            bytes_served < 32 * 1024,
+            "{bytes_served} bytes were requested"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_conda_metadata_without_range_requests() {
+        fetch_metadata(true, false).await;
+    }
+
+    #[tokio::test]
+    async fn test_fetch_tar_bz2_metadata() {
+        fetch_metadata(false, true).await;
+    }
+}
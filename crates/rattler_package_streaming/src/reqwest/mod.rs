@@ -1,2 +1,5 @@
 //! Functionality to stream and extract packages directly from a [`reqwest::Url`].
+mod metadata;
 pub mod tokio;
+
+pub use metadata::{fetch_package_metadata, PackageMetadata};
@@ -54,6 +54,20 @@ async fn get_reader(
     }
 }
 
+/// Returns an error if the archive that was extracted does not have the expected hash.
+fn verify_sha256(
+    result: ExtractResult,
+    expected_sha256: Option<Sha256Hash>,
+) -> Result<ExtractResult, ExtractError> {
+    match expected_sha256 {
+        Some(expected) if expected != result.sha256 => Err(ExtractError::HashMismatch {
+            expected,
+            actual: result.sha256,
+        }),
+        _ => Ok(result),
+    }
+}
+
 /// Extracts the contents a `.tar.bz2` package archive from the specified remote location.
 ///
 /// ```rust,no_run
@@ -81,7 +95,8 @@ pub async fn extract_tar_bz2(
 ) -> Result<ExtractResult, ExtractError> {
     let reader = get_reader(url.clone(), client, expected_sha256).await?;
     // The `response` is used to stream in the package data
-    crate::tokio::async_read::extract_tar_bz2(reader, destination).await
+    let result = crate::tokio::async_read::extract_tar_bz2(reader, destination).await?;
+    verify_sha256(result, expected_sha256)
 }
 
 /// Extracts the contents a `.conda` package archive from the specified remote location.
@@ -111,7 +126,8 @@ pub async fn extract_conda(
 ) -> Result<ExtractResult, ExtractError> {
     // The `response` is used to stream in the package data
     let reader = get_reader(url.clone(), client, expected_sha256).await?;
-    crate::tokio::async_read::extract_conda(reader, destination).await
+    let result = crate::tokio::async_read::extract_conda(reader, destination).await?;
+    verify_sha256(result, expected_sha256)
 }
 
 /// Extracts the contents a package archive from the specified remote location. The type of package
@@ -0,0 +1,691 @@
+//! A safety layer for extracting untrusted package archives.
+//!
+//! The functions in [`crate::read`] rely on the safety checks of the `tar` crate. When extracting
+//! packages from untrusted sources more control is often required. The [`unpack_sanitized`]
+//! function extracts a tar archive according to an [`ExtractionPolicy`] which describes how to
+//! deal with absolute paths, `..` traversal, symbolic and hard links that point outside of the
+//! destination, special files (like devices and FIFOs) and setuid/setgid bits. Every entry that
+//! was sanitized is recorded in a [`SanitizationReport`].
+
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use crate::ExtractError;
+
+/// Describes what to do with an archive entry that violates the [`ExtractionPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SafetyAction {
+    /// Make the entry safe and continue extracting. What this means depends on the issue, see
+    /// [`SafetyIssue`].
+    #[default]
+    Sanitize,
+
+    /// Abort the extraction with an [`ExtractError::UnsafeArchiveEntry`] error.
+    Reject,
+}
+
+/// Describes how to handle potentially unsafe entries when extracting an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExtractionPolicy {
+    /// What to do with entries that have an absolute path.
+    pub absolute_paths: SafetyAction,
+
+    /// What to do with entries whose path contains `..` components.
+    pub parent_traversal: SafetyAction,
+
+    /// What to do with symbolic links and hard links that point outside of the destination.
+    pub escaping_links: SafetyAction,
+
+    /// What to do with device files, FIFOs and other special files.
+    pub special_files: SafetyAction,
+
+    /// What to do with entries that have the setuid, setgid or sticky bit set.
+    pub setuid_bits: SafetyAction,
+}
+
+impl ExtractionPolicy {
+    /// Returns a policy that sanitizes all unsafe entries. This is the default.
+    pub fn sanitize() -> Self {
+        Self::default()
+    }
+
+    /// Returns a policy that rejects all unsafe entries.
+    pub fn strict() -> Self {
+        Self {
+            absolute_paths: SafetyAction::Reject,
+            parent_traversal: SafetyAction::Reject,
+            escaping_links: SafetyAction::Reject,
+            special_files: SafetyAction::Reject,
+            setuid_bits: SafetyAction::Reject,
+        }
+    }
+}
+
+/// A potentially unsafe property of an archive entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SafetyIssue {
+    /// The path of the entry is absolute. Sanitizing strips the root (and prefix) of the path.
+    AbsolutePath,
+
+    /// The path of the entry contains `..` components. Sanitizing resolves the components
+    /// lexically and never goes above the destination.
+    ParentTraversal,
+
+    /// The entry is a symbolic link or hard link that points outside of the destination.
+    /// Sanitizing skips the entry.
+    EscapingLink(PathBuf),
+
+    /// The entry is a device file, FIFO or another special file. Sanitizing skips the entry.
+    SpecialFile,
+
+    /// The entry has the setuid, setgid or sticky bit set. Sanitizing clears the bits.
+    SetuidBits(u32),
+}
+
+impl Display for SafetyIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SafetyIssue::AbsolutePath => write!(f, "the path is absolute"),
+            SafetyIssue::ParentTraversal => write!(f, "the path contains '..' components"),
+            SafetyIssue::EscapingLink(target) => write!(
+                f,
+                "the link target '{}' is outside of the destination",
+                target.display()
+            ),
+            SafetyIssue::SpecialFile => write!(f, "the entry is a special file"),
+            SafetyIssue::SetuidBits(mode) => {
+                write!(f, "the entry has setuid/setgid/sticky bits set ({mode:o})")
+            }
+        }
+    }
+}
+
+/// An entry that was sanitized during extraction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SanitizedEntry {
+    /// The path of the entry as it was stored in the archive.
+    pub path: PathBuf,
+
+    /// The issue that was found.
+    pub issue: SafetyIssue,
+
+    /// Whether the entry was skipped entirely.
+    pub skipped: bool,
+}
+
+/// An audit report of all entries that were sanitized during extraction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SanitizationReport {
+    /// All sanitized entries in the order they appeared in the archive.
+    pub entries: Vec<SanitizedEntry>,
+}
+
+impl SanitizationReport {
+    /// Returns true if no entry had to be sanitized.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Merges the entries of another report into this one.
+    pub fn extend(&mut self, other: SanitizationReport) {
+        self.entries.extend(other.entries);
+    }
+}
+
+/// Extracts all entries of the tar `archive` into `destination` according to `policy`.
+pub fn unpack_sanitized(
+    mut archive: tar::Archive<impl Read>,
+    destination: &Path,
+    policy: &ExtractionPolicy,
+) -> Result<SanitizationReport, ExtractError> {
+    let mut report = SanitizationReport::default();
+    std::fs::create_dir_all(destination).map_err(ExtractError::CouldNotCreateDestination)?;
+    let canonical_destination = std::fs::canonicalize(destination)?;
+
+    // Directories are unpacked with their permissions set at the end to make sure read-only
+    // directories do not prevent other files from being unpacked.
+    let mut directories = Vec::new();
+
+    // Symbolic links are checked again once all entries are unpacked, because a link that was
+    // extracted later can change where an earlier link resolves to.
+    let mut symlinks = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let original_path = entry.path()?.into_owned();
+        let mut check = |issue: SafetyIssue, action: SafetyAction, skipped: bool| match action {
+            SafetyAction::Reject => Err(ExtractError::UnsafeArchiveEntry(
+                original_path.clone(),
+                issue,
+            )),
+            SafetyAction::Sanitize => {
+                report.entries.push(SanitizedEntry {
+                    path: original_path.clone(),
+                    issue,
+                    skipped,
+                });
+                Ok(())
+            }
+        };
+
+        // Sanitize the path of the entry itself.
+        if original_path.has_root()
+            || matches!(
+                original_path.components().next(),
+                Some(Component::Prefix(_))
+            )
+        {
+            check(SafetyIssue::AbsolutePath, policy.absolute_paths, false)?;
+        }
+        if original_path
+            .components()
+            .any(|c| matches!(c, Component::ParentDir))
+        {
+            check(SafetyIssue::ParentTraversal, policy.parent_traversal, false)?;
+        }
+        let relative_path = normalize_lexically(&original_path);
+        if relative_path.as_os_str().is_empty() {
+            continue;
+        }
+        let target_path = destination.join(&relative_path);
+
+        // Check the type of the entry.
+        let entry_type = entry.header().entry_type();
+        if !(entry_type.is_file()
+            || entry_type.is_dir()
+            || entry_type.is_symlink()
+            || entry_type.is_hard_link()
+            || entry_type.is_contiguous())
+        {
+            if entry_type.is_pax_global_extensions()
+                || entry_type.is_pax_local_extensions()
+                || entry_type.is_gnu_longname()
+                || entry_type.is_gnu_longlink()
+            {
+                continue;
+            }
+            check(SafetyIssue::SpecialFile, policy.special_files, true)?;
+            continue;
+        }
+
+        // Check the permission bits.
+        let mode = entry.header().mode().unwrap_or(0o644);
+        if mode & 0o7000 != 0 {
+            check(SafetyIssue::SetuidBits(mode), policy.setuid_bits, false)?;
+        }
+
+        // Make sure the directory that contains the entry (or the entry itself if it is a
+        // directory) exists and is inside the destination. Components could be symbolic links that
+        // were extracted earlier so they are checked one by one before anything is created.
+        let directory = if entry_type.is_dir() {
+            Some(relative_path.as_path())
+        } else {
+            relative_path.parent()
+        };
+        if let Some(escaping) = directory
+            .map(|directory| create_dir_inside(destination, &canonical_destination, directory))
+            .transpose()?
+            .flatten()
+        {
+            check(
+                SafetyIssue::EscapingLink(escaping),
+                policy.escaping_links,
+                true,
+            )?;
+            continue;
+        }
+
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let link_name = entry
+                .link_name()?
+                .map(std::borrow::Cow::into_owned)
+                .unwrap_or_default();
+
+            // Symbolic links are relative to the directory that contains the link, hard links are
+            // relative to the root of the archive.
+            let resolved = if entry_type.is_symlink() {
+                relative_path
+                    .parent()
+                    .map_or_else(|| link_name.clone(), |parent| parent.join(&link_name))
+            } else {
+                link_name.clone()
+            };
+            if link_name.has_root() || escapes_root(&resolved) {
+                check(
+                    SafetyIssue::EscapingLink(link_name),
+                    policy.escaping_links,
+                    true,
+                )?;
+                continue;
+            }
+
+            if entry_type.is_hard_link() {
+                // The source could be reached through symbolic links, so the file that is
+                // actually linked is determined on disk. Sources that do not exist, e.g. because
+                // they were skipped, cannot be verified and are treated the same way.
+                let Some(source) =
+                    std::fs::canonicalize(destination.join(normalize_lexically(&resolved)))
+                        .ok()
+                        .filter(|source| source.starts_with(&canonical_destination))
+                else {
+                    check(
+                        SafetyIssue::EscapingLink(link_name),
+                        policy.escaping_links,
+                        true,
+                    )?;
+                    continue;
+                };
+                remove_existing(&target_path)?;
+                std::fs::hard_link(source, &target_path)?;
+                continue;
+            }
+
+            // The directory that contains the link could itself be reached through symbolic
+            // links, so the target is resolved starting from its location on disk.
+            let parent = target_path.parent().unwrap_or(destination);
+            if resolve_link_target(parent, &link_name, &canonical_destination, 0).is_none() {
+                check(
+                    SafetyIssue::EscapingLink(link_name),
+                    policy.escaping_links,
+                    true,
+                )?;
+                continue;
+            }
+            symlinks.push((original_path.clone(), link_name, target_path.clone()));
+        }
+
+        // The setuid bits are stripped by the `tar` crate unless permissions are preserved.
+        entry.set_preserve_permissions(false);
+        if entry_type.is_dir() {
+            directories.push(entry.header().mode().ok().map(|mode| (target_path, mode)));
+        } else {
+            remove_existing(&target_path)?;
+            entry.unpack(&target_path)?;
+        }
+    }
+
+    for (original_path, link_name, path) in symlinks {
+        let escapes = match std::fs::read_link(&path) {
+            Ok(target) => resolve_link_target(
+                path.parent().unwrap_or(destination),
+                &target,
+                &canonical_destination,
+                0,
+            )
+            .is_none(),
+            // The link was replaced by a later entry.
+            Err(_) => false,
+        };
+        if escapes {
+            let issue = SafetyIssue::EscapingLink(link_name);
+            if policy.escaping_links == SafetyAction::Reject {
+                return Err(ExtractError::UnsafeArchiveEntry(original_path, issue));
+            }
+            std::fs::remove_file(&path)?;
+            report.entries.push(SanitizedEntry {
+                path: original_path,
+                issue,
+                skipped: true,
+            });
+        }
+    }
+
+    // A directory could have been replaced by a symbolic link by a later entry. The permissions
+    // are only applied to actual directories to never modify the target of a link.
+    #[cfg(unix)]
+    for (path, mode) in directories.into_iter().flatten() {
+        use std::os::unix::fs::PermissionsExt;
+        if std::fs::symlink_metadata(&path)?.is_dir() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))?;
+        }
+    }
+    #[cfg(not(unix))]
+    drop(directories);
+
+    Ok(report)
+}
+
+/// Creates the directory `relative_path` inside `destination` one component at a time. Existing
+/// components that are symbolic links are only followed if they resolve to a location inside the
+/// destination. Returns the first component that resolves outside of the destination, in which case
+/// nothing below it is created.
+fn create_dir_inside(
+    destination: &Path,
+    canonical_destination: &Path,
+    relative_path: &Path,
+) -> std::io::Result<Option<PathBuf>> {
+    let mut path = destination.to_path_buf();
+    for component in relative_path.components() {
+        path.push(component);
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_symlink() => {
+                let inside = std::fs::canonicalize(&path)
+                    .is_ok_and(|resolved| resolved.starts_with(canonical_destination));
+                if !inside {
+                    return Ok(Some(path));
+                }
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => std::fs::create_dir(&path)?,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// The maximum number of symbolic links that are followed while resolving a link target.
+const MAX_LINK_DEPTH: usize = 40;
+
+/// Resolves the target of a symbolic link located in the directory `parent` on disk. Symbolic
+/// links in the target that already exist are followed, components that do not exist are resolved
+/// lexically. Returns `None` if the resolution leaves `root`, which must be canonical, at any point
+/// or if too many links are followed.
+fn resolve_link_target(parent: &Path, target: &Path, root: &Path, depth: usize) -> Option<PathBuf> {
+    if depth > MAX_LINK_DEPTH || target.has_root() {
+        return None;
+    }
+    let mut resolved = std::fs::canonicalize(parent).ok()?;
+    if !resolved.starts_with(root) {
+        return None;
+    }
+    for component in target.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                resolved.push(name);
+                if let Ok(target) = std::fs::read_link(&resolved) {
+                    let parent = resolved.parent()?.to_path_buf();
+                    resolved = resolve_link_target(&parent, &target, root, depth + 1)?;
+                }
+            }
+            Component::Prefix(_) | Component::RootDir => return None,
+        }
+        if !resolved.starts_with(root) {
+            return None;
+        }
+    }
+    Some(resolved)
+}
+
+/// Removes an existing file or symlink at the given path so it can be replaced.
+fn remove_existing(path: &Path) -> std::io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+/// Lexically normalizes a path by removing the root, `.` components and resolving `..`
+/// components. The resulting path never goes above its root.
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => result.push(part),
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+    result
+}
+
+/// Returns true if resolving the `..` components of the relative `path` goes above its root.
+pub(crate) fn escapes_root(path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir => {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            Component::Prefix(_) | Component::RootDir => return true,
+            Component::CurDir => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_archive(build: impl FnOnce(&mut tar::Builder<Vec<u8>>)) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        build(&mut builder);
+        builder.into_inner().unwrap()
+    }
+
+    fn header(entry_type: tar::EntryType, mode: u32, size: u64) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(size);
+        header
+    }
+
+    /// Writes the path directly into the header to bypass the validation of the `tar` crate.
+    fn set_raw_path(header: &mut tar::Header, path: &str) {
+        let name = &mut header.as_old_mut().name;
+        name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+    }
+
+    fn unsafe_archive() -> Vec<u8> {
+        build_archive(|builder| {
+            let mut h = header(tar::EntryType::Regular, 0o644, 3);
+            set_raw_path(&mut h, "/absolute.txt");
+            builder.append(&h, &b"abc"[..]).unwrap();
+
+            let mut h = header(tar::EntryType::Regular, 0o644, 3);
+            set_raw_path(&mut h, "a/../../escape.txt");
+            builder.append(&h, &b"abc"[..]).unwrap();
+
+            let mut h = header(tar::EntryType::Symlink, 0o777, 0);
+            builder
+                .append_link(&mut h, "link", "../../etc/passwd")
+                .unwrap();
+
+            let mut h = header(tar::EntryType::Regular, 0o4755, 3);
+            builder.append_data(&mut h, "setuid", &b"abc"[..]).unwrap();
+
+            let mut h = header(tar::EntryType::Char, 0o644, 0);
+            builder.append_data(&mut h, "device", &b""[..]).unwrap();
+
+            let mut h = header(tar::EntryType::Regular, 0o644, 3);
+            builder.append_data(&mut h, "ok.txt", &b"abc"[..]).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("dst");
+        let archive = tar::Archive::new(std::io::Cursor::new(unsafe_archive()));
+        let report =
+            unpack_sanitized(archive, &destination, &ExtractionPolicy::sanitize()).unwrap();
+
+        let issues = report
+            .entries
+            .iter()
+            .map(|e| (e.issue.clone(), e.skipped))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            issues,
+            vec![
+                (SafetyIssue::AbsolutePath, false),
+                (SafetyIssue::ParentTraversal, false),
+                (
+                    SafetyIssue::EscapingLink(PathBuf::from("../../etc/passwd")),
+                    true
+                ),
+                (SafetyIssue::SetuidBits(0o4755), false),
+                (SafetyIssue::SpecialFile, true),
+            ]
+        );
+
+        assert!(destination.join("absolute.txt").is_file());
+        assert!(destination.join("escape.txt").is_file());
+        assert!(!dir.path().join("escape.txt").exists());
+        assert!(std::fs::symlink_metadata(destination.join("link")).is_err());
+        assert!(!destination.join("device").exists());
+        assert!(destination.join("ok.txt").is_file());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(destination.join("setuid"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o7777, 0o755);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_parent() {
+        // The second link looks harmless lexically, but because its parent is a link to the
+        // destination itself it resolves to the parent of the destination.
+        let archive = build_archive(|builder| {
+            let mut h = header(tar::EntryType::Symlink, 0o777, 0);
+            builder.append_link(&mut h, "x", ".").unwrap();
+
+            let mut h = header(tar::EntryType::Symlink, 0o777, 0);
+            builder.append_link(&mut h, "x/y", "..").unwrap();
+
+            let mut h = header(tar::EntryType::Regular, 0o644, 3);
+            builder
+                .append_data(&mut h, "x/y/evil/file.txt", &b"abc"[..])
+                .unwrap();
+
+            let mut h = header(tar::EntryType::Directory, 0o700, 0);
+            builder
+                .append_data(&mut h, "x/y/evil-dir", &b""[..])
+                .unwrap();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("dst");
+        let report = unpack_sanitized(
+            tar::Archive::new(std::io::Cursor::new(archive)),
+            &destination,
+            &ExtractionPolicy::sanitize(),
+        )
+        .unwrap();
+
+        // The second link is rejected, so the other entries end up inside the destination.
+        assert_eq!(
+            report.entries,
+            vec![SanitizedEntry {
+                path: PathBuf::from("x/y"),
+                issue: SafetyIssue::EscapingLink(PathBuf::from("..")),
+                skipped: true,
+            }]
+        );
+        assert!(!dir.path().join("evil").exists());
+        assert!(!dir.path().join("evil-dir").exists());
+        assert!(destination.join("y/evil/file.txt").is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hard_link_through_symlinks() {
+        // `l1/l2` resolves lexically to the destination but on disk to its parent, the hard link
+        // would then link a file from outside of the destination into the package.
+        let archive = build_archive(|builder| {
+            let mut h = header(tar::EntryType::Symlink, 0o777, 0);
+            builder.append_link(&mut h, "l1", ".").unwrap();
+
+            let mut h = header(tar::EntryType::Symlink, 0o777, 0);
+            builder.append_link(&mut h, "l1/l2", "..").unwrap();
+
+            let mut h = header(tar::EntryType::Link, 0o644, 0);
+            builder.append_link(&mut h, "x", "l2/secret").unwrap();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("secret"), "secret").unwrap();
+        let destination = dir.path().join("dst");
+        let report = unpack_sanitized(
+            tar::Archive::new(std::io::Cursor::new(archive.clone())),
+            &destination,
+            &ExtractionPolicy::sanitize(),
+        )
+        .unwrap();
+
+        assert!(!destination.join("x").exists());
+        assert!(std::fs::symlink_metadata(destination.join("l2")).is_err());
+        assert_eq!(report.entries.len(), 2);
+        assert!(report
+            .entries
+            .iter()
+            .all(|entry| entry.skipped && matches!(entry.issue, SafetyIssue::EscapingLink(_))));
+
+        // Even if the intermediate link was in place, the hard link must be rejected.
+        let destination = dir.path().join("dst2");
+        std::fs::create_dir_all(&destination).unwrap();
+        std::os::unix::fs::symlink("..", destination.join("l2")).unwrap();
+        let archive = build_archive(|builder| {
+            let mut h = header(tar::EntryType::Link, 0o644, 0);
+            builder.append_link(&mut h, "x", "l2/secret").unwrap();
+        });
+        let err = unpack_sanitized(
+            tar::Archive::new(std::io::Cursor::new(archive)),
+            &destination,
+            &ExtractionPolicy::strict(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ExtractError::UnsafeArchiveEntry(_, SafetyIssue::EscapingLink(_))
+        ));
+        assert!(!destination.join("x").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_changed_by_later_entry() {
+        // `a` looks harmless when it is extracted because `b` does not exist yet, but once `b`
+        // points to the destination itself `a` resolves to its parent.
+        let archive = build_archive(|builder| {
+            let mut h = header(tar::EntryType::Symlink, 0o777, 0);
+            builder.append_link(&mut h, "a", "b/..").unwrap();
+
+            let mut h = header(tar::EntryType::Symlink, 0o777, 0);
+            builder.append_link(&mut h, "b", ".").unwrap();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("dst");
+        let report = unpack_sanitized(
+            tar::Archive::new(std::io::Cursor::new(archive)),
+            &destination,
+            &ExtractionPolicy::sanitize(),
+        )
+        .unwrap();
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!(report.entries[0].path, PathBuf::from("a"));
+        assert!(std::fs::symlink_metadata(destination.join("a")).is_err());
+        assert!(std::fs::symlink_metadata(destination.join("b")).is_ok());
+    }
+
+    #[test]
+    fn test_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tar::Archive::new(std::io::Cursor::new(unsafe_archive()));
+        let err = unpack_sanitized(archive, dir.path(), &ExtractionPolicy::strict()).unwrap_err();
+        assert!(matches!(
+            err,
+            ExtractError::UnsafeArchiveEntry(_, SafetyIssue::AbsolutePath)
+        ));
+    }
+}
@@ -0,0 +1,638 @@
+//! Functionality to sign packages and to verify package signatures, modelled after
+//! [conda content trust](https://github.com/conda/conda-content-trust).
+//!
+//! The `info/index.json` and `info/paths.json` files of a package are signed with one or more
+//! ed25519 keys. The signatures are stored in `info/signatures.json` as a mapping from the hex
+//! encoded public key to the hex encoded signature of the canonical JSON representation of both
+//! files. Because `info/paths.json` records the sha256 hash of every file in the package, the
+//! signature covers the entire contents of the package: [`verify_package_directory`] also checks
+//! that the files on disk match these hashes. Signing is done with [`sign_package_directory`]
+//! before the package archive is created.
+//!
+//! Which keys are allowed to sign packages is determined by a chain of delegations. A trusted
+//! `root.json` delegates to the keys of the `key_mgr` role, whose `key_mgr.json` (hosted on the
+//! channel) in turn delegates to the keys of the `pkg_mgr` role. [`TrustedKeys::from_delegations`]
+//! verifies this chain and returns the keys that are allowed to sign packages, which can then be
+//! used with [`verify_package_directory`].
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signer, Verifier};
+use rattler_conda_types::package::{PackageFile, PathType, PathsEntry, PathsJson};
+use rattler_digest::compute_file_digest;
+use serde::{Deserialize, Serialize};
+
+use crate::sanitize::escapes_root;
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// The path of the file that contains the signatures of a package.
+pub const SIGNATURES_PATH: &str = "info/signatures.json";
+
+/// The paths of the files that are signed.
+pub const SIGNED_PATHS: [&str; 2] = ["info/index.json", "info/paths.json"];
+
+/// An error that can occur when signing or verifying signatures.
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum SignatureError {
+    #[error("an io error occurred")]
+    IoError(#[from] std::io::Error),
+
+    #[error("failed to parse '{0}'")]
+    ParseError(String, #[source] serde_json::Error),
+
+    #[error("the package is not signed")]
+    MissingSignatures,
+
+    #[error("invalid public key '{0}'")]
+    InvalidPublicKey(String),
+
+    #[error("expected metadata of type '{expected}' but found '{found}'")]
+    UnexpectedRole { expected: String, found: String },
+
+    #[error("the metadata for the '{0}' role has expired")]
+    Expired(String),
+
+    #[error("the '{0}' role has not been delegated")]
+    MissingDelegation(String),
+
+    #[error("only {found} valid signature(s) were found but at least {threshold} are required")]
+    ThresholdNotMet { found: usize, threshold: usize },
+
+    #[error("the contents of '{0}' do not match the signed hash")]
+    ContentMismatch(PathBuf),
+
+    #[cfg(feature = "reqwest")]
+    #[error(transparent)]
+    ReqwestError(::reqwest_middleware::Error),
+}
+
+#[cfg(feature = "reqwest")]
+impl From<::reqwest_middleware::Error> for SignatureError {
+    fn from(err: ::reqwest_middleware::Error) -> Self {
+        use rattler_networking::Redact;
+        SignatureError::ReqwestError(err.redact())
+    }
+}
+
+/// A single signature.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Signature {
+    /// The hex encoded ed25519 signature.
+    pub signature: String,
+}
+
+/// A mapping from hex encoded public keys to the signature made with the corresponding private
+/// key.
+pub type Signatures = BTreeMap<String, Signature>;
+
+/// A set of public keys and the number of keys that need to sign a piece of metadata for it to be
+/// trusted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Delegation {
+    /// The hex encoded public keys.
+    pub pubkeys: Vec<String>,
+
+    /// The minimum number of valid signatures.
+    pub threshold: usize,
+}
+
+/// Signed role metadata like `root.json` or `key_mgr.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedMetadata {
+    /// The signatures of the `signed` field.
+    pub signatures: Signatures,
+
+    /// The metadata that is signed. This is kept as a raw JSON value because the signatures are
+    /// computed over the exact contents. Use [`SignedMetadata::role_metadata`] to interpret it.
+    pub signed: serde_json::Value,
+}
+
+/// The contents of role metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleMetadata {
+    /// The role this metadata describes (e.g. `root` or `key_mgr`).
+    #[serde(rename = "type")]
+    pub role: String,
+
+    /// The version of the metadata.
+    pub version: u64,
+
+    /// After this moment the metadata is no longer valid.
+    pub expiration: Option<DateTime<Utc>>,
+
+    /// The roles that this role delegates trust to.
+    #[serde(default)]
+    pub delegations: BTreeMap<String, Delegation>,
+}
+
+impl SignedMetadata {
+    /// Parses signed metadata from a JSON string.
+    pub fn from_json_str(str: &str) -> Result<Self, SignatureError> {
+        serde_json::from_str(str)
+            .map_err(|e| SignatureError::ParseError(String::from("role metadata"), e))
+    }
+
+    /// Reads signed metadata from a file.
+    pub fn from_path(path: &Path) -> Result<Self, SignatureError> {
+        serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| SignatureError::ParseError(path.display().to_string(), e))
+    }
+
+    /// Interprets the signed metadata.
+    pub fn role_metadata(&self) -> Result<RoleMetadata, SignatureError> {
+        serde_json::from_value(self.signed.clone())
+            .map_err(|e| SignatureError::ParseError(String::from("role metadata"), e))
+    }
+
+    /// Verifies that this is valid metadata for the given `role` signed by the keys in
+    /// `delegation`.
+    pub fn verify(&self, role: &str, delegation: &Delegation) -> Result<(), SignatureError> {
+        let metadata = self.role_metadata()?;
+        if metadata.role != role {
+            return Err(SignatureError::UnexpectedRole {
+                expected: role.to_owned(),
+                found: metadata.role,
+            });
+        }
+
+        if metadata
+            .expiration
+            .is_some_and(|expiration| expiration < Utc::now())
+        {
+            return Err(SignatureError::Expired(role.to_owned()));
+        }
+
+        TrustedKeys::from_delegation(delegation)?
+            .verify(&canonical_json(&self.signed), &self.signatures)
+    }
+
+    /// Returns the delegation to the specified role.
+    pub fn delegation(&self, role: &str) -> Result<Delegation, SignatureError> {
+        self.role_metadata()?
+            .delegations
+            .remove(role)
+            .ok_or_else(|| SignatureError::MissingDelegation(role.to_owned()))
+    }
+}
+
+/// The keys that are trusted to sign packages.
+#[derive(Debug, Clone)]
+pub struct TrustedKeys {
+    keys: BTreeMap<String, VerifyingKey>,
+    threshold: usize,
+}
+
+impl TrustedKeys {
+    /// Constructs a new instance from a set of keys that are trusted directly and the number of
+    /// keys that need to sign a package.
+    pub fn new(keys: impl IntoIterator<Item = VerifyingKey>, threshold: usize) -> Self {
+        Self {
+            keys: keys
+                .into_iter()
+                .map(|key| (hex::encode(key.as_bytes()), key))
+                .collect(),
+            threshold,
+        }
+    }
+
+    /// Constructs a new instance from the keys in a delegation.
+    pub fn from_delegation(delegation: &Delegation) -> Result<Self, SignatureError> {
+        let keys = delegation
+            .pubkeys
+            .iter()
+            .map(|key| parse_verifying_key(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(keys, delegation.threshold))
+    }
+
+    /// Verifies the chain of delegations from the trusted `root` metadata via the `key_mgr`
+    /// metadata and returns the keys that are trusted to sign packages (the `pkg_mgr` role).
+    pub fn from_delegations(
+        root: &SignedMetadata,
+        key_mgr: &SignedMetadata,
+    ) -> Result<Self, SignatureError> {
+        root.verify("root", &root.delegation("root")?)?;
+        key_mgr.verify("key_mgr", &root.delegation("key_mgr")?)?;
+        Self::from_delegation(&key_mgr.delegation("pkg_mgr")?)
+    }
+
+    /// Fetches the `key_mgr.json` file from the channel at `channel_url` and verifies the chain of
+    /// delegations starting at the trusted `root` metadata. See [`Self::from_delegations`].
+    #[cfg(feature = "reqwest")]
+    pub async fn fetch(
+        client: reqwest_middleware::ClientWithMiddleware,
+        channel_url: &url::Url,
+        root: &SignedMetadata,
+    ) -> Result<Self, SignatureError> {
+        let url = channel_url
+            .join("key_mgr.json")
+            .expect("key_mgr.json is a valid relative url");
+        let key_mgr = client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()
+            .map_err(reqwest_middleware::Error::Reqwest)?
+            .text()
+            .await
+            .map_err(reqwest_middleware::Error::Reqwest)?;
+        Self::from_delegations(root, &SignedMetadata::from_json_str(&key_mgr)?)
+    }
+
+    /// Verifies that at least `threshold` of the trusted keys signed `payload`. Signatures from
+    /// unknown keys are ignored.
+    pub fn verify(&self, payload: &[u8], signatures: &Signatures) -> Result<(), SignatureError> {
+        let found = signatures
+            .iter()
+            .filter(|(key, signature)| {
+                let Some(key) = self.keys.get(key.as_str()) else {
+                    return false;
+                };
+                let Some(signature) = hex::decode(&signature.signature)
+                    .ok()
+                    .and_then(|bytes| ed25519_dalek::Signature::from_slice(&bytes).ok())
+                else {
+                    return false;
+                };
+                key.verify(payload, &signature).is_ok()
+            })
+            .count();
+
+        if found >= self.threshold && found > 0 {
+            Ok(())
+        } else {
+            Err(SignatureError::ThresholdNotMet {
+                found,
+                threshold: self.threshold,
+            })
+        }
+    }
+}
+
+fn parse_verifying_key(key: &str) -> Result<VerifyingKey, SignatureError> {
+    hex::decode(key)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| SignatureError::InvalidPublicKey(key.to_owned()))
+}
+
+/// Serializes a JSON value in the canonical form that is used to compute signatures: keys are
+/// sorted and the output is indented with two spaces.
+pub fn canonical_json(value: &serde_json::Value) -> Vec<u8> {
+    fn sort(value: &serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(map) => {
+                let sorted: BTreeMap<_, _> = map.iter().map(|(k, v)| (k, sort(v))).collect();
+                serde_json::Value::Object(sorted.into_iter().map(|(k, v)| (k.clone(), v)).collect())
+            }
+            serde_json::Value::Array(values) => {
+                serde_json::Value::Array(values.iter().map(sort).collect())
+            }
+            value => value.clone(),
+        }
+    }
+
+    serde_json::to_vec_pretty(&sort(value)).expect("serializing a json value cannot fail")
+}
+
+/// Reads the canonical representation of the signed files of the package at `package_dir`. The
+/// payload is a JSON object that maps the path of every signed file to its contents.
+fn read_signed_payload(package_dir: &Path) -> Result<Vec<u8>, SignatureError> {
+    let mut payload = serde_json::Map::new();
+    for path in SIGNED_PATHS {
+        let contents = std::fs::read_to_string(package_dir.join(path))?;
+        let contents: serde_json::Value = serde_json::from_str(&contents)
+            .map_err(|e| SignatureError::ParseError(path.to_owned(), e))?;
+        payload.insert(path.to_owned(), contents);
+    }
+    Ok(canonical_json(&serde_json::Value::Object(payload)))
+}
+
+/// Verifies that the files of the package at `package_dir` match the hashes and sizes recorded in
+/// its `info/paths.json`.
+///
+/// Like `conda-build`, the hash and size of a symbolic link are those of the file it points to, or
+/// those of an empty file if it does not point to a file. Links must stay inside the package.
+fn verify_package_contents(package_dir: &Path) -> Result<(), SignatureError> {
+    let paths_json = PathsJson::from_package_directory(package_dir)?;
+    let canonical_package_dir = package_dir.canonicalize()?;
+    for entry in paths_json.paths {
+        let path = package_dir.join(&entry.relative_path);
+        let matches = match entry.path_type {
+            PathType::HardLink => {
+                let metadata = std::fs::symlink_metadata(&path)?;
+                metadata.is_file()
+                    && entry
+                        .size_in_bytes
+                        .map_or(true, |size| size == metadata.len())
+                    && match entry.sha256 {
+                        Some(expected) => {
+                            compute_file_digest::<rattler_digest::Sha256>(&path)? == expected
+                        }
+                        None => false,
+                    }
+            }
+            PathType::SoftLink => soft_link_matches(package_dir, &canonical_package_dir, &entry)?,
+            PathType::Directory => std::fs::symlink_metadata(&path)?.is_dir(),
+        };
+        if !matches {
+            return Err(SignatureError::ContentMismatch(entry.relative_path));
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the symbolic link of `entry` stays inside the package and points to a file with
+/// the recorded hash and size, or to nothing if the recorded hash is that of an empty file.
+fn soft_link_matches(
+    package_dir: &Path,
+    canonical_package_dir: &Path,
+    entry: &PathsEntry,
+) -> Result<bool, SignatureError> {
+    let Some(expected) = entry.sha256 else {
+        return Ok(false);
+    };
+    let path = package_dir.join(&entry.relative_path);
+    if !std::fs::symlink_metadata(&path)?.is_symlink() {
+        return Ok(false);
+    }
+    let link = std::fs::read_link(&path)?;
+    let parent = entry.relative_path.parent().unwrap_or(Path::new(""));
+    if link.is_absolute() || escapes_root(&parent.join(link)) {
+        return Ok(false);
+    }
+
+    let (size, hash) = match path.canonicalize() {
+        Ok(target) if !target.starts_with(canonical_package_dir) => return Ok(false),
+        Ok(target) if target.is_file() => (
+            std::fs::metadata(&target)?.len(),
+            compute_file_digest::<rattler_digest::Sha256>(&target)?,
+        ),
+        Ok(_) => (0, empty_file_hash()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, empty_file_hash()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(entry
+        .size_in_bytes
+        .map_or(true, |expected| expected == size)
+        && hash == expected)
+}
+
+/// Returns the hash of an empty file, which `conda-build` records for links that do not point to a
+/// file.
+fn empty_file_hash() -> rattler_digest::Sha256Hash {
+    rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>([])
+}
+
+/// Reads the signatures of the package at `package_dir`.
+pub fn read_signatures(package_dir: &Path) -> Result<Signatures, SignatureError> {
+    match std::fs::read_to_string(package_dir.join(SIGNATURES_PATH)) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| SignatureError::ParseError(SIGNATURES_PATH.to_owned(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(SignatureError::MissingSignatures)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Signs `payload` with the given keys and adds the signatures to `signatures`. Existing
+/// signatures from other keys are retained.
+pub fn sign_payload(payload: &[u8], keys: &[SigningKey], signatures: &mut Signatures) {
+    for key in keys {
+        signatures.insert(
+            hex::encode(key.verifying_key().as_bytes()),
+            Signature {
+                signature: hex::encode(key.sign(payload).to_bytes()),
+            },
+        );
+    }
+}
+
+/// Signs the `info/index.json` and `info/paths.json` files of the package contents at `base_path` with the given keys and
+/// writes the signatures to `info/signatures.json`. Existing signatures from other keys are
+/// retained.
+///
+/// This function should be called before the package archive is written. The returned path must
+/// be included in the paths that are passed to the functions in [`crate::write`].
+pub fn sign_package_directory(
+    base_path: &Path,
+    keys: &[SigningKey],
+) -> Result<PathBuf, SignatureError> {
+    let payload = read_signed_payload(base_path)?;
+    let mut signatures = match read_signatures(base_path) {
+        Ok(signatures) => signatures,
+        Err(SignatureError::MissingSignatures) => Signatures::new(),
+        Err(e) => return Err(e),
+    };
+
+    sign_payload(&payload, keys, &mut signatures);
+
+    let path = base_path.join(SIGNATURES_PATH);
+    let contents = serde_json::to_vec_pretty(&signatures)
+        .map_err(|e| SignatureError::ParseError(SIGNATURES_PATH.to_owned(), e))?;
+    std::fs::write(&path, contents)?;
+    Ok(path)
+}
+
+/// Verifies that the metadata of the extracted package at `package_dir` has been signed by the
+/// trusted keys and that the files of the package match the signed metadata.
+pub fn verify_package_directory(
+    package_dir: &Path,
+    trusted_keys: &TrustedKeys,
+) -> Result<(), SignatureError> {
+    let signatures = read_signatures(package_dir)?;
+    let payload = read_signed_payload(package_dir)?;
+    trusted_keys.verify(&payload, &signatures)?;
+    verify_package_contents(package_dir)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn signed(
+        role: &str,
+        delegations: &[(&str, &SigningKey)],
+        signers: &[&SigningKey],
+    ) -> SignedMetadata {
+        let signed = RoleMetadata {
+            role: role.to_owned(),
+            version: 1,
+            expiration: None,
+            delegations: delegations
+                .iter()
+                .map(|(role, key)| {
+                    (
+                        (*role).to_owned(),
+                        Delegation {
+                            pubkeys: vec![hex::encode(key.verifying_key().as_bytes())],
+                            threshold: 1,
+                        },
+                    )
+                })
+                .collect(),
+        };
+        let signed = serde_json::to_value(&signed).unwrap();
+        let payload = canonical_json(&signed);
+        let signatures = signers
+            .iter()
+            .map(|key| {
+                (
+                    hex::encode(key.verifying_key().as_bytes()),
+                    Signature {
+                        signature: hex::encode(key.sign(&payload).to_bytes()),
+                    },
+                )
+            })
+            .collect();
+        SignedMetadata { signatures, signed }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (root_key, key_mgr_key, pkg_mgr_key, other_key) = (key(1), key(2), key(3), key(4));
+        let root = signed(
+            "root",
+            &[("root", &root_key), ("key_mgr", &key_mgr_key)],
+            &[&root_key],
+        );
+        let key_mgr = signed("key_mgr", &[("pkg_mgr", &pkg_mgr_key)], &[&key_mgr_key]);
+        let trusted_keys = TrustedKeys::from_delegations(&root, &key_mgr).unwrap();
+
+        // key_mgr metadata that is not signed by the delegated key is rejected.
+        let forged_key_mgr = signed("key_mgr", &[("pkg_mgr", &other_key)], &[&other_key]);
+        assert!(matches!(
+            TrustedKeys::from_delegations(&root, &forged_key_mgr),
+            Err(SignatureError::ThresholdNotMet { .. })
+        ));
+
+        let package_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(package_dir.path().join("info")).unwrap();
+        std::fs::write(
+            package_dir.path().join("info/index.json"),
+            r#"{"name": "foo", "version": "1.0", "build": "0", "build_number": 0}"#,
+        )
+        .unwrap();
+        std::fs::write(package_dir.path().join("foo.txt"), "foo").unwrap();
+        std::fs::write(
+            package_dir.path().join("info/paths.json"),
+            format!(
+                r#"{{"paths_version": 1, "paths": [{{"_path": "foo.txt", "path_type": "hardlink", "sha256": "{:x}", "size_in_bytes": 3}}]}}"#,
+                rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>("foo")
+            ),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            verify_package_directory(package_dir.path(), &trusted_keys),
+            Err(SignatureError::MissingSignatures)
+        ));
+
+        sign_package_directory(package_dir.path(), &[other_key]).unwrap();
+        assert!(matches!(
+            verify_package_directory(package_dir.path(), &trusted_keys),
+            Err(SignatureError::ThresholdNotMet { found: 0, .. })
+        ));
+
+        sign_package_directory(package_dir.path(), &[pkg_mgr_key]).unwrap();
+        verify_package_directory(package_dir.path(), &trusted_keys).unwrap();
+
+        // Modifying a file of the package is detected through the signed paths.json
+        std::fs::write(package_dir.path().join("foo.txt"), "bar").unwrap();
+        assert!(matches!(
+            verify_package_directory(package_dir.path(), &trusted_keys),
+            Err(SignatureError::ContentMismatch(path)) if path == Path::new("foo.txt")
+        ));
+        std::fs::write(package_dir.path().join("foo.txt"), "foo").unwrap();
+        verify_package_directory(package_dir.path(), &trusted_keys).unwrap();
+
+        // Modifying the metadata invalidates the signature
+        std::fs::write(
+            package_dir.path().join("info/index.json"),
+            r#"{"name": "foo", "version": "2.0", "build": "0", "build_number": 0}"#,
+        )
+        .unwrap();
+        assert!(verify_package_directory(package_dir.path(), &trusted_keys).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_soft_links() {
+        let (root_key, key_mgr_key, pkg_mgr_key) = (key(1), key(2), key(3));
+        let root = signed(
+            "root",
+            &[("root", &root_key), ("key_mgr", &key_mgr_key)],
+            &[&root_key],
+        );
+        let key_mgr = signed("key_mgr", &[("pkg_mgr", &pkg_mgr_key)], &[&key_mgr_key]);
+        let trusted_keys = TrustedKeys::from_delegations(&root, &key_mgr).unwrap();
+
+        let package_dir = tempfile::tempdir().unwrap();
+        let path = |relative_path: &str| package_dir.path().join(relative_path);
+        std::fs::create_dir_all(path("info")).unwrap();
+        std::fs::create_dir_all(path("bin")).unwrap();
+        std::fs::write(
+            path("info/index.json"),
+            r#"{"name": "foo", "version": "1.0", "build": "0", "build_number": 0}"#,
+        )
+        .unwrap();
+        std::fs::write(path("foo.txt"), "foo").unwrap();
+        std::fs::write(path("other.txt"), "other").unwrap();
+        std::os::unix::fs::symlink("../foo.txt", path("bin/foo")).unwrap();
+        std::os::unix::fs::symlink("missing", path("dangling")).unwrap();
+        let hash = |contents: &str| {
+            format!(
+                "{:x}",
+                rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(contents)
+            )
+        };
+        std::fs::write(
+            path("info/paths.json"),
+            format!(
+                r#"{{"paths_version": 1, "paths": [
+                    {{"_path": "foo.txt", "path_type": "hardlink", "sha256": "{foo}", "size_in_bytes": 3}},
+                    {{"_path": "other.txt", "path_type": "hardlink", "sha256": "{other}", "size_in_bytes": 5}},
+                    {{"_path": "bin/foo", "path_type": "softlink", "sha256": "{foo}", "size_in_bytes": 3}},
+                    {{"_path": "dangling", "path_type": "softlink", "sha256": "{empty}", "size_in_bytes": 0}}
+                ]}}"#,
+                foo = hash("foo"),
+                other = hash("other"),
+                empty = hash(""),
+            ),
+        )
+        .unwrap();
+        sign_package_directory(package_dir.path(), &[pkg_mgr_key]).unwrap();
+        verify_package_directory(package_dir.path(), &trusted_keys).unwrap();
+
+        // Pointing a link to another file of the package, or outside of the package, is detected.
+        for target in ["../other.txt", "../../outside", "/etc/passwd"] {
+            std::fs::remove_file(path("bin/foo")).unwrap();
+            std::os::unix::fs::symlink(target, path("bin/foo")).unwrap();
+            assert!(matches!(
+                verify_package_directory(package_dir.path(), &trusted_keys),
+                Err(SignatureError::ContentMismatch(path)) if path == Path::new("bin/foo")
+            ));
+        }
+
+        std::fs::remove_file(path("bin/foo")).unwrap();
+        std::os::unix::fs::symlink("../foo.txt", path("bin/foo")).unwrap();
+        verify_package_directory(package_dir.path(), &trusted_keys).unwrap();
+
+        // A dangling link that is made to point to a file is detected.
+        std::fs::write(path("missing"), "surprise").unwrap();
+        assert!(matches!(
+            verify_package_directory(package_dir.path(), &trusted_keys),
+            Err(SignatureError::ContentMismatch(path)) if path == Path::new("dangling")
+        ));
+    }
+}
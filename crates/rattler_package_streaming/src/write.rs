@@ -66,7 +66,7 @@ impl Read for ProgressBarReader {
 }
 
 /// a function that sorts paths into two iterators, one that starts with `info/` and one that does not
-/// both iterators are sorted alphabetically for reproducibility
+/// both iterators are sorted alphabetically and deduplicated for reproducibility
 fn sort_paths<'a>(paths: &'a [PathBuf], base_path: &'a Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
     let info = Path::new("info/");
     let (mut info_paths, mut other_paths): (Vec<_>, Vec<_>) = paths
@@ -76,7 +76,9 @@ fn sort_paths<'a>(paths: &'a [PathBuf], base_path: &'a Path) -> (Vec<PathBuf>, V
         .partition(|path| path.starts_with(info));
 
     info_paths.sort();
+    info_paths.dedup();
     other_paths.sort();
+    other_paths.dedup();
 
     (info_paths, other_paths)
 }
@@ -241,7 +243,16 @@ fn write_zst_archive<W: Write>(
     let tar_file = File::open(&tar_path)?;
     let compression_level = compression_level.to_zstd_level()?;
     let mut zst_encoder = zstd::Encoder::new(writer, compression_level)?;
-    zst_encoder.multithread(num_threads.unwrap_or_else(|| num_cpus::get() as u32))?;
+
+    // zstd only guarantees identical output across different numbers of workers when at least one
+    // worker is used. Single threaded mode (0 workers) produces a different frame layout, so we
+    // never use it to make sure the output does not depend on the machine the package is built on.
+    let num_threads = num_threads.unwrap_or_else(|| num_cpus::get() as u32).max(1);
+    zst_encoder.multithread(num_threads)?;
+
+    // Pin the parameters that would otherwise depend on the zstd defaults.
+    zst_encoder.include_checksum(false)?;
+    zst_encoder.long_distance_matching(false)?;
 
     progress_bar_wrapper.reset_position();
     if let Ok(tar_total_size) = tar_file.metadata().map(|v| v.len()) {
@@ -271,9 +282,17 @@ fn write_zst_archive<W: Write>(
 /// * `paths` - a list of paths to include in the package
 /// * `compression_level` - the compression level to use for the inner zstd encoded files
 /// * `compression_num_threads` - the number of threads to use for zstd compression (defaults to
-/// the number of CPU cores if `None`)
+/// the number of CPU cores if `None`). The number of threads does not influence the output.
 /// * `timestamp` - optional a timestamp to use for all archive files (useful for reproducible builds)
 ///
+/// # Reproducibility
+///
+/// The output of this function only depends on the contents of the files, their permissions (only
+/// the executable bit is retained), the `compression_level` and the `timestamp`. Writing the same
+/// input tree twice will therefore result in byte-identical packages, regardless of the order of
+/// `paths`, the modification times or ownership of the files on disk, or the number of threads
+/// used for compression.
+///
 /// # Errors
 ///
 /// This function will return an error if the writer returns an error, or if the paths are not
@@ -310,7 +329,8 @@ pub fn write_conda_package<W: Write + Seek>(
 
     let options = zip::write::FileOptions::default()
         .compression_method(zip::CompressionMethod::Stored)
-        .last_modified_time(last_modified_time);
+        .last_modified_time(last_modified_time)
+        .unix_permissions(0o644);
 
     // write the metadata as first file in the zip archive
     let package_metadata = PackageMetadata::default();
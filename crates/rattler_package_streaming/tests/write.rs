@@ -234,3 +234,41 @@ fn test_rewrite_conda() {
         compare_two_conda_archives(&file_path, &new_archive);
     }
 }
+
+#[test]
+fn test_write_conda_reproducible() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let base_path = temp_dir.path().join("package");
+    std::fs::create_dir_all(base_path.join("info")).unwrap();
+    std::fs::create_dir_all(base_path.join("lib")).unwrap();
+    std::fs::write(base_path.join("info/index.json"), r#"{"name": "foo"}"#).unwrap();
+    std::fs::write(base_path.join("lib/a.txt"), "a".repeat(100_000)).unwrap();
+    std::fs::write(base_path.join("lib/b.txt"), "b").unwrap();
+
+    let write = |paths: &[PathBuf], num_threads: u32| {
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        write_conda_package(
+            &mut buffer,
+            &base_path,
+            paths,
+            CompressionLevel::Default,
+            Some(num_threads),
+            "foo",
+            None,
+            None,
+        )
+        .unwrap();
+        buffer.into_inner()
+    };
+
+    let mut paths = find_all_package_files(&base_path);
+    let first = write(&paths, 1);
+
+    // Touch a file, reorder and duplicate the paths and use a different number of threads.
+    std::fs::write(base_path.join("lib/b.txt"), "b").unwrap();
+    paths.reverse();
+    paths.push(paths[0].clone());
+    let second = write(&paths, 4);
+
+    assert!(first == second, "the packages should be byte-identical");
+}
@@ -84,7 +84,7 @@ use blake2::digest::{FixedOutput, Update};
 use rattler_digest::{
     parse_digest_from_hex, serde::SerializableHash, Blake2b256, Blake2b256Hash, Blake2bMac256,
 };
-use rattler_networking::Redact;
+use rattler_networking::{Redact, RedactedUrl};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
     Response, StatusCode,
@@ -493,7 +493,10 @@ async fn fetch_jlap_with_retry(
     client: &ClientWithMiddleware,
     position: u64,
 ) -> Result<(Response, u64), JLAPError> {
-    tracing::info!("fetching JLAP state from {url} (bytes={position}-)");
+    tracing::info!(
+        "fetching JLAP state from {} (bytes={position}-)",
+        RedactedUrl::from(url)
+    );
     let range = format!("bytes={position}-");
 
     match fetch_jlap(url, client, &range).await {
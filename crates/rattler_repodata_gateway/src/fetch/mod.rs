@@ -3,12 +3,14 @@
 use crate::reporter::ResponseReporterExt;
 use crate::utils::{AsyncEncoding, Encoding, LockedFile};
 use crate::Reporter;
-use cache::{CacheHeaders, Expiring, RepoDataState};
+pub(crate) use cache::Expiring;
+use cache::{CacheHeaders, RepoDataState};
 use cache_control::{Cachability, CacheControl};
+use file_url::{to_long_path, url_to_path};
 use futures::{future::ready, FutureExt, TryStreamExt};
 use humansize::{SizeFormatter, DECIMAL};
 use rattler_digest::{compute_file_digest, Blake2b256, HashingWriter};
-use rattler_networking::Redact;
+use rattler_networking::{Redact, RedactedUrl};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
     Response, StatusCode,
@@ -231,7 +233,8 @@ async fn repodata_from_file(
     lock_file: LockedFile,
 ) -> Result<CachedRepoData, FetchRepoDataError> {
     // copy file from subdir_url to out_path
-    if let Err(e) = tokio::fs::copy(&subdir_url.to_file_path().unwrap(), &out_path).await {
+    let source = to_long_path(url_to_path(&subdir_url).expect("must be a file url"));
+    if let Err(e) = tokio::fs::copy(&source, &out_path).await {
         return if e.kind() == ErrorKind::NotFound {
             Err(FetchRepoDataError::NotFound(
                 RepoDataNotFoundError::FileSystemError(e),
@@ -389,11 +392,12 @@ pub async fn fetch_repo_data(
     };
 
     // Determine the availability of variants based on the cache or by querying the remote.
-    let variant_availability = check_variant_availability(
+    let mut variant_availability = check_enabled_variant_availability(
         &client,
         &subdir_url,
         cache_state.as_ref(),
         options.variant.file_name(),
+        &options,
     )
     .await;
 
@@ -452,22 +456,16 @@ pub async fn fetch_repo_data(
         None
     };
 
-    // Determine which variant to download
-    let repo_data_url = if has_zst {
-        subdir_url
-            .join(&format!("{}.zst", options.variant.file_name()))
-            .unwrap()
-    } else if has_bz2 {
-        subdir_url
-            .join(&format!("{}.bz2", options.variant.file_name()))
-            .unwrap()
-    } else {
-        subdir_url.join(options.variant.file_name()).unwrap()
-    };
-
-    // Construct the HTTP request
-    tracing::debug!("fetching '{}'", &repo_data_url);
-    let request_builder = client.get(repo_data_url.clone());
+    // Determine which variants to try, in order of preference. If a compressed variant that was
+    // assumed to be available turns out to be missing, the next variant is tried.
+    let mut encodings = Vec::new();
+    if has_zst {
+        encodings.push(Encoding::Zst);
+    }
+    if has_bz2 {
+        encodings.push(Encoding::Bz2);
+    }
+    encodings.push(Encoding::Passthrough);
 
     let mut headers = HeaderMap::default();
 
@@ -488,21 +486,57 @@ pub async fn fetch_repo_data(
     if let Some(cache_headers) = cache_state.as_ref().map(|state| &state.cache_headers) {
         cache_headers.add_to_request(&mut headers);
     }
-    // Send the request and wait for a reply
+
+    let mut encodings = encodings.into_iter().peekable();
+    let (repo_data_url, content_encoding, response) = loop {
+        let encoding = encodings.next().expect("the plain variant is always tried");
+        let repo_data_url = match encoding {
+            Encoding::Zst => subdir_url.join(&format!("{}.zst", options.variant.file_name())),
+            Encoding::Bz2 => subdir_url.join(&format!("{}.bz2", options.variant.file_name())),
+            _ => subdir_url.join(options.variant.file_name()),
+        }
+        .unwrap();
+
+        // Send the request and wait for a reply
+        tracing::debug!("fetching '{}'", RedactedUrl::from(&repo_data_url));
+        let response = match client
+            .get(repo_data_url.clone())
+            .headers(headers.clone())
+            .send()
+            .await
+        {
+            Ok(response) if response.status() == StatusCode::NOT_FOUND => {
+                if encodings.peek().is_none() {
+                    return Err(FetchRepoDataError::NotFound(RepoDataNotFoundError::from(
+                        response.error_for_status().unwrap_err(),
+                    )));
+                }
+
+                // Remember that the variant is not available so it is not requested again.
+                tracing::warn!(
+                    "'{}' was not found, falling back to the next variant",
+                    RedactedUrl::from(&repo_data_url)
+                );
+                let unavailable = Some(Expiring {
+                    value: false,
+                    last_checked: chrono::Utc::now(),
+                });
+                match encoding {
+                    Encoding::Zst => variant_availability.has_zst = unavailable,
+                    _ => variant_availability.has_bz2 = unavailable,
+                }
+                continue;
+            }
+            Ok(response) => response.error_for_status()?,
+            Err(e) => {
+                return Err(FetchRepoDataError::from(e));
+            }
+        };
+        break (repo_data_url, encoding, response);
+    };
     let download_reporter = reporter
         .as_deref()
         .map(|r| (r, r.on_download_start(&repo_data_url)));
-    let response = match request_builder.headers(headers).send().await {
-        Ok(response) if response.status() == StatusCode::NOT_FOUND => {
-            return Err(FetchRepoDataError::NotFound(RepoDataNotFoundError::from(
-                response.error_for_status().unwrap_err(),
-            )));
-        }
-        Ok(response) => response.error_for_status()?,
-        Err(e) => {
-            return Err(FetchRepoDataError::from(e));
-        }
-    };
 
     // If the content didn't change, simply return whatever we have on disk.
     if response.status() == StatusCode::NOT_MODIFIED {
@@ -542,13 +576,7 @@ pub async fn fetch_repo_data(
     let (temp_file, blake2_hash) = stream_and_decode_to_file(
         repo_data_url.clone(),
         response,
-        if has_zst {
-            Encoding::Zst
-        } else if has_bz2 {
-            Encoding::Bz2
-        } else {
-            Encoding::Passthrough
-        },
+        content_encoding,
         &cache_path,
         download_reporter,
     )
@@ -705,6 +733,26 @@ pub async fn check_variant_availability(
     subdir_url: &Url,
     cache_state: Option<&RepoDataState>,
     filename: &str,
+) -> VariantAvailability {
+    check_enabled_variant_availability(
+        client,
+        subdir_url,
+        cache_state,
+        filename,
+        &FetchRepoDataOptions::default(),
+    )
+    .await
+}
+
+/// Determine the availability of the `repodata.json` variants that are enabled in `options`.
+/// Variants that are disabled are never requested from the server, whatever was cached about them
+/// before is kept.
+async fn check_enabled_variant_availability(
+    client: &reqwest_middleware::ClientWithMiddleware,
+    subdir_url: &Url,
+    cache_state: Option<&RepoDataState>,
+    filename: &str,
+    options: &FetchRepoDataOptions,
 ) -> VariantAvailability {
     // Determine from the cache which variant are available. This is currently cached for a maximum
     // of 14 days.
@@ -727,26 +775,26 @@ pub async fn check_variant_availability(
     let bz2_repodata_url = subdir_url.join(&format!("{filename}.bz2")).unwrap();
     let jlap_repodata_url = subdir_url.join(jlap::JLAP_FILE_NAME).unwrap();
 
-    let zst_future = match has_zst {
-        Some(_) => {
-            // The last cached value was valid, so we simply copy that
-            ready(cache_state.and_then(|state| state.has_zst.clone())).left_future()
-        }
-        None => async {
+    let zst_future = if has_zst.is_some() || !options.zstd_enabled {
+        // The last cached value was valid or we are not going to use the variant anyway, so we
+        // simply copy that
+        ready(cache_state.and_then(|state| state.has_zst.clone())).left_future()
+    } else {
+        async {
             Some(Expiring {
                 value: check_valid_download_target(&zst_repodata_url, client).await,
                 last_checked: chrono::Utc::now(),
             })
         }
-        .right_future(),
+        .right_future()
     };
 
     // Create a future to determine if bz2 is available. We only check this if we dont already know that
     // zst is available because if that's available we're going to use that anyway.
-    let bz2_future = if has_zst == Some(true) {
-        // If we already know that zst is available we simply copy the availability value from the last
-        // time we checked.
-        ready(cache_state.and_then(|state| state.has_zst.clone())).right_future()
+    let bz2_future = if (options.zstd_enabled && has_zst == Some(true)) || !options.bz2_enabled {
+        // If we already know that zst is available, or if bz2 is not going to be used, we simply
+        // copy the availability value from the last time we checked.
+        ready(cache_state.and_then(|state| state.has_bz2.clone())).right_future()
     } else {
         // If the zst variant might not be available we need to check whether bz2 is available.
         async {
@@ -764,18 +812,18 @@ pub async fn check_variant_availability(
         .left_future()
     };
 
-    let jlap_future = match has_jlap {
-        Some(_) => {
-            // The last cached value is valid, so we simply copy that
-            ready(cache_state.and_then(|state| state.has_jlap.clone())).left_future()
-        }
-        None => async {
+    let jlap_future = if has_jlap.is_some() || !options.jlap_enabled {
+        // The last cached value is valid or JLAP is not going to be used, so we simply copy
+        // that
+        ready(cache_state.and_then(|state| state.has_jlap.clone())).left_future()
+    } else {
+        async {
             Some(Expiring {
                 value: check_valid_download_target(&jlap_repodata_url, client).await,
                 last_checked: chrono::Utc::now(),
             })
         }
-        .right_future(),
+        .right_future()
     };
 
     // Await all futures so they happen concurrently. Note that a request might not actually happen if
@@ -790,18 +838,21 @@ pub async fn check_variant_availability(
 }
 
 /// Performs a HEAD request on the given URL to see if it is available.
-async fn check_valid_download_target(
+pub(crate) async fn check_valid_download_target(
     url: &Url,
     client: &reqwest_middleware::ClientWithMiddleware,
 ) -> bool {
-    tracing::debug!("checking availability of '{url}'");
+    let redacted_url = RedactedUrl::from(url);
+    tracing::debug!("checking availability of '{redacted_url}'");
 
     if url.scheme() == "file" {
         // If the url is a file url we can simply check if the file exists.
-        let path = url.to_file_path().unwrap();
-        let exists = tokio::fs::metadata(path).await.is_ok();
+        let exists = match url_to_path(url) {
+            Some(path) => tokio::fs::metadata(to_long_path(path)).await.is_ok(),
+            None => false,
+        };
         tracing::debug!(
-            "'{url}' seems to be {}",
+            "'{redacted_url}' seems to be {}",
             if exists { "available" } else { "unavailable" }
         );
         exists
@@ -810,16 +861,16 @@ async fn check_valid_download_target(
         match client.head(url.clone()).send().await {
             Ok(response) => {
                 if response.status().is_success() {
-                    tracing::debug!("'{url}' seems to be available");
+                    tracing::debug!("'{redacted_url}' seems to be available");
                     true
                 } else {
-                    tracing::debug!("'{url}' seems to be unavailable");
+                    tracing::debug!("'{redacted_url}' seems to be unavailable");
                     false
                 }
             }
             Err(e) => {
                 tracing::warn!(
-                    "failed to perform HEAD request on '{url}': {e}. Assuming its unavailable.."
+                    "failed to perform HEAD request on '{redacted_url}': {e}. Assuming its unavailable.."
                 );
                 false
             }
@@ -1325,6 +1376,61 @@ mod test {
         );
     }
 
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    pub async fn test_missing_variant_falls_back() {
+        let subdir_path = TempDir::new().unwrap();
+        std::fs::write(subdir_path.path().join("repodata.json"), FAKE_REPO_DATA).unwrap();
+        write_encoded(
+            FAKE_REPO_DATA.as_bytes(),
+            &subdir_path.path().join("repodata.json.zst"),
+            Encoding::Zst,
+        )
+        .await
+        .unwrap();
+
+        let server = SimpleChannelServer::new(subdir_path.path()).await;
+
+        // Download the data from the channel with an empty cache, this caches that the zst variant
+        // is available.
+        let cache_dir = TempDir::new().unwrap();
+        let result = fetch_repo_data(
+            server.url(),
+            ClientWithMiddleware::from(Client::new()),
+            cache_dir.path().to_owned(),
+            FetchRepoDataOptions::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(result.cache_state.url.path().ends_with("repodata.json.zst"));
+        drop(result);
+
+        // Remove the zst variant, the cached availability is now wrong.
+        std::fs::remove_file(subdir_path.path().join("repodata.json.zst")).unwrap();
+
+        let result = fetch_repo_data(
+            server.url(),
+            ClientWithMiddleware::from(Client::new()),
+            cache_dir.path().to_owned(),
+            FetchRepoDataOptions::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&result.repo_data_json_path).unwrap(),
+            FAKE_REPO_DATA
+        );
+        assert!(result.cache_state.url.path().ends_with("repodata.json"));
+        assert_matches!(
+            result.cache_state.has_zst, Some(super::Expiring {
+                value, ..
+            }) if !value
+        );
+    }
+
     #[tracing_test::traced_test]
     #[tokio::test]
     pub async fn test_gzip_transfer_encoding() {
@@ -1,6 +1,8 @@
-use crate::gateway::GatewayInner;
+use crate::gateway::{channel_variants::ChannelVariantsCache, GatewayInner};
 use crate::{ChannelConfig, Gateway};
 use dashmap::DashMap;
+use rattler_config::Config;
+use rattler_networking::AuthenticationStorage;
 use reqwest::Client;
 use reqwest_middleware::ClientWithMiddleware;
 use std::path::PathBuf;
@@ -13,6 +15,7 @@ pub struct GatewayBuilder {
     client: Option<ClientWithMiddleware>,
     cache: Option<PathBuf>,
     max_concurrent_requests: Option<usize>,
+    authentication_storage: Option<AuthenticationStorage>,
 }
 
 impl GatewayBuilder {
@@ -60,6 +63,21 @@ impl GatewayBuilder {
         self
     }
 
+    /// Applies the shared configuration: repodata is cached in the configured cache directory.
+    #[must_use]
+    pub fn with_config(mut self, config: &Config) -> Self {
+        self.set_config(config);
+        self
+    }
+
+    /// Applies the shared configuration: repodata is cached in the configured cache directory.
+    pub fn set_config(&mut self, config: &Config) -> &mut Self {
+        if let Some(cache) = config.cache_dir() {
+            self.cache = Some(cache);
+        }
+        self
+    }
+
     /// Sets the maximum number of concurrent HTTP requests to make.
     #[must_use]
     pub fn with_max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
@@ -73,6 +91,27 @@ impl GatewayBuilder {
         self
     }
 
+    /// Sets the authentication storage that is used to look up the credentials of channels on
+    /// file shares, e.g. `file://server/share/channel`. If not set, the default storage is used.
+    #[must_use]
+    pub fn with_authentication_storage(
+        mut self,
+        authentication_storage: AuthenticationStorage,
+    ) -> Self {
+        self.set_authentication_storage(authentication_storage);
+        self
+    }
+
+    /// Sets the authentication storage that is used to look up the credentials of channels on
+    /// file shares, e.g. `file://server/share/channel`. If not set, the default storage is used.
+    pub fn set_authentication_storage(
+        &mut self,
+        authentication_storage: AuthenticationStorage,
+    ) -> &mut Self {
+        self.authentication_storage = Some(authentication_storage);
+        self
+    }
+
     /// Finish the construction of the gateway returning a constructed gateway.
     pub fn finish(self) -> Gateway {
         let client = self
@@ -80,9 +119,9 @@ impl GatewayBuilder {
             .unwrap_or_else(|| ClientWithMiddleware::from(Client::new()));
 
         let cache = self.cache.unwrap_or_else(|| {
-            dirs::cache_dir()
+            Config::default()
+                .cache_dir()
                 .unwrap_or_else(|| PathBuf::from("."))
-                .join("rattler/cache")
         });
 
         let max_concurrent_requests = self.max_concurrent_requests.unwrap_or(100);
@@ -91,10 +130,12 @@ impl GatewayBuilder {
                 subdirs: DashMap::default(),
                 client,
                 channel_config: self.channel_config,
+                channel_variants: ChannelVariantsCache::new(cache.clone()),
                 cache,
                 concurrent_requests_semaphore: Arc::new(tokio::sync::Semaphore::new(
                     max_concurrent_requests,
                 )),
+                authentication_storage: self.authentication_storage,
             }),
         }
     }
@@ -15,6 +15,10 @@ pub struct SourceConfig {
     /// When enabled, the bz2 variant will be used if available (defaults to true)
     pub bz2_enabled: bool,
 
+    /// When enabled, an index is stored next to the cached repodata that allows reading the
+    /// records of individual packages without parsing the entire file (defaults to true)
+    pub indexed_cache_enabled: bool,
+
     /// Describes fetching repodata from a channel should interact with any
     /// caches.
     pub cache_action: CacheAction,
@@ -26,6 +30,7 @@ impl Default for SourceConfig {
             jlap_enabled: true,
             zstd_enabled: true,
             bz2_enabled: true,
+            indexed_cache_enabled: true,
             cache_action: CacheAction::default(),
         }
     }
@@ -0,0 +1,298 @@
+//! Remembers which variants of the repodata the server of a channel provides. See
+//! [`ChannelVariantsCache`].
+
+use crate::fetch::{check_valid_download_target, CacheAction, Expiring};
+use crate::utils::{run_blocking_task, url_to_cache_filename};
+use crate::GatewayError;
+use dashmap::DashMap;
+use rattler_conda_types::{Channel, Platform};
+use reqwest_middleware::ClientWithMiddleware;
+use serde::{Deserialize, Serialize};
+use std::{io::Write, path::PathBuf, sync::Arc};
+use url::Url;
+
+/// The name of the index of a sharded subdirectory.
+const REPODATA_SHARDS_FILENAME: &str = "repodata_shards.msgpack.zst";
+
+/// The variants of the repodata that the server of a channel is known to provide.
+///
+/// Servers that do not provide a compressed variant of the `repodata.json` typically do not
+/// provide it for any subdirectory. By remembering this per channel, the availability that was
+/// learned for one subdirectory is reused for the others and no requests are made for variants
+/// that are known to be missing.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct ChannelVariants {
+    /// Whether the `repodata.json.zst` is available.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_zst: Option<Expiring<bool>>,
+
+    /// Whether the `repodata.json.bz2` is available.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_bz2: Option<Expiring<bool>>,
+
+    /// Whether the channel provides sharded repodata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_shards: Option<Expiring<bool>>,
+}
+
+impl ChannelVariants {
+    /// Returns the value of the given availability if it has not expired yet. Availabilities are
+    /// refreshed after 14 days, just like the availabilities that are cached per subdirectory.
+    fn known(value: Option<&Expiring<bool>>) -> Option<bool> {
+        let expiration_duration =
+            chrono::TimeDelta::try_days(14).expect("14 days is a valid duration");
+        value
+            .and_then(|value| value.value(expiration_duration))
+            .copied()
+    }
+
+    /// Returns true if the `repodata.json.zst` is known to be missing.
+    pub fn lacks_zst(&self) -> bool {
+        Self::known(self.has_zst.as_ref()) == Some(false)
+    }
+
+    /// Returns true if the `repodata.json.bz2` is known to be missing.
+    pub fn lacks_bz2(&self) -> bool {
+        Self::known(self.has_bz2.as_ref()) == Some(false)
+    }
+
+    /// Records availabilities that were observed for a subdirectory of the channel. An
+    /// observation only replaces what is known if it was made more recently.
+    pub fn observe(&mut self, has_zst: Option<&Expiring<bool>>, has_bz2: Option<&Expiring<bool>>) {
+        fn merge(known: &mut Option<Expiring<bool>>, observed: Option<&Expiring<bool>>) {
+            if let Some(observed) = observed {
+                if known
+                    .as_ref()
+                    .map_or(true, |known| known.last_checked < observed.last_checked)
+                {
+                    *known = Some(observed.clone());
+                }
+            }
+        }
+        merge(&mut self.has_zst, has_zst);
+        merge(&mut self.has_bz2, has_bz2);
+    }
+}
+
+/// Caches the [`ChannelVariants`] of channels, both in memory and in the cache directory.
+pub(crate) struct ChannelVariantsCache {
+    /// The directory in which the variants of the channels are stored.
+    cache_dir: PathBuf,
+
+    /// The variants of each channel, keyed by the base url of the channel. The variants of a
+    /// channel are only read from disk when they are first needed.
+    channels: DashMap<Url, Arc<tokio::sync::Mutex<Option<ChannelVariants>>>>,
+}
+
+impl ChannelVariantsCache {
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Self {
+            cache_dir,
+            channels: DashMap::default(),
+        }
+    }
+
+    /// Returns the known variants of the given channel.
+    pub async fn get(&self, channel: &Channel) -> ChannelVariants {
+        let entry = self.entry(channel);
+        let mut variants = entry.lock().await;
+        self.load(channel, &mut variants).await.clone()
+    }
+
+    /// Records the availabilities that were observed for a subdirectory of the given channel.
+    pub async fn observe(
+        &self,
+        channel: &Channel,
+        has_zst: Option<&Expiring<bool>>,
+        has_bz2: Option<&Expiring<bool>>,
+    ) {
+        let entry = self.entry(channel);
+        let mut variants = entry.lock().await;
+        let variants = self.load(channel, &mut variants).await;
+        variants.observe(has_zst, has_bz2);
+        self.store(channel, variants.clone()).await;
+    }
+
+    /// Returns true if the channel provides sharded repodata. If this is not known yet, the
+    /// server is asked for the shard index of the `noarch` subdirectory, which every channel
+    /// must have. Unless `cache_action` allows it, the server is never asked and unknown
+    /// channels are assumed to not be sharded.
+    pub async fn has_shards(
+        &self,
+        channel: &Channel,
+        client: &ClientWithMiddleware,
+        cache_action: CacheAction,
+    ) -> bool {
+        // Hold the lock while checking the server so the server is only asked once, even if
+        // multiple subdirectories of the channel are requested concurrently.
+        let entry = self.entry(channel);
+        let mut variants = entry.lock().await;
+        let variants = self.load(channel, &mut variants).await;
+        if let Some(has_shards) = ChannelVariants::known(variants.has_shards.as_ref()) {
+            return has_shards;
+        }
+
+        if matches!(
+            cache_action,
+            CacheAction::UseCacheOnly | CacheAction::ForceCacheOnly
+        ) {
+            return false;
+        }
+
+        let shard_index_url = channel
+            .platform_url(Platform::NoArch)
+            .join(REPODATA_SHARDS_FILENAME)
+            .expect("file name is valid");
+        let has_shards = check_valid_download_target(&shard_index_url, client).await;
+        variants.has_shards = Some(Expiring {
+            value: has_shards,
+            last_checked: chrono::Utc::now(),
+        });
+        self.store(channel, variants.clone()).await;
+        has_shards
+    }
+
+    fn entry(&self, channel: &Channel) -> Arc<tokio::sync::Mutex<Option<ChannelVariants>>> {
+        self.channels
+            .entry(channel.base_url().clone())
+            .or_default()
+            .clone()
+    }
+
+    fn cache_path(&self, channel: &Channel) -> PathBuf {
+        self.cache_dir.join(format!(
+            "{}.variants.json",
+            url_to_cache_filename(channel.base_url())
+        ))
+    }
+
+    /// Reads the variants of the channel from disk if they have not been read before. Variants
+    /// that cannot be read are considered unknown.
+    async fn load<'a>(
+        &self,
+        channel: &Channel,
+        variants: &'a mut Option<ChannelVariants>,
+    ) -> &'a mut ChannelVariants {
+        if variants.is_none() {
+            let cache_path = self.cache_path(channel);
+            let loaded = match tokio::fs::read(&cache_path).await {
+                Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+                    tracing::warn!(
+                        "failed to parse the cached variants of {}: {err}",
+                        channel.canonical_name()
+                    );
+                    ChannelVariants::default()
+                }),
+                Err(_) => ChannelVariants::default(),
+            };
+            *variants = Some(loaded);
+        }
+        variants.as_mut().expect("the variants have been loaded")
+    }
+
+    /// Writes the variants of the channel to disk. This is best effort, failures are only
+    /// logged.
+    async fn store(&self, channel: &Channel, variants: ChannelVariants) {
+        let cache_path = self.cache_path(channel);
+        let cache_dir = self.cache_dir.clone();
+        let result = run_blocking_task(move || {
+            let write = || {
+                std::fs::create_dir_all(&cache_dir)?;
+                let mut file = tempfile::NamedTempFile::new_in(&cache_dir)?;
+                file.write_all(&serde_json::to_vec_pretty(&variants)?)?;
+                file.persist(&cache_path)?;
+                Ok::<_, std::io::Error>(())
+            };
+            write().map_err(|err| {
+                GatewayError::IoError("failed to write the cached channel variants".into(), err)
+            })
+        })
+        .await;
+        if let Err(err) = result {
+            tracing::warn!("{err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ChannelVariantsCache;
+    use crate::fetch::{CacheAction, Expiring};
+    use crate::utils::simple_channel_server::SimpleChannelServer;
+    use reqwest::Client;
+    use reqwest_middleware::ClientWithMiddleware;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_channel_variants() {
+        let channel_dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(channel_dir.path().join("noarch")).unwrap();
+        let server = SimpleChannelServer::new(channel_dir.path()).await;
+        let channel = server.channel();
+        let client = ClientWithMiddleware::from(Client::new());
+        let cache_dir = TempDir::new().unwrap();
+
+        // Without asking the server, the channel is not known to be sharded.
+        let cache = ChannelVariantsCache::new(cache_dir.path().to_path_buf());
+        assert!(
+            !cache
+                .has_shards(&channel, &client, CacheAction::ForceCacheOnly)
+                .await
+        );
+        assert!(cache.get(&channel).await.has_shards.is_none());
+
+        // The server does not provide a shard index.
+        assert!(
+            !cache
+                .has_shards(&channel, &client, CacheAction::CacheOrFetch)
+                .await
+        );
+
+        // Observations of subdirectories are shared between all subdirectories of the channel.
+        let unavailable = Expiring {
+            value: false,
+            last_checked: chrono::Utc::now(),
+        };
+        cache
+            .observe(&channel, Some(&unavailable), Some(&unavailable))
+            .await;
+        let variants = cache.get(&channel).await;
+        assert!(variants.lacks_zst());
+        assert!(variants.lacks_bz2());
+
+        // Older observations do not replace newer ones.
+        let stale = Expiring {
+            value: true,
+            last_checked: unavailable.last_checked - chrono::TimeDelta::try_days(1).unwrap(),
+        };
+        cache.observe(&channel, Some(&stale), None).await;
+        assert!(cache.get(&channel).await.lacks_zst());
+
+        // The variants are also remembered across caches, even if the shard index appears.
+        std::fs::write(
+            channel_dir
+                .path()
+                .join("noarch/repodata_shards.msgpack.zst"),
+            b"",
+        )
+        .unwrap();
+        let cache = ChannelVariantsCache::new(cache_dir.path().to_path_buf());
+        let variants = cache.get(&channel).await;
+        assert!(variants.lacks_zst());
+        assert!(variants.lacks_bz2());
+        assert!(
+            !cache
+                .has_shards(&channel, &client, CacheAction::CacheOrFetch)
+                .await
+        );
+
+        // A channel that is not known yet is checked.
+        let other_cache_dir = TempDir::new().unwrap();
+        let cache = ChannelVariantsCache::new(other_cache_dir.path().to_path_buf());
+        assert!(
+            cache
+                .has_shards(&channel, &client, CacheAction::CacheOrFetch)
+                .await
+        );
+    }
+}
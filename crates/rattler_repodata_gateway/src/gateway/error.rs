@@ -1,12 +1,14 @@
 use crate::fetch;
 use crate::fetch::{FetchRepoDataError, RepoDataNotFoundError};
 use crate::utils::Cancelled;
-use rattler_conda_types::Channel;
-use rattler_networking::Redact;
+use rattler_conda_types::{Channel, MatchSpec};
+use rattler_digest::Sha256Hash;
+use rattler_networking::{Redact, RedactedUrl};
 use reqwest_middleware::Error;
 use std::fmt::{Display, Formatter};
 use std::io;
 use thiserror::Error;
+use url::Url;
 
 #[derive(Debug, Error)]
 #[allow(missing_docs)]
@@ -34,6 +36,19 @@ pub enum GatewayError {
 
     #[error("the operation was cancelled")]
     Cancelled,
+
+    #[error("the spec '{0}' does not name a package")]
+    MatchSpecWithoutName(Box<MatchSpec>),
+
+    #[error(
+        "the shard downloaded from {} has hash {actual:x} instead of {expected:x}",
+        RedactedUrl::from(.url)
+    )]
+    ShardHashMismatch {
+        url: Url,
+        expected: Sha256Hash,
+        actual: Sha256Hash,
+    },
 }
 
 impl From<Cancelled> for GatewayError {
@@ -0,0 +1,451 @@
+//! An on-disk index of a cached `repodata.json` that allows reading the records of a single
+//! package without parsing the entire file.
+//!
+//! The index file starts with a small header that contains the offset of a zstd compressed frame
+//! for every package name in the repodata. Loading the records of a package only requires reading
+//! the header and decompressing the frame of that package, which is much cheaper than parsing the
+//! full `repodata.json` for very large channels.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use memmap2::Mmap;
+use rattler_conda_types::{
+    compute_package_url, Channel, PackageName, PackageRecord, RepoDataRecord,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+use crate::{
+    gateway::{subdir::SubdirClient, GatewayError},
+    utils::run_blocking_task,
+    Reporter,
+};
+
+/// Magic number that identifies the index file format.
+const MAGIC_NUMBER: &[u8] = b"REPODATA-INDEX-V1";
+
+/// The extension of the index file that is stored next to the cached `repodata.json`.
+const INDEX_EXTENSION: &str = "index-v1";
+
+/// The zstd compression level used for the package frames.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// The header of an index file.
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexHeader {
+    /// The size of the `repodata.json` the index was created from.
+    source_size: u64,
+
+    /// The modification time of the `repodata.json` the index was created from in nanoseconds
+    /// since the unix epoch.
+    source_modified: u64,
+
+    /// The `base_url` from the `info` section of the `repodata.json`.
+    base_url: Option<String>,
+
+    /// The offset and length of the frame of each package relative to the start of the body,
+    /// keyed by normalized package name.
+    packages: BTreeMap<String, (u64, u64)>,
+}
+
+/// The parts of a `repodata.json` that are required to build an index.
+#[derive(Deserialize)]
+struct RawRepoData<'i> {
+    #[serde(default)]
+    info: Option<RawChannelInfo>,
+
+    #[serde(borrow, default)]
+    packages: HashMap<&'i str, &'i RawValue>,
+
+    #[serde(borrow, default, rename = "packages.conda")]
+    conda_packages: HashMap<&'i str, &'i RawValue>,
+}
+
+#[derive(Deserialize)]
+struct RawChannelInfo {
+    #[serde(default)]
+    base_url: Option<String>,
+}
+
+/// A memory mapped index file.
+pub struct IndexedRepoData {
+    /// The memory mapped index file.
+    mmap: Mmap,
+
+    /// The parsed header of the file.
+    header: IndexHeader,
+
+    /// The offset of the body in the file.
+    body_offset: usize,
+
+    /// The channel the repodata belongs to.
+    channel: Channel,
+
+    /// The subdirectory of the repodata.
+    subdir: String,
+}
+
+impl IndexedRepoData {
+    /// Opens the index of the `repodata.json` at the given path, (re)creating it if it does not
+    /// exist or if it is out of date.
+    ///
+    /// The caller is expected to hold the lock of the `repodata.json` while calling this
+    /// function.
+    pub fn from_repodata_path(
+        channel: Channel,
+        subdir: impl Into<String>,
+        repodata_path: &Path,
+    ) -> io::Result<Self> {
+        let subdir = subdir.into();
+        let (source_size, source_modified) = source_fingerprint(repodata_path)?;
+        let index_path = index_path(repodata_path);
+
+        match Self::open(&index_path, channel.clone(), subdir.clone()) {
+            Ok(index)
+                if index.header.source_size == source_size
+                    && index.header.source_modified == source_modified =>
+            {
+                return Ok(index);
+            }
+            Ok(_) => {
+                tracing::debug!("index at {} is out of date", index_path.display());
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                tracing::debug!("failed to read index at {}: {err}", index_path.display());
+            }
+        }
+
+        write_index(repodata_path, &index_path, source_size, source_modified)?;
+        Self::open(&index_path, channel, subdir)
+    }
+
+    /// Opens an existing index file.
+    fn open(index_path: &Path, channel: Channel, subdir: String) -> io::Result<Self> {
+        let file = File::open(index_path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let bytes = mmap.as_ref();
+        if !bytes.starts_with(MAGIC_NUMBER) {
+            return Err(invalid_data("invalid magic number"));
+        }
+        let header_start = MAGIC_NUMBER.len() + 4;
+        let header_length = bytes
+            .get(MAGIC_NUMBER.len()..header_start)
+            .map(|length| u32::from_le_bytes(length.try_into().unwrap()) as usize)
+            .ok_or_else(|| invalid_data("missing header length"))?;
+        let body_offset = header_start + header_length;
+        let header_bytes = bytes
+            .get(header_start..body_offset)
+            .ok_or_else(|| invalid_data("truncated header"))?;
+        let header: IndexHeader =
+            rmp_serde::from_slice(header_bytes).map_err(|e| invalid_data(e.to_string()))?;
+
+        Ok(Self {
+            mmap,
+            header,
+            body_offset,
+            channel,
+            subdir,
+        })
+    }
+
+    /// Returns all the records for the package with the given name.
+    pub fn load_records(&self, package_name: &PackageName) -> io::Result<Vec<RepoDataRecord>> {
+        let Some(&(offset, length)) = self.header.packages.get(package_name.as_normalized()) else {
+            return Ok(Vec::new());
+        };
+
+        let start = self.body_offset + offset as usize;
+        let frame = self
+            .mmap
+            .get(start..start + length as usize)
+            .ok_or_else(|| invalid_data("package frame is out of bounds"))?;
+        let bytes = zstd::decode_all(frame)?;
+        let entries: Vec<(String, PackageRecord)> = serde_json::from_slice(&bytes)?;
+
+        let channel_name = self.channel.canonical_name();
+        let mut result = Vec::with_capacity(entries.len());
+        for (file_name, mut package_record) in entries {
+            // Overwrite subdir if its empty
+            if package_record.subdir.is_empty() {
+                package_record.subdir.clone_from(&self.subdir);
+            }
+            result.push(RepoDataRecord {
+                url: compute_package_url(
+                    &self
+                        .channel
+                        .base_url
+                        .join(&format!("{}/", &package_record.subdir))
+                        .expect("failed determine repo_base_url"),
+                    self.header.base_url.as_deref(),
+                    &file_name,
+                ),
+                channel: channel_name.clone(),
+                package_record,
+                file_name,
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+/// A client that reads records from the index of a cached `repodata.json`.
+pub struct IndexedSubdirClient {
+    index: Arc<IndexedRepoData>,
+}
+
+impl IndexedSubdirClient {
+    /// Opens (or creates) the index of the `repodata.json` at the given path.
+    pub async fn from_repodata_path(
+        repodata_path: &Path,
+        channel: Channel,
+        subdir: &str,
+    ) -> Result<Self, GatewayError> {
+        let repodata_path = repodata_path.to_path_buf();
+        let subdir = subdir.to_string();
+        let index = run_blocking_task(move || {
+            IndexedRepoData::from_repodata_path(channel, subdir, &repodata_path).map_err(|err| {
+                GatewayError::IoError(format!("failed to index {}", repodata_path.display()), err)
+            })
+        })
+        .await?;
+
+        Ok(Self {
+            index: Arc::new(index),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl SubdirClient for IndexedSubdirClient {
+    async fn fetch_package_records(
+        &self,
+        name: &PackageName,
+        _reporter: Option<&dyn Reporter>,
+    ) -> Result<Arc<[RepoDataRecord]>, GatewayError> {
+        let index = self.index.clone();
+        let name = name.clone();
+        run_blocking_task(move || match index.load_records(&name) {
+            Ok(records) => Ok(records.into()),
+            Err(err) => Err(GatewayError::IoError(
+                "failed to extract repodata records from the repodata index".to_string(),
+                err,
+            )),
+        })
+        .await
+    }
+
+    fn package_names(&self) -> Vec<String> {
+        self.index.header.packages.keys().cloned().collect()
+    }
+}
+
+/// Returns the path of the index file of the `repodata.json` at the given path.
+fn index_path(repodata_path: &Path) -> PathBuf {
+    repodata_path.with_extension(INDEX_EXTENSION)
+}
+
+/// Returns the size and modification time of a file, which are used to determine whether an
+/// index is out of date.
+fn source_fingerprint(path: &Path) -> io::Result<(u64, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos() as u64);
+    Ok((metadata.len(), modified))
+}
+
+/// Parses the `repodata.json` at `repodata_path` and writes an index of it to `index_path`.
+fn write_index(
+    repodata_path: &Path,
+    index_path: &Path,
+    source_size: u64,
+    source_modified: u64,
+) -> io::Result<()> {
+    let file = File::open(repodata_path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let repodata: RawRepoData<'_> = serde_json::from_slice(&mmap)?;
+
+    // Group the records by package name. The `.tar.bz2` records come before the `.conda` records,
+    // just like when the records are read from the `repodata.json` directly.
+    let mut records_by_name: BTreeMap<&str, Vec<(&str, &RawValue)>> = BTreeMap::new();
+    for packages in [&repodata.packages, &repodata.conda_packages] {
+        let mut packages = packages.iter().collect::<Vec<_>>();
+        packages.sort_unstable_by_key(|(filename, _)| **filename);
+        for (&filename, &raw_record) in packages {
+            let name = filename
+                .rsplitn(3, '-')
+                .nth(2)
+                .ok_or_else(|| invalid_data(format!("invalid filename '{filename}'")))?;
+            records_by_name
+                .entry(name)
+                .or_default()
+                .push((filename, raw_record));
+        }
+    }
+
+    // Compress the records of each package into a separate frame.
+    let mut body = Vec::new();
+    let mut packages = BTreeMap::new();
+    for (name, records) in records_by_name {
+        let json = serde_json::to_vec(&records)?;
+        let frame = zstd::bulk::compress(&json, COMPRESSION_LEVEL)?;
+        packages.insert(name.to_owned(), (body.len() as u64, frame.len() as u64));
+        body.extend_from_slice(&frame);
+    }
+
+    let header = rmp_serde::encode::to_vec(&IndexHeader {
+        source_size,
+        source_modified,
+        base_url: repodata.info.and_then(|info| info.base_url),
+        packages,
+    })
+    .expect("failed to encode index header");
+
+    // Write the index to a temporary file first and atomically move it into place.
+    let index_dir = index_path
+        .parent()
+        .expect("the index path must have a parent");
+    let mut temp_file = tempfile::Builder::new().tempfile_in(index_dir)?;
+    temp_file.write_all(MAGIC_NUMBER)?;
+    temp_file.write_all(&(header.len() as u32).to_le_bytes())?;
+    temp_file.write_all(&header)?;
+    temp_file.write_all(&body)?;
+    temp_file.persist(index_path)?;
+
+    Ok(())
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeSet, path::Path};
+
+    use rattler_conda_types::{Channel, ChannelConfig, PackageName};
+
+    use super::{index_path, IndexedRepoData};
+    use crate::sparse::SparseRepoData;
+
+    const REPODATA: &str = r#"{
+        "info": { "subdir": "linux-64", "base_url": "https://mirror.example.com/linux-64/" },
+        "packages": {
+            "clang-format-12.0.1-default_0.tar.bz2": { "name": "clang-format", "version": "12.0.1", "build": "default_0", "build_number": 0, "depends": [] },
+            "clang-format-13-13.0.0-default_0.tar.bz2": { "name": "clang-format-13", "version": "13.0.0", "build": "default_0", "build_number": 0, "depends": [] },
+            "clang-format-13.0.0-default_0.tar.bz2": { "name": "clang-format", "version": "13.0.0", "build": "default_0", "build_number": 0, "depends": ["clang-format-13 13.0.0"] },
+            "python-3.12.0-h1_0.tar.bz2": { "name": "python", "version": "3.12.0", "build": "h1_0", "build_number": 0, "depends": [], "subdir": "linux-64" }
+        },
+        "packages.conda": {
+            "python-3.12.1-h1_0.conda": { "name": "python", "version": "3.12.1", "build": "h1_0", "build_number": 0, "depends": [], "subdir": "linux-64" }
+        }
+    }"#;
+
+    fn channel() -> Channel {
+        let channel_config = ChannelConfig::default_with_root_dir(std::env::current_dir().unwrap());
+        Channel::from_str("conda-forge", &channel_config).unwrap()
+    }
+
+    fn modified(path: &Path) -> std::time::SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[test]
+    fn test_index_matches_sparse() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repodata_path = temp_dir.path().join("repodata.json");
+        std::fs::write(&repodata_path, REPODATA).unwrap();
+
+        let index =
+            IndexedRepoData::from_repodata_path(channel(), "linux-64", &repodata_path).unwrap();
+        let sparse = SparseRepoData::new(channel(), "linux-64", &repodata_path, None).unwrap();
+
+        assert_eq!(
+            index.header.packages.keys().collect::<Vec<_>>(),
+            sparse
+                .package_names()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+        for name in [
+            "clang-format",
+            "clang-format-13",
+            "python",
+            "does-not-exist",
+        ] {
+            let name = PackageName::new_unchecked(name);
+            let mut indexed = index.load_records(&name).unwrap();
+            let mut expected = sparse.load_records(&name).unwrap();
+            indexed.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+            expected.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+            assert_eq!(indexed, expected);
+        }
+
+        let python = index
+            .load_records(&PackageName::new_unchecked("python"))
+            .unwrap();
+        assert_eq!(
+            python
+                .iter()
+                .map(|record| record.url.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "https://mirror.example.com/linux-64/python-3.12.0-h1_0.tar.bz2",
+                "https://mirror.example.com/linux-64/python-3.12.1-h1_0.conda"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_index_is_recreated_when_out_of_date() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let repodata_path = temp_dir.path().join("repodata.json");
+        std::fs::write(&repodata_path, REPODATA).unwrap();
+
+        IndexedRepoData::from_repodata_path(channel(), "linux-64", &repodata_path).unwrap();
+        let index_path = index_path(&repodata_path);
+        assert!(index_path.is_file());
+
+        // Opening the index again reuses the existing file.
+        let index_modified = modified(&index_path);
+        IndexedRepoData::from_repodata_path(channel(), "linux-64", &repodata_path).unwrap();
+        assert_eq!(modified(&index_path), index_modified);
+
+        // A corrupt index is recreated.
+        std::fs::write(&index_path, b"garbage").unwrap();
+        let index =
+            IndexedRepoData::from_repodata_path(channel(), "linux-64", &repodata_path).unwrap();
+        assert_eq!(index.header.packages.len(), 3);
+
+        // Replacing the repodata invalidates the index.
+        std::fs::write(
+            &repodata_path,
+            r#"{"packages":{"foo-1.0-0.tar.bz2":{"name":"foo","version":"1.0","build":"0","build_number":0,"depends":[]}}}"#,
+        )
+        .unwrap();
+        let index =
+            IndexedRepoData::from_repodata_path(channel(), "noarch", &repodata_path).unwrap();
+        assert_eq!(index.header.packages.keys().collect::<Vec<_>>(), ["foo"]);
+        let records = index
+            .load_records(&PackageName::new_unchecked("foo"))
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].package_record.subdir, "noarch");
+        assert_eq!(
+            records[0].url.as_str(),
+            "https://conda.anaconda.org/conda-forge/noarch/foo-1.0-0.tar.bz2"
+        );
+    }
+}
@@ -64,4 +64,8 @@ impl SubdirClient for LocalSubdirClient {
         })
         .await
     }
+
+    fn package_names(&self) -> Vec<String> {
+        self.sparse.package_names().map(str::to_string).collect()
+    }
 }
@@ -1,12 +1,16 @@
 mod barrier_cell;
 mod builder;
 mod channel_config;
+mod channel_variants;
 mod error;
+mod indexed_subdir;
 mod local_subdir;
 mod query;
 mod remote_subdir;
 mod repo_data;
+mod reverse_dependency_query;
 mod sharded_subdir;
+mod spec_closure;
 mod subdir;
 
 pub use barrier_cell::BarrierCell;
@@ -14,13 +18,22 @@ pub use builder::GatewayBuilder;
 pub use channel_config::{ChannelConfig, SourceConfig};
 pub use error::GatewayError;
 pub use query::GatewayQuery;
-pub use repo_data::RepoData;
+pub use repo_data::{PrioritizedRecord, RepoData};
+pub use reverse_dependency_query::ReverseDependencyQuery;
 
-use crate::{fetch::FetchRepoDataError, gateway::error::SubdirNotFoundError, Reporter};
+use crate::{
+    fetch::FetchRepoDataError, gateway::error::SubdirNotFoundError, utils::run_blocking_task,
+    Reporter,
+};
+use channel_variants::ChannelVariantsCache;
 use dashmap::{mapref::entry::Entry, DashMap};
-use file_url::url_to_path;
+use file_url::{to_long_path, url_to_path};
 use local_subdir::LocalSubdirClient;
 use rattler_conda_types::{Channel, MatchSpec, Platform};
+use rattler_networking::{
+    file_share::{connect_file_share, unc_share},
+    AuthenticationStorage,
+};
 use reqwest_middleware::ClientWithMiddleware;
 use std::collections::HashSet;
 use std::{
@@ -110,6 +123,58 @@ impl Gateway {
         )
     }
 
+    /// Constructs a new `GatewayQuery` that returns the records in the dependency
+    /// closure of the given specs.
+    ///
+    /// Starting from the root specs, the query iteratively fetches the records of
+    /// every package name that is required by a record in the closure. Only the
+    /// records that match a spec that is requested for their name are returned, which
+    /// is exactly the set of records a solver needs. For sharded channels this only
+    /// fetches the shards of the packages in the closure instead of the whole channel.
+    pub fn query_closure<AsChannel, ChannelIter, PlatformIter, PackageNameIter, IntoMatchSpec>(
+        &self,
+        channels: ChannelIter,
+        platforms: PlatformIter,
+        specs: PackageNameIter,
+    ) -> GatewayQuery
+    where
+        AsChannel: Into<Channel>,
+        ChannelIter: IntoIterator<Item = AsChannel>,
+        PlatformIter: IntoIterator<Item = Platform>,
+        <PlatformIter as IntoIterator>::IntoIter: Clone,
+        PackageNameIter: IntoIterator<Item = IntoMatchSpec>,
+        IntoMatchSpec: Into<MatchSpec>,
+    {
+        self.query(channels, platforms, specs).closure(true)
+    }
+
+    /// Constructs a new [`ReverseDependencyQuery`] that returns the records in the given channels
+    /// that depend on the package of `spec`.
+    ///
+    /// If `spec` only names a package, the records that depend on any version of it are returned.
+    /// Otherwise only the records that depend on the package in a way that can be satisfied by a
+    /// record matching `spec` are returned. This can be used to assess which packages are affected
+    /// when a package is removed or patched.
+    pub fn query_reverse_dependencies<AsChannel, ChannelIter, PlatformIter, IntoMatchSpec>(
+        &self,
+        channels: ChannelIter,
+        platforms: PlatformIter,
+        spec: IntoMatchSpec,
+    ) -> ReverseDependencyQuery
+    where
+        AsChannel: Into<Channel>,
+        ChannelIter: IntoIterator<Item = AsChannel>,
+        PlatformIter: IntoIterator<Item = Platform>,
+        IntoMatchSpec: Into<MatchSpec>,
+    {
+        ReverseDependencyQuery::new(
+            self.inner.clone(),
+            channels.into_iter().map(Into::into).collect(),
+            platforms.into_iter().collect(),
+            spec.into(),
+        )
+    }
+
     /// Clears any in-memory cache for the given channel.
     ///
     /// Any subsequent query will re-fetch any required data from the source.
@@ -135,8 +200,15 @@ struct GatewayInner {
     /// The directory to store any cache
     cache: PathBuf,
 
+    /// The variants of the repodata that the servers of channels are known to provide.
+    channel_variants: ChannelVariantsCache,
+
     /// A semaphore to limit the number of concurrent requests.
     concurrent_requests_semaphore: Arc<tokio::sync::Semaphore>,
+
+    /// The storage to look up the credentials of file shares in, the default storage is used if
+    /// this is not set.
+    authentication_storage: Option<AuthenticationStorage>,
 }
 
 impl GatewayInner {
@@ -243,8 +315,24 @@ impl GatewayInner {
         let url = channel.platform_url(platform);
         let subdir_data = if url.scheme() == "file" {
             if let Some(path) = url_to_path(&url) {
+                if unc_share(&url).is_some() {
+                    let storage = self
+                        .authentication_storage
+                        .clone()
+                        .unwrap_or_else(|| AuthenticationStorage::from_env().unwrap_or_default());
+                    let share_url = url.clone();
+                    run_blocking_task(move || {
+                        connect_file_share(&share_url, &storage).map_err(|err| {
+                            GatewayError::IoError(
+                                format!("failed to connect to the file share of {share_url}"),
+                                err,
+                            )
+                        })
+                    })
+                    .await?;
+                }
                 LocalSubdirClient::from_channel_subdir(
-                    &path.join("repodata.json"),
+                    &to_long_path(path.join("repodata.json")),
                     channel.clone(),
                     platform.as_str(),
                 )
@@ -256,9 +344,14 @@ impl GatewayInner {
                 ));
             }
         } else if url.scheme() == "http" || url.scheme() == "https" {
-            if url.host_str() == Some("fast.prefiks.dev")
+            let source_config = self.channel_config.get(channel);
+            let has_shards = url.host_str() == Some("fast.prefiks.dev")
                 || url.host_str() == Some("fast.prefix.dev")
-            {
+                || self
+                    .channel_variants
+                    .has_shards(channel, &self.client, source_config.cache_action)
+                    .await;
+            if has_shards {
                 sharded_subdir::ShardedSubdir::new(
                     channel.clone(),
                     platform.to_string(),
@@ -275,7 +368,8 @@ impl GatewayInner {
                     platform,
                     self.client.clone(),
                     self.cache.clone(),
-                    self.channel_config.get(channel).clone(),
+                    source_config.clone(),
+                    &self.channel_variants,
                     reporter,
                 )
                 .await
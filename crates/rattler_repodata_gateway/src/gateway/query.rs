@@ -1,4 +1,6 @@
-use super::{subdir::Subdir, BarrierCell, GatewayError, GatewayInner, RepoData};
+use super::{
+    spec_closure::SpecClosure, subdir::Subdir, BarrierCell, GatewayError, GatewayInner, RepoData,
+};
 use crate::Reporter;
 use futures::{select_biased, stream::FuturesUnordered, FutureExt, StreamExt};
 use itertools::Itertools;
@@ -37,6 +39,9 @@ pub struct GatewayQuery {
     /// Whether to recursively fetch dependencies
     recursive: bool,
 
+    /// Whether to only return the records in the closure of the specs
+    closure: bool,
+
     /// The reporter to use by the query.
     reporter: Option<Arc<dyn Reporter>>,
 }
@@ -57,6 +62,7 @@ impl GatewayQuery {
             specs,
 
             recursive: false,
+            closure: false,
             reporter: None,
         }
     }
@@ -72,6 +78,19 @@ impl GatewayQuery {
         Self { recursive, ..self }
     }
 
+    /// Sets whether the query should only return the records in the dependency
+    /// closure of the root specs.
+    ///
+    /// Unlike a [recursive](Self::recursive) query, which fetches all the records of
+    /// every package name that any record depends on, a closure query keeps track
+    /// of the specs that are requested for every package name. Only the records that
+    /// match one of these specs are returned and only their dependencies are
+    /// followed. The result is exactly the set of records a solver can select from.
+    #[must_use]
+    pub(super) fn closure(self, closure: bool) -> Self {
+        Self { closure, ..self }
+    }
+
     /// Sets the reporter to use for this query.
     ///
     /// The reporter is notified of important evens during the execution of the
@@ -121,8 +140,16 @@ impl GatewayQuery {
         // Package names that we have or will issue requests for.
         let mut seen = HashSet::new();
         let mut pending_package_specs = HashMap::new();
+        let mut closure = self.closure.then(SpecClosure::default);
         for spec in self.specs {
-            if let Some(name) = &spec.name {
+            if let Some(closure) = &mut closure {
+                if let Some(name) = spec.name.clone() {
+                    for name in closure.insert_spec(name, spec) {
+                        seen.insert(name.clone());
+                        pending_package_specs.insert(name, Vec::new());
+                    }
+                }
+            } else if let Some(name) = &spec.name {
                 seen.insert(name.clone());
                 pending_package_specs
                     .entry(name.clone())
@@ -136,7 +163,7 @@ impl GatewayQuery {
         let mut pending_records = FuturesUnordered::new();
 
         // The resulting list of repodata records.
-        let mut result = vec![RepoData::default(); self.channels.len()];
+        let mut result = RepoData::for_channels(self.channels.len());
 
         // Loop until all pending package names have been fetched.
         loop {
@@ -154,8 +181,10 @@ impl GatewayQuery {
                             Subdir::Found(subdir) => subdir
                                 .get_or_fetch_package_records(&package_name, reporter)
                                 .await
-                                .map(|records| (channel_idx, specs, records)),
-                            Subdir::NotFound => Ok((channel_idx, specs, Arc::from(vec![]))),
+                                .map(|records| (channel_idx, package_name, specs, records)),
+                            Subdir::NotFound => {
+                                Ok((channel_idx, package_name, specs, Arc::from(vec![])))
+                            }
                         }
                     });
                 }
@@ -170,32 +199,38 @@ impl GatewayQuery {
 
                 // Handle any records that were fetched
                 records = pending_records.select_next_some() => {
-                    let (channel_idx, request_specs, records) = records?;
-
-                    if self.recursive {
-                        // Extract the dependencies from the records and recursively add them to the
-                        // list of package names that we need to fetch.
-                        for record in records.iter() {
-                            if !request_specs.iter().any(|spec| spec.matches(&record.package_record)) {
-                                // Do not recurse into records that do not match to root spec.
-                                continue;
+                    let (channel_idx, package_name, request_specs, records) = records?;
+
+                    if let Some(closure) = &mut closure {
+                        // The closure determines which dependencies to follow and which records
+                        // are returned.
+                        for name in closure.insert_records(package_name, channel_idx, records) {
+                            if seen.insert(name.clone()) {
+                                pending_package_specs.insert(name, Vec::new());
                             }
-                            for dependency in &record.package_record.depends {
-                                let dependency_name = PackageName::new_unchecked(
-                                    dependency.split_once(' ').unwrap_or((dependency, "")).0,
-                                );
-                                if seen.insert(dependency_name.clone()) {
-                                    pending_package_specs.insert(dependency_name.clone(), vec![dependency_name.into()]);
+                        }
+                    } else {
+                        if self.recursive {
+                            // Extract the dependencies from the records and recursively add them to the
+                            // list of package names that we need to fetch.
+                            for record in records.iter() {
+                                if !request_specs.iter().any(|spec| spec.matches(&record.package_record)) {
+                                    // Do not recurse into records that do not match to root spec.
+                                    continue;
+                                }
+                                for dependency in &record.package_record.depends {
+                                    let dependency_name = PackageName::new_unchecked(
+                                        dependency.split_once(' ').unwrap_or((dependency, "")).0,
+                                    );
+                                    if seen.insert(dependency_name.clone()) {
+                                        pending_package_specs.insert(dependency_name.clone(), vec![dependency_name.into()]);
+                                    }
                                 }
                             }
                         }
-                    }
 
-                    // Add the records to the result
-                    if records.len() > 0 {
-                        let result = &mut result[channel_idx];
-                        result.len += records.len();
-                        result.shards.push(records);
+                        // Add the records to the result
+                        result[channel_idx].push_shard(records);
                     }
                 }
 
@@ -207,6 +242,11 @@ impl GatewayQuery {
             }
         }
 
+        if let Some(closure) = closure {
+            return Ok(closure.into_repo_data(self.channels.len()));
+        }
+
+        result.iter_mut().for_each(RepoData::sort_shards);
         Ok(result)
     }
 }
@@ -1,4 +1,7 @@
-use super::{local_subdir::LocalSubdirClient, GatewayError, SourceConfig};
+use super::{
+    channel_variants::ChannelVariantsCache, indexed_subdir::IndexedSubdirClient,
+    local_subdir::LocalSubdirClient, GatewayError, SourceConfig,
+};
 use crate::fetch::{fetch_repo_data, FetchRepoDataError, FetchRepoDataOptions, Variant};
 use crate::gateway::error::SubdirNotFoundError;
 use crate::gateway::subdir::SubdirClient;
@@ -8,7 +11,7 @@ use reqwest_middleware::ClientWithMiddleware;
 use std::{path::PathBuf, sync::Arc};
 
 pub struct RemoteSubdirClient {
-    sparse: LocalSubdirClient,
+    inner: Box<dyn SubdirClient>,
 }
 
 impl RemoteSubdirClient {
@@ -18,10 +21,14 @@ impl RemoteSubdirClient {
         client: ClientWithMiddleware,
         cache_dir: PathBuf,
         source_config: SourceConfig,
+        channel_variants: &ChannelVariantsCache,
         reporter: Option<Arc<dyn Reporter>>,
     ) -> Result<Self, GatewayError> {
         let subdir_url = channel.platform_url(platform);
 
+        // Don't request variants that the server of the channel is known to not provide.
+        let variants = channel_variants.get(&channel).await;
+
         // Fetch the repodata from the remote server
         let repodata = fetch_repo_data(
             subdir_url,
@@ -31,8 +38,8 @@ impl RemoteSubdirClient {
                 cache_action: source_config.cache_action,
                 variant: Variant::default(),
                 jlap_enabled: source_config.jlap_enabled,
-                zstd_enabled: source_config.zstd_enabled,
-                bz2_enabled: source_config.bz2_enabled,
+                zstd_enabled: source_config.zstd_enabled && !variants.lacks_zst(),
+                bz2_enabled: source_config.bz2_enabled && !variants.lacks_bz2(),
             },
             reporter,
         )
@@ -48,6 +55,37 @@ impl RemoteSubdirClient {
             e => GatewayError::FetchRepoDataError(e),
         })?;
 
+        // Share what was learned about the variants with the other subdirectories of the channel.
+        channel_variants
+            .observe(
+                &channel,
+                repodata.cache_state.has_zst.as_ref(),
+                repodata.cache_state.has_bz2.as_ref(),
+            )
+            .await;
+
+        // Prefer reading records through an index of the cached repodata. Reading from the index
+        // only decompresses the records of the requested packages instead of parsing the entire
+        // file. The lock on the repodata is held while the index is (re)created.
+        if source_config.indexed_cache_enabled {
+            match IndexedSubdirClient::from_repodata_path(
+                &repodata.repo_data_json_path,
+                channel.clone(),
+                platform.as_str(),
+            )
+            .await
+            {
+                Ok(indexed) => {
+                    return Ok(Self {
+                        inner: Box::new(indexed),
+                    })
+                }
+                Err(err) => tracing::warn!(
+                    "failed to use the repodata index, falling back to the repodata.json: {err}"
+                ),
+            }
+        }
+
         // Create a new sparse repodata client that can be used to read records from the repodata.
         let sparse = LocalSubdirClient::from_channel_subdir(
             &repodata.repo_data_json_path,
@@ -56,7 +94,9 @@ impl RemoteSubdirClient {
         )
         .await?;
 
-        Ok(Self { sparse })
+        Ok(Self {
+            inner: Box::new(sparse),
+        })
     }
 }
 
@@ -67,6 +107,10 @@ impl SubdirClient for RemoteSubdirClient {
         name: &PackageName,
         reporter: Option<&dyn Reporter>,
     ) -> Result<Arc<[RepoDataRecord]>, GatewayError> {
-        self.sparse.fetch_package_records(name, reporter).await
+        self.inner.fetch_package_records(name, reporter).await
+    }
+
+    fn package_names(&self) -> Vec<String> {
+        self.inner.package_names()
     }
 }
@@ -9,13 +9,96 @@ use std::sync::Arc;
 ///
 /// `RepoData` uses internal reference counting, therefor it is relatively
 /// cheap to clone.
+///
+/// A query returns one instance per channel, in the order of the channels. The
+/// records of an instance are sorted by package name and subdirectory so the
+/// order does not depend on the order in which they were fetched.
 #[derive(Debug, Default, Clone)]
 pub struct RepoData {
     pub(crate) shards: Vec<Arc<[RepoDataRecord]>>,
     pub(crate) len: usize,
+    pub(crate) channel_index: usize,
+    pub(crate) channel_priority: usize,
+}
+
+/// A record returned from the [`super::Gateway`] together with the channel it
+/// was found in. See [`RepoData::iter_prioritized`].
+#[derive(Debug, Clone, Copy)]
+pub struct PrioritizedRecord<'r> {
+    /// The record.
+    pub record: &'r RepoDataRecord,
+
+    /// The index of the channel of the record in the channels of the query.
+    pub channel_index: usize,
+
+    /// The priority of the channel of the record, see
+    /// [`RepoData::channel_priority`].
+    pub channel_priority: usize,
 }
 
 impl RepoData {
+    /// Returns empty instances for the channels of a query, in the order of the
+    /// channels.
+    pub(crate) fn for_channels(channel_count: usize) -> Vec<Self> {
+        (0..channel_count)
+            .map(|channel_index| Self {
+                shards: Vec::new(),
+                len: 0,
+                channel_index,
+                channel_priority: channel_count - channel_index,
+            })
+            .collect()
+    }
+
+    /// Adds the records of a single package and subdirectory.
+    pub(crate) fn push_shard(&mut self, records: Arc<[RepoDataRecord]>) {
+        if !records.is_empty() {
+            self.len += records.len();
+            self.shards.push(records);
+        }
+    }
+
+    /// Sorts the shards by package name and subdirectory, so the order of the
+    /// records does not depend on the order in which they were fetched.
+    pub(crate) fn sort_shards(&mut self) {
+        self.shards.sort_by_cached_key(|shard| {
+            let record = &shard[0];
+            (
+                record.package_record.name.as_normalized().to_string(),
+                record.package_record.subdir.clone(),
+                record.url.to_string(),
+            )
+        });
+    }
+
+    /// Returns the index of the channel of the records in the channels of the
+    /// query.
+    pub fn channel_index(&self) -> usize {
+        self.channel_index
+    }
+
+    /// Returns the priority of the channel of the records. The first channel of
+    /// the query has the highest priority, which equals the number of
+    /// channels, and every next channel has a priority that is one lower.
+    pub fn channel_priority(&self) -> usize {
+        self.channel_priority
+    }
+
+    /// Returns an iterator over the records of all channels of a query,
+    /// annotated with their channel. The records are returned in the order of
+    /// the channels.
+    pub fn iter_prioritized(
+        repo_data: &[RepoData],
+    ) -> impl Iterator<Item = PrioritizedRecord<'_>> + '_ {
+        repo_data.iter().flat_map(|repo_data| {
+            repo_data.iter().map(|record| PrioritizedRecord {
+                record,
+                channel_index: repo_data.channel_index,
+                channel_priority: repo_data.channel_priority,
+            })
+        })
+    }
+
     /// Returns an iterator over all the records in this instance.
     pub fn iter(&self) -> RepoDataIterator<'_> {
         RepoDataIterator {
@@ -87,3 +170,76 @@ impl ExactSizeIterator for RepoDataIterator<'_> {
         self.records.len - self.total
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::gateway::Gateway;
+    use crate::RepoData;
+    use rattler_conda_types::{Channel, MatchSpec, ParseStrictness, Platform};
+    use std::path::Path;
+
+    const LINUX_64: &str = r#"{
+        "info": { "subdir": "linux-64" },
+        "packages": {
+            "foo-1.0-0.tar.bz2": { "name": "foo", "version": "1.0", "build": "0", "build_number": 0, "depends": ["bar"], "subdir": "linux-64" },
+            "bar-1.0-0.tar.bz2": { "name": "bar", "version": "1.0", "build": "0", "build_number": 0, "depends": [], "subdir": "linux-64" }
+        }
+    }"#;
+
+    const NOARCH: &str = r#"{
+        "info": { "subdir": "noarch" },
+        "packages": {
+            "bar-2.0-0.tar.bz2": { "name": "bar", "version": "2.0", "build": "0", "build_number": 0, "depends": [], "subdir": "noarch" }
+        }
+    }"#;
+
+    fn create_channel(dir: &Path) -> Channel {
+        for (subdir, repodata) in [("linux-64", LINUX_64), ("noarch", NOARCH)] {
+            let subdir = dir.join(subdir);
+            std::fs::create_dir_all(&subdir).unwrap();
+            std::fs::write(subdir.join("repodata.json"), repodata).unwrap();
+        }
+        Channel::from_directory(dir)
+    }
+
+    #[tokio::test]
+    async fn test_prioritized_records() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let channels = [create_channel(first.path()), create_channel(second.path())];
+
+        let gateway = Gateway::new();
+        let records = gateway
+            .query(
+                channels,
+                [Platform::NoArch, Platform::Linux64],
+                [MatchSpec::from_str("foo", ParseStrictness::Strict).unwrap()],
+            )
+            .recursive(true)
+            .await
+            .unwrap();
+
+        let prioritized = RepoData::iter_prioritized(&records)
+            .map(|record| {
+                (
+                    record.channel_index,
+                    record.channel_priority,
+                    record.record.file_name.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            prioritized,
+            [
+                (0, 2, "bar-1.0-0.tar.bz2"),
+                (0, 2, "bar-2.0-0.tar.bz2"),
+                (0, 2, "foo-1.0-0.tar.bz2"),
+                (1, 1, "bar-1.0-0.tar.bz2"),
+                (1, 1, "bar-2.0-0.tar.bz2"),
+                (1, 1, "foo-1.0-0.tar.bz2"),
+            ]
+        );
+        assert_eq!(records[1].channel_index(), 1);
+        assert_eq!(records[1].channel_priority(), 1);
+    }
+}
@@ -0,0 +1,271 @@
+use super::{subdir::Subdir, GatewayError, GatewayInner, RepoData};
+use crate::Reporter;
+use futures::{stream, FutureExt, StreamExt, TryStreamExt};
+use itertools::Itertools;
+use rattler_conda_types::{
+    Channel, MatchSpec, PackageName, ParseStrictness, Platform, RepoDataRecord,
+};
+use std::{collections::BTreeSet, future::IntoFuture, sync::Arc};
+
+/// The maximum number of packages whose records are fetched concurrently.
+const MAX_CONCURRENT_FETCHES: usize = 100;
+
+/// Represents a query for the records that depend on a package, executed with a
+/// [`super::Gateway`]. Construct it with [`super::Gateway::query_reverse_dependencies`].
+///
+/// Answering the query requires the records of every package in the channels. For sharded
+/// channels this fetches every shard, which is slow the first time but the shards are cached
+/// afterwards.
+///
+/// If the spec only names the package, every record that depends on any version of the package
+/// is returned. Otherwise only the records with a dependency on the package that can be satisfied
+/// by a record that matches the spec are returned. This tells which packages are affected if the
+/// records that match the spec are removed or patched. Run constraints (`constrains`) are not
+/// considered dependencies.
+#[derive(Clone)]
+pub struct ReverseDependencyQuery {
+    /// The gateway that manages all resources
+    gateway: Arc<GatewayInner>,
+
+    /// The channels to search
+    channels: Vec<Channel>,
+
+    /// The platforms to search
+    platforms: Vec<Platform>,
+
+    /// The spec of the package to find the dependents of
+    spec: MatchSpec,
+
+    /// The reporter to use by the query.
+    reporter: Option<Arc<dyn Reporter>>,
+}
+
+impl ReverseDependencyQuery {
+    /// Constructs a new instance. This should not be called directly, use
+    /// [`super::Gateway::query_reverse_dependencies`] instead.
+    pub(super) fn new(
+        gateway: Arc<GatewayInner>,
+        channels: Vec<Channel>,
+        platforms: Vec<Platform>,
+        spec: MatchSpec,
+    ) -> Self {
+        Self {
+            gateway,
+            channels,
+            platforms,
+            spec,
+            reporter: None,
+        }
+    }
+
+    /// Sets the reporter to use for this query.
+    ///
+    /// The reporter is notified of important evens during the execution of the
+    /// query. This allows reporting progress back to a user.
+    #[must_use]
+    pub fn with_reporter(self, reporter: impl Reporter + 'static) -> Self {
+        Self {
+            reporter: Some(Arc::new(reporter)),
+            ..self
+        }
+    }
+
+    /// Execute the query and return the records that depend on the package, per channel.
+    pub async fn execute(self) -> Result<Vec<RepoData>, GatewayError> {
+        let Some(name) = self.spec.name.clone() else {
+            return Err(GatewayError::MatchSpecWithoutName(Box::new(self.spec)));
+        };
+
+        // Load all the subdirectories.
+        let subdirs = futures::future::try_join_all(
+            self.channels
+                .iter()
+                .cloned()
+                .enumerate()
+                .cartesian_product(self.platforms.iter().copied())
+                .map(|((channel_idx, channel), platform)| {
+                    let gateway = self.gateway.clone();
+                    let reporter = self.reporter.clone();
+                    async move {
+                        gateway
+                            .get_or_create_subdir(&channel, platform, reporter)
+                            .await
+                            .map(|subdir| (channel_idx, subdir))
+                    }
+                }),
+        )
+        .await?;
+
+        // Unless any version of the package is requested, find the records that match the spec so
+        // dependencies can be checked against them.
+        let targets = if self.spec == MatchSpec::from(name.clone()) {
+            None
+        } else {
+            let mut targets = Vec::new();
+            for (_, subdir) in &subdirs {
+                if let Subdir::Found(subdir) = subdir.as_ref() {
+                    let records = subdir
+                        .get_or_fetch_package_records(&name, self.reporter.clone())
+                        .await?;
+                    targets.extend(
+                        records
+                            .iter()
+                            .filter(|record| self.spec.matches(&record.package_record))
+                            .cloned(),
+                    );
+                }
+            }
+            Some(targets)
+        };
+
+        // Fetch the records of all packages and keep the ones that depend on the package.
+        let mut fetches = Vec::new();
+        for (channel_idx, subdir) in &subdirs {
+            if let Subdir::Found(data) = subdir.as_ref() {
+                let names = data.package_names().into_iter().collect::<BTreeSet<_>>();
+                fetches.extend(
+                    names
+                        .into_iter()
+                        .map(|package_name| (*channel_idx, subdir.clone(), package_name)),
+                );
+            }
+        }
+        let reporter = self.reporter.clone();
+        let mut records = stream::iter(fetches)
+            .map(move |(channel_idx, subdir, package_name)| {
+                let reporter = reporter.clone();
+                async move {
+                    let Subdir::Found(subdir) = subdir.as_ref() else {
+                        return Ok((channel_idx, Arc::from(Vec::new())));
+                    };
+                    subdir
+                        .get_or_fetch_package_records(
+                            &PackageName::new_unchecked(package_name),
+                            reporter,
+                        )
+                        .await
+                        .map(|records| (channel_idx, records))
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_FETCHES);
+
+        let mut result = RepoData::for_channels(self.channels.len());
+        while let Some((channel_idx, records)) = records.try_next().await? {
+            let dependents = records
+                .iter()
+                .filter(|record| depends_on(record, &name, targets.as_deref()))
+                .cloned()
+                .collect::<Vec<_>>();
+            result[channel_idx].push_shard(dependents.into());
+        }
+
+        result.iter_mut().for_each(RepoData::sort_shards);
+        Ok(result)
+    }
+}
+
+/// Returns true if the `record` depends on the package with the given `name`. If `targets` is
+/// specified, the dependency must be satisfiable by one of the targets that is available for the
+/// subdirectory of the record.
+fn depends_on(
+    record: &RepoDataRecord,
+    name: &PackageName,
+    targets: Option<&[RepoDataRecord]>,
+) -> bool {
+    record.package_record.depends.iter().any(|dependency| {
+        let dependency_name = dependency
+            .split_once(' ')
+            .map_or(dependency.as_str(), |(name, _)| name);
+        if PackageName::new_unchecked(dependency_name) != *name {
+            return false;
+        }
+        let Some(targets) = targets else {
+            return true;
+        };
+        // A dependency that cannot be parsed is assumed to be satisfiable.
+        let Ok(spec) = MatchSpec::from_str(dependency, ParseStrictness::Lenient) else {
+            return true;
+        };
+        let subdir = &record.package_record.subdir;
+        targets.iter().any(|target| {
+            let target_subdir = &target.package_record.subdir;
+            (subdir == "noarch" || target_subdir == "noarch" || target_subdir == subdir)
+                && spec.matches(&target.package_record)
+        })
+    })
+}
+
+impl IntoFuture for ReverseDependencyQuery {
+    type Output = Result<Vec<RepoData>, GatewayError>;
+    type IntoFuture = futures::future::BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.execute().boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gateway::Gateway;
+    use crate::RepoData;
+    use rattler_conda_types::{Channel, MatchSpec, ParseStrictness, Platform};
+    use std::path::Path;
+
+    const LINUX_64: &str = r#"{
+        "info": { "subdir": "linux-64" },
+        "packages": {
+            "foo-1.0-0.tar.bz2": { "name": "foo", "version": "1.0", "build": "0", "build_number": 0, "depends": [], "subdir": "linux-64" },
+            "foo-2.0-0.tar.bz2": { "name": "foo", "version": "2.0", "build": "0", "build_number": 0, "depends": [], "subdir": "linux-64" },
+            "bar-1.0-0.tar.bz2": { "name": "bar", "version": "1.0", "build": "0", "build_number": 0, "depends": ["foo >=1,<2"], "subdir": "linux-64" },
+            "baz-1.0-0.tar.bz2": { "name": "baz", "version": "1.0", "build": "0", "build_number": 0, "depends": ["foo", "qux"], "subdir": "linux-64" },
+            "qux-1.0-0.tar.bz2": { "name": "qux", "version": "1.0", "build": "0", "build_number": 0, "depends": [], "constrains": ["foo <2"], "subdir": "linux-64" }
+        }
+    }"#;
+
+    const NOARCH: &str = r#"{
+        "info": { "subdir": "noarch" },
+        "packages": {},
+        "packages.conda": {
+            "foo-py-1.0-0.conda": { "name": "foo-py", "version": "1.0", "build": "0", "build_number": 0, "depends": ["foo >=2"], "subdir": "noarch" }
+        }
+    }"#;
+
+    fn names(records: &[RepoData]) -> Vec<String> {
+        let mut names = records
+            .iter()
+            .flat_map(RepoData::iter)
+            .map(|record| record.package_record.name.as_normalized().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_reverse_dependencies() {
+        let channel_dir = tempfile::tempdir().unwrap();
+        for (subdir, repodata) in [("linux-64", LINUX_64), ("noarch", NOARCH)] {
+            let subdir = channel_dir.path().join(subdir);
+            std::fs::create_dir_all(&subdir).unwrap();
+            std::fs::write(subdir.join("repodata.json"), repodata).unwrap();
+        }
+        let channel = Channel::from_directory(Path::new(channel_dir.path()));
+        let gateway = Gateway::new();
+        let query = |spec: &str| {
+            gateway.query_reverse_dependencies(
+                [channel.clone()],
+                [Platform::Linux64, Platform::NoArch],
+                MatchSpec::from_str(spec, ParseStrictness::Strict).unwrap(),
+            )
+        };
+
+        // Constraints are not dependencies.
+        assert_eq!(
+            names(&query("foo").await.unwrap()),
+            ["bar", "baz", "foo-py"]
+        );
+        assert_eq!(names(&query("foo >=2").await.unwrap()), ["baz", "foo-py"]);
+        assert_eq!(names(&query("foo 1.*").await.unwrap()), ["bar", "baz"]);
+        assert!(names(&query("foo >=3").await.unwrap()).is_empty());
+        assert_eq!(names(&query("qux").await.unwrap()), ["baz"]);
+    }
+}
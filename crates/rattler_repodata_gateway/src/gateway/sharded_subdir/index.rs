@@ -6,6 +6,7 @@ use bytes::Bytes;
 use futures::{FutureExt, TryFutureExt};
 use http::{HeaderMap, Method, Uri};
 use http_cache_semantics::{AfterResponse, BeforeRequest, CachePolicy, RequestLike};
+use rattler_networking::RedactedUrl;
 use reqwest::Response;
 use reqwest_middleware::ClientWithMiddleware;
 use serde::{Deserialize, Serialize};
@@ -89,7 +90,10 @@ pub async fn fetch_index(
                 .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
                 .map_err(|e| {
                     GatewayError::IoError(
-                        format!("failed to parse shard index from {response_url}"),
+                        format!(
+                            "failed to parse shard index from {}",
+                            RedactedUrl::from(&response_url)
+                        ),
                         e,
                     )
                 })
@@ -131,7 +135,7 @@ pub async fn fetch_index(
             .before_request(&canonical_request, SystemTime::now())
         {
             BeforeRequest::Fresh(_) => {
-                if let Ok(shard_index) = read_shard_index_from_reader(file).await {
+                if let Ok((shard_index, _)) = read_shard_index_from_reader(file).await {
                     tracing::debug!("shard index cache hit");
                     return Ok(shard_index);
                 }
@@ -171,12 +175,25 @@ pub async fn fetch_index(
                     &response,
                     SystemTime::now(),
                 ) {
-                    AfterResponse::NotModified(_policy, _) => {
+                    AfterResponse::NotModified(policy, _) => {
                         // The cached file is still valid
                         match read_shard_index_from_reader(file).await {
-                            Ok(shard_index) => {
+                            Ok((shard_index, bytes)) => {
                                 tracing::debug!("shard index cache was not modified");
                                 // If reading the file failed for some reason we'll just fetch it again.
+
+                                // Store the updated policy so the cached index is considered
+                                // fresh again and is not revalidated on every request.
+                                if policy.is_storable() {
+                                    if let Err(e) =
+                                        update_shard_index_cache(&cache_path, policy, bytes).await
+                                    {
+                                        tracing::warn!(
+                                            "failed to update the cached shard index at {}: {e}",
+                                            cache_path.display()
+                                        );
+                                    }
+                                }
                                 return Ok(shard_index);
                             }
                             Err(e) => {
@@ -269,24 +286,39 @@ async fn write_shard_index_cache(
     .await?
 }
 
-/// Read the shard index from a reader and deserialize it.
+/// Replaces the cache policy of the cached shard index, keeping the cached index itself.
+async fn update_shard_index_cache(
+    cache_path: &Path,
+    policy: CachePolicy,
+    decoded_bytes: Bytes,
+) -> std::io::Result<()> {
+    let temp_file = write_shard_index_cache(cache_path, policy, decoded_bytes).await?;
+    temp_file.persist(cache_path)?;
+    Ok(())
+}
+
+/// Read the shard index from a reader and deserialize it. Returns the index together with the
+/// bytes it was deserialized from.
 async fn read_shard_index_from_reader(
     mut reader: BufReader<File>,
-) -> Result<ShardedRepodata, GatewayError> {
+) -> Result<(ShardedRepodata, Bytes), GatewayError> {
     // Read the file to memory
     let mut bytes = Vec::new();
     reader
         .read_to_end(&mut bytes)
         .await
         .map_err(|e| GatewayError::IoError("failed to read shard index buffer".to_string(), e))?;
+    let bytes = Bytes::from(bytes);
 
     // Deserialize the bytes
-    run_blocking_task(move || {
-        rmp_serde::from_slice(&bytes)
+    let decoded_bytes = bytes.clone();
+    let shard_index = run_blocking_task(move || {
+        rmp_serde::from_slice(&decoded_bytes)
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
             .map_err(|e| GatewayError::IoError("failed to parse shard index".to_string(), e))
     })
-    .await
+    .await?;
+    Ok((shard_index, bytes))
 }
 
 /// Cache information stored at the start of the cache file.
@@ -3,12 +3,12 @@ use crate::reporter::ResponseReporterExt;
 use crate::utils::run_blocking_task;
 use crate::Reporter;
 use crate::{fetch::FetchRepoDataError, gateway::subdir::SubdirClient, GatewayError};
-use futures::TryFutureExt;
 use http::header::CACHE_CONTROL;
 use http::{HeaderValue, StatusCode};
 use rattler_conda_types::{Channel, PackageName, RepoDataRecord, Shard, ShardedRepodata};
+use rattler_digest::{compute_bytes_digest, Sha256};
 use reqwest_middleware::ClientWithMiddleware;
-use std::{borrow::Cow, path::PathBuf, sync::Arc};
+use std::{borrow::Cow, io::Write, path::PathBuf, sync::Arc};
 use token::TokenClient;
 use url::Url;
 
@@ -103,14 +103,21 @@ impl SubdirClient for ShardedSubdir {
         // Read the cached shard
         match tokio::fs::read(&shard_cache_path).await {
             Ok(cached_bytes) => {
-                // Decode the cached shard
-                return parse_records(
+                // Decode the cached shard. A cached shard that cannot be decoded is downloaded
+                // again.
+                match parse_records(
                     cached_bytes,
                     self.channel.canonical_name(),
                     self.sharded_repodata.info.base_url.clone(),
                 )
                 .await
-                .map(Arc::from);
+                {
+                    Ok(records) => return Ok(records.into()),
+                    Err(err) => tracing::warn!(
+                        "the cached shard {} is corrupted, downloading it again: {err}",
+                        shard_cache_path.display()
+                    ),
+                }
             }
             Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
                 // The file is missing from the cache, we need to download it.
@@ -159,12 +166,21 @@ impl SubdirClient for ShardedSubdir {
             bytes
         };
 
+        // Shards are addressed by the hash of their compressed bytes. Verify the hash so a shard
+        // is never cached under a hash that does not match its content.
+        let actual_hash = compute_bytes_digest::<Sha256>(&shard_bytes);
+        if actual_hash != *shard {
+            return Err(GatewayError::ShardHashMismatch {
+                url: shard_url,
+                expected: *shard,
+                actual: actual_hash,
+            });
+        }
+
         let shard_bytes = decode_zst_bytes_async(shard_bytes).await?;
 
         // Create a future to write the cached bytes to disk
-        let write_to_cache_fut = tokio::fs::write(&shard_cache_path, shard_bytes.clone())
-            .map_err(FetchRepoDataError::IoError)
-            .map_err(GatewayError::from);
+        let write_to_cache_fut = write_shard_to_cache(shard_cache_path, shard_bytes.clone());
 
         // Create a future to parse the records from the shard
         let parse_records_fut = parse_records(
@@ -178,6 +194,30 @@ impl SubdirClient for ShardedSubdir {
 
         Ok(records.into())
     }
+
+    fn package_names(&self) -> Vec<String> {
+        self.sharded_repodata.shards.keys().cloned().collect()
+    }
+}
+
+/// Writes a decoded shard to the cache. The shard is written to a temporary file first so a
+/// partially written shard is never read from the cache.
+async fn write_shard_to_cache(cache_path: PathBuf, bytes: Vec<u8>) -> Result<(), GatewayError> {
+    run_blocking_task(move || {
+        let cache_dir = cache_path
+            .parent()
+            .expect("the cache path must have a parent");
+        let mut temp_file =
+            tempfile::NamedTempFile::new_in(cache_dir).map_err(FetchRepoDataError::IoError)?;
+        temp_file
+            .write_all(&bytes)
+            .map_err(FetchRepoDataError::IoError)?;
+        temp_file
+            .persist(&cache_path)
+            .map_err(|e| FetchRepoDataError::IoError(e.error))?;
+        Ok::<_, GatewayError>(())
+    })
+    .await
 }
 
 async fn decode_zst_bytes_async<R: AsRef<[u8]> + Send + 'static>(
@@ -232,3 +272,114 @@ fn add_trailing_slash(url: &Url) -> Cow<'_, Url> {
         Cow::Owned(url)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::ShardedSubdir;
+    use crate::gateway::subdir::SubdirClient;
+    use crate::utils::simple_channel_server::SimpleChannelServer;
+    use crate::GatewayError;
+    use assert_matches::assert_matches;
+    use rattler_conda_types::{
+        Channel, PackageName, PackageRecord, Shard, ShardedRepodata, ShardedSubdirInfo,
+    };
+    use rattler_digest::{compute_bytes_digest, Sha256};
+    use std::{path::Path, sync::Arc};
+
+    /// Writes a compressed shard with a single record of the package to the subdirectory and
+    /// returns its hash.
+    fn write_shard(subdir: &Path, name: &str) -> rattler_digest::Sha256Hash {
+        let record: PackageRecord = serde_json::from_str(&format!(
+            r#"{{ "name": "{name}", "version": "1.0", "build": "0", "build_number": 0, "depends": [], "subdir": "linux-64" }}"#
+        ))
+        .unwrap();
+        let shard = Shard {
+            packages: [(format!("{name}-1.0-0.tar.bz2"), record)]
+                .into_iter()
+                .collect(),
+            conda_packages: Default::default(),
+            removed: Default::default(),
+        };
+        let bytes =
+            zstd::encode_all(rmp_serde::to_vec_named(&shard).unwrap().as_slice(), 0).unwrap();
+        let hash = compute_bytes_digest::<Sha256>(&bytes);
+        std::fs::write(subdir.join(format!("shards/{hash:x}.msgpack.zst")), bytes).unwrap();
+        hash
+    }
+
+    #[tokio::test]
+    async fn test_shard_cache() {
+        let channel_dir = tempfile::tempdir().unwrap();
+        let subdir = channel_dir.path().join("linux-64");
+        std::fs::create_dir_all(subdir.join("shards")).unwrap();
+        std::fs::write(subdir.join("token"), "{}").unwrap();
+        let foo_hash = write_shard(&subdir, "foo");
+
+        // The shard of `bar` is served with the content of another shard.
+        let bar_hash = write_shard(&subdir, "bar");
+        let baz_hash = write_shard(&subdir, "baz");
+        std::fs::copy(
+            subdir.join(format!("shards/{baz_hash:x}.msgpack.zst")),
+            subdir.join(format!("shards/{bar_hash:x}.msgpack.zst")),
+        )
+        .unwrap();
+
+        let server = SimpleChannelServer::new(channel_dir.path()).await;
+        let index = ShardedRepodata {
+            info: ShardedSubdirInfo {
+                subdir: "linux-64".to_string(),
+                base_url: server.url().join("linux-64/").unwrap(),
+            },
+            shards: [("foo".to_string(), foo_hash), ("bar".to_string(), bar_hash)]
+                .into_iter()
+                .collect(),
+        };
+        std::fs::write(
+            subdir.join("repodata_shards.msgpack.zst"),
+            zstd::encode_all(rmp_serde::to_vec_named(&index).unwrap().as_slice(), 0).unwrap(),
+        )
+        .unwrap();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let sharded_subdir = ShardedSubdir::new(
+            Channel::from_url(server.url()),
+            "linux-64".to_string(),
+            reqwest::Client::new().into(),
+            cache_dir.path().to_path_buf(),
+            Arc::new(tokio::sync::Semaphore::new(10)),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let foo = PackageName::new_unchecked("foo");
+        let records = sharded_subdir
+            .fetch_package_records(&foo, None)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+
+        // A corrupted shard in the cache is downloaded again.
+        let cached_shard = cache_dir
+            .path()
+            .join(format!("shards-v1/{foo_hash:x}.msgpack"));
+        std::fs::write(&cached_shard, b"corrupted").unwrap();
+        let records = sharded_subdir
+            .fetch_package_records(&foo, None)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_ne!(std::fs::read(&cached_shard).unwrap(), b"corrupted");
+
+        // A shard that does not match its hash is rejected and not cached.
+        let result = sharded_subdir
+            .fetch_package_records(&PackageName::new_unchecked("bar"), None)
+            .await;
+        assert_matches!(result, Err(GatewayError::ShardHashMismatch { expected, actual, .. })
+            if expected == bar_hash && actual == baz_hash);
+        assert!(!cache_dir
+            .path()
+            .join(format!("shards-v1/{bar_hash:x}.msgpack"))
+            .exists());
+    }
+}
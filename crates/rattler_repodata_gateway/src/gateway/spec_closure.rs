@@ -0,0 +1,208 @@
+use super::RepoData;
+use rattler_conda_types::{MatchSpec, PackageName, ParseStrictness, RepoDataRecord};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+
+/// The records fetched for a single package, together with the index of the channel they were
+/// fetched from.
+type FetchedRecords = Vec<(usize, Arc<[RepoDataRecord]>)>;
+
+/// Keeps track of the closure of a set of root specs.
+///
+/// For every package name in the closure this stores all the specs that were requested for it,
+/// either by the root specs or by the dependencies of a record in the closure. A record is part of
+/// the closure if it matches any of the specs requested for its name. Only the dependencies of
+/// records in the closure are followed.
+///
+/// Records are added as they are fetched, and because specs for a name can be discovered after its
+/// records have been fetched, adding a spec also follows the dependencies of the records that were
+/// already fetched.
+#[derive(Default)]
+pub(super) struct SpecClosure {
+    /// The specs that were requested for every package in the closure.
+    specs: HashMap<PackageName, Vec<MatchSpec>>,
+
+    /// The records that were fetched for every package.
+    records: HashMap<PackageName, FetchedRecords>,
+}
+
+impl SpecClosure {
+    /// Adds a spec to the closure. Returns the package names that were not part of the closure
+    /// before and whose records must be fetched.
+    pub fn insert_spec(&mut self, name: PackageName, spec: MatchSpec) -> Vec<PackageName> {
+        let mut new_names = Vec::new();
+        let mut queue = vec![(name, spec)];
+        while let Some((name, spec)) = queue.pop() {
+            let specs = match self.specs.entry(name.clone()) {
+                Entry::Vacant(entry) => {
+                    new_names.push(name.clone());
+                    entry.insert(Vec::new())
+                }
+                Entry::Occupied(entry) => entry.into_mut(),
+            };
+            if specs.contains(&spec) {
+                continue;
+            }
+            specs.push(spec.clone());
+
+            // Follow the dependencies of the records that were already fetched and that match the
+            // new spec.
+            for (_, records) in self.records.get(&name).into_iter().flatten() {
+                for record in records
+                    .iter()
+                    .filter(|record| spec.matches(&record.package_record))
+                {
+                    queue.extend(dependencies(record));
+                }
+            }
+        }
+        new_names
+    }
+
+    /// Adds the records fetched for a package from the channel with the given index. Returns the
+    /// package names that were not part of the closure before and whose records must be fetched.
+    pub fn insert_records(
+        &mut self,
+        name: PackageName,
+        channel_idx: usize,
+        records: Arc<[RepoDataRecord]>,
+    ) -> Vec<PackageName> {
+        let mut queue = Vec::new();
+        if let Some(specs) = self.specs.get(&name) {
+            for record in records.iter().filter(|record| {
+                specs
+                    .iter()
+                    .any(|spec| spec.matches(&record.package_record))
+            }) {
+                queue.extend(dependencies(record));
+            }
+        }
+        self.records
+            .entry(name)
+            .or_default()
+            .push((channel_idx, records));
+
+        queue
+            .into_iter()
+            .flat_map(|(name, spec)| self.insert_spec(name, spec))
+            .collect()
+    }
+
+    /// Returns the records in the closure per channel.
+    pub fn into_repo_data(self, channel_count: usize) -> Vec<RepoData> {
+        let mut result = RepoData::for_channels(channel_count);
+        for (name, fetched) in self.records {
+            let Some(specs) = self.specs.get(&name) else {
+                continue;
+            };
+            for (channel_idx, records) in fetched {
+                let records = records
+                    .iter()
+                    .filter(|record| {
+                        specs
+                            .iter()
+                            .any(|spec| spec.matches(&record.package_record))
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                result[channel_idx].push_shard(records.into());
+            }
+        }
+        result.iter_mut().for_each(RepoData::sort_shards);
+        result
+    }
+}
+
+/// Returns the names and specs of the dependencies of a record. Virtual packages are skipped
+/// because they are never part of a channel.
+fn dependencies(record: &RepoDataRecord) -> impl Iterator<Item = (PackageName, MatchSpec)> + '_ {
+    record
+        .package_record
+        .depends
+        .iter()
+        .filter_map(|dependency| {
+            let spec = MatchSpec::from_str(dependency, ParseStrictness::Lenient).ok()?;
+            let name = spec.name.clone()?;
+            Some((name, spec))
+        })
+        .filter(|(name, _)| !name.as_normalized().starts_with("__"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rattler_conda_types::{PackageRecord, Version};
+    use std::str::FromStr;
+
+    fn record(name: &str, version: &str, depends: &[&str]) -> RepoDataRecord {
+        let file_name = format!("{name}-{version}-0.conda");
+        let mut package_record = PackageRecord::new(
+            name.parse().unwrap(),
+            Version::from_str(version).unwrap(),
+            "0".to_string(),
+        );
+        package_record.depends = depends.iter().map(ToString::to_string).collect();
+        RepoDataRecord {
+            package_record,
+            url: format!("https://conda.anaconda.org/conda-forge/noarch/{file_name}")
+                .parse()
+                .unwrap(),
+            file_name,
+            channel: String::from("https://conda.anaconda.org/conda-forge/"),
+        }
+    }
+
+    fn spec(spec: &str) -> (PackageName, MatchSpec) {
+        let spec = MatchSpec::from_str(spec, ParseStrictness::Strict).unwrap();
+        (spec.name.clone().unwrap(), spec)
+    }
+
+    fn names(names: &[&str]) -> Vec<PackageName> {
+        names.iter().map(|name| name.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_spec_closure() {
+        let mut closure = SpecClosure::default();
+
+        let (name, root) = spec("foo >=2");
+        assert_eq!(closure.insert_spec(name.clone(), root), names(&["foo"]));
+
+        // Only the dependencies of the records that match the root spec are followed.
+        let foo = [
+            record("foo", "1.0", &["old-dep"]),
+            record("foo", "2.0", &["bar <2", "__glibc >=2.17"]),
+        ];
+        assert_eq!(
+            closure.insert_records(name, 0, Arc::from(foo)),
+            names(&["bar"])
+        );
+
+        let bar = [
+            record("bar", "1.0", &["baz"]),
+            record("bar", "2.0", &["qux"]),
+        ];
+        assert_eq!(
+            closure.insert_records("bar".parse().unwrap(), 0, Arc::from(bar)),
+            names(&["baz"])
+        );
+
+        // A spec for a package whose records were already fetched follows the dependencies of the
+        // records that match it.
+        let (name, bar_2) = spec("bar >=2");
+        assert_eq!(closure.insert_spec(name, bar_2), names(&["qux"]));
+
+        let repo_data = closure.into_repo_data(1);
+        let mut records = repo_data[0]
+            .iter()
+            .map(|record| record.file_name.as_str())
+            .collect::<Vec<_>>();
+        records.sort_unstable();
+        assert_eq!(
+            records,
+            ["bar-1.0-0.conda", "bar-2.0-0.conda", "foo-2.0-0.conda"]
+        );
+    }
+}
@@ -32,6 +32,11 @@ impl SubdirData {
         }
     }
 
+    /// Returns the names of all the packages in the subdirectory.
+    pub fn package_names(&self) -> Vec<String> {
+        self.client.package_names()
+    }
+
     pub async fn get_or_fetch_package_records(
         &self,
         name: &PackageName,
@@ -146,4 +151,7 @@ pub trait SubdirClient: Send + Sync {
         name: &PackageName,
         reporter: Option<&dyn Reporter>,
     ) -> Result<Arc<[RepoDataRecord]>, GatewayError>;
+
+    /// Returns the names of all the packages in the channel subdirectory.
+    fn package_names(&self) -> Vec<String>;
 }
@@ -64,6 +64,8 @@ pub mod fetch;
 mod reporter;
 #[cfg(feature = "sparse")]
 pub mod sparse;
+#[cfg(any(test, feature = "test-server"))]
+pub mod test_server;
 mod utils;
 pub use reporter::Reporter;
 
@@ -72,5 +74,6 @@ mod gateway;
 
 #[cfg(feature = "gateway")]
 pub use gateway::{
-    ChannelConfig, Gateway, GatewayBuilder, GatewayError, RepoData, SourceConfig, SubdirSelection,
+    ChannelConfig, Gateway, GatewayBuilder, GatewayError, PrioritizedRecord, RepoData,
+    SourceConfig, SubdirSelection,
 };
@@ -246,6 +246,105 @@ impl SparseRepoData {
     pub fn subdir(&self) -> &str {
         &self.subdir
     }
+
+    /// Returns the total number of records in this repodata file.
+    pub fn len(&self) -> usize {
+        let repo_data = self.inner.borrow_repo_data();
+        repo_data.packages.len() + repo_data.conda_packages.len()
+    }
+
+    /// Returns true if this repodata file does not contain any records.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the unparsed records for the specified package name.
+    ///
+    /// Unlike [`SparseRepoData::load_records`] this does not parse any records, the returned
+    /// [`LazyRecord`]s refer directly to the raw json of the underlying file. This makes it cheap
+    /// to inspect the filenames of all records of a package and only parse the ones that are
+    /// actually needed.
+    pub fn lazy_records<'a>(
+        &'a self,
+        package_name: &PackageName,
+    ) -> impl Iterator<Item = LazyRecord<'a>> + 'a {
+        let repo_data = self.inner.borrow_repo_data();
+        let name = package_name.as_normalized();
+        let packages = &repo_data.packages[repo_data
+            .packages
+            .equal_range_by(|(package, _)| package.package.cmp(name))];
+        let conda_packages = &repo_data.conda_packages[repo_data
+            .conda_packages
+            .equal_range_by(|(package, _)| package.package.cmp(name))];
+        packages
+            .iter()
+            .chain(conda_packages)
+            .map(move |&(filename, raw_json)| LazyRecord {
+                filename,
+                raw_json,
+                repo_data: self,
+            })
+    }
+
+    /// Returns all records in this repodata file without parsing them.
+    pub fn iter_lazy_records(&self) -> impl Iterator<Item = LazyRecord<'_>> + '_ {
+        let repo_data = self.inner.borrow_repo_data();
+        repo_data
+            .packages
+            .iter()
+            .chain(repo_data.conda_packages.iter())
+            .map(move |&(filename, raw_json)| LazyRecord {
+                filename,
+                raw_json,
+                repo_data: self,
+            })
+    }
+}
+
+/// A record of a [`SparseRepoData`] that has not been parsed yet.
+///
+/// The record borrows its filename and raw json directly from the memory mapped `repodata.json`,
+/// no allocations are made until [`LazyRecord::parse`] is called.
+#[derive(Clone, Copy)]
+pub struct LazyRecord<'a> {
+    filename: PackageFilename<'a>,
+    raw_json: &'a RawValue,
+    repo_data: &'a SparseRepoData,
+}
+
+impl<'a> LazyRecord<'a> {
+    /// Returns the filename of the record, e.g. `python-3.12.0-h1_0.conda`.
+    pub fn file_name(&self) -> &'a str {
+        self.filename.filename
+    }
+
+    /// Returns the name of the package as derived from the filename.
+    pub fn package_name(&self) -> &'a str {
+        self.filename.package
+    }
+
+    /// Returns the raw json of the record.
+    pub fn raw_json(&self) -> &'a str {
+        self.raw_json.get()
+    }
+
+    /// Parses the record.
+    pub fn parse(&self) -> io::Result<RepoDataRecord> {
+        let repo_data = self.repo_data.inner.borrow_repo_data();
+        let base_url = repo_data.info.as_ref().and_then(|i| i.base_url.as_deref());
+        let mut record = parse_record(
+            self.filename.filename,
+            self.raw_json,
+            base_url,
+            &self.repo_data.channel,
+            self.repo_data.channel.canonical_name(),
+            &self.repo_data.subdir,
+        )?;
+        if let Some(patch_fn) = self.repo_data.patch_record_fn {
+            patch_fn(&mut record.package_record);
+        }
+        Ok(record)
+    }
 }
 
 /// A serde compatible struct that only sparsely parses a repodata.json file.
@@ -284,24 +383,14 @@ fn parse_records<'i>(
         packages.equal_range_by(|(package, _)| package.package.cmp(package_name.as_normalized()));
     let mut result = Vec::with_capacity(package_indices.len());
     for (key, raw_json) in &packages[package_indices] {
-        let mut package_record: PackageRecord = serde_json::from_str(raw_json.get())?;
-        // Overwrite subdir if its empty
-        if package_record.subdir.is_empty() {
-            package_record.subdir = subdir.to_owned();
-        }
-        result.push(RepoDataRecord {
-            url: compute_package_url(
-                &channel
-                    .base_url
-                    .join(&format!("{}/", &package_record.subdir))
-                    .expect("failed determine repo_base_url"),
-                base_url,
-                key.filename,
-            ),
-            channel: channel_name.clone(),
-            package_record,
-            file_name: key.filename.to_owned(),
-        });
+        result.push(parse_record(
+            key.filename,
+            raw_json,
+            base_url,
+            channel,
+            channel_name.clone(),
+            subdir,
+        )?);
     }
 
     // Apply the patch function if one was specified
@@ -314,6 +403,35 @@ fn parse_records<'i>(
     Ok(result)
 }
 
+/// Parse a single record from its raw json
+fn parse_record(
+    filename: &str,
+    raw_json: &RawValue,
+    base_url: Option<&str>,
+    channel: &Channel,
+    channel_name: String,
+    subdir: &str,
+) -> io::Result<RepoDataRecord> {
+    let mut package_record: PackageRecord = serde_json::from_str(raw_json.get())?;
+    // Overwrite subdir if its empty
+    if package_record.subdir.is_empty() {
+        package_record.subdir = subdir.to_owned();
+    }
+    Ok(RepoDataRecord {
+        url: compute_package_url(
+            &channel
+                .base_url
+                .join(&format!("{}/", &package_record.subdir))
+                .expect("failed determine repo_base_url"),
+            base_url,
+            filename,
+        ),
+        channel: channel_name,
+        package_record,
+        file_name: filename.to_owned(),
+    })
+}
+
 /// A helper function that immediately loads the records for the given packages (and their dependencies).
 /// Records for the specified packages are loaded from the repodata files.
 /// The `patch_record_fn` is applied to each record after it has been parsed and can mutate the record after
@@ -412,6 +530,7 @@ fn deserialize_filename_and_raw_record<'d, D: Deserializer<'d>>(
 }
 
 /// A struct that holds both a filename and the part of the filename thats just the package name.
+#[derive(Clone, Copy)]
 struct PackageFilename<'i> {
     package: &'i str,
     filename: &'i str,
@@ -442,7 +561,7 @@ impl<'de> TryFrom<&'de str> for PackageFilename<'de> {
 
 #[cfg(test)]
 mod test {
-    use super::{load_repo_data_recursively, PackageFilename, SparseRepoData};
+    use super::{load_repo_data_recursively, LazyRecord, PackageFilename, SparseRepoData};
     use bytes::Bytes;
     use rattler_conda_types::{Channel, ChannelConfig, PackageName, RepoData, RepoDataRecord};
     use rstest::rstest;
@@ -631,4 +750,51 @@ mod test {
     fn test_deserialize_package_name(#[case] filename: &str, #[case] result: &str) {
         assert_eq!(PackageFilename::try_from(filename).unwrap().package, result);
     }
+
+    #[test]
+    fn test_lazy_records() {
+        let channel_config = ChannelConfig::default_with_root_dir(std::env::current_dir().unwrap());
+        let channel = Channel::from_str("conda-forge", &channel_config).unwrap();
+        let bytes = Bytes::from_static(
+            br#"{
+                "packages": {
+                    "clang-format-13-13.0.0-default_0.tar.bz2": { "name": "clang-format-13", "version": "13.0.0", "build": "default_0", "build_number": 0, "depends": [] },
+                    "clang-format-13.0.0-default_0.tar.bz2": { "name": "clang-format", "version": "13.0.0", "build": "default_0", "build_number": 0, "depends": [] }
+                },
+                "packages.conda": {
+                    "clang-format-14.0.0-default_0.conda": { "name": "clang-format", "version": "14.0.0", "build": "default_0", "build_number": 0, "depends": [] },
+                    "invalid-1.0-0.conda": { "name": "invalid", "version": "1.0" }
+                }
+            }"#,
+        );
+        let sparse = SparseRepoData::from_bytes(channel, "linux-64", bytes, None).unwrap();
+        assert_eq!(sparse.len(), 4);
+
+        let clang_format = PackageName::new_unchecked("clang-format");
+        let lazy = sparse.lazy_records(&clang_format).collect::<Vec<_>>();
+        assert_eq!(
+            lazy.iter().map(LazyRecord::file_name).collect::<Vec<_>>(),
+            [
+                "clang-format-13.0.0-default_0.tar.bz2",
+                "clang-format-14.0.0-default_0.conda"
+            ]
+        );
+        assert!(lazy
+            .iter()
+            .all(|record| record.package_name() == "clang-format"));
+        assert_eq!(
+            lazy.iter()
+                .map(|record| record.parse().unwrap())
+                .collect::<Vec<_>>(),
+            sparse.load_records(&clang_format).unwrap()
+        );
+
+        // Records are only parsed on demand, an invalid record does not affect the others.
+        let invalid = sparse
+            .iter_lazy_records()
+            .find(|record| record.package_name() == "invalid")
+            .unwrap();
+        assert!(invalid.raw_json().contains("\"invalid\""));
+        assert!(invalid.parse().is_err());
+    }
 }
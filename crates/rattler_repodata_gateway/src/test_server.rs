@@ -0,0 +1,514 @@
+//! A channel that is served over HTTP from memory, intended for tests. See [`TestChannel`].
+
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use bytes::Bytes;
+use rattler_conda_types::{
+    Channel, ChannelInfo, PackageRecord, RepoData, Shard, ShardedRepodata, ShardedSubdirInfo,
+};
+use rattler_digest::{compute_bytes_digest, Md5, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    future::IntoFuture,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+use url::Url;
+
+/// Describes a channel that can be served over HTTP from memory with [`TestChannel::serve`].
+///
+/// This allows exercising code that fetches repodata or packages without touching the network or
+/// the filesystem. The server can also be configured to respond slowly, to fail certain requests
+/// or to require authentication.
+///
+/// ```rust
+/// # use rattler_conda_types::{PackageName, PackageRecord};
+/// # use rattler_repodata_gateway::test_server::TestChannel;
+/// # async fn example() {
+/// let mut record = PackageRecord::new(
+///     PackageName::new_unchecked("foo"),
+///     "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+///     "0".to_string(),
+/// );
+/// record.subdir = "noarch".to_string();
+///
+/// let server = TestChannel::default()
+///     .with_package("foo-1.0-0.conda", record, b"archive".to_vec())
+///     .with_shards()
+///     .serve()
+///     .await;
+/// println!("serving {}", server.url());
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct TestChannel {
+    subdirs: BTreeMap<String, Vec<(String, PackageRecord)>>,
+    files: HashMap<String, Bytes>,
+    sharded: bool,
+    latency: Option<Duration>,
+    errors: HashMap<String, InjectedError>,
+    authorization: Option<HeaderValue>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InjectedError {
+    status: StatusCode,
+    remaining: Option<usize>,
+}
+
+/// A request that was received by a [`TestChannelServer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedRequest {
+    /// The method of the request.
+    pub method: Method,
+
+    /// The path of the request relative to the channel, e.g. `noarch/repodata.json`.
+    pub path: String,
+}
+
+impl TestChannel {
+    /// Adds a record to the repodata of the subdirectory of the record. The archive of the
+    /// package is not served. File names ending in `.conda` are added to the `packages.conda`
+    /// section of the repodata.
+    #[must_use]
+    pub fn with_record(mut self, file_name: impl Into<String>, record: PackageRecord) -> Self {
+        self.subdirs
+            .entry(record.subdir.clone())
+            .or_default()
+            .push((file_name.into(), record));
+        self
+    }
+
+    /// Adds a record to the repodata of the subdirectory of the record and serves `archive` as
+    /// the archive of the package. The size and hashes of the record are set from the archive.
+    #[must_use]
+    pub fn with_package(
+        mut self,
+        file_name: impl Into<String>,
+        mut record: PackageRecord,
+        archive: impl Into<Bytes>,
+    ) -> Self {
+        let file_name = file_name.into();
+        let archive = archive.into();
+        record.size = Some(archive.len() as u64);
+        record.sha256 = Some(compute_bytes_digest::<Sha256>(&archive));
+        record.md5 = Some(compute_bytes_digest::<Md5>(&archive));
+        self.files
+            .insert(format!("{}/{file_name}", record.subdir), archive);
+        self.with_record(file_name, record)
+    }
+
+    /// Serves `content` at `path`, relative to the channel. This can be used to serve additional
+    /// files, like a compressed variant of the repodata. Files that are generated by the server,
+    /// like the `repodata.json`, cannot be replaced.
+    #[must_use]
+    pub fn with_file(mut self, path: impl Into<String>, content: impl Into<Bytes>) -> Self {
+        self.files.insert(path.into(), content.into());
+        self
+    }
+
+    /// Also serves the repodata of every subdirectory as sharded repodata.
+    #[must_use]
+    pub fn with_shards(mut self) -> Self {
+        self.sharded = true;
+        self
+    }
+
+    /// Delays every response by the given duration.
+    #[must_use]
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Responds to every request for `path` with the given status code.
+    #[must_use]
+    pub fn with_error(mut self, path: impl Into<String>, status: StatusCode) -> Self {
+        self.errors.insert(
+            path.into(),
+            InjectedError {
+                status,
+                remaining: None,
+            },
+        );
+        self
+    }
+
+    /// Responds to the first `times` requests for `path` with the given status code. This can be
+    /// used to test retry behavior.
+    #[must_use]
+    pub fn with_transient_error(
+        mut self,
+        path: impl Into<String>,
+        status: StatusCode,
+        times: usize,
+    ) -> Self {
+        self.errors.insert(
+            path.into(),
+            InjectedError {
+                status,
+                remaining: Some(times),
+            },
+        );
+        self
+    }
+
+    /// Requires requests to authenticate with the given bearer token.
+    #[must_use]
+    pub fn with_bearer_token(mut self, token: &str) -> Self {
+        self.authorization =
+            Some(HeaderValue::from_str(&format!("Bearer {token}")).expect("invalid token"));
+        self
+    }
+
+    /// Requires requests to authenticate with the given basic authentication credentials.
+    #[must_use]
+    pub fn with_basic_auth(mut self, username: &str, password: &str) -> Self {
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"));
+        self.authorization = Some(
+            HeaderValue::from_str(&format!("Basic {credentials}")).expect("invalid credentials"),
+        );
+        self
+    }
+
+    /// Starts serving the channel on a random port of localhost. The server is stopped when the
+    /// returned [`TestChannelServer`] is dropped.
+    pub async fn serve(self) -> TestChannelServer {
+        // Listen on a random port so multiple servers can run at the same time.
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let local_addr = listener.local_addr().unwrap();
+        let url = Url::parse(&format!("http://localhost:{}/", local_addr.port())).unwrap();
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let state = Arc::new(ServerState {
+            files: self.files(&url),
+            latency: self.latency,
+            errors: Mutex::new(self.errors),
+            authorization: self.authorization,
+            requests: requests.clone(),
+        });
+        let app = axum::Router::new().fallback(handle).with_state(state);
+
+        let (tx, rx) = oneshot::channel();
+        let server = axum::serve(listener, app)
+            .with_graceful_shutdown(async {
+                rx.await.ok();
+            })
+            .into_future();
+        tokio::spawn(server);
+
+        TestChannelServer {
+            url,
+            requests,
+            shutdown_sender: Some(tx),
+        }
+    }
+
+    /// Returns all files that are served by the channel, keyed by their path.
+    fn files(&self, url: &Url) -> HashMap<String, Bytes> {
+        let mut files = self.files.clone();
+
+        // Every channel has a `noarch` subdirectory.
+        let mut subdirs = self.subdirs.clone();
+        subdirs.entry("noarch".to_string()).or_default();
+
+        for (subdir, records) in subdirs {
+            let mut repodata = RepoData {
+                info: Some(ChannelInfo {
+                    subdir: subdir.clone(),
+                    base_url: None,
+                }),
+                packages: HashMap::default(),
+                conda_packages: HashMap::default(),
+                removed: HashSet::default(),
+                version: Some(2),
+            };
+            for (file_name, record) in records {
+                if file_name.ends_with(".conda") {
+                    repodata.conda_packages.insert(file_name, record);
+                } else {
+                    repodata.packages.insert(file_name, record);
+                }
+            }
+
+            if self.sharded {
+                let mut shards: BTreeMap<String, Shard> = BTreeMap::new();
+                for (packages, is_conda) in [
+                    (&repodata.packages, false),
+                    (&repodata.conda_packages, true),
+                ] {
+                    for (file_name, record) in packages {
+                        let shard = shards
+                            .entry(record.name.as_normalized().to_string())
+                            .or_insert_with(|| Shard {
+                                packages: HashMap::default(),
+                                conda_packages: HashMap::default(),
+                                removed: HashSet::default(),
+                            });
+                        let shard_packages = if is_conda {
+                            &mut shard.conda_packages
+                        } else {
+                            &mut shard.packages
+                        };
+                        shard_packages.insert(file_name.clone(), record.clone());
+                    }
+                }
+
+                let mut index = ShardedRepodata {
+                    info: ShardedSubdirInfo {
+                        subdir: subdir.clone(),
+                        base_url: url.join(&format!("{subdir}/")).unwrap(),
+                    },
+                    shards: HashMap::default(),
+                };
+                for (name, shard) in shards {
+                    let bytes = compress_msgpack(&shard);
+                    let hash = compute_bytes_digest::<Sha256>(&bytes);
+                    files.insert(format!("{subdir}/shards/{hash:x}.msgpack.zst"), bytes);
+                    index.shards.insert(name, hash);
+                }
+                files.insert(
+                    format!("{subdir}/repodata_shards.msgpack.zst"),
+                    compress_msgpack(&index),
+                );
+                files.insert(format!("{subdir}/token"), Bytes::from_static(b"{}"));
+            }
+
+            files.insert(
+                format!("{subdir}/repodata.json"),
+                serde_json::to_vec(&repodata).unwrap().into(),
+            );
+        }
+
+        files
+    }
+}
+
+/// Serializes the value as zstd compressed msgpack, the format of sharded repodata.
+fn compress_msgpack(value: &impl serde::Serialize) -> Bytes {
+    let bytes = rmp_serde::to_vec_named(value).unwrap();
+    zstd::encode_all(bytes.as_slice(), 0).unwrap().into()
+}
+
+struct ServerState {
+    files: HashMap<String, Bytes>,
+    latency: Option<Duration>,
+    errors: Mutex<HashMap<String, InjectedError>>,
+    authorization: Option<HeaderValue>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl ServerState {
+    /// Returns the status code to respond with if an error was injected for the path.
+    fn take_error(&self, path: &str) -> Option<StatusCode> {
+        let mut errors = self.errors.lock().unwrap();
+        let error = errors.get_mut(path)?;
+        match &mut error.remaining {
+            None => Some(error.status),
+            Some(0) => None,
+            Some(remaining) => {
+                *remaining -= 1;
+                Some(error.status)
+            }
+        }
+    }
+}
+
+async fn handle(
+    State(state): State<Arc<ServerState>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    let path = uri.path().trim_start_matches('/').to_string();
+    state.requests.lock().unwrap().push(RecordedRequest {
+        method,
+        path: path.clone(),
+    });
+
+    if let Some(latency) = state.latency {
+        tokio::time::sleep(latency).await;
+    }
+
+    if let Some(authorization) = &state.authorization {
+        if headers.get(AUTHORIZATION) != Some(authorization) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    if let Some(status) = state.take_error(&path) {
+        return status.into_response();
+    }
+
+    match state.files.get(&path) {
+        Some(content) => content.clone().into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// A running server of a [`TestChannel`]. The server is stopped when this instance is dropped.
+pub struct TestChannelServer {
+    url: Url,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    shutdown_sender: Option<oneshot::Sender<()>>,
+}
+
+impl TestChannelServer {
+    /// Returns the url of the channel.
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    /// Returns the served channel.
+    pub fn channel(&self) -> Channel {
+        Channel::from_url(self.url())
+    }
+
+    /// Returns the requests that the server received so far, in the order in which they were
+    /// received.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Returns true if the server received a request for `path`.
+    pub fn was_requested(&self, path: &str) -> bool {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|request| request.path == path)
+    }
+}
+
+impl Drop for TestChannelServer {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown_sender.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TestChannel;
+    use axum::http::StatusCode;
+    use rattler_conda_types::{PackageName, PackageRecord, RepoData, Version};
+    use std::str::FromStr;
+
+    fn record(name: &str, subdir: &str) -> PackageRecord {
+        let mut record = PackageRecord::new(
+            PackageName::new_unchecked(name),
+            Version::from_str("1.0").unwrap(),
+            "0".to_string(),
+        );
+        record.subdir = subdir.to_string();
+        record
+    }
+
+    #[tokio::test]
+    async fn test_serve_channel() {
+        let server = TestChannel::default()
+            .with_package(
+                "foo-1.0-0.conda",
+                record("foo", "linux-64"),
+                b"foo".to_vec(),
+            )
+            .with_transient_error("linux-64/foo-1.0-0.conda", StatusCode::BAD_GATEWAY, 1)
+            .with_error("noarch/missing.conda", StatusCode::FORBIDDEN)
+            .with_basic_auth("user", "password")
+            .serve()
+            .await;
+        let client = reqwest::Client::new();
+        let get = |path: &str| {
+            client
+                .get(server.url().join(path).unwrap())
+                .basic_auth("user", Some("password"))
+                .send()
+        };
+
+        let repodata: RepoData = get("linux-64/repodata.json")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let foo = &repodata.conda_packages["foo-1.0-0.conda"];
+        assert_eq!(foo.size, Some(3));
+        assert!(foo.sha256.is_some());
+        assert_eq!(
+            get("noarch/repodata.json").await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        // Injected errors
+        let archive = get("linux-64/foo-1.0-0.conda").await.unwrap();
+        assert_eq!(archive.status(), StatusCode::BAD_GATEWAY);
+        let archive = get("linux-64/foo-1.0-0.conda").await.unwrap();
+        assert_eq!(archive.bytes().await.unwrap().as_ref(), b"foo");
+        assert_eq!(
+            get("noarch/missing.conda").await.unwrap().status(),
+            StatusCode::FORBIDDEN
+        );
+
+        // Authentication is required.
+        let response = client
+            .get(server.url().join("noarch/repodata.json").unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        assert!(server.was_requested("linux-64/repodata.json"));
+        assert_eq!(server.requests().len(), 6);
+    }
+
+    #[cfg(feature = "gateway")]
+    #[tokio::test]
+    async fn test_serve_sharded_channel() {
+        use crate::Gateway;
+        use rattler_conda_types::{MatchSpec, ParseStrictness, Platform};
+
+        let mut foo = record("foo", "linux-64");
+        foo.depends = vec!["bar".to_string()];
+        let server = TestChannel::default()
+            .with_record("foo-1.0-0.tar.bz2", foo)
+            .with_record("bar-1.0-0.conda", record("bar", "noarch"))
+            .with_record("baz-1.0-0.conda", record("baz", "noarch"))
+            .with_shards()
+            .serve()
+            .await;
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let gateway = Gateway::builder().with_cache_dir(cache_dir.path()).finish();
+        let records = gateway
+            .query(
+                [server.channel()],
+                [Platform::Linux64, Platform::NoArch],
+                [MatchSpec::from_str("foo", ParseStrictness::Strict).unwrap()],
+            )
+            .recursive(true)
+            .await
+            .unwrap();
+
+        let total_records: usize = records.iter().map(crate::RepoData::len).sum();
+        assert_eq!(total_records, 2);
+
+        // Only the shards of the required packages are downloaded.
+        assert!(server.was_requested("linux-64/repodata_shards.msgpack.zst"));
+        assert!(!server.was_requested("linux-64/repodata.json"));
+        let shard_requests = server
+            .requests()
+            .into_iter()
+            .filter(|request| request.path.contains("/shards/"))
+            .count();
+        assert_eq!(shard_requests, 2);
+    }
+}
@@ -2,7 +2,7 @@
 
 //! This crate provides helper functions to activate and deactivate virtual environments.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::process::ExitStatus;
 use std::{
     fs,
@@ -11,6 +11,7 @@ use std::{
 
 use crate::shell::{Shell, ShellScript};
 use indexmap::IndexMap;
+use itertools::Itertools;
 use rattler_conda_types::Platform;
 
 const ENV_START_SEPERATOR: &str = "____RATTLER_ENV_START____";
@@ -39,15 +40,43 @@ pub struct ActivationVariables {
 
     /// The type of behavior of what should happen with the defined paths.
     pub path_modification_behavior: PathModificationBehavior,
+
+    /// The value of the `CONDA_SHLVL` environment variable, the number of environments on the
+    /// activation stack.
+    pub conda_shlvl: Option<u32>,
+
+    /// The environment below the active environment on the activation stack, the value of the
+    /// `CONDA_PREFIX_<CONDA_SHLVL - 1>` environment variable. Used by [`Activator::deactivation`].
+    pub previous_prefix: Option<PathBuf>,
+
+    /// True if the active environment was stacked on top of the previous environment, the value
+    /// of the `CONDA_STACKED_<CONDA_SHLVL>` environment variable. Used by
+    /// [`Activator::deactivation`].
+    pub is_stacked: bool,
+
+    /// Activate the environment on top of the active environment instead of replacing it. The
+    /// paths and environment variables of the active environment are preserved.
+    pub stack: bool,
 }
 
 impl ActivationVariables {
     /// Create a new `ActivationVariables` struct from the environment variables.
     pub fn from_env() -> Result<Self, std::env::VarError> {
+        let conda_shlvl = std::env::var("CONDA_SHLVL")
+            .ok()
+            .and_then(|shlvl| shlvl.parse::<u32>().ok());
+        let level = conda_shlvl.unwrap_or(0);
         Ok(Self {
             conda_prefix: std::env::var("CONDA_PREFIX").ok().map(PathBuf::from),
             path: None,
             path_modification_behavior: PathModificationBehavior::Prepend,
+            conda_shlvl,
+            previous_prefix: level
+                .checked_sub(1)
+                .and_then(|previous| std::env::var(format!("CONDA_PREFIX_{previous}")).ok())
+                .map(PathBuf::from),
+            is_stacked: std::env::var(format!("CONDA_STACKED_{level}")).is_ok_and(|v| v == "true"),
+            stack: false,
         })
     }
 }
@@ -337,6 +366,55 @@ impl<T: Shell + Clone> Activator<T> {
         })
     }
 
+    /// Computes the modifications of the environment that activation performs.
+    fn activation_plan(
+        &self,
+        variables: &ActivationVariables,
+    ) -> Result<ActivationPlan, ActivationError> {
+        let mut plan = ActivationPlan::default();
+
+        let mut path = variables.path.clone().unwrap_or_default();
+        let shlvl = variables
+            .conda_shlvl
+            .unwrap_or(u32::from(variables.conda_prefix.is_some()));
+        let stacked = variables.stack && variables.conda_prefix.is_some();
+        if let Some(conda_prefix) = &variables.conda_prefix {
+            // A stacked environment keeps the paths and variables of the active environment.
+            if !stacked {
+                let deactivate =
+                    Activator::from_path(conda_prefix, self.shell_type.clone(), self.platform)?;
+                plan.unset = deactivate.env_vars.into_keys().collect();
+                plan.deactivation_scripts = deactivate.deactivation_scripts;
+                path.retain(|x| !deactivate.paths.contains(x));
+            }
+        }
+
+        // prepend new paths
+        plan.path = [self.paths.clone(), path].concat();
+
+        if let Some(conda_prefix) = &variables.conda_prefix {
+            // Remember the active environment so deactivation can return to it.
+            plan.set.insert(
+                format!("CONDA_PREFIX_{shlvl}"),
+                conda_prefix.to_string_lossy().into_owned(),
+            );
+        }
+        plan.set.insert(
+            "CONDA_PREFIX".to_string(),
+            self.target_prefix.to_string_lossy().into_owned(),
+        );
+        plan.set
+            .insert("CONDA_SHLVL".to_string(), (shlvl + 1).to_string());
+        if stacked {
+            plan.set
+                .insert(format!("CONDA_STACKED_{}", shlvl + 1), "true".to_string());
+        }
+        plan.set.extend(self.env_vars.clone());
+        plan.activation_scripts = self.activation_scripts.clone();
+
+        Ok(plan)
+    }
+
     /// Create an activation script for a given shell and platform. This
     /// returns a tuple of the newly computed PATH variable and the activation script.
     pub fn activation(
@@ -344,40 +422,142 @@ impl<T: Shell + Clone> Activator<T> {
         variables: ActivationVariables,
     ) -> Result<ActivationResult<T>, ActivationError> {
         let mut script = ShellScript::new(self.shell_type.clone(), self.platform);
+        let plan = self.activation_plan(&variables)?;
 
-        let mut path = variables.path.clone().unwrap_or_default();
-        if let Some(conda_prefix) = variables.conda_prefix {
-            let deactivate = Activator::from_path(
-                Path::new(&conda_prefix),
-                self.shell_type.clone(),
-                self.platform,
-            )?;
+        for key in &plan.unset {
+            script.unset_env_var(key)?;
+        }
 
-            for (key, _) in &deactivate.env_vars {
-                script.unset_env_var(key)?;
-            }
+        for deactivation_script in &plan.deactivation_scripts {
+            script.run_script(deactivation_script)?;
+        }
 
-            for deactivation_script in &deactivate.deactivation_scripts {
-                script.run_script(deactivation_script)?;
-            }
+        script.set_path(plan.path.as_slice(), variables.path_modification_behavior)?;
 
-            path.retain(|x| !deactivate.paths.contains(x));
+        for (key, value) in &plan.set {
+            script.set_env_var(key, value)?;
         }
 
-        // prepend new paths
-        let path = [self.paths.clone(), path].concat();
+        for activation_script in &plan.activation_scripts {
+            script.run_script(activation_script)?;
+        }
+
+        Ok(ActivationResult {
+            script,
+            path: plan.path,
+        })
+    }
+
+    /// Computes the environment variables that activation sets, without generating a shell
+    /// script. The result can be written as JSON or as a `.env` file, so the activation can be
+    /// consumed by tools that don't run a shell.
+    ///
+    /// The `PATH` variable contains the paths of the environment and the entries of
+    /// [`ActivationVariables::path`], ordered according to
+    /// [`ActivationVariables::path_modification_behavior`]. Because there is no shell to expand
+    /// `$PATH`, the `PATH` of the current process is used when the behavior is to prepend or
+    /// append and [`ActivationVariables::path`] is not set. Activation scripts of packages can't
+    /// be represented this way, they are listed in [`ActivationEnvironment::activation_scripts`]
+    /// but are not executed.
+    pub fn activation_environment(
+        &self,
+        mut variables: ActivationVariables,
+    ) -> Result<ActivationEnvironment, ActivationError> {
+        let behavior = variables.path_modification_behavior.clone();
+        if variables.path.is_none() && !matches!(behavior, PathModificationBehavior::Replace) {
+            variables.path =
+                std::env::var_os("PATH").map(|path| std::env::split_paths(&path).collect());
+        }
+        let mut plan = self.activation_plan(&variables)?;
+        if matches!(behavior, PathModificationBehavior::Append) {
+            plan.path.rotate_left(self.paths.len());
+        }
+        let separator = if self.platform.is_windows() { ";" } else { ":" };
+
+        let mut set = IndexMap::new();
+        set.insert(
+            "PATH".to_string(),
+            plan.path
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .join(separator),
+        );
+        set.extend(plan.set);
 
-        script.set_path(path.as_slice(), variables.path_modification_behavior)?;
+        Ok(ActivationEnvironment {
+            set,
+            unset: plan.unset,
+            path: plan.path,
+            activation_scripts: plan.activation_scripts,
+        })
+    }
 
-        // deliberately not taking care of `CONDA_SHLVL` or any other complications at this point
-        script.set_env_var("CONDA_PREFIX", &self.target_prefix.to_string_lossy())?;
+    /// Create a script that deactivates this environment, which must be the active environment,
+    /// and pops exactly one level from the activation stack. If this environment replaced the
+    /// previous environment, the previous environment is activated again. If it was stacked on
+    /// top of the previous environment, only the paths and variables of this environment are
+    /// removed.
+    ///
+    /// The `PATH` is only modified if [`ActivationVariables::path`] is set, in which case it is
+    /// replaced by the computed list of paths.
+    pub fn deactivation(
+        &self,
+        variables: ActivationVariables,
+    ) -> Result<ActivationResult<T>, ActivationError> {
+        let mut script = ShellScript::new(self.shell_type.clone(), self.platform);
 
-        for (key, value) in &self.env_vars {
-            script.set_env_var(key, value)?;
+        for deactivation_script in &self.deactivation_scripts {
+            script.run_script(deactivation_script)?;
         }
 
-        for activation_script in &self.activation_scripts {
-            script.run_script(activation_script)?;
+        for (key, _) in &self.env_vars {
+            script.unset_env_var(key)?;
+        }
+
+        let mut path = variables.path.clone().unwrap_or_default();
+        path.retain(|x| !self.paths.contains(x));
+
+        let shlvl = variables.conda_shlvl.unwrap_or(1);
+        let previous_level = shlvl.saturating_sub(1);
+
+        // Reactivate the previous environment if this environment replaced it.
+        let reactivate = match &variables.previous_prefix {
+            Some(previous_prefix) if !variables.is_stacked => Some(Activator::from_path(
+                previous_prefix,
+                self.shell_type.clone(),
+                self.platform,
+            )?),
+            _ => None,
+        };
+        if let Some(previous) = &reactivate {
+            path = [previous.paths.clone(), path].concat();
+        }
+
+        if variables.path.is_some() {
+            script.set_path(path.as_slice(), PathModificationBehavior::Replace)?;
+        }
+
+        match &variables.previous_prefix {
+            Some(previous_prefix) if previous_level > 0 => {
+                script.set_env_var("CONDA_PREFIX", &previous_prefix.to_string_lossy())?;
+                script.unset_env_var(&format!("CONDA_PREFIX_{previous_level}"))?;
+            }
+            _ => {
+                script.unset_env_var("CONDA_PREFIX")?;
+            }
+        }
+        script.set_env_var("CONDA_SHLVL", &previous_level.to_string())?;
+        if variables.is_stacked {
+            script.unset_env_var(&format!("CONDA_STACKED_{shlvl}"))?;
+        }
+
+        if let Some(previous) = &reactivate {
+            for (key, value) in &previous.env_vars {
+                script.set_env_var(key, value)?;
+            }
+            for activation_script in &previous.activation_scripts {
+                script.run_script(activation_script)?;
+            }
         }
 
         Ok(ActivationResult { script, path })
@@ -389,6 +569,25 @@ impl<T: Shell + Clone> Activator<T> {
         &self,
         variables: ActivationVariables,
     ) -> Result<HashMap<String, String>, ActivationError> {
+        let diff = self.run_activation_diff(variables)?;
+        Ok(diff
+            .added
+            .into_iter()
+            .chain(
+                diff.changed
+                    .into_iter()
+                    .map(|(key, change)| (key, change.new)),
+            )
+            .collect())
+    }
+
+    /// Runs the activation script in the shell and returns the variables that were added, changed
+    /// and removed by it. The diff can be applied to another environment with
+    /// [`EnvironmentDiff::apply`] without sourcing the activation script.
+    pub fn run_activation_diff(
+        &self,
+        variables: ActivationVariables,
+    ) -> Result<EnvironmentDiff, ActivationError> {
         let activation_script = self.activation(variables)?.script;
 
         // Create a script that starts by emitting all environment variables, then runs the
@@ -440,16 +639,180 @@ impl<T: Shell + Clone> Activator<T> {
         let before_env = self.shell_type.parse_env(before_env);
         let after_env = self.shell_type.parse_env(after_env);
 
-        // Find and return the differences
-        Ok(after_env
-            .into_iter()
-            .filter(|(key, value)| before_env.get(key) != Some(value))
-            // this happens on Windows for some reason
-            // @SET "=C:=C:\Users\robostack\Programs\pixi"
-            // @SET "=ExitCode=00000000"
-            .filter(|(key, _)| !key.is_empty())
-            .map(|(key, value)| (key.to_owned(), value.to_owned()))
-            .collect())
+        Ok(EnvironmentDiff::between(&before_env, &after_env))
+    }
+}
+
+/// The modifications of the environment that are performed by activation, in order.
+#[derive(Default)]
+struct ActivationPlan {
+    unset: Vec<String>,
+    deactivation_scripts: Vec<PathBuf>,
+    path: Vec<PathBuf>,
+    set: IndexMap<String, String>,
+    activation_scripts: Vec<PathBuf>,
+}
+
+/// The environment variables of an activated environment. Created by
+/// [`Activator::activation_environment`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivationEnvironment {
+    /// The variables that are set, including `PATH`, in the order in which they are set.
+    pub set: IndexMap<String, String>,
+
+    /// The variables of the previously active environment that are unset.
+    pub unset: Vec<String>,
+
+    /// The entries of the `PATH` variable.
+    pub path: Vec<PathBuf>,
+
+    /// The activation scripts of the packages in the environment. These are not reflected in the
+    /// variables.
+    pub activation_scripts: Vec<PathBuf>,
+}
+
+impl ActivationEnvironment {
+    /// Returns the environment as a JSON object with the keys `set`, `unset`, `path` and
+    /// `activation_scripts`.
+    pub fn to_json(&self) -> String {
+        let value = serde_json::json!({
+            "set": self.set,
+            "unset": self.unset,
+            "path": self.path.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>(),
+            "activation_scripts": self
+                .activation_scripts
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>(),
+        });
+        serde_json::to_string_pretty(&value).expect("serializing strings cannot fail")
+    }
+
+    /// Returns the environment in the `.env` format, one `KEY=VALUE` line per variable. Values
+    /// are single quoted so they are taken literally, unless they contain a single quote or a
+    /// line break in which case they are double quoted and escaped, including `$` and backticks
+    /// so they are not expanded. Variables that are unset are
+    /// listed in a comment because the format has no way to unset a variable.
+    pub fn to_dotenv(&self) -> String {
+        let mut dotenv = String::new();
+        for key in &self.unset {
+            dotenv.push_str(&format!("# unset {key}\n"));
+        }
+        for (key, value) in &self.set {
+            dotenv.push_str(&format!("{key}={}\n", quote_dotenv(value)));
+        }
+        dotenv
+    }
+}
+
+/// Quotes a value for a `.env` file.
+fn quote_dotenv(value: &str) -> String {
+    if !value.contains(['\'', '\n', '\r']) {
+        return format!("'{value}'");
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '$' => quoted.push_str("\\$"),
+            '`' => quoted.push_str("\\`"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A variable whose value was changed by activation, see [`EnvironmentDiff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedEnvVar {
+    /// The value before activation.
+    pub old: String,
+
+    /// The value after activation.
+    pub new: String,
+}
+
+/// The difference between the environment variables before and after activation. Created by
+/// [`Activator::run_activation_diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvironmentDiff {
+    /// Variables that did not exist before activation, with their new value.
+    pub added: BTreeMap<String, String>,
+
+    /// Variables whose value was changed by activation.
+    pub changed: BTreeMap<String, ChangedEnvVar>,
+
+    /// Variables that were removed by activation, with their old value.
+    pub removed: BTreeMap<String, String>,
+}
+
+impl EnvironmentDiff {
+    /// Computes the difference between two sets of environment variables.
+    pub fn between(before: &HashMap<&str, &str>, after: &HashMap<&str, &str>) -> Self {
+        // Variables with an empty name show up on Windows for some reason
+        // @SET "=C:=C:\Users\robostack\Programs\pixi"
+        // @SET "=ExitCode=00000000"
+        let mut diff = Self::default();
+        for (&key, &value) in after.iter().filter(|(key, _)| !key.is_empty()) {
+            match before.get(key) {
+                None => {
+                    diff.added.insert(key.to_owned(), value.to_owned());
+                }
+                Some(&old) if old != value => {
+                    diff.changed.insert(
+                        key.to_owned(),
+                        ChangedEnvVar {
+                            old: old.to_owned(),
+                            new: value.to_owned(),
+                        },
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        for (&key, &value) in before.iter().filter(|(key, _)| !key.is_empty()) {
+            if !after.contains_key(key) {
+                diff.removed.insert(key.to_owned(), value.to_owned());
+            }
+        }
+        diff
+    }
+
+    /// Returns true if activation did not change any variable.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// Applies the diff to the given environment variables.
+    pub fn apply(&self, env: &mut HashMap<String, String>) {
+        for (key, value) in &self.added {
+            env.insert(key.clone(), value.clone());
+        }
+        for (key, change) in &self.changed {
+            env.insert(key.clone(), change.new.clone());
+        }
+        for key in self.removed.keys() {
+            env.remove(key);
+        }
+    }
+
+    /// Applies the diff to the environment of a command that inherits the environment of the
+    /// current process.
+    pub fn apply_to_command(&self, command: &mut std::process::Command) {
+        command.envs(&self.added);
+        command.envs(
+            self.changed
+                .iter()
+                .map(|(key, change)| (key.as_str(), change.new.as_str())),
+        );
+        for key in self.removed.keys() {
+            command.env_remove(key);
+        }
     }
 }
 
@@ -466,6 +829,170 @@ mod tests {
     use crate::activation::PathModificationBehavior;
     use crate::shell::ShellEnum;
 
+    #[test]
+    #[cfg(unix)]
+    fn test_stacked_activation() {
+        let base = create_temp_dir();
+        let env = create_temp_dir();
+        let base_bin = base.path().join("bin");
+        let activator = Activator::from_path(env.path(), shell::Bash, Platform::Linux64).unwrap();
+
+        let result = activator
+            .activation(ActivationVariables {
+                conda_prefix: Some(base.path().to_path_buf()),
+                path: Some(vec![base_bin.clone(), PathBuf::from("/usr/bin")]),
+                path_modification_behavior: PathModificationBehavior::Replace,
+                conda_shlvl: Some(1),
+                stack: true,
+                ..ActivationVariables::default()
+            })
+            .unwrap();
+        assert_eq!(
+            result.path,
+            vec![
+                env.path().join("bin"),
+                base_bin.clone(),
+                PathBuf::from("/usr/bin")
+            ]
+        );
+        let script = result.script.contents().unwrap();
+        assert!(script.contains(&format!(
+            "export CONDA_PREFIX_1=\"{}\"",
+            base.path().display()
+        )));
+        assert!(script.contains("export CONDA_SHLVL=\"2\""));
+        assert!(script.contains("export CONDA_STACKED_2=\"true\""));
+
+        // Deactivating pops the stacked environment but keeps the base environment.
+        let result = activator
+            .deactivation(ActivationVariables {
+                path: Some(result.path),
+                conda_shlvl: Some(2),
+                previous_prefix: Some(base.path().to_path_buf()),
+                is_stacked: true,
+                ..ActivationVariables::default()
+            })
+            .unwrap();
+        assert_eq!(result.path, vec![base_bin, PathBuf::from("/usr/bin")]);
+        let script = result.script.contents().unwrap();
+        assert!(script.contains(&format!(
+            "export CONDA_PREFIX=\"{}\"",
+            base.path().display()
+        )));
+        assert!(script.contains("unset CONDA_PREFIX_1"));
+        assert!(script.contains("export CONDA_SHLVL=\"1\""));
+        assert!(script.contains("unset CONDA_STACKED_2"));
+    }
+
+    #[test]
+    fn test_activation_environment() {
+        let tdir = create_temp_dir();
+        let env_vars_dir = tdir.path().join("etc/conda/env_vars.d");
+        fs::create_dir_all(&env_vars_dir).unwrap();
+        fs::write(
+            env_vars_dir.join("pkg.json"),
+            r#"{"QUOTED": "it's", "PLAIN": "a $b", "EXPANDED": "it's $HOME `id`"}"#,
+        )
+        .unwrap();
+        let activator = Activator::from_path(tdir.path(), shell::Bash, Platform::Linux64).unwrap();
+
+        let environment = activator
+            .activation_environment(ActivationVariables {
+                path: Some(vec![PathBuf::from("/usr/bin")]),
+                ..ActivationVariables::default()
+            })
+            .unwrap();
+        assert_eq!(
+            environment.set["PATH"],
+            format!("{}:/usr/bin", tdir.path().join("bin").display())
+        );
+        assert_eq!(environment.set["PLAIN"], "a $b");
+
+        let dotenv = environment.to_dotenv();
+        assert!(dotenv.contains("PLAIN='a $b'\n"));
+        assert!(dotenv.contains("QUOTED=\"it's\"\n"));
+        assert!(dotenv.contains("CONDA_SHLVL='1'\n"));
+        assert!(dotenv.contains("EXPANDED=\"it's \\$HOME \\`id\\`\"\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&environment.to_json()).unwrap();
+        assert_eq!(json["set"]["PLAIN"], "a $b");
+        assert_eq!(json["activation_scripts"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_activation_environment_path_modification() {
+        let tdir = create_temp_dir();
+        let bin = tdir.path().join("bin");
+        let activator = Activator::from_path(tdir.path(), shell::Bash, Platform::Linux64).unwrap();
+
+        let path_of = |path_modification_behavior| {
+            let environment = activator
+                .activation_environment(ActivationVariables {
+                    path: Some(vec![PathBuf::from("/usr/bin")]),
+                    path_modification_behavior,
+                    ..ActivationVariables::default()
+                })
+                .unwrap();
+            let json: serde_json::Value = serde_json::from_str(&environment.to_json()).unwrap();
+            assert_eq!(json["set"]["PATH"], environment.set["PATH"]);
+            environment.path
+        };
+
+        assert_eq!(
+            path_of(PathModificationBehavior::Prepend),
+            vec![bin.clone(), PathBuf::from("/usr/bin")]
+        );
+        assert_eq!(
+            path_of(PathModificationBehavior::Append),
+            vec![PathBuf::from("/usr/bin"), bin.clone()]
+        );
+
+        // Without an explicit `PATH` the paths of the current process are kept.
+        let environment = activator
+            .activation_environment(ActivationVariables {
+                path_modification_behavior: PathModificationBehavior::Prepend,
+                ..ActivationVariables::default()
+            })
+            .unwrap();
+        let current = std::env::split_paths(&std::env::var_os("PATH").unwrap()).collect_vec();
+        assert_eq!(environment.path, [vec![bin.clone()], current].concat());
+        let environment = activator
+            .activation_environment(ActivationVariables::default())
+            .unwrap();
+        assert_eq!(environment.path, vec![bin]);
+    }
+
+    #[test]
+    fn test_environment_diff() {
+        let before = HashMap::from([("KEEP", "1"), ("CHANGE", "old"), ("REMOVE", "x"), ("", "y")]);
+        let after = HashMap::from([("KEEP", "1"), ("CHANGE", "new"), ("ADD", "z")]);
+        let diff = EnvironmentDiff::between(&before, &after);
+        assert_eq!(
+            diff.added,
+            BTreeMap::from([("ADD".to_string(), "z".to_string())])
+        );
+        assert_eq!(
+            diff.changed["CHANGE"],
+            ChangedEnvVar {
+                old: "old".to_string(),
+                new: "new".to_string()
+            }
+        );
+        assert_eq!(
+            diff.removed,
+            BTreeMap::from([("REMOVE".to_string(), "x".to_string())])
+        );
+
+        let mut env = before
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        diff.apply(&mut env);
+        assert_eq!(env.get("ADD").map(String::as_str), Some("z"));
+        assert_eq!(env.get("CHANGE").map(String::as_str), Some("new"));
+        assert!(!env.contains_key("REMOVE"));
+    }
+
     #[test]
     fn test_collect_scripts() {
         let tdir = TempDir::new("test").unwrap();
@@ -599,6 +1126,7 @@ mod tests {
                     PathBuf::from("/usr/local/bin"),
                 ]),
                 path_modification_behavior,
+                ..ActivationVariables::default()
             })
             .unwrap();
         let prefix = tdir.path().to_str().unwrap();
@@ -674,6 +1202,17 @@ mod tests {
         insta::assert_snapshot!(script);
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_activation_script_nushell() {
+        let script = get_script(shell::NuShell, PathModificationBehavior::Append);
+        insta::assert_snapshot!("test_activation_script_nushell_append", script);
+        let script = get_script(shell::NuShell, PathModificationBehavior::Replace);
+        insta::assert_snapshot!("test_activation_script_nushell_replace", script);
+        let script = get_script(shell::NuShell, PathModificationBehavior::Prepend);
+        insta::assert_snapshot!("test_activation_script_nushell_prepend", script);
+    }
+
     fn test_run_activation(shell: ShellEnum, with_unicode: bool) {
         let environment_dir = tempfile::TempDir::new().unwrap();
 
@@ -733,6 +1272,7 @@ mod tests {
 
         // Remove system specific environment variables.
         env_diff.remove("CONDA_PREFIX");
+        env_diff.remove("CONDA_SHLVL");
         env_diff.remove("Path");
         env_diff.remove("PATH");
 
@@ -772,6 +1312,13 @@ mod tests {
         test_run_activation(crate::shell::Fish.into(), false);
     }
 
+    #[test]
+    #[cfg(unix)]
+    #[ignore]
+    fn test_run_activation_nushell() {
+        test_run_activation(crate::shell::NuShell.into(), false);
+    }
+
     #[test]
     #[cfg(unix)]
     #[ignore]
@@ -0,0 +1,406 @@
+//! Caching of rendered activation scripts. See [`ActivationCache`].
+
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fmt::Write as _, fs};
+
+use rattler_conda_types::Platform;
+use rattler_digest::{compute_bytes_digest, Sha256};
+
+use crate::activation::{
+    ActivationError, ActivationVariables, Activator, PathModificationBehavior,
+};
+use crate::shell::Shell;
+
+/// The directories of a prefix whose contents determine the activation of the prefix.
+const ACTIVATION_STATE_DIRS: [&str; 4] = [
+    "conda-meta",
+    "etc/conda/activate.d",
+    "etc/conda/deactivate.d",
+    "etc/conda/env_vars.d",
+];
+
+/// The default maximum number of entries of an [`ActivationCache`].
+pub const DEFAULT_MAX_ENTRIES: usize = 64;
+
+/// Error that can occur when reading or writing the activation cache.
+#[derive(thiserror::Error, Debug)]
+pub enum ActivationCacheError {
+    /// Computing the activation failed.
+    #[error(transparent)]
+    ActivationError(#[from] ActivationError),
+
+    /// Reading the state of the prefix or writing a cache entry failed.
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// An activation script that was rendered by [`ActivationCache::activation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedActivation {
+    /// The contents of the activation script.
+    pub script: String,
+
+    /// The new path entries that are added to the PATH environment variable.
+    pub path: Vec<PathBuf>,
+
+    /// True if the script was read from the cache instead of being computed.
+    pub from_cache: bool,
+}
+
+/// A cache of rendered activation scripts.
+///
+/// Computing the activation of an environment requires walking the `etc/conda/activate.d`
+/// directories and reading the environment variables of the packages, which is repeated on every
+/// launch of a shell. The cache stores the rendered script per prefix, shell, platform and set of
+/// [`ActivationVariables`]. Every entry records a hash of the state of the prefix, derived from the
+/// names, sizes and modification times of the files in `conda-meta` and the activation
+/// directories, and a hash of [`ActivationVariables::path`]. When the environment or the `PATH`
+/// changes the hash changes and the entry is recomputed.
+///
+/// The cache holds at most [`ActivationCache::with_max_entries`] entries, the least recently used
+/// entries are removed when a new entry is written.
+#[derive(Debug, Clone)]
+pub struct ActivationCache {
+    cache_dir: PathBuf,
+    max_entries: usize,
+}
+
+impl ActivationCache {
+    /// Constructs a cache that stores its entries in the given directory. The directory is created
+    /// when the first entry is written.
+    pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            cache_dir: cache_dir.into(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+
+    /// Sets the maximum number of entries in the cache. Defaults to [`DEFAULT_MAX_ENTRIES`].
+    #[must_use]
+    pub fn with_max_entries(self, max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            ..self
+        }
+    }
+
+    /// The directory that contains the entries of the cache.
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// Returns the activation script of the prefix, either from the cache or by computing it with
+    /// [`Activator::activation`] and storing the result in the cache.
+    pub fn activation<T: Shell + Clone + 'static>(
+        &self,
+        prefix: &Path,
+        shell: T,
+        platform: Platform,
+        variables: ActivationVariables,
+    ) -> Result<CachedActivation, ActivationCacheError> {
+        let entry_path = self.entry_path(prefix, &shell, platform, &variables);
+
+        // The deactivation scripts of the active environment are part of the script, so its state
+        // is part of the state of the entry. The `PATH` ends up in the script as well but changes
+        // often, so instead of creating an entry per `PATH` it invalidates the entry.
+        let mut state = prefix_state_hash(prefix)?;
+        if let Some(conda_prefix) = &variables.conda_prefix {
+            if !variables.stack {
+                state.push_str(&prefix_state_hash(conda_prefix)?);
+            }
+        }
+        state.push_str(&path_hash(variables.path.as_deref()));
+
+        if let Some(cached) = read_entry(&entry_path, &state) {
+            touch(&entry_path);
+            return Ok(cached);
+        }
+
+        let activator = Activator::from_path(prefix, shell, platform)?;
+        let result = activator.activation(variables)?;
+        let activation = CachedActivation {
+            script: result
+                .script
+                .contents()
+                .map_err(ActivationError::FailedToWriteActivationScript)?,
+            path: result.path,
+            from_cache: false,
+        };
+        self.write_entry(&entry_path, &state, &activation)?;
+        self.evict();
+        Ok(activation)
+    }
+
+    /// Removes all entries from the cache.
+    pub fn clear(&self) -> std::io::Result<()> {
+        match fs::remove_dir_all(&self.cache_dir) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the path of the entry for the given inputs of the activation. The `PATH` is not
+    /// part of the key, it is part of the state of the entry instead.
+    fn entry_path(
+        &self,
+        prefix: &Path,
+        shell: &impl Shell,
+        platform: Platform,
+        variables: &ActivationVariables,
+    ) -> PathBuf {
+        let optional_path = |path: &Option<PathBuf>| {
+            path.as_deref()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let path_modification_behavior = match variables.path_modification_behavior {
+            PathModificationBehavior::Replace => "replace",
+            PathModificationBehavior::Append => "append",
+            PathModificationBehavior::Prepend => "prepend",
+        };
+        let key = [
+            prefix.to_string_lossy().into_owned(),
+            shell.executable().to_string(),
+            platform.to_string(),
+            optional_path(&variables.conda_prefix),
+            path_modification_behavior.to_string(),
+            variables
+                .conda_shlvl
+                .map(|shlvl| shlvl.to_string())
+                .unwrap_or_default(),
+            optional_path(&variables.previous_prefix),
+            variables.is_stacked.to_string(),
+            variables.stack.to_string(),
+        ]
+        .join("\0");
+        let hash = compute_bytes_digest::<Sha256>(key);
+        self.cache_dir.join(format!("{hash:x}.json"))
+    }
+
+    /// Removes the least recently used entries if the cache holds more than the maximum number of
+    /// entries. Failing to remove an entry is not an error, it is retried on the next write.
+    fn evict(&self) {
+        let Ok(entries) = fs::read_dir(&self.cache_dir) else {
+            return;
+        };
+        let mut entries = entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect::<Vec<_>>();
+        if entries.len() <= self.max_entries {
+            return;
+        }
+        entries.sort();
+        for (_, path) in &entries[..entries.len() - self.max_entries] {
+            if let Err(e) = fs::remove_file(path) {
+                tracing::debug!(
+                    "failed to evict activation cache entry {}: {e}",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    fn write_entry(
+        &self,
+        entry_path: &Path,
+        state: &str,
+        activation: &CachedActivation,
+    ) -> std::io::Result<()> {
+        let entry = serde_json::json!({
+            "state": state,
+            "script": activation.script,
+            "path": activation.path,
+        });
+
+        // Write the entry atomically so concurrently launched shells never read a partial entry.
+        fs::create_dir_all(&self.cache_dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(&self.cache_dir)?;
+        file.write_all(entry.to_string().as_bytes())?;
+        file.persist(entry_path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+/// Marks the entry at the given path as recently used.
+fn touch(entry_path: &Path) {
+    if let Ok(file) = fs::File::options().write(true).open(entry_path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+/// Computes a hash of the entries of the `PATH` that distinguishes an unset `PATH` from an empty
+/// one.
+fn path_hash(path: Option<&[PathBuf]>) -> String {
+    let key = match path {
+        None => String::new(),
+        Some(path) => path
+            .iter()
+            .map(|path| format!("{}\0", path.to_string_lossy()))
+            .collect(),
+    };
+    let hash = compute_bytes_digest::<Sha256>(format!("{}{key}", u8::from(path.is_some())));
+    format!("{hash:x}")
+}
+
+/// Reads the entry at the given path. Returns `None` if the entry doesn't exist, cannot be read or
+/// was created for a different state of the prefix.
+fn read_entry(entry_path: &Path, state: &str) -> Option<CachedActivation> {
+    let contents = fs::read_to_string(entry_path).ok()?;
+    let entry: serde_json::Value = serde_json::from_str(&contents).ok()?;
+    if entry.get("state")?.as_str()? != state {
+        tracing::debug!("activation cache entry {} is stale", entry_path.display());
+        return None;
+    }
+    Some(CachedActivation {
+        script: entry.get("script")?.as_str()?.to_string(),
+        path: entry
+            .get("path")?
+            .as_array()?
+            .iter()
+            .map(|path| path.as_str().map(PathBuf::from))
+            .collect::<Option<_>>()?,
+        from_cache: true,
+    })
+}
+
+/// Computes a hash of the state of the prefix that changes whenever packages are installed or
+/// removed, or the activation scripts or environment variables of the prefix are modified.
+pub fn prefix_state_hash(prefix: &Path) -> std::io::Result<String> {
+    let mut state = String::new();
+    for dir in ACTIVATION_STATE_DIRS {
+        let entries = match fs::read_dir(prefix.join(dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            files.push((entry.file_name(), metadata.len(), modified.as_nanos()));
+        }
+        files.sort();
+
+        for (name, len, modified) in files {
+            writeln!(state, "{dir}/{}\0{len}\0{modified}", name.to_string_lossy())
+                .expect("writing to a string cannot fail");
+        }
+    }
+    Ok(format!("{:x}", compute_bytes_digest::<Sha256>(state)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell;
+
+    #[test]
+    fn test_activation_cache() {
+        let prefix = tempdir::TempDir::new("rattler_shell_prefix").unwrap();
+        let cache_dir = tempdir::TempDir::new("rattler_shell_cache").unwrap();
+        let env_vars_dir = prefix.path().join("etc/conda/env_vars.d");
+        fs::create_dir_all(&env_vars_dir).unwrap();
+        fs::write(env_vars_dir.join("a.json"), r#"{"FOO": "1"}"#).unwrap();
+
+        let cache = ActivationCache::new(cache_dir.path());
+        let activate = || {
+            cache
+                .activation(
+                    prefix.path(),
+                    shell::Bash,
+                    Platform::Linux64,
+                    ActivationVariables::default(),
+                )
+                .unwrap()
+        };
+
+        let first = activate();
+        assert!(!first.from_cache);
+        assert!(first.script.contains("export FOO=\"1\""));
+
+        let second = activate();
+        assert!(second.from_cache);
+        assert_eq!(second.script, first.script);
+        assert_eq!(second.path, first.path);
+
+        // Adding a package to the environment invalidates the entry.
+        fs::write(env_vars_dir.join("b.json"), r#"{"BAR": "2"}"#).unwrap();
+        let third = activate();
+        assert!(!third.from_cache);
+        assert!(third.script.contains("export BAR=\"2\""));
+
+        cache.clear().unwrap();
+        assert!(!activate().from_cache);
+    }
+
+    #[test]
+    fn test_activation_cache_path() {
+        let prefix = tempdir::TempDir::new("rattler_shell_prefix").unwrap();
+        let cache_dir = tempdir::TempDir::new("rattler_shell_cache").unwrap();
+
+        let cache = ActivationCache::new(cache_dir.path());
+        let activate = |path: &str| {
+            cache
+                .activation(
+                    prefix.path(),
+                    shell::Bash,
+                    Platform::Linux64,
+                    ActivationVariables {
+                        path: Some(vec![PathBuf::from(path)]),
+                        ..ActivationVariables::default()
+                    },
+                )
+                .unwrap()
+        };
+
+        let first = activate("/usr/bin");
+        assert!(first.path.contains(&PathBuf::from("/usr/bin")));
+        assert!(activate("/usr/bin").from_cache);
+
+        // A different `PATH` replaces the entry instead of adding one.
+        let second = activate("/opt/bin");
+        assert!(!second.from_cache);
+        assert!(second.path.contains(&PathBuf::from("/opt/bin")));
+        assert!(!second.path.contains(&PathBuf::from("/usr/bin")));
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_activation_cache_eviction() {
+        let cache_dir = tempdir::TempDir::new("rattler_shell_cache").unwrap();
+        let cache = ActivationCache::new(cache_dir.path()).with_max_entries(2);
+        let prefixes = (0..3)
+            .map(|_| tempdir::TempDir::new("rattler_shell_prefix").unwrap())
+            .collect::<Vec<_>>();
+        let activate = |prefix: &tempdir::TempDir| {
+            cache
+                .activation(
+                    prefix.path(),
+                    shell::Bash,
+                    Platform::Linux64,
+                    ActivationVariables::default(),
+                )
+                .unwrap()
+        };
+
+        for prefix in &prefixes {
+            assert!(!activate(prefix).from_cache);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 2);
+
+        // The oldest entry was evicted.
+        assert!(activate(&prefixes[2]).from_cache);
+        assert!(!activate(&prefixes[0]).from_cache);
+    }
+}
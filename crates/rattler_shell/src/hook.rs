@@ -0,0 +1,324 @@
+//! Generation of shell hooks. A hook is a snippet that a user adds to the startup file of their
+//! shell. It defines a function that wraps an executable, so that `<function> activate <env>`
+//! modifies the environment of the running shell. See [`ShellHook`].
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::shell::{quote_nu, quote_xonsh, ShellEnum};
+
+/// The subcommands of the wrapped executable whose output is evaluated by the hook.
+const EVALUATED_SUBCOMMANDS: [&str; 2] = ["activate", "deactivate"];
+
+/// An error that can occur when generating a shell hook.
+#[derive(Debug, Error)]
+pub enum ShellHookError {
+    /// The shell cannot define functions that modify the environment of the running shell.
+    #[error("{0} does not support shell hooks")]
+    UnsupportedShell(String),
+
+    /// The name of the function is not a valid identifier.
+    #[error("'{0}' is not a valid function name")]
+    InvalidFunctionName(String),
+
+    /// Writing the hook failed.
+    #[error(transparent)]
+    FormatError(#[from] std::fmt::Error),
+}
+
+/// Generates the hook that defines a shell function wrapping an executable.
+///
+/// The function forwards all arguments to the executable. If the first argument is `activate` or
+/// `deactivate`, `--shell <name>` is appended to the arguments and the output of the executable is
+/// evaluated in the running shell, so the executable is expected to print an activation script
+/// for that shell, e.g. the one created by [`crate::activation::Activator::activation`]. Nushell
+/// cannot evaluate code at runtime, so for `nu` the executable is instead expected to print a
+/// JSON object like the one created by [`crate::activation::ActivationEnvironment::to_json`].
+///
+/// Hooks are supported for all shells except `cmd.exe`.
+///
+/// # Example
+///
+/// ```
+/// use rattler_shell::{hook::ShellHook, shell};
+///
+/// let hook = ShellHook::new("/usr/local/bin/rattler")
+///     .script(&shell::Bash.into())
+///     .unwrap();
+/// assert!(hook.starts_with("rattler() {"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShellHook {
+    executable: PathBuf,
+    function_name: String,
+}
+
+impl ShellHook {
+    /// Constructs a hook for the given executable. The function is named after the file stem of
+    /// the executable.
+    pub fn new(executable: impl Into<PathBuf>) -> Self {
+        let executable = executable.into();
+        let function_name = executable
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self {
+            executable,
+            function_name,
+        }
+    }
+
+    /// Sets the name of the function that is defined by the hook.
+    #[must_use]
+    pub fn with_function_name(self, function_name: impl Into<String>) -> Self {
+        Self {
+            function_name: function_name.into(),
+            ..self
+        }
+    }
+
+    /// The executable that is wrapped by the hook.
+    pub fn executable(&self) -> &Path {
+        &self.executable
+    }
+
+    /// The name of the function that is defined by the hook.
+    pub fn function_name(&self) -> &str {
+        &self.function_name
+    }
+
+    /// Returns the hook for the given shell.
+    pub fn script(&self, shell: &ShellEnum) -> Result<String, ShellHookError> {
+        if !is_valid_function_name(&self.function_name) {
+            return Err(ShellHookError::InvalidFunctionName(
+                self.function_name.clone(),
+            ));
+        }
+
+        let mut script = String::new();
+        match shell {
+            ShellEnum::Bash(_) => self.write_posix(&mut script, "bash")?,
+            ShellEnum::Zsh(_) => self.write_posix(&mut script, "zsh")?,
+            ShellEnum::Fish(_) => self.write_fish(&mut script)?,
+            ShellEnum::Xonsh(_) => self.write_xonsh(&mut script)?,
+            ShellEnum::PowerShell(_) => self.write_powershell(&mut script)?,
+            ShellEnum::NuShell(_) => self.write_nushell(&mut script)?,
+            ShellEnum::CmdExe(_) => {
+                return Err(ShellHookError::UnsupportedShell("cmd.exe".to_string()))
+            }
+        }
+        Ok(script)
+    }
+
+    fn write_posix(&self, f: &mut impl Write, shell_name: &str) -> std::fmt::Result {
+        let name = &self.function_name;
+        let variable = format!("__{}_script", name.replace('-', "_"));
+        let exe = quote_posix(&self.executable.to_string_lossy());
+        let subcommands = EVALUATED_SUBCOMMANDS.join("|");
+        writeln!(f, "{name}() {{")?;
+        writeln!(f, "    case \"${{1-}}\" in")?;
+        writeln!(f, "        {subcommands})")?;
+        writeln!(
+            f,
+            "            {variable}=\"$({exe} \"$@\" --shell {shell_name})\" || return $?"
+        )?;
+        writeln!(f, "            eval \"${variable}\"")?;
+        writeln!(f, "            unset {variable}")?;
+        writeln!(f, "            ;;")?;
+        writeln!(f, "        *)")?;
+        writeln!(f, "            {exe} \"$@\"")?;
+        writeln!(f, "            ;;")?;
+        writeln!(f, "    esac")?;
+        writeln!(f, "}}")
+    }
+
+    fn write_fish(&self, f: &mut impl Write) -> std::fmt::Result {
+        let exe = quote_fish(&self.executable.to_string_lossy());
+        writeln!(f, "function {}", self.function_name)?;
+        writeln!(f, "    switch \"$argv[1]\"")?;
+        writeln!(f, "        case {}", EVALUATED_SUBCOMMANDS.join(" "))?;
+        writeln!(
+            f,
+            "            set -l script ({exe} $argv --shell fish | string collect); or return"
+        )?;
+        writeln!(f, "            eval $script")?;
+        writeln!(f, "        case '*'")?;
+        writeln!(f, "            {exe} $argv")?;
+        writeln!(f, "    end")?;
+        writeln!(f, "end")
+    }
+
+    fn write_xonsh(&self, f: &mut impl Write) -> std::fmt::Result {
+        let name = &self.function_name;
+        let function = format!("_{}_hook", name.replace('-', "_"));
+        let exe = quote_xonsh(&self.executable.to_string_lossy());
+        let subcommands = EVALUATED_SUBCOMMANDS
+            .iter()
+            .map(|subcommand| quote_xonsh(subcommand))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(f, "def {function}(args):")?;
+        writeln!(f, "    if args and args[0] in ({subcommands}):")?;
+        writeln!(f, "        result = !(@({exe}) @(args) --shell xonsh)")?;
+        writeln!(f, "        if result.returncode != 0:")?;
+        writeln!(f, "            return result.returncode")?;
+        writeln!(f, "        execx(result.output)")?;
+        writeln!(f, "    else:")?;
+        writeln!(f, "        ![@({exe}) @(args)]")?;
+        writeln!(f, "aliases[{}] = {function}", quote_xonsh(name))
+    }
+
+    fn write_powershell(&self, f: &mut impl Write) -> std::fmt::Result {
+        let exe = quote_powershell(&self.executable.to_string_lossy());
+        let subcommands = EVALUATED_SUBCOMMANDS
+            .iter()
+            .map(|subcommand| quote_powershell(subcommand))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(f, "function {} {{", self.function_name)?;
+        writeln!(
+            f,
+            "    if ($args.Count -gt 0 -and @({subcommands}) -contains $args[0]) {{"
+        )?;
+        writeln!(
+            f,
+            "        $script = & {exe} @args --shell powershell | Out-String"
+        )?;
+        writeln!(f, "        if ($LASTEXITCODE -ne 0) {{ return }}")?;
+        writeln!(f, "        Invoke-Expression $script")?;
+        writeln!(f, "    }} else {{")?;
+        writeln!(f, "        & {exe} @args")?;
+        writeln!(f, "    }}")?;
+        writeln!(f, "}}")
+    }
+
+    fn write_nushell(&self, f: &mut impl Write) -> std::fmt::Result {
+        let exe = quote_nu(&self.executable.to_string_lossy());
+        let subcommands = EVALUATED_SUBCOMMANDS
+            .iter()
+            .map(|subcommand| quote_nu(subcommand))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(f, "def --env --wrapped {} [...args] {{", self.function_name)?;
+        writeln!(
+            f,
+            "    if ($args | is-not-empty) and ($args.0 in [{subcommands}]) {{"
+        )?;
+        writeln!(
+            f,
+            "        let changes = (^{exe} ...$args --shell nu | from json)"
+        )?;
+        writeln!(f, "        for key in $changes.unset {{")?;
+        writeln!(f, "            hide-env --ignore-errors $key")?;
+        writeln!(f, "        }}")?;
+        writeln!(
+            f,
+            "        load-env ($changes.set | reject --ignore-errors PATH Path)"
+        )?;
+        writeln!(
+            f,
+            "        let path_var = if $nu.os-info.name == \"windows\" {{ \"Path\" }} else {{ \"PATH\" }}"
+        )?;
+        writeln!(f, "        load-env {{ $path_var: $changes.path }}")?;
+        writeln!(f, "    }} else {{")?;
+        writeln!(f, "        ^{exe} ...$args")?;
+        writeln!(f, "    }}")?;
+        writeln!(f, "}}")
+    }
+}
+
+/// Returns true if the name can be used as a function name in all supported shells.
+fn is_valid_function_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Quotes a string with single quotes for a POSIX shell.
+fn quote_posix(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quotes a string with single quotes for fish, in which backslashes and single quotes are
+/// escaped with a backslash.
+fn quote_fish(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// Quotes a string with single quotes for PowerShell, in which single quotes are doubled.
+fn quote_powershell(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell;
+
+    #[test]
+    fn test_hooks() {
+        let hook = ShellHook::new("/opt/my tools/rattler's");
+        assert_eq!(hook.function_name(), "rattler's");
+        assert!(matches!(
+            hook.script(&shell::Bash.into()),
+            Err(ShellHookError::InvalidFunctionName(_))
+        ));
+
+        let hook = hook.with_function_name("rattler");
+        insta::assert_snapshot!("bash", hook.script(&shell::Bash.into()).unwrap());
+        insta::assert_snapshot!("fish", hook.script(&shell::Fish.into()).unwrap());
+        insta::assert_snapshot!("xonsh", hook.script(&shell::Xonsh.into()).unwrap());
+        insta::assert_snapshot!(
+            "powershell",
+            hook.script(&shell::PowerShell::default().into()).unwrap()
+        );
+        insta::assert_snapshot!("nushell", hook.script(&shell::NuShell.into()).unwrap());
+        assert!(matches!(
+            hook.script(&shell::CmdExe.into()),
+            Err(ShellHookError::UnsupportedShell(_))
+        ));
+    }
+
+    #[test]
+    fn test_bash_hook_activates() {
+        if !cfg!(unix)
+            || std::process::Command::new("bash")
+                .arg("--version")
+                .output()
+                .is_err()
+        {
+            return;
+        }
+        let dir = tempdir::TempDir::new("rattler_shell_hook").unwrap();
+        let executable = dir.path().join("fake-rattler");
+        std::fs::write(
+            &executable,
+            "#!/bin/sh\nif [ \"$1\" = activate ]; then echo \"export FOO='$2 $4'\"; else echo \"plain $*\"; fi\n",
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let hook = ShellHook::new(&executable)
+            .script(&shell::Bash.into())
+            .unwrap();
+        let output = std::process::Command::new("bash")
+            .arg("-c")
+            .arg(format!(
+                "{hook}\nfake-rattler activate myenv\necho \"$FOO\"\nfake-rattler info"
+            ))
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "myenv bash\nplain info\n"
+        );
+    }
+}
@@ -3,6 +3,11 @@
 //! This crate provides helper functions to activate and deactivate virtual environments.
 
 pub mod activation;
+pub mod cache;
+pub mod hook;
 pub mod run;
 pub mod shell;
-pub use run::run_in_environment;
+pub use run::{
+    activated_command, activated_environment, run_activated, run_in_environment,
+    run_in_environment_with_timeout, ActivationMethod,
+};
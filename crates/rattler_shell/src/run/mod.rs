@@ -1,7 +1,11 @@
 //! Helpers to run commands in an activated environment.
 
 use rattler_conda_types::Platform;
-use std::process::{Command, Output};
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, path::Path};
 
 use crate::activation::{ActivationError, PathModificationBehavior};
@@ -22,6 +26,104 @@ pub enum RunError {
 
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
+
+    #[error("the script did not finish within {0:?}")]
+    Timeout(Duration),
+}
+
+/// Determines how the environment variables of an activated environment are computed.
+#[derive(Debug, Clone)]
+pub enum ActivationMethod {
+    /// Run the activation script of the environment in the given shell and capture the resulting
+    /// environment variables. This runs the activation scripts of the packages in the
+    /// environment.
+    Shell(ShellEnum),
+
+    /// Compute the environment variables without starting a shell: `PATH`, `CONDA_PREFIX` and
+    /// the variables from `conda-meta/state` and `etc/conda/env_vars.d`. The activation scripts of
+    /// the packages in the environment are not executed.
+    Emulate,
+}
+
+impl Default for ActivationMethod {
+    fn default() -> Self {
+        ActivationMethod::Shell(ShellEnum::default())
+    }
+}
+
+/// Returns the environment variables that are set or changed by activating the environment in
+/// `prefix`, starting from the environment of the current process.
+pub fn activated_environment(
+    prefix: &Path,
+    method: &ActivationMethod,
+) -> Result<HashMap<String, String>, RunError> {
+    let conda_prefix = std::env::var_os("CONDA_PREFIX").map(PathBuf::from);
+    match method {
+        ActivationMethod::Shell(shell) => {
+            let activator = Activator::from_path(prefix, shell.clone(), Platform::current())?;
+            Ok(activator.run_activation(ActivationVariables {
+                conda_prefix,
+                path: None,
+                path_modification_behavior: PathModificationBehavior::Prepend,
+                ..ActivationVariables::default()
+            })?)
+        }
+        ActivationMethod::Emulate => {
+            let activator =
+                Activator::from_path(prefix, ShellEnum::default(), Platform::current())?;
+            let mut path = std::env::var_os("PATH")
+                .map(|path| std::env::split_paths(&path).collect::<Vec<_>>())
+                .unwrap_or_default();
+
+            // Remove the paths of the environment that is currently active.
+            if let Some(conda_prefix) = conda_prefix {
+                if let Ok(active) =
+                    Activator::from_path(&conda_prefix, ShellEnum::default(), Platform::current())
+                {
+                    path.retain(|path| !active.paths.contains(path));
+                }
+            }
+
+            let path = std::env::join_paths(activator.paths.iter().chain(path.iter()))
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            let mut env_vars = HashMap::from([
+                ("PATH".to_string(), path.to_string_lossy().into_owned()),
+                (
+                    "CONDA_PREFIX".to_string(),
+                    prefix.to_string_lossy().into_owned(),
+                ),
+            ]);
+            env_vars.extend(activator.env_vars);
+            Ok(env_vars)
+        }
+    }
+}
+
+/// Returns a [`Command`] that runs `program` in the activated environment in `prefix`. The
+/// command can be configured further, for instance to redirect its stdio, before it is spawned.
+pub fn activated_command(
+    prefix: &Path,
+    program: impl AsRef<OsStr>,
+    method: &ActivationMethod,
+) -> Result<Command, RunError> {
+    let env_vars = activated_environment(prefix, method)?;
+    let mut command = Command::new(program);
+    command.envs(env_vars);
+    Ok(command)
+}
+
+/// Runs `program` with the given arguments in the activated environment in `prefix` and waits for
+/// it to finish, capturing its stdout and stderr. This is the library equivalent of `conda run`.
+pub fn run_activated(
+    prefix: &Path,
+    program: impl AsRef<OsStr>,
+    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+    method: &ActivationMethod,
+) -> Result<Output, RunError> {
+    Ok(activated_command(prefix, program, method)?
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?)
 }
 
 /// Execute a script in an activated environment.
@@ -30,6 +132,18 @@ pub fn run_in_environment(
     script: &Path,
     shell: ShellEnum,
     env_vars: &HashMap<String, String>,
+) -> Result<Output, RunError> {
+    run_in_environment_with_timeout(prefix, script, shell, env_vars, None)
+}
+
+/// Execute a script in an activated environment. If the script does not finish within `timeout`
+/// the process is killed and [`RunError::Timeout`] is returned.
+pub fn run_in_environment_with_timeout(
+    prefix: &Path,
+    script: &Path,
+    shell: ShellEnum,
+    env_vars: &HashMap<String, String>,
+    timeout: Option<Duration>,
 ) -> Result<Output, RunError> {
     let mut shell_script = shell::ShellScript::new(shell.clone(), Platform::current());
 
@@ -48,6 +162,7 @@ pub fn run_in_environment(
         conda_prefix,
         path: current_path,
         path_modification_behavior: PathModificationBehavior::default(),
+        ..ActivationVariables::default()
     };
 
     let host_activation = activator.activation(activation_vars)?;
@@ -60,12 +175,130 @@ pub fn run_in_environment(
         .tempfile()?;
     std::fs::write(file.path(), shell_script.contents()?)?;
 
+    let mut command = Command::new(shell.executable());
     match shell {
-        ShellEnum::Bash(_) => Ok(Command::new(shell.executable()).arg(file.path()).output()?),
-        ShellEnum::CmdExe(_) => Ok(Command::new(shell.executable())
-            .arg("/c")
-            .arg(file.path())
-            .output()?),
+        ShellEnum::Bash(_) => command.arg(file.path()),
+        ShellEnum::CmdExe(_) => command.arg("/c").arg(file.path()),
         _ => unimplemented!("Unsupported shell: {:?}", shell),
+    };
+
+    match timeout {
+        None => Ok(command.output()?),
+        Some(timeout) => {
+            // Run the script in its own process group so the processes it starts can be killed
+            // together with it.
+            #[cfg(unix)]
+            std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+            let child = command
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            wait_with_timeout(child, timeout)
+        }
+    }
+}
+
+/// Waits for the child to exit while collecting its output. If the child does not exit, or its
+/// output is not closed, within `timeout` the child and the processes it started are killed.
+fn wait_with_timeout(mut child: Child, timeout: Duration) -> Result<Output, RunError> {
+    // Read the output on separate threads to make sure the child does not block on a full pipe.
+    fn read_to_end(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = pipe.read_to_end(&mut buffer);
+            buffer
+        })
+    }
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+    let join = |handle: Option<std::thread::JoinHandle<Vec<u8>>>| {
+        handle
+            .map(|handle| handle.join().unwrap_or_default())
+            .unwrap_or_default()
+    };
+
+    // Processes that are started in the background by the script keep the pipes open after the
+    // script exited, so wait for the readers as well.
+    let deadline = Instant::now() + timeout;
+    let mut status = None;
+    loop {
+        if status.is_none() {
+            status = child.try_wait()?;
+        }
+        let readers_finished = [&stdout, &stderr]
+            .into_iter()
+            .flatten()
+            .all(std::thread::JoinHandle::is_finished);
+        if let (Some(status), true) = (status, readers_finished) {
+            return Ok(Output {
+                status,
+                stdout: join(stdout),
+                stderr: join(stderr),
+            });
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // Killing the process group closes the pipes, which stops the reader threads.
+    kill_process_group(&mut child);
+    let _ = child.wait();
+    join(stdout);
+    join(stderr);
+    Err(RunError::Timeout(timeout))
+}
+
+/// Kills the child and all processes in its process group.
+fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: `kill` has no memory safety requirements. The id of a process group is not
+        // reused while the group has members.
+        unsafe { libc::kill(-pid, libc::SIGKILL) };
+    }
+
+    // The process might have exited in the meantime, in which case killing fails.
+    let _ = child.kill();
+}
+
+#[cfg(test)]
+mod test {
+    use super::{activated_environment, run_activated, ActivationMethod};
+
+    #[test]
+    fn test_emulated_activation() {
+        let prefix = tempfile::tempdir().unwrap();
+        let env_vars_dir = prefix.path().join("etc/conda/env_vars.d");
+        std::fs::create_dir_all(&env_vars_dir).unwrap();
+        std::fs::write(env_vars_dir.join("pkg.json"), r#"{"PKG_VAR": "value"}"#).unwrap();
+
+        let env_vars = activated_environment(prefix.path(), &ActivationMethod::Emulate).unwrap();
+        assert_eq!(env_vars["PKG_VAR"], "value");
+        assert_eq!(env_vars["CONDA_PREFIX"], prefix.path().to_string_lossy());
+        let first_path = std::env::split_paths(&env_vars["PATH"]).next().unwrap();
+        assert!(first_path.starts_with(prefix.path()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_activated() {
+        let prefix = tempfile::tempdir().unwrap();
+        let env_vars_dir = prefix.path().join("etc/conda/env_vars.d");
+        std::fs::create_dir_all(&env_vars_dir).unwrap();
+        std::fs::write(env_vars_dir.join("pkg.json"), r#"{"PKG_VAR": "value"}"#).unwrap();
+
+        let output = run_activated(
+            prefix.path(),
+            "sh",
+            ["-c", "echo $PKG_VAR"],
+            &ActivationMethod::Emulate,
+        )
+        .unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "value");
     }
 }
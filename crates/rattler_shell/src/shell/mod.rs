@@ -287,17 +287,39 @@ impl Shell for Zsh {
     }
 }
 
+/// Quotes a string as a Python string literal for use in a Xonsh script.
+pub(crate) fn quote_xonsh(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 /// A [`Shell`] implementation for the Xonsh shell.
+///
+/// Xonsh scripts use Python syntax. `$PATH` is a list of paths in Xonsh, so paths are added by
+/// concatenating lists.
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Xonsh;
 
 impl Shell for Xonsh {
     fn set_env_var(&self, f: &mut impl Write, env_var: &str, value: &str) -> std::fmt::Result {
-        writeln!(f, "${env_var} = \"{value}\"")
+        writeln!(f, "${env_var} = {}", quote_xonsh(value))
     }
 
     fn unset_env_var(&self, f: &mut impl Write, env_var: &str) -> std::fmt::Result {
-        writeln!(f, "del ${env_var}")
+        // Unlike `del`, this doesn't fail if the variable doesn't exist.
+        writeln!(f, "${{...}}.pop({}, None)", quote_xonsh(env_var))
     }
 
     fn run_script(&self, f: &mut impl Write, path: &Path) -> std::fmt::Result {
@@ -306,7 +328,7 @@ impl Shell for Xonsh {
             Some("sh") => "source-bash",
             _ => "source",
         };
-        writeln!(f, "{} \"{}\"", cmd, path.to_string_lossy())
+        writeln!(f, "{cmd} {}", quote_xonsh(&path.to_string_lossy()))
     }
 
     fn can_run_script(&self, path: &Path) -> bool {
@@ -317,6 +339,25 @@ impl Shell for Xonsh {
                 .map_or(false, |ext| ext == "xsh" || ext == "sh")
     }
 
+    fn set_path(
+        &self,
+        f: &mut impl Write,
+        paths: &[PathBuf],
+        modification_behavior: PathModificationBehavior,
+        _platform: &Platform,
+    ) -> std::fmt::Result {
+        let paths = paths
+            .iter()
+            .map(|path| quote_xonsh(&path.to_string_lossy()))
+            .join(", ");
+
+        match modification_behavior {
+            PathModificationBehavior::Replace => writeln!(f, "$PATH = [{paths}]"),
+            PathModificationBehavior::Prepend => writeln!(f, "$PATH = [{paths}] + list($PATH)"),
+            PathModificationBehavior::Append => writeln!(f, "$PATH = list($PATH) + [{paths}]"),
+        }
+    }
+
     fn extension(&self) -> &str {
         "xsh"
     }
@@ -330,6 +371,22 @@ impl Shell for Xonsh {
         cmd.arg(path);
         cmd
     }
+
+    fn format_env_var(&self, var_name: &str) -> String {
+        format!("${var_name}")
+    }
+
+    fn echo(&self, f: &mut impl Write, text: &str) -> std::fmt::Result {
+        writeln!(f, "print({})", quote_xonsh(text))
+    }
+
+    fn print_env(&self, f: &mut impl Write) -> std::fmt::Result {
+        // `detype` converts all values, including lists like `$PATH`, to strings.
+        writeln!(
+            f,
+            r#"print("\n".join(f"{{k}}={{v}}" for k, v in ${{...}}.detype().items()))"#
+        )
+    }
 }
 
 /// A [`Shell`] implementation for the cmd.exe shell.
@@ -507,26 +564,42 @@ impl Shell for Fish {
     }
 }
 
-fn escape_backslashes(s: &str) -> String {
-    s.replace('\\', "\\\\")
+/// Quotes a string for use in a Nushell script. Double quoted strings in Nushell support escape
+/// sequences, so backslashes (e.g. in Windows paths) and double quotes have to be escaped.
+pub(crate) fn quote_nu(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
 }
 
-/// A [`Shell`] implementation for the Bash shell.
+/// A [`Shell`] implementation for Nushell.
+///
+/// Nushell stores `PATH` as a list, so paths are added with `prepend` and `append` instead of
+/// string concatenation. On Windows the variable is called `Path`.
 #[derive(Debug, Clone, Copy, Default)]
 pub struct NuShell;
 
+impl NuShell {
+    /// The name of the variable that contains the search paths on the given platform.
+    fn path_var(platform: &Platform) -> &'static str {
+        if platform.is_windows() {
+            "Path"
+        } else {
+            "PATH"
+        }
+    }
+}
+
 impl Shell for NuShell {
     fn set_env_var(&self, f: &mut impl Write, env_var: &str, value: &str) -> std::fmt::Result {
-        // escape backslashes for Windows (make them double backslashes)
-        writeln!(f, "$env.{} = \"{}\"", env_var, escape_backslashes(value))
+        writeln!(f, "$env.{env_var} = {}", quote_nu(value))
     }
 
     fn unset_env_var(&self, f: &mut impl Write, env_var: &str) -> std::fmt::Result {
-        writeln!(f, "hide-env {env_var}")
+        // Hiding a variable that doesn't exist is an error unless errors are ignored.
+        writeln!(f, "hide-env --ignore-errors {env_var}")
     }
 
     fn run_script(&self, f: &mut impl Write, path: &Path) -> std::fmt::Result {
-        writeln!(f, "source \"{}\"", path.to_string_lossy())
+        writeln!(f, "source {}", quote_nu(&path.to_string_lossy()))
     }
 
     fn set_path(
@@ -534,23 +607,24 @@ impl Shell for NuShell {
         f: &mut impl Write,
         paths: &[PathBuf],
         modification_behavior: PathModificationBehavior,
-        _platform: &Platform,
+        platform: &Platform,
     ) -> std::fmt::Result {
-        let path = paths
+        let path_var = Self::path_var(platform);
+        let paths = paths
             .iter()
-            .map(|path| escape_backslashes(&format!("\"{}\"", path.to_string_lossy().into_owned())))
+            .map(|path| quote_nu(&path.to_string_lossy()))
             .join(", ");
 
         // Replace, Append, or Prepend the path variable to the paths.
         match modification_behavior {
             PathModificationBehavior::Replace => {
-                writeln!(f, "$env.PATH = [{path}]")
+                writeln!(f, "$env.{path_var} = [{paths}]")
             }
             PathModificationBehavior::Prepend => {
-                writeln!(f, "$env.PATH = ($env.PATH | prepend [{path}])")
+                writeln!(f, "$env.{path_var} = ($env.{path_var} | prepend [{paths}])")
             }
             PathModificationBehavior::Append => {
-                writeln!(f, "$env.PATH = ($env.PATH | append [{path}])")
+                writeln!(f, "$env.{path_var} = ($env.{path_var} | append [{paths}])")
             }
         }
     }
@@ -568,6 +642,26 @@ impl Shell for NuShell {
         cmd.arg(path);
         cmd
     }
+
+    fn format_env_var(&self, var_name: &str) -> String {
+        format!("$env.{var_name}")
+    }
+
+    fn echo(&self, f: &mut impl Write, text: &str) -> std::fmt::Result {
+        writeln!(f, "print {}", quote_nu(text))
+    }
+
+    fn print_env(&self, f: &mut impl Write) -> std::fmt::Result {
+        // Lists, like `PATH`, are joined with the path separator. Values that are not strings,
+        // like the closures in `ENV_CONVERSIONS`, are skipped.
+        writeln!(
+            f,
+            r#"$env | items {{|key, value|
+    let value = if ($value | describe | str starts-with "list") {{ $value | str join (char esep) }} else {{ $value }}
+    if ($value | describe) == "string" {{ $"($key)=($value)" }}
+}} | compact | str join (char nl) | print"#
+        )
+    }
 }
 
 /// A generic [`Shell`] implementation for concrete shell types.
@@ -844,6 +938,26 @@ mod tests {
         insta::assert_snapshot!(script.contents);
     }
 
+    #[test]
+    fn test_nushell() {
+        let mut script = ShellScript::new(NuShell, Platform::Linux64);
+
+        script
+            .set_env_var("FOO", r#"C:\path with "quotes""#)
+            .unwrap()
+            .unset_env_var("FOO")
+            .unwrap()
+            .set_path(
+                &[PathBuf::from("/opt/env/bin")],
+                PathModificationBehavior::Prepend,
+            )
+            .unwrap()
+            .run_script(&PathBuf::from_str("foo.nu").unwrap())
+            .unwrap();
+
+        insta::assert_snapshot!(script.contents);
+    }
+
     #[test]
     fn test_xonsh_bash() {
         let mut script = ShellScript::new(Xonsh, Platform::Linux64);
@@ -855,6 +969,21 @@ mod tests {
         insta::assert_snapshot!(script.contents);
     }
 
+    #[test]
+    fn test_xonsh_path() {
+        let mut script = ShellScript::new(Xonsh, Platform::Win64);
+        script
+            .set_env_var("FOO", r#"C:\path with "quotes""#)
+            .unwrap()
+            .set_path(
+                &[PathBuf::from(r"C:\env\Scripts")],
+                PathModificationBehavior::Prepend,
+            )
+            .unwrap();
+
+        insta::assert_snapshot!(script.contents);
+    }
+
     #[test]
     fn test_xonsh_xsh() {
         let mut script = ShellScript::new(Xonsh, Platform::Linux64);
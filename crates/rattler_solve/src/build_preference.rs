@@ -0,0 +1,100 @@
+use rattler_conda_types::{GenericVirtualPackage, PackageName, RepoDataRecord, StringMatcher};
+
+/// Expresses a preference between builds of the same version of a package.
+///
+/// When the solver orders the candidates of a package it first prefers candidates without tracked
+/// features and then the highest version. Among the builds of the same version, the build with the
+/// highest score is preferred before the build number and the other rules are considered.
+///
+/// Build preferences are only supported by the `resolvo` backend.
+pub trait BuildPreference: Send + Sync {
+    /// Returns the score of a record, given the virtual packages that are considered active.
+    /// Builds with a higher score are preferred. Records without a preference should score `0`.
+    fn score(&self, record: &RepoDataRecord, virtual_packages: &[GenericVirtualPackage]) -> i64;
+}
+
+impl<F> BuildPreference for F
+where
+    F: Fn(&RepoDataRecord, &[GenericVirtualPackage]) -> i64 + Send + Sync,
+{
+    fn score(&self, record: &RepoDataRecord, virtual_packages: &[GenericVirtualPackage]) -> i64 {
+        self(record, virtual_packages)
+    }
+}
+
+/// A [`BuildPreference`] that scores builds by matching their build string against patterns.
+///
+/// The score of a record is the sum of the scores of all rules whose pattern matches the build
+/// string. A rule can be restricted to environments in which a virtual package is present.
+///
+/// ```rust
+/// # use std::str::FromStr;
+/// # use rattler_conda_types::{PackageName, StringMatcher};
+/// # use rattler_solve::BuildStringPreference;
+/// // Prefer CUDA builds over CPU builds, but only if CUDA is available.
+/// let preference = BuildStringPreference::default()
+///     .with_rule_if_present(
+///         StringMatcher::from_str("*cuda*").unwrap(),
+///         1,
+///         PackageName::new_unchecked("__cuda"),
+///     );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BuildStringPreference {
+    rules: Vec<BuildStringRule>,
+}
+
+#[derive(Debug, Clone)]
+struct BuildStringRule {
+    pattern: StringMatcher,
+    score: i64,
+    virtual_package: Option<PackageName>,
+}
+
+impl BuildStringPreference {
+    /// Adds a rule that adds `score` to the score of every build whose build string matches
+    /// `pattern`. Use a negative score to avoid builds.
+    #[must_use]
+    pub fn with_rule(mut self, pattern: StringMatcher, score: i64) -> Self {
+        self.rules.push(BuildStringRule {
+            pattern,
+            score,
+            virtual_package: None,
+        });
+        self
+    }
+
+    /// Adds a rule that adds `score` to the score of every build whose build string matches
+    /// `pattern`, but only if the virtual package `virtual_package` is present.
+    #[must_use]
+    pub fn with_rule_if_present(
+        mut self,
+        pattern: StringMatcher,
+        score: i64,
+        virtual_package: PackageName,
+    ) -> Self {
+        self.rules.push(BuildStringRule {
+            pattern,
+            score,
+            virtual_package: Some(virtual_package),
+        });
+        self
+    }
+}
+
+impl BuildPreference for BuildStringPreference {
+    fn score(&self, record: &RepoDataRecord, virtual_packages: &[GenericVirtualPackage]) -> i64 {
+        self.rules
+            .iter()
+            .filter(|rule| {
+                rule.virtual_package.as_ref().map_or(true, |name| {
+                    virtual_packages
+                        .iter()
+                        .any(|virtual_package| &virtual_package.name == name)
+                })
+            })
+            .filter(|rule| rule.pattern.matches(&record.package_record.build))
+            .map(|rule| rule.score)
+            .sum()
+    }
+}
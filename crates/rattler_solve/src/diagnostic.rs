@@ -0,0 +1,264 @@
+//! Conversion of a [`SolveError`] into a structured [`miette::Diagnostic`].
+
+use std::fmt;
+
+use miette::{Diagnostic, Severity};
+
+use crate::SolveError;
+
+/// A [`miette::Diagnostic`] that describes why a solve failed.
+///
+/// The explanation of an unsolvable problem is a tree: the root states that the requested
+/// packages are incompatible, its children describe every conflicting spec and their children
+/// describe the candidates of these specs. Every node of the tree becomes a related diagnostic,
+/// with a severity that reflects whether it is the cause of the conflict or merely information
+/// about the candidates that were considered.
+#[derive(Debug, Clone)]
+pub struct SolveDiagnostic {
+    code: &'static str,
+    message: String,
+    severity: Severity,
+    help: Option<&'static str>,
+    related: Vec<SolveDiagnostic>,
+}
+
+impl SolveDiagnostic {
+    fn new(code: &'static str, message: String, severity: Severity) -> Self {
+        Self {
+            code,
+            message,
+            severity,
+            help: None,
+            related: Vec::new(),
+        }
+    }
+
+    /// Returns the diagnostics of the causes of this diagnostic.
+    pub fn causes(&self) -> &[SolveDiagnostic] {
+        &self.related
+    }
+
+    /// Builds the diagnostic of a node in the explanation of an unsolvable problem.
+    fn from_cause(message: &str, related: Vec<SolveDiagnostic>) -> Self {
+        let severity = if message.contains("is locked") || message.contains("is excluded") {
+            Severity::Warning
+        } else if message.contains("cannot be installed")
+            || message.contains("conflicts with")
+            || message.contains("No candidates")
+            || message.contains("is incompatible")
+        {
+            Severity::Error
+        } else {
+            Severity::Advice
+        };
+        Self {
+            related,
+            ..Self::new("rattler_solve::cause", message.to_string(), severity)
+        }
+    }
+}
+
+impl fmt::Display for SolveDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SolveDiagnostic {}
+
+impl Diagnostic for SolveDiagnostic {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(self.code))
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        Some(self.severity)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.help
+            .map(|help| Box::new(help) as Box<dyn fmt::Display + 'a>)
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        if self.related.is_empty() {
+            return None;
+        }
+        Some(Box::new(
+            self.related
+                .iter()
+                .map(|related| related as &dyn Diagnostic),
+        ))
+    }
+}
+
+impl From<&SolveError> for SolveDiagnostic {
+    fn from(error: &SolveError) -> Self {
+        match error {
+            SolveError::Unsolvable(problems) => {
+                let mut causes = problems
+                    .iter()
+                    .flat_map(|problem| parse_problem(problem))
+                    .collect::<Vec<_>>();
+
+                // The explanation usually starts with a single line that introduces the conflicting
+                // specs, use it as the message.
+                let (message, related) = if causes.len() == 1 && !causes[0].related.is_empty() {
+                    let root = causes.remove(0);
+                    (root.message, root.related)
+                } else {
+                    (String::from("Cannot solve the request"), causes)
+                };
+                Self {
+                    help: Some(
+                        "relax the version constraints of the conflicting specs or add channels that contain compatible packages",
+                    ),
+                    related,
+                    ..Self::new("rattler_solve::unsolvable", message, Severity::Error)
+                }
+            }
+            SolveError::UnsupportedOperations(operations) => Self {
+                related: operations
+                    .iter()
+                    .map(|operation| {
+                        Self::new(
+                            "rattler_solve::unsupported_operation",
+                            operation.clone(),
+                            Severity::Error,
+                        )
+                    })
+                    .collect(),
+                ..Self::new(
+                    "rattler_solve::unsupported_operations",
+                    String::from("The solver returned operations that are not supported"),
+                    Severity::Error,
+                )
+            },
+            SolveError::ParseMatchSpecError(_) => Self::new(
+                "rattler_solve::invalid_match_spec",
+                error.to_string(),
+                Severity::Error,
+            ),
+            SolveError::Cancelled => Self::new(
+                "rattler_solve::cancelled",
+                error.to_string(),
+                Severity::Warning,
+            ),
+        }
+    }
+}
+
+impl From<SolveError> for SolveDiagnostic {
+    fn from(error: SolveError) -> Self {
+        Self::from(&error)
+    }
+}
+
+/// Splits a line of an explanation into its depth in the tree and its message. Every level of the
+/// tree is indented with three characters, either `│  `, `├─ `, `└─ ` or spaces.
+fn split_tree_prefix(line: &str) -> (usize, &str) {
+    let mut depth = 0;
+    let mut rest = line;
+    loop {
+        let Some(next) = ["│  ", "├─ ", "└─ ", "   "]
+            .iter()
+            .find_map(|prefix| rest.strip_prefix(prefix))
+        else {
+            return (depth, rest.trim());
+        };
+        depth += 1;
+        rest = next;
+    }
+}
+
+/// Parses the tree of causes in a human readable explanation of an unsolvable problem.
+fn parse_problem(problem: &str) -> Vec<SolveDiagnostic> {
+    // Collect the lines with their depth in the tree.
+    let lines = problem
+        .lines()
+        .map(split_tree_prefix)
+        .filter(|(_, message)| !message.is_empty())
+        .collect::<Vec<_>>();
+
+    let min_depth = lines.iter().map(|(depth, _)| *depth).min().unwrap_or(0);
+    build_causes(&lines, &mut 0, min_depth)
+}
+
+/// Builds the diagnostics of all consecutive lines starting at `idx` that are at least `depth`
+/// deep. Deeper lines become the causes of the line that precedes them.
+fn build_causes(lines: &[(usize, &str)], idx: &mut usize, depth: usize) -> Vec<SolveDiagnostic> {
+    let mut result = Vec::new();
+    while let Some(&(line_depth, message)) = lines.get(*idx) {
+        if line_depth < depth {
+            break;
+        }
+        *idx += 1;
+        let related = build_causes(lines, idx, line_depth + 1);
+        result.push(SolveDiagnostic::from_cause(message, related));
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROBLEM: &str = "The following packages are incompatible
+├─ foobar >=2 can be installed with any of the following options:
+│  └─ foobar 2.0 | 2.1 would require
+│     └─ bors <2.0, which can be installed with any of the following options:
+│        └─ bors 1.2.1
+└─ bors >=2 cannot be installed because there are no viable options:
+   ├─ bors 2.1, which conflicts with the versions reported above.
+   └─ bors 2.0 is locked, but another version is required as reported above
+";
+
+    #[test]
+    fn test_unsolvable_diagnostic() {
+        let diagnostic = SolveDiagnostic::from(SolveError::Unsolvable(vec![PROBLEM.to_string()]));
+        assert_eq!(
+            diagnostic.to_string(),
+            "The following packages are incompatible"
+        );
+        assert_eq!(diagnostic.severity(), Some(Severity::Error));
+        assert!(diagnostic.help().is_some());
+
+        // Every conflicting spec is a related diagnostic.
+        let [foobar, bors] = diagnostic.causes() else {
+            panic!("expected two causes, got {:?}", diagnostic.causes());
+        };
+        assert_eq!(
+            foobar.to_string(),
+            "foobar >=2 can be installed with any of the following options:"
+        );
+        assert_eq!(foobar.severity, Severity::Advice);
+        assert_eq!(
+            foobar.causes()[0].causes()[0].causes()[0].message,
+            "bors 1.2.1"
+        );
+        assert_eq!(bors.severity, Severity::Error);
+        assert_eq!(
+            bors.causes()
+                .iter()
+                .map(|cause| cause.severity)
+                .collect::<Vec<_>>(),
+            [Severity::Error, Severity::Warning]
+        );
+    }
+
+    #[test]
+    fn test_single_line_diagnostic() {
+        let diagnostic = SolveDiagnostic::from(SolveError::Unsolvable(vec![
+            "No candidates were found for asdfasdf *.\n".to_string(),
+        ]));
+        assert_eq!(diagnostic.to_string(), "Cannot solve the request");
+        let [cause] = diagnostic.causes() else {
+            panic!("expected a single cause");
+        };
+        assert_eq!(
+            cause.to_string(),
+            "No candidates were found for asdfasdf *."
+        );
+        assert_eq!(cause.severity, Severity::Error);
+    }
+}
@@ -3,14 +3,20 @@
 
 #![deny(missing_docs)]
 
+mod build_preference;
+#[cfg(feature = "miette")]
+mod diagnostic;
 #[cfg(feature = "libsolv_c")]
 pub mod libsolv_c;
 #[cfg(feature = "resolvo")]
 pub mod resolvo;
 
+pub use build_preference::{BuildPreference, BuildStringPreference};
 use chrono::{DateTime, Utc};
+#[cfg(feature = "miette")]
+pub use diagnostic::SolveDiagnostic;
 use rattler_conda_types::{GenericVirtualPackage, MatchSpec, RepoDataRecord};
-use std::fmt;
+use std::{fmt, sync::Arc};
 
 /// Represents a solver implementation, capable of solving [`SolverTask`]s
 pub trait SolverImpl {
@@ -87,6 +93,15 @@ pub enum ChannelPriority {
     Disabled,
 }
 
+impl From<rattler_config::ChannelPriority> for ChannelPriority {
+    fn from(priority: rattler_config::ChannelPriority) -> Self {
+        match priority {
+            rattler_config::ChannelPriority::Strict => ChannelPriority::Strict,
+            rattler_config::ChannelPriority::Disabled => ChannelPriority::Disabled,
+        }
+    }
+}
+
 /// Represents a dependency resolution task, to be solved by one of the backends (currently only
 /// libsolv is supported)
 pub struct SolverTask<TAvailablePackagesIterator> {
@@ -117,6 +132,12 @@ pub struct SolverTask<TAvailablePackagesIterator> {
     /// The specs we want to solve
     pub specs: Vec<MatchSpec>,
 
+    /// Specs of packages that do not have to be installed, but if a package with the same name is
+    /// selected it must match the spec. This is what conda calls pinned specs.
+    ///
+    /// Only supported by the `resolvo` backend.
+    pub constraints: Vec<MatchSpec>,
+
     /// The timeout after which the solver should stop
     pub timeout: Option<std::time::Duration>,
 
@@ -126,6 +147,11 @@ pub struct SolverTask<TAvailablePackagesIterator> {
 
     /// Exclude any package that has a timestamp newer than the specified timestamp.
     pub exclude_newer: Option<DateTime<Utc>>,
+
+    /// Scores builds of the same version to express which build should be preferred, for
+    /// instance CUDA builds over CPU builds when CUDA is available. Only supported by the
+    /// `resolvo` backend.
+    pub build_preference: Option<Arc<dyn BuildPreference>>,
 }
 
 impl<'r, I: IntoIterator<Item = &'r RepoDataRecord>> FromIterator<I>
@@ -138,9 +164,11 @@ impl<'r, I: IntoIterator<Item = &'r RepoDataRecord>> FromIterator<I>
             pinned_packages: Vec::new(),
             virtual_packages: Vec::new(),
             specs: Vec::new(),
+            constraints: Vec::new(),
             timeout: None,
             channel_priority: ChannelPriority::default(),
             exclude_newer: None,
+            build_preference: None,
         }
     }
 }
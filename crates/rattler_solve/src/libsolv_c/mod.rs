@@ -97,6 +97,18 @@ impl super::SolverImpl for Solver {
             ]));
         }
 
+        if !task.constraints.is_empty() {
+            return Err(SolveError::UnsupportedOperations(vec![
+                "constraints".to_string()
+            ]));
+        }
+
+        if task.build_preference.is_some() {
+            return Err(SolveError::UnsupportedOperations(vec![
+                "build preference".to_string()
+            ]));
+        }
+
         // Construct a default libsolv pool
         let pool = Pool::default();
 
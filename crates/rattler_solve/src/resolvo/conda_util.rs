@@ -14,6 +14,7 @@ pub(super) fn compare_candidates<'a>(
         VersionSetId,
         Option<(rattler_conda_types::Version, bool)>,
     >,
+    build_scores: &HashMap<SolvableId, i64>,
 ) -> Ordering {
     let pool = solver.pool();
 
@@ -40,6 +41,15 @@ pub(super) fn compare_candidates<'a>(
         Ordering::Equal => {}
     };
 
+    // Otherwise, select the variant that is preferred by the build preference
+    let a_score = build_scores.get(&a).copied().unwrap_or(0);
+    let b_score = build_scores.get(&b).copied().unwrap_or(0);
+    match a_score.cmp(&b_score) {
+        Ordering::Less => return Ordering::Greater,
+        Ordering::Greater => return Ordering::Less,
+        Ordering::Equal => {}
+    };
+
     // Otherwise, select the variant with the highest build number
     match a_record.build_number().cmp(&b_record.build_number()) {
         Ordering::Less => return Ordering::Greater,
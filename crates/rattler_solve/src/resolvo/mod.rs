@@ -1,11 +1,13 @@
 //! Provides an solver implementation based on the [`resolvo`] crate.
 
-use crate::{ChannelPriority, IntoRepoData, SolveError, SolverRepoData, SolverTask};
+use crate::{
+    BuildPreference, ChannelPriority, IntoRepoData, SolveError, SolverRepoData, SolverTask,
+};
 use chrono::{DateTime, Utc};
 use rattler_conda_types::package::ArchiveType;
 use rattler_conda_types::{
     GenericVirtualPackage, MatchSpec, NamelessMatchSpec, PackageRecord, ParseMatchSpecError,
-    ParseStrictness, RepoDataRecord,
+    ParseStrictness, RepoDataRecord, VersionSpec,
 };
 use resolvo::{
     Candidates, Dependencies, DependencyProvider, KnownDependencies, NameId, Pool, SolvableDisplay,
@@ -167,7 +169,13 @@ pub(crate) struct CondaDependencyProvider<'a> {
 
     parse_match_spec_cache: RefCell<HashMap<&'a str, VersionSetId>>,
 
+    normalized_version_sets: RefCell<NormalizedVersionSets>,
+
     stop_time: Option<std::time::SystemTime>,
+
+    /// The scores of the records according to the build preference, records without a score are
+    /// scored `0`.
+    build_scores: HashMap<SolvableId, i64>,
 }
 
 impl<'a> CondaDependencyProvider<'a> {
@@ -178,12 +186,15 @@ impl<'a> CondaDependencyProvider<'a> {
         locked_records: &'a [RepoDataRecord],
         virtual_packages: &'a [GenericVirtualPackage],
         match_specs: &[MatchSpec],
+        constraints: &[MatchSpec],
         stop_time: Option<std::time::SystemTime>,
         channel_priority: ChannelPriority,
         exclude_newer: Option<DateTime<Utc>>,
+        build_preference: Option<&dyn BuildPreference>,
     ) -> Self {
         let pool = Rc::new(Pool::default());
         let mut records: HashMap<NameId, Candidates> = HashMap::default();
+        let mut build_scores = HashMap::new();
 
         // Add virtual packages to the records
         for virtual_package in virtual_packages {
@@ -274,6 +285,13 @@ impl<'a> CondaDependencyProvider<'a> {
                 let candidates = records.entry(package_name).or_default();
                 candidates.candidates.push(solvable_id);
 
+                if let Some(build_preference) = build_preference {
+                    let score = build_preference.score(record, virtual_packages);
+                    if score != 0 {
+                        build_scores.insert(solvable_id, score);
+                    }
+                }
+
                 // Filter out any records that are newer than a specific date.
                 match (&exclude_newer, &record.package_record.timestamp) {
                     (Some(exclude_newer), Some(record_timestamp))
@@ -287,6 +305,14 @@ impl<'a> CondaDependencyProvider<'a> {
                     _ => {}
                 }
 
+                // Add to excluded when the package does not match a constraint.
+                if let Some(reason) = constraint_violation(constraints, record) {
+                    candidates
+                        .excluded
+                        .push((solvable_id, pool.intern_string(reason)));
+                    continue;
+                }
+
                 // Add to excluded when package is not in the specified channel.
                 if !channel_specific_specs.is_empty() {
                     if let Some(spec) = channel_specific_specs.iter().find(|&&spec| {
@@ -358,6 +384,11 @@ impl<'a> CondaDependencyProvider<'a> {
             let candidates = records.entry(name).or_default();
             candidates.candidates.push(solvable);
             candidates.favored = Some(solvable);
+            if let Some(reason) = constraint_violation(constraints, favored_record) {
+                candidates
+                    .excluded
+                    .push((solvable, pool.intern_string(reason)));
+            }
         }
 
         for locked_record in locked_records {
@@ -366,6 +397,11 @@ impl<'a> CondaDependencyProvider<'a> {
             let candidates = records.entry(name).or_default();
             candidates.candidates.push(solvable);
             candidates.locked = Some(solvable);
+            if let Some(reason) = constraint_violation(constraints, locked_record) {
+                candidates
+                    .excluded
+                    .push((solvable, pool.intern_string(reason)));
+            }
         }
 
         Self {
@@ -373,7 +409,9 @@ impl<'a> CondaDependencyProvider<'a> {
             records,
             matchspec_to_highest_version: RefCell::default(),
             parse_match_spec_cache: RefCell::default(),
+            normalized_version_sets: RefCell::default(),
             stop_time,
+            build_scores,
         }
     }
 }
@@ -396,7 +434,13 @@ impl<'a> DependencyProvider<SolverMatchSpec<'a>> for CondaDependencyProvider<'a>
     ) {
         let mut highest_version_spec = self.matchspec_to_highest_version.borrow_mut();
         solvables.sort_by(|&p1, &p2| {
-            conda_util::compare_candidates(p1, p2, solver, &mut highest_version_spec)
+            conda_util::compare_candidates(
+                p1,
+                p2,
+                solver,
+                &mut highest_version_spec,
+                &self.build_scores,
+            )
         });
     }
 
@@ -411,15 +455,26 @@ impl<'a> DependencyProvider<SolverMatchSpec<'a>> for CondaDependencyProvider<'a>
         };
 
         let mut parse_match_spec_cache = self.parse_match_spec_cache.borrow_mut();
+        let mut normalized_version_sets = self.normalized_version_sets.borrow_mut();
         for depends in rec.package_record.depends.iter() {
-            let version_set_id =
-                parse_match_spec(&self.pool, depends, &mut parse_match_spec_cache).unwrap();
+            let version_set_id = parse_match_spec(
+                &self.pool,
+                depends,
+                &mut parse_match_spec_cache,
+                &mut normalized_version_sets,
+            )
+            .unwrap();
             dependencies.requirements.push(version_set_id);
         }
 
         for constrains in rec.package_record.constrains.iter() {
-            let version_set_id =
-                parse_match_spec(&self.pool, constrains, &mut parse_match_spec_cache).unwrap();
+            let version_set_id = parse_match_spec(
+                &self.pool,
+                constrains,
+                &mut parse_match_spec_cache,
+                &mut normalized_version_sets,
+            )
+            .unwrap();
             dependencies.constrains.push(version_set_id);
         }
 
@@ -481,13 +536,16 @@ impl super::SolverImpl for Solver {
             &task.pinned_packages,
             &task.virtual_packages,
             task.specs.clone().as_ref(),
+            &task.constraints,
             stop_time,
             task.channel_priority,
             task.exclude_newer,
+            task.build_preference.as_deref(),
         );
         let pool = provider.pool.clone();
 
         // Construct the requirements that the solver needs to satisfy.
+        let mut normalized_version_sets = provider.normalized_version_sets.borrow_mut();
         let root_requirements = task
             .specs
             .iter()
@@ -495,9 +553,10 @@ impl super::SolverImpl for Solver {
                 let (name, spec) = spec.clone().into_nameless();
                 let name = name.expect("cannot use matchspec without a name");
                 let name_id = provider.pool.intern_package_name(name.as_normalized());
-                provider.pool.intern_version_set(name_id, spec.into())
+                normalized_version_sets.intern(&provider.pool, name_id, spec)
             })
             .collect();
+        drop(normalized_version_sets);
 
         // Construct a solver and solve the problems in the queue
         let mut solver = LibSolvRsSolver::new(provider);
@@ -529,10 +588,23 @@ impl super::SolverImpl for Solver {
     }
 }
 
+/// Returns the reason why the record is excluded if it does not match a constraint with the same
+/// name.
+fn constraint_violation(constraints: &[MatchSpec], record: &RepoDataRecord) -> Option<String> {
+    constraints
+        .iter()
+        .find(|constraint| {
+            constraint.name.as_ref() == Some(&record.package_record.name)
+                && !constraint.matches(&record.package_record)
+        })
+        .map(|constraint| format!("the package does not match the constraint '{constraint}'"))
+}
+
 fn parse_match_spec<'a>(
     pool: &Pool<SolverMatchSpec<'a>>,
     spec_str: &'a str,
     parse_match_spec_cache: &mut HashMap<&'a str, VersionSetId>,
+    normalized_version_sets: &mut NormalizedVersionSets,
 ) -> Result<VersionSetId, ParseMatchSpecError> {
     if let Some(spec_id) = parse_match_spec_cache.get(spec_str) {
         Ok(*spec_id)
@@ -544,8 +616,62 @@ fn parse_match_spec<'a>(
                 .expect("match specs without names are not supported")
                 .as_normalized(),
         );
-        let version_set_id = pool.intern_version_set(dependency_name, spec.into());
+        let version_set_id = normalized_version_sets.intern(pool, dependency_name, spec);
         parse_match_spec_cache.insert(spec_str, version_set_id);
         Ok(version_set_id)
     }
 }
+
+/// Deduplicates the version sets of equivalent specs. The pool only deduplicates identical specs,
+/// so for instance `>=1.0,<2` and `<2,>=1` would otherwise result in two version sets with the
+/// same candidates.
+#[derive(Default)]
+struct NormalizedVersionSets(HashMap<(NameId, NamelessMatchSpec), VersionSetId>);
+
+impl NormalizedVersionSets {
+    /// Interns the version set of a spec, reusing the version set of an equivalent spec that was
+    /// interned before.
+    fn intern<'a>(
+        &mut self,
+        pool: &Pool<SolverMatchSpec<'a>>,
+        name: NameId,
+        spec: NamelessMatchSpec,
+    ) -> VersionSetId {
+        let normalized = NamelessMatchSpec {
+            version: spec.version.as_ref().map(VersionSpec::normalized),
+            ..spec.clone()
+        };
+        *self
+            .0
+            .entry((name, normalized))
+            .or_insert_with(|| pool.intern_version_set(name, spec.into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalized_version_sets() {
+        let pool = Pool::default();
+        let mut parse_match_spec_cache = HashMap::new();
+        let mut normalized_version_sets = NormalizedVersionSets::default();
+        let mut intern = |spec: &'static str| {
+            parse_match_spec(
+                &pool,
+                spec,
+                &mut parse_match_spec_cache,
+                &mut normalized_version_sets,
+            )
+            .unwrap()
+        };
+
+        let a = intern("foo >=1.0,<2");
+        assert_eq!(intern("foo <2,>=1"), a);
+        assert_eq!(intern("foo >=1,<2,>=1.0"), a);
+        assert_ne!(intern("foo >=1,<3"), a);
+        assert_ne!(intern("bar >=1.0,<2"), a);
+        assert_ne!(intern("foo >=1.0,<2 py*"), a);
+    }
+}
@@ -530,10 +530,14 @@ macro_rules! solver_backend_tests {
 mod libsolv_c {
     use super::{
         dummy_channel_json_path, installed_package, solve, solve_real_world, FromStr,
-        GenericVirtualPackage, SimpleSolveTask, SolveError, Version,
+        GenericVirtualPackage, RepoDataRecord, SimpleSolveTask, SolveError, SolverImpl, SolverTask,
+        Version,
     };
+    use rattler_conda_types::{PackageName, StringMatcher};
     #[allow(unused_imports)] // For some reason windows thinks this is an unused import.
     use rattler_solve::ChannelPriority;
+    use rattler_solve::{BuildPreference, BuildStringPreference};
+    use std::sync::Arc;
 
     solver_backend_tests!(rattler_solve::libsolv_c::Solver);
 
@@ -571,10 +575,12 @@ mod libsolv_c {
                 virtual_packages: Vec::new(),
                 available_packages: [libsolv_repodata],
                 specs,
+                constraints: Vec::new(),
                 pinned_packages: Vec::new(),
                 timeout: None,
                 channel_priority: ChannelPriority::default(),
                 exclude_newer: None,
+                build_preference: None,
             })
             .unwrap();
 
@@ -619,8 +625,12 @@ mod libsolv_c {
 mod resolvo {
     use super::{
         dummy_channel_json_path, installed_package, solve, solve_real_world, FromStr,
-        GenericVirtualPackage, SimpleSolveTask, SolveError, Version,
+        GenericVirtualPackage, RepoDataRecord, SimpleSolveTask, SolveError, SolverImpl, SolverTask,
+        Version,
     };
+    use rattler_conda_types::{PackageName, StringMatcher};
+    use rattler_solve::{BuildPreference, BuildStringPreference};
+    use std::sync::Arc;
 
     solver_backend_tests!(rattler_solve::resolvo::Solver);
 
@@ -662,6 +672,101 @@ mod resolvo {
         // We expect an error here. `bors` is pinnend to 1, but we try to install `>=2`.
         insta::assert_snapshot!(result.unwrap_err());
     }
+
+    #[test]
+    fn test_build_preference() {
+        let repo_data = [
+            ("1.0", "cpu_1", 1),
+            ("1.0", "cuda_0", 0),
+            ("0.9", "cuda_1", 1),
+        ]
+        .map(|(version, build, build_number)| RepoDataRecord {
+            file_name: format!("foo-{version}-{build}.conda"),
+            ..installed_package(
+                "conda-forge",
+                "linux-64",
+                "foo",
+                version,
+                build,
+                build_number,
+            )
+        });
+        let cuda = GenericVirtualPackage {
+            name: PackageName::new_unchecked("__cuda"),
+            version: Version::from_str("12.0").unwrap(),
+            build_string: "0".to_string(),
+        };
+        let preference: Arc<dyn BuildPreference> =
+            Arc::new(BuildStringPreference::default().with_rule_if_present(
+                StringMatcher::from_str("*cuda*").unwrap(),
+                1,
+                PackageName::new_unchecked("__cuda"),
+            ));
+        let solve_build =
+            |virtual_packages: Vec<GenericVirtualPackage>,
+             build_preference: Option<Arc<dyn BuildPreference>>| {
+                let records = rattler_solve::resolvo::Solver
+                    .solve(SolverTask {
+                        specs: vec!["foo".parse().unwrap()],
+                        virtual_packages,
+                        build_preference,
+                        ..SolverTask::from_iter([&repo_data])
+                    })
+                    .unwrap();
+                assert_eq!(records.len(), 1);
+                records[0].package_record.build.clone()
+            };
+
+        // Without a preference the highest build number is selected.
+        assert_eq!(solve_build(vec![cuda.clone()], None), "cpu_1");
+        // The preference is only applied if cuda is available.
+        assert_eq!(solve_build(Vec::new(), Some(preference.clone())), "cpu_1");
+        // The preference does not select an older version.
+        assert_eq!(solve_build(vec![cuda], Some(preference)), "cuda_0");
+    }
+
+    #[test]
+    fn test_constraints() {
+        let record = |name: &str, version: &str, depends: &[&str]| {
+            let mut record = installed_package("conda-forge", "linux-64", name, version, "0", 0);
+            record.file_name = format!("{name}-{version}-0.conda");
+            record.package_record.depends = depends.iter().map(ToString::to_string).collect();
+            record
+        };
+        let repo_data = [
+            record("foo", "1.0", &[]),
+            record("foo", "2.0", &[]),
+            record("bar", "1.0", &["foo"]),
+            record("baz", "1.0", &[]),
+        ];
+        let solve_constrained = |spec: &str| {
+            rattler_solve::resolvo::Solver
+                .solve(SolverTask {
+                    specs: vec![spec.parse().unwrap()],
+                    constraints: vec!["foo <2".parse().unwrap()],
+                    ..SolverTask::from_iter([&repo_data])
+                })
+                .map(|records| {
+                    let mut records = records
+                        .iter()
+                        .map(|record| record.package_record.to_string())
+                        .collect::<Vec<_>>();
+                    records.sort();
+                    records
+                })
+        };
+
+        // The constrained package is only installed when it is required.
+        assert_eq!(solve_constrained("baz").unwrap(), ["baz=1.0=0"]);
+        assert_eq!(
+            solve_constrained("bar").unwrap(),
+            ["bar=1.0=0", "foo=1.0=0"]
+        );
+        assert!(matches!(
+            solve_constrained("foo >=2"),
+            Err(SolveError::Unsolvable(_))
+        ));
+    }
 }
 
 #[derive(Default)]
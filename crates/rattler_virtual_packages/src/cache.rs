@@ -0,0 +1,107 @@
+//! A cache of detected virtual packages for long-running processes. See [`VirtualPackageCache`].
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::{DetectVirtualPackageError, VirtualPackage};
+
+/// A thread-safe cache of the virtual packages of the current system.
+///
+/// [`VirtualPackage::current`] detects the virtual packages once per process. Long-running
+/// processes, like services, may want to pick up changes to the system, e.g. an updated NVIDIA
+/// driver, without restarting. This cache detects the virtual packages on first use and again when
+/// the cached result is older than the time-to-live, or when [`VirtualPackageCache::refresh`] is
+/// called.
+#[derive(Debug, Default)]
+pub struct VirtualPackageCache {
+    ttl: Option<Duration>,
+    entry: Mutex<Option<CacheEntry>>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    detected_at: Instant,
+    packages: Arc<[VirtualPackage]>,
+}
+
+impl VirtualPackageCache {
+    /// Constructs a cache whose result never expires.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time after which the detected virtual packages are detected again.
+    #[must_use]
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+
+    /// Returns the cached virtual packages. The virtual packages are detected if nothing is
+    /// cached yet or if the cached result has expired.
+    ///
+    /// Concurrent callers wait for a single detection instead of all detecting the virtual
+    /// packages themselves.
+    pub fn get(&self) -> Result<Arc<[VirtualPackage]>, DetectVirtualPackageError> {
+        let mut entry = self.entry.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entry) = entry.as_ref() {
+            let expired = self
+                .ttl
+                .is_some_and(|ttl| entry.detected_at.elapsed() >= ttl);
+            if !expired {
+                return Ok(entry.packages.clone());
+            }
+        }
+        Self::detect_into(&mut entry)
+    }
+
+    /// Detects the virtual packages again regardless of the age of the cached result.
+    pub fn refresh(&self) -> Result<Arc<[VirtualPackage]>, DetectVirtualPackageError> {
+        let mut entry = self.entry.lock().unwrap_or_else(PoisonError::into_inner);
+        Self::detect_into(&mut entry)
+    }
+
+    /// Removes the cached result. The next call to [`VirtualPackageCache::get`] detects the
+    /// virtual packages again.
+    pub fn invalidate(&self) {
+        *self.entry.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    fn detect_into(
+        entry: &mut Option<CacheEntry>,
+    ) -> Result<Arc<[VirtualPackage]>, DetectVirtualPackageError> {
+        let packages: Arc<[VirtualPackage]> = VirtualPackage::detect()?.into();
+        *entry = Some(CacheEntry {
+            detected_at: Instant::now(),
+            packages: packages.clone(),
+        });
+        Ok(packages)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VirtualPackageCache;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cache() {
+        let cache = VirtualPackageCache::new();
+        let first = cache.get().unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.get().unwrap()));
+
+        let refreshed = cache.refresh().unwrap();
+        assert!(!Arc::ptr_eq(&first, &refreshed));
+        assert_eq!(first, refreshed);
+
+        cache.invalidate();
+        assert!(!Arc::ptr_eq(&refreshed, &cache.get().unwrap()));
+
+        let expiring = VirtualPackageCache::new().with_ttl(Duration::ZERO);
+        let first = expiring.get().unwrap();
+        assert!(!Arc::ptr_eq(&first, &expiring.get().unwrap()));
+    }
+}
@@ -1,41 +1,76 @@
 //! Provides functionality to detect the CUDA version present on the current system.
 //!
-//! Two methods are provided:
+//! Three methods are provided:
 //!
 //! * [`detect_cuda_version_via_nvml`]
 //! * [`detect_cuda_version_via_libcuda`]
+//! * [`detect_cuda_version_via_nvidia_smi`]
 //!
-//! Both will detect the current supported CUDA version but the first method has less edge cases.
-//! See the function documentation for more information.
+//! All of them will detect the current supported CUDA version but the first method has less edge
+//! cases. See the function documentation for more information. [`detect_cuda_driver`] tries them
+//! in order until one succeeds.
 
 use libloading::Symbol;
 use once_cell::sync::OnceCell;
 use rattler_conda_types::Version;
 use std::process::Command;
 use std::{
+    ffi::CStr,
     mem::MaybeUninit,
-    os::raw::{c_int, c_uint, c_ulong},
+    os::raw::{c_char, c_int, c_uint, c_ulong},
     str::FromStr,
 };
 
+/// Information about the NVIDIA driver that is installed on the current system.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CudaDriverInfo {
+    /// The maximum CUDA version that is supported by the driver.
+    pub cuda_version: Version,
+
+    /// The version of the driver itself, e.g. `535.104.05`, if it could be determined.
+    pub driver_version: Option<String>,
+}
+
 /// Returns the maximum Cuda version available on the current platform.
 pub fn cuda_version() -> Option<Version> {
-    static DETECTED_CUDA_VERSION: OnceCell<Option<Version>> = OnceCell::new();
-    DETECTED_CUDA_VERSION
-        .get_or_init(detect_cuda_version)
-        .clone()
+    cuda_driver().map(|driver| driver.cuda_version)
+}
+
+/// Returns information about the NVIDIA driver on the current platform. The result is memoized,
+/// use [`detect_cuda_driver`] to detect the driver again.
+pub fn cuda_driver() -> Option<CudaDriverInfo> {
+    static DETECTED_CUDA_DRIVER: OnceCell<Option<CudaDriverInfo>> = OnceCell::new();
+    DETECTED_CUDA_DRIVER.get_or_init(detect_cuda_driver).clone()
 }
 
 /// Attempts to detect the version of CUDA present in the current operating system by employing the
 /// best technique available for the current environment.
 pub fn detect_cuda_version() -> Option<Version> {
+    detect_cuda_driver().map(|driver| driver.cuda_version)
+}
+
+/// Attempts to detect the NVIDIA driver of the current operating system. The NVIDIA Management
+/// Library is queried first, if that fails the CUDA driver library is queried and finally the
+/// output of `nvidia-smi` is parsed.
+pub fn detect_cuda_driver() -> Option<CudaDriverInfo> {
     if cfg!(target_env = "musl") {
         // Dynamically loading a library is not supported on musl so we have to fall-back to using
         // the nvidia-smi command.
-        detect_cuda_version_via_nvidia_smi()
-    } else {
-        detect_cuda_version_via_nvml()
+        return detect_cuda_driver_via_nvidia_smi();
     }
+
+    detect_cuda_driver_via_nvml()
+        .or_else(|| {
+            tracing::debug!("failed to query NVML, falling back to libcuda");
+            detect_cuda_version_via_libcuda().map(|cuda_version| CudaDriverInfo {
+                cuda_version,
+                driver_version: None,
+            })
+        })
+        .or_else(|| {
+            tracing::debug!("failed to query libcuda, falling back to nvidia-smi");
+            detect_cuda_driver_via_nvidia_smi()
+        })
 }
 
 /// Attempts to detect the version of CUDA present in the current operating system by loading the
@@ -47,6 +82,12 @@ pub fn detect_cuda_version() -> Option<Version> {
 /// considered old enough to be usable for our use case. Since Conda doesnt provide old versions of
 /// the CUDA SDK anyway this is considered a non-issue.
 pub fn detect_cuda_version_via_nvml() -> Option<Version> {
+    detect_cuda_driver_via_nvml().map(|driver| driver.cuda_version)
+}
+
+/// Queries the NVIDIA Management Library for the CUDA driver version and the version of the
+/// driver itself. See [`detect_cuda_version_via_nvml`].
+fn detect_cuda_driver_via_nvml() -> Option<CudaDriverInfo> {
     // Try to open the library
     let library = nvml_library_paths()
         .iter()
@@ -75,6 +116,11 @@ pub fn detect_cuda_version_via_nvml() -> Option<Version> {
         }
         .ok()?;
 
+    // The driver version is optional, it is only used as metadata.
+    let nvml_system_get_driver_version: Option<
+        Symbol<'_, unsafe extern "C" fn(*mut c_char, c_uint) -> c_int>,
+    > = unsafe { library.get(b"nvmlSystemGetDriverVersion\0") }.ok();
+
     // Call the initialization function
     if unsafe { nvml_init() } != 0 {
         return None;
@@ -84,6 +130,16 @@ pub fn detect_cuda_version_via_nvml() -> Option<Version> {
     let mut cuda_driver_version = MaybeUninit::uninit();
     let result = unsafe { nvml_system_get_cuda_driver_version(cuda_driver_version.as_mut_ptr()) };
 
+    // Get the driver version. The buffer size is `NVML_SYSTEM_DRIVER_VERSION_BUFFER_SIZE`.
+    let driver_version = nvml_system_get_driver_version.and_then(|get_driver_version| {
+        let mut buffer = [0 as c_char; 80];
+        if unsafe { get_driver_version(buffer.as_mut_ptr(), buffer.len() as c_uint) } != 0 {
+            return None;
+        }
+        let version = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+        Some(version.to_string_lossy().into_owned())
+    });
+
     // Call the shutdown function (don't care about the result of the function). Whatever happens,
     // after calling `nvmlInit` we have to call `nvmlShutdown`.
     let _ = unsafe { nvml_shutdown() };
@@ -96,7 +152,14 @@ pub fn detect_cuda_version_via_nvml() -> Option<Version> {
     // We can assume the value is initialized by the `nvmlSystemGetCudaDriverVersion` function.
     let version = unsafe { cuda_driver_version.assume_init() };
 
-    // Convert the version integer to a version string
+    Some(CudaDriverInfo {
+        cuda_version: cuda_version_from_int(version)?,
+        driver_version,
+    })
+}
+
+/// Converts a CUDA version integer as returned by the driver (e.g. `12020`) to a version.
+fn cuda_version_from_int(version: c_int) -> Option<Version> {
     Version::from_str(&format!("{}.{}", version / 1000, (version % 1000) / 10)).ok()
 }
 
@@ -168,7 +231,7 @@ pub fn detect_cuda_version_via_libcuda() -> Option<Version> {
     let version = unsafe { version_int.assume_init() };
 
     // Convert the version integer to a version string
-    Version::from_str(&format!("{}.{}", version / 1000, (version % 1000) / 10)).ok()
+    cuda_version_from_int(version)
 }
 
 /// Returns platform specific set of search paths for the CUDA library.
@@ -216,12 +279,13 @@ fn cuda_library_paths() -> &'static [&'static str] {
 /// The upside of using this detection function over any of the others is that this method does not
 /// dynamically load a library which might not be supported on all systems. The downside is that
 /// executing a subprocess is generally slower and more prone to errors.
-fn detect_cuda_version_via_nvidia_smi() -> Option<Version> {
-    static CUDA_VERSION_RE: once_cell::sync::Lazy<regex::Regex> =
-        once_cell::sync::Lazy::new(|| {
-            regex::Regex::new("<cuda_version>(.*)<\\/cuda_version>").unwrap()
-        });
+pub fn detect_cuda_version_via_nvidia_smi() -> Option<Version> {
+    detect_cuda_driver_via_nvidia_smi().map(|driver| driver.cuda_version)
+}
 
+/// Executes `nvidia-smi` to determine the CUDA driver version and the version of the driver
+/// itself. See [`detect_cuda_version_via_nvidia_smi`].
+fn detect_cuda_driver_via_nvidia_smi() -> Option<CudaDriverInfo> {
     // Invoke the "nvidia-smi" command to query the driver version that is usually installed when
     // Cuda drivers are installed.
     let nvidia_smi_output = Command::new("nvidia-smi")
@@ -245,13 +309,33 @@ fn detect_cuda_version_via_nvidia_smi() -> Option<Version> {
     // characters. If thats the case we simply assume the version in the file also wont make sense
     // during parsing.
     let output = String::from_utf8_lossy(&nvidia_smi_output.stdout);
+    parse_nvidia_smi_output(&output)
+}
+
+/// Extracts the CUDA version and the driver version from the XML output of `nvidia-smi`.
+fn parse_nvidia_smi_output(output: &str) -> Option<CudaDriverInfo> {
+    static CUDA_VERSION_RE: once_cell::sync::Lazy<regex::Regex> =
+        once_cell::sync::Lazy::new(|| {
+            regex::Regex::new("<cuda_version>(.*)<\\/cuda_version>").unwrap()
+        });
+    static DRIVER_VERSION_RE: once_cell::sync::Lazy<regex::Regex> =
+        once_cell::sync::Lazy::new(|| {
+            regex::Regex::new("<driver_version>(.*)<\\/driver_version>").unwrap()
+        });
 
     // Extract the version from the XML
-    let version_match = CUDA_VERSION_RE.captures(&output)?;
+    let version_match = CUDA_VERSION_RE.captures(output)?;
     let version_str = version_match.get(1)?.as_str();
+    let driver_version = DRIVER_VERSION_RE
+        .captures(output)
+        .and_then(|captures| captures.get(1))
+        .map(|version| version.as_str().trim().to_string());
 
     // Parse and return
-    Version::from_str(version_str).ok()
+    Some(CudaDriverInfo {
+        cuda_version: Version::from_str(version_str.trim()).ok()?,
+        driver_version,
+    })
 }
 
 #[cfg(test)]
@@ -269,4 +353,13 @@ mod test {
         let version = detect_cuda_version_via_nvidia_smi();
         println!("Cuda {version:?}");
     }
+
+    #[test]
+    pub fn test_parse_nvidia_smi_output() {
+        let output = "<nvidia_smi_log>\n\t<driver_version>535.104.05</driver_version>\n\t<cuda_version>12.2</cuda_version>\n</nvidia_smi_log>";
+        let driver = parse_nvidia_smi_output(output).unwrap();
+        assert_eq!(driver.cuda_version, Version::from_str("12.2").unwrap());
+        assert_eq!(driver.driver_version.as_deref(), Some("535.104.05"));
+        assert!(parse_nvidia_smi_output("<nvidia_smi_log/>").is_none());
+    }
 }
@@ -0,0 +1,189 @@
+//! Detection of the environment the current process runs in, like the Windows Subsystem for
+//! Linux or a container. See [`ExecutionEnvironment`].
+//!
+//! This information is not exposed as virtual packages but can be used to implement policies, for
+//! instance to avoid CUDA packages under WSL1 which has no GPU support, or to warn that the glibc
+//! inside a container may be newer than the kernel of the host supports.
+
+use once_cell::sync::OnceCell;
+
+/// The version of the Windows Subsystem for Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Wsl {
+    /// WSL1 translates Linux system calls, there is no real Linux kernel and no GPU support.
+    Wsl1,
+
+    /// WSL2 runs a real Linux kernel in a lightweight virtual machine.
+    Wsl2,
+}
+
+/// A container runtime that the current process may run in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContainerRuntime {
+    /// A Docker container.
+    Docker,
+
+    /// A Podman container.
+    Podman,
+
+    /// A container in a Kubernetes pod.
+    Kubernetes,
+
+    /// A containerd container.
+    Containerd,
+
+    /// An LXC container.
+    Lxc,
+
+    /// Another container runtime, identified by the `container` environment variable.
+    Other(String),
+}
+
+/// Information about the environment the current process runs in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ExecutionEnvironment {
+    /// The version of WSL, if the process runs under the Windows Subsystem for Linux.
+    pub wsl: Option<Wsl>,
+
+    /// The container runtime, if the process runs in a container.
+    pub container: Option<ContainerRuntime>,
+}
+
+impl ExecutionEnvironment {
+    /// Returns the environment of the current process. The result is memoized, use
+    /// [`ExecutionEnvironment::detect`] to detect the environment again.
+    pub fn current() -> &'static Self {
+        static DETECTED_ENVIRONMENT: OnceCell<ExecutionEnvironment> = OnceCell::new();
+        DETECTED_ENVIRONMENT.get_or_init(Self::detect)
+    }
+
+    /// Detects the environment of the current process. WSL and containers are only detected on
+    /// Linux.
+    pub fn detect() -> Self {
+        if !cfg!(target_os = "linux") {
+            return Self::default();
+        }
+
+        let read = |path: &str| std::fs::read_to_string(path).unwrap_or_default();
+        Self {
+            wsl: parse_wsl(
+                &read("/proc/sys/kernel/osrelease"),
+                std::env::var_os("WSL_INTEROP").is_some(),
+            ),
+            container: detect_container(&read("/proc/1/cgroup")),
+        }
+    }
+
+    /// Returns true if the process runs under WSL, regardless of the version.
+    pub fn is_wsl(&self) -> bool {
+        self.wsl.is_some()
+    }
+
+    /// Returns true if the process runs in a container.
+    pub fn is_container(&self) -> bool {
+        self.container.is_some()
+    }
+}
+
+/// Determines the WSL version from the kernel release, e.g. `4.4.0-19041-Microsoft` for WSL1 and
+/// `5.15.133.1-microsoft-standard-WSL2` for WSL2. The `WSL_INTEROP` environment variable is only
+/// set by WSL2.
+fn parse_wsl(osrelease: &str, has_interop: bool) -> Option<Wsl> {
+    let osrelease = osrelease.trim();
+    if !osrelease.to_lowercase().contains("microsoft") {
+        return None;
+    }
+    if osrelease.contains("WSL2") || osrelease.contains("microsoft-standard") || has_interop {
+        Some(Wsl::Wsl2)
+    } else {
+        Some(Wsl::Wsl1)
+    }
+}
+
+/// Detects the container runtime from marker files, environment variables and the control groups
+/// of the init process.
+fn detect_container(init_cgroup: &str) -> Option<ContainerRuntime> {
+    if std::path::Path::new("/.dockerenv").exists() {
+        return Some(ContainerRuntime::Docker);
+    }
+    if std::path::Path::new("/run/.containerenv").exists() {
+        return Some(ContainerRuntime::Podman);
+    }
+    if std::env::var_os("KUBERNETES_SERVICE_HOST").is_some() {
+        return Some(ContainerRuntime::Kubernetes);
+    }
+    // Set by systemd-nspawn, podman and others.
+    if let Ok(container) = std::env::var("container") {
+        return Some(parse_container_name(&container));
+    }
+    parse_cgroup(init_cgroup)
+}
+
+fn parse_container_name(name: &str) -> ContainerRuntime {
+    match name.trim() {
+        "docker" => ContainerRuntime::Docker,
+        "podman" => ContainerRuntime::Podman,
+        "lxc" | "lxc-libvirt" => ContainerRuntime::Lxc,
+        other => ContainerRuntime::Other(other.to_string()),
+    }
+}
+
+/// Determines the container runtime from the contents of `/proc/1/cgroup`. With cgroups v2 the
+/// file only contains `0::/` and nothing can be derived from it.
+fn parse_cgroup(cgroup: &str) -> Option<ContainerRuntime> {
+    cgroup.lines().find_map(|line| {
+        let path = line.splitn(3, ':').nth(2)?;
+        if path.contains("kubepods") {
+            Some(ContainerRuntime::Kubernetes)
+        } else if path.contains("docker") {
+            Some(ContainerRuntime::Docker)
+        } else if path.contains("libpod") {
+            Some(ContainerRuntime::Podman)
+        } else if path.contains("containerd") {
+            Some(ContainerRuntime::Containerd)
+        } else if path.contains("lxc") {
+            Some(ContainerRuntime::Lxc)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn doesnt_crash() {
+        let environment = ExecutionEnvironment::detect();
+        println!("{environment:?}");
+    }
+
+    #[test]
+    fn test_parse_wsl() {
+        assert_eq!(parse_wsl("4.4.0-19041-Microsoft\n", false), Some(Wsl::Wsl1));
+        assert_eq!(
+            parse_wsl("5.15.133.1-microsoft-standard-WSL2\n", false),
+            Some(Wsl::Wsl2)
+        );
+        assert_eq!(
+            parse_wsl("5.10.16.3-microsoft-custom", true),
+            Some(Wsl::Wsl2)
+        );
+        assert_eq!(parse_wsl("6.5.0-14-generic", false), None);
+    }
+
+    #[test]
+    fn test_parse_cgroup() {
+        assert_eq!(
+            parse_cgroup("12:cpuset:/docker/3601745b3bd54d9780436faa5f0e4f72\n"),
+            Some(ContainerRuntime::Docker)
+        );
+        assert_eq!(
+            parse_cgroup("11:memory:/kubepods/besteffort/pod1/abc\n"),
+            Some(ContainerRuntime::Kubernetes)
+        );
+        assert_eq!(parse_cgroup("0::/\n"), None);
+        assert_eq!(parse_cgroup("0::/init.scope\n"), None);
+    }
+}
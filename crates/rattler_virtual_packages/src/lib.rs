@@ -28,10 +28,14 @@
 //! detections that are not tied to anything related to virtual packages. See
 //! [`cuda::detect_cuda_version_via_libcuda`] as an example.
 
+pub mod cache;
 pub mod cuda;
+pub mod environment;
 pub mod libc;
 pub mod linux;
 pub mod osx;
+pub mod overrides;
+pub mod win;
 
 use archspec::cpu::Microarchitecture;
 use once_cell::sync::OnceCell;
@@ -42,13 +46,17 @@ use std::sync::Arc;
 use crate::osx::ParseOsxVersionError;
 use libc::DetectLibCError;
 use linux::ParseLinuxVersionError;
+use overrides::InvalidOverrideError;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 /// An enum that represents all virtual package types provided by this library.
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub enum VirtualPackage {
     /// Available on windows
-    Win,
+    Win(Windows),
+
+    /// Available Universal C Runtime version on windows
+    Ucrt(Ucrt),
 
     /// Available on unix based platforms
     Unix,
@@ -72,11 +80,8 @@ pub enum VirtualPackage {
 impl From<VirtualPackage> for GenericVirtualPackage {
     fn from(package: VirtualPackage) -> Self {
         match package {
-            VirtualPackage::Win => GenericVirtualPackage {
-                name: PackageName::new_unchecked("__win"),
-                version: Version::major(0),
-                build_string: "0".into(),
-            },
+            VirtualPackage::Win(windows) => windows.into(),
+            VirtualPackage::Ucrt(ucrt) => ucrt.into(),
             VirtualPackage::Unix => GenericVirtualPackage {
                 name: PackageName::new_unchecked("__unix"),
                 version: Version::major(0),
@@ -94,12 +99,21 @@ impl From<VirtualPackage> for GenericVirtualPackage {
 impl VirtualPackage {
     /// Returns virtual packages detected for the current system or an error if the versions could
     /// not be properly detected.
+    ///
+    /// Detected versions can be overridden with `CONDA_OVERRIDE_*` environment variables, see
+    /// [`overrides`]. The result is memoized for the lifetime of the process, use
+    /// [`VirtualPackage::detect`] or a [`cache::VirtualPackageCache`] to detect changes.
     pub fn current() -> Result<&'static [Self], DetectVirtualPackageError> {
         static DETECED_VIRTUAL_PACKAGES: OnceCell<Vec<VirtualPackage>> = OnceCell::new();
         DETECED_VIRTUAL_PACKAGES
-            .get_or_try_init(try_detect_virtual_packages)
+            .get_or_try_init(Self::detect)
             .map(Vec::as_slice)
     }
+
+    /// Detects the virtual packages of the current system without using memoized results.
+    pub fn detect() -> Result<Vec<Self>, DetectVirtualPackageError> {
+        try_detect_virtual_packages()
+    }
 }
 
 /// An error that might be returned by [`VirtualPackage::current`].
@@ -114,6 +128,9 @@ pub enum DetectVirtualPackageError {
 
     #[error(transparent)]
     DetectLibC(#[from] DetectLibCError),
+
+    #[error(transparent)]
+    InvalidOverride(#[from] InvalidOverrideError),
 }
 
 // Detect the available virtual packages on the system
@@ -126,35 +143,144 @@ fn try_detect_virtual_packages() -> Result<Vec<VirtualPackage>, DetectVirtualPac
     }
 
     if platform.is_windows() {
-        result.push(VirtualPackage::Win);
+        let version = match overrides::version_override(overrides::WIN)? {
+            Some(version) => version.into_value(),
+            None => win::detect_windows_version(),
+        };
+        result.push(Windows { version }.into());
+
+        let ucrt = match overrides::version_override(overrides::UCRT)? {
+            Some(version) => version.into_value().map(|version| Ucrt { version }),
+            None => win::detect_ucrt_version().map(|version| Ucrt { version }),
+        };
+        if let Some(ucrt) = ucrt {
+            result.push(ucrt.into());
+        }
     }
 
     if platform.is_linux() {
-        if let Some(linux_version) = Linux::current()? {
-            result.push(linux_version.into());
+        let linux = match overrides::version_override(overrides::LINUX)? {
+            Some(version) => version.into_value().map(|version| Linux { version }),
+            None => linux::detect_linux_version()?.map(|version| Linux { version }),
+        };
+        if let Some(linux) = linux {
+            result.push(linux.into());
         }
-        if let Some(libc) = LibC::current()? {
+
+        let libc = match overrides::version_override(overrides::GLIBC)? {
+            Some(version) => version.into_value().map(|version| LibC {
+                family: String::from("glibc"),
+                version,
+            }),
+            None => libc::detect_libc_family_and_version()?
+                .map(|(family, version)| LibC { family, version }),
+        };
+        if let Some(libc) = libc {
             result.push(libc.into());
         }
     }
 
     if platform.is_osx() {
-        if let Some(osx) = Osx::current()? {
+        let osx = match overrides::version_override(overrides::OSX)? {
+            Some(version) => version.into_value().map(|version| Osx { version }),
+            None => osx::detect_osx_version()?.map(|version| Osx { version }),
+        };
+        if let Some(osx) = osx {
             result.push(osx.into());
         }
     }
 
-    if let Some(cuda) = Cuda::current() {
+    let cuda = match overrides::version_override(overrides::CUDA)? {
+        Some(version) => version.into_value().map(|version| Cuda {
+            version,
+            driver_version: None,
+        }),
+        None => cuda::detect_cuda_driver().map(|driver| Cuda {
+            version: driver.cuda_version,
+            driver_version: driver.driver_version,
+        }),
+    };
+    if let Some(cuda) = cuda {
         result.push(cuda.into());
     }
 
-    if let Some(archspec) = Archspec::current() {
+    let archspec = match overrides::string_override(overrides::ARCHSPEC) {
+        Some(name) => name.into_value().map(|name| Archspec::from_name(&name)),
+        None => Archspec::current(),
+    };
+    if let Some(archspec) = archspec {
         result.push(archspec.into());
     }
 
     Ok(result)
 }
 
+/// Windows virtual package description
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize)]
+pub struct Windows {
+    /// The version of Windows, e.g. `10.0.22631`. The `__win` virtual package has version `0` if
+    /// the version is unknown.
+    #[serde(default)]
+    pub version: Option<Version>,
+}
+
+impl Windows {
+    /// Returns the Windows version of the current platform. The version is `None` if the current
+    /// platform is not Windows or if the version could not be determined.
+    pub fn current() -> Self {
+        Self {
+            version: win::windows_version(),
+        }
+    }
+}
+
+impl From<Windows> for GenericVirtualPackage {
+    fn from(windows: Windows) -> Self {
+        GenericVirtualPackage {
+            name: PackageName::new_unchecked("__win"),
+            version: windows.version.unwrap_or_else(|| Version::major(0)),
+            build_string: "0".into(),
+        }
+    }
+}
+
+impl From<Windows> for VirtualPackage {
+    fn from(windows: Windows) -> Self {
+        VirtualPackage::Win(windows)
+    }
+}
+
+/// Universal C Runtime virtual package description
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
+pub struct Ucrt {
+    /// The version of the Universal C Runtime.
+    pub version: Version,
+}
+
+impl Ucrt {
+    /// Returns the version of the Universal C Runtime of the current platform. Returns `None` if
+    /// the current platform is not Windows or if the runtime is not available.
+    pub fn current() -> Option<Self> {
+        win::ucrt_version().map(|version| Self { version })
+    }
+}
+
+impl From<Ucrt> for GenericVirtualPackage {
+    fn from(ucrt: Ucrt) -> Self {
+        GenericVirtualPackage {
+            name: PackageName::new_unchecked("__ucrt"),
+            version: ucrt.version,
+            build_string: "0".into(),
+        }
+    }
+}
+
+impl From<Ucrt> for VirtualPackage {
+    fn from(ucrt: Ucrt) -> Self {
+        VirtualPackage::Ucrt(ucrt)
+    }
+}
+
 /// Linux virtual package description
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
 pub struct Linux {
@@ -191,7 +317,8 @@ impl From<Linux> for VirtualPackage {
 /// `LibC` virtual package description
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
 pub struct LibC {
-    /// The family of LibC. This could be glibc for instance.
+    /// The family of LibC, either `glibc` or `musl`. The virtual package is named after the
+    /// family, e.g. `__glibc` or `__musl`.
     pub family: String,
 
     /// The version of the libc distribution.
@@ -234,12 +361,20 @@ impl From<LibC> for VirtualPackage {
 pub struct Cuda {
     /// The maximum supported Cuda version.
     pub version: Version,
+
+    /// The version of the NVIDIA driver, if it is known. This is metadata only, it is not part of
+    /// the virtual package.
+    #[serde(default)]
+    pub driver_version: Option<String>,
 }
 
 impl Cuda {
     /// Returns the maximum Cuda version available on the current platform.
     pub fn current() -> Option<Self> {
-        cuda::cuda_version().map(|version| Self { version })
+        cuda::cuda_driver().map(|driver| Self {
+            version: driver.cuda_version,
+            driver_version: driver.driver_version,
+        })
     }
 }
 
@@ -281,11 +416,7 @@ impl<'de> Deserialize<'de> for Archspec {
         D: Deserializer<'de>,
     {
         let name = String::deserialize(deserializer)?;
-        let spec = archspec::cpu::Microarchitecture::known_targets()
-            .get(&name)
-            .cloned()
-            .unwrap_or_else(|| Arc::new(archspec::cpu::Microarchitecture::generic(&name)));
-        Ok(Self { spec })
+        Ok(Self::from_name(&name))
     }
 }
 
@@ -315,6 +446,57 @@ impl Archspec {
         archspec::cpu::host().ok().map(Into::into)
     }
 
+    /// Returns the microarchitecture with the given name. Unknown names result in a generic
+    /// microarchitecture without any ancestors.
+    pub fn from_name(name: &str) -> Self {
+        archspec::cpu::Microarchitecture::known_targets()
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| Arc::new(archspec::cpu::Microarchitecture::generic(name)))
+            .into()
+    }
+
+    /// Returns the microarchitectures that code compiled for can run on this microarchitecture,
+    /// ordered from the most to the least specific. The first entry is the microarchitecture
+    /// itself, e.g. for `zen3` this returns `zen3`, `zen2`, `zen`, `x86_64_v3`, `x86_64_v2`, and
+    /// `x86_64`.
+    ///
+    /// A solver can use the position of the microarchitecture of an optimized build in this list
+    /// to prefer the most optimized build that is still compatible.
+    pub fn compatible_targets(&self) -> Vec<Arc<Microarchitecture>> {
+        let mut targets = std::iter::once(self.spec.clone())
+            .chain(self.spec.ancestors().iter().cloned())
+            .collect::<Vec<_>>();
+
+        // A microarchitecture always has more ancestors than each of its ancestors. The sort is
+        // stable so the order of ancestors with the same depth is preserved.
+        targets.sort_by_key(|target| std::cmp::Reverse(target.ancestors().len()));
+        targets
+    }
+
+    /// Returns the position of the microarchitecture with the given name in
+    /// [`Archspec::compatible_targets`], or `None` if code compiled for that microarchitecture
+    /// cannot run on this microarchitecture. Lower is better.
+    pub fn compatibility_rank(&self, target: &str) -> Option<usize> {
+        self.compatible_targets()
+            .iter()
+            .position(|compatible| compatible.name() == target)
+    }
+
+    /// Returns true if code compiled for the microarchitecture with the given name can run on
+    /// this microarchitecture.
+    pub fn is_compatible_with(&self, target: &str) -> bool {
+        self.compatibility_rank(target).is_some()
+    }
+
+    /// Returns the most specific vendor independent microarchitecture that this
+    /// microarchitecture is compatible with, e.g. `x86_64_v3` for `zen3` or `skylake`.
+    pub fn generic_level(&self) -> Option<Arc<Microarchitecture>> {
+        self.compatible_targets()
+            .into_iter()
+            .find(|target| target.vendor() == "generic")
+    }
+
     /// Returns the minimal supported archspec architecture for the given
     /// platform.
     #[allow(clippy::match_same_arms)]
@@ -403,11 +585,45 @@ impl From<Osx> for VirtualPackage {
 
 #[cfg(test)]
 mod test {
-    use crate::VirtualPackage;
+    use crate::{Archspec, VirtualPackage};
+    use archspec::cpu::Microarchitecture;
 
     #[test]
     fn doesnt_crash() {
         let virtual_packages = VirtualPackage::current().unwrap();
         println!("{virtual_packages:?}");
     }
+
+    #[test]
+    fn test_archspec_compatibility() {
+        let archspec = |name: &str| -> Archspec {
+            Microarchitecture::known_targets()
+                .get(name)
+                .cloned()
+                .unwrap()
+                .into()
+        };
+
+        let zen3 = archspec("zen3");
+        let targets = zen3
+            .compatible_targets()
+            .iter()
+            .map(|target| target.name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(targets.first().map(String::as_str), Some("zen3"));
+        assert_eq!(targets.last().map(String::as_str), Some("x86_64"));
+        assert_eq!(zen3.generic_level().unwrap().name(), "x86_64_v3");
+        assert!(zen3.compatibility_rank("x86_64_v3") < zen3.compatibility_rank("x86_64_v2"));
+        assert!(zen3.is_compatible_with("zen2"));
+        assert!(!zen3.is_compatible_with("skylake"));
+        assert!(!zen3.is_compatible_with("x86_64_v4"));
+
+        let skylake = archspec("skylake");
+        assert_eq!(skylake.generic_level().unwrap().name(), "x86_64_v3");
+        assert!(skylake.is_compatible_with("haswell"));
+
+        let m1 = archspec("m1");
+        assert!(m1.is_compatible_with("aarch64"));
+        assert!(!m1.is_compatible_with("x86_64"));
+    }
 }
@@ -6,11 +6,12 @@ use rattler_conda_types::{ParseVersionError, Version};
 /// Returns the `LibC` version and family of the current platform.
 ///
 /// Returns an error if determining the `LibC` family and version resulted in an error. Returns
-/// `None` if the current platform does not provide a version of `LibC`.
+/// `None` if the current platform does not provide a version of `LibC`. The result is memoized,
+/// use [`detect_libc_family_and_version`] to detect the version again.
 pub fn libc_family_and_version() -> Result<Option<(String, Version)>, DetectLibCError> {
     static DETECTED_LIBC_VERSION: OnceCell<Option<(String, Version)>> = OnceCell::new();
     DETECTED_LIBC_VERSION
-        .get_or_try_init(try_detect_libc_version)
+        .get_or_try_init(detect_libc_family_and_version)
         .cloned()
 }
 
@@ -28,32 +29,41 @@ pub enum DetectLibCError {
 /// instance when compiling against musl libc the resulting binary can still run on a glibc based
 /// system. For environments we are interested in the libc family that is available on the *system*.
 ///
-/// Currently this code is only able to detect glibc properly. We can add more detection methods in
-/// the future.
+/// Both glibc and musl are detected, the family is reported as `glibc` or `musl` respectively.
 #[cfg(unix)]
-fn try_detect_libc_version() -> Result<Option<(String, Version)>, DetectLibCError> {
-    // GNU libc writes to stdout
-    static GNU_LIBC_RE: once_cell::sync::Lazy<regex::Regex> = once_cell::sync::Lazy::new(|| {
-        regex::Regex::new("(?mi)(?:glibc|gnu libc).*?([0-9]+(:?.[0-9]+)*)$").unwrap()
-    });
-
+pub fn detect_libc_family_and_version() -> Result<Option<(String, Version)>, DetectLibCError> {
     // Run `ldd --version` to detect the libc version and family on the system. `ldd` is shipped
     // with libc so if an error occured during its execution we can assume no libc is available on
-    // the system.
+    // the system, unless the musl dynamic loader exists.
     let output = match std::process::Command::new("ldd").arg("--version").output() {
         Err(e) => {
-            tracing::info!(
-                "failed to execute `ldd --version`: {e}. Assuming libc is not available."
-            );
-            return Ok(None);
+            tracing::info!("failed to execute `ldd --version`: {e}.");
+            return try_detect_musl_via_loader();
         }
         Ok(output) => output,
     };
 
     let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match parse_ldd_output(&stdout, &stderr)? {
+        Some(libc) => Ok(Some(libc)),
+        None => try_detect_musl_via_loader(),
+    }
+}
+
+/// Parses the output of `ldd --version`. GNU libc writes its version to stdout, while musl writes
+/// it to stderr and exits with a non-zero exit code.
+#[cfg(unix)]
+fn parse_ldd_output(
+    stdout: &str,
+    stderr: &str,
+) -> Result<Option<(String, Version)>, DetectLibCError> {
+    static GNU_LIBC_RE: once_cell::sync::Lazy<regex::Regex> = once_cell::sync::Lazy::new(|| {
+        regex::Regex::new("(?mi)(?:glibc|gnu libc).*?([0-9]+(:?.[0-9]+)*)$").unwrap()
+    });
 
     if let Some(version_match) = GNU_LIBC_RE
-        .captures(&stdout)
+        .captures(stdout)
         .and_then(|captures| captures.get(1))
         .map(|version_match| version_match.as_str())
     {
@@ -61,11 +71,67 @@ fn try_detect_libc_version() -> Result<Option<(String, Version)>, DetectLibCErro
         return Ok(Some((String::from("glibc"), version)));
     }
 
-    Ok(None)
+    parse_musl_output(stderr)
+}
+
+/// Parses the version banner that musl prints, which looks like:
+///
+/// ```text
+/// musl libc (x86_64)
+/// Version 1.2.4
+/// ```
+#[cfg(unix)]
+fn parse_musl_output(output: &str) -> Result<Option<(String, Version)>, DetectLibCError> {
+    static MUSL_RE: once_cell::sync::Lazy<regex::Regex> = once_cell::sync::Lazy::new(|| {
+        regex::Regex::new("(?mi)^musl libc.*\\n\\s*version\\s+([0-9]+(?:\\.[0-9]+)*)").unwrap()
+    });
+
+    match MUSL_RE
+        .captures(output)
+        .and_then(|captures| captures.get(1))
+        .map(|version_match| version_match.as_str())
+    {
+        Some(version) => Ok(Some((
+            String::from("musl"),
+            std::str::FromStr::from_str(version)?,
+        ))),
+        None => Ok(None),
+    }
 }
 
+/// Detects musl by executing its dynamic loader, which prints the version banner when it is
+/// invoked without arguments. This is used on systems where `ldd` is not installed, which is
+/// common for minimal Alpine containers.
+#[cfg(unix)]
+fn try_detect_musl_via_loader() -> Result<Option<(String, Version)>, DetectLibCError> {
+    let Ok(entries) = std::fs::read_dir("/lib") else {
+        return Ok(None);
+    };
+    let loader = entries.filter_map(Result::ok).find(|entry| {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        name.starts_with("ld-musl-") && name.ends_with(".so.1")
+    });
+    let Some(loader) = loader else {
+        return Ok(None);
+    };
+
+    match std::process::Command::new(loader.path()).output() {
+        Ok(output) => parse_musl_output(&String::from_utf8_lossy(&output.stderr)),
+        Err(e) => {
+            tracing::info!(
+                "failed to execute {}: {e}. Assuming libc is not available.",
+                loader.path().display()
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Detects the `LibC` family and version of the current platform. Always returns `None` on this platform.
 #[cfg(not(unix))]
-const fn try_detect_libc_version() -> Result<Option<(String, Version)>, DetectLibCError> {
+pub const fn detect_libc_family_and_version() -> Result<Option<(String, Version)>, DetectLibCError>
+{
     Ok(None)
 }
 
@@ -74,7 +140,31 @@ mod test {
     #[test]
     #[cfg(unix)]
     pub fn doesnt_crash() {
-        let version = super::try_detect_libc_version().unwrap();
+        let version = super::detect_libc_family_and_version().unwrap();
         println!("LibC {version:?}");
     }
+
+    #[test]
+    #[cfg(unix)]
+    pub fn test_parse_ldd_output() {
+        let glibc = super::parse_ldd_output(
+            "ldd (Ubuntu GLIBC 2.35-0ubuntu3.1) 2.35\nCopyright (C) 2022 Free Software Foundation, Inc.\n",
+            "",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(glibc.0, "glibc");
+        assert_eq!(glibc.1.to_string(), "2.35");
+
+        let musl = super::parse_ldd_output(
+            "",
+            "musl libc (x86_64)\nVersion 1.2.4\nDynamic Program Loader\nUsage: ldd [options] [--] pathname\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(musl.0, "musl");
+        assert_eq!(musl.1.to_string(), "1.2.4");
+
+        assert_eq!(super::parse_ldd_output("", "").unwrap(), None);
+    }
 }
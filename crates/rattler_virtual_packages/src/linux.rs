@@ -7,17 +7,18 @@ use std::str::FromStr;
 /// Returns the Linux version of the current platform.
 ///
 /// Returns an error if determining the Linux version resulted in an error. Returns `None` if
-/// the current platform is not a Linux platform.
+/// the current platform is not a Linux platform. The result is memoized, use
+/// [`detect_linux_version`] to detect the version again.
 pub fn linux_version() -> Result<Option<Version>, ParseLinuxVersionError> {
     static DETECTED_LINUX_VERSION: OnceCell<Option<Version>> = OnceCell::new();
     DETECTED_LINUX_VERSION
-        .get_or_try_init(try_detect_linux_version)
+        .get_or_try_init(detect_linux_version)
         .cloned()
 }
 
 /// Detects the current linux version.
 #[cfg(target_os = "linux")]
-fn try_detect_linux_version() -> Result<Option<Version>, ParseLinuxVersionError> {
+pub fn detect_linux_version() -> Result<Option<Version>, ParseLinuxVersionError> {
     use std::{ffi::CStr, mem::MaybeUninit};
 
     mod ffi {
@@ -52,8 +53,9 @@ fn try_detect_linux_version() -> Result<Option<Version>, ParseLinuxVersionError>
     parse_linux_version(release_str.as_ref()).map(Some)
 }
 
+/// Detects the Linux version of the current platform. Always returns `None` on this platform.
 #[cfg(not(target_os = "linux"))]
-const fn try_detect_linux_version() -> Result<Option<Version>, ParseLinuxVersionError> {
+pub const fn detect_linux_version() -> Result<Option<Version>, ParseLinuxVersionError> {
     Ok(None)
 }
 
@@ -124,7 +126,7 @@ mod test {
     #[test]
     #[cfg(target_os = "linux")]
     pub fn doesnt_crash() {
-        let version = super::try_detect_linux_version();
+        let version = super::detect_linux_version();
         println!("Linux {:?}", version);
     }
 }
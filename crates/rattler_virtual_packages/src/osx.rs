@@ -6,17 +6,18 @@ use rattler_conda_types::{ParseVersionError, Version};
 /// Returns the OSX version of the current platform.
 ///
 /// Returns an error if determining the version resulted in an error. Returns `None` if
-/// the current platform is not a OSX platform.
+/// the current platform is not a OSX platform. The result is memoized, use [`detect_osx_version`]
+/// to detect the version again.
 pub fn osx_version() -> Result<Option<Version>, ParseOsxVersionError> {
     static DETECTED_OSX_VERSION: OnceCell<Option<Version>> = OnceCell::new();
     DETECTED_OSX_VERSION
-        .get_or_try_init(try_detect_osx_version)
+        .get_or_try_init(detect_osx_version)
         .cloned()
 }
 
-/// Detects the current linux version.
+/// Detects the current OSX version.
 #[cfg(target_os = "macos")]
-fn try_detect_osx_version() -> Result<Option<Version>, ParseOsxVersionError> {
+pub fn detect_osx_version() -> Result<Option<Version>, ParseOsxVersionError> {
     use std::str::FromStr;
 
     let file = std::fs::read_to_string("/System/Library/CoreServices/SystemVersion.plist")
@@ -36,8 +37,9 @@ fn try_detect_osx_version() -> Result<Option<Version>, ParseOsxVersionError> {
     Ok(Some(Version::from_str(version)?))
 }
 
+/// Detects the OSX version of the current platform. Always returns `None` on this platform.
 #[cfg(not(target_os = "macos"))]
-const fn try_detect_osx_version() -> Result<Option<Version>, ParseOsxVersionError> {
+pub const fn detect_osx_version() -> Result<Option<Version>, ParseOsxVersionError> {
     Ok(None)
 }
 
@@ -65,7 +67,7 @@ mod test {
     #[test]
     #[cfg(target_os = "macos")]
     pub fn doesnt_crash() {
-        let version = super::try_detect_osx_version();
+        let version = super::detect_osx_version();
         println!("MacOS version {version:?}");
     }
 }
@@ -0,0 +1,136 @@
+//! Overrides of detected virtual packages through `CONDA_OVERRIDE_*` environment variables.
+//!
+//! The variables follow the same rules as conda:
+//!
+//! * If a variable is not set, the virtual package is detected from the system.
+//! * If a variable is set to a non-empty value, that value is used instead of the detected one,
+//!   even if the virtual package could not be detected at all.
+//! * If a variable is set to an empty string, the virtual package is not reported, even if it was
+//!   detected.
+//!
+//! Overrides only apply to virtual packages that exist on the current platform, e.g. setting
+//! [`GLIBC`] on macOS has no effect.
+
+use std::str::FromStr;
+
+use rattler_conda_types::{ParseVersionError, Version};
+
+/// Overrides the version of the `__cuda` virtual package.
+pub const CUDA: &str = "CONDA_OVERRIDE_CUDA";
+
+/// Overrides the version of the `__glibc` virtual package.
+pub const GLIBC: &str = "CONDA_OVERRIDE_GLIBC";
+
+/// Overrides the version of the `__linux` virtual package.
+pub const LINUX: &str = "CONDA_OVERRIDE_LINUX";
+
+/// Overrides the version of the `__osx` virtual package.
+pub const OSX: &str = "CONDA_OVERRIDE_OSX";
+
+/// Overrides the version of the `__win` virtual package.
+pub const WIN: &str = "CONDA_OVERRIDE_WIN";
+
+/// Overrides the version of the `__ucrt` virtual package.
+pub const UCRT: &str = "CONDA_OVERRIDE_UCRT";
+
+/// Overrides the microarchitecture of the `__archspec` virtual package.
+pub const ARCHSPEC: &str = "CONDA_OVERRIDE_ARCHSPEC";
+
+/// The value of an override environment variable that is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Override<T> {
+    /// The variable is set to a value which replaces the detected value.
+    Value(T),
+
+    /// The variable is set to an empty string, the virtual package is not reported.
+    Suppress,
+}
+
+impl<T> Override<T> {
+    /// Returns the value of the override, or `None` if the virtual package is suppressed.
+    pub fn into_value(self) -> Option<T> {
+        match self {
+            Override::Value(value) => Some(value),
+            Override::Suppress => None,
+        }
+    }
+}
+
+/// The value of an override environment variable is not a valid version.
+#[derive(Debug, Clone, thiserror::Error, PartialEq, Eq)]
+#[error("invalid value '{value}' for {env_var}")]
+pub struct InvalidOverrideError {
+    /// The name of the environment variable.
+    pub env_var: String,
+
+    /// The value of the environment variable.
+    pub value: String,
+
+    /// The reason the value could not be parsed.
+    #[source]
+    pub source: ParseVersionError,
+}
+
+/// Returns the value of the given override environment variable, or `None` if the variable is not
+/// set. Surrounding whitespace is ignored.
+pub fn string_override(env_var: &str) -> Option<Override<String>> {
+    parse_override(std::env::var(env_var).ok())
+}
+
+/// Returns the version of the given override environment variable, see [`string_override`].
+pub fn version_override(env_var: &str) -> Result<Option<Override<Version>>, InvalidOverrideError> {
+    parse_version_override(env_var, std::env::var(env_var).ok())
+}
+
+fn parse_override(value: Option<String>) -> Option<Override<String>> {
+    let value = value?;
+    let value = value.trim();
+    Some(if value.is_empty() {
+        Override::Suppress
+    } else {
+        Override::Value(value.to_string())
+    })
+}
+
+fn parse_version_override(
+    env_var: &str,
+    value: Option<String>,
+) -> Result<Option<Override<Version>>, InvalidOverrideError> {
+    match parse_override(value) {
+        None => Ok(None),
+        Some(Override::Suppress) => Ok(Some(Override::Suppress)),
+        Some(Override::Value(value)) => match Version::from_str(&value) {
+            Ok(version) => Ok(Some(Override::Value(version))),
+            Err(source) => Err(InvalidOverrideError {
+                env_var: env_var.to_string(),
+                value,
+                source,
+            }),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_version_override() {
+        assert_eq!(parse_version_override(CUDA, None).unwrap(), None);
+        assert_eq!(
+            parse_version_override(CUDA, Some(String::from(" "))).unwrap(),
+            Some(Override::Suppress)
+        );
+        assert_eq!(
+            parse_version_override(CUDA, Some(String::from("12.1"))).unwrap(),
+            Some(Override::Value(Version::from_str("12.1").unwrap()))
+        );
+
+        let err = parse_version_override(GLIBC, Some(String::from("2..17"))).unwrap_err();
+        assert_eq!(err.env_var, GLIBC);
+        assert_eq!(
+            err.to_string(),
+            "invalid value '2..17' for CONDA_OVERRIDE_GLIBC"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Low-level functions to detect the Windows version and the version of the Universal C Runtime
+//! (UCRT) of the system. See [`windows_version`] and [`ucrt_version`].
+
+use once_cell::sync::OnceCell;
+use rattler_conda_types::Version;
+
+/// Returns the version of Windows, e.g. `10.0.22631`.
+///
+/// Returns `None` if the current platform is not Windows or if the version could not be
+/// determined. The result is memoized, use [`detect_windows_version`] to detect the version again.
+pub fn windows_version() -> Option<Version> {
+    static DETECTED_WINDOWS_VERSION: OnceCell<Option<Version>> = OnceCell::new();
+    DETECTED_WINDOWS_VERSION
+        .get_or_init(detect_windows_version)
+        .clone()
+}
+
+/// Returns the file version of the Universal C Runtime (`ucrtbase.dll`), e.g. `10.0.22621.2506`.
+///
+/// The UCRT is part of Windows 10 and later and can be installed on older versions of Windows.
+/// Returns `None` if the current platform is not Windows or if the UCRT is not available. The
+/// result is memoized, use [`detect_ucrt_version`] to detect the version again.
+pub fn ucrt_version() -> Option<Version> {
+    static DETECTED_UCRT_VERSION: OnceCell<Option<Version>> = OnceCell::new();
+    DETECTED_UCRT_VERSION
+        .get_or_init(detect_ucrt_version)
+        .clone()
+}
+
+/// Detects the Windows version with `RtlGetVersion`. Unlike `GetVersionEx` this function is not
+/// affected by the compatibility manifest of the executable, so it always reports the actual
+/// version.
+#[cfg(windows)]
+pub fn detect_windows_version() -> Option<Version> {
+    use libloading::Symbol;
+    use std::str::FromStr;
+
+    #[repr(C)]
+    #[allow(non_snake_case, dead_code)]
+    struct OSVERSIONINFOW {
+        dwOSVersionInfoSize: u32,
+        dwMajorVersion: u32,
+        dwMinorVersion: u32,
+        dwBuildNumber: u32,
+        dwPlatformId: u32,
+        szCSDVersion: [u16; 128],
+    }
+
+    let ntdll = unsafe { libloading::Library::new("ntdll.dll") }.ok()?;
+    let rtl_get_version: Symbol<'_, unsafe extern "system" fn(*mut OSVERSIONINFOW) -> i32> =
+        unsafe { ntdll.get(b"RtlGetVersion\0") }.ok()?;
+
+    let mut info = OSVERSIONINFOW {
+        dwOSVersionInfoSize: std::mem::size_of::<OSVERSIONINFOW>() as u32,
+        dwMajorVersion: 0,
+        dwMinorVersion: 0,
+        dwBuildNumber: 0,
+        dwPlatformId: 0,
+        szCSDVersion: [0; 128],
+    };
+    if unsafe { rtl_get_version(&mut info) } != 0 {
+        return None;
+    }
+
+    Version::from_str(&format!(
+        "{}.{}.{}",
+        info.dwMajorVersion, info.dwMinorVersion, info.dwBuildNumber
+    ))
+    .ok()
+}
+
+/// Detects the Windows version. Always returns `None` on this platform.
+#[cfg(not(windows))]
+pub const fn detect_windows_version() -> Option<Version> {
+    None
+}
+
+/// Detects the version of the UCRT by reading the version resource of `ucrtbase.dll` with the
+/// functions from `version.dll`.
+#[cfg(windows)]
+pub fn detect_ucrt_version() -> Option<Version> {
+    use libloading::Symbol;
+    use std::ffi::c_void;
+    use std::str::FromStr;
+
+    #[repr(C)]
+    #[allow(non_snake_case, dead_code)]
+    struct VS_FIXEDFILEINFO {
+        dwSignature: u32,
+        dwStrucVersion: u32,
+        dwFileVersionMS: u32,
+        dwFileVersionLS: u32,
+    }
+
+    let library = unsafe { libloading::Library::new("version.dll") }.ok()?;
+    let get_file_version_info_size: Symbol<
+        '_,
+        unsafe extern "system" fn(*const u16, *mut u32) -> u32,
+    > = unsafe { library.get(b"GetFileVersionInfoSizeW\0") }.ok()?;
+    let get_file_version_info: Symbol<
+        '_,
+        unsafe extern "system" fn(*const u16, u32, u32, *mut c_void) -> i32,
+    > = unsafe { library.get(b"GetFileVersionInfoW\0") }.ok()?;
+    let ver_query_value: Symbol<
+        '_,
+        unsafe extern "system" fn(*const c_void, *const u16, *mut *mut c_void, *mut u32) -> i32,
+    > = unsafe { library.get(b"VerQueryValueW\0") }.ok()?;
+
+    let wide = |s: &str| s.encode_utf16().chain(Some(0)).collect::<Vec<u16>>();
+    let file_name = wide("ucrtbase.dll");
+    let root_block = wide("\\");
+
+    // If the UCRT is not installed the size of the version information cannot be determined.
+    let mut handle = 0;
+    let size = unsafe { get_file_version_info_size(file_name.as_ptr(), &mut handle) };
+    if size == 0 {
+        return None;
+    }
+
+    let mut buffer = vec![0u8; size as usize];
+    if unsafe {
+        get_file_version_info(
+            file_name.as_ptr(),
+            0,
+            size,
+            buffer.as_mut_ptr().cast::<c_void>(),
+        )
+    } == 0
+    {
+        return None;
+    }
+
+    let mut info = std::ptr::null_mut();
+    let mut info_len = 0;
+    if unsafe {
+        ver_query_value(
+            buffer.as_ptr().cast::<c_void>(),
+            root_block.as_ptr(),
+            &mut info,
+            &mut info_len,
+        )
+    } == 0
+        || (info_len as usize) < std::mem::size_of::<VS_FIXEDFILEINFO>()
+    {
+        return None;
+    }
+
+    // The pointer points into `buffer` which is still alive.
+    let info = unsafe { &*info.cast::<VS_FIXEDFILEINFO>() };
+    Version::from_str(&format!(
+        "{}.{}.{}.{}",
+        info.dwFileVersionMS >> 16,
+        info.dwFileVersionMS & 0xffff,
+        info.dwFileVersionLS >> 16,
+        info.dwFileVersionLS & 0xffff
+    ))
+    .ok()
+}
+
+/// Detects the UCRT version. Always returns `None` on this platform.
+#[cfg(not(windows))]
+pub const fn detect_ucrt_version() -> Option<Version> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    pub fn doesnt_crash() {
+        let version = super::windows_version();
+        let ucrt = super::ucrt_version();
+        println!("Windows {version:?}, UCRT {ucrt:?}");
+        if cfg!(not(windows)) {
+            assert!(version.is_none());
+            assert!(ucrt.is_none());
+        }
+    }
+}
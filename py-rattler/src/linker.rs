@@ -200,6 +200,7 @@ pub async fn install_package_to_environment(
         paths_data: paths.into(),
         requested_spec: None,
         link: None,
+        installed_system_menus: Vec::new(),
     };
 
     let target_prefix = target_prefix.clone();
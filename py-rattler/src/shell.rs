@@ -52,6 +52,7 @@ impl PyActivationVariables {
             conda_prefix,
             path,
             path_modification_behavior: path_modification_behavior.0,
+            ..ActivationVariables::default()
         };
         activation_vars.into()
     }
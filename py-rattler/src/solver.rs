@@ -58,9 +58,11 @@ pub fn py_solve(
                     .collect::<PyResult<Vec<_>>>()?,
                 virtual_packages: virtual_packages.into_iter().map(Into::into).collect(),
                 specs: specs.into_iter().map(Into::into).collect(),
+                constraints: Vec::new(),
                 timeout: timeout.map(std::time::Duration::from_micros),
                 channel_priority: channel_priority.into(),
                 exclude_newer,
+                build_preference: None,
             };
 
             Ok::<_, PyErr>(